@@ -148,7 +148,9 @@ impl EndpointConfig {
         self.addressing_style
     }
 
-    /// resolve the endpoint from the [EndpointConfig] and the bucket name
+    /// resolve the endpoint from the [EndpointConfig] and the bucket name. The bucket can also be
+    /// an access point ARN, or an access point's bucket-style alias, which resolves like a bucket
+    /// name to the access point's virtual-hosted endpoint.
     pub fn resolve_for_bucket(&self, bucket: &str) -> Result<ResolvedEndpointInfo, EndpointError> {
         let allocator = Allocator::default();
         let mut endpoint_request_context: RequestContext = RequestContext::new(&allocator).unwrap();
@@ -390,6 +392,27 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_access_point_alias() {
+        let alias = "my-access-point-hrzrlukc5m36ft7okagglf3gmwluquse1b-s3alias";
+        let endpoint_config = EndpointConfig::new("us-west-2");
+        let resolved_endpoint = endpoint_config.resolve_for_bucket(alias).unwrap();
+        assert_eq!(
+            "https://my-access-point-hrzrlukc5m36ft7okagglf3gmwluquse1b-s3alias.s3.us-west-2.amazonaws.com",
+            resolved_endpoint.uri().unwrap().as_os_str()
+        );
+        let endpoint_auth_scheme = resolved_endpoint.auth_scheme().unwrap();
+        assert_eq!(endpoint_auth_scheme.signing_name(), "s3");
+        assert_eq!(endpoint_auth_scheme.signing_region(), "us-west-2");
+
+        let endpoint_config = EndpointConfig::new("us-west-2").use_fips(true).use_dual_stack(true);
+        let endpoint_uri = endpoint_config.resolve_for_bucket(alias).unwrap().uri().unwrap();
+        assert_eq!(
+            "https://my-access-point-hrzrlukc5m36ft7okagglf3gmwluquse1b-s3alias.s3-fips.dualstack.us-west-2.amazonaws.com",
+            endpoint_uri.as_os_str()
+        );
+    }
+
     #[test]
     fn test_outpost() {
         let endpoint_config = EndpointConfig::new("us-gov-west-1");
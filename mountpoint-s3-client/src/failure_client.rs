@@ -14,10 +14,10 @@ use futures::Stream;
 use pin_project::pin_project;
 
 use crate::object_client::{
-    DeleteObjectError, DeleteObjectResult, ETag, GetBodyPart, GetObjectAttributesError, GetObjectAttributesResult,
-    GetObjectError, HeadObjectError, HeadObjectResult, ListObjectsError, ListObjectsResult, ObjectAttribute,
-    ObjectClientError, ObjectClientResult, PutObjectError, PutObjectParams, PutObjectRequest, PutObjectResult,
-    UploadReview,
+    CopyObjectError, CopyObjectResult, DeleteObjectError, DeleteObjectResult, DeleteObjectsResult, ETag, GetBodyPart,
+    GetObjectAttributesError, GetObjectAttributesResult, GetObjectError, HeadObjectError, HeadObjectResult,
+    ListObjectsError, ListObjectsResult, ObjectAttribute, ObjectClientError, ObjectClientResult, PutObjectError,
+    PutObjectParams, PutObjectRequest, PutObjectResult, UploadPartSource, UploadReview,
 };
 use crate::ObjectClient;
 
@@ -27,6 +27,17 @@ pub struct FailureRequestWrapper<Client: ObjectClient, RequestWrapperState> {
     result_fn: fn(&mut RequestWrapperState) -> Result<(), Client::ClientError>,
 }
 
+impl<Client: ObjectClient, RequestWrapperState> FailureRequestWrapper<Client, RequestWrapperState> {
+    /// Create a wrapper that calls `result_fn` before every read from a get stream or write to a
+    /// put request, and fails the operation if it returns an error.
+    pub fn new(
+        state: RequestWrapperState,
+        result_fn: fn(&mut RequestWrapperState) -> Result<(), Client::ClientError>,
+    ) -> Self {
+        Self { state, result_fn }
+    }
+}
+
 #[allow(clippy::type_complexity)]
 pub struct FailureClient<Client: ObjectClient, State, RequestWrapperState> {
     pub client: Client,
@@ -77,6 +88,10 @@ where
         self.client.part_size()
     }
 
+    fn is_access_denied(&self, error: &Self::ClientError) -> bool {
+        self.client.is_access_denied(error)
+    }
+
     async fn delete_object(
         &self,
         bucket: &str,
@@ -86,6 +101,27 @@ where
         self.client.delete_object(bucket, key).await
     }
 
+    async fn delete_objects(
+        &self,
+        bucket: &str,
+        keys: &[String],
+    ) -> ObjectClientResult<DeleteObjectsResult, DeleteObjectError, Self::ClientError> {
+        self.client.delete_objects(bucket, keys).await
+    }
+
+    async fn copy_object(
+        &self,
+        bucket: &str,
+        source_key: &str,
+        destination_key: &str,
+        source_etag: Option<&ETag>,
+        params: &PutObjectParams,
+    ) -> ObjectClientResult<CopyObjectResult, CopyObjectError, Self::ClientError> {
+        self.client
+            .copy_object(bucket, source_key, destination_key, source_etag, params)
+            .await
+    }
+
     async fn get_object(
         &self,
         bucket: &str,
@@ -130,6 +166,29 @@ where
             .await
     }
 
+    async fn list_objects_start_after(
+        &self,
+        bucket: &str,
+        start_after: &str,
+        delimiter: &str,
+        max_keys: usize,
+        prefix: &str,
+    ) -> ObjectClientResult<ListObjectsResult, ListObjectsError, Self::ClientError> {
+        // This is the first page of a listing, like a `list_objects` without a continuation token
+        (self.list_objects_cb)(
+            &mut *self.state.lock().unwrap(),
+            bucket,
+            None,
+            delimiter,
+            max_keys,
+            prefix,
+        )?;
+
+        self.client
+            .list_objects_start_after(bucket, start_after, delimiter, max_keys, prefix)
+            .await
+    }
+
     async fn head_object(
         &self,
         bucket: &str,
@@ -154,6 +213,23 @@ where
         })
     }
 
+    async fn put_object_from_parts(
+        &self,
+        bucket: &str,
+        key: &str,
+        source_etag: &ETag,
+        parts: &[UploadPartSource],
+        params: &PutObjectParams,
+    ) -> ObjectClientResult<PutObjectResult, PutObjectError, Self::ClientError> {
+        // The parts are all sent at once, so this fails like a PutObject request that fails on its
+        // first write
+        let mut wrapper = (self.put_object_cb)(&mut *self.state.lock().unwrap(), bucket, key, params)?;
+        (wrapper.result_fn)(&mut wrapper.state).map_err(ObjectClientError::ClientError)?;
+        self.client
+            .put_object_from_parts(bucket, key, source_etag, parts, params)
+            .await
+    }
+
     async fn get_object_attributes(
         &self,
         bucket: &str,
@@ -53,6 +53,7 @@ pub mod instance_info;
 #[doc(hidden)]
 pub mod mock_client;
 mod object_client;
+mod proxy_config;
 mod s3_crt_client;
 #[doc(hidden)]
 pub mod user_agent;
@@ -65,16 +66,18 @@ pub use s3_crt_client::{get_object::S3GetObjectRequest, put_object::S3PutObjectR
 /// Configuration for the S3 client
 pub mod config {
     pub use super::endpoint_config::{AddressingStyle, EndpointConfig};
-    pub use super::s3_crt_client::{S3ClientAuthConfig, S3ClientConfig};
+    pub use super::proxy_config::{ProxyConfig, ProxyConfigError};
+    pub use super::s3_crt_client::{RetryDecision, S3ClientAuthConfig, S3ClientConfig, S3Error};
 }
 
 /// Types used by all object clients
 pub mod types {
     pub use super::object_client::{
-        Checksum, ChecksumAlgorithm, DeleteObjectResult, ETag, GetBodyPart, GetObjectAttributesParts,
-        GetObjectAttributesResult, HeadObjectResult, ListObjectsResult, ObjectAttribute, ObjectClientResult,
-        ObjectInfo, ObjectPart, PutObjectParams, PutObjectResult, PutObjectTrailingChecksums, RestoreStatus,
-        UploadReview, UploadReviewPart,
+        BodyBufferPool, Checksum, ChecksumAlgorithm, CopyObjectResult, DeleteObjectResult, DeleteObjectsKeyError,
+        DeleteObjectsResult, ETag, GetBodyPart, GetObjectAttributesParts, GetObjectAttributesResult, HeadObjectResult,
+        ListObjectsResult, ObjectAttribute, ObjectClientResult, ObjectInfo, ObjectPart, PutObjectParams,
+        PutObjectResult, PutObjectTrailingChecksums, RestoreStatus, UploadPartSource, UploadReview, UploadReviewPart,
+        MAX_DELETE_OBJECTS_KEYS,
     };
 }
 
@@ -85,8 +88,8 @@ pub mod types {
 /// client errors. See its documentation for more details.
 pub mod error {
     pub use super::object_client::{
-        DeleteObjectError, GetObjectAttributesError, GetObjectError, HeadObjectError, ListObjectsError,
-        ObjectClientError, PutObjectError,
+        CopyObjectError, DeleteObjectError, GetObjectAttributesError, GetObjectError, HeadObjectError,
+        ListObjectsError, ObjectClientError, PutObjectError,
     };
     #[doc(hidden)]
     pub use super::s3_crt_client::HeadBucketError;
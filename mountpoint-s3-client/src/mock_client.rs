@@ -4,13 +4,16 @@
 
 use std::borrow::Cow;
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
-use std::ops::Range;
+use std::ops::{Bound, Range};
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
 use std::task::{Context, Poll};
 use std::time::{Duration, SystemTime};
 
 use async_trait::async_trait;
+use base64ct::{Base64, Encoding};
+use bytes::Bytes;
 use futures::{Stream, StreamExt};
 use lazy_static::lazy_static;
 use mountpoint_s3_crt::checksums::crc32c;
@@ -19,15 +22,17 @@ use rand::SeedableRng;
 use rand_chacha::ChaCha20Rng;
 use thiserror::Error;
 use time::OffsetDateTime;
-use tracing::trace;
+use tracing::{trace, Instrument};
 
 use crate::checksums::crc32c_to_base64;
 use crate::object_client::{
-    Checksum, ChecksumAlgorithm, DeleteObjectError, DeleteObjectResult, ETag, GetBodyPart, GetObjectAttributesError,
+    BodyBufferPool, Checksum, ChecksumAlgorithm, CopyObjectError, CopyObjectResult, DeleteObjectError,
+    DeleteObjectResult, DeleteObjectsKeyError, DeleteObjectsResult, ETag, GetBodyPart, GetObjectAttributesError,
     GetObjectAttributesParts, GetObjectAttributesResult, GetObjectError, HeadObjectError, HeadObjectResult,
     ListObjectsError, ListObjectsResult, ObjectAttribute, ObjectClient, ObjectClientError, ObjectClientResult,
     ObjectInfo, ObjectPart, PutObjectError, PutObjectParams, PutObjectRequest, PutObjectResult,
-    PutObjectTrailingChecksums, RestoreStatus, UploadReview, UploadReviewPart,
+    PutObjectTrailingChecksums, RestoreStatus, UploadPartSource, UploadReview, UploadReviewPart,
+    MAX_DELETE_OBJECTS_KEYS,
 };
 
 mod leaky_bucket;
@@ -72,6 +77,28 @@ pub struct MockClient {
     objects: Arc<RwLock<BTreeMap<String, MockObject>>>,
     in_progress_uploads: Arc<RwLock<BTreeSet<String>>>,
     operation_counts: Arc<RwLock<HashMap<Operation, u64>>>,
+    /// Held for writing while HeadObject requests are paused
+    head_object_pause: Arc<async_lock::RwLock<()>>,
+    /// How long requests of each operation take to complete
+    operation_latencies: Arc<RwLock<HashMap<Operation, Duration>>>,
+    /// Number of upcoming GetObject responses whose bodies should fail partway through
+    incomplete_get_object_bodies: Arc<RwLock<u64>>,
+    /// Number of upcoming ranged GetObject responses that should hold a different range than requested
+    mismatched_get_object_ranges: Arc<RwLock<u64>>,
+    /// Operations whose requests all fail, to simulate a partial outage
+    failing_operations: Arc<RwLock<HashSet<Operation>>>,
+    /// Whether GetObject ranges that reach past the end of an object return the bytes up to its end
+    truncate_get_object_ranges: Arc<RwLock<bool>>,
+    /// Keys that delete requests fail to delete, to simulate objects the caller may not delete
+    undeletable_keys: Arc<RwLock<HashSet<String>>>,
+    /// Keys that HeadObject and GetObject requests fail on, to simulate objects the caller may not access
+    forbidden_keys: Arc<RwLock<HashSet<String>>>,
+    /// Counter for the request IDs each request logs when it finishes
+    next_request_id: Arc<AtomicU64>,
+    /// Pool to copy GetObject response bodies into, like [S3ClientConfig::body_buffer_pool]
+    ///
+    /// [S3ClientConfig::body_buffer_pool]: crate::config::S3ClientConfig::body_buffer_pool
+    body_buffer_pool: Arc<RwLock<Option<Arc<dyn BodyBufferPool>>>>,
 }
 
 fn add_object(objects: &Arc<RwLock<BTreeMap<String, MockObject>>>, key: &str, value: MockObject) {
@@ -86,6 +113,16 @@ impl MockClient {
             objects: Default::default(),
             in_progress_uploads: Default::default(),
             operation_counts: Default::default(),
+            head_object_pause: Default::default(),
+            operation_latencies: Default::default(),
+            incomplete_get_object_bodies: Default::default(),
+            mismatched_get_object_ranges: Default::default(),
+            failing_operations: Default::default(),
+            truncate_get_object_ranges: Default::default(),
+            undeletable_keys: Default::default(),
+            forbidden_keys: Default::default(),
+            next_request_id: Default::default(),
+            body_buffer_pool: Default::default(),
         }
     }
 
@@ -124,6 +161,16 @@ impl MockClient {
         }
     }
 
+    /// Returns the `Content-MD5` headers sent with each part of the upload that created the object,
+    /// or `None` if the object was not uploaded with `Content-MD5`.
+    pub fn get_object_content_md5(&self, key: &str) -> Result<Option<Vec<String>>, MockClientError> {
+        if let Some(mock_object) = self.objects.read().unwrap().get(key) {
+            Ok(mock_object.content_md5.to_owned())
+        } else {
+            Err(MockClientError("object not found".into()))
+        }
+    }
+
     /// Returns error if object does not exist
     pub fn restore_object(&self, key: &str) -> Result<(), MockClientError> {
         match self.objects.write().unwrap().get_mut(key) {
@@ -148,6 +195,114 @@ impl MockClient {
         }
     }
 
+    /// Make HeadObject requests wait until the returned guard is dropped, so that tests can issue
+    /// other requests while a HeadObject is in flight. Requests are still counted when they start.
+    pub fn pause_head_object(&self) -> async_lock::RwLockWriteGuardArc<()> {
+        self.head_object_pause
+            .try_write_arc()
+            .expect("HeadObject requests are already paused")
+    }
+
+    /// Make every request of the given operation take `latency` to complete, to simulate a
+    /// high-latency connection to S3. Requests are still counted when they start.
+    pub fn set_operation_latency(&self, operation: Operation, latency: Duration) {
+        self.operation_latencies.write().unwrap().insert(operation, latency);
+    }
+
+    /// Make the bodies of the next `count` GetObject responses fail partway through. Each of them
+    /// returns the first half of the requested range and then an error, the same way the real
+    /// client reports a response that claimed success but was cut short or ended with an error
+    /// document.
+    pub fn fail_next_get_object_bodies(&self, count: u64) {
+        *self.incomplete_get_object_bodies.write().unwrap() = count;
+    }
+
+    /// Make the next `count` ranged GetObject responses hold a different range of the object than
+    /// was requested. Each of them fails the same way the real client reports a response whose
+    /// Content-Range doesn't match the request.
+    pub fn mismatch_next_get_object_ranges(&self, count: u64) {
+        *self.mismatched_get_object_ranges.write().unwrap() = count;
+    }
+
+    /// Make GetObject requests for ranges that start within an object but reach past its end return
+    /// the bytes up to its end, as S3 does, rather than failing with
+    /// [GetObjectError::InvalidRange]. Ranges that start past the end still fail.
+    pub fn truncate_get_object_ranges(&self, truncate: bool) {
+        *self.truncate_get_object_ranges.write().unwrap() = truncate;
+    }
+
+    /// Copy the bodies of GetObject responses into buffers rented from `pool`, as the CRT client
+    /// does when configured with one, rather than returning slices of the objects' data
+    pub fn set_body_buffer_pool(&self, pool: Arc<dyn BodyBufferPool>) {
+        *self.body_buffer_pool.write().unwrap() = Some(pool);
+    }
+
+    /// Make every HeadObject, GetObject, or ListObjectsV2 request fail, or stop failing, to simulate
+    /// an outage of part of S3. Requests are still counted when they start.
+    pub fn set_operation_failing(&self, operation: Operation, failing: bool) {
+        let mut failing_operations = self.failing_operations.write().unwrap();
+        if failing {
+            failing_operations.insert(operation);
+        } else {
+            failing_operations.remove(&operation);
+        }
+    }
+
+    /// Make delete requests fail to delete the given key, or allow them to again. DeleteObject
+    /// requests for the key fail, and DeleteObjects requests report it as not deleted.
+    pub fn set_key_undeletable(&self, key: &str, undeletable: bool) {
+        let mut undeletable_keys = self.undeletable_keys.write().unwrap();
+        if undeletable {
+            undeletable_keys.insert(key.to_owned());
+        } else {
+            undeletable_keys.remove(key);
+        }
+    }
+
+    /// Make requests that read the given key fail as if access to it was denied, or allow them
+    /// again. HeadObject and GetObject requests for the key fail with a [MockClientError] that
+    /// [is_access_denied](ObjectClient::is_access_denied).
+    pub fn set_key_forbidden(&self, key: &str, forbidden: bool) {
+        let mut forbidden_keys = self.forbidden_keys.write().unwrap();
+        if forbidden {
+            forbidden_keys.insert(key.to_owned());
+        } else {
+            forbidden_keys.remove(key);
+        }
+    }
+
+    /// Fail if reading the given key has been forbidden
+    fn check_forbidden(&self, key: &str) -> Result<(), MockClientError> {
+        if self.forbidden_keys.read().unwrap().contains(key) {
+            return Err(MockClientError::access_denied());
+        }
+        Ok(())
+    }
+
+    /// Fail if requests of the given operation have been set to fail
+    fn check_failing(&self, operation: Operation) -> Result<(), MockClientError> {
+        if self.failing_operations.read().unwrap().contains(&operation) {
+            return Err(MockClientError(format!("injected failure of {operation:?}").into()));
+        }
+        Ok(())
+    }
+
+    /// Wait for the latency configured for the given operation, if any. Like the real client's
+    /// requests, the wait has its own span, and logs a request ID when it finishes.
+    async fn simulate_latency(&self, operation: &Operation) {
+        let latency = self.operation_latencies.read().unwrap().get(operation).copied();
+        let request_id = format!("mock-{}", self.next_request_id.fetch_add(1, Ordering::SeqCst));
+        let span = tracing::warn_span!(target: "mountpoint_s3_client::mock_client::request", "request", ?operation);
+        async move {
+            if let Some(latency) = latency {
+                async_io::Timer::after(latency).await;
+            }
+            tracing::debug!(%request_id, "mock request finished");
+        }
+        .instrument(span)
+        .await
+    }
+
     /// Create a new counter for the given operation, starting at 0.
     pub fn new_counter(&self, operation: Operation) -> OperationCounter<'_> {
         let op_counts = self.operation_counts.read().unwrap();
@@ -166,10 +321,11 @@ impl MockClient {
         op_counts.entry(operation).and_modify(|count| *count += 1).or_insert(1);
     }
 
-    /// Ordered list implementation
+    /// Ordered list implementation. Like S3, `start_after` is ignored when continuing a listing.
     fn list_objects_ordered(
         &self,
         continuation_token: Option<&str>,
+        start_after: Option<&str>,
         delimiter: &str,
         max_keys: usize,
         prefix: &str,
@@ -186,9 +342,14 @@ impl MockClient {
         // When handling prefixes and delimiters, we care about characters, not bytes.
         let prefix_len = prefix.chars().count();
 
-        // If there is a continuation token, set up an iterator starting at that token. Otherwise,
-        // start at the beginning of the bucket.
-        let object_iterator = objects.range(continuation_token.unwrap_or("").to_string()..);
+        // If there is a continuation token, set up an iterator starting at that token, or else just
+        // after the start-after key. Otherwise, start at the beginning of the bucket.
+        let start = match (continuation_token, start_after) {
+            (Some(continuation_token), _) => Bound::Included(continuation_token.to_string()),
+            (None, Some(start_after)) => Bound::Excluded(start_after.to_string()),
+            (None, None) => Bound::Unbounded,
+        };
+        let object_iterator = objects.range((start, Bound::Unbounded));
 
         for (key, object) in object_iterator {
             let key_len = key.chars().count();
@@ -244,7 +405,8 @@ impl MockClient {
             } else {
                 object_vec.push(ObjectInfo {
                     key: key.to_string(),
-                    size: object.len() as u64,
+                    size: object.reported_size(),
+                    unknown_size: object.unknown_size,
                     last_modified: object.last_modified,
                     etag: object.etag.as_str().to_string(),
                     storage_class: object.storage_class.clone(),
@@ -305,7 +467,8 @@ impl MockClient {
             } else {
                 object_vec.push(ObjectInfo {
                     key: key.to_string(),
-                    size: object.len() as u64,
+                    size: object.reported_size(),
+                    unknown_size: object.unknown_size,
                     last_modified: object.last_modified,
                     etag: object.etag.as_str().to_string(),
                     storage_class: object.storage_class.clone(),
@@ -329,12 +492,16 @@ impl MockClient {
 /// Operations for use in operation counters.
 #[derive(Debug, Eq, Hash, PartialEq)]
 pub enum Operation {
+    CopyObject,
     DeleteObject,
+    DeleteObjects,
     HeadObject,
     GetObject,
     GetObjectAttributes,
     ListObjectsV2,
     PutObject,
+    UploadPart,
+    UploadPartCopy,
 }
 
 /// Counter for a specific client [Operation].
@@ -359,43 +526,57 @@ impl<'a> OperationCounter<'a> {
 
 #[derive(Clone)]
 pub struct MockObject {
-    generator: Arc<dyn Fn(u64, usize) -> Box<[u8]> + Send + Sync>,
+    generator: Arc<dyn Fn(u64, usize) -> Bytes + Send + Sync>,
     size: usize,
     storage_class: Option<String>,
     restore_status: Option<RestoreStatus>,
     last_modified: OffsetDateTime,
     etag: ETag,
     parts: Option<MockObjectParts>,
+    content_md5: Option<Vec<String>>,
+    unknown_size: bool,
+    content_encoding: Option<String>,
 }
 
 impl MockObject {
-    pub fn read(&self, offset: u64, size: usize) -> Box<[u8]> {
+    pub fn read(&self, offset: u64, size: usize) -> Bytes {
         let read_size = self.size.saturating_sub(offset as usize);
         (self.generator)(offset, size.min(read_size))
     }
 
     pub fn from_bytes(bytes: &[u8], etag: ETag) -> Self {
-        let bytes: Box<[u8]> = bytes.into();
+        Self::from_shared_bytes(Bytes::copy_from_slice(bytes), etag)
+    }
+
+    /// Create an object whose reads return slices of `bytes` itself rather than copies, so tests can
+    /// check that buffers are passed through without copying.
+    pub fn from_shared_bytes(bytes: Bytes, etag: ETag) -> Self {
         Self {
             size: bytes.len(),
-            generator: Arc::new(move |offset, size| bytes[offset as usize..offset as usize + size].into()),
+            generator: Arc::new(move |offset, size| bytes.slice(offset as usize..offset as usize + size)),
             storage_class: None,
             restore_status: None,
             last_modified: OffsetDateTime::now_utc(),
             etag,
             parts: None,
+            content_md5: None,
+            unknown_size: false,
+            content_encoding: None,
         }
     }
 
     pub fn constant(v: u8, size: usize, etag: ETag) -> Self {
         Self {
-            generator: Arc::new(move |_offset, size| vec![v; size].into_boxed_slice()),
+            generator: Arc::new(move |_offset, size| vec![v; size].into()),
             size,
             storage_class: None,
             restore_status: None,
             last_modified: OffsetDateTime::now_utc(),
             etag,
             parts: None,
+            content_md5: None,
+            unknown_size: false,
+            content_encoding: None,
         }
     }
 
@@ -410,7 +591,7 @@ impl MockObject {
                     vec.extend_from_slice(&RAMP_BYTES[offs..offs + nbyte]);
                     size -= nbyte;
                 }
-                vec.into_boxed_slice()
+                vec.into()
             }),
             size,
             storage_class: None,
@@ -418,6 +599,9 @@ impl MockObject {
             last_modified: OffsetDateTime::now_utc(),
             etag,
             parts: None,
+            content_md5: None,
+            unknown_size: false,
+            content_encoding: None,
         }
     }
 
@@ -433,6 +617,27 @@ impl MockObject {
         self.restore_status = restore_status;
     }
 
+    /// Make HeadObject and ListObjectsV2 report that this object's size is unknown, like objects
+    /// served through an S3 Object Lambda access point. GetObject still returns the whole object.
+    pub fn set_unknown_size(&mut self, unknown_size: bool) {
+        self.unknown_size = unknown_size;
+    }
+
+    /// Set the `Content-Encoding` HeadObject reports for this object. GetObject still returns the
+    /// object's bytes as they are.
+    pub fn set_content_encoding(&mut self, content_encoding: Option<String>) {
+        self.content_encoding = content_encoding;
+    }
+
+    /// The size reported for this object by HeadObject and ListObjectsV2
+    fn reported_size(&self) -> u64 {
+        if self.unknown_size {
+            0
+        } else {
+            self.size as u64
+        }
+    }
+
     pub fn len(&self) -> usize {
         self.size
     }
@@ -460,6 +665,8 @@ impl std::fmt::Debug for MockObject {
             .field("last_modified", &self.last_modified)
             .field("etag", &self.etag)
             .field("restored", &self.restore_status)
+            .field("unknown_size", &self.unknown_size)
+            .field("content_encoding", &self.content_encoding)
             .finish()
     }
 }
@@ -470,6 +677,9 @@ pub struct GetObjectResult {
     next_offset: u64,
     length: usize,
     part_size: usize,
+    /// If set, the number of bytes to return before failing the response
+    fail_after: Option<usize>,
+    body_buffer_pool: Option<Arc<dyn BodyBufferPool>>,
 }
 
 impl GetObjectResult {
@@ -495,8 +705,27 @@ impl Stream for GetObjectResult {
             return Poll::Ready(None);
         }
 
-        let next_part_size = self.part_size.min(self.length);
-        let next_part = self.object.read(self.next_offset, next_part_size);
+        let mut next_part_size = self.part_size.min(self.length);
+        if let Some(pool) = &self.body_buffer_pool {
+            next_part_size = next_part_size.min(pool.buffer_size().max(1));
+        }
+        if let Some(fail_after) = self.fail_after {
+            if fail_after == 0 {
+                self.length = 0;
+                return Poll::Ready(Some(mock_client_error("incomplete response body")));
+            }
+            next_part_size = next_part_size.min(fail_after);
+            self.fail_after = Some(fail_after - next_part_size);
+        }
+        let mut next_part = None;
+        if let Some(pool) = &self.body_buffer_pool {
+            // Like the CRT client, copy into pooled buffers, falling back to our own when none are free
+            next_part = pool.rent().map(|mut buffer| {
+                buffer.extend_from_slice(&self.object.read(self.next_offset, next_part_size));
+                pool.freeze(buffer)
+            });
+        }
+        let next_part = next_part.unwrap_or_else(|| self.object.read(self.next_offset, next_part_size));
 
         let result = (self.next_offset, next_part);
         self.next_offset += next_part_size as u64;
@@ -508,6 +737,15 @@ impl Stream for GetObjectResult {
 #[derive(Debug, Error, PartialEq, Eq)]
 pub struct MockClientError(pub Cow<'static, str>);
 
+impl MockClientError {
+    const ACCESS_DENIED: &'static str = "access denied";
+
+    /// The error for requests the caller isn't allowed to make
+    pub fn access_denied() -> Self {
+        Self(Self::ACCESS_DENIED.into())
+    }
+}
+
 impl std::fmt::Display for MockClientError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.0)
@@ -528,6 +766,10 @@ impl ObjectClient for MockClient {
         Some(self.config.part_size)
     }
 
+    fn is_access_denied(&self, error: &Self::ClientError) -> bool {
+        error.0 == MockClientError::ACCESS_DENIED
+    }
+
     async fn delete_object(
         &self,
         bucket: &str,
@@ -535,16 +777,91 @@ impl ObjectClient for MockClient {
     ) -> ObjectClientResult<DeleteObjectResult, DeleteObjectError, Self::ClientError> {
         trace!(bucket, key, "DeleteObject");
         self.inc_op_count(Operation::DeleteObject);
+        self.simulate_latency(&Operation::DeleteObject).await;
 
         if bucket != self.config.bucket {
             return Err(ObjectClientError::ServiceError(DeleteObjectError::NoSuchBucket));
         }
 
+        if self.undeletable_keys.read().unwrap().contains(key) {
+            return Err(ObjectClientError::ClientError(MockClientError::access_denied()));
+        }
+
         self.remove_object(key);
 
         Ok(DeleteObjectResult {})
     }
 
+    async fn delete_objects(
+        &self,
+        bucket: &str,
+        keys: &[String],
+    ) -> ObjectClientResult<DeleteObjectsResult, DeleteObjectError, Self::ClientError> {
+        trace!(bucket, num_keys = keys.len(), "DeleteObjects");
+        self.inc_op_count(Operation::DeleteObjects);
+        self.simulate_latency(&Operation::DeleteObjects).await;
+
+        if bucket != self.config.bucket {
+            return Err(ObjectClientError::ServiceError(DeleteObjectError::NoSuchBucket));
+        }
+        if keys.len() > MAX_DELETE_OBJECTS_KEYS {
+            return mock_client_error("too many keys in DeleteObjects request");
+        }
+
+        let undeletable_keys = self.undeletable_keys.read().unwrap();
+        let mut objects = self.objects.write().unwrap();
+        let mut errors = Vec::new();
+        for key in keys {
+            if undeletable_keys.contains(key) {
+                errors.push(DeleteObjectsKeyError::new(key, "AccessDenied", "Access Denied"));
+            } else {
+                objects.remove(key);
+            }
+        }
+
+        Ok(DeleteObjectsResult { errors })
+    }
+
+    async fn copy_object(
+        &self,
+        bucket: &str,
+        source_key: &str,
+        destination_key: &str,
+        source_etag: Option<&ETag>,
+        params: &PutObjectParams,
+    ) -> ObjectClientResult<CopyObjectResult, CopyObjectError, Self::ClientError> {
+        trace!(bucket, source_key, destination_key, ?source_etag, "CopyObject");
+        self.inc_op_count(Operation::CopyObject);
+        self.simulate_latency(&Operation::CopyObject).await;
+
+        if bucket != self.config.bucket {
+            return Err(ObjectClientError::ServiceError(CopyObjectError::NoSuchBucket));
+        }
+
+        let mut objects = self.objects.write().unwrap();
+        let Some(source) = objects.get(source_key) else {
+            return Err(ObjectClientError::ServiceError(CopyObjectError::NotFound));
+        };
+        if source_etag.is_some_and(|etag| *etag != source.etag) {
+            return Err(ObjectClientError::ServiceError(CopyObjectError::PreconditionFailed));
+        }
+        let mut object = source.clone();
+        object.set_last_modified(OffsetDateTime::now_utc());
+        // The copy is a single part, which only has a checksum if one was asked for
+        object.parts = if params.trailing_checksums == PutObjectTrailingChecksums::Enabled {
+            let checksum = crc32c::checksum(&object.read(0, object.size));
+            Some(MockObjectParts::Parts(vec![MockObjectPartAttributes {
+                size: object.size,
+                checksum: Some(crc32c_to_base64(&checksum)),
+            }]))
+        } else {
+            None
+        };
+        objects.insert(destination_key.to_owned(), object);
+
+        Ok(CopyObjectResult {})
+    }
+
     async fn get_object(
         &self,
         bucket: &str,
@@ -554,6 +871,10 @@ impl ObjectClient for MockClient {
     ) -> ObjectClientResult<Self::GetObjectResult, GetObjectError, Self::ClientError> {
         trace!(bucket, key, ?range, ?if_match, "GetObject");
         self.inc_op_count(Operation::GetObject);
+        self.simulate_latency(&Operation::GetObject).await;
+        self.check_failing(Operation::GetObject)
+            .map_err(ObjectClientError::ClientError)?;
+        self.check_forbidden(key).map_err(ObjectClientError::ClientError)?;
 
         if bucket != self.config.bucket {
             return Err(ObjectClientError::ServiceError(GetObjectError::NoSuchBucket));
@@ -569,19 +890,50 @@ impl ObjectClient for MockClient {
             }
 
             let (next_offset, length) = if let Some(range) = range {
-                if range.start >= object.len() as u64 || range.end > object.len() as u64 {
-                    return mock_client_error(format!("invalid range, length={}", object.len()));
+                let truncate = *self.truncate_get_object_ranges.read().unwrap();
+                let end = if truncate {
+                    range.end.min(object.len() as u64)
+                } else {
+                    range.end
+                };
+                if range.start >= object.len() as u64 || end > object.len() as u64 {
+                    return Err(ObjectClientError::ServiceError(GetObjectError::InvalidRange {
+                        object_size: Some(object.len() as u64),
+                    }));
                 }
-                (range.start, (range.end - range.start) as usize)
+                let mut mismatched_ranges = self.mismatched_get_object_ranges.write().unwrap();
+                if *mismatched_ranges > 0 {
+                    *mismatched_ranges -= 1;
+                    return mock_client_error(format!(
+                        "unexpected Content-Range bytes {}-{}/{} for range {:?}",
+                        range.start + 1,
+                        end,
+                        object.len(),
+                        range,
+                    ));
+                }
+                (range.start, (end - range.start) as usize)
             } else {
                 (0, object.len())
             };
 
+            let fail_after = {
+                let mut incomplete_bodies = self.incomplete_get_object_bodies.write().unwrap();
+                if *incomplete_bodies > 0 {
+                    *incomplete_bodies -= 1;
+                    Some(length / 2)
+                } else {
+                    None
+                }
+            };
+
             Ok(GetObjectResult {
                 object: object.clone(),
                 next_offset,
                 length,
                 part_size: self.config.part_size,
+                fail_after,
+                body_buffer_pool: self.body_buffer_pool.read().unwrap().clone(),
             })
         } else {
             Err(ObjectClientError::ServiceError(GetObjectError::NoSuchKey))
@@ -595,6 +947,11 @@ impl ObjectClient for MockClient {
     ) -> ObjectClientResult<HeadObjectResult, HeadObjectError, Self::ClientError> {
         trace!(bucket, key, "HeadObject");
         self.inc_op_count(Operation::HeadObject);
+        let _pause = self.head_object_pause.read().await;
+        self.simulate_latency(&Operation::HeadObject).await;
+        self.check_failing(Operation::HeadObject)
+            .map_err(ObjectClientError::ClientError)?;
+        self.check_forbidden(key).map_err(ObjectClientError::ClientError)?;
 
         if bucket != self.config.bucket {
             return Err(ObjectClientError::ServiceError(HeadObjectError::NotFound));
@@ -606,12 +963,14 @@ impl ObjectClient for MockClient {
                 bucket: bucket.to_string(),
                 object: ObjectInfo {
                     key: key.to_string(),
-                    size: object.size as u64,
+                    size: object.reported_size(),
+                    unknown_size: object.unknown_size,
                     last_modified: object.last_modified,
                     etag: object.etag.as_str().to_string(),
                     storage_class: object.storage_class.clone(),
                     restore_status: object.restore_status,
                 },
+                content_encoding: object.content_encoding.clone(),
             })
         } else {
             Err(ObjectClientError::ServiceError(HeadObjectError::NotFound))
@@ -628,6 +987,9 @@ impl ObjectClient for MockClient {
     ) -> ObjectClientResult<ListObjectsResult, ListObjectsError, Self::ClientError> {
         trace!(bucket, ?continuation_token, delimiter, max_keys, prefix, "ListObjects");
         self.inc_op_count(Operation::ListObjectsV2);
+        self.simulate_latency(&Operation::ListObjectsV2).await;
+        self.check_failing(Operation::ListObjectsV2)
+            .map_err(ObjectClientError::ClientError)?;
 
         if bucket != self.config.bucket {
             return Err(ObjectClientError::ServiceError(ListObjectsError::NoSuchBucket));
@@ -636,8 +998,35 @@ impl ObjectClient for MockClient {
         if let Some(seed) = self.config.unordered_list_seed {
             Ok(self.list_objects_unordered(continuation_token, delimiter, max_keys, prefix, seed))
         } else {
-            Ok(self.list_objects_ordered(continuation_token, delimiter, max_keys, prefix))
+            Ok(self.list_objects_ordered(continuation_token, None, delimiter, max_keys, prefix))
+        }
+    }
+
+    async fn list_objects_start_after(
+        &self,
+        bucket: &str,
+        start_after: &str,
+        delimiter: &str,
+        max_keys: usize,
+        prefix: &str,
+    ) -> ObjectClientResult<ListObjectsResult, ListObjectsError, Self::ClientError> {
+        trace!(bucket, start_after, delimiter, max_keys, prefix, "ListObjects");
+        self.inc_op_count(Operation::ListObjectsV2);
+        self.simulate_latency(&Operation::ListObjectsV2).await;
+        self.check_failing(Operation::ListObjectsV2)
+            .map_err(ObjectClientError::ClientError)?;
+
+        if bucket != self.config.bucket {
+            return Err(ObjectClientError::ServiceError(ListObjectsError::NoSuchBucket));
+        }
+
+        // Like S3 Express One Zone, whose listings are unordered, so have no position to start after
+        if self.config.unordered_list_seed.is_some() {
+            return Err(ObjectClientError::ClientError(MockClientError(
+                "start-after is not supported for unordered listings".into(),
+            )));
         }
+        Ok(self.list_objects_ordered(None, Some(start_after), delimiter, max_keys, prefix))
     }
 
     async fn put_object(
@@ -648,6 +1037,7 @@ impl ObjectClient for MockClient {
     ) -> ObjectClientResult<Self::PutObjectRequest, PutObjectError, Self::ClientError> {
         trace!(bucket, key, "PutObject");
         self.inc_op_count(Operation::PutObject);
+        self.simulate_latency(&Operation::PutObject).await;
 
         if bucket != self.config.bucket {
             return Err(ObjectClientError::ServiceError(PutObjectError::NoSuchBucket));
@@ -663,6 +1053,70 @@ impl ObjectClient for MockClient {
         Ok(put_request)
     }
 
+    async fn put_object_from_parts(
+        &self,
+        bucket: &str,
+        key: &str,
+        source_etag: &ETag,
+        parts: &[UploadPartSource],
+        params: &PutObjectParams,
+    ) -> ObjectClientResult<PutObjectResult, PutObjectError, Self::ClientError> {
+        use md5::Digest as _;
+
+        trace!(bucket, key, ?source_etag, num_parts = parts.len(), "PutObjectFromParts");
+
+        if bucket != self.config.bucket {
+            return Err(ObjectClientError::ServiceError(PutObjectError::NoSuchBucket));
+        }
+
+        let source = match self.objects.read().unwrap().get(key) {
+            Some(object) if object.etag == *source_etag => object.clone(),
+            _ => return Err(ObjectClientError::ServiceError(PutObjectError::PreconditionFailed)),
+        };
+
+        let mut buffer = Vec::new();
+        let mut part_attributes = Vec::with_capacity(parts.len());
+        let mut content_md5 = Vec::with_capacity(parts.len());
+        for part in parts {
+            let data = match part {
+                UploadPartSource::Copy(range) => {
+                    self.inc_op_count(Operation::UploadPartCopy);
+                    if range.start >= range.end || range.end > source.len() as u64 {
+                        return mock_client_error(format!("invalid copy range, length={}", source.len()));
+                    }
+                    source.read(range.start, (range.end - range.start) as usize)
+                }
+                UploadPartSource::Data(bytes) => {
+                    self.inc_op_count(Operation::UploadPart);
+                    bytes.clone()
+                }
+            };
+            let checksum = (params.trailing_checksums != PutObjectTrailingChecksums::Disabled)
+                .then(|| crc32c_to_base64(&crc32c::checksum(&data)));
+            part_attributes.push(MockObjectPartAttributes {
+                size: data.len(),
+                checksum,
+            });
+            content_md5.push(Base64::encode_string(&md5::Md5::digest(&data)));
+            buffer.extend_from_slice(&data);
+        }
+
+        let mut object: MockObject = buffer.into();
+        object.set_storage_class(params.storage_class.clone());
+        object.content_md5 = params.content_md5.then_some(content_md5);
+        if params.trailing_checksums == PutObjectTrailingChecksums::Enabled {
+            object.parts = Some(MockObjectParts::Parts(part_attributes));
+        } else {
+            object.parts = Some(MockObjectParts::Count(parts.len()));
+        }
+        add_object(&self.objects, key, object);
+
+        Ok(PutObjectResult {
+            sse_type: None,
+            sse_kms_key_id: None,
+        })
+    }
+
     async fn get_object_attributes(
         &self,
         bucket: &str,
@@ -673,6 +1127,7 @@ impl ObjectClient for MockClient {
     ) -> ObjectClientResult<GetObjectAttributesResult, GetObjectAttributesError, Self::ClientError> {
         trace!(bucket, key, "GetObjectAttributes");
         self.inc_op_count(Operation::GetObjectAttributes);
+        self.simulate_latency(&Operation::GetObjectAttributes).await;
 
         if bucket != self.config.bucket {
             return Err(ObjectClientError::ServiceError(GetObjectAttributesError::NoSuchBucket));
@@ -786,13 +1241,25 @@ impl MockPutObjectRequest {
             .collect()
     }
 
+    /// The `Content-MD5` header values for each part of the upload
+    fn content_md5(&self) -> Vec<String> {
+        use md5::Digest as _;
+
+        self.buffer
+            .chunks(self.part_size)
+            .map(|part| Base64::encode_string(&md5::Md5::digest(part)))
+            .collect()
+    }
+
     fn complete_inner(
         mut self,
         parts: Vec<MockObjectPartAttributes>,
     ) -> ObjectClientResult<PutObjectResult, PutObjectError, MockClientError> {
+        let content_md5 = self.params.content_md5.then(|| self.content_md5());
         let buffer = std::mem::take(&mut self.buffer);
         let mut object: MockObject = buffer.into();
         object.set_storage_class(self.params.storage_class.clone());
+        object.content_md5 = content_md5;
         // For S3 Standard, part attributes are only available when additional checksums are used
         if self.params.trailing_checksums == PutObjectTrailingChecksums::Enabled {
             object.parts = Some(MockObjectParts::Parts(parts));
@@ -872,6 +1339,8 @@ enum MockObjectParts {
 
 #[cfg(test)]
 mod tests {
+    use std::str::FromStr;
+
     use futures::StreamExt;
     use rand::{Rng, RngCore, SeedableRng};
     use rand_chacha::ChaChaRng;
@@ -932,18 +1401,6 @@ mod tests {
         rng.fill_bytes(&mut body);
         client.add_object("key1", body[..].into());
 
-        macro_rules! assert_client_error {
-            ($e:expr, $err:expr) => {
-                let err = $e.expect_err("should fail");
-                match err {
-                    ObjectClientError::ClientError(MockClientError(m)) => {
-                        assert_eq!(&*m, $err);
-                    }
-                    _ => assert!(false, "wrong error type"),
-                }
-            };
-        }
-
         assert!(matches!(
             client.get_object("wrong_bucket", "key1", None, None).await,
             Err(ObjectClientError::ServiceError(GetObjectError::NoSuchBucket))
@@ -954,26 +1411,78 @@ mod tests {
             Err(ObjectClientError::ServiceError(GetObjectError::NoSuchKey))
         ));
 
-        assert_client_error!(
+        assert!(matches!(
             client.get_object("test_bucket", "key1", Some(0..2001), None).await,
-            "invalid range, length=2000"
-        );
-        assert_client_error!(
+            Err(ObjectClientError::ServiceError(GetObjectError::InvalidRange {
+                object_size: Some(2000)
+            }))
+        ));
+        assert!(matches!(
             client.get_object("test_bucket", "key1", Some(2000..2000), None).await,
-            "invalid range, length=2000"
-        );
-        assert_client_error!(
+            Err(ObjectClientError::ServiceError(GetObjectError::InvalidRange {
+                object_size: Some(2000)
+            }))
+        ));
+        assert!(matches!(
             client.get_object("test_bucket", "key1", Some(500..2001), None).await,
-            "invalid range, length=2000"
-        );
-        assert_client_error!(
+            Err(ObjectClientError::ServiceError(GetObjectError::InvalidRange {
+                object_size: Some(2000)
+            }))
+        ));
+        assert!(matches!(
             client.get_object("test_bucket", "key1", Some(5000..2001), None).await,
-            "invalid range, length=2000"
-        );
-        assert_client_error!(
+            Err(ObjectClientError::ServiceError(GetObjectError::InvalidRange {
+                object_size: Some(2000)
+            }))
+        ));
+        assert!(matches!(
             client.get_object("test_bucket", "key1", Some(5000..1), None).await,
-            "invalid range, length=2000"
-        );
+            Err(ObjectClientError::ServiceError(GetObjectError::InvalidRange {
+                object_size: Some(2000)
+            }))
+        ));
+    }
+
+    #[tokio::test]
+    async fn get_object_incomplete_body() {
+        let client = MockClient::new(MockClientConfig {
+            bucket: "test_bucket".to_string(),
+            part_size: 1024,
+            unordered_list_seed: None,
+        });
+        let body = ramp_bytes(0, 3000);
+        client.add_object("key1", body[..].into());
+        client.fail_next_get_object_bodies(1);
+
+        // The first response fails after returning half of the requested range
+        let mut get_request = client
+            .get_object("test_bucket", "key1", Some(100..3000), None)
+            .await
+            .expect("should not fail");
+        let mut accum = vec![];
+        let err = loop {
+            match get_request.next().await.expect("body should not end without an error") {
+                Ok((offset, part)) => {
+                    assert_eq!(offset, 100 + accum.len() as u64);
+                    accum.extend_from_slice(&part);
+                }
+                Err(e) => break e,
+            }
+        };
+        assert!(matches!(
+            err,
+            ObjectClientError::ClientError(MockClientError(ref m)) if m == "incomplete response body"
+        ));
+        assert_eq!(&accum[..], &body[100..1550]);
+        assert!(get_request.next().await.is_none());
+
+        // Later responses are complete
+        let get_request = client
+            .get_object("test_bucket", "key1", Some(100..3000), None)
+            .await
+            .expect("should not fail");
+        let accum = get_request.collect().await.expect("body should be complete");
+        assert_eq!(&accum[..], &body[100..3000]);
     }
 
     #[tokio::test]
@@ -1064,6 +1573,46 @@ mod tests {
         check_continuation!("/", 2, "dirs/dir2/", &keys[7..9], &[]);
     }
 
+    #[tokio::test]
+    async fn list_objects_start_after() {
+        let client = MockClient::new(MockClientConfig {
+            bucket: "test_bucket".to_string(),
+            part_size: 1024,
+            unordered_list_seed: None,
+        });
+        for key in ["dir/a", "dir/a-b", "dir/a/x", "dir/b", "dir/c/y", "other"] {
+            client.add_object(key, MockObject::constant(0u8, 5, ETag::for_tests()));
+        }
+
+        // Keys after the start-after key, and the common prefixes of those keys
+        let result = client
+            .list_objects_start_after("test_bucket", "dir/a", "/", 1000, "dir/")
+            .await
+            .expect("should not fail");
+        let keys: Vec<_> = result.objects.iter().map(|object| object.key.as_str()).collect();
+        assert_eq!(keys, ["dir/a-b", "dir/b"]);
+        assert_eq!(result.common_prefixes, ["dir/a/", "dir/c/"]);
+        assert!(result.next_continuation_token.is_none());
+
+        // Later pages continue from the first
+        let mut listed = vec![];
+        let mut result = client
+            .list_objects_start_after("test_bucket", "dir/a-b", "", 1, "dir/")
+            .await
+            .expect("should not fail");
+        loop {
+            listed.extend(result.objects.into_iter().map(|object| object.key));
+            let Some(token) = result.next_continuation_token else {
+                break;
+            };
+            result = client
+                .list_objects("test_bucket", Some(&token), "", 1, "dir/")
+                .await
+                .expect("should not fail");
+        }
+        assert_eq!(listed, ["dir/a/x", "dir/b", "dir/c/y"]);
+    }
+
     #[tokio::test]
     async fn list_objects_unicode() {
         let client = MockClient::new(MockClientConfig {
@@ -1356,6 +1905,111 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_put_object_from_parts() {
+        let client = MockClient::new(MockClientConfig {
+            bucket: "test_bucket".to_string(),
+            part_size: 1024,
+            unordered_list_seed: None,
+        });
+        let obj = MockObject::ramp(0xaa, 3000, ETag::for_tests());
+        client.add_object("key1", obj.clone());
+
+        let parts = [
+            UploadPartSource::Copy(0..1024),
+            UploadPartSource::Data(Bytes::from_static(&[0xff; 1024])),
+            UploadPartSource::Copy(2048..3000),
+        ];
+
+        let other_etag = ETag::from_str("other").unwrap();
+        let err = client
+            .put_object_from_parts("test_bucket", "key1", &other_etag, &parts, &Default::default())
+            .await
+            .expect_err("source ETag does not match");
+        assert!(matches!(
+            err,
+            ObjectClientError::ServiceError(PutObjectError::PreconditionFailed)
+        ));
+
+        let copy_counter = client.new_counter(Operation::UploadPartCopy);
+        let upload_counter = client.new_counter(Operation::UploadPart);
+        client
+            .put_object_from_parts("test_bucket", "key1", &ETag::for_tests(), &parts, &Default::default())
+            .await
+            .expect("put_object_from_parts failed");
+        assert_eq!(copy_counter.count(), 2);
+        assert_eq!(upload_counter.count(), 1);
+
+        let body = client
+            .get_object("test_bucket", "key1", None, None)
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        assert_eq!(&body[..1024], &obj.read(0, 1024)[..]);
+        assert_eq!(&body[1024..2048], &[0xff; 1024][..]);
+        assert_eq!(&body[2048..], &obj.read(2048, 952)[..]);
+    }
+
+    #[tokio::test]
+    async fn test_copy_object() {
+        let client = MockClient::new(MockClientConfig {
+            bucket: "test_bucket".to_string(),
+            part_size: 1024,
+            unordered_list_seed: None,
+        });
+        let obj = MockObject::ramp(0xaa, 3000, ETag::for_tests());
+        client.add_object("key1", obj.clone());
+
+        let err = client
+            .copy_object("test_bucket", "missing", "key2", None, &Default::default())
+            .await
+            .expect_err("source does not exist");
+        assert!(matches!(
+            err,
+            ObjectClientError::ServiceError(CopyObjectError::NotFound)
+        ));
+        assert!(!client.contains_key("key2"));
+
+        let err = client
+            .copy_object(
+                "test_bucket",
+                "key1",
+                "key2",
+                Some(&ETag::from_str("\"other\"").unwrap()),
+                &Default::default(),
+            )
+            .await
+            .expect_err("source has a different ETag");
+        assert!(matches!(
+            err,
+            ObjectClientError::ServiceError(CopyObjectError::PreconditionFailed)
+        ));
+        assert!(!client.contains_key("key2"));
+
+        client
+            .copy_object(
+                "test_bucket",
+                "key1",
+                "key2",
+                Some(&ETag::for_tests()),
+                &Default::default(),
+            )
+            .await
+            .expect("copy_object failed");
+        assert!(client.contains_key("key1"));
+
+        let body = client
+            .get_object("test_bucket", "key2", None, None)
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        assert_eq!(&body[..], &obj.read(0, 3000)[..]);
+    }
+
     proptest::proptest! {
         #[test]
         fn test_ramp(size in 1..2*RAMP_BUFFER_SIZE, read_size in 1..2*RAMP_BUFFER_SIZE, offset in 0..RAMP_BUFFER_SIZE) {
@@ -1400,6 +2054,37 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_unknown_size() {
+        let bucket = "test_bucket";
+        let client = MockClient::new(MockClientConfig {
+            bucket: bucket.to_owned(),
+            part_size: 1024,
+            unordered_list_seed: None,
+        });
+
+        let mut object = MockObject::ramp(0x11, 3000, ETag::for_tests());
+        object.set_unknown_size(true);
+        client.add_object("key1", object);
+
+        let head_result = client.head_object(bucket, "key1").await.unwrap();
+        assert!(head_result.object.unknown_size);
+        assert_eq!(head_result.object.size, 0);
+
+        let list_result = client.list_objects(bucket, None, "/", 1, "").await.unwrap();
+        assert!(matches!(&list_result.objects[..], [object] if object.unknown_size && object.size == 0));
+
+        // The whole object can still be read
+        let body = client
+            .get_object(bucket, "key1", None, None)
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        assert_eq!(body.len(), 3000);
+    }
+
     #[tokio::test]
     async fn counter_test() {
         let bucket = "test_bucket";
@@ -1428,6 +2113,84 @@ mod tests {
         assert_eq!(1, head_counter_2.count());
     }
 
+    #[tokio::test]
+    async fn delete_objects_test() {
+        let bucket = "test_bucket";
+        let client = MockClient::new(MockClientConfig {
+            bucket: bucket.to_owned(),
+            part_size: 1024,
+            unordered_list_seed: None,
+        });
+        for key in ["a", "b", "c"] {
+            client.add_object(key, MockObject::constant(0u8, 5, ETag::for_tests()));
+        }
+        client.set_key_undeletable("b", true);
+
+        let keys = ["a", "b", "missing"].map(String::from);
+        let result = client.delete_objects(bucket, &keys).await.unwrap();
+        assert_eq!(
+            result.errors,
+            vec![DeleteObjectsKeyError::new("b", "AccessDenied", "Access Denied")]
+        );
+        assert!(!client.contains_key("a"));
+        assert!(client.contains_key("b"));
+        assert!(client.contains_key("c"));
+
+        let too_many: Vec<_> = (0..=MAX_DELETE_OBJECTS_KEYS).map(|i| format!("key{i}")).collect();
+        client
+            .delete_objects(bucket, &too_many)
+            .await
+            .expect_err("too many keys for one request");
+
+        let result = client.delete_objects("other_bucket", &keys).await;
+        assert!(matches!(
+            result,
+            Err(ObjectClientError::ServiceError(DeleteObjectError::NoSuchBucket))
+        ));
+    }
+
+    #[tokio::test]
+    async fn forbidden_key_test() {
+        let bucket = "test_bucket";
+        let client = MockClient::new(MockClientConfig {
+            bucket: bucket.to_owned(),
+            part_size: 1024,
+            unordered_list_seed: None,
+        });
+        for key in ["a", "b"] {
+            client.add_object(key, MockObject::constant(0u8, 5, ETag::for_tests()));
+        }
+        client.set_key_forbidden("a", true);
+
+        fn is_access_denied<S>(client: &MockClient, err: &ObjectClientError<S, MockClientError>) -> bool {
+            matches!(err, ObjectClientError::ClientError(e) if client.is_access_denied(e))
+        }
+        let err = client.head_object(bucket, "a").await.expect_err("key is forbidden");
+        assert!(is_access_denied(&client, &err), "unexpected error: {err:?}");
+        let err = client
+            .get_object(bucket, "a", None, None)
+            .await
+            .err()
+            .expect("key is forbidden");
+        assert!(is_access_denied(&client, &err), "unexpected error: {err:?}");
+        client
+            .head_object(bucket, "b")
+            .await
+            .expect("other keys are unaffected");
+
+        let err = client
+            .head_object(bucket, "missing")
+            .await
+            .expect_err("key doesn't exist");
+        assert!(
+            matches!(err, ObjectClientError::ServiceError(HeadObjectError::NotFound)),
+            "unexpected error: {err:?}"
+        );
+
+        client.set_key_forbidden("a", false);
+        client.head_object(bucket, "a").await.expect("key is allowed again");
+    }
+
     #[test_case(PutObjectTrailingChecksums::Enabled; "enabled")]
     #[test_case(PutObjectTrailingChecksums::ReviewOnly; "review only")]
     #[test_case(PutObjectTrailingChecksums::Disabled; "disabled")]
@@ -1,5 +1,6 @@
 use std::ops::Range;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 use std::time::Duration;
 
@@ -11,9 +12,10 @@ use pin_project::pin_project;
 use crate::mock_client::leaky_bucket::LeakyBucket;
 use crate::mock_client::{MockClient, MockClientConfig, MockClientError, MockObject, MockPutObjectRequest};
 use crate::object_client::{
-    DeleteObjectError, DeleteObjectResult, GetBodyPart, GetObjectAttributesError, GetObjectAttributesResult,
-    GetObjectError, HeadObjectError, HeadObjectResult, ListObjectsError, ListObjectsResult, ObjectAttribute,
-    ObjectClient, ObjectClientResult, PutObjectError, PutObjectParams,
+    BodyBufferPool, CopyObjectError, CopyObjectResult, DeleteObjectError, DeleteObjectResult, DeleteObjectsResult,
+    GetBodyPart, GetObjectAttributesError, GetObjectAttributesResult, GetObjectError, HeadObjectError,
+    HeadObjectResult, ListObjectsError, ListObjectsResult, ObjectAttribute, ObjectClient, ObjectClientResult,
+    PutObjectError, PutObjectParams, PutObjectResult, UploadPartSource,
 };
 use crate::types::ETag;
 
@@ -54,6 +56,11 @@ impl ThroughputMockClient {
     pub fn add_object(&self, key: &str, value: MockObject) {
         self.inner.add_object(key, value);
     }
+
+    /// Copy the bodies of GetObject responses into buffers rented from `pool`
+    pub fn set_body_buffer_pool(&self, pool: Arc<dyn BodyBufferPool>) {
+        self.inner.set_body_buffer_pool(pool);
+    }
 }
 
 #[pin_project]
@@ -81,6 +88,10 @@ impl ObjectClient for ThroughputMockClient {
         self.inner.part_size()
     }
 
+    fn is_access_denied(&self, error: &Self::ClientError) -> bool {
+        self.inner.is_access_denied(error)
+    }
+
     async fn delete_object(
         &self,
         bucket: &str,
@@ -89,6 +100,27 @@ impl ObjectClient for ThroughputMockClient {
         self.inner.delete_object(bucket, key).await
     }
 
+    async fn delete_objects(
+        &self,
+        bucket: &str,
+        keys: &[String],
+    ) -> ObjectClientResult<DeleteObjectsResult, DeleteObjectError, Self::ClientError> {
+        self.inner.delete_objects(bucket, keys).await
+    }
+
+    async fn copy_object(
+        &self,
+        bucket: &str,
+        source_key: &str,
+        destination_key: &str,
+        source_etag: Option<&ETag>,
+        params: &PutObjectParams,
+    ) -> ObjectClientResult<CopyObjectResult, CopyObjectError, Self::ClientError> {
+        self.inner
+            .copy_object(bucket, source_key, destination_key, source_etag, params)
+            .await
+    }
+
     async fn get_object(
         &self,
         bucket: &str,
@@ -123,6 +155,19 @@ impl ObjectClient for ThroughputMockClient {
             .await
     }
 
+    async fn list_objects_start_after(
+        &self,
+        bucket: &str,
+        start_after: &str,
+        delimiter: &str,
+        max_keys: usize,
+        prefix: &str,
+    ) -> ObjectClientResult<ListObjectsResult, ListObjectsError, Self::ClientError> {
+        self.inner
+            .list_objects_start_after(bucket, start_after, delimiter, max_keys, prefix)
+            .await
+    }
+
     async fn head_object(
         &self,
         bucket: &str,
@@ -140,6 +185,19 @@ impl ObjectClient for ThroughputMockClient {
         self.inner.put_object(bucket, key, params).await
     }
 
+    async fn put_object_from_parts(
+        &self,
+        bucket: &str,
+        key: &str,
+        source_etag: &ETag,
+        parts: &[UploadPartSource],
+        params: &PutObjectParams,
+    ) -> ObjectClientResult<PutObjectResult, PutObjectError, Self::ClientError> {
+        self.inner
+            .put_object_from_parts(bucket, key, source_etag, parts, params)
+            .await
+    }
+
     async fn get_object_attributes(
         &self,
         bucket: &str,
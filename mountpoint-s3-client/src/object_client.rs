@@ -1,5 +1,6 @@
 use async_trait::async_trait;
 use auto_impl::auto_impl;
+use bytes::Bytes;
 use futures::Stream;
 use std::str::FromStr;
 use std::time::SystemTime;
@@ -13,7 +14,27 @@ use time::OffsetDateTime;
 
 /// A single element of a [`get_object`](ObjectClient::get_object) response stream is a pair of
 /// offset within the object and the bytes starting at that offset.
-pub type GetBodyPart = (u64, Box<[u8]>);
+///
+/// The bytes are reference counted, so consumers can split and slice them without copying.
+pub type GetBodyPart = (u64, Bytes);
+
+/// A source of buffers for the bodies of [`get_object`](ObjectClient::get_object) responses.
+///
+/// Clients configured with a pool copy response bodies straight into buffers rented from it, rather
+/// than into buffers they allocate themselves, so the pool can reuse that memory and account for
+/// it. The [GetBodyPart]s the client returns are then slices of frozen pooled buffers.
+pub trait BodyBufferPool: std::fmt::Debug + Send + Sync {
+    /// Capacity, in bytes, of the buffers the pool rents out
+    fn buffer_size(&self) -> usize;
+
+    /// Rent an empty buffer with a capacity of [buffer_size](Self::buffer_size) bytes, or `None`
+    /// if the pool has none to spare, in which case the client allocates its own.
+    fn rent(&self) -> Option<Vec<u8>>;
+
+    /// Freeze a rented buffer into [Bytes], which give it back to the pool once they, and every
+    /// [Bytes] split from them, have been dropped.
+    fn freeze(&self, buffer: Vec<u8>) -> Bytes;
+}
 
 /// An ETag (entity tag) is a unique identifier for a HTTP object.
 ///
@@ -79,6 +100,14 @@ pub trait ObjectClient {
     /// can be `None` if the client does not do multi-part operations.
     fn part_size(&self) -> Option<usize>;
 
+    /// Whether a request failed with `error` because the caller isn't allowed to make it, like a
+    /// 403 response from S3. Making the same request again won't succeed unless permissions
+    /// change. Clients that can't tell return `false`, which is the default.
+    fn is_access_denied(&self, error: &Self::ClientError) -> bool {
+        let _ = error;
+        false
+    }
+
     /// Delete a single object from the object store.
     ///
     /// DeleteObject will succeed even if the object within the bucket does not exist.
@@ -88,6 +117,28 @@ pub trait ObjectClient {
         key: &str,
     ) -> ObjectClientResult<DeleteObjectResult, DeleteObjectError, Self::ClientError>;
 
+    /// Delete up to [MAX_DELETE_OBJECTS_KEYS] objects from the object store in a single request.
+    ///
+    /// Like [delete_object](Self::delete_object), deleting a key that doesn't exist succeeds. Keys
+    /// that couldn't be deleted don't fail the request, but are reported in the result instead.
+    async fn delete_objects(
+        &self,
+        bucket: &str,
+        keys: &[String],
+    ) -> ObjectClientResult<DeleteObjectsResult, DeleteObjectError, Self::ClientError>;
+
+    /// Copy an existing object to a new key in the same bucket, server-side. Any object already at
+    /// `destination_key` is replaced. If `source_etag` is given, the copy fails with
+    /// [CopyObjectError::PreconditionFailed] unless the source object still has that ETag.
+    async fn copy_object(
+        &self,
+        bucket: &str,
+        source_key: &str,
+        destination_key: &str,
+        source_etag: Option<&ETag>,
+        params: &PutObjectParams,
+    ) -> ObjectClientResult<CopyObjectResult, CopyObjectError, Self::ClientError>;
+
     /// Get an object from the object store. Returns a stream of body parts of the object. Parts are
     /// guaranteed to be returned by the stream in order and contiguously.
     async fn get_object(
@@ -108,6 +159,18 @@ pub trait ObjectClient {
         prefix: &str,
     ) -> ObjectClientResult<ListObjectsResult, ListObjectsError, Self::ClientError>;
 
+    /// List the objects in a bucket under a given prefix whose keys come after `start_after`,
+    /// including the common prefixes of those keys. Further pages are fetched with
+    /// [list_objects](Self::list_objects) using the returned continuation token.
+    async fn list_objects_start_after(
+        &self,
+        bucket: &str,
+        start_after: &str,
+        delimiter: &str,
+        max_keys: usize,
+        prefix: &str,
+    ) -> ObjectClientResult<ListObjectsResult, ListObjectsError, Self::ClientError>;
+
     /// Retrieve object metadata without retrieving the object contents
     async fn head_object(
         &self,
@@ -124,6 +187,19 @@ pub trait ObjectClient {
         params: &PutObjectParams,
     ) -> ObjectClientResult<Self::PutObjectRequest, PutObjectError, Self::ClientError>;
 
+    /// Replace an existing object with a new one assembled from `parts` in a multi-part upload.
+    /// [Copy](UploadPartSource::Copy) parts are copied server-side from the existing object, which
+    /// must still have the ETag `source_etag`, and [Data](UploadPartSource::Data) parts are
+    /// uploaded from the given bytes.
+    async fn put_object_from_parts(
+        &self,
+        bucket: &str,
+        key: &str,
+        source_etag: &ETag,
+        parts: &[UploadPartSource],
+        params: &PutObjectParams,
+    ) -> ObjectClientResult<PutObjectResult, PutObjectError, Self::ClientError>;
+
     /// Retrieves all the metadata from an object without returning the object contents.
     async fn get_object_attributes(
         &self,
@@ -176,6 +252,11 @@ pub enum GetObjectError {
 
     #[error("At least one of the preconditions specified did not hold")]
     PreconditionFailed,
+
+    /// The requested range doesn't overlap the object, or, for the mock client, extends past its
+    /// end. Holds the object's actual size, if S3 reported it.
+    #[error("The requested range is not satisfiable")]
+    InvalidRange { object_size: Option<u64> },
 }
 
 /// Result of a [`list_objects`](ObjectClient::list_objects) request
@@ -210,6 +291,9 @@ pub struct HeadObjectResult {
 
     /// Object metadata
     pub object: ObjectInfo,
+
+    /// The object's `Content-Encoding`, if it has one
+    pub content_encoding: Option<String>,
 }
 
 /// Errors returned by a [`head_object`](ObjectClient::head_object) request
@@ -237,6 +321,58 @@ pub enum DeleteObjectError {
     NoSuchBucket,
 }
 
+/// The most keys a single [`delete_objects`](ObjectClient::delete_objects) request can delete
+pub const MAX_DELETE_OBJECTS_KEYS: usize = 1000;
+
+/// Result of a [`delete_objects`](ObjectClient::delete_objects) request
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct DeleteObjectsResult {
+    /// The keys that could not be deleted. Every other key in the request was deleted.
+    pub errors: Vec<DeleteObjectsKeyError>,
+}
+
+/// A key that a [`delete_objects`](ObjectClient::delete_objects) request failed to delete
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DeleteObjectsKeyError {
+    /// The key that wasn't deleted
+    pub key: String,
+    /// The error code returned for the key, such as `AccessDenied`
+    pub code: String,
+    /// A description of the error
+    pub message: String,
+}
+
+impl DeleteObjectsKeyError {
+    pub fn new(key: impl Into<String>, code: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            code: code.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Result of a [`copy_object`](ObjectClient::copy_object) request
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct CopyObjectResult {}
+
+/// Errors returned by a [`copy_object`](ObjectClient::copy_object) request
+#[derive(Debug, Error, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CopyObjectError {
+    #[error("The source object does not exist")]
+    NotFound,
+
+    #[error("The bucket does not exist")]
+    NoSuchBucket,
+
+    #[error("The source object's ETag didn't match")]
+    PreconditionFailed,
+}
+
 /// Result of a [`get_object_attributes`](ObjectClient::get_object_attributes) request
 #[derive(Debug, Default)]
 pub struct GetObjectAttributesResult {
@@ -280,6 +416,11 @@ pub struct PutObjectParams {
     /// If `server_side_encryption` has a valid value of aws:kms or aws:kms:dsse, this value may be used to specify AWS KMS key ID to be used
     /// when creating new S3 object
     pub ssekms_key_id: Option<String>,
+    /// Send the base64-encoded MD5 digest of the payload in a `Content-MD5` header, for each part
+    /// of a multi-part upload. Some buckets (for example, those with Object Lock enabled) require it.
+    /// The parts are then sent without [trailing checksums](Self::trailing_checksums), which are
+    /// only computed for the upload review.
+    pub content_md5: bool,
 }
 
 impl PutObjectParams {
@@ -311,6 +452,12 @@ impl PutObjectParams {
         self.ssekms_key_id = value;
         self
     }
+
+    /// Set whether to send a `Content-MD5` header with the payload.
+    pub fn content_md5(mut self, value: bool) -> Self {
+        self.content_md5 = value;
+        self
+    }
 }
 
 /// How CRC32c checksums are used for parts of a multi-part PutObject request
@@ -372,12 +519,42 @@ pub struct PutObjectResult {
     pub sse_kms_key_id: Option<String>,
 }
 
-/// Errors returned by a [`put_object`](ObjectClient::put_object) request
+/// Errors returned by a [`put_object`](ObjectClient::put_object) or
+/// [`put_object_from_parts`](ObjectClient::put_object_from_parts) request
 #[derive(Debug, Error, PartialEq, Eq)]
 #[non_exhaustive]
 pub enum PutObjectError {
     #[error("The bucket does not exist")]
     NoSuchBucket,
+
+    /// The object to copy parts from no longer exists or has a different ETag
+    #[error("The source object does not exist or has changed")]
+    PreconditionFailed,
+}
+
+/// The content of one part of a [`put_object_from_parts`](ObjectClient::put_object_from_parts)
+/// request
+#[derive(Debug, Clone)]
+pub enum UploadPartSource {
+    /// Copy the given range of the existing object server-side
+    Copy(Range<u64>),
+    /// Upload the given bytes
+    Data(Bytes),
+}
+
+impl UploadPartSource {
+    /// The size of this part in bytes
+    pub fn len(&self) -> u64 {
+        match self {
+            UploadPartSource::Copy(range) => range.end - range.start,
+            UploadPartSource::Data(bytes) => bytes.len() as u64,
+        }
+    }
+
+    /// Whether this part is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 }
 
 /// Restoration status for S3 objects in flexible retrieval storage classes.
@@ -408,6 +585,11 @@ pub struct ObjectInfo {
     /// Size of this object in bytes.
     pub size: u64,
 
+    /// The object's size isn't known until it's read, and `size` is 0. This is the case for
+    /// objects served through an S3 Object Lambda access point whose transformation doesn't report
+    /// a `Content-Length` in advance.
+    pub unknown_size: bool,
+
     /// The time this object was last modified.
     pub last_modified: OffsetDateTime,
 
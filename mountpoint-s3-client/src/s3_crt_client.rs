@@ -33,13 +33,17 @@ use futures::channel::oneshot;
 use percent_encoding::{percent_encode, AsciiSet, NON_ALPHANUMERIC};
 use pin_project::{pin_project, pinned_drop};
 use thiserror::Error;
-use tracing::{debug, error, trace, Span};
+use tracing::{debug, error, info, trace, Span};
 
 use self::get_object::S3GetObjectRequest;
 use self::put_object::S3PutObjectRequest;
+use self::put_object_from_parts::URLENCODE_COPY_SOURCE;
+use self::retry_classifier::RetryClassifier;
+pub use self::retry_classifier::{RetryDecision, S3Error};
 use crate::endpoint_config::EndpointConfig;
 use crate::endpoint_config::EndpointError;
 use crate::object_client::*;
+use crate::proxy_config::ProxyConfig;
 use crate::user_agent::UserAgent;
 
 macro_rules! request_span {
@@ -55,12 +59,16 @@ macro_rules! request_span {
     ($self:expr, $method:expr) => { request_span!($self, $method,) };
 }
 
+pub(crate) mod copy_object;
 pub(crate) mod delete_object;
+pub(crate) mod delete_objects;
 pub(crate) mod get_object;
 pub(crate) mod get_object_attributes;
 pub(crate) mod head_object;
 pub(crate) mod list_objects;
 pub(crate) mod put_object;
+pub(crate) mod put_object_from_parts;
+pub(crate) mod retry_classifier;
 
 pub(crate) mod head_bucket;
 pub use head_bucket::HeadBucketError;
@@ -83,6 +91,7 @@ macro_rules! event {
 #[derive(Debug, Clone)]
 pub struct S3ClientConfig {
     auth_config: S3ClientAuthConfig,
+    prefix_auth_configs: Vec<(String, S3ClientAuthConfig)>,
     throughput_target_gbps: f64,
     part_size: usize,
     endpoint_config: EndpointConfig,
@@ -90,12 +99,17 @@ pub struct S3ClientConfig {
     request_payer: Option<String>,
     bucket_owner: Option<String>,
     max_attempts: Option<NonZeroUsize>,
+    compute_content_md5: bool,
+    retry_classifier: Option<RetryClassifier>,
+    proxy: Option<ProxyConfig>,
+    body_buffer_pool: Option<Arc<dyn BodyBufferPool>>,
 }
 
 impl Default for S3ClientConfig {
     fn default() -> Self {
         Self {
             auth_config: Default::default(),
+            prefix_auth_configs: Vec::new(),
             throughput_target_gbps: 10.0,
             part_size: 8 * 1024 * 1024,
             endpoint_config: EndpointConfig::new("us-east-1"),
@@ -103,6 +117,10 @@ impl Default for S3ClientConfig {
             request_payer: None,
             bucket_owner: None,
             max_attempts: None,
+            compute_content_md5: false,
+            retry_classifier: None,
+            proxy: None,
+            body_buffer_pool: None,
         }
     }
 }
@@ -119,6 +137,19 @@ impl S3ClientConfig {
         self
     }
 
+    /// Authenticate requests for keys starting with `prefix` with `auth_config` instead of the
+    /// client's [auth_config](Self::auth_config). When several configured prefixes match a key, the
+    /// longest one is used. Server-side copies are matched by their destination key, so those
+    /// credentials must also be allowed to read the source. Requests that aren't for a single key
+    /// are matched by the longest prefix shared by all their keys: ListObjectsV2 by its listing
+    /// prefix, and DeleteObjects by the keys it deletes. HeadBucket always uses the client's own
+    /// configuration.
+    #[must_use = "S3ClientConfig follows a builder pattern"]
+    pub fn prefix_auth_config(mut self, prefix: &str, auth_config: S3ClientAuthConfig) -> Self {
+        self.prefix_auth_configs.push((prefix.to_owned(), auth_config));
+        self
+    }
+
     /// Set the part size for multi-part operations to S3 (both PUT and GET)
     #[must_use = "S3ClientConfig follows a builder pattern"]
     pub fn part_size(mut self, part_size: usize) -> Self {
@@ -168,6 +199,46 @@ impl S3ClientConfig {
         self.max_attempts = Some(max_attempts);
         self
     }
+
+    /// Compute the MD5 of each uploaded part and send it in a `Content-MD5` header. Required to
+    /// make [PutObjectParams::content_md5] requests.
+    #[must_use = "S3ClientConfig follows a builder pattern"]
+    pub fn compute_content_md5(mut self, compute_content_md5: bool) -> Self {
+        self.compute_content_md5 = compute_content_md5;
+        self
+    }
+
+    /// Retry requests that fail with error responses the client doesn't recognize, when
+    /// `classifier` returns [RetryDecision::Retry] for them. Requests are attempted at most
+    /// [max_attempts](Self::max_attempts) times in total. Errors the client already retries, like
+    /// 5xx and throttling responses, aren't given to `classifier`.
+    ///
+    /// This lets the client retry transient errors from S3-compatible services that report them
+    /// differently to S3. It applies to every request except GetObject and PutObject, whose
+    /// streamed bodies can't be replayed.
+    #[must_use = "S3ClientConfig follows a builder pattern"]
+    pub fn retry_classifier(mut self, classifier: impl Fn(&S3Error) -> RetryDecision + Send + Sync + 'static) -> Self {
+        self.retry_classifier = Some(RetryClassifier::new(classifier));
+        self
+    }
+
+    /// Send all requests through the given HTTP proxy. Without this, the client uses the proxy
+    /// configured by the `HTTPS_PROXY` and `NO_PROXY` environment variables, if any; see
+    /// [ProxyConfig] for details.
+    #[must_use = "S3ClientConfig follows a builder pattern"]
+    pub fn proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Copy the bodies of GetObject responses into buffers rented from `pool`, rather than into
+    /// buffers the client allocates itself. The client still allocates its own buffers when the
+    /// pool has none to spare.
+    #[must_use = "S3ClientConfig follows a builder pattern"]
+    pub fn body_buffer_pool(mut self, pool: Arc<dyn BodyBufferPool>) -> Self {
+        self.body_buffer_pool = Some(pool);
+        self
+    }
 }
 
 /// Authentication configuration for the CRT-based S3 client
@@ -215,6 +286,24 @@ impl S3CrtClient {
     pub fn event_loop_group(&self) -> EventLoopGroup {
         self.inner.event_loop_group.clone()
     }
+
+    /// Run `request`, retrying it if it fails with an error the configured [RetryClassifier]
+    /// marks as retryable. The classifier's retries are attempts of their own, so a request is
+    /// made at most `max_attempts` times whichever of the CRT and the classifier retries it.
+    async fn with_retry_classifier<T, E, F, Fut>(&self, mut request: F) -> ObjectClientResult<T, E, S3RequestError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = ObjectClientResult<T, E, S3RequestError>>,
+    {
+        match &self.inner.retry_classifier {
+            Some(classifier) => {
+                classifier
+                    .run(&self.inner.event_loop_group, self.inner.max_attempts, request)
+                    .await
+            }
+            None => request().await,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -230,8 +319,70 @@ struct S3CrtClientInner {
     request_payer: Option<String>,
     part_size: usize,
     bucket_owner: Option<String>,
+    compute_content_md5: bool,
     credentials_provider: Option<CredentialsProvider>,
+    /// Providers for keys under particular prefixes, used instead of [Self::credentials_provider]
+    prefix_credentials_providers: Vec<(String, CredentialsProvider)>,
     host_resolver: HostResolver,
+    retry_classifier: Option<RetryClassifier>,
+    max_attempts: usize,
+    body_buffer_pool: Option<Arc<dyn BodyBufferPool>>,
+}
+
+/// Read the proxy for requests to the S3 endpoint from the environment. The client isn't tied to a
+/// bucket, so `NO_PROXY` is checked against the endpoint for a placeholder bucket, without the
+/// bucket's own label when it's addressed virtually. If the endpoint can't be resolved, it's
+/// treated as an `https://` endpoint that `NO_PROXY` doesn't exclude.
+fn proxy_from_env(endpoint_config: &EndpointConfig) -> Result<Option<ProxyConfig>, NewClientError> {
+    const PLACEHOLDER_BUCKET: &str = "mountpoint-proxy-check";
+
+    let endpoint = endpoint_config
+        .resolve_for_bucket(PLACEHOLDER_BUCKET)
+        .and_then(|endpoint| endpoint.uri());
+    let (scheme, host) = match &endpoint {
+        Ok(uri) => {
+            let host = uri.host_name().to_string_lossy();
+            let host = host
+                .strip_prefix(&format!("{PLACEHOLDER_BUCKET}."))
+                .map_or_else(|| host.to_string(), str::to_owned);
+            (uri.scheme(), host)
+        }
+        Err(e) => {
+            debug!(error=?e, "could not resolve the S3 endpoint to check NO_PROXY against");
+            (OsStr::new("https"), String::new())
+        }
+    };
+    ProxyConfig::from_env(scheme, &host).map_err(|e| NewClientError::InvalidConfiguration(e.to_string()))
+}
+
+/// Create the credentials provider for an [S3ClientAuthConfig]
+fn new_credentials_provider(
+    allocator: &Allocator,
+    client_bootstrap: &mut ClientBootstrap,
+    auth_config: S3ClientAuthConfig,
+) -> Result<CredentialsProvider, NewClientError> {
+    let provider = match auth_config {
+        S3ClientAuthConfig::Default => {
+            let credentials_chain_default_options = CredentialsProviderChainDefaultOptions {
+                bootstrap: client_bootstrap,
+            };
+            CredentialsProvider::new_chain_default(allocator, credentials_chain_default_options)
+                .map_err(NewClientError::ProviderFailure)?
+        }
+        S3ClientAuthConfig::NoSigning => {
+            CredentialsProvider::new_anonymous(allocator).map_err(NewClientError::ProviderFailure)?
+        }
+        S3ClientAuthConfig::Profile(profile_name) => {
+            let credentials_profile_options = CredentialsProviderProfileOptions {
+                bootstrap: client_bootstrap,
+                profile_name_override: &profile_name,
+            };
+            CredentialsProvider::new_profile(allocator, credentials_profile_options)
+                .map_err(NewClientError::ProviderFailure)?
+        }
+        S3ClientAuthConfig::Provider(provider) => provider,
+    };
+    Ok(provider)
 }
 
 impl S3CrtClientInner {
@@ -256,13 +407,13 @@ impl S3CrtClientInner {
 
         let mut client_config = ClientConfig::new();
 
+        let max_attempts = std::env::var("AWS_MAX_ATTEMPTS")
+            .ok()
+            .and_then(|s| s.parse::<usize>().ok())
+            .or_else(|| config.max_attempts.map(|m| m.get()))
+            .unwrap_or(3);
         let retry_strategy = {
             let mut retry_strategy_options = StandardRetryOptions::default(&mut event_loop_group);
-            let max_attempts = std::env::var("AWS_MAX_ATTEMPTS")
-                .ok()
-                .and_then(|s| s.parse::<usize>().ok())
-                .or_else(|| config.max_attempts.map(|m| m.get()))
-                .unwrap_or(3);
             // Max *attempts* includes the initial attempt, the CRT's max *retries* does not, so
             // decrement by one
             retry_strategy_options.backoff_retry_options.max_retries = max_attempts.saturating_sub(1);
@@ -272,30 +423,27 @@ impl S3CrtClientInner {
         };
 
         trace!("constructing client with auth config {:?}", config.auth_config);
-        let credentials_provider = match config.auth_config {
-            S3ClientAuthConfig::Default => {
-                let credentials_chain_default_options = CredentialsProviderChainDefaultOptions {
-                    bootstrap: &mut client_bootstrap,
-                };
-                CredentialsProvider::new_chain_default(&allocator, credentials_chain_default_options)
-                    .map_err(NewClientError::ProviderFailure)?
-            }
-            S3ClientAuthConfig::NoSigning => {
-                CredentialsProvider::new_anonymous(&allocator).map_err(NewClientError::ProviderFailure)?
-            }
-            S3ClientAuthConfig::Profile(profile_name) => {
-                let credentials_profile_options = CredentialsProviderProfileOptions {
-                    bootstrap: &mut client_bootstrap,
-                    profile_name_override: &profile_name,
-                };
-                CredentialsProvider::new_profile(&allocator, credentials_profile_options)
-                    .map_err(NewClientError::ProviderFailure)?
-            }
-            S3ClientAuthConfig::Provider(provider) => provider,
-        };
+        let credentials_provider = new_credentials_provider(&allocator, &mut client_bootstrap, config.auth_config)?;
+        let prefix_credentials_providers = config
+            .prefix_auth_configs
+            .into_iter()
+            .map(|(prefix, auth_config)| {
+                trace!(prefix, "constructing client with auth config {:?}", auth_config);
+                let provider = new_credentials_provider(&allocator, &mut client_bootstrap, auth_config)?;
+                Ok((prefix, provider))
+            })
+            .collect::<Result<_, NewClientError>>()?;
 
         let endpoint_config = config.endpoint_config;
         client_config.region(endpoint_config.get_region());
+        let proxy = match config.proxy {
+            Some(proxy) => Some(proxy),
+            None => proxy_from_env(&endpoint_config)?,
+        };
+        if let Some(proxy) = proxy {
+            info!("sending S3 requests through proxy {}:{}", proxy.host(), proxy.port());
+            client_config.proxy_options(proxy.to_proxy_options());
+        }
         let signing_config = init_signing_config(
             endpoint_config.get_region(),
             credentials_provider.clone(),
@@ -321,6 +469,7 @@ impl S3CrtClientInner {
             )));
         }
         client_config.part_size(config.part_size);
+        client_config.compute_content_md5(config.compute_content_md5);
 
         let user_agent = config.user_agent.unwrap_or_else(|| UserAgent::new(None));
         let user_agent_header = user_agent.build();
@@ -337,21 +486,26 @@ impl S3CrtClientInner {
             request_payer: config.request_payer,
             part_size: config.part_size,
             bucket_owner: config.bucket_owner,
+            compute_content_md5: config.compute_content_md5,
             credentials_provider: Some(credentials_provider),
+            prefix_credentials_providers,
             host_resolver,
+            retry_classifier: config.retry_classifier,
+            max_attempts,
+            body_buffer_pool: config.body_buffer_pool,
         })
     }
 
-    /// Create a new HTTP request template for the given HTTP method and S3 bucket name.
-    /// Pre-populates common headers used across all requests. Sets the "accept" header assuming the
-    /// response should be XML; this header should be overwritten for requests like GET that return
-    /// object data.
-    fn new_request_template(&self, method: &str, bucket: &str) -> Result<S3Message, ConstructionError> {
+    /// Create a new HTTP request template for the given HTTP method and S3 bucket name, signed with
+    /// the credentials for `key` (see [S3ClientConfig::prefix_auth_config]). Pre-populates common
+    /// headers used across all requests. Sets the "accept" header assuming the response should be
+    /// XML; this header should be overwritten for requests like GET that return object data.
+    fn new_request_template(&self, method: &str, bucket: &str, key: &str) -> Result<S3Message, ConstructionError> {
         let endpoint = self.endpoint_config.resolve_for_bucket(bucket)?;
         let uri = endpoint.uri()?;
         trace!(?uri, "resolved endpoint");
 
-        let signing_config = if let Some(credentials_provider) = &self.credentials_provider {
+        let signing_config = if let Some(credentials_provider) = self.credentials_provider_for_key(key) {
             let auth_scheme = match endpoint.auth_scheme() {
                 Ok(auth_scheme) => auth_scheme,
                 Err(e) => {
@@ -406,6 +560,35 @@ impl S3CrtClientInner {
         })
     }
 
+    /// The credentials provider for requests for `key`: the one for the longest configured prefix
+    /// of `key`, or the client's own provider if no prefix matches.
+    fn credentials_provider_for_key(&self, key: &str) -> Option<&CredentialsProvider> {
+        self.prefix_credentials_providers
+            .iter()
+            .filter(|(prefix, _)| key.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, provider)| provider)
+            .or(self.credentials_provider.as_ref())
+    }
+
+    /// Set the source of a server-side copy to `key` in `bucket`. If an expected bucket owner is
+    /// configured, S3 also checks that the source bucket is owned by it.
+    fn set_copy_source(
+        &self,
+        message: &mut S3Message,
+        bucket: &str,
+        key: &str,
+    ) -> Result<(), mountpoint_s3_crt::common::error::Error> {
+        let copy_source = format!("/{bucket}/{key}");
+        let copy_source = percent_encode(copy_source.as_bytes(), URLENCODE_COPY_SOURCE).to_string();
+        message.set_header(&Header::new("x-amz-copy-source", copy_source))?;
+
+        if let Some(ref owner) = self.bucket_owner {
+            message.set_header(&Header::new("x-amz-source-expected-bucket-owner", owner))?;
+        }
+        Ok(())
+    }
+
     fn new_meta_request_options(message: S3Message, meta_request_type: MetaRequestType) -> MetaRequestOptions {
         let mut options = MetaRequestOptions::new();
         if let Some(checksum_config) = message.checksum_config {
@@ -857,6 +1040,14 @@ pub enum S3RequestError {
     #[error("Unknown response error: {0:?}")]
     ResponseError(MetaRequestResult),
 
+    /// The request reported success, but the response body was cut short or ended with an error
+    #[error("Incomplete response body: {0}")]
+    IncompleteResponseBody(String),
+
+    /// The response to a ranged request holds a different range of the object than was requested
+    #[error("Unexpected Content-Range: {0}")]
+    UnexpectedContentRange(String),
+
     /// The request was made to the wrong region
     #[error("Wrong region (expecting {0})")]
     IncorrectRegion(String),
@@ -889,6 +1080,10 @@ pub enum ConstructionError {
     /// The S3 endpoint was invalid
     #[error("Invalid S3 endpoint")]
     InvalidEndpoint(#[from] EndpointError),
+
+    /// The request needs Content-MD5 but the client was not configured to compute it
+    #[error("Content-MD5 requested but not enabled on the client")]
+    ContentMd5NotEnabled,
 }
 
 /// Return a string version of a [RequestType] for use in metrics
@@ -913,20 +1108,29 @@ fn request_type_to_metrics_string(request_type: RequestType) -> &'static str {
 fn extract_range_header(headers: &Headers) -> Option<Range<u64>> {
     let header = headers.get("Content-Range").ok()?;
     let value = header.value().to_str()?;
+    parse_content_range_header(value).map(|(range, _)| range)
+}
 
+/// Parse the byte range and object size out of a Content-Range header value. The size is `None`
+/// if the server didn't say.
+pub(crate) fn parse_content_range_header(value: &str) -> Option<(Range<u64>, Option<u64>)> {
     // Content-Range: <unit> <range-start>-<range-end>/<size>
 
-    if !value.starts_with("bytes ") {
-        return None;
-    }
-    let (_, value) = value.split_at("bytes ".len());
-    let (range, _) = value.split_once('/')?;
+    let value = value.strip_prefix("bytes ")?;
+    let (range, size) = value.split_once('/')?;
     let (start, end) = range.split_once('-')?;
     let start = start.parse::<u64>().ok()?;
     let end = end.parse::<u64>().ok()?;
+    if end < start {
+        return None;
+    }
+    let size = match size {
+        "*" => None,
+        size => Some(size.parse::<u64>().ok()?),
+    };
 
     // Rust ranges are exclusive at the end, but Content-Range is inclusive
-    Some(start..end + 1)
+    Some((start..end + 1, size))
 }
 
 /// Try to parse a modeled error out of a failing meta request
@@ -1022,12 +1226,38 @@ impl ObjectClient for S3CrtClient {
         Some(self.inner.part_size)
     }
 
+    fn is_access_denied(&self, error: &Self::ClientError) -> bool {
+        matches!(error, S3RequestError::Forbidden(_))
+    }
+
     async fn delete_object(
         &self,
         bucket: &str,
         key: &str,
     ) -> ObjectClientResult<DeleteObjectResult, DeleteObjectError, Self::ClientError> {
-        self.delete_object(bucket, key).await
+        self.with_retry_classifier(move || self.delete_object(bucket, key))
+            .await
+    }
+
+    async fn delete_objects(
+        &self,
+        bucket: &str,
+        keys: &[String],
+    ) -> ObjectClientResult<DeleteObjectsResult, DeleteObjectError, Self::ClientError> {
+        self.with_retry_classifier(move || self.delete_objects(bucket, keys))
+            .await
+    }
+
+    async fn copy_object(
+        &self,
+        bucket: &str,
+        source_key: &str,
+        destination_key: &str,
+        source_etag: Option<&ETag>,
+        params: &PutObjectParams,
+    ) -> ObjectClientResult<CopyObjectResult, CopyObjectError, Self::ClientError> {
+        self.with_retry_classifier(move || self.copy_object(bucket, source_key, destination_key, source_etag, params))
+            .await
     }
 
     async fn get_object(
@@ -1050,8 +1280,24 @@ impl ObjectClient for S3CrtClient {
         max_keys: usize,
         prefix: &str,
     ) -> ObjectClientResult<ListObjectsResult, ListObjectsError, Self::ClientError> {
-        self.list_objects(bucket, continuation_token, delimiter, max_keys, prefix)
-            .await
+        self.with_retry_classifier(move || {
+            self.list_objects(bucket, continuation_token, None, delimiter, max_keys, prefix)
+        })
+        .await
+    }
+
+    async fn list_objects_start_after(
+        &self,
+        bucket: &str,
+        start_after: &str,
+        delimiter: &str,
+        max_keys: usize,
+        prefix: &str,
+    ) -> ObjectClientResult<ListObjectsResult, ListObjectsError, Self::ClientError> {
+        self.with_retry_classifier(move || {
+            self.list_objects(bucket, None, Some(start_after), delimiter, max_keys, prefix)
+        })
+        .await
     }
 
     async fn head_object(
@@ -1059,7 +1305,7 @@ impl ObjectClient for S3CrtClient {
         bucket: &str,
         key: &str,
     ) -> ObjectClientResult<HeadObjectResult, HeadObjectError, Self::ClientError> {
-        self.head_object(bucket, key).await
+        self.with_retry_classifier(move || self.head_object(bucket, key)).await
     }
 
     async fn put_object(
@@ -1071,6 +1317,18 @@ impl ObjectClient for S3CrtClient {
         self.put_object(bucket, key, params).await
     }
 
+    async fn put_object_from_parts(
+        &self,
+        bucket: &str,
+        key: &str,
+        source_etag: &ETag,
+        parts: &[UploadPartSource],
+        params: &PutObjectParams,
+    ) -> ObjectClientResult<PutObjectResult, PutObjectError, Self::ClientError> {
+        self.put_object_from_parts(bucket, key, source_etag, parts, params)
+            .await
+    }
+
     async fn get_object_attributes(
         &self,
         bucket: &str,
@@ -1079,8 +1337,10 @@ impl ObjectClient for S3CrtClient {
         part_number_marker: Option<usize>,
         object_attributes: &[ObjectAttribute],
     ) -> ObjectClientResult<GetObjectAttributesResult, GetObjectAttributesError, Self::ClientError> {
-        self.get_object_attributes(bucket, key, max_parts, part_number_marker, object_attributes)
-            .await
+        self.with_retry_classifier(move || {
+            self.get_object_attributes(bucket, key, max_parts, part_number_marker, object_attributes)
+        })
+        .await
     }
 }
 
@@ -1090,6 +1350,7 @@ mod tests {
     use std::assert_eq;
 
     use super::*;
+    use crate::endpoint_config::AddressingStyle;
     use test_case::test_case;
 
     /// Test explicit validation in [Client::new]
@@ -1140,7 +1401,7 @@ mod tests {
 
         let mut message = client
             .inner
-            .new_request_template("GET", "doc-example-bucket")
+            .new_request_template("GET", "doc-example-bucket", "")
             .expect("new request template expected");
 
         let headers = message.inner.get_headers().expect("Expected a block of HTTP headers");
@@ -1166,7 +1427,7 @@ mod tests {
 
         let mut message = client
             .inner
-            .new_request_template("GET", "doc-example-bucket")
+            .new_request_template("GET", "doc-example-bucket", "")
             .expect("new request template expected");
 
         let headers = message.inner.get_headers().expect("Expected a block of HTTP headers");
@@ -1181,6 +1442,32 @@ mod tests {
             .starts_with(expected_user_agent));
     }
 
+    #[test_case(false, AddressingStyle::Automatic, "doc-example-bucket.s3.eu-west-1.amazonaws.com")]
+    #[test_case(
+        true,
+        AddressingStyle::Automatic,
+        "doc-example-bucket.s3.dualstack.eu-west-1.amazonaws.com"
+    )]
+    #[test_case(true, AddressingStyle::Path, "s3.dualstack.eu-west-1.amazonaws.com")]
+    fn test_dual_stack_host(dual_stack: bool, addressing_style: AddressingStyle, expected_host: &str) {
+        let endpoint_config = EndpointConfig::new("eu-west-1")
+            .use_dual_stack(dual_stack)
+            .addressing_style(addressing_style);
+        let config = S3ClientConfig::new().endpoint_config(endpoint_config);
+        let client = S3CrtClient::new(config).expect("Create test client");
+
+        // Every request starts from this template, so they all go to the same host
+        for method in ["GET", "HEAD", "PUT", "POST", "DELETE"] {
+            let mut message = client
+                .inner
+                .new_request_template(method, "doc-example-bucket", "")
+                .expect("new request template expected");
+            let headers = message.inner.get_headers().expect("Expected a block of HTTP headers");
+            let host = headers.get("Host").expect("the headers should contain Host");
+            assert_eq!(host.value(), expected_host);
+        }
+    }
+
     #[test_case("bytes 200-1000/67589" => Some(200..1001))]
     #[test_case("bytes 200-1000/*" => Some(200..1001))]
     #[test_case("bytes 200-1000" => None)]
@@ -1204,7 +1491,7 @@ mod tests {
 
         let mut message = client
             .inner
-            .new_request_template("GET", "doc-example-bucket")
+            .new_request_template("GET", "doc-example-bucket", "")
             .expect("new request template expected");
 
         let headers = message.inner.get_headers().expect("Expected a block of HTTP headers");
@@ -1219,6 +1506,41 @@ mod tests {
             .starts_with(expected_bucket_owner));
     }
 
+    #[test_case(None; "no expected owner")]
+    #[test_case(Some("111122223333"); "expected owner")]
+    fn test_copy_source_expected_bucket_owner(expected_bucket_owner: Option<&str>) {
+        let mut config = S3ClientConfig::new();
+        if let Some(owner) = expected_bucket_owner {
+            config = config.bucket_owner(owner);
+        }
+        let client = S3CrtClient::new(config).expect("Create test client");
+
+        let mut message = client
+            .inner
+            .new_request_template("PUT", "doc-example-bucket", "")
+            .expect("new request template expected");
+        client
+            .inner
+            .set_copy_source(&mut message, "doc-example-bucket", "dir/source key")
+            .expect("copy source should be set");
+
+        let headers = message.inner.get_headers().expect("Expected a block of HTTP headers");
+        let copy_source = headers
+            .get("x-amz-copy-source")
+            .expect("copy source header should be set");
+        assert_eq!(copy_source.value(), "/doc-example-bucket/dir/source%20key");
+
+        let header_value = |name: &str| headers.get(name).ok().map(|h| h.value().to_string_lossy().into_owned());
+        assert_eq!(
+            header_value("x-amz-expected-bucket-owner").as_deref(),
+            expected_bucket_owner
+        );
+        assert_eq!(
+            header_value("x-amz-source-expected-bucket-owner").as_deref(),
+            expected_bucket_owner
+        );
+    }
+
     fn make_result(
         response_status: i32,
         body: impl Into<OsString>,
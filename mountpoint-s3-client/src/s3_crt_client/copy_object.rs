@@ -0,0 +1,138 @@
+use std::ops::Deref;
+use std::os::unix::prelude::OsStrExt;
+
+use mountpoint_s3_crt::http::request_response::Header;
+use mountpoint_s3_crt::s3::client::{MetaRequestResult, MetaRequestType};
+
+use crate::object_client::{
+    CopyObjectError, CopyObjectResult, ETag, ObjectClientResult, PutObjectParams, PutObjectTrailingChecksums,
+};
+use crate::s3_crt_client::{S3CrtClient, S3RequestError};
+
+const SSE_TYPE_HEADER_NAME: &str = "x-amz-server-side-encryption";
+const SSE_KEY_ID_HEADER_NAME: &str = "x-amz-server-side-encryption-aws-kms-key-id";
+const CHECKSUM_ALGORITHM_HEADER_NAME: &str = "x-amz-checksum-algorithm";
+
+impl S3CrtClient {
+    /// Create and begin a new CopyObject request.
+    pub(super) async fn copy_object(
+        &self,
+        bucket: &str,
+        source_key: &str,
+        destination_key: &str,
+        source_etag: Option<&ETag>,
+        params: &PutObjectParams,
+    ) -> ObjectClientResult<CopyObjectResult, CopyObjectError, S3RequestError> {
+        let span = request_span!(
+            self.inner,
+            "copy_object",
+            bucket,
+            source_key,
+            destination_key,
+            ?source_etag
+        );
+
+        // Scope the endpoint, message, etc. since otherwise rustc thinks we use Message across the await.
+        let request = {
+            let mut message = self
+                .inner
+                .new_request_template("PUT", bucket, destination_key)
+                .map_err(S3RequestError::construction_failure)?;
+            message
+                .set_request_path(format!("/{destination_key}"))
+                .map_err(S3RequestError::construction_failure)?;
+
+            self.inner
+                .set_copy_source(&mut message, bucket, source_key)
+                .map_err(S3RequestError::construction_failure)?;
+
+            let mut headers = vec![];
+            if let Some(etag) = source_etag {
+                headers.push(("x-amz-copy-source-if-match", etag.as_str()));
+            }
+            if let Some(storage_class) = params.storage_class.as_ref() {
+                headers.push(("x-amz-storage-class", storage_class.as_str()));
+            }
+            if let Some(sse) = params.server_side_encryption.as_ref() {
+                headers.push((SSE_TYPE_HEADER_NAME, sse.as_str()));
+            }
+            if let Some(key_id) = params.ssekms_key_id.as_ref() {
+                headers.push((SSE_KEY_ID_HEADER_NAME, key_id.as_str()));
+            }
+            // S3 doesn't carry the source's additional checksum over to the copy unless asked to
+            if params.trailing_checksums == PutObjectTrailingChecksums::Enabled {
+                headers.push((CHECKSUM_ALGORITHM_HEADER_NAME, "CRC32C"));
+            }
+            for (name, value) in headers {
+                message
+                    .set_header(&Header::new(name, value))
+                    .map_err(S3RequestError::construction_failure)?;
+            }
+
+            self.inner
+                .make_simple_http_request(message, MetaRequestType::CopyObject, span, parse_copy_object_error)?
+        };
+
+        let _body = request.await?;
+
+        Ok(CopyObjectResult {})
+    }
+}
+
+fn parse_copy_object_error(result: &MetaRequestResult) -> Option<CopyObjectError> {
+    match result.response_status {
+        412 => Some(CopyObjectError::PreconditionFailed),
+        404 => {
+            let body = result.error_response_body.as_ref()?;
+            let root = xmltree::Element::parse(body.as_bytes()).ok()?;
+            let error_code = root.get_child("Code")?;
+            let error_str = error_code.get_text()?;
+            match error_str.deref() {
+                "NoSuchKey" => Some(CopyObjectError::NotFound),
+                "NoSuchBucket" => Some(CopyObjectError::NoSuchBucket),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{OsStr, OsString};
+
+    use super::*;
+
+    fn make_result(response_status: i32, body: impl Into<OsString>) -> MetaRequestResult {
+        MetaRequestResult {
+            response_status,
+            crt_error: 1i32.into(),
+            error_response_headers: None,
+            error_response_body: Some(body.into()),
+        }
+    }
+
+    #[test]
+    fn parse_404_no_such_key() {
+        let body = br#"<?xml version="1.0" encoding="UTF-8"?><Error><Code>NoSuchKey</Code><Message>The specified key does not exist.</Message><Key>not-a-key</Key><RequestId>NN0M3F1SYC1TR6EJ</RequestId><HostId>7dPm3Y0QvCtmFm5N3i9JtH0bVq2Qq9dYQlcW6vBbE8uQ2NOnxcvgLWGaWQOKt4jA0ys30OJDUWk=</HostId></Error>"#;
+        let result = make_result(404, OsStr::from_bytes(&body[..]));
+        let result = parse_copy_object_error(&result);
+        assert_eq!(result, Some(CopyObjectError::NotFound));
+    }
+
+    #[test]
+    fn parse_412_precondition_failed() {
+        let body = br#"<?xml version="1.0" encoding="UTF-8"?><Error><Code>PreconditionFailed</Code><Message>At least one of the pre-conditions you specified did not hold</Message><Condition>x-amz-copy-source-If-Match</Condition></Error>"#;
+        let result = make_result(412, OsStr::from_bytes(&body[..]));
+        let result = parse_copy_object_error(&result);
+        assert_eq!(result, Some(CopyObjectError::PreconditionFailed));
+    }
+
+    #[test]
+    fn parse_404_no_such_bucket() {
+        let body = br#"<?xml version="1.0" encoding="UTF-8"?><Error><Code>NoSuchBucket</Code><Message>The specified bucket does not exist</Message><BucketName>amzn-s3-demo-bucket</BucketName><RequestId>BHCQ0FTYY0HKMV43</RequestId><HostId>ntCK1jQfPxY7sSNL/GB13RttgJLjSETfIuOiuRnwImO0dQP2ttj2Qqpn5S/jSLt3Ql0TgHWuYF0=</HostId></Error>"#;
+        let result = make_result(404, OsStr::from_bytes(&body[..]));
+        let result = parse_copy_object_error(&result);
+        assert_eq!(result, Some(CopyObjectError::NoSuchBucket));
+    }
+}
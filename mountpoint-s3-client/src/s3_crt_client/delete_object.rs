@@ -19,7 +19,7 @@ impl S3CrtClient {
         let request = {
             let mut message = self
                 .inner
-                .new_request_template("DELETE", bucket)
+                .new_request_template("DELETE", bucket, key)
                 .map_err(S3RequestError::construction_failure)?;
             message
                 .set_request_path(format!("/{key}"))
@@ -0,0 +1,239 @@
+use std::ops::Deref;
+use std::os::unix::prelude::OsStrExt;
+
+use mountpoint_s3_crt::checksums::crc32c;
+use mountpoint_s3_crt::http::request_response::Header;
+use mountpoint_s3_crt::s3::client::{MetaRequestResult, MetaRequestType};
+use thiserror::Error;
+
+use crate::checksums::crc32c_to_base64;
+use crate::object_client::{
+    DeleteObjectError, DeleteObjectsKeyError, DeleteObjectsResult, ObjectClientError, ObjectClientResult,
+};
+use crate::s3_crt_client::{S3CrtClient, S3RequestError};
+
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum ParseError {
+    #[error("XML parsing error: {0:?}")]
+    Xml(#[from] xmltree::ParseError),
+
+    #[error("Missing field {1} from XML element {0:?}")]
+    MissingField(xmltree::Element, String),
+
+    #[error("Error in DeleteObjects response: {0:?}")]
+    DeleteFailed(xmltree::Element),
+}
+
+impl S3CrtClient {
+    /// Create and begin a new DeleteObjects request.
+    pub(super) async fn delete_objects(
+        &self,
+        bucket: &str,
+        keys: &[String],
+    ) -> ObjectClientResult<DeleteObjectsResult, DeleteObjectError, S3RequestError> {
+        let span = request_span!(self.inner, "delete_objects", bucket, num_keys = keys.len());
+
+        // Scope the endpoint, message, etc. since otherwise rustc thinks we use Message across the await.
+        let request = {
+            let mut message = self
+                .inner
+                .new_request_template("POST", bucket, common_prefix(keys))
+                .map_err(S3RequestError::construction_failure)?;
+            message
+                .set_request_path_and_query("/", &[("delete", "")])
+                .map_err(S3RequestError::construction_failure)?;
+
+            // DeleteObjects requires a checksum of the request body
+            let request_body = delete_objects_body(keys);
+            let checksum = crc32c_to_base64(&crc32c::checksum(request_body.as_bytes()));
+            for (name, value) in [
+                ("Content-Length", request_body.len().to_string()),
+                ("x-amz-checksum-crc32c", checksum),
+            ] {
+                message
+                    .set_header(&Header::new(name, value))
+                    .map_err(S3RequestError::construction_failure)?;
+            }
+            message
+                .inner
+                .set_body(&self.inner.allocator, request_body.into_bytes())
+                .map_err(S3RequestError::construction_failure)?;
+
+            self.inner
+                .make_simple_http_request(message, MetaRequestType::Default, span, parse_delete_objects_error)?
+        };
+
+        let body = request.await?;
+        parse_delete_objects_result(&body)
+            .map_err(|e| ObjectClientError::ClientError(S3RequestError::InternalError(e.into())))
+    }
+}
+
+/// Build the XML body of a DeleteObjects request. Quiet mode means the response only lists the
+/// keys that couldn't be deleted.
+fn delete_objects_body(keys: &[String]) -> String {
+    let mut body = String::from("<Delete xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\"><Quiet>true</Quiet>");
+    for key in keys {
+        body.push_str(&format!("<Object><Key>{}</Key></Object>", escape_xml(key)));
+    }
+    body.push_str("</Delete>");
+    body
+}
+
+/// The longest prefix shared by all of `keys`, which picks the credentials to sign the request with
+fn common_prefix(keys: &[String]) -> &str {
+    let Some((first, rest)) = keys.split_first() else {
+        return "";
+    };
+    let mut prefix = first.as_str();
+    for key in rest {
+        let len = prefix
+            .char_indices()
+            .zip(key.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or_else(|| prefix.len().min(key.len()), |((i, _), _)| i);
+        prefix = &prefix[..len];
+    }
+    prefix
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Parse the keys that failed to delete out of a DeleteObjects response. The request can also fail
+/// as a whole after returning a 200 status, with the error in the body.
+fn parse_delete_objects_result(body: &[u8]) -> Result<DeleteObjectsResult, ParseError> {
+    let root = xmltree::Element::parse(body)?;
+    if root.name == "Error" {
+        return Err(ParseError::DeleteFailed(root));
+    }
+
+    let mut errors = Vec::new();
+    for error in root.children.iter().filter_map(|child| child.as_element()) {
+        if error.name != "Error" {
+            continue;
+        }
+        errors.push(DeleteObjectsKeyError {
+            key: get_field(error, "Key")?,
+            code: get_field(error, "Code")?,
+            message: get_field(error, "Message").unwrap_or_default(),
+        });
+    }
+    Ok(DeleteObjectsResult { errors })
+}
+
+fn get_field(element: &xmltree::Element, name: &str) -> Result<String, ParseError> {
+    element
+        .get_child(name)
+        .and_then(|child| child.get_text())
+        .map(|text| text.to_string())
+        .ok_or_else(|| ParseError::MissingField(element.clone(), name.to_owned()))
+}
+
+fn parse_delete_objects_error(result: &MetaRequestResult) -> Option<DeleteObjectError> {
+    match result.response_status {
+        404 => {
+            let body = result.error_response_body.as_ref()?;
+            let root = xmltree::Element::parse(body.as_bytes()).ok()?;
+            let error_code = root.get_child("Code")?;
+            let error_str = error_code.get_text()?;
+
+            match error_str.deref() {
+                "NoSuchBucket" => Some(DeleteObjectError::NoSuchBucket),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{OsStr, OsString};
+
+    use super::*;
+
+    fn make_result(response_status: i32, body: impl Into<OsString>) -> MetaRequestResult {
+        MetaRequestResult {
+            response_status,
+            crt_error: 1i32.into(),
+            error_response_headers: None,
+            error_response_body: Some(body.into()),
+        }
+    }
+
+    #[test]
+    fn parse_404_no_such_bucket() {
+        let body = br#"<?xml version="1.0" encoding="UTF-8"?><Error><Code>NoSuchBucket</Code><Message>The specified bucket does not exist</Message><BucketName>nosuchbucket</BucketName><RequestId>BHCQ0FTYY0HKMV43</RequestId></Error>"#;
+        let result = make_result(404, OsStr::from_bytes(&body[..]));
+        let result = parse_delete_objects_error(&result);
+        assert_eq!(result, Some(DeleteObjectError::NoSuchBucket));
+    }
+
+    #[test]
+    fn body_escapes_keys() {
+        let keys = vec!["a/b.txt".to_owned(), "<tom & jerry's>".to_owned()];
+        assert_eq!(
+            delete_objects_body(&keys),
+            "<Delete xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\"><Quiet>true</Quiet>\
+             <Object><Key>a/b.txt</Key></Object>\
+             <Object><Key>&lt;tom &amp; jerry&apos;s&gt;</Key></Object></Delete>"
+        );
+    }
+
+    #[test]
+    fn common_prefix_of_keys() {
+        let keys = |keys: &[&str]| keys.iter().map(|k| k.to_string()).collect::<Vec<_>>();
+        assert_eq!(common_prefix(&[]), "");
+        assert_eq!(common_prefix(&keys(&["a/b/c"])), "a/b/c");
+        assert_eq!(common_prefix(&keys(&["a/b/c", "a/b/d", "a/bc"])), "a/b");
+        assert_eq!(common_prefix(&keys(&["a/b", "a/b/c"])), "a/b");
+        assert_eq!(common_prefix(&keys(&["a/é", "a/è"])), "a/");
+        assert_eq!(common_prefix(&keys(&["a", "b"])), "");
+    }
+
+    #[test]
+    fn parse_partial_failures() {
+        let body = br#"<?xml version="1.0" encoding="UTF-8"?>
+<DeleteResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Error><Key>dir/a&amp;b</Key><Code>AccessDenied</Code><Message>Access Denied</Message></Error>
+  <Error><Key>dir/c</Key><Code>InternalError</Code><Message>We encountered an internal error. Please try again.</Message></Error>
+</DeleteResult>"#;
+        let result = parse_delete_objects_result(body).unwrap();
+        assert_eq!(
+            result.errors,
+            vec![
+                DeleteObjectsKeyError::new("dir/a&b", "AccessDenied", "Access Denied"),
+                DeleteObjectsKeyError::new(
+                    "dir/c",
+                    "InternalError",
+                    "We encountered an internal error. Please try again."
+                ),
+            ]
+        );
+
+        let body = br#"<?xml version="1.0" encoding="UTF-8"?><DeleteResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/"></DeleteResult>"#;
+        let result = parse_delete_objects_result(body).unwrap();
+        assert!(result.errors.is_empty());
+    }
+
+    #[test]
+    fn parse_error_after_200() {
+        let body = br#"<?xml version="1.0" encoding="UTF-8"?><Error><Code>InternalError</Code><Message>We encountered an internal error. Please try again.</Message></Error>"#;
+        let err = parse_delete_objects_result(body).expect_err("should fail");
+        assert!(matches!(err, ParseError::DeleteFailed(_)));
+    }
+}
@@ -1,19 +1,22 @@
+use std::collections::VecDeque;
 use std::future::Future;
 use std::ops::Deref;
 use std::ops::Range;
 use std::os::unix::prelude::OsStrExt;
 use std::pin::Pin;
+use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
 
-use futures::channel::mpsc::UnboundedReceiver;
+use bytes::{Bytes, BytesMut};
+use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender};
 use futures::Stream;
 use mountpoint_s3_crt::common::error::Error;
-use mountpoint_s3_crt::http::request_response::Header;
+use mountpoint_s3_crt::http::request_response::{Header, Headers};
 use mountpoint_s3_crt::s3::client::{MetaRequestResult, MetaRequestType};
 use pin_project::pin_project;
 
-use crate::object_client::{ETag, GetBodyPart, GetObjectError, ObjectClientError, ObjectClientResult};
-use crate::s3_crt_client::{S3CrtClient, S3HttpRequest, S3RequestError};
+use crate::object_client::{BodyBufferPool, ETag, GetBodyPart, GetObjectError, ObjectClientError, ObjectClientResult};
+use crate::s3_crt_client::{parse_content_range_header, S3CrtClient, S3HttpRequest, S3RequestError};
 
 impl S3CrtClient {
     /// Create and begin a new GetObject request. The returned [GetObjectRequest] is a [Stream] of
@@ -29,7 +32,7 @@ impl S3CrtClient {
 
         let mut message = self
             .inner
-            .new_request_template("GET", bucket)
+            .new_request_template("GET", bucket, key)
             .map_err(S3RequestError::construction_failure)?;
 
         // Overwrite "accept" header since this returns raw object data.
@@ -49,7 +52,9 @@ impl S3CrtClient {
         // requests. For auto-ranged-gets, the CRT takes care of adjusting the offset returned to
         // the body callback to include the range start, but for manual requests we need to do it
         // ourselves with `range_start`.
-        let (request_type, range_start) = if let Some(range) = range {
+        // We also check that manual requests get back the range we asked for, since we trust it to
+        // place the body within the object. The CRT checks the ranges of the parts it requests.
+        let (request_type, range_start, expected_range) = if let Some(range) = range {
             // Range HTTP header is bounded below *inclusive*
             let range_value = format!("bytes={}-{}", range.start, range.end.saturating_sub(1));
             message
@@ -58,12 +63,12 @@ impl S3CrtClient {
 
             let length = range.end.saturating_sub(range.start);
             if length >= self.inner.part_size as u64 {
-                (MetaRequestType::GetObject, 0)
+                (MetaRequestType::GetObject, 0, None)
             } else {
-                (MetaRequestType::Default, range.start)
+                (MetaRequestType::Default, range.start, Some(range))
             }
         } else {
-            (MetaRequestType::GetObject, 0)
+            (MetaRequestType::GetObject, 0, None)
         };
 
         let key = format!("/{key}");
@@ -72,20 +77,48 @@ impl S3CrtClient {
             .map_err(S3RequestError::construction_failure)?;
 
         let (sender, receiver) = futures::channel::mpsc::unbounded();
+        let body = Arc::new(Mutex::new(ResponseBody::new(
+            sender,
+            self.inner.body_buffer_pool.clone(),
+        )));
+        let body_headers = body.clone();
+        let body_finish = body.clone();
 
         let request = self.inner.make_meta_request(
             message,
             request_type,
             span,
-            |_, _| (),
+            move |headers, response_status| {
+                if (200..300).contains(&response_status) {
+                    let mut body = body_headers.lock().unwrap();
+                    body.content_length = parse_content_length(headers);
+                    if let Some(expected_range) = &expected_range {
+                        let content_range = headers
+                            .get("Content-Range")
+                            .ok()
+                            .and_then(|header| header.value().to_str().map(str::to_owned));
+                        body.range_error = check_content_range(
+                            expected_range,
+                            response_status,
+                            content_range.as_deref(),
+                            body.content_length,
+                        )
+                        .err();
+                    }
+                }
+            },
             move |offset, data| {
-                let _ = sender.unbounded_send(Ok((range_start + offset, data.into())));
+                body.lock().unwrap().push(range_start + offset, data);
             },
             move |result| {
                 if result.is_err() {
                     Err(parse_get_object_error(result).map(ObjectClientError::ServiceError))
                 } else {
-                    Ok(())
+                    body_finish
+                        .lock()
+                        .unwrap()
+                        .finish()
+                        .map_err(|e| Some(ObjectClientError::ClientError(e)))
                 }
             },
         )?;
@@ -141,6 +174,294 @@ impl Stream for S3GetObjectRequest {
     }
 }
 
+/// The most bytes we hold back from the end of a response body so we can check that it doesn't end
+/// with an embedded error document. S3 error documents are well under this size.
+const MAX_ERROR_DOCUMENT_SIZE: usize = 4 * 1024;
+
+/// The body of a GetObject response as it streams in.
+///
+/// Some S3-compatible stores (and occasionally S3 itself) send a 200 OK response and then fail
+/// partway through the body, either by ending the body before the advertised Content-Length or by
+/// appending an XML error document to it. The CRT treats both as a successful request, so we check
+/// the body ourselves once the request finishes. To avoid ever delivering an error document as
+/// object data, we hold back the last [MAX_ERROR_DOCUMENT_SIZE] bytes of the body until then.
+struct ResponseBody {
+    sender: UnboundedSender<Result<GetBodyPart, Error>>,
+    buffer_pool: Option<Arc<dyn BodyBufferPool>>,
+    /// Body bytes that haven't been sent yet, starting at object offset `pending_offset`: first the
+    /// buffers that have been frozen, then the one still being filled
+    frozen: VecDeque<Bytes>,
+    filling: BodyBuffer,
+    pending_offset: u64,
+    /// Total number of body bytes received so far
+    received: u64,
+    /// The Content-Length the response advertised, if any
+    content_length: Option<u64>,
+    /// Why the range the response holds isn't the one requested, if it isn't. The body is then
+    /// dropped rather than sent.
+    range_error: Option<String>,
+}
+
+/// A buffer that body bytes are copied into
+enum BodyBuffer {
+    /// Rented from the client's [BodyBufferPool]. It can't be shared until it's frozen, so its bytes
+    /// are only sent once it's full or the body is complete.
+    Pooled {
+        buffer: Vec<u8>,
+        pool: Arc<dyn BodyBufferPool>,
+    },
+    /// Allocated by the client, which sends its bytes as they arrive
+    Owned(BytesMut),
+}
+
+impl BodyBuffer {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            BodyBuffer::Pooled { buffer, .. } => buffer,
+            BodyBuffer::Owned(buffer) => buffer,
+        }
+    }
+
+    fn freeze(self) -> Bytes {
+        match self {
+            BodyBuffer::Pooled { buffer, pool } => pool.freeze(buffer),
+            BodyBuffer::Owned(buffer) => buffer.freeze(),
+        }
+    }
+}
+
+impl ResponseBody {
+    fn new(sender: UnboundedSender<Result<GetBodyPart, Error>>, buffer_pool: Option<Arc<dyn BodyBufferPool>>) -> Self {
+        Self {
+            sender,
+            buffer_pool: buffer_pool.filter(|pool| pool.buffer_size() > 0),
+            frozen: VecDeque::new(),
+            filling: BodyBuffer::Owned(BytesMut::new()),
+            pending_offset: 0,
+            received: 0,
+            content_length: None,
+            range_error: None,
+        }
+    }
+
+    fn push(&mut self, offset: u64, mut data: &[u8]) {
+        if self.range_error.is_some() {
+            return;
+        }
+        let pending_len = self.pending_len();
+        if pending_len == 0 {
+            self.pending_offset = offset;
+        } else if offset != self.pending_offset + pending_len as u64 {
+            // Body parts should always arrive in order, but don't merge them if they don't
+            self.send(pending_len);
+            self.pending_offset = offset;
+        }
+        self.received += data.len() as u64;
+
+        // The CRT reuses its buffers once the body callback returns, so this is the one copy on the
+        // read path. Parts we send are frozen or split off these buffers without copying.
+        while !data.is_empty() {
+            if let BodyBuffer::Owned(_) = self.filling {
+                self.try_rent();
+            }
+            match &mut self.filling {
+                BodyBuffer::Pooled { buffer, pool } => {
+                    let room = pool.buffer_size().saturating_sub(buffer.len());
+                    if room == 0 {
+                        self.freeze_filling();
+                        continue;
+                    }
+                    let len = room.min(data.len());
+                    buffer.extend_from_slice(&data[..len]);
+                    data = &data[len..];
+                }
+                BodyBuffer::Owned(buffer) => {
+                    buffer.extend_from_slice(data);
+                    data = &[];
+                }
+            }
+        }
+        self.send(self.pending_len().saturating_sub(MAX_ERROR_DOCUMENT_SIZE));
+    }
+
+    /// Fill a buffer rented from the pool from now on, if there's a pool and it has one to spare
+    fn try_rent(&mut self) {
+        let Some(pool) = &self.buffer_pool else {
+            return;
+        };
+        let Some(buffer) = pool.rent() else {
+            return;
+        };
+        let pool = pool.clone();
+        self.freeze_filling();
+        self.filling = BodyBuffer::Pooled { buffer, pool };
+    }
+
+    /// Freeze the buffer being filled, so its bytes can be sent, and carry on with an empty one
+    fn freeze_filling(&mut self) {
+        let filling = std::mem::replace(&mut self.filling, BodyBuffer::Owned(BytesMut::new()));
+        // Always freeze pooled buffers, even empty ones, so they go back to the pool
+        let frozen = filling.freeze();
+        if !frozen.is_empty() {
+            self.frozen.push_back(frozen);
+        }
+    }
+
+    fn pending_len(&self) -> usize {
+        self.frozen.iter().map(Bytes::len).sum::<usize>() + self.filling.as_slice().len()
+    }
+
+    /// Copy out the last `len` pending bytes, or all of them if there are fewer
+    fn pending_tail(&self, len: usize) -> Vec<u8> {
+        let mut skip = self.pending_len().saturating_sub(len);
+        let mut tail = Vec::with_capacity(len);
+        for chunk in self
+            .frozen
+            .iter()
+            .map(|part| &part[..])
+            .chain([self.filling.as_slice()])
+        {
+            let start = skip.min(chunk.len());
+            skip -= start;
+            tail.extend_from_slice(&chunk[start..]);
+        }
+        tail
+    }
+
+    /// Send up to the first `len` pending bytes as body parts. Bytes in a pooled buffer that's
+    /// still being filled aren't sent until it's frozen.
+    fn send(&mut self, mut len: usize) {
+        if let BodyBuffer::Pooled { buffer, pool } = &self.filling {
+            if buffer.len() >= pool.buffer_size() {
+                self.freeze_filling();
+            }
+        }
+        while len > 0 {
+            let part = match self.frozen.front_mut() {
+                Some(front) if front.len() <= len => self.frozen.pop_front().unwrap(),
+                Some(front) => front.split_to(len),
+                None => match &mut self.filling {
+                    BodyBuffer::Owned(buffer) if !buffer.is_empty() => buffer.split_to(len.min(buffer.len())).freeze(),
+                    _ => break,
+                },
+            };
+            len -= part.len();
+            let offset = self.pending_offset;
+            self.pending_offset += part.len() as u64;
+            let _ = self.sender.unbounded_send(Ok((offset, part)));
+        }
+    }
+
+    /// Check the body of a response the CRT reported as successful, and send what's left of it if
+    /// it's complete
+    fn finish(&mut self) -> Result<(), S3RequestError> {
+        if let Some(range_error) = self.range_error.take() {
+            return Err(S3RequestError::UnexpectedContentRange(range_error));
+        }
+        let tail = self.pending_tail(MAX_ERROR_DOCUMENT_SIZE);
+        check_response_body(self.content_length, self.received, &tail)?;
+        self.freeze_filling();
+        self.send(self.pending_len());
+        Ok(())
+    }
+}
+
+impl Drop for ResponseBody {
+    fn drop(&mut self) {
+        // Give a pooled buffer that's still being filled back to its pool
+        self.freeze_filling();
+    }
+}
+
+fn parse_content_length(headers: &Headers) -> Option<u64> {
+    let header = headers.get("Content-Length").ok()?;
+    header.value().to_str()?.parse().ok()
+}
+
+/// Check that the response to a request for the `requested` range of an object holds that range,
+/// given its status and `Content-Range` and `Content-Length` headers. Like S3, servers may cut the
+/// range short at the end of the object, and may ignore the range and send the whole object, as
+/// long as that's what was requested anyway.
+fn check_content_range(
+    requested: &Range<u64>,
+    response_status: i32,
+    content_range: Option<&str>,
+    content_length: Option<u64>,
+) -> Result<(), String> {
+    let Some(content_range) = content_range else {
+        if response_status == 200 && requested.start == 0 && content_length.is_some_and(|len| len <= requested.end) {
+            return Ok(());
+        }
+        return Err(format!("no Content-Range in response to request for {requested:?}"));
+    };
+    let Some((range, object_size)) = parse_content_range_header(content_range) else {
+        return Err(format!("can't parse {content_range:?}"));
+    };
+
+    let expected_end = object_size.map_or(requested.end, |size| requested.end.min(size));
+    if range.start != requested.start || range.end != expected_end {
+        return Err(format!("{content_range:?} in response to request for {requested:?}"));
+    }
+    if let Some(content_length) = content_length {
+        if content_length != range.end - range.start {
+            return Err(format!("{content_range:?} with Content-Length {content_length}"));
+        }
+    }
+    Ok(())
+}
+
+/// Check that a response body that was `received` bytes long, ending with `tail`, is complete.
+///
+/// A body that's exactly as long as its Content-Length is complete whatever it holds, since objects
+/// can legitimately end with something that looks like an error document. We only look for one in
+/// the bytes past the Content-Length, or in the whole tail if the response didn't advertise one.
+fn check_response_body(content_length: Option<u64>, received: u64, tail: &[u8]) -> Result<(), S3RequestError> {
+    let extra = match content_length {
+        Some(content_length) if received < content_length => {
+            return Err(S3RequestError::IncompleteResponseBody(format!(
+                "received {received} of {content_length} bytes"
+            )));
+        }
+        Some(content_length) if received == content_length => return Ok(()),
+        Some(content_length) => {
+            let extra = (received - content_length).min(tail.len() as u64) as usize;
+            &tail[tail.len() - extra..]
+        }
+        None => tail,
+    };
+    if let Some(code) = parse_embedded_error(extra) {
+        return Err(S3RequestError::IncompleteResponseBody(format!(
+            "body ended with an error document: {code}"
+        )));
+    }
+    if let Some(content_length) = content_length {
+        return Err(S3RequestError::IncompleteResponseBody(format!(
+            "received {received} bytes, more than the Content-Length of {content_length}"
+        )));
+    }
+    Ok(())
+}
+
+/// If `tail` ends with an S3 error document, return the error code it contains
+fn parse_embedded_error(tail: &[u8]) -> Option<String> {
+    fn rfind(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        haystack.windows(needle.len()).rposition(|window| window == needle)
+    }
+
+    let end = tail.iter().rposition(|b| !b.is_ascii_whitespace())? + 1;
+    let tail = &tail[..end];
+    if !tail.ends_with(b"</Error>") {
+        return None;
+    }
+    let start = rfind(tail, b"<?xml").or_else(|| rfind(tail, b"<Error>"))?;
+    let root = xmltree::Element::parse(&tail[start..]).ok()?;
+    if root.name != "Error" {
+        return None;
+    }
+    let error_code = root.get_child("Code")?.get_text()?;
+    Some(error_code.into_owned())
+}
+
 fn parse_get_object_error(result: &MetaRequestResult) -> Option<GetObjectError> {
     match result.response_status {
         404 => {
@@ -155,6 +476,18 @@ fn parse_get_object_error(result: &MetaRequestResult) -> Option<GetObjectError>
             }
         }
         412 => Some(GetObjectError::PreconditionFailed),
+        416 => {
+            let body = result.error_response_body.as_ref()?;
+            let root = xmltree::Element::parse(body.as_bytes()).ok()?;
+            if root.get_child("Code")?.get_text()?.deref() != "InvalidRange" {
+                return None;
+            }
+            let object_size = root
+                .get_child("ActualObjectSize")
+                .and_then(|size| size.get_text())
+                .and_then(|size| size.parse().ok());
+            Some(GetObjectError::InvalidRange { object_size })
+        }
         _ => None,
     }
 }
@@ -163,6 +496,8 @@ fn parse_get_object_error(result: &MetaRequestResult) -> Option<GetObjectError>
 mod tests {
     use std::ffi::{OsStr, OsString};
 
+    use test_case::test_case;
+
     use super::*;
 
     fn make_result(response_status: i32, body: impl Into<OsString>) -> MetaRequestResult {
@@ -197,4 +532,171 @@ mod tests {
         let result = parse_get_object_error(&result);
         assert_eq!(result, None);
     }
+
+    #[test]
+    fn parse_416_invalid_range() {
+        let body = br#"<?xml version="1.0" encoding="UTF-8"?><Error><Code>InvalidRange</Code><Message>The requested range is not satisfiable</Message><RangeRequested>bytes=2000-2999</RangeRequested><ActualObjectSize>1500</ActualObjectSize><RequestId>4VAGDP5HMYTDNB3Y</RequestId><HostId>JMgGqpVKIaaTieG68IODiV2piWw/q9VCTowGvWP36BEz6oIVEXiesn8cDE5ph7if0gpY5WU1Wc8=</HostId></Error>"#;
+        let result = make_result(416, OsStr::from_bytes(&body[..]));
+        let result = parse_get_object_error(&result);
+        assert_eq!(
+            result,
+            Some(GetObjectError::InvalidRange {
+                object_size: Some(1500)
+            })
+        );
+    }
+
+    #[test_case(0..100, 206, Some("bytes 0-99/1000"), Some(100); "exact range")]
+    #[test_case(900..1100, 206, Some("bytes 900-999/1000"), Some(100); "range cut short at end of object")]
+    #[test_case(500..600, 206, Some("bytes 500-599/*"), Some(100); "unknown object size")]
+    #[test_case(0..2000, 200, None, Some(1000); "whole object")]
+    fn check_matching_content_range(
+        requested: Range<u64>,
+        status: i32,
+        content_range: Option<&str>,
+        content_length: Option<u64>,
+    ) {
+        check_content_range(&requested, status, content_range, content_length).expect("range should match");
+    }
+
+    #[test_case(0..100, 206, Some("bytes 100-199/1000"), Some(100); "different start")]
+    #[test_case(0..100, 206, Some("bytes 0-49/1000"), Some(50); "shorter range")]
+    #[test_case(0..100, 206, Some("bytes 0-99/50"), Some(100); "range past end of object")]
+    #[test_case(0..100, 206, Some("bytes 0-99/1000"), Some(90); "wrong content length")]
+    #[test_case(0..100, 206, Some("bytes 0-99"), Some(100); "invalid header")]
+    #[test_case(100..200, 200, None, Some(1000); "range ignored")]
+    #[test_case(0..100, 200, None, Some(1000); "range ignored for bigger object")]
+    fn check_mismatched_content_range(
+        requested: Range<u64>,
+        status: i32,
+        content_range: Option<&str>,
+        content_length: Option<u64>,
+    ) {
+        check_content_range(&requested, status, content_range, content_length).expect_err("range shouldn't match");
+    }
+
+    #[test]
+    fn range_mismatch_drops_body() {
+        let (sender, mut receiver) = futures::channel::mpsc::unbounded();
+        let mut body = ResponseBody::new(sender, None);
+        body.range_error = Some("mismatch".to_owned());
+        body.push(0, b"hello");
+        let err = body.finish().expect_err("mismatched range should fail");
+        assert!(matches!(err, S3RequestError::UnexpectedContentRange(_)));
+        drop(body);
+        assert!(receiver.try_next().unwrap().is_none(), "no body should be sent");
+    }
+
+    /// A pool that rents out up to `max_buffers` buffers and never takes them back
+    #[derive(Debug)]
+    struct TestPool {
+        buffer_size: usize,
+        max_buffers: usize,
+        rented: Mutex<usize>,
+    }
+
+    impl BodyBufferPool for TestPool {
+        fn buffer_size(&self) -> usize {
+            self.buffer_size
+        }
+
+        fn rent(&self) -> Option<Vec<u8>> {
+            let mut rented = self.rented.lock().unwrap();
+            if *rented == self.max_buffers {
+                return None;
+            }
+            *rented += 1;
+            Some(Vec::with_capacity(self.buffer_size))
+        }
+
+        fn freeze(&self, buffer: Vec<u8>) -> Bytes {
+            Bytes::from(buffer)
+        }
+    }
+
+    #[test_case(0; "no pool")]
+    #[test_case(1; "pool runs out")]
+    #[test_case(100; "pooled")]
+    fn push_into_pooled_buffers(max_buffers: usize) {
+        const BUFFER_SIZE: usize = 8 * 1024;
+
+        let pool = Arc::new(TestPool {
+            buffer_size: BUFFER_SIZE,
+            max_buffers,
+            rented: Mutex::new(0),
+        });
+        let (sender, mut receiver) = futures::channel::mpsc::unbounded();
+        let mut body = ResponseBody::new(sender, Some(pool.clone()));
+        let data: Vec<u8> = (0..5 * BUFFER_SIZE + 100).map(|i| i as u8).collect();
+        let (first, second) = data.split_at(3 * BUFFER_SIZE + 10);
+        body.content_length = Some(data.len() as u64);
+        body.push(1000, first);
+        body.push(1000 + first.len() as u64, second);
+        body.finish().expect("complete body should succeed");
+        drop(body);
+
+        let mut received = Vec::new();
+        while let Some(part) = receiver.try_next().unwrap() {
+            let (offset, part) = part.unwrap();
+            assert_eq!(offset, 1000 + received.len() as u64);
+            if max_buffers >= 6 {
+                assert!(part.len() <= BUFFER_SIZE, "parts should be slices of pooled buffers");
+            }
+            received.extend_from_slice(&part);
+        }
+        assert_eq!(received, data);
+        assert_eq!(*pool.rented.lock().unwrap(), max_buffers.min(6));
+    }
+
+    #[test]
+    fn check_complete_body() {
+        assert!(check_response_body(Some(5), 5, b"hello").is_ok());
+        assert!(check_response_body(None, 5, b"hello").is_ok());
+        // Objects can contain XML, as long as it isn't an error document
+        let body = br#"<?xml version="1.0" encoding="UTF-8"?><Config><Code>1</Code></Config>"#;
+        assert!(check_response_body(Some(body.len() as u64), body.len() as u64, body).is_ok());
+    }
+
+    #[test]
+    fn check_truncated_body() {
+        let err = check_response_body(Some(10), 5, b"hello").expect_err("short body should fail");
+        assert!(
+            matches!(err, S3RequestError::IncompleteResponseBody(ref message) if message.contains("5 of 10")),
+            "unexpected error: {err:?}"
+        );
+    }
+
+    #[test]
+    fn check_body_with_embedded_error() {
+        let data = b"some object data";
+        let body = br#"some object data<?xml version="1.0" encoding="UTF-8"?>
+<Error><Code>InternalError</Code><Message>We encountered an internal error. Please try again.</Message><RequestId>656c76696e6727732072657175657374</RequestId></Error>
+"#;
+        let err = check_response_body(Some(data.len() as u64), body.len() as u64, body)
+            .expect_err("embedded error document past the Content-Length should fail");
+        assert!(
+            matches!(err, S3RequestError::IncompleteResponseBody(ref message) if message.contains("InternalError")),
+            "unexpected error: {err:?}"
+        );
+        let err = check_response_body(None, body.len() as u64, body)
+            .expect_err("embedded error document without a Content-Length should fail");
+        assert!(
+            matches!(err, S3RequestError::IncompleteResponseBody(ref message) if message.contains("InternalError")),
+            "unexpected error: {err:?}"
+        );
+
+        // An object whose content happens to end with an error document is read as it is
+        assert!(check_response_body(Some(body.len() as u64), body.len() as u64, body).is_ok());
+
+        // Extra bytes that aren't an error document still mean the body isn't the object
+        let err = check_response_body(Some(5), data.len() as u64, data).expect_err("long body should fail");
+        assert!(
+            matches!(err, S3RequestError::IncompleteResponseBody(ref message) if message.contains("more than")),
+            "unexpected error: {err:?}"
+        );
+
+        // Error documents aren't always preceded by an XML declaration
+        let body = b"some object data<Error><Code>SlowDown</Code></Error>";
+        assert_eq!(parse_embedded_error(body).as_deref(), Some("SlowDown"));
+    }
 }
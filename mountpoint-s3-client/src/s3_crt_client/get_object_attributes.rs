@@ -111,7 +111,7 @@ impl S3CrtClient {
         let body = {
             let mut message = self
                 .inner
-                .new_request_template("GET", bucket)
+                .new_request_template("GET", bucket, key)
                 .map_err(S3RequestError::construction_failure)?;
 
             let query = vec![("attributes", "")];
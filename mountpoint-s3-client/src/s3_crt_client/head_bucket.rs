@@ -17,7 +17,7 @@ impl S3CrtClient {
             {
                 let mut message = self
                     .inner
-                    .new_request_template("HEAD", bucket)
+                    .new_request_template("HEAD", bucket, "")
                     .map_err(S3RequestError::construction_failure)?;
 
                 message
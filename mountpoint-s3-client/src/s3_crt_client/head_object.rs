@@ -88,20 +88,32 @@ impl HeadObjectResult {
     fn parse_from_hdr(bucket: String, key: String, headers: &Headers) -> Result<Self, ParseError> {
         let last_modified = OffsetDateTime::parse(&get_field(headers, "Last-Modified")?, &Rfc2822)
             .map_err(|e| ParseError::OffsetDateTime(e, "LastModified".into()))?;
-        let size = u64::from_str(&get_field(headers, "Content-Length")?)
-            .map_err(|e| ParseError::Int(e, "ContentLength".into()))?;
+        // Objects transformed by S3 Object Lambda can be streamed without a `Content-Length`
+        let (size, unknown_size) = match get_optional_field(headers, "Content-Length")? {
+            Some(length) => {
+                let size = u64::from_str(&length).map_err(|e| ParseError::Int(e, "ContentLength".into()))?;
+                (size, false)
+            }
+            None => (0, true),
+        };
         let etag = get_field(headers, "Etag")?;
         let storage_class = get_optional_field(headers, "x-amz-storage-class")?;
         let restore_status = Self::parse_restore_status(headers)?;
+        let content_encoding = get_optional_field(headers, "Content-Encoding")?;
         let object = ObjectInfo {
             key,
             size,
+            unknown_size,
             last_modified,
             storage_class,
             restore_status,
             etag,
         };
-        Ok(HeadObjectResult { bucket, object })
+        Ok(HeadObjectResult {
+            bucket,
+            object,
+            content_encoding,
+        })
     }
 }
 
@@ -119,7 +131,7 @@ impl S3CrtClient {
         let request = {
             let mut message = self
                 .inner
-                .new_request_template("HEAD", bucket)
+                .new_request_template("HEAD", bucket, key)
                 .map_err(S3RequestError::construction_failure)?;
 
             let key = key.to_string();
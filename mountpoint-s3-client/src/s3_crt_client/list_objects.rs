@@ -137,6 +137,7 @@ fn parse_object_info_from_xml(element: &xmltree::Element) -> Result<ObjectInfo,
     Ok(ObjectInfo {
         key,
         size,
+        unknown_size: false,
         last_modified,
         storage_class,
         restore_status,
@@ -149,6 +150,7 @@ impl S3CrtClient {
         &self,
         bucket: &str,
         continuation_token: Option<&str>,
+        start_after: Option<&str>,
         delimiter: &str,
         max_keys: usize,
         prefix: &str,
@@ -157,7 +159,7 @@ impl S3CrtClient {
         let body = {
             let mut message = self
                 .inner
-                .new_request_template("GET", bucket)
+                .new_request_template("GET", bucket, prefix)
                 .map_err(S3RequestError::construction_failure)?;
             message
                 .set_header(&Header::new("x-amz-optional-object-attributes", "RestoreStatus"))
@@ -172,6 +174,9 @@ impl S3CrtClient {
             if let Some(continuation_token) = continuation_token {
                 query.push(("continuation-token", continuation_token));
             }
+            if let Some(start_after) = start_after {
+                query.push(("start-after", start_after));
+            }
 
             message
                 .set_request_path_and_query("/", query)
@@ -182,6 +187,7 @@ impl S3CrtClient {
                 "list_objects",
                 bucket,
                 continued = continuation_token.is_some(),
+                start_after,
                 delimiter,
                 max_keys,
                 prefix
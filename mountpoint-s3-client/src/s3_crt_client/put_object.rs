@@ -2,7 +2,9 @@ use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
 use crate::object_client::{ObjectClientResult, PutObjectError, PutObjectParams, PutObjectRequest, PutObjectResult};
-use crate::s3_crt_client::{emit_throughput_metric, PutObjectTrailingChecksums, S3CrtClient, S3RequestError};
+use crate::s3_crt_client::{
+    emit_throughput_metric, ConstructionError, PutObjectTrailingChecksums, S3CrtClient, S3RequestError,
+};
 use async_trait::async_trait;
 use futures::channel::oneshot;
 use mountpoint_s3_crt::http::request_response::{Header, Headers};
@@ -22,9 +24,15 @@ impl S3CrtClient {
         params: &PutObjectParams,
     ) -> ObjectClientResult<S3PutObjectRequest, PutObjectError, S3RequestError> {
         let span = request_span!(self.inner, "put_object", bucket, key);
+
+        // The CRT computes Content-MD5 for all uploads of a client or none of them
+        if params.content_md5 && !self.inner.compute_content_md5 {
+            return Err(S3RequestError::construction_failure(ConstructionError::ContentMd5NotEnabled).into());
+        }
+
         let mut message = self
             .inner
-            .new_request_template("PUT", bucket)
+            .new_request_template("PUT", bucket, key)
             .map_err(S3RequestError::construction_failure)?;
 
         let key = format!("/{}", key);
@@ -32,7 +40,10 @@ impl S3CrtClient {
             .set_request_path(&key)
             .map_err(S3RequestError::construction_failure)?;
 
+        // The CRT leaves out Content-MD5 for parts that carry a trailing checksum, so when we need it
+        // the CRC32C checksums are only computed for the upload review and not sent
         let checksum_config = match params.trailing_checksums {
+            PutObjectTrailingChecksums::Enabled if params.content_md5 => Some(ChecksumConfig::upload_review_crc32c()),
             PutObjectTrailingChecksums::Enabled => Some(ChecksumConfig::trailing_crc32c()),
             PutObjectTrailingChecksums::ReviewOnly => Some(ChecksumConfig::upload_review_crc32c()),
             PutObjectTrailingChecksums::Disabled => None,
@@ -0,0 +1,493 @@
+use std::ops::{Deref, Range};
+use std::os::unix::prelude::OsStrExt;
+use std::sync::{Arc, Mutex};
+
+use base64ct::{Base64, Encoding};
+use futures::{stream, FutureExt, StreamExt, TryStreamExt};
+use md5::Digest as _;
+use mountpoint_s3_crt::checksums::crc32c;
+use mountpoint_s3_crt::http::request_response::{Header, Headers};
+use mountpoint_s3_crt::s3::client::{MetaRequestResult, MetaRequestType};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC};
+use thiserror::Error;
+use tracing::{debug, Span};
+
+use crate::checksums::crc32c_to_base64;
+use crate::object_client::{
+    ETag, ObjectClientError, ObjectClientResult, PutObjectError, PutObjectParams, PutObjectResult,
+    PutObjectTrailingChecksums, UploadPartSource,
+};
+use crate::s3_crt_client::{S3CrtClient, S3Message, S3RequestError};
+
+const SSE_TYPE_HEADER_NAME: &str = "x-amz-server-side-encryption";
+const SSE_KEY_ID_HEADER_NAME: &str = "x-amz-server-side-encryption-aws-kms-key-id";
+
+/// RFC 3986 encoding for the key in the `x-amz-copy-source` header, keeping `/` as is.
+pub(super) const URLENCODE_COPY_SOURCE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b'/');
+
+/// Most parts of a [put_object_from_parts](S3CrtClient::put_object_from_parts) request that are
+/// uploaded or copied at the same time
+const MAX_CONCURRENT_PARTS: usize = 16;
+
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum ParseError {
+    #[error("XML parsing error: {0:?}")]
+    Xml(#[from] xmltree::ParseError),
+
+    #[error("Missing field {1} from XML element {0:?}")]
+    MissingField(xmltree::Element, String),
+
+    #[error("Missing header {0}")]
+    MissingHeader(String),
+
+    #[error("Error in CompleteMultipartUpload response: {0:?}")]
+    CompleteFailed(xmltree::Element),
+
+    #[error("Error in UploadPartCopy response: {0:?}")]
+    CopyFailed(xmltree::Element),
+}
+
+/// A part that was uploaded or copied, to be listed in the CompleteMultipartUpload request.
+#[derive(Debug)]
+struct CompletedPart {
+    part_number: usize,
+    etag: String,
+    checksum_crc32c: Option<String>,
+}
+
+impl S3CrtClient {
+    /// Replace an object with a multi-part upload made of copied and uploaded parts, up to
+    /// [MAX_CONCURRENT_PARTS] at a time. The upload is aborted if any part fails. Uploaded parts
+    /// carry their own `Content-MD5` header if asked for, whether or not the client computes it.
+    pub(super) async fn put_object_from_parts(
+        &self,
+        bucket: &str,
+        key: &str,
+        source_etag: &ETag,
+        parts: &[UploadPartSource],
+        params: &PutObjectParams,
+    ) -> ObjectClientResult<PutObjectResult, PutObjectError, S3RequestError> {
+        let span = request_span!(
+            self.inner,
+            "put_object_from_parts",
+            bucket,
+            key,
+            num_parts = parts.len()
+        );
+        let with_checksums = params.trailing_checksums == PutObjectTrailingChecksums::Enabled;
+
+        let upload_id = self.create_multipart_upload(bucket, key, params, span.clone()).await?;
+
+        let upload_parts = parts.iter().enumerate().map(|(i, part)| {
+            // Part numbers start at 1
+            let part_number = i + 1;
+            match part {
+                UploadPartSource::Copy(range) => self
+                    .upload_part_copy(bucket, key, &upload_id, part_number, source_etag, range, span.clone())
+                    .boxed(),
+                UploadPartSource::Data(data) => self
+                    .upload_part(
+                        bucket,
+                        key,
+                        &upload_id,
+                        part_number,
+                        data,
+                        with_checksums,
+                        params.content_md5,
+                        span.clone(),
+                    )
+                    .boxed(),
+            }
+        });
+        // Stops at the first failed part, cancelling the others still in flight
+        let completed_parts = stream::iter(upload_parts)
+            .buffer_unordered(MAX_CONCURRENT_PARTS)
+            .try_collect::<Vec<_>>()
+            .await;
+        let result = match completed_parts {
+            Ok(mut completed_parts) => {
+                completed_parts.sort_by_key(|part| part.part_number);
+                self.complete_multipart_upload(bucket, key, &upload_id, completed_parts, span.clone())
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+
+        if result.is_err() {
+            if let Err(abort_err) = self.abort_multipart_upload(bucket, key, &upload_id, span).await {
+                debug!(?upload_id, error=?abort_err, "failed to abort multi-part upload");
+            }
+        }
+        result
+    }
+
+    async fn create_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        params: &PutObjectParams,
+        span: Span,
+    ) -> ObjectClientResult<String, PutObjectError, S3RequestError> {
+        let body = {
+            let mut message = self.new_part_request("POST", bucket, key, &[("uploads", "")])?;
+            let mut headers = vec![];
+            if let Some(storage_class) = params.storage_class.as_ref() {
+                headers.push(("x-amz-storage-class", storage_class.as_str()));
+            }
+            if let Some(sse) = params.server_side_encryption.as_ref() {
+                headers.push((SSE_TYPE_HEADER_NAME, sse.as_str()));
+            }
+            if let Some(key_id) = params.ssekms_key_id.as_ref() {
+                headers.push((SSE_KEY_ID_HEADER_NAME, key_id.as_str()));
+            }
+            if params.trailing_checksums == PutObjectTrailingChecksums::Enabled {
+                headers.push(("x-amz-checksum-algorithm", "CRC32C"));
+            }
+            for (name, value) in headers {
+                message
+                    .set_header(&Header::new(name, value))
+                    .map_err(S3RequestError::construction_failure)?;
+            }
+
+            self.inner
+                .make_simple_http_request(message, MetaRequestType::Default, span, parse_put_object_error)?
+        };
+
+        let body = body.await?;
+        let root = xmltree::Element::parse(&body[..]).map_err(|e| parse_failure(e.into()))?;
+        get_field(&root, "UploadId").map_err(parse_failure)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn upload_part_copy(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        part_number: usize,
+        source_etag: &ETag,
+        range: &Range<u64>,
+        span: Span,
+    ) -> ObjectClientResult<CompletedPart, PutObjectError, S3RequestError> {
+        let body = {
+            let part_number = part_number.to_string();
+            let query = [("partNumber", part_number.as_str()), ("uploadId", upload_id)];
+            let mut message = self.new_part_request("PUT", bucket, key, &query)?;
+
+            self.inner
+                .set_copy_source(&mut message, bucket, key)
+                .map_err(S3RequestError::construction_failure)?;
+
+            let copy_range = format!("bytes={}-{}", range.start, range.end - 1);
+            for (name, value) in [
+                ("x-amz-copy-source-range", copy_range.as_str()),
+                ("x-amz-copy-source-if-match", source_etag.as_str()),
+            ] {
+                message
+                    .set_header(&Header::new(name, value))
+                    .map_err(S3RequestError::construction_failure)?;
+            }
+
+            self.inner
+                .make_simple_http_request(message, MetaRequestType::Default, span, parse_put_object_error)?
+        };
+
+        // Like CompleteMultipartUpload, UploadPartCopy can fail after returning a 200 status
+        let body = body.await?;
+        let root = xmltree::Element::parse(&body[..]).map_err(|e| parse_failure(e.into()))?;
+        if root.name == "Error" {
+            return Err(match get_field(&root, "Code").ok().as_deref() {
+                Some("PreconditionFailed") | Some("NoSuchKey") => {
+                    ObjectClientError::ServiceError(PutObjectError::PreconditionFailed)
+                }
+                _ => parse_failure(ParseError::CopyFailed(root)),
+            });
+        }
+        Ok(CompletedPart {
+            part_number,
+            etag: get_field(&root, "ETag").map_err(parse_failure)?,
+            checksum_crc32c: get_field(&root, "ChecksumCRC32C").ok(),
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn upload_part(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        part_number: usize,
+        data: &[u8],
+        with_checksums: bool,
+        with_content_md5: bool,
+        span: Span,
+    ) -> ObjectClientResult<CompletedPart, PutObjectError, S3RequestError> {
+        let checksum_crc32c = with_checksums.then(|| crc32c_to_base64(&crc32c::checksum(data)));
+        let content_md5 = with_content_md5.then(|| Base64::encode_string(&md5::Md5::digest(data)));
+        let response_headers: Arc<Mutex<Option<Headers>>> = Default::default();
+
+        let body = {
+            let part_number = part_number.to_string();
+            let query = [("partNumber", part_number.as_str()), ("uploadId", upload_id)];
+            let mut message = self.new_part_request("PUT", bucket, key, &query)?;
+            message
+                .set_header(&Header::new("Content-Length", data.len().to_string()))
+                .map_err(S3RequestError::construction_failure)?;
+            if let Some(checksum) = checksum_crc32c.as_ref() {
+                message
+                    .set_header(&Header::new("x-amz-checksum-crc32c", checksum))
+                    .map_err(S3RequestError::construction_failure)?;
+            }
+            if let Some(content_md5) = content_md5.as_ref() {
+                message
+                    .set_header(&Header::new("Content-MD5", content_md5))
+                    .map_err(S3RequestError::construction_failure)?;
+            }
+            message
+                .inner
+                .set_body(&self.inner.allocator, data.to_vec())
+                .map_err(S3RequestError::construction_failure)?;
+
+            let response_headers_writer = response_headers.clone();
+            let options = super::S3CrtClientInner::new_meta_request_options(message, MetaRequestType::Default);
+            self.inner.make_simple_http_request_from_options(
+                options,
+                span,
+                |_| {},
+                parse_put_object_error,
+                move |headers: &Headers, _: i32| {
+                    *response_headers_writer.lock().unwrap() = Some(headers.clone());
+                },
+            )?
+        };
+
+        let _body = body.await?;
+        let etag = response_headers
+            .lock()
+            .unwrap()
+            .take()
+            .and_then(|headers| try_get_header_value(&headers, "ETag"))
+            .ok_or_else(|| parse_failure(ParseError::MissingHeader("ETag".to_owned())))?;
+        Ok(CompletedPart {
+            part_number,
+            etag,
+            checksum_crc32c,
+        })
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        parts: Vec<CompletedPart>,
+        span: Span,
+    ) -> ObjectClientResult<PutObjectResult, PutObjectError, S3RequestError> {
+        let response_headers: Arc<Mutex<Option<Headers>>> = Default::default();
+
+        let body = {
+            let mut message = self.new_part_request("POST", bucket, key, &[("uploadId", upload_id)])?;
+            let request_body = complete_multipart_upload_body(&parts);
+            message
+                .set_header(&Header::new("Content-Length", request_body.len().to_string()))
+                .map_err(S3RequestError::construction_failure)?;
+            message
+                .inner
+                .set_body(&self.inner.allocator, request_body.into_bytes())
+                .map_err(S3RequestError::construction_failure)?;
+
+            let response_headers_writer = response_headers.clone();
+            let options = super::S3CrtClientInner::new_meta_request_options(message, MetaRequestType::Default);
+            self.inner.make_simple_http_request_from_options(
+                options,
+                span,
+                |_| {},
+                parse_put_object_error,
+                move |headers: &Headers, _: i32| {
+                    *response_headers_writer.lock().unwrap() = Some(headers.clone());
+                },
+            )?
+        };
+
+        // CompleteMultipartUpload can fail after returning a 200 status, with the error in the body
+        let body = body.await?;
+        let root = xmltree::Element::parse(&body[..]).map_err(|e| parse_failure(e.into()))?;
+        if root.name == "Error" {
+            return Err(parse_failure(ParseError::CompleteFailed(root)));
+        }
+
+        let response_headers = response_headers.lock().unwrap().take();
+        let header = |name| response_headers.as_ref().and_then(|h| try_get_header_value(h, name));
+        Ok(PutObjectResult {
+            sse_type: header(SSE_TYPE_HEADER_NAME),
+            sse_kms_key_id: header(SSE_KEY_ID_HEADER_NAME),
+        })
+    }
+
+    async fn abort_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        span: Span,
+    ) -> ObjectClientResult<(), PutObjectError, S3RequestError> {
+        let request = {
+            let message = self.new_part_request("DELETE", bucket, key, &[("uploadId", upload_id)])?;
+            self.inner
+                .make_simple_http_request(message, MetaRequestType::Default, span, parse_put_object_error)?
+        };
+        request.await?;
+        Ok(())
+    }
+
+    fn new_part_request(
+        &self,
+        method: &str,
+        bucket: &str,
+        key: &str,
+        query: &[(&str, &str)],
+    ) -> Result<S3Message, S3RequestError> {
+        let mut message = self
+            .inner
+            .new_request_template(method, bucket, key)
+            .map_err(S3RequestError::construction_failure)?;
+        message
+            .set_request_path_and_query(format!("/{key}"), query)
+            .map_err(S3RequestError::construction_failure)?;
+        Ok(message)
+    }
+}
+
+/// Build the XML body of a CompleteMultipartUpload request.
+fn complete_multipart_upload_body(parts: &[CompletedPart]) -> String {
+    let mut body = String::from("<CompleteMultipartUpload xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">");
+    for part in parts {
+        body.push_str("<Part>");
+        if let Some(checksum) = part.checksum_crc32c.as_ref() {
+            body.push_str(&format!("<ChecksumCRC32C>{checksum}</ChecksumCRC32C>"));
+        }
+        // ETags are quoted strings, so escape the quotes for XML
+        let etag = part.etag.replace('"', "&quot;");
+        body.push_str(&format!(
+            "<ETag>{etag}</ETag><PartNumber>{}</PartNumber>",
+            part.part_number
+        ));
+        body.push_str("</Part>");
+    }
+    body.push_str("</CompleteMultipartUpload>");
+    body
+}
+
+fn parse_failure(error: ParseError) -> ObjectClientError<PutObjectError, S3RequestError> {
+    ObjectClientError::ClientError(S3RequestError::InternalError(error.into()))
+}
+
+fn get_field(element: &xmltree::Element, name: &str) -> Result<String, ParseError> {
+    element
+        .get_child(name)
+        .and_then(|child| child.get_text())
+        .map(|text| text.to_string())
+        .ok_or_else(|| ParseError::MissingField(element.clone(), name.to_owned()))
+}
+
+fn try_get_header_value(headers: &Headers, key: &str) -> Option<String> {
+    headers.get(key).ok()?.value().clone().into_string().ok()
+}
+
+fn parse_put_object_error(result: &MetaRequestResult) -> Option<PutObjectError> {
+    let error_code = || {
+        let body = result.error_response_body.as_ref()?;
+        let root = xmltree::Element::parse(body.as_bytes()).ok()?;
+        Some(root.get_child("Code")?.get_text()?.to_string())
+    };
+    match result.response_status {
+        // The copy source no longer matches the ETag we expect
+        412 => Some(PutObjectError::PreconditionFailed),
+        404 => match error_code()?.deref() {
+            "NoSuchBucket" => Some(PutObjectError::NoSuchBucket),
+            // The copy source was deleted
+            "NoSuchKey" => Some(PutObjectError::PreconditionFailed),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{OsStr, OsString};
+
+    use super::*;
+
+    fn make_result(response_status: i32, body: impl Into<OsString>) -> MetaRequestResult {
+        MetaRequestResult {
+            response_status,
+            crt_error: 1i32.into(),
+            error_response_headers: None,
+            error_response_body: Some(body.into()),
+        }
+    }
+
+    #[test]
+    fn parse_412_precondition_failed() {
+        let body = br#"<?xml version="1.0" encoding="UTF-8"?><Error><Code>PreconditionFailed</Code><Message>At least one of the pre-conditions you specified did not hold</Message><Condition>x-amz-copy-source-If-Match</Condition></Error>"#;
+        let result = make_result(412, OsStr::from_bytes(&body[..]));
+        assert_eq!(
+            parse_put_object_error(&result),
+            Some(PutObjectError::PreconditionFailed)
+        );
+    }
+
+    #[test]
+    fn parse_404_no_such_key() {
+        let body = br#"<?xml version="1.0" encoding="UTF-8"?><Error><Code>NoSuchKey</Code><Message>The specified key does not exist.</Message><Key>hello</Key></Error>"#;
+        let result = make_result(404, OsStr::from_bytes(&body[..]));
+        assert_eq!(
+            parse_put_object_error(&result),
+            Some(PutObjectError::PreconditionFailed)
+        );
+    }
+
+    #[test]
+    fn complete_body_lists_parts_in_order() {
+        let parts = vec![
+            CompletedPart {
+                part_number: 1,
+                etag: "\"etag1\"".to_owned(),
+                checksum_crc32c: None,
+            },
+            CompletedPart {
+                part_number: 2,
+                etag: "\"etag2\"".to_owned(),
+                checksum_crc32c: Some("AAAAAA==".to_owned()),
+            },
+        ];
+        let body = complete_multipart_upload_body(&parts);
+        let root = xmltree::Element::parse(body.as_bytes()).unwrap();
+        let parsed: Vec<_> = root
+            .children
+            .iter()
+            .filter_map(|node| node.as_element())
+            .map(|part| {
+                (
+                    get_field(part, "PartNumber").unwrap(),
+                    get_field(part, "ETag").unwrap(),
+                    get_field(part, "ChecksumCRC32C").ok(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            parsed,
+            vec![
+                ("1".to_owned(), "\"etag1\"".to_owned(), None),
+                ("2".to_owned(), "\"etag2\"".to_owned(), Some("AAAAAA==".to_owned())),
+            ]
+        );
+    }
+}
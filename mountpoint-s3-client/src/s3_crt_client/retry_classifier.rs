@@ -0,0 +1,271 @@
+//! Retries of failed requests that the CRT doesn't consider retryable.
+//!
+//! The CRT retries requests that fail with errors it knows to be transient, like 5xx responses and
+//! S3's throttling errors. S3-compatible services can report transient failures with other
+//! statuses and error codes, which the CRT treats as fatal. A [RetryClassifier] lets users mark
+//! those errors as retryable, in which case the client makes the whole request again. Errors the
+//! CRT already retried are never given to the classifier, so a request is never retried by both.
+
+use std::fmt::Debug;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use mountpoint_s3_crt::io::event_loop::{EventLoopGroup, EventLoopTimer, EventLoopTimerError};
+use mountpoint_s3_crt::s3::client::MetaRequestResult;
+use mountpoint_s3_crt_sys::aws_s3_errors::AWS_ERROR_S3_INVALID_RESPONSE_STATUS;
+use tracing::{debug, warn};
+
+use crate::object_client::{ObjectClientError, ObjectClientResult};
+use crate::s3_crt_client::S3RequestError;
+
+/// How long to wait before the first retry of a request; later retries back off exponentially
+const BACKOFF_SCALE_FACTOR: Duration = Duration::from_millis(500);
+
+/// Longest time to wait between retries of a request
+const MAX_BACKOFF: Duration = Duration::from_secs(20);
+
+/// An error response from S3 that the client didn't recognize, given to a [RetryClassifier]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct S3Error {
+    /// The HTTP status of the response
+    pub status: i32,
+    /// The error code from the response body, if any
+    pub code: Option<String>,
+    /// The error message from the response body, if any
+    pub message: Option<String>,
+}
+
+impl S3Error {
+    pub fn new(status: i32, code: Option<String>, message: Option<String>) -> Self {
+        Self { status, code, message }
+    }
+
+    fn from_result(result: &MetaRequestResult) -> Self {
+        let body = result
+            .error_response_body
+            .as_ref()
+            .and_then(|body| xmltree::Element::parse(body.as_bytes()).ok());
+        let child_text = |name: &str| {
+            let elem = body.as_ref()?.get_child(name)?;
+            Some(elem.get_text()?.into_owned())
+        };
+        Self {
+            status: result.response_status,
+            code: child_text("Code"),
+            message: child_text("Message"),
+        }
+    }
+}
+
+/// Whether to retry a request that failed with an [S3Error]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryDecision {
+    /// Make the request again, if it hasn't been attempted the maximum number of times yet
+    Retry,
+    /// Fail the request with the error
+    DontRetry,
+}
+
+/// A user-provided function that decides whether requests that failed with an unrecognized error
+/// response should be retried. See [S3ClientConfig::retry_classifier](super::S3ClientConfig::retry_classifier).
+#[derive(Clone)]
+pub struct RetryClassifier(Arc<dyn Fn(&S3Error) -> RetryDecision + Send + Sync>);
+
+impl RetryClassifier {
+    pub fn new(classify: impl Fn(&S3Error) -> RetryDecision + Send + Sync + 'static) -> Self {
+        Self(Arc::new(classify))
+    }
+
+    /// Run `request` until it succeeds, fails with an error this classifier doesn't want retried,
+    /// or has been attempted `max_attempts` times. Only unrecognized error responses
+    /// ([S3RequestError::ResponseError]) that the CRT didn't retry itself are given to the
+    /// classifier; all other errors are returned as they are. Backoffs between attempts wait on a
+    /// timer in `event_loop_group`.
+    pub(crate) async fn run<T, E, F, Fut>(
+        &self,
+        event_loop_group: &EventLoopGroup,
+        max_attempts: usize,
+        mut request: F,
+    ) -> ObjectClientResult<T, E, S3RequestError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = ObjectClientResult<T, E, S3RequestError>>,
+    {
+        let mut attempt = 1;
+        loop {
+            let result = request().await;
+            let error = match &result {
+                Err(ObjectClientError::ClientError(S3RequestError::ResponseError(response)))
+                    if !is_retried_by_crt(response) =>
+                {
+                    S3Error::from_result(response)
+                }
+                _ => return result,
+            };
+            if attempt >= max_attempts || (self.0)(&error) == RetryDecision::DontRetry {
+                return result;
+            }
+            let backoff = BACKOFF_SCALE_FACTOR
+                .saturating_mul(1 << (attempt - 1).min(16))
+                .min(MAX_BACKOFF);
+            debug!(
+                ?error,
+                attempt,
+                ?backoff,
+                "retrying request after an error the classifier marked retryable"
+            );
+            metrics::counter!("s3.client.classifier_retries").increment(1);
+            if let Err(err) = sleep(event_loop_group, backoff).await {
+                warn!(?err, "retry backoff failed, not retrying");
+                return result;
+            }
+            attempt += 1;
+        }
+    }
+}
+
+impl Debug for RetryClassifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("RetryClassifier").finish_non_exhaustive()
+    }
+}
+
+/// Whether the CRT treated the error as transient, in which case it has already retried the request
+/// as many times as it's allowed to. The CRT only gives up right away on error responses it reports
+/// as [AWS_ERROR_S3_INVALID_RESPONSE_STATUS].
+fn is_retried_by_crt(result: &MetaRequestResult) -> bool {
+    result.crt_error.raw_error() != AWS_ERROR_S3_INVALID_RESPONSE_STATUS as i32
+}
+
+/// Wait for `duration` on one of the CRT's event loops, without blocking the caller's executor
+async fn sleep(event_loop_group: &EventLoopGroup, duration: Duration) -> Result<(), EventLoopTimerError> {
+    if duration.is_zero() {
+        return Ok(());
+    }
+    let event_loop = event_loop_group.get_next_loop()?;
+    EventLoopTimer::new(&event_loop, duration).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsString;
+
+    use mountpoint_s3_crt::common::allocator::Allocator;
+    use mountpoint_s3_crt_sys::aws_s3_errors::AWS_ERROR_S3_SLOW_DOWN;
+
+    use super::*;
+    use crate::object_client::HeadObjectError;
+
+    const BACKEND_BUSY: &str = r#"<?xml version="1.0" encoding="UTF-8"?><Error><Code>BackendBusy</Code><Message>Please try again.</Message></Error>"#;
+
+    /// A BackendBusy error response, as the CRT reports it after `crt_error`
+    fn error_response(status: i32, crt_error: u32) -> ObjectClientResult<(), HeadObjectError, S3RequestError> {
+        Err(ObjectClientError::ClientError(S3RequestError::ResponseError(
+            MetaRequestResult {
+                response_status: status,
+                crt_error: (crt_error as i32).into(),
+                error_response_headers: None,
+                error_response_body: Some(OsString::from(BACKEND_BUSY)),
+            },
+        )))
+    }
+
+    /// A BackendBusy error the CRT gave up on without retrying
+    fn busy_response() -> ObjectClientResult<(), HeadObjectError, S3RequestError> {
+        error_response(400, AWS_ERROR_S3_INVALID_RESPONSE_STATUS)
+    }
+
+    /// Run a request that fails with `failures` of the given errors before succeeding, and return
+    /// its result and how many times it was attempted.
+    async fn run_request(
+        classifier: &RetryClassifier,
+        max_attempts: usize,
+        failures: usize,
+        failure: fn() -> ObjectClientResult<(), HeadObjectError, S3RequestError>,
+    ) -> (ObjectClientResult<(), HeadObjectError, S3RequestError>, usize) {
+        let event_loop_group = EventLoopGroup::new_default(&Allocator::default(), None, || {}).unwrap();
+        let mut attempts = 0;
+        let result = classifier
+            .run(&event_loop_group, max_attempts, || {
+                attempts += 1;
+                let attempt = attempts;
+                async move {
+                    if attempt <= failures {
+                        failure()
+                    } else {
+                        Ok(())
+                    }
+                }
+            })
+            .await;
+        (result, attempts)
+    }
+
+    fn retry_backend_busy(error: &S3Error) -> RetryDecision {
+        if error.code.as_deref() == Some("BackendBusy") {
+            RetryDecision::Retry
+        } else {
+            RetryDecision::DontRetry
+        }
+    }
+
+    #[test]
+    fn parse_error_response() {
+        let Err(ObjectClientError::ClientError(S3RequestError::ResponseError(result))) = busy_response() else {
+            unreachable!();
+        };
+        let error = S3Error::from_result(&result);
+        assert_eq!(
+            error,
+            S3Error::new(
+                400,
+                Some("BackendBusy".to_owned()),
+                Some("Please try again.".to_owned())
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn classifier_retries_fatal_error() {
+        let classifier = RetryClassifier::new(retry_backend_busy);
+        let (result, attempts) = run_request(&classifier, 3, 1, busy_response).await;
+        assert!(result.is_ok(), "request should succeed after a retry: {result:?}");
+        assert_eq!(attempts, 2);
+    }
+
+    #[tokio::test]
+    async fn classifier_declines_retry() {
+        let classifier = RetryClassifier::new(|_| RetryDecision::DontRetry);
+        let (result, attempts) = run_request(&classifier, 3, 1, busy_response).await;
+        assert!(matches!(
+            result,
+            Err(ObjectClientError::ClientError(S3RequestError::ResponseError(_)))
+        ));
+        assert_eq!(attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn classifier_respects_max_attempts() {
+        let classifier = RetryClassifier::new(retry_backend_busy);
+        let (result, attempts) = run_request(&classifier, 2, 5, busy_response).await;
+        assert!(matches!(
+            result,
+            Err(ObjectClientError::ClientError(S3RequestError::ResponseError(_)))
+        ));
+        assert_eq!(attempts, 2);
+    }
+
+    #[tokio::test]
+    async fn classifier_skips_errors_retried_by_crt() {
+        // The CRT already used up the request's attempts on a 503 SlowDown before reporting it
+        let classifier = RetryClassifier::new(retry_backend_busy);
+        let (result, attempts) = run_request(&classifier, 3, 1, || error_response(503, AWS_ERROR_S3_SLOW_DOWN)).await;
+        assert!(matches!(
+            result,
+            Err(ObjectClientError::ClientError(S3RequestError::ResponseError(_)))
+        ));
+        assert_eq!(attempts, 1);
+    }
+}
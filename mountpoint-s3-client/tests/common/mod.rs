@@ -1,5 +1,3 @@
-#![cfg(feature = "s3_tests")]
-
 use aws_config::BehaviorVersion;
 use aws_sdk_s3 as s3;
 use aws_sdk_s3::config::Region;
@@ -19,6 +17,7 @@ use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt as _;
 use tracing_subscriber::{EnvFilter, Layer};
 
+pub mod stub_server;
 pub mod tracing_test;
 
 /// Enable tracing and CRT logging when running unit tests.
@@ -165,7 +164,7 @@ pub async fn get_mpu_count_for_key(
 
 /// Check the result of a GET against expected bytes.
 pub async fn check_get_result<E: std::fmt::Debug>(
-    result: impl Stream<Item = Result<(u64, Box<[u8]>), E>>,
+    result: impl Stream<Item = Result<(u64, Bytes), E>>,
     range: Option<Range<u64>>,
     expected: &[u8],
 ) {
@@ -0,0 +1,121 @@
+//! A local HTTP server with canned responses, for tests of how the client behaves against
+//! responses that S3 can't be made to send.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+
+pub const EMPTY_LISTING: &str = r#"<?xml version="1.0" encoding="UTF-8"?><ListBucketResult><Name>bucket</Name><Prefix></Prefix><KeyCount>0</KeyCount><MaxKeys>1000</MaxKeys><IsTruncated>false</IsTruncated></ListBucketResult>"#;
+
+/// A request received by a [StubServer]
+#[derive(Debug, Clone)]
+pub struct StubRequest {
+    /// The request line, like `GET /bucket?list-type=2 HTTP/1.1`
+    pub request_line: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl StubRequest {
+    /// The value of the header called `name`, ignoring case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The access key ID the request was signed with, if it was signed
+    pub fn access_key_id(&self) -> Option<&str> {
+        // Authorization: AWS4-HMAC-SHA256 Credential=<access key ID>/<scope>, ...
+        let (_, credential) = self.header("authorization")?.split_once("Credential=")?;
+        credential.split('/').next()
+    }
+}
+
+/// A server that answers the `n`th request it receives (counting from 0) with the status and body
+/// `respond` returns for it, and records the requests. Only requests without a body are supported.
+#[derive(Debug)]
+pub struct StubServer {
+    port: u16,
+    requests: Receiver<StubRequest>,
+    count: Arc<AtomicUsize>,
+}
+
+type Respond = dyn Fn(usize, &StubRequest) -> (u16, &'static str) + Send + Sync;
+
+impl StubServer {
+    pub fn start(respond: impl Fn(usize, &StubRequest) -> (u16, &'static str) + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sender, requests) = channel();
+        let count = Arc::new(AtomicUsize::new(0));
+        let respond: Arc<Respond> = Arc::new(respond);
+        {
+            let count = count.clone();
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let Ok(stream) = stream else {
+                        continue;
+                    };
+                    let (sender, count, respond) = (sender.clone(), count.clone(), respond.clone());
+                    std::thread::spawn(move || serve_connection(stream, &*respond, &count, &sender));
+                }
+            });
+        }
+        Self { port, requests, count }
+    }
+
+    /// The URL to reach the server at
+    pub fn endpoint(&self) -> String {
+        format!("http://127.0.0.1:{}", self.port)
+    }
+
+    /// Wait for the next request the server receives
+    pub fn next_request(&self) -> StubRequest {
+        self.requests.recv().expect("server should still be running")
+    }
+
+    /// Number of requests the server has received
+    pub fn request_count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+}
+
+/// Answer the requests on one keep-alive connection until the client closes it
+fn serve_connection(stream: TcpStream, respond: &Respond, count: &AtomicUsize, sender: &Sender<StubRequest>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    loop {
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+            return;
+        }
+        let mut request = StubRequest {
+            request_line: request_line.trim_end().to_owned(),
+            headers: Vec::new(),
+        };
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                return;
+            }
+            if line.trim_end().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                request.headers.push((name.trim().to_owned(), value.trim().to_owned()));
+            }
+        }
+        let (status, body) = respond(count.fetch_add(1, Ordering::SeqCst), &request);
+        let _ = sender.send(request);
+        let response = format!(
+            "HTTP/1.1 {status} Status\r\nContent-Type: application/xml\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        if writer.write_all(response.as_bytes()).is_err() {
+            return;
+        }
+    }
+}
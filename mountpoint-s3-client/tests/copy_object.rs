@@ -0,0 +1,60 @@
+#![cfg(feature = "s3_tests")]
+
+pub mod common;
+
+use aws_sdk_s3::primitives::ByteStream;
+use bytes::Bytes;
+use common::*;
+use mountpoint_s3_client::error::{CopyObjectError, ObjectClientError};
+use mountpoint_s3_client::{ObjectClient, S3CrtClient};
+
+#[tokio::test]
+async fn test_copy_object() {
+    let sdk_client = get_test_sdk_client().await;
+    let (bucket, prefix) = get_test_bucket_and_prefix("test_copy_object");
+
+    let source_key = format!("{prefix}/source");
+    let destination_key = format!("{prefix}/dest");
+    let body = b"hello world!";
+    sdk_client
+        .put_object()
+        .bucket(&bucket)
+        .key(&source_key)
+        .body(ByteStream::from(Bytes::from_static(body)))
+        .send()
+        .await
+        .unwrap();
+
+    let client: S3CrtClient = get_test_client();
+    let _result = client
+        .copy_object(&bucket, &source_key, &destination_key, None, &Default::default())
+        .await
+        .expect("copy_object should succeed");
+
+    let result = sdk_client
+        .get_object()
+        .bucket(&bucket)
+        .key(&destination_key)
+        .send()
+        .await
+        .expect("destination object should exist");
+    let copied = result.body.collect().await.unwrap().into_bytes();
+    assert_eq!(&copied[..], &body[..]);
+}
+
+#[tokio::test]
+async fn test_copy_object_no_source() {
+    let (bucket, prefix) = get_test_bucket_and_prefix("test_copy_object_no_source");
+
+    let source_key = format!("{prefix}/nonexistent_key");
+    let destination_key = format!("{prefix}/dest");
+
+    let client: S3CrtClient = get_test_client();
+    let result = client
+        .copy_object(&bucket, &source_key, &destination_key, None, &Default::default())
+        .await;
+    assert!(matches!(
+        result,
+        Err(ObjectClientError::ServiceError(CopyObjectError::NotFound))
+    ));
+}
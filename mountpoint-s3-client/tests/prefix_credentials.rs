@@ -0,0 +1,53 @@
+//! Tests that requests are signed with the credentials configured for their key's prefix, against
+//! a local HTTP server that records who signed each request.
+
+pub mod common;
+
+use common::stub_server::{StubServer, EMPTY_LISTING};
+use mountpoint_s3_client::config::{AddressingStyle, EndpointConfig, S3ClientAuthConfig, S3ClientConfig};
+use mountpoint_s3_client::{ObjectClient, S3CrtClient};
+use mountpoint_s3_crt::auth::credentials::{CredentialsProvider, CredentialsProviderStaticOptions};
+use mountpoint_s3_crt::common::allocator::Allocator;
+use mountpoint_s3_crt::common::uri::Uri;
+use test_case::test_case;
+
+fn static_provider(access_key_id: &str) -> S3ClientAuthConfig {
+    let options = CredentialsProviderStaticOptions {
+        access_key_id,
+        secret_access_key: "secret",
+        session_token: None,
+    };
+    let provider = CredentialsProvider::new_static(&Allocator::default(), options).unwrap();
+    S3ClientAuthConfig::Provider(provider)
+}
+
+fn new_client(server: &StubServer) -> S3CrtClient {
+    let endpoint = Uri::new_from_str(&Allocator::default(), server.endpoint()).unwrap();
+    let config = S3ClientConfig::new()
+        .endpoint_config(
+            EndpointConfig::new("us-east-1")
+                .endpoint(endpoint)
+                .addressing_style(AddressingStyle::Path),
+        )
+        .auth_config(static_provider("DEFAULT"))
+        .prefix_auth_config("a/", static_provider("PREFIXA"))
+        .prefix_auth_config("a/b/", static_provider("PREFIXAB"));
+    S3CrtClient::new(config).expect("could not create test client")
+}
+
+#[test_case("c/d", "DEFAULT"; "no matching prefix")]
+#[test_case("a/x", "PREFIXA"; "one matching prefix")]
+#[test_case("a/b/x", "PREFIXAB"; "longest matching prefix")]
+#[test_case("a/bx", "PREFIXA"; "partial component")]
+#[tokio::test]
+async fn test_prefix_credentials(key: &str, expected_access_key_id: &str) {
+    let server = StubServer::start(|_, _| (200, EMPTY_LISTING));
+    let client = new_client(&server);
+
+    // Only who signed the requests matters, not whether the responses make sense for them
+    let _ = client.delete_object("bucket", key).await;
+    assert_eq!(server.next_request().access_key_id(), Some(expected_access_key_id));
+
+    let _ = client.list_objects("bucket", None, "/", 1000, key).await;
+    assert_eq!(server.next_request().access_key_id(), Some(expected_access_key_id));
+}
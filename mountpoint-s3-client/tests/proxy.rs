@@ -0,0 +1,36 @@
+#![cfg(feature = "s3_tests")]
+
+pub mod common;
+
+use common::stub_server::StubServer;
+use common::*;
+use mountpoint_s3_client::config::{EndpointConfig, ProxyConfig, S3ClientConfig};
+use mountpoint_s3_client::{ObjectClient, S3CrtClient};
+
+#[tokio::test]
+async fn test_requests_sent_to_proxy() {
+    // A "proxy" that refuses every request
+    let proxy = StubServer::start(|_, _| (502, ""));
+
+    let bucket = get_test_bucket();
+    let endpoint_config = EndpointConfig::new(&get_test_region());
+    let config = S3ClientConfig::new()
+        .endpoint_config(endpoint_config.clone())
+        .proxy(ProxyConfig::from_url(&proxy.endpoint()).unwrap());
+    let client = S3CrtClient::new(config).expect("could not create test client");
+
+    let _ = client
+        .head_object(&bucket, "test_requests_sent_to_proxy")
+        .await
+        .expect_err("the proxy doesn't forward requests");
+
+    // Requests to S3 are over TLS, so the client should have asked the proxy for a tunnel to the
+    // bucket's endpoint
+    let request_line = proxy.next_request().request_line;
+    let endpoint = endpoint_config.resolve_for_bucket(&bucket).unwrap().uri().unwrap();
+    let expected = format!("CONNECT {}:443 ", endpoint.host_name().to_str().unwrap());
+    assert!(
+        request_line.starts_with(&expected),
+        "unexpected request to proxy: {request_line:?}"
+    );
+}
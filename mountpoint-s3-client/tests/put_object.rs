@@ -347,6 +347,71 @@ async fn test_put_checksums(trailing_checksums: PutObjectTrailingChecksums) {
     }
 }
 
+#[tokio::test]
+async fn test_put_content_md5() {
+    const PART_SIZE: usize = 5 * 1024 * 1024;
+    let (bucket, prefix) = get_test_bucket_and_prefix("test_put_content_md5");
+    let key = format!("{prefix}hello");
+    let params = PutObjectParams::new()
+        .trailing_checksums(PutObjectTrailingChecksums::Enabled)
+        .content_md5(true);
+
+    // A client that doesn't compute Content-MD5 refuses the request
+    let client_config = S3ClientConfig::new()
+        .part_size(PART_SIZE)
+        .endpoint_config(EndpointConfig::new(&get_test_region()));
+    let client = S3CrtClient::new(client_config).expect("could not create test client");
+    let err = client
+        .put_object(&bucket, &key, &params)
+        .await
+        .expect_err("put_object should fail without compute_content_md5");
+    assert!(matches!(
+        err,
+        ObjectClientError::ClientError(S3RequestError::ConstructionFailure(_))
+    ));
+
+    let client_config = S3ClientConfig::new()
+        .part_size(PART_SIZE)
+        .compute_content_md5(true)
+        .endpoint_config(EndpointConfig::new(&get_test_region()));
+    let client = S3CrtClient::new(client_config).expect("could not create test client");
+
+    let mut rng = rand::thread_rng();
+    let mut contents = vec![0u8; PART_SIZE * 2];
+    rng.fill(&mut contents[..]);
+
+    let mut request = client
+        .put_object(&bucket, &key, &params)
+        .await
+        .expect("put_object should succeed");
+    request.write(&contents).await.unwrap();
+    request
+        .review_and_complete(move |review| {
+            // The checksums are still computed for the review
+            assert_eq!(review.checksum_algorithm, Some(ChecksumAlgorithm::Crc32c));
+            true
+        })
+        .await
+        .unwrap();
+
+    // The parts were sent with Content-MD5 rather than trailing checksums
+    let sdk_client = get_test_sdk_client().await;
+    let attributes = sdk_client
+        .get_object_attributes()
+        .bucket(&bucket)
+        .key(&key)
+        .object_attributes(aws_sdk_s3::types::ObjectAttributes::ObjectParts)
+        .send()
+        .await
+        .unwrap();
+    for part in attributes.object_parts().map(|parts| parts.parts()).unwrap_or_default() {
+        assert!(part.checksum_crc32_c().is_none());
+    }
+
+    let result = client.get_object(&bucket, &key, None, None).await.unwrap();
+    check_get_result(result, None, &contents[..]).await;
+}
+
 #[test_case(true; "pass review")]
 #[test_case(false; "fail review")]
 #[tokio::test]
@@ -0,0 +1,69 @@
+#![cfg(feature = "s3_tests")]
+
+pub mod common;
+
+use std::str::FromStr;
+
+use aws_sdk_s3::primitives::ByteStream;
+use bytes::Bytes;
+use common::*;
+use mountpoint_s3_client::error::{ObjectClientError, PutObjectError};
+use mountpoint_s3_client::types::{ETag, PutObjectParams, PutObjectTrailingChecksums, UploadPartSource};
+use mountpoint_s3_client::{ObjectClient, S3CrtClient};
+use rand::Rng;
+use test_case::test_case;
+
+const PART_SIZE: usize = 5 * 1024 * 1024;
+
+#[test_case(PutObjectTrailingChecksums::Disabled; "no checksums")]
+#[test_case(PutObjectTrailingChecksums::Enabled; "crc32c")]
+#[tokio::test]
+async fn test_put_object_from_parts_replace_middle_part(trailing_checksums: PutObjectTrailingChecksums) {
+    let sdk_client = get_test_sdk_client().await;
+    let (bucket, prefix) = get_test_bucket_and_prefix("test_put_object_from_parts_replace_middle_part");
+    let key = format!("{prefix}/hello");
+
+    let mut contents = vec![0u8; 3 * PART_SIZE - 1000];
+    rand::thread_rng().fill(&mut contents[..]);
+    sdk_client
+        .put_object()
+        .bucket(&bucket)
+        .key(&key)
+        .body(ByteStream::from(contents.clone()))
+        .send()
+        .await
+        .unwrap();
+
+    let client: S3CrtClient = get_test_client();
+    let etag = ETag::from_str(&client.head_object(&bucket, &key).await.unwrap().object.etag).unwrap();
+
+    let mut new_part = vec![0u8; PART_SIZE];
+    rand::thread_rng().fill(&mut new_part[..]);
+    let parts = [
+        UploadPartSource::Copy(0..PART_SIZE as u64),
+        UploadPartSource::Data(Bytes::from(new_part.clone())),
+        UploadPartSource::Copy(2 * PART_SIZE as u64..contents.len() as u64),
+    ];
+    let params = PutObjectParams::new().trailing_checksums(trailing_checksums);
+    client
+        .put_object_from_parts(&bucket, &key, &etag, &parts, &params)
+        .await
+        .expect("put_object_from_parts should succeed");
+
+    contents[PART_SIZE..2 * PART_SIZE].copy_from_slice(&new_part);
+    let result = client
+        .get_object(&bucket, &key, None, None)
+        .await
+        .expect("get_object should succeed");
+    check_get_result(result, None, &contents[..]).await;
+
+    // The source ETag no longer matches now that the object has been replaced
+    let err = client
+        .put_object_from_parts(&bucket, &key, &etag, &parts, &params)
+        .await
+        .expect_err("put_object_from_parts should fail");
+    assert!(
+        matches!(err, ObjectClientError::ServiceError(PutObjectError::PreconditionFailed)),
+        "unexpected error: {err:?}"
+    );
+}
@@ -0,0 +1,67 @@
+//! Tests of the retry classifier against a local HTTP server, which can return errors that S3
+//! doesn't.
+
+pub mod common;
+
+use std::num::NonZeroUsize;
+
+use common::stub_server::{StubServer, EMPTY_LISTING};
+use mountpoint_s3_client::config::{
+    AddressingStyle, EndpointConfig, RetryDecision, S3ClientAuthConfig, S3ClientConfig, S3Error,
+};
+use mountpoint_s3_client::error::ObjectClientError;
+use mountpoint_s3_client::{ObjectClient, S3CrtClient};
+use mountpoint_s3_crt::common::allocator::Allocator;
+use mountpoint_s3_crt::common::uri::Uri;
+
+const BACKEND_BUSY: &str = r#"<?xml version="1.0" encoding="UTF-8"?><Error><Code>BackendBusy</Code><Message>Please try again.</Message></Error>"#;
+
+fn retry_backend_busy(error: &S3Error) -> RetryDecision {
+    if error.code.as_deref() == Some("BackendBusy") {
+        RetryDecision::Retry
+    } else {
+        RetryDecision::DontRetry
+    }
+}
+
+fn new_client(server: &StubServer, max_attempts: usize) -> S3CrtClient {
+    let endpoint = Uri::new_from_str(&Allocator::default(), server.endpoint()).unwrap();
+    let config = S3ClientConfig::new()
+        .endpoint_config(
+            EndpointConfig::new("us-east-1")
+                .endpoint(endpoint)
+                .addressing_style(AddressingStyle::Path),
+        )
+        .auth_config(S3ClientAuthConfig::NoSigning)
+        .max_attempts(NonZeroUsize::new(max_attempts).unwrap())
+        .retry_classifier(retry_backend_busy);
+    S3CrtClient::new(config).expect("could not create test client")
+}
+
+#[tokio::test]
+async fn test_classifier_retries_fatal_error() {
+    // A 400 is fatal to the CRT, so only the classifier retries it
+    let server = StubServer::start(|n, _| match n {
+        0 => (400, BACKEND_BUSY),
+        _ => (200, EMPTY_LISTING),
+    });
+    let client = new_client(&server, 3);
+
+    let result = client.list_objects("bucket", None, "/", 1000, "").await;
+    assert!(result.is_ok(), "list should succeed after a retry: {result:?}");
+    assert_eq!(server.request_count(), 2);
+}
+
+#[tokio::test]
+async fn test_classifier_shares_attempts_with_crt() {
+    // The CRT retries a 503 on its own, and the classifier mustn't retry it again once the CRT gives up
+    let server = StubServer::start(|_, _| (503, BACKEND_BUSY));
+    let client = new_client(&server, 3);
+
+    let result = client.list_objects("bucket", None, "/", 1000, "").await;
+    assert!(
+        matches!(result, Err(ObjectClientError::ClientError(_))),
+        "list should fail: {result:?}"
+    );
+    assert_eq!(server.request_count(), 3);
+}
@@ -38,6 +38,7 @@ const CRT_HEADERS: &[&str] = &[
     "common/error.h",
     "http/http.h",
     "http/connection.h",
+    "http/proxy.h",
     "http/request_response.h",
     "io/async_stream.h",
     "io/channel_bootstrap.h",
@@ -0,0 +1,60 @@
+//! Connecting through HTTP proxies
+
+use mountpoint_s3_crt_sys::*;
+
+use crate::ToAwsByteCursor;
+
+/// Options for sending requests through an HTTP proxy. Requests over TLS are tunneled through the
+/// proxy with `CONNECT`, and plain HTTP requests are forwarded to it.
+#[derive(Debug, Clone)]
+pub struct ProxyOptions {
+    host: String,
+    port: u16,
+    basic_auth: Option<(String, String)>,
+}
+
+impl ProxyOptions {
+    /// Connect to the proxy at `host` and `port`.
+    pub fn new(host: &str, port: u16) -> Self {
+        Self {
+            host: host.to_owned(),
+            port,
+            basic_auth: None,
+        }
+    }
+
+    /// Authenticate to the proxy with HTTP basic authentication.
+    pub fn basic_auth(mut self, username: &str, password: &str) -> Self {
+        self.basic_auth = Some((username.to_owned(), password.to_owned()));
+        self
+    }
+
+    /// The host name of the proxy
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// The port of the proxy
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Build the CRT's representation of these options.
+    ///
+    /// SAFETY: the returned struct borrows the strings in `self`, so `self` must outlive it and
+    /// must not be mutated while it's in use.
+    pub(crate) unsafe fn to_inner(&self) -> aws_http_proxy_options {
+        let mut inner = aws_http_proxy_options {
+            connection_type: aws_http_proxy_connection_type::AWS_HPCT_HTTP_LEGACY,
+            host: self.host.as_aws_byte_cursor(),
+            port: self.port as u32,
+            ..Default::default()
+        };
+        if let Some((username, password)) = &self.basic_auth {
+            inner.auth_type = aws_http_proxy_authentication_type::AWS_HPAT_BASIC;
+            inner.auth_username = username.as_aws_byte_cursor();
+            inner.auth_password = password.as_aws_byte_cursor();
+        }
+        inner
+    }
+}
@@ -253,6 +253,9 @@ impl<'a> Iterator for HeadersIterator<'a> {
 pub struct Message {
     /// The pointer to the inner `aws_http_message`.
     pub(crate) inner: NonNull<aws_http_message>,
+
+    /// The input stream for the body of this message, if set, and the bytes it reads from.
+    body: Option<(NonNull<aws_input_stream>, Box<[u8]>)>,
 }
 
 impl Message {
@@ -264,7 +267,7 @@ impl Message {
         // SAFETY: `allocator.inner` is a valid `aws_allocator`.
         let inner = unsafe { aws_http_message_new_request(allocator.inner.as_ptr()).ok_or_last_error()? };
 
-        Ok(Self { inner })
+        Ok(Self { inner, body: None })
     }
 
     /// Add a header to this message. If the header already exists in the message, this will add a
@@ -306,6 +309,28 @@ impl Message {
         }
     }
 
+    /// Set the body of this message. The message keeps ownership of the bytes so that the body
+    /// stream can read them for as long as the message is alive. This does not set the
+    /// `Content-Length` header.
+    pub fn set_body(&mut self, allocator: &Allocator, body: impl Into<Box<[u8]>>) -> Result<(), Error> {
+        let body = body.into();
+        // SAFETY: the stream reads from `body`, which we keep alive (and unmodified) until after
+        // the stream is released in `Drop`. `aws_input_stream_new_from_cursor` copies the cursor
+        // struct itself.
+        let stream = unsafe {
+            let cursor = body.as_aws_byte_cursor();
+            aws_input_stream_new_from_cursor(allocator.inner.as_ptr(), &cursor).ok_or_last_error()?
+        };
+        // SAFETY: `self.inner` and `stream` are valid. Any previous body is released below, after
+        // the message stopped pointing to it.
+        unsafe { aws_http_message_set_body_stream(self.inner.as_ptr(), stream.as_ptr()) };
+        if let Some((old_stream, _old_body)) = self.body.replace((stream, body)) {
+            // SAFETY: we held a reference to `old_stream`, which is no longer used by the message.
+            unsafe { aws_input_stream_release(old_stream.as_ptr()) };
+        }
+        Ok(())
+    }
+
     /// get the headers from the message and increases the reference count for the Headers in CRT.
     pub fn get_headers(&mut self) -> Result<Headers, Error> {
         // SAFETY: `aws_http_message_get_headers` is safe because self.inner is a valid NonNull `aws_http_message`.
@@ -324,6 +349,13 @@ impl Drop for Message {
         unsafe {
             aws_http_message_release(self.inner.as_ptr());
         }
+        if let Some((stream, _body)) = self.body.take() {
+            // SAFETY: the message no longer uses the stream, and we drop its bytes only after
+            // releasing our reference to the stream.
+            unsafe {
+                aws_input_stream_release(stream.as_ptr());
+            }
+        }
     }
 }
 
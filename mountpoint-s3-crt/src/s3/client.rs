@@ -6,6 +6,7 @@ use crate::common::allocator::Allocator;
 use crate::common::error::Error;
 use crate::common::thread::ThreadId;
 use crate::common::uri::Uri;
+use crate::http::proxy::ProxyOptions;
 use crate::http::request_response::{Headers, Message};
 use crate::io::channel_bootstrap::ClientBootstrap;
 use crate::io::futures::FutureVoid;
@@ -58,6 +59,10 @@ pub struct ClientConfig {
 
     /// The region
     region: Option<String>,
+
+    /// The proxy to send requests through, and the CRT's view of it, which borrows its strings.
+    /// Boxed so that the pointer in `inner` stays valid when this [ClientConfig] moves.
+    proxy_options: Option<(ProxyOptions, Box<aws_http_proxy_options>)>,
 }
 
 impl ClientConfig {
@@ -122,6 +127,26 @@ impl ClientConfig {
         self
     }
 
+    /// Compute the MD5 of each uploaded part and send it in a `Content-MD5` header.
+    pub fn compute_content_md5(&mut self, compute_content_md5: bool) -> &mut Self {
+        self.inner.compute_content_md5 = if compute_content_md5 {
+            aws_s3_meta_request_compute_content_md5::AWS_MR_CONTENT_MD5_ENABLED
+        } else {
+            aws_s3_meta_request_compute_content_md5::AWS_MR_CONTENT_MD5_DISABLED
+        };
+        self
+    }
+
+    /// Send requests through an HTTP proxy
+    pub fn proxy_options(&mut self, proxy_options: ProxyOptions) -> &mut Self {
+        // SAFETY: the strings the inner options borrow are owned by `proxy_options`, which we store
+        // alongside them and never mutate, and which lives as long as the `ClientConfig`.
+        let mut inner = Box::new(unsafe { proxy_options.to_inner() });
+        self.inner.proxy_options = &mut *inner;
+        self.proxy_options = Some((proxy_options, inner));
+        self
+    }
+
     /// When set, this will cap the number of active connections. Otherwise, the client will
     /// determine this value based on throughput_target_gbps. (Recommended)
     pub fn max_active_connections_override(&mut self, max_active_connections_override: u32) -> &mut Self {
@@ -1,10 +1,10 @@
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use clap::{Arg, Command};
+use clap::{Arg, ArgAction, Command};
 use futures::executor::{block_on, ThreadPool};
-use mountpoint_s3::prefetch::{default_prefetch, Prefetch, PrefetchResult};
+use mountpoint_s3::prefetch::{default_prefetch, BufferPool, Prefetch, PrefetchResult, MAX_PREFERRED_PART_SIZE};
 use mountpoint_s3_client::config::{EndpointConfig, S3ClientConfig};
 use mountpoint_s3_client::types::ETag;
 use mountpoint_s3_client::S3CrtClient;
@@ -48,6 +48,22 @@ fn main() {
                 .long("iterations")
                 .help("Number of times to download"),
         )
+        .arg(
+            Arg::new("read-size")
+                .long("read-size")
+                .help("Size of each read from the prefetcher [default: 1MiB]"),
+        )
+        .arg(
+            Arg::new("vectored")
+                .long("vectored")
+                .action(ArgAction::SetTrue)
+                .help("Read with read_vectored, which doesn't copy reads that span several parts"),
+        )
+        .arg(
+            Arg::new("buffer-pool-size")
+                .long("buffer-pool-size")
+                .help("Memory in bytes for a pool of buffers to hold downloaded parts [default: no pool]"),
+        )
         .arg(Arg::new("region").long("region").default_value("us-east-1"))
         .get_matches();
 
@@ -67,6 +83,15 @@ fn main() {
     let iterations = matches
         .get_one::<String>("iterations")
         .map(|s| s.parse::<usize>().expect("iterations must be a number"));
+    let read_size = matches
+        .get_one::<String>("read-size")
+        .map(|s| s.parse::<usize>().expect("read size must be a usize"))
+        .unwrap_or(1 << 20);
+    let vectored = matches.get_flag("vectored");
+    let buffer_pool_size = matches
+        .get_one::<String>("buffer-pool-size")
+        .map(|s| s.parse::<usize>().expect("buffer pool size must be a usize"))
+        .unwrap_or(0);
     let region = matches.get_one::<String>("region").unwrap();
 
     let mut config = S3ClientConfig::new().endpoint_config(EndpointConfig::new(region));
@@ -76,6 +101,10 @@ fn main() {
     if let Some(part_size) = part_size {
         config = config.part_size(part_size);
     }
+    if buffer_pool_size > 0 {
+        let buffer_pool = BufferPool::with_memory_limit(MAX_PREFERRED_PART_SIZE, buffer_pool_size, Default::default());
+        config = config.body_buffer_pool(Arc::new(buffer_pool));
+    }
     let client = Arc::new(S3CrtClient::new(config).expect("couldn't create client"));
 
     for i in 0..iterations.unwrap_or(1) {
@@ -84,6 +113,7 @@ fn main() {
         let received_size = Arc::new(AtomicU64::new(0));
 
         let start = Instant::now();
+        let start_cpu = cpu_time();
 
         let mut request = manager.prefetch(client.clone(), bucket, key, size, ETag::for_tests());
         block_on(async {
@@ -92,20 +122,39 @@ fn main() {
                 if offset >= size {
                     break;
                 }
-                let bytes = request.read(offset, 1 << 20).await.unwrap();
-                received_size.fetch_add(bytes.len() as u64, Ordering::SeqCst);
+                // Validate the checksums, like the file system does before replying to a read
+                let len = if vectored {
+                    let parts = request.read_vectored(offset, read_size).await.unwrap();
+                    parts.into_iter().map(|part| part.into_bytes().unwrap().len()).sum()
+                } else {
+                    let bytes = request.read(offset, read_size).await.unwrap();
+                    bytes.into_bytes().unwrap().len()
+                };
+                received_size.fetch_add(len as u64, Ordering::SeqCst);
             }
         });
 
         let elapsed = start.elapsed();
+        let elapsed_cpu = cpu_time() - start_cpu;
 
         let received_size = received_size.load(Ordering::SeqCst);
         println!(
-            "{}: received {} bytes in {:.2}s: {:.2}MiB/s",
+            "{}: received {} bytes in {:.2}s: {:.2}MiB/s, {:.2}s CPU time",
             i,
             received_size,
             elapsed.as_secs_f64(),
-            (received_size as f64) / elapsed.as_secs_f64() / (1024 * 1024) as f64
+            (received_size as f64) / elapsed.as_secs_f64() / (1024 * 1024) as f64,
+            elapsed_cpu.as_secs_f64(),
         );
     }
 }
+
+/// User and system CPU time used by this process so far
+fn cpu_time() -> Duration {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    // SAFETY: `usage` is a valid `rusage` for `getrusage` to write to
+    let ret = unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) };
+    assert_eq!(ret, 0, "getrusage failed");
+    let to_duration = |t: libc::timeval| Duration::new(t.tv_sec as u64, t.tv_usec as u32 * 1000);
+    to_duration(usage.ru_utime) + to_duration(usage.ru_stime)
+}
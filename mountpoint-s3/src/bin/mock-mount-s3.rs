@@ -10,8 +10,11 @@
 //!
 //! This binary is intended only for use in testing and development of Mountpoint.
 
+use std::sync::Arc;
+
 use futures::executor::ThreadPool;
 use mountpoint_s3::cli::CliArgs;
+use mountpoint_s3::mem_limiter::MemoryLimiter;
 use mountpoint_s3::s3::S3Personality;
 use mountpoint_s3_client::mock_client::throughput_client::ThroughputMockClient;
 use mountpoint_s3_client::mock_client::{MockClientConfig, MockObject};
@@ -21,7 +24,10 @@ fn main() -> anyhow::Result<()> {
     mountpoint_s3::cli::main(create_mock_client)
 }
 
-fn create_mock_client(args: &CliArgs) -> anyhow::Result<(ThroughputMockClient, ThreadPool, S3Personality)> {
+fn create_mock_client(
+    args: &CliArgs,
+    memory_limiter: &Arc<MemoryLimiter>,
+) -> anyhow::Result<(ThroughputMockClient, ThreadPool, S3Personality)> {
     // An extra little safety thing to make sure we can distinguish the real mount-s3 binary and
     // this one. Buckets starting with "sthree-" are always invalid against real S3:
     // https://docs.aws.amazon.com/AmazonS3/latest/userguide/bucketnamingrules.html
@@ -41,6 +47,9 @@ fn create_mock_client(args: &CliArgs) -> anyhow::Result<(ThroughputMockClient, T
         unordered_list_seed: None,
     };
     let client = ThroughputMockClient::new(config, max_throughput_gbps);
+    if let Some(buffer_pool) = args.read_buffer_pool(memory_limiter) {
+        client.set_body_buffer_pool(Arc::new(buffer_pool));
+    }
 
     let runtime = ThreadPool::builder().name_prefix("runtime").create()?;
 
@@ -0,0 +1,347 @@
+//! A synchronous facade over [S3Filesystem], for applications that don't run an async executor.
+//!
+//! [BlockingFilesystem] works in paths relative to the mount point rather than inodes and handles.
+//! Each call runs to completion as a single task on the runtime it was given, while the calling
+//! thread waits for it for at most the configured timeout. A call that times out keeps running in
+//! the background until it finishes, so it still releases any inodes and handles it used.
+//!
+//! ```
+//! use std::sync::Arc;
+//!
+//! use futures::executor::ThreadPool;
+//! use mountpoint_s3::blocking::BlockingFilesystem;
+//! use mountpoint_s3::test_utils::{make_test_filesystem, ETag, MockObject};
+//!
+//! let (client, fs) = make_test_filesystem("test_bucket", &Default::default(), Default::default());
+//! client.add_object("dir/hello.txt", MockObject::from_bytes(b"hello world", ETag::for_tests()));
+//!
+//! let fs = BlockingFilesystem::new(Arc::new(fs), ThreadPool::new().unwrap());
+//! let data = fs.read_range("dir/hello.txt", 6, 5).unwrap();
+//! assert_eq!(&data[..], b"world");
+//! ```
+
+use std::future::Future;
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+
+use futures::task::{Spawn, SpawnExt};
+use mountpoint_s3_client::ObjectClient;
+use tracing::trace;
+
+use crate::fs::{DirectoryEntry, DirectoryReplier, Entry, Error, InodeNo};
+use crate::prefetch::Prefetch;
+use crate::sync::Arc;
+use crate::{err, S3Filesystem};
+
+/// How long each call waits for its result unless [BlockingFilesystem::with_timeout] says otherwise
+pub const DEFAULT_CALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Size of each read [BlockingFilesystem::read_range] makes from the file system
+const READ_SIZE: u32 = 1024 * 1024;
+
+/// A synchronous wrapper around an [S3Filesystem]. Calls block the current thread, so they must not
+/// be made from async code, which should use the [S3Filesystem] directly.
+pub struct BlockingFilesystem<Client, Prefetcher, Runtime>
+where
+    Client: ObjectClient + Send + Sync + 'static,
+    Prefetcher: Prefetch,
+{
+    fs: Arc<S3Filesystem<Client, Prefetcher>>,
+    runtime: Runtime,
+    timeout: Duration,
+}
+
+impl<Client, Prefetcher, Runtime> BlockingFilesystem<Client, Prefetcher, Runtime>
+where
+    Client: ObjectClient + Send + Sync + 'static,
+    Prefetcher: Prefetch,
+    S3Filesystem<Client, Prefetcher>: Send + Sync,
+    Runtime: Spawn,
+{
+    /// Wrap a file system, running its calls on `runtime`. The runtime can be shared with the rest
+    /// of the application, including the file system's prefetcher.
+    pub fn new(fs: Arc<S3Filesystem<Client, Prefetcher>>, runtime: Runtime) -> Self {
+        Self {
+            fs,
+            runtime,
+            timeout: DEFAULT_CALL_TIMEOUT,
+        }
+    }
+
+    /// Fail calls with `ETIMEDOUT` if they take longer than `timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The wrapped file system
+    pub fn fs(&self) -> &Arc<S3Filesystem<Client, Prefetcher>> {
+        &self.fs
+    }
+
+    /// Look up the file or directory at `path`, relative to the mount point. An empty path is the
+    /// mount point itself.
+    pub fn lookup_path(&self, path: impl AsRef<Path>) -> Result<Entry, Error> {
+        let path = path.as_ref();
+        trace!(?path, "blocking:lookup_path");
+        self.run("lookup_path", path, |_fs, entry| async move { Ok(entry) })
+    }
+
+    /// Read up to `len` bytes of the file at `path`, starting at `offset`. The result is only
+    /// shorter than `len` if the file ends first.
+    pub fn read_range(&self, path: impl AsRef<Path>, offset: u64, len: usize) -> Result<Vec<u8>, Error> {
+        let path = path.as_ref();
+        trace!(?path, offset, len, "blocking:read_range");
+        self.run("read_range", path, move |fs, entry| async move {
+            let ino = entry.attr.ino;
+            let fh = fs.open(ino, libc::O_RDONLY, 0).await?.fh;
+            let result = async {
+                // Don't trust `len` for the allocation, as callers may ask for "the rest of the file"
+                let remaining = entry.attr.size.saturating_sub(offset);
+                let mut data = Vec::with_capacity(len.min(usize::try_from(remaining).unwrap_or(usize::MAX)));
+                while data.len() < len {
+                    let size = (len - data.len()).min(READ_SIZE as usize) as u32;
+                    let parts = fs
+                        .read_vectored(ino, fh, (offset + data.len() as u64) as i64, size, 0, None)
+                        .await?;
+                    let before = data.len();
+                    for part in parts {
+                        data.extend_from_slice(&part);
+                    }
+                    if data.len() == before {
+                        break;
+                    }
+                }
+                Ok(data)
+            }
+            .await;
+            fs.release(ino, fh, 0, None, false).await?;
+            result
+        })
+    }
+
+    /// List the directory at `path`, relative to the mount point, without `.` and `..`
+    pub fn list_dir(&self, path: impl AsRef<Path>) -> Result<Vec<DirectoryEntry>, Error> {
+        let path = path.as_ref();
+        trace!(?path, "blocking:list_dir");
+        self.run("list_dir", path, |fs, entry| async move {
+            let ino = entry.attr.ino;
+            let fh = fs.opendir(ino, 0).await?.fh;
+            let result = async {
+                let mut entries = Vec::new();
+                let mut offset = 0;
+                loop {
+                    let reply = fs.readdir(ino, fh, offset, EntryCollector::default()).await?;
+                    let Some(last) = reply.entries.last() else {
+                        break;
+                    };
+                    offset = last.offset;
+                    entries.extend(
+                        reply
+                            .entries
+                            .into_iter()
+                            .filter(|entry| entry.name != "." && entry.name != ".."),
+                    );
+                }
+                Ok(entries)
+            }
+            .await;
+            fs.releasedir(ino, fh, 0).await?;
+            result
+        })
+    }
+
+    /// Look up `path` and run `op` on its entry, as one task on the runtime, and wait for the
+    /// result. The inodes looked up along the way are forgotten once `op` is done.
+    fn run<T, Op, Fut>(&self, name: &str, path: &Path, op: Op) -> Result<T, Error>
+    where
+        T: Send + 'static,
+        Op: FnOnce(Arc<S3Filesystem<Client, Prefetcher>>, Entry) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<T, Error>> + Send,
+    {
+        check_not_async(name);
+
+        let fs = self.fs.clone();
+        let path = path.to_owned();
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let task = async move {
+            let mut looked_up: Vec<InodeNo> = Vec::new();
+            let result = match fs.lookup_path(&path, &mut looked_up).await {
+                Ok(entry) => op(fs.clone(), entry).await,
+                Err(e) => Err(e),
+            };
+            // Nobody else holds on to the inodes we looked up, so drop them as the kernel would
+            for ino in looked_up.into_iter().rev() {
+                fs.forget(ino, 1).await;
+            }
+            // The caller may have given up waiting, in which case nobody wants the result
+            let _ = sender.send(result);
+        };
+        self.runtime
+            .spawn(task)
+            .map_err(|e| err!(libc::EIO, source:e, "failed to spawn {}", name))?;
+
+        match receiver.recv_timeout(self.timeout) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => Err(err!(
+                libc::ETIMEDOUT,
+                "{} did not complete within {:?}",
+                name,
+                self.timeout
+            )),
+            Err(RecvTimeoutError::Disconnected) => Err(err!(libc::EIO, "{} was dropped before completing", name)),
+        }
+    }
+}
+
+/// Panic if we're running inside a futures executor or (with the `tokio` feature) a Tokio runtime,
+/// including their `block_on` calls, where blocking would stall the executor
+fn check_not_async(name: &str) {
+    let in_futures_executor = futures::executor::enter().is_err();
+    #[cfg(any(test, feature = "tokio"))]
+    let in_tokio_runtime = tokio::runtime::Handle::try_current().is_ok();
+    #[cfg(not(any(test, feature = "tokio")))]
+    let in_tokio_runtime = false;
+    if in_futures_executor || in_tokio_runtime {
+        panic!("BlockingFilesystem::{name} must not be called from async code; use S3Filesystem directly instead");
+    }
+}
+
+/// A [DirectoryReplier] that takes every entry it's given
+#[derive(Debug, Default)]
+struct EntryCollector {
+    entries: Vec<DirectoryEntry>,
+}
+
+impl DirectoryReplier for EntryCollector {
+    fn add(&mut self, entry: DirectoryEntry) -> bool {
+        self.entries.push(entry);
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use futures::executor::ThreadPool;
+
+    use crate::fs::FUSE_ROOT_INODE;
+    use crate::prefetch::DefaultPrefetcher;
+    use crate::test_utils::{make_test_filesystem, ETag, MockClient, MockObject, Operation};
+
+    use super::*;
+
+    type TestBlockingFilesystem = BlockingFilesystem<Arc<MockClient>, DefaultPrefetcher<ThreadPool>, ThreadPool>;
+
+    fn make_blocking_filesystem(bucket: &str) -> (Arc<MockClient>, TestBlockingFilesystem) {
+        let (client, fs) = make_test_filesystem(bucket, &Default::default(), Default::default());
+        let runtime = ThreadPool::builder().pool_size(2).create().unwrap();
+        (client, BlockingFilesystem::new(Arc::new(fs), runtime))
+    }
+
+    #[test]
+    fn test_blocking_from_threads() {
+        let (client, fs) = make_blocking_filesystem("test_blocking_from_threads");
+        let object = MockObject::ramp(0xaa, 3 * 1024 * 1024 + 17, ETag::for_tests());
+        let expected = object.read(0, object.len());
+        client.add_object("dir/ramp.bin", object);
+        client.add_object("dir/sub/file.txt", MockObject::from_bytes(b"hello", ETag::for_tests()));
+
+        let fs = Arc::new(fs);
+        let threads: Vec<_> = (0..4)
+            .map(|i| {
+                let fs = fs.clone();
+                let expected = expected.clone();
+                thread::spawn(move || {
+                    let offset = i * 1024 * 1024 + 5;
+                    let data = fs.read_range("dir/ramp.bin", offset as u64, 1024 * 1024).unwrap();
+                    let end = (offset + 1024 * 1024).min(expected.len());
+                    assert_eq!(&data[..], &expected[offset..end]);
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let entry = fs.lookup_path("dir/sub/file.txt").unwrap();
+        assert_eq!(entry.attr.size, 5);
+        let root = fs.lookup_path("").unwrap();
+        assert_eq!(root.attr.ino, FUSE_ROOT_INODE);
+
+        let names: Vec<_> = fs
+            .list_dir("dir")
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        assert_eq!(names, ["ramp.bin", "sub"]);
+
+        let err = fs
+            .read_range("dir/missing.bin", 0, 10)
+            .expect_err("file should not exist");
+        assert_eq!(err.to_errno(), libc::ENOENT);
+        let err = fs.list_dir("dir/ramp.bin").expect_err("files can't be listed");
+        assert_eq!(err.to_errno(), libc::ENOTDIR);
+    }
+
+    #[test]
+    fn test_blocking_read_rest_of_file() {
+        let (client, fs) = make_blocking_filesystem("test_blocking_read_rest_of_file");
+        let object = MockObject::ramp(0xaa, 2 * 1024 * 1024 + 17, ETag::for_tests());
+        let expected = object.read(0, object.len());
+        client.add_object("ramp.bin", object);
+
+        let data = fs.read_range("ramp.bin", 0, usize::MAX).unwrap();
+        assert_eq!(&data[..], &expected[..]);
+        let data = fs.read_range("ramp.bin", 1024 * 1024, usize::MAX).unwrap();
+        assert_eq!(&data[..], &expected[1024 * 1024..]);
+        let data = fs.read_range("ramp.bin", 4 * 1024 * 1024, usize::MAX).unwrap();
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn test_blocking_timeout() {
+        let (client, fs) = make_blocking_filesystem("test_blocking_timeout");
+        client.add_object("file.txt", MockObject::from_bytes(b"hello", ETag::for_tests()));
+        let fs = fs.with_timeout(Duration::from_millis(100));
+
+        client.set_operation_latency(Operation::HeadObject, Duration::from_secs(1));
+        client.set_operation_latency(Operation::ListObjectsV2, Duration::from_secs(1));
+        let err = fs.lookup_path("file.txt").expect_err("lookup should time out");
+        assert_eq!(err.to_errno(), libc::ETIMEDOUT);
+    }
+
+    #[tokio::test]
+    async fn test_blocking_in_tokio_task_panics() {
+        let (_client, fs) = make_blocking_filesystem("test_blocking_in_tokio_task_panics");
+        let err = tokio::spawn(async move {
+            let _ = fs.lookup_path("file.txt");
+        })
+        .await
+        .expect_err("blocking call should panic");
+        let message = err.into_panic().downcast::<String>().unwrap();
+        assert!(message.contains("must not be called from async code"), "{message}");
+    }
+
+    #[test]
+    #[should_panic(expected = "must not be called from async code")]
+    fn test_blocking_in_tokio_block_on_panics() {
+        let (_client, fs) = make_blocking_filesystem("test_blocking_in_tokio_block_on_panics");
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        runtime.block_on(async {
+            let _ = fs.lookup_path("file.txt");
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "must not be called from async code")]
+    fn test_blocking_in_futures_executor_panics() {
+        let (_client, fs) = make_blocking_filesystem("test_blocking_in_futures_executor_panics");
+        futures::executor::block_on(async {
+            let _ = fs.lookup_path("file.txt");
+        });
+    }
+}
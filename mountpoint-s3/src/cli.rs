@@ -5,6 +5,7 @@ use std::num::NonZeroUsize;
 use std::os::fd::AsRawFd;
 use std::os::unix::prelude::FromRawFd;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::{anyhow, Context as _};
@@ -12,7 +13,7 @@ use clap::{value_parser, Parser, ValueEnum};
 use fuser::{MountOption, Session};
 use futures::task::Spawn;
 use mountpoint_s3_client::config::{AddressingStyle, EndpointConfig, S3ClientAuthConfig, S3ClientConfig};
-use mountpoint_s3_client::error::ObjectClientError;
+use mountpoint_s3_client::error::{ListObjectsError, ObjectClientError};
 use mountpoint_s3_client::instance_info::InstanceInfo;
 use mountpoint_s3_client::user_agent::UserAgent;
 use mountpoint_s3_client::{ObjectClient, S3CrtClient, S3RequestError};
@@ -26,13 +27,19 @@ use nix::unistd::ForkResult;
 use regex::Regex;
 
 use crate::build_info;
-use crate::data_cache::{CacheLimit, DiskDataCache, DiskDataCacheConfig, ManagedCacheDir};
+use crate::data_cache::{CacheLimit, DiskDataCache, DiskDataCacheConfig, ManagedCacheDir, SharedCacheDir};
 use crate::fs::ServerSideEncryption;
-use crate::fs::{CacheConfig, S3FilesystemConfig};
+use crate::fs::{CacheConfig, FuseNotifier, S3FilesystemConfig};
 use crate::fuse::session::FuseSession;
 use crate::fuse::S3FuseFilesystem;
-use crate::logging::{init_logging, LoggingConfig};
-use crate::prefetch::{caching_prefetch, default_prefetch, Prefetch};
+#[cfg(feature = "otel")]
+use crate::logging::OtelConfig;
+use crate::logging::{init_logging, shutdown_logging, LoggingConfig, SlowOpConfig};
+use crate::mem_limiter::MemoryLimiter;
+use crate::prefetch::{
+    caching_prefetch, default_prefetch, BufferPool, IdleBufferPolicy, Prefetch, PrefetcherConfig,
+    MAX_PREFERRED_PART_SIZE,
+};
 use crate::prefix::Prefix;
 use crate::s3::S3Personality;
 use crate::{autoconfigure, metrics};
@@ -45,6 +52,9 @@ const LOGGING_OPTIONS_HEADER: &str = "Logging options";
 const CACHING_OPTIONS_HEADER: &str = "Caching options";
 const ADVANCED_OPTIONS_HEADER: &str = "Advanced options";
 
+/// Data kept for an idle open file with `--idle-read-buffer-policy shrink`, if not configured
+const DEFAULT_IDLE_READ_BUFFER_FLOOR: u64 = 8 * 1024 * 1024;
+
 #[derive(Parser, Debug)]
 #[clap(name = "mount-s3", about = "Mountpoint for Amazon S3", version = build_info::FULL_VERSION)]
 pub struct CliArgs {
@@ -100,6 +110,17 @@ pub struct CliArgs {
     #[clap(long, help = "Use a specific profile from your credential file.", help_heading = AWS_CREDENTIALS_OPTIONS_HEADER)]
     pub profile: Option<String>,
 
+    #[clap(
+        long,
+        help = "Use a specific profile from your credential file for keys under a prefix. \
+                The prefix is relative to --prefix and must end in '/'. Can be repeated; the longest matching prefix is used.",
+        value_name = "PREFIX=PROFILE",
+        value_parser = parse_prefix_profile,
+        conflicts_with = "no_sign_request",
+        help_heading = AWS_CREDENTIALS_OPTIONS_HEADER
+    )]
+    pub prefix_profile: Vec<(Prefix, String)>,
+
     #[clap(
         long,
         help = "Mount file system in read-only mode",
@@ -117,6 +138,18 @@ pub struct CliArgs {
     )]
     pub allow_delete: bool,
 
+    #[clap(
+        long,
+        help = "Delete the objects of removed files in batches, up to this long after they're removed, \
+                rather than one at a time. Removing a file succeeds before its object is deleted, so objects \
+                that fail to delete, or that are still waiting if Mountpoint is killed, reappear",
+        value_name = "MILLISECONDS",
+        value_parser = value_parser!(u64).range(1..),
+        requires = "allow_delete",
+        help_heading = MOUNT_OPTIONS_HEADER
+    )]
+    pub unlink_batch_delay: Option<u64>,
+
     #[clap(
         long,
         help = "Allow overwrite operations on file system",
@@ -124,9 +157,26 @@ pub struct CliArgs {
     )]
     pub allow_overwrite: bool,
 
+    #[clap(
+        long,
+        help = "Allow files opened for writing without truncation to overwrite part of their contents, starting on a part boundary",
+        help_heading = MOUNT_OPTIONS_HEADER,
+        requires = "allow_overwrite"
+    )]
+    pub allow_partial_writes: bool,
+
     #[clap(long, help = "Automatically unmount on exit", help_heading = MOUNT_OPTIONS_HEADER)]
     pub auto_unmount: bool,
 
+    #[clap(
+        long,
+        help = "Unmount after no file system operations, and no open files or directories, for this many seconds [default: never]",
+        value_name = "SECONDS",
+        value_parser = value_parser!(u64).range(1..),
+        help_heading = MOUNT_OPTIONS_HEADER
+    )]
+    pub idle_timeout: Option<u64>,
+
     #[clap(long, help = "Allow root user to access file system", help_heading = MOUNT_OPTIONS_HEADER)]
     pub allow_root: bool,
 
@@ -166,6 +216,74 @@ pub struct CliArgs {
     )]
     pub part_size: u64,
 
+    #[clap(
+        long,
+        help = "Minimum size of each GET request, so that small reads fetch at least this many bytes [default: 0]",
+        value_name = "BYTES",
+        value_parser = value_parser!(u64).range(..usize::MAX as u64),
+        help_heading = CLIENT_OPTIONS_HEADER
+    )]
+    pub min_read_request_size: Option<u64>,
+
+    #[clap(
+        long,
+        help = "Fetch the skipped data rather than start a new GET request when a read lands fewer than this many bytes past the data already requested [default: 0]",
+        value_name = "BYTES",
+        help_heading = CLIENT_OPTIONS_HEADER
+    )]
+    pub read_coalesce_gap: Option<u64>,
+
+    #[clap(
+        long,
+        help = "Memory to set aside for reusable buffers that hold downloaded data until it's read [default: 0]",
+        value_name = "BYTES",
+        value_parser = value_parser!(u64).range(..usize::MAX as u64),
+        help_heading = CLIENT_OPTIONS_HEADER
+    )]
+    pub read_buffer_pool_size: Option<u64>,
+
+    #[clap(
+        long,
+        help = "Most memory to use for downloaded data and writes waiting to be uploaded [default: no limit]",
+        value_name = "BYTES",
+        help_heading = CLIENT_OPTIONS_HEADER
+    )]
+    pub max_memory_target: Option<u64>,
+
+    #[clap(
+        long,
+        help = "Fetch objects smaller than this whole on their first read, rather than prefetching them [default: 0]",
+        value_name = "BYTES",
+        help_heading = CLIENT_OPTIONS_HEADER
+    )]
+    pub prefetch_min_file_size: Option<u64>,
+
+    #[clap(
+        long,
+        help = "What to do with the data prefetched for an open file once it goes --idle-read-buffer-timeout without reads: \
+                keep it, shrink it to --idle-read-buffer-floor, or release it all [default: retain]",
+        value_name = "POLICY",
+        help_heading = CLIENT_OPTIONS_HEADER
+    )]
+    pub idle_read_buffer_policy: Option<IdleReadBufferPolicy>,
+
+    #[clap(
+        long,
+        help = "Time an open file goes without reads before --idle-read-buffer-policy applies [default: 10]",
+        value_name = "SECONDS",
+        help_heading = CLIENT_OPTIONS_HEADER
+    )]
+    pub idle_read_buffer_timeout: Option<u64>,
+
+    #[clap(
+        long,
+        help = "Prefetched data to keep for an idle open file with --idle-read-buffer-policy shrink [default: 8388608]",
+        value_name = "BYTES",
+        value_parser = value_parser!(u64).range(..usize::MAX as u64),
+        help_heading = CLIENT_OPTIONS_HEADER
+    )]
+    pub idle_read_buffer_floor: Option<u64>,
+
     #[clap(
         long,
         help = "Owner UID [default: current user's UID]",
@@ -232,10 +350,52 @@ pub struct CliArgs {
         long,
         help = "Disable all logging. You will still see stdout messages.",
         help_heading = LOGGING_OPTIONS_HEADER,
-        conflicts_with_all(["log_directory", "debug", "debug_crt", "log_metrics"])
+        conflicts_with_all(["log_directory", "debug", "debug_crt", "log_metrics", "slow_metadata_op_threshold", "slow_data_op_threshold"])
     )]
     pub no_log: bool,
 
+    #[clap(
+        long,
+        help = "Log a warning, with the S3 requests it made, for each metadata operation (like lookup or readdir) \
+                that takes longer than this [default: 1000 if --slow-data-op-threshold is set]",
+        help_heading = LOGGING_OPTIONS_HEADER,
+        value_name = "MILLISECONDS",
+        value_parser = value_parser!(u64).range(1..),
+    )]
+    pub slow_metadata_op_threshold: Option<u64>,
+
+    #[clap(
+        long,
+        help = "Log a warning, with the S3 requests it made, for each read or write that takes longer than this \
+                [default: 5000 if --slow-metadata-op-threshold is set]",
+        help_heading = LOGGING_OPTIONS_HEADER,
+        value_name = "MILLISECONDS",
+        value_parser = value_parser!(u64).range(1..),
+    )]
+    pub slow_data_op_threshold: Option<u64>,
+
+    #[cfg(feature = "otel")]
+    #[clap(
+        long,
+        help = "Export traces of file system operations, with the S3 requests they made, to the OTLP/HTTP receiver \
+                of an OpenTelemetry collector at this URL (like http://localhost:4318)",
+        help_heading = LOGGING_OPTIONS_HEADER,
+        value_name = "URL",
+        conflicts_with = "no_log"
+    )]
+    pub otlp_endpoint: Option<String>,
+
+    #[cfg(feature = "otel")]
+    #[clap(
+        long,
+        help = "Export traces of file system operations to an OpenTelemetry collector, configured by this TOML file. \
+                --otlp-endpoint overrides the endpoint it sets.",
+        help_heading = LOGGING_OPTIONS_HEADER,
+        value_name = "FILE",
+        conflicts_with = "no_log"
+    )]
+    pub otel_config: Option<PathBuf>,
+
     #[clap(
         long,
         help = "Enable caching of object metadata and content to the given directory",
@@ -264,6 +424,15 @@ pub struct CliArgs {
     )]
     pub max_cache_size: Option<u64>,
 
+    #[clap(
+        long,
+        help = "Share cached object content with other mounts using the same cache directory, rather than \
+            giving this mount its own. --max-cache-size applies to the content all of them cached.",
+        help_heading = CACHING_OPTIONS_HEADER,
+        requires = "cache",
+    )]
+    pub shared_cache: bool,
+
     #[clap(
         long,
         help = "Configure a string to be prepended to the 'User-Agent' HTTP request header for all S3 requests",
@@ -295,6 +464,13 @@ pub struct CliArgs {
         value_name = "ALGORITHM",
     )]
     pub upload_checksums: Option<UploadChecksums>,
+
+    #[clap(
+        long,
+        help = "Send a Content-MD5 header with uploads, for buckets that require it",
+        help_heading = BUCKET_OPTIONS_HEADER,
+    )]
+    pub require_content_md5: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -344,6 +520,27 @@ impl ValueEnum for UploadChecksums {
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+pub enum IdleReadBufferPolicy {
+    Retain,
+    Shrink,
+    Reset,
+}
+
+impl ValueEnum for IdleReadBufferPolicy {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Retain, Self::Shrink, Self::Reset]
+    }
+
+    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+        match self {
+            Self::Retain => Some(clap::builder::PossibleValue::new("retain")),
+            Self::Shrink => Some(clap::builder::PossibleValue::new("shrink")),
+            Self::Reset => Some(clap::builder::PossibleValue::new("reset")),
+        }
+    }
+}
+
 impl CliArgs {
     fn addressing_style(&self) -> AddressingStyle {
         if self.force_path_style {
@@ -357,7 +554,7 @@ impl CliArgs {
         self.prefix.as_ref().cloned().unwrap_or_default()
     }
 
-    fn logging_config(&self) -> LoggingConfig {
+    fn logging_config(&self) -> anyhow::Result<LoggingConfig> {
         let default_filter = if self.no_log {
             String::from("off")
         } else {
@@ -374,11 +571,61 @@ impl CliArgs {
             filter
         };
 
-        LoggingConfig {
+        let slow_ops =
+            (self.slow_metadata_op_threshold.is_some() || self.slow_data_op_threshold.is_some()).then(|| {
+                let mut slow_ops = SlowOpConfig::default();
+                if let Some(threshold) = self.slow_metadata_op_threshold {
+                    slow_ops.metadata_threshold = Duration::from_millis(threshold);
+                }
+                if let Some(threshold) = self.slow_data_op_threshold {
+                    slow_ops.data_threshold = Duration::from_millis(threshold);
+                }
+                slow_ops
+            });
+
+        Ok(LoggingConfig {
             log_directory: self.log_directory.clone(),
             log_to_stdout: self.foreground,
             default_filter,
+            slow_ops,
+            #[cfg(feature = "otel")]
+            otel: self.otel_config()?,
+        })
+    }
+
+    /// The configuration for exporting traces, from the file given with `--otel-config` and the
+    /// endpoint given with `--otlp-endpoint`, or `None` if neither was given
+    #[cfg(feature = "otel")]
+    fn otel_config(&self) -> anyhow::Result<Option<OtelConfig>> {
+        let mut config = match &self.otel_config {
+            Some(path) => {
+                let contents = std::fs::read_to_string(path)
+                    .with_context(|| format!("couldn't read OpenTelemetry config file {}", path.display()))?;
+                OtelConfig::from_toml_str(&contents)?
+            }
+            None if self.otlp_endpoint.is_some() => OtelConfig::default(),
+            None => return Ok(None),
+        };
+        if let Some(endpoint) = &self.otlp_endpoint {
+            config.endpoint = endpoint.clone();
         }
+        Ok(Some(config))
+    }
+
+    /// The budget for memory used by file data, shared by everything that holds on to it
+    fn memory_limiter(&self) -> MemoryLimiter {
+        self.max_memory_target.map(MemoryLimiter::new).unwrap_or_default()
+    }
+
+    /// The pool of buffers for the client to copy downloaded data into, if one was asked for. Its
+    /// buffers are charged to `memory_limiter`.
+    pub fn read_buffer_pool(&self, memory_limiter: &Arc<MemoryLimiter>) -> Option<BufferPool> {
+        let pool_size = self.read_buffer_pool_size.filter(|&size| size > 0)?;
+        Some(BufferPool::with_memory_limit(
+            MAX_PREFERRED_PART_SIZE,
+            pool_size as usize,
+            memory_limiter.clone(),
+        ))
     }
 
     /// Human-readable description of the bucket being mounted
@@ -412,17 +659,19 @@ impl CliArgs {
 
         let mount_point = self.mount_point.to_owned();
         let max_threads = self.max_threads as usize;
+        let idle_timeout = self.idle_timeout.map(Duration::from_secs);
         FuseSessionConfig {
             mount_point,
             options,
             max_threads,
+            idle_timeout,
         }
     }
 }
 
 pub fn main<ClientBuilder, Client, Runtime>(client_builder: ClientBuilder) -> anyhow::Result<()>
 where
-    ClientBuilder: FnOnce(&CliArgs) -> anyhow::Result<(Client, Runtime, S3Personality)>,
+    ClientBuilder: FnOnce(&CliArgs, &Arc<MemoryLimiter>) -> anyhow::Result<(Client, Runtime, S3Personality)>,
     Client: ObjectClient + Send + Sync + 'static,
     Runtime: Spawn + Send + Sync + 'static,
 {
@@ -434,7 +683,7 @@ where
     );
 
     if args.foreground {
-        init_logging(args.logging_config()).context("failed to initialize logging")?;
+        init_logging(args.logging_config()?).context("failed to initialize logging")?;
 
         let _metrics = metrics::install();
 
@@ -444,6 +693,7 @@ where
         println!("{successful_mount_msg}");
 
         session.join().context("failed to join session")?;
+        shutdown_logging();
     } else {
         // mount file system as a background process
 
@@ -461,7 +711,7 @@ where
         match pid.expect("Failed to fork mount process") {
             ForkResult::Child => {
                 let args = CliArgs::parse();
-                init_logging(args.logging_config()).context("failed to initialize logging")?;
+                init_logging(args.logging_config()?).context("failed to initialize logging")?;
 
                 let _metrics = metrics::install();
 
@@ -490,6 +740,7 @@ where
                         nix::unistd::close(std::io::stderr().as_raw_fd()).context("couldn't close stderr")?;
 
                         session.join().context("failed to join session")?;
+                        shutdown_logging();
                     }
                     Err(e) => {
                         pipe_file
@@ -502,7 +753,7 @@ where
             ForkResult::Parent { child } => {
                 let args = CliArgs::parse();
 
-                init_logging(args.logging_config()).context("failed to initialize logging")?;
+                init_logging(args.logging_config()?).context("failed to initialize logging")?;
                 // close unused file descriptor, we only read from this end.
                 nix::unistd::close(write_fd).context("Failed to close unused file descriptor")?;
 
@@ -556,7 +807,10 @@ where
 }
 
 /// Create a real S3 client
-pub fn create_s3_client(args: &CliArgs) -> anyhow::Result<(S3CrtClient, EventLoopGroup, S3Personality)> {
+pub fn create_s3_client(
+    args: &CliArgs,
+    memory_limiter: &Arc<MemoryLimiter>,
+) -> anyhow::Result<(S3CrtClient, EventLoopGroup, S3Personality)> {
     const DEFAULT_TARGET_THROUGHPUT: f64 = 10.0;
 
     // Placeholder region will be filled in by [create_client_for_bucket]
@@ -610,16 +864,26 @@ pub fn create_s3_client(args: &CliArgs) -> anyhow::Result<(S3CrtClient, EventLoo
         .throughput_target_gbps(throughput_target_gbps)
         .part_size(args.part_size as usize)
         .user_agent(user_agent);
+    for (prefix, profile_name) in &args.prefix_profile {
+        let prefix = format!("{}{}", args.prefix(), prefix);
+        client_config = client_config.prefix_auth_config(&prefix, S3ClientAuthConfig::Profile(profile_name.to_owned()));
+    }
     if args.requester_pays {
         client_config = client_config.request_payer("requester");
     }
     if let Some(owner) = &args.expected_bucket_owner {
         client_config = client_config.bucket_owner(owner);
     }
+    if args.require_content_md5 {
+        client_config = client_config.compute_content_md5(true);
+    }
     // Transient errors are really bad for file systems (applications don't usually expect them), so
     // let's be more stubborn than the SDK default. With the CRT defaults of 500ms backoff, full
     // jitter, and 20s max backoff time, 10 attempts will take an average of 55 seconds.
     client_config = client_config.max_attempts(NonZeroUsize::new(10).unwrap());
+    if let Some(buffer_pool) = args.read_buffer_pool(memory_limiter) {
+        client_config = client_config.body_buffer_pool(Arc::new(buffer_pool));
+    }
 
     let client = create_client_for_bucket(
         &args.bucket_name,
@@ -639,7 +903,7 @@ pub fn create_s3_client(args: &CliArgs) -> anyhow::Result<(S3CrtClient, EventLoo
 
 fn mount<ClientBuilder, Client, Runtime>(args: CliArgs, client_builder: ClientBuilder) -> anyhow::Result<FuseSession>
 where
-    ClientBuilder: FnOnce(&CliArgs) -> anyhow::Result<(Client, Runtime, S3Personality)>,
+    ClientBuilder: FnOnce(&CliArgs, &Arc<MemoryLimiter>) -> anyhow::Result<(Client, Runtime, S3Personality)>,
     Client: ObjectClient + Send + Sync + 'static,
     Runtime: Spawn + Send + Sync + 'static,
 {
@@ -651,7 +915,8 @@ where
         validate_sse_args(args.sse.as_deref(), args.sse_kms_key_id.as_deref())?;
     }
 
-    let (client, runtime, s3_personality) = client_builder(&args)?;
+    let memory_limiter = Arc::new(args.memory_limiter());
+    let (client, runtime, s3_personality) = client_builder(&args, &memory_limiter)?;
 
     let bucket_description = args.bucket_description();
     tracing::debug!("using S3 personality {s3_personality:?} for {bucket_description}");
@@ -673,9 +938,12 @@ where
     }
     filesystem_config.storage_class = args.storage_class;
     filesystem_config.allow_delete = args.allow_delete;
+    filesystem_config.unlink_batch_delay = args.unlink_batch_delay.map(Duration::from_millis);
     filesystem_config.allow_overwrite = args.allow_overwrite;
+    filesystem_config.allow_partial_writes = args.allow_partial_writes;
     filesystem_config.s3_personality = s3_personality;
     filesystem_config.server_side_encryption = ServerSideEncryption::new(args.sse, args.sse_kms_key_id);
+    filesystem_config.memory_limiter = memory_limiter.clone();
 
     // Written in this awkward way to force us to update it if we add new checksum types
     filesystem_config.use_upload_checksums = match args.upload_checksums {
@@ -686,8 +954,28 @@ where
         tracing::info!("disabling upload checksums because target S3 personality does not support them");
         filesystem_config.use_upload_checksums = false;
     }
+    filesystem_config.require_content_md5 = args.require_content_md5;
 
-    let prefetcher_config = Default::default();
+    let mut prefetcher_config = PrefetcherConfig::default();
+    if let Some(min_read_request_size) = args.min_read_request_size {
+        prefetcher_config.min_read_request_size = min_read_request_size as usize;
+    }
+    if let Some(read_coalesce_gap) = args.read_coalesce_gap {
+        prefetcher_config.read_coalesce_gap = read_coalesce_gap;
+    }
+    if let Some(prefetch_min_file_size) = args.prefetch_min_file_size {
+        prefetcher_config.prefetch_min_file_size = prefetch_min_file_size;
+    }
+    filesystem_config.idle_read_buffer_policy = match args.idle_read_buffer_policy {
+        Some(IdleReadBufferPolicy::Retain) | None => IdleBufferPolicy::Retain,
+        Some(IdleReadBufferPolicy::Shrink) => IdleBufferPolicy::Shrink {
+            floor: args.idle_read_buffer_floor.unwrap_or(DEFAULT_IDLE_READ_BUFFER_FLOOR) as usize,
+        },
+        Some(IdleReadBufferPolicy::Reset) => IdleBufferPolicy::Reset,
+    };
+    if let Some(idle_read_buffer_timeout) = args.idle_read_buffer_timeout {
+        filesystem_config.idle_read_buffer_timeout = Duration::from_secs(idle_read_buffer_timeout);
+    }
 
     if let Some(path) = args.cache {
         let metadata_cache_ttl = args.metadata_ttl.unwrap_or(Duration::from_secs(1));
@@ -711,9 +999,22 @@ where
         };
 
         if let Some(cache_config) = cache_config {
-            let managed_cache_dir =
-                ManagedCacheDir::new_from_parent(path).context("failed to create cache directory")?;
-            let cache = DiskDataCache::new(managed_cache_dir.as_path_buf(), cache_config);
+            // Kept until the file system is unmounted, when it cleans up the cache directory
+            let (cache, cache_dir): (DiskDataCache, Box<dyn Send>) = if args.shared_cache {
+                let shared_cache_dir =
+                    SharedCacheDir::new_from_parent(path).context("failed to open shared cache directory")?;
+                (
+                    DiskDataCache::new_shared(&shared_cache_dir, &args.bucket_name, cache_config),
+                    Box::new(shared_cache_dir),
+                )
+            } else {
+                let managed_cache_dir =
+                    ManagedCacheDir::new_from_parent(path).context("failed to create cache directory")?;
+                (
+                    DiskDataCache::new(managed_cache_dir.as_path_buf(), cache_config),
+                    Box::new(managed_cache_dir),
+                )
+            };
             let prefetcher = caching_prefetch(cache, runtime, prefetcher_config);
             let mut fuse_session = create_filesystem(
                 client,
@@ -726,7 +1027,7 @@ where
             )?;
 
             fuse_session.run_on_close(Box::new(move || {
-                drop(managed_cache_dir);
+                drop(cache_dir);
             }));
 
             return Ok(fuse_session);
@@ -759,9 +1060,17 @@ where
     Prefetcher: Prefetch + Send + Sync + 'static,
 {
     let fs = S3FuseFilesystem::new(client, prefetcher, bucket_name, prefix, filesystem_config);
+    let notifier_slot = fs.notifier_slot();
+    let open_handles = fs.open_handles();
     let session = Session::new(fs, &fuse_session_config.mount_point, &fuse_session_config.options)
         .context("Failed to create FUSE session")?;
-    let session = FuseSession::new(session, fuse_session_config.max_threads).context("Failed to start FUSE session")?;
+    notifier_slot.set(FuseNotifier::new(session.notifier()));
+    let mut session =
+        FuseSession::new(session, fuse_session_config.max_threads).context("Failed to start FUSE session")?;
+    if let Some(idle_timeout) = fuse_session_config.idle_timeout {
+        session.track_open_handles(open_handles);
+        session.unmount_on_idle(idle_timeout)?;
+    }
 
     tracing::info!(
         "successfully mounted {} at {}",
@@ -778,6 +1087,8 @@ struct FuseSessionConfig {
     pub mount_point: PathBuf,
     pub options: Vec<MountOption>,
     pub max_threads: usize,
+    /// Unmount after this long with no file system operations
+    pub idle_timeout: Option<Duration>,
 }
 
 /// Create a client for a bucket in the given region and send a ListObjectsV2 request to validate
@@ -822,10 +1133,47 @@ fn create_client_for_bucket(
             let list_request = new_client.list_objects(bucket, None, "", 0, prefix.as_str());
             futures::executor::block_on(list_request)
                 .map(|_| new_client)
-                .with_context(|| format!("initial ListObjectsV2 failed for bucket {bucket} in region {region}"))
+                .map_err(|e| bucket_probe_error(bucket, &region, e))
         }
-        Err(e) => Err(e)
-            .with_context(|| format!("initial ListObjectsV2 failed for bucket {bucket} in region {region_to_try}")),
+        Err(e) => Err(bucket_probe_error(bucket, &region_to_try, e)),
+    }
+}
+
+/// Why the bucket couldn't be mounted, when the initial ListObjectsV2 request to it fails in a way
+/// the user can fix
+#[derive(Debug, thiserror::Error)]
+pub enum BucketProbeError {
+    #[error("bucket {bucket:?} does not exist in region {region}")]
+    NoSuchBucket { bucket: String, region: String },
+    #[error("access denied to bucket {bucket:?} in region {region}: {message}. Check that your credentials allow s3:ListBucket on the bucket")]
+    AccessDenied {
+        bucket: String,
+        region: String,
+        message: String,
+    },
+}
+
+/// Turn a failed initial ListObjectsV2 request into an error for the user, with a
+/// [BucketProbeError] for the failures that have an obvious cause
+fn bucket_probe_error(
+    bucket: &str,
+    region: &str,
+    error: ObjectClientError<ListObjectsError, S3RequestError>,
+) -> anyhow::Error {
+    let (bucket, region) = (bucket.to_owned(), region.to_owned());
+    match error {
+        ObjectClientError::ServiceError(ListObjectsError::NoSuchBucket) => {
+            BucketProbeError::NoSuchBucket { bucket, region }.into()
+        }
+        ObjectClientError::ClientError(S3RequestError::Forbidden(message)) => BucketProbeError::AccessDenied {
+            bucket,
+            region,
+            message,
+        }
+        .into(),
+        e => anyhow::Error::new(e).context(format!(
+            "initial ListObjectsV2 failed for bucket {bucket} in region {region}"
+        )),
     }
 }
 
@@ -862,6 +1210,22 @@ fn parse_bucket_name(bucket_name: &str) -> anyhow::Result<String> {
     Ok(bucket_name.to_owned())
 }
 
+/// Parse a `PREFIX=PROFILE` pair for `--prefix-profile`
+fn parse_prefix_profile(prefix_profile: &str) -> anyhow::Result<(Prefix, String)> {
+    let (prefix, profile) = prefix_profile
+        .split_once('=')
+        .ok_or_else(|| anyhow!("must be of the form PREFIX=PROFILE"))?;
+    if prefix.is_empty() {
+        return Err(anyhow!(
+            "prefix must not be empty (use --profile to set the profile for all keys)"
+        ));
+    }
+    if profile.is_empty() {
+        return Err(anyhow!("profile must not be empty"));
+    }
+    Ok((Prefix::new(prefix)?, profile.to_owned()))
+}
+
 fn parse_ttl_seconds(seconds_str: &str) -> anyhow::Result<Duration> {
     const MAXIMUM_TTL_YEARS: u64 = 100;
     const MAXIMUM_TTL_SECONDS: u64 = MAXIMUM_TTL_YEARS * 365 * 24 * 60 * 60;
@@ -1008,4 +1372,24 @@ mod tests {
             parsed.expect_err("invalid bucket name");
         }
     }
+
+    #[test_case("logs/=logs-reader", Some(("logs/", "logs-reader")); "simple")]
+    #[test_case("a/b/=profile=x", Some(("a/b/", "profile=x")); "equals in profile")]
+    #[test_case("logs=logs-reader", None; "prefix not ending in slash")]
+    #[test_case("=logs-reader", None; "empty prefix")]
+    #[test_case("logs/=", None; "empty profile")]
+    #[test_case("logs/", None; "no profile")]
+    fn validate_prefix_profile(arg: &str, expected: Option<(&str, &str)>) {
+        let parsed = parse_prefix_profile(arg);
+        match expected {
+            Some((prefix, profile)) => {
+                let (parsed_prefix, parsed_profile) = parsed.expect("valid prefix profile");
+                assert_eq!(parsed_prefix.as_str(), prefix);
+                assert_eq!(parsed_profile, profile);
+            }
+            None => {
+                parsed.expect_err("invalid prefix profile");
+            }
+        }
+    }
 }
@@ -0,0 +1,49 @@
+//! Sources of the current time, so that tests can control how much of it passes.
+
+use std::fmt::Debug;
+use std::time::Instant;
+
+/// A source of the current time, so that tests can control it
+pub trait Clock: Debug + Send + Sync + 'static {
+    fn now(&self) -> Instant;
+}
+
+/// The real clock
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when told to
+#[cfg(any(test, feature = "test-utils"))]
+#[derive(Debug)]
+pub struct MockClock(std::sync::Mutex<Instant>);
+
+#[cfg(any(test, feature = "test-utils"))]
+impl MockClock {
+    pub fn new() -> Self {
+        Self(std::sync::Mutex::new(Instant::now()))
+    }
+
+    pub fn advance(&self, duration: std::time::Duration) {
+        *self.0.lock().unwrap() += duration;
+    }
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.0.lock().unwrap()
+    }
+}
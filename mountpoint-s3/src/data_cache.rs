@@ -11,7 +11,7 @@ mod in_memory_data_cache;
 use thiserror::Error;
 
 pub use crate::checksums::ChecksummedBytes;
-pub use crate::data_cache::cache_directory::ManagedCacheDir;
+pub use crate::data_cache::cache_directory::{ManagedCacheDir, SharedCacheDir, SharedCacheUsage};
 pub use crate::data_cache::disk_data_cache::{CacheLimit, DiskDataCache, DiskDataCacheConfig};
 pub use crate::data_cache::in_memory_data_cache::InMemoryDataCache;
 
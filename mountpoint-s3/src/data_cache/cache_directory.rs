@@ -5,14 +5,24 @@
 //! to mitigate any impact from the user providing a directory that already contains data.
 //! Using a new sub-directory minimizes the interference with the existing directory structure,
 //! and limits the risk from deleting or overwriting data to files written within this sub-directory.
+//!
+//! Several mounts can instead share a [SharedCacheDir], which is only cleaned up by the first mount
+//! to start using it and the last to stop.
 
 use std::fs;
 use std::io;
-use std::os::unix::fs::DirBuilderExt;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{DirBuilderExt, FileExt, OpenOptionsExt};
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
+use nix::errno::Errno;
+use nix::fcntl::{flock, FlockArg};
+use sha2::{Digest, Sha256};
 use thiserror::Error;
 
+use crate::sync::{Arc, Mutex};
+
 /// Cache directory that has been created and emptied, and will be emptied when dropped.
 #[derive(Debug)]
 pub struct ManagedCacheDir {
@@ -25,6 +35,8 @@ pub enum ManagedCacheDirError {
     CreationFailure(#[source] io::Error),
     #[error("cleanup of cache sub-directory failed due to IO error: {0}")]
     CleanupFailure(#[source] io::Error),
+    #[error("locking of shared cache sub-directory failed due to IO error: {0}")]
+    LockFailure(#[source] io::Error),
 }
 
 impl ManagedCacheDir {
@@ -35,7 +47,7 @@ impl ManagedCacheDir {
             managed_path: parent_path.as_ref().join("mountpoint-cache"),
         };
 
-        managed_cache_dir.remove()?;
+        remove_cache_dir(managed_cache_dir.as_path())?;
 
         let mkdir_result = fs::DirBuilder::new().mode(0o700).create(managed_cache_dir.as_path());
         if let Err(mkdir_err) = mkdir_result {
@@ -51,19 +63,6 @@ impl ManagedCacheDir {
         Ok(managed_cache_dir)
     }
 
-    /// Remove the cache sub-directory, along with its contents if any
-    fn remove(&self) -> Result<(), ManagedCacheDirError> {
-        tracing::debug!(cache_subdirectory = ?self.managed_path, "removing the cache sub-directory and any contents");
-        if let Err(remove_dir_err) = fs::remove_dir_all(&self.managed_path) {
-            match remove_dir_err.kind() {
-                io::ErrorKind::NotFound => (),
-                _kind => return Err(ManagedCacheDirError::CleanupFailure(remove_dir_err)),
-            }
-        }
-        tracing::trace!(cache_subdirectory = ?self.managed_path, "cache sub-directory removal complete");
-        Ok(())
-    }
-
     /// Retrieve a reference to the managed path
     pub fn as_path(&self) -> &Path {
         self.managed_path.as_path()
@@ -77,18 +76,314 @@ impl ManagedCacheDir {
 
 impl Drop for ManagedCacheDir {
     fn drop(&mut self) {
-        if let Err(err) = self.remove() {
+        if let Err(err) = remove_cache_dir(&self.managed_path) {
             tracing::error!(cache_subdirectory = ?self.managed_path, "failed to remove cache sub-directory: {err}");
         }
     }
 }
 
+/// Cache directory shared by every mount using the same parent directory, so that mounts of
+/// overlapping data only download each block once. Blocks are kept in a separate directory for
+/// each bucket and block size, and identified by the object's key and ETag within it.
+///
+/// Each user holds a shared lock on a lockfile next to the directory, which is never removed. The
+/// first user to take the lock empties the directory, since blocks left behind by earlier mounts
+/// aren't tracked for eviction, and the last user to release it removes the directory. The total
+/// size of the blocks in the directory is kept in another file next to it, see [SharedCacheUsage].
+#[derive(Debug)]
+pub struct SharedCacheDir {
+    shared_path: PathBuf,
+    /// Holds our lock for as long as the directory is in use
+    lock_file: fs::File,
+    usage: SharedCacheUsage,
+}
+
+/// Whether a lock on the lockfile is shared with other users or held by one user alone
+#[derive(Debug, Clone, Copy)]
+enum LockKind {
+    Shared,
+    Exclusive,
+}
+
+impl SharedCacheDir {
+    /// Start using the shared directory inside the provided parent path, creating it if this is
+    /// the only user. Blocks until any user that's removing the directory has finished.
+    pub fn new_from_parent<P: AsRef<Path>>(parent_path: P) -> Result<Self, ManagedCacheDirError> {
+        let parent_path = parent_path.as_ref();
+        let lock_file = open_lock_file(&parent_path.join("mountpoint-cache-shared.lock"))?;
+        let size_file = open_lock_file(&parent_path.join("mountpoint-cache-shared.size"))?;
+        let shared_path = parent_path.join("mountpoint-cache-shared");
+        let shared_cache_dir = Self {
+            usage: SharedCacheUsage(Arc::new(SharedCacheUsageInner {
+                shared_path: shared_path.clone(),
+                size_file: Mutex::new(size_file),
+            })),
+            shared_path,
+            lock_file,
+        };
+
+        // Only the first user can take the lock exclusively. Everyone else waits for it to be
+        // shared, which also waits for the last user to finish removing the directory.
+        let first_user = match shared_cache_dir.lock(LockKind::Exclusive, false) {
+            Ok(()) => true,
+            Err(Errno::EAGAIN | Errno::EACCES) => {
+                shared_cache_dir
+                    .lock(LockKind::Shared, true)
+                    .map_err(|errno| ManagedCacheDirError::LockFailure(errno.into()))?;
+                false
+            }
+            Err(errno) => return Err(ManagedCacheDirError::LockFailure(errno.into())),
+        };
+        if first_user {
+            remove_cache_dir(shared_cache_dir.as_path())?;
+            shared_cache_dir
+                .usage
+                .update(|size| {
+                    *size = 0;
+                    Ok(())
+                })
+                .map_err(ManagedCacheDirError::CleanupFailure)?;
+        }
+
+        let mkdir_result = fs::DirBuilder::new().mode(0o700).create(shared_cache_dir.as_path());
+        if let Err(mkdir_err) = mkdir_result {
+            if mkdir_err.kind() != io::ErrorKind::AlreadyExists {
+                return Err(ManagedCacheDirError::CreationFailure(mkdir_err));
+            }
+        }
+
+        if first_user {
+            shared_cache_dir
+                .lock(LockKind::Shared, true)
+                .map_err(|errno| ManagedCacheDirError::LockFailure(errno.into()))?;
+        }
+        tracing::debug!(cache_dir = ?shared_cache_dir.as_path(), first_user, "using shared cache directory");
+        Ok(shared_cache_dir)
+    }
+
+    /// Replace our lock on the lockfile with one of the given kind, waiting for it if `wait`.
+    ///
+    /// These are open file description locks, which are held by this open of the lockfile like
+    /// flock(2) locks are, so users in one process exclude each other too. Unlike flock(2) locks,
+    /// they're converted between shared and exclusive atomically, so the first user never gives up
+    /// its lock between emptying the directory and sharing it, and the last user keeps its shared
+    /// lock if another user has the directory open.
+    #[cfg(target_os = "linux")]
+    fn lock(&self, kind: LockKind, wait: bool) -> nix::Result<()> {
+        use nix::fcntl::{fcntl, FcntlArg};
+
+        // SAFETY: `flock` is a plain C struct, for which all zeroes is a valid value. Zero start
+        // and length lock the whole file, and open file description locks require a zero pid.
+        let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+        let lock_type = match kind {
+            LockKind::Shared => libc::F_RDLCK,
+            LockKind::Exclusive => libc::F_WRLCK,
+        };
+        lock.l_type = lock_type as _;
+        lock.l_whence = libc::SEEK_SET as _;
+        let arg = if wait {
+            FcntlArg::F_OFD_SETLKW(&lock)
+        } else {
+            FcntlArg::F_OFD_SETLK(&lock)
+        };
+        fcntl(self.lock_file.as_raw_fd(), arg).map(drop)
+    }
+
+    /// Replace our lock on the lockfile with one of the given kind, waiting for it if `wait`.
+    ///
+    /// Without open file description locks, this falls back to flock(2), which can briefly drop our
+    /// lock while converting it.
+    #[cfg(not(target_os = "linux"))]
+    fn lock(&self, kind: LockKind, wait: bool) -> nix::Result<()> {
+        let arg = match (kind, wait) {
+            (LockKind::Shared, true) => FlockArg::LockShared,
+            (LockKind::Shared, false) => FlockArg::LockSharedNonblock,
+            (LockKind::Exclusive, true) => FlockArg::LockExclusive,
+            (LockKind::Exclusive, false) => FlockArg::LockExclusiveNonblock,
+        };
+        flock(self.lock_file.as_raw_fd(), arg)
+    }
+
+    /// Retrieve a reference to the shared path
+    pub fn as_path(&self) -> &Path {
+        self.shared_path.as_path()
+    }
+
+    /// The directory to cache blocks of the given size of objects in the given bucket in. Mounts
+    /// with different block sizes keep their blocks apart, since blocks are identified by their
+    /// index. Bucket names are hashed, since access point ARNs can contain `/`.
+    pub fn cache_path(&self, bucket: &str, block_size: u64) -> PathBuf {
+        let hashed_bucket = hex::encode(Sha256::digest(bucket.as_bytes()));
+        self.shared_path.join(hashed_bucket).join(block_size.to_string())
+    }
+
+    /// The total size of the blocks in the directory, which every user keeps up to date
+    pub fn usage(&self) -> SharedCacheUsage {
+        self.usage.clone()
+    }
+}
+
+impl Drop for SharedCacheDir {
+    fn drop(&mut self) {
+        // Only the last user can take the lock exclusively. Our lock is released when the lockfile
+        // is closed, whether or not this succeeds.
+        if self.lock(LockKind::Exclusive, false).is_err() {
+            return;
+        }
+        if let Err(err) = remove_cache_dir(&self.shared_path) {
+            tracing::error!(cache_subdirectory = ?self.shared_path, "failed to remove shared cache sub-directory: {err}");
+        }
+    }
+}
+
+/// The total size of the blocks in a [SharedCacheDir], counted by every user of the directory, so
+/// that a size limit can apply to the directory as a whole. It's stored in a file next to the
+/// directory, which users lock exclusively while they add or remove blocks.
+#[derive(Debug, Clone)]
+pub struct SharedCacheUsage(Arc<SharedCacheUsageInner>);
+
+#[derive(Debug)]
+struct SharedCacheUsageInner {
+    shared_path: PathBuf,
+    /// Threads of one process share the file's lock, so they also take turns with this mutex
+    size_file: Mutex<fs::File>,
+}
+
+impl SharedCacheUsage {
+    /// The total size of the blocks in the directory
+    pub fn size(&self) -> io::Result<u64> {
+        self.update(|size| Ok(*size))
+    }
+
+    /// Run `f` with the total size while no other user can change it. `f` updates the size to
+    /// match any blocks it adds or removes.
+    pub fn update<T>(&self, f: impl FnOnce(&mut u64) -> io::Result<T>) -> io::Result<T> {
+        let size_file = self.0.size_file.lock().unwrap();
+        let fd = size_file.as_raw_fd();
+        flock(fd, FlockArg::LockExclusive)?;
+        let result = Self::update_locked(&size_file, f);
+        flock(fd, FlockArg::Unlock)?;
+        result
+    }
+
+    fn update_locked<T>(size_file: &fs::File, f: impl FnOnce(&mut u64) -> io::Result<T>) -> io::Result<T> {
+        // A new file is empty, which counts as zero
+        let mut bytes = [0u8; 8];
+        let read = size_file.read_at(&mut bytes, 0)?;
+        let mut size = if read == bytes.len() {
+            u64::from_le_bytes(bytes)
+        } else {
+            0
+        };
+        let result = f(&mut size);
+        size_file.write_all_at(&size.to_le_bytes(), 0)?;
+        result
+    }
+
+    /// Remove the blocks in the directory that were written longest ago, whoever wrote them, while
+    /// `is_exceeded` is true of the total size. Returns how many blocks were removed. This finds
+    /// blocks no user is tracking, like those of mounts that have exited.
+    pub fn evict_oldest(&self, is_exceeded: impl Fn(u64) -> bool) -> io::Result<usize> {
+        let mut blocks = Vec::new();
+        find_blocks(&self.0.shared_path, &mut blocks)?;
+        blocks.sort_unstable();
+
+        let mut removed = 0;
+        for (_modified, path) in blocks {
+            if !is_exceeded(self.size()?) {
+                break;
+            }
+            if self.remove_block(&path)? {
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Remove a block file and take its size off the total. Returns whether the file was there, since
+    /// another user may have removed it already.
+    pub fn remove_block(&self, path: &Path) -> io::Result<bool> {
+        self.update(|size| {
+            let len = match fs::metadata(path) {
+                Ok(metadata) => metadata.len(),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+                Err(err) => return Err(err),
+            };
+            fs::remove_file(path)?;
+            *size = size.saturating_sub(len);
+            Ok(true)
+        })
+    }
+
+    /// Rename a newly written block file into place, replacing any block already there, and
+    /// update the total to match.
+    pub fn rename_block(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.update(|size| {
+            let len = fs::metadata(from)?.len();
+            let replaced_len = match fs::metadata(to) {
+                Ok(metadata) => metadata.len(),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => 0,
+                Err(err) => return Err(err),
+            };
+            fs::rename(from, to)?;
+            *size = size.saturating_sub(replaced_len).saturating_add(len);
+            Ok(())
+        })
+    }
+}
+
+/// Add the block files under `path` to `blocks`, with when they were last modified. Temporary
+/// files that blocks are written to are left out, since they aren't counted yet.
+fn find_blocks(path: &Path, blocks: &mut Vec<(SystemTime, PathBuf)>) -> io::Result<()> {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            find_blocks(&entry.path(), blocks)?;
+        } else if file_type.is_file() && !entry.file_name().to_string_lossy().ends_with(".tmp") {
+            let modified = entry.metadata()?.modified()?;
+            blocks.push((modified, entry.path()));
+        }
+    }
+    Ok(())
+}
+
+fn open_lock_file(path: &Path) -> Result<fs::File, ManagedCacheDirError> {
+    fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(0o600)
+        .open(path)
+        .map_err(ManagedCacheDirError::LockFailure)
+}
+
+/// Remove a cache sub-directory, along with its contents if any
+fn remove_cache_dir(path: &Path) -> Result<(), ManagedCacheDirError> {
+    tracing::debug!(cache_subdirectory = ?path, "removing the cache sub-directory and any contents");
+    if let Err(remove_dir_err) = fs::remove_dir_all(path) {
+        match remove_dir_err.kind() {
+            io::ErrorKind::NotFound => (),
+            _kind => return Err(ManagedCacheDirError::CleanupFailure(remove_dir_err)),
+        }
+    }
+    tracing::trace!(cache_subdirectory = ?path, "cache sub-directory removal complete");
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
-    use super::ManagedCacheDir;
+    use super::{ManagedCacheDir, SharedCacheDir};
 
     use std::fs;
     use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    use std::time::{Duration, SystemTime};
 
     const EXPECTED_DIR_MODE: u32 = 0o700;
 
@@ -186,4 +481,95 @@ mod tests {
 
         temp_dir.close().unwrap();
     }
+
+    #[test]
+    fn test_shared() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let expected_path = temp_dir.path().join("mountpoint-cache-shared");
+
+        // Left behind by an earlier user that didn't clean up
+        fs::create_dir(&expected_path).unwrap();
+        fs::File::create(expected_path.join("stale.txt")).unwrap();
+
+        let first = SharedCacheDir::new_from_parent(temp_dir.path()).expect("first user should succeed");
+        assert_eq!(first.as_path(), expected_path);
+        assert!(
+            !expected_path.join("stale.txt").try_exists().unwrap(),
+            "first user should empty the directory"
+        );
+        fs::File::create(expected_path.join("file.txt")).unwrap();
+
+        let second = SharedCacheDir::new_from_parent(temp_dir.path()).expect("second user should succeed");
+        assert_eq!(first.cache_path("bucket", 1024), second.cache_path("bucket", 1024));
+        assert_ne!(first.cache_path("bucket", 1024), first.cache_path("other-bucket", 1024));
+        assert_ne!(first.cache_path("bucket", 1024), first.cache_path("bucket", 2048));
+        assert!(
+            expected_path.join("file.txt").try_exists().unwrap(),
+            "second user should keep the contents"
+        );
+
+        drop(first);
+        assert!(
+            expected_path.join("file.txt").try_exists().unwrap(),
+            "contents should be kept while there's another user"
+        );
+
+        drop(second);
+        assert!(
+            !expected_path.try_exists().unwrap(),
+            "{expected_path:?} should be removed by the last user"
+        );
+
+        temp_dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_shared_usage() {
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        let first = SharedCacheDir::new_from_parent(temp_dir.path()).expect("first user should succeed");
+        let second = SharedCacheDir::new_from_parent(temp_dir.path()).expect("second user should succeed");
+        let (first_usage, second_usage) = (first.usage(), second.usage());
+        assert_eq!(first_usage.size().unwrap(), 0);
+
+        // Each user sees the blocks the other adds and removes
+        let block_path = first.as_path().join("block");
+        fs::write(first.as_path().join("block.tmp"), [0u8; 100]).unwrap();
+        first_usage
+            .rename_block(&first.as_path().join("block.tmp"), &block_path)
+            .unwrap();
+        assert_eq!(second_usage.size().unwrap(), 100);
+
+        // Replacing a block only counts the new one
+        fs::write(second.as_path().join("block.tmp"), [0u8; 60]).unwrap();
+        second_usage
+            .rename_block(&second.as_path().join("block.tmp"), &block_path)
+            .unwrap();
+        assert_eq!(first_usage.size().unwrap(), 60);
+
+        assert!(first_usage.remove_block(&block_path).unwrap());
+        assert!(!second_usage.remove_block(&block_path).unwrap());
+        assert_eq!(second_usage.size().unwrap(), 0);
+
+        // Blocks are evicted oldest first until the total is under the limit
+        for (age, name) in [(3, "a"), (2, "b"), (1, "c")] {
+            let temp_path = first.as_path().join(format!("{name}.tmp"));
+            let file = fs::File::create(&temp_path).unwrap();
+            file.set_len(10).unwrap();
+            file.set_modified(SystemTime::now() - Duration::from_secs(age * 60))
+                .unwrap();
+            first_usage
+                .rename_block(&temp_path, &first.as_path().join(name))
+                .unwrap();
+        }
+        assert_eq!(second_usage.evict_oldest(|size| size > 15).unwrap(), 2);
+        assert_eq!(second_usage.size().unwrap(), 10);
+        assert!(first.as_path().join("c").try_exists().unwrap());
+
+        // A new first user starts counting from zero again
+        drop(first);
+        drop(second);
+        let third = SharedCacheDir::new_from_parent(temp_dir.path()).expect("third user should succeed");
+        assert_eq!(third.usage().size().unwrap(), 0);
+    }
 }
@@ -1,7 +1,7 @@
 //! Module for the on-disk data cache implementation.
 
 use std::fs;
-use std::io::{ErrorKind, Read, Seek, Write};
+use std::io::{self, ErrorKind, Read, Seek, Write};
 use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
 use std::path::{Path, PathBuf};
 use std::time::Instant;
@@ -15,6 +15,7 @@ use thiserror::Error;
 use tracing::{trace, warn};
 
 use crate::checksums::IntegrityError;
+use crate::data_cache::cache_directory::{SharedCacheDir, SharedCacheUsage};
 use crate::data_cache::DataCacheError;
 use crate::object::ObjectId;
 use crate::sync::Mutex;
@@ -33,6 +34,8 @@ pub struct DiskDataCache {
     config: DiskDataCacheConfig,
     /// Tracks blocks usage. `None` when no cache limit was set.
     usage: Option<Mutex<UsageInfo<DiskBlockKey>>>,
+    /// The total size of the blocks in the shared directory the cache is in, if any
+    shared_usage: Option<SharedCacheUsage>,
 }
 
 /// Configuration for a [DiskDataCache].
@@ -213,6 +216,19 @@ impl DiskDataCache {
             cache_directory,
             config,
             usage,
+            shared_usage: None,
+        }
+    }
+
+    /// Create a new instance of an [DiskDataCache] that keeps the blocks of objects in `bucket` in
+    /// a [SharedCacheDir]. The cache limit applies to the blocks every user of the directory has
+    /// cached, and when the blocks this cache wrote itself aren't enough to get under it, it
+    /// evicts the blocks in the directory that were written longest ago.
+    pub fn new_shared(shared_cache_dir: &SharedCacheDir, bucket: &str, config: DiskDataCacheConfig) -> Self {
+        let cache_directory = shared_cache_dir.cache_path(bucket, config.block_size);
+        DiskDataCache {
+            shared_usage: Some(shared_cache_dir.usage()),
+            ..Self::new(cache_directory, config)
         }
     }
 
@@ -280,14 +296,33 @@ impl DiskDataCache {
             "writing block at {}",
             path.as_ref().display()
         );
+        // Write the block to a temporary file and rename it into place, so that readers (including
+        // other mounts sharing the cache directory) never see a partly written block
+        let temp_path = temp_block_path(path.as_ref());
+        let result = Self::write_block_file(&temp_path, &block).and_then(|size| {
+            self.rename_block_file(&temp_path, path.as_ref())?;
+            Ok(size)
+        });
+        if result.is_err() {
+            match fs::remove_file(&temp_path) {
+                Err(remove_err) if remove_err.kind() != ErrorKind::NotFound => {
+                    warn!("unable to remove temporary block: {:?}", remove_err)
+                }
+                _ => {}
+            }
+        }
+        result
+    }
+
+    fn write_block_file(path: &Path, block: &DiskBlock) -> DataCacheResult<usize> {
         let mut file = fs::OpenOptions::new()
             .write(true)
             .create(true)
             .truncate(true)
             .mode(0o600)
-            .open(path.as_ref())?;
+            .open(path)?;
         file.write_all(CACHE_VERSION.as_bytes())?;
-        let serialize_result = bincode::serialize_into(&mut file, &block);
+        let serialize_result = bincode::serialize_into(&mut file, block);
         if let Err(err) = serialize_result {
             return match *err {
                 bincode::ErrorKind::Io(io_err) => return Err(DataCacheError::from(io_err)),
@@ -323,19 +358,62 @@ impl DiskDataCache {
             return Ok(());
         };
 
-        while self.is_limit_exceeded(usage.lock().unwrap().size) {
+        while self.is_limit_exceeded(self.cached_size(usage)?) {
             let Some(to_remove) = usage.lock().unwrap().evict_lru() else {
+                // Blocks cached by other users of a shared directory count towards the limit too,
+                // including those of users that have exited, which nobody tracks any more
+                if let Some(shared_usage) = &self.shared_usage {
+                    if shared_usage.evict_oldest(|size| self.is_limit_exceeded(size as usize))? > 0 {
+                        continue;
+                    }
+                }
                 warn!("cache limit exceeded but nothing to evict");
                 return Err(DataCacheError::EvictionFailure);
             };
             let path_to_remove = self.get_path_for_block_key(&to_remove);
             trace!("evicting block at {}", path_to_remove.display());
-            if let Err(remove_err) = fs::remove_file(&path_to_remove) {
+            if let Err(remove_err) = self.remove_block_file(&path_to_remove) {
                 warn!("unable to remove invalid block: {:?}", remove_err);
             }
         }
         Ok(())
     }
+
+    /// The size the cache limit applies to: the total size of the blocks in a shared directory, or
+    /// otherwise of the blocks this cache wrote
+    fn cached_size(&self, usage: &Mutex<UsageInfo<DiskBlockKey>>) -> DataCacheResult<usize> {
+        match &self.shared_usage {
+            Some(shared_usage) => Ok(shared_usage.size()? as usize),
+            None => Ok(usage.lock().unwrap().size),
+        }
+    }
+
+    /// Remove a block, keeping the total size of a shared directory up to date
+    fn remove_block_file(&self, path: &Path) -> io::Result<()> {
+        match &self.shared_usage {
+            Some(shared_usage) => shared_usage.remove_block(path).map(drop),
+            None => fs::remove_file(path),
+        }
+    }
+
+    /// Move a newly written block into place, keeping the total size of a shared directory up to date
+    fn rename_block_file(&self, from: &Path, to: &Path) -> io::Result<()> {
+        match &self.shared_usage {
+            Some(shared_usage) => shared_usage.rename_block(from, to),
+            None => fs::rename(from, to),
+        }
+    }
+}
+
+/// A unique path next to a block's path, to write the block to before renaming it into place
+fn temp_block_path(path: &Path) -> PathBuf {
+    // Shared by every cache in the process, and not something Shuttle needs to model
+    static NEXT_TEMP_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+    let temp_id = NEXT_TEMP_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let mut file_name = path.file_name().expect("block path has a file name").to_owned();
+    file_name.push(format!(".{}.{temp_id}.tmp", std::process::id()));
+    path.with_file_name(file_name)
 }
 
 /// Hash the cache key using its fields as well as the [CACHE_VERSION].
@@ -383,7 +461,7 @@ impl DataCache for DiskDataCache {
                 // Invalid block. Count as cache miss.
                 metrics::counter!("disk_data_cache.block_hit").increment(0);
                 metrics::counter!("disk_data_cache.block_err").increment(1);
-                match fs::remove_file(&path) {
+                match self.remove_block_file(&path) {
                     Ok(()) => {
                         if let Some(usage) = &self.usage {
                             usage.lock().unwrap().remove(&block_key);
@@ -836,6 +914,67 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_shared_eviction() {
+        const BLOCK_SIZE: usize = 100 * 1024;
+        const CACHE_LIMIT: usize = 500 * 1024;
+
+        fn put_blocks(cache: &DiskDataCache, name: &str, count: u64) {
+            let cache_key = ObjectId::new(name.into(), ETag::for_tests());
+            for block_idx in 0..count {
+                let bytes = ChecksummedBytes::new(vec![block_idx as u8; BLOCK_SIZE].into());
+                cache
+                    .put_block(cache_key.clone(), block_idx, block_idx * BLOCK_SIZE as u64, bytes)
+                    .expect("put should succeed");
+            }
+        }
+
+        fn size_on_disk(path: &Path) -> u64 {
+            fs::read_dir(path)
+                .unwrap()
+                .map(|entry| {
+                    let entry = entry.unwrap();
+                    let metadata = entry.metadata().unwrap();
+                    if metadata.is_dir() {
+                        size_on_disk(&entry.path())
+                    } else {
+                        metadata.len()
+                    }
+                })
+                .sum()
+        }
+
+        let config = || DiskDataCacheConfig {
+            block_size: BLOCK_SIZE as u64,
+            limit: CacheLimit::TotalSize { max_size: CACHE_LIMIT },
+        };
+        let parent_directory = tempfile::tempdir().unwrap();
+        let first_dir = SharedCacheDir::new_from_parent(parent_directory.path()).unwrap();
+        let second_dir = SharedCacheDir::new_from_parent(parent_directory.path()).unwrap();
+
+        // The first cache fills the directory and goes away, leaving blocks nobody tracks
+        let first = DiskDataCache::new_shared(&first_dir, "bucket", config());
+        put_blocks(&first, "first", 10);
+        drop(first);
+
+        // The second cache has to evict those to stay under the limit
+        let second = DiskDataCache::new_shared(&second_dir, "bucket", config());
+        put_blocks(&second, "second", 10);
+
+        let total_size = second_dir.usage().size().unwrap();
+        assert_eq!(total_size, size_on_disk(second_dir.as_path()));
+        // Blocks are evicted before each write, so the total can go over by the last block
+        assert!(
+            total_size < (CACHE_LIMIT + 2 * BLOCK_SIZE) as u64,
+            "total size {total_size} should be near the limit {CACHE_LIMIT}"
+        );
+        let second_key = ObjectId::new("second".into(), ETag::for_tests());
+        assert!(second
+            .get_block(&second_key, 9, 9 * BLOCK_SIZE as u64)
+            .unwrap()
+            .is_some());
+    }
+
     #[test]
     fn data_block_extract_checks() {
         let data_1 = ChecksummedBytes::new("Foo".into());
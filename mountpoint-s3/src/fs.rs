@@ -1,45 +1,137 @@
 //! FUSE file system types and operations, not tied to the _fuser_ library bindings.
 
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
+use futures::{pin_mut, AsyncWrite, AsyncWriteExt, TryStreamExt};
+use globset::{Glob, GlobSet, GlobSetBuilder};
 use mountpoint_s3_crt::checksums::crc32c::{Crc32c, Hasher};
 use nix::unistd::{getgid, getuid};
+use serde::Deserialize;
 use std::collections::HashMap;
 use std::ffi::{OsStr, OsString};
+use std::io::BufWriter;
+use std::ops::Range;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::time::{Duration, UNIX_EPOCH};
+use std::time::{Duration, Instant, UNIX_EPOCH};
 use thiserror::Error;
 use time::OffsetDateTime;
-use tracing::{debug, error, trace, Level};
+use tracing::{debug, error, trace, warn, Level};
 
-use fuser::consts::FOPEN_DIRECT_IO;
-use fuser::{FileAttr, KernelConfig};
-use mountpoint_s3_client::error::{GetObjectError, ObjectClientError};
-use mountpoint_s3_client::types::ETag;
+#[cfg(feature = "fuse")]
+use fuser::KernelConfig;
+use mountpoint_s3_client::error::{CopyObjectError, GetObjectError, ObjectClientError};
+use mountpoint_s3_client::types::{ETag, MAX_DELETE_OBJECTS_KEYS};
 use mountpoint_s3_client::ObjectClient;
 
-use crate::inode::{Inode, InodeError, InodeKind, LookedUp, ReaddirHandle, Superblock, SuperblockConfig, WriteHandle};
+use crate::clock::{Clock, SystemClock};
+use crate::inode::{
+    validate_inode_name, DirectoryPoller, Inode, InodeError, InodeKind, InodeStat, LookedUp, ReaddirHandle, Superblock,
+    SuperblockConfig, WriteHandle, MAX_NAME_LEN,
+};
 use crate::logging;
-use crate::prefetch::{Prefetch, PrefetchReadError, PrefetchResult};
+use crate::mem_limiter::MemoryLimiter;
+use crate::prefetch::{
+    IdleBufferPolicy, Prefetch, PrefetchBuffers, PrefetchReadError, PrefetchResult, PrefetchStats, ReadSource,
+};
 use crate::prefix::Prefix;
 use crate::s3::S3Personality;
-use crate::sync::atomic::{AtomicI64, AtomicU64, Ordering};
-use crate::sync::{Arc, AsyncMutex, AsyncRwLock};
-use crate::upload::{UploadRequest, Uploader};
+use crate::sync::atomic::{AtomicBool, AtomicI64, AtomicU32, AtomicU64, Ordering};
+use crate::sync::{Arc, AsyncMutex, InstrumentedAsyncRwLock, Mutex};
+use crate::upload::{UploadCompleteError, UploadRequest, Uploader};
 
 pub use crate::inode::InodeNo;
 
+mod config;
+pub(crate) use config::deserialize_duration;
+pub use config::{ConfigError, InvalidConfigValue, S3FilesystemConfigBuilder};
+
+mod fuse_types;
+use fuse_types::FOPEN_DIRECT_IO;
+
+mod idle_reads;
+use idle_reads::{IdleRead, IdleReadSweeper, IdleReads};
+
+mod manifest;
+pub use fuse_types::{FileAttr, FileType};
+pub use manifest::ManifestError;
+
+mod notifier;
+#[cfg(feature = "fuse")]
+pub use notifier::FuseNotifier;
+use notifier::{invalidate_changed_directory, NegativeReplies};
+pub use notifier::{KernelNotifier, NotifierSlot};
+
+mod path_rules;
+pub(crate) use path_rules::PathRules;
+pub use path_rules::{InvalidPrefixPattern, PathOverrides, PrefixPattern};
+
 #[macro_use]
 mod error;
 pub use error::{Error, ToErrno};
 
+mod circuit_breaker;
+pub(crate) use circuit_breaker::{CircuitBreaker, Permit};
+pub use circuit_breaker::{CircuitBreakerConfig, CircuitBreakerOpen};
+
+mod decompress;
+use decompress::ContentEncoding;
+
+mod unknown_length;
+use unknown_length::UnknownLengthRead;
+
+mod local_write;
+use local_write::LocalWrite;
+
+mod partial_write;
+use partial_write::{PartialWrite, PartialWriteState};
+
+mod pending_unlinks;
+use pending_unlinks::{PendingUnlinks, UnlinkFlusher};
+
+mod view;
+pub use view::S3FilesystemView;
+
 pub const FUSE_ROOT_INODE: InodeNo = 1u64;
 
+/// Name of the extended attribute that holds a file's ETag, when
+/// [S3FilesystemConfig::etag_xattr] is enabled. The ETag changes whenever the object's content
+/// does, even if its size and modification time look the same.
+pub const ETAG_XATTR: &str = "user.s3.etag";
+
+/// The error for a missing extended attribute
+#[cfg(target_os = "linux")]
+const ENOATTR: libc::c_int = libc::ENODATA;
+#[cfg(not(target_os = "linux"))]
+const ENOATTR: libc::c_int = libc::ENOATTR;
+
+/// Size of each read [S3Filesystem::download_to] makes from the prefetcher
+const DOWNLOAD_READ_SIZE: u32 = 1024 * 1024;
+
+/// How far ahead of an upload the kernel's writeback cache can send writes, if the client doesn't
+/// have a part size to go by
+const DEFAULT_WRITEBACK_REORDER_WINDOW: usize = 8 * 1024 * 1024;
+
+/// Most entries one `readdir` call will skip to reach a later offset than the stream is at. Each
+/// skipped entry may have to be listed from S3, so an arbitrary offset could otherwise cost a
+/// listing of the whole directory.
+pub const MAX_READDIR_SKIP: i64 = 10_000;
+
+/// An open directory stream. All of a stream's state (its position, listing continuation, and
+/// snapshot) belongs to its handle, so streams over the same directory never wait on each other.
+/// The only state they share is the superblock's cache of complete listings, which is consulted
+/// when the stream starts and only ever holds listings that have finished.
 #[derive(Debug)]
 struct DirHandle {
     #[allow(unused)]
     ino: InodeNo,
     handle: AsyncMutex<ReaddirHandle>,
+    options: DirOptions,
     offset: AtomicI64,
+    /// The offset just past the last entry of the directory, once the stream has reached it.
+    /// [i64::MAX] until then. Reset whenever the stream rewinds, since a refreshed listing may be
+    /// shorter or longer.
+    end_offset: AtomicI64,
     last_response: AsyncMutex<Option<(i64, Vec<DirectoryEntry>)>>,
 }
 
@@ -52,7 +144,17 @@ impl DirHandle {
         self.offset.fetch_add(1, Ordering::SeqCst);
     }
 
+    fn end_offset(&self) -> i64 {
+        self.end_offset.load(Ordering::SeqCst)
+    }
+
+    /// Record that the stream has no entries after the current offset
+    fn reached_end(&self) {
+        self.end_offset.store(self.offset(), Ordering::SeqCst);
+    }
+
     fn rewind_offset(&self) {
+        self.end_offset.store(i64::MAX, Ordering::SeqCst);
         self.offset.store(0, Ordering::SeqCst);
     }
 }
@@ -66,6 +168,44 @@ where
     inode: Inode,
     full_key: String,
     state: AsyncMutex<FileHandleState<Client, Prefetcher>>,
+    /// Whether the handle was opened with `FOPEN_DIRECT_IO`, so every read of it reaches us rather
+    /// than being served from the kernel's page cache
+    direct_io: bool,
+    /// The reads served through the handle so far
+    reads: Mutex<HandleReads>,
+    /// For write handles, the data written through the handle that's kept for reads through this
+    /// mount, see [S3FilesystemConfig::local_read_window]
+    local_write: Option<Arc<LocalWrite>>,
+}
+
+/// The reads served through a file handle, summarized when it's released
+#[derive(Debug, Default)]
+struct HandleReads {
+    /// Number of reads
+    count: u64,
+    /// Total bytes returned by the reads
+    bytes: u64,
+    /// Furthest offset any read reached
+    max_end: u64,
+    /// Bytes the application said it read through the handle, see
+    /// [S3Filesystem::report_application_reads]
+    reported_bytes: Option<u64>,
+}
+
+impl HandleReads {
+    fn record(&mut self, offset: u64, len: usize) {
+        self.count += 1;
+        if len > 0 {
+            self.bytes += len as u64;
+            self.max_end = self.max_end.max(offset + len as u64);
+        }
+    }
+
+    /// Bytes the application likely read through the handle: what it reported, or otherwise
+    /// everything up to the furthest offset we served, as sequential readers do
+    fn application_bytes(&self) -> u64 {
+        self.reported_bytes.unwrap_or(self.max_end)
+    }
 }
 
 enum FileHandleState<Client, Prefetcher>
@@ -73,10 +213,23 @@ where
     Client: ObjectClient + Send + Sync + 'static,
     Prefetcher: Prefetch,
 {
-    /// The file handle has been assigned as a read handle
-    Read(Prefetcher::PrefetchResult<Client>),
+    /// The file handle has been assigned as a read handle, sharing its read state with the other
+    /// read handles open on the same inode
+    Read(Arc<SharedRead<Client, Prefetcher>>),
+    /// The file handle has been assigned as a read handle for an object whose size isn't known,
+    /// which can only be read sequentially
+    ReadUnknownLength(UnknownLengthRead<Client>),
+    /// The file handle has been assigned as a read handle for a file that's being written through
+    /// this mount, and is served from the data recently written to it
+    ReadLocal(Arc<LocalWrite>),
     /// The file handle has been assigned as a write handle
     Write(UploadState<Client>),
+    /// The file handle has been assigned as a write handle that overwrites part of an existing
+    /// object, see [S3FilesystemConfig::allow_partial_writes]
+    PartialWrite(PartialWriteState),
+    /// The file handle was opened with `O_PATH`, so it can't be read or written, only used to
+    /// refer to the file
+    Path,
 }
 
 impl<Client, Prefetcher> std::fmt::Debug for FileHandleState<Client, Prefetcher>
@@ -86,8 +239,12 @@ where
 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            FileHandleState::Read(_) => f.debug_struct("Read").finish(),
+            FileHandleState::Read(_) => f.debug_tuple("Read").finish(),
+            FileHandleState::ReadUnknownLength(arg0) => f.debug_tuple("ReadUnknownLength").field(arg0).finish(),
+            FileHandleState::ReadLocal(_) => f.debug_tuple("ReadLocal").finish(),
             FileHandleState::Write(arg0) => f.debug_tuple("Write").field(arg0).finish(),
+            FileHandleState::PartialWrite(arg0) => f.debug_tuple("PartialWrite").field(arg0).finish(),
+            FileHandleState::Path => f.write_str("Path"),
         }
     }
 }
@@ -128,6 +285,41 @@ where
         Ok(handle)
     }
 
+    /// A write handle that overwrites part of the existing object of a remote file, keeping the
+    /// rest of its contents
+    async fn new_partial_write_handle(
+        lookup: &LookedUp,
+        ino: InodeNo,
+        pid: u32,
+        fs: &S3Filesystem<Client, Prefetcher>,
+    ) -> Result<FileHandleState<Client, Prefetcher>, Error> {
+        let Some(part_size) = fs.client.part_size() else {
+            return Err(err!(
+                libc::EOPNOTSUPP,
+                "writes into existing files need a client that uses multi-part uploads"
+            ));
+        };
+        let Some(etag) = lookup.stat.etag.as_deref() else {
+            return Err(err!(libc::EBADF, "no E-Tag for inode {}", ino));
+        };
+        let etag = ETag::from_str(etag).expect("E-Tag should be set");
+        let handle = fs
+            .superblock
+            .write(
+                &fs.client,
+                ino,
+                lookup.inode.parent(),
+                pid,
+                fs.config.allow_overwrite,
+                false,
+            )
+            .await
+            .start_partial_writing()?;
+        let write = PartialWrite::new(etag, lookup.stat.size as u64, part_size as u64);
+        metrics::gauge!("fs.current_handles", "type" => "write").increment(1.0);
+        Ok(FileHandleState::PartialWrite(PartialWriteState::new(write, handle)))
+    }
+
     async fn new_read_handle(
         lookup: &LookedUp,
         fs: &S3Filesystem<Client, Prefetcher>,
@@ -138,20 +330,203 @@ where
                 "objects in flexible retrieval storage classes are not accessible",
             ));
         }
+        if !lookup.inode.is_remote()? {
+            if let Some(local_write) = fs.local_write(lookup.inode.ino()) {
+                metrics::gauge!("fs.current_handles", "type" => "read").increment(1.0);
+                return Ok(FileHandleState::ReadLocal(local_write));
+            }
+        }
         lookup.inode.start_reading()?;
         let full_key = lookup.inode.full_key().to_owned();
         let object_size = lookup.stat.size as u64;
-        let etag = match &lookup.stat.etag {
-            None => return Err(err!(libc::EBADF, "no E-Tag for inode {}", lookup.inode.ino())),
-            Some(etag) => ETag::from_str(etag).expect("E-Tag should be set"),
-        };
-        let request = fs
-            .prefetcher
-            .prefetch(fs.client.clone(), &fs.bucket, &full_key, object_size, etag.clone());
-        let handle = FileHandleState::Read(request);
+        let Some(etag) = lookup.stat.etag.clone() else {
+            return Err(err!(libc::EBADF, "no E-Tag for inode {}", lookup.inode.ino()));
+        };
+        let encoding = fs.decompressed_encoding(&lookup.stat);
+        if lookup.stat.unknown_size || encoding.is_some() {
+            let request = UnknownLengthRead::new(
+                fs.client.clone(),
+                &fs.bucket,
+                &full_key,
+                ETag::from_str(&etag).expect("E-Tag should be set"),
+                encoding,
+            );
+            metrics::gauge!("fs.current_handles", "type" => "read").increment(1.0);
+            return Ok(FileHandleState::ReadUnknownLength(request));
+        }
+        let read = fs.acquire_shared_read(lookup.inode.ino(), object_size, etag);
         metrics::gauge!("fs.current_handles", "type" => "read").increment(1.0);
-        Ok(handle)
+        Ok(FileHandleState::Read(read))
+    }
+}
+
+/// Reads starting at most this many bytes after the end of a [ReadStream]'s last read continue
+/// that stream, rather than starting a new one
+const READ_STREAM_FORWARD_DISTANCE: u64 = 16 * 1024 * 1024;
+/// Reads starting at most this many bytes before the end of a [ReadStream]'s last read continue
+/// that stream, rather than starting a new one
+const READ_STREAM_BACKWARD_DISTANCE: u64 = 1024 * 1024;
+/// Most sequential streams kept for an object's shared reads. Past this, a new stream replaces the
+/// one that was used least recently.
+const MAX_READ_STREAMS: usize = 8;
+
+/// The read state for an object, shared by all the read handles open on its inode so that they
+/// don't each fetch (and cache) the same data. Reads are grouped into sequential streams, each with
+/// its own prefetcher, so that readers at different places in the object don't serialize on one
+/// prefetcher or keep resetting each other's prefetch windows.
+struct SharedRead<Client, Prefetcher>
+where
+    Client: ObjectClient + Send + Sync + 'static,
+    Prefetcher: Prefetch,
+{
+    /// ETag of the object the reads are conditional on
+    etag: String,
+    /// Size of the object, for new streams. It only shrinks if the object is found to be smaller
+    /// than when it was opened.
+    object_size: AtomicU64,
+    /// The sequential streams, least recently used first
+    streams: Mutex<Vec<StreamSlot<Client, Prefetcher>>>,
+}
+
+/// A [ReadStream] and where the last read assigned to it ends
+struct StreamSlot<Client, Prefetcher>
+where
+    Client: ObjectClient + Send + Sync + 'static,
+    Prefetcher: Prefetch,
+{
+    next_offset: u64,
+    stream: Arc<AsyncMutex<ReadStream<Prefetcher::PrefetchResult<Client>>>>,
+}
+
+/// One sequential stream of reads of a shared object, with the prefetch `Request` that serves it
+struct ReadStream<Request> {
+    request: Request,
+    /// Size of the object this stream's prefetcher was started with
+    object_size: u64,
+    /// When the last read of the stream started, or when the stream did if there hasn't been one
+    last_read: Instant,
+    /// Whether the [IdleBufferPolicy] has already applied since the last read
+    idle_released: bool,
+}
+
+impl<Request: PrefetchBuffers> ReadStream<Request> {
+    fn new(request: Request, object_size: u64, now: Instant) -> Self {
+        Self {
+            request,
+            object_size,
+            last_read: now,
+            idle_released: false,
+        }
+    }
+
+    /// Apply `policy` if the stream has gone `timeout` without reads by `now`, at most once per
+    /// pause. Returns whether any data was released.
+    fn release_if_idle(&mut self, policy: IdleBufferPolicy, timeout: Duration, now: Instant) -> bool {
+        if self.idle_released || now.saturating_duration_since(self.last_read) < timeout {
+            return false;
+        }
+        self.idle_released = true;
+        self.request.release_idle_buffers(policy)
+    }
+
+    /// Record that a read of the stream started at `now`
+    fn start_read(&mut self, now: Instant) {
+        self.last_read = now;
+        self.idle_released = false;
+    }
+}
+
+impl<Request: PrefetchBuffers + 'static> IdleRead for AsyncMutex<ReadStream<Request>> {
+    fn release_if_idle(&self, policy: IdleBufferPolicy, timeout: Duration, now: Instant) -> bool {
+        // A stream with a read in progress isn't idle
+        match self.try_lock() {
+            Some(mut stream) => stream.release_if_idle(policy, timeout, now),
+            None => false,
+        }
+    }
+}
+
+impl<Client, Prefetcher> SharedRead<Client, Prefetcher>
+where
+    Client: ObjectClient + Send + Sync + 'static,
+    Prefetcher: Prefetch,
+{
+    fn new(etag: String, object_size: u64) -> Self {
+        Self {
+            etag,
+            object_size: AtomicU64::new(object_size),
+            streams: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Find the stream a read of `size` bytes at `offset` continues, starting a new one with
+    /// `start_stream` if there's none
+    fn stream_for_read(
+        &self,
+        offset: u64,
+        size: u64,
+        start_stream: impl FnOnce(u64) -> Arc<AsyncMutex<ReadStream<Prefetcher::PrefetchResult<Client>>>>,
+    ) -> Arc<AsyncMutex<ReadStream<Prefetcher::PrefetchResult<Client>>>> {
+        let mut streams = self.streams.lock().unwrap();
+        let closest = streams
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| {
+                offset <= slot.next_offset.saturating_add(READ_STREAM_FORWARD_DISTANCE)
+                    && offset.saturating_add(READ_STREAM_BACKWARD_DISTANCE) >= slot.next_offset
+            })
+            .min_by_key(|(_, slot)| offset.abs_diff(slot.next_offset))
+            .map(|(index, _)| index);
+        let mut slot = match closest {
+            Some(index) => streams.remove(index),
+            None => {
+                if streams.len() >= MAX_READ_STREAMS {
+                    streams.remove(0);
+                }
+                let object_size = self.object_size.load(Ordering::SeqCst);
+                trace!(offset, streams = streams.len() + 1, "starting a new read stream");
+                StreamSlot {
+                    next_offset: offset,
+                    stream: start_stream(object_size),
+                }
+            }
+        };
+        // Claim the range now rather than once the read is done, so concurrent sequential reads
+        // find this stream too
+        slot.next_offset = offset.saturating_add(size);
+        let stream = slot.stream.clone();
+        streams.push(slot);
+        stream
     }
+
+    /// Record that the object was found to be only `new_size` bytes
+    fn shrink(&self, new_size: u64) {
+        self.object_size.fetch_min(new_size, Ordering::SeqCst);
+    }
+
+    /// The combined stats of the streams' prefetch requests
+    async fn stats(&self) -> PrefetchStats {
+        let streams: Vec<_> = {
+            let streams = self.streams.lock().unwrap();
+            streams.iter().map(|slot| slot.stream.clone()).collect()
+        };
+        let mut stats = PrefetchStats::default();
+        for stream in streams {
+            stats = stats + stream.lock().await.request.stats();
+        }
+        stats
+    }
+}
+
+/// A [SharedRead] and the number of read handles using it
+struct SharedReadEntry<Client, Prefetcher>
+where
+    Client: ObjectClient + Send + Sync + 'static,
+    Prefetcher: Prefetch,
+{
+    read: Arc<SharedRead<Client, Prefetcher>>,
+    etag: String,
+    handles: usize,
 }
 
 #[derive(Debug)]
@@ -166,14 +541,30 @@ enum UploadState<Client: ObjectClient> {
 }
 
 impl<Client: ObjectClient> UploadState<Client> {
-    async fn write(&mut self, offset: i64, data: &[u8], key: &str) -> Result<u32, Error> {
+    /// The size of the object being uploaded, or zero if the upload is no longer in progress
+    fn size(&self) -> u64 {
+        match self {
+            Self::InProgress { request, .. } => request.size(),
+            Self::Completed | Self::Failed(_) => 0,
+        }
+    }
+
+    /// Write `data` at `offset`, calling `committed` with each piece of data as it's added to the
+    /// upload (see [UploadRequest::write_with])
+    async fn write(
+        &mut self,
+        offset: i64,
+        data: &[u8],
+        key: &str,
+        committed: impl FnMut(u64, &[u8]) + Send,
+    ) -> Result<u32, Error> {
         let upload = match self {
             Self::InProgress { request, .. } => request,
             Self::Completed => return Err(err!(libc::EIO, "upload already completed for key {:?}", key)),
             Self::Failed(e) => return Err(err!(*e, "upload already aborted for key {:?}", key)),
         };
 
-        match upload.write(offset, data).await {
+        match upload.write_with(offset, data, committed).await {
             Ok(len) => Ok(len as u32),
             Err(e) => {
                 // Abort the request.
@@ -226,6 +617,44 @@ impl<Client: ObjectClient> UploadState<Client> {
         result
     }
 
+    /// Complete the upload with a server-side copy of the object `source_key`, which must still
+    /// have the ETag `source_etag`, instead of the data written to it. Returns `false`, leaving the
+    /// upload untouched, if data was already written.
+    async fn complete_with_copy(&mut self, key: &str, source_key: &str, source_etag: &ETag) -> Result<bool, Error> {
+        match self {
+            Self::InProgress { request, .. } if request.size() == 0 => {}
+            Self::InProgress { .. } | Self::Completed => return Ok(false),
+            Self::Failed(e) => return Err(err!(*e, "upload already aborted for key {:?}", key)),
+        }
+
+        let (upload, handle) = match std::mem::replace(self, Self::Completed) {
+            Self::InProgress { request, handle } => (request, handle),
+            Self::Failed(_) | Self::Completed => unreachable!("checked above"),
+        };
+
+        let result = match upload.complete_with_copy(source_key, source_etag).await {
+            Ok(()) => {
+                debug!(key, source_key, "completed upload with a server-side copy");
+                Ok(true)
+            }
+            // The source was overwritten since it was opened, so don't copy the new object instead
+            Err(
+                e @ UploadCompleteError::CopyFailed(ObjectClientError::ServiceError(
+                    CopyObjectError::PreconditionFailed,
+                )),
+            ) => Err(err!(libc::ESTALE, source:e, "source object {:?} changed while copying", source_key)),
+            Err(e) => Err(err!(libc::EIO, source:e, "copy failed")),
+        };
+        if let Err(err) = handle.finish_writing() {
+            // Log the issue but still return the copy result.
+            error!(?err, ?key, "error updating the inode status");
+        }
+        if let Err(e) = &result {
+            *self = Self::Failed(e.to_errno());
+        }
+        result
+    }
+
     async fn complete_if_in_progress(self, key: &str) -> Result<(), Error> {
         match self {
             Self::InProgress { request, handle } => Self::complete_upload(request, key, handle).await,
@@ -287,7 +716,102 @@ fn are_from_same_process(pid1: u32, pid2: u32) -> bool {
     tgid1 == tgid2
 }
 
-#[derive(Debug, Clone)]
+/// A position in a directory stream that survives the process, see
+/// [S3Filesystem::encode_dir_position]
+#[derive(Debug, PartialEq, Eq)]
+struct DirPosition {
+    /// Key of the directory, including the mount's prefix
+    dir_key: String,
+    /// Name of the entry the position is just after
+    name: String,
+}
+
+impl DirPosition {
+    fn new(dir_key: &str, name: &str) -> Self {
+        Self {
+            dir_key: dir_key.to_owned(),
+            name: name.to_owned(),
+        }
+    }
+
+    /// Encode the position as a token of hex digits, with the directory key and name separated by
+    /// a `.`, so that it can be stored anywhere a plain string can
+    fn encode(&self) -> String {
+        format!("{}.{}", hex::encode(&self.dir_key), hex::encode(&self.name))
+    }
+
+    fn decode(token: &str) -> Option<Self> {
+        let (dir_key, name) = token.split_once('.')?;
+        let decode = |part: &str| String::from_utf8(hex::decode(part).ok()?).ok();
+        Some(Self {
+            dir_key: decode(dir_key)?,
+            name: decode(name)?,
+        })
+    }
+}
+
+/// Compile path globs from the config into a set, or `None` if there are none. `what` names them
+/// in the warning logged if they can't be compiled, in which case none of them match.
+fn build_glob_set(globs: &[Glob], what: &str) -> Option<GlobSet> {
+    if globs.is_empty() {
+        return None;
+    }
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(glob.clone());
+    }
+    match builder.build() {
+        Ok(globs) => Some(globs),
+        Err(e) => {
+            warn!(error=?e, "failed to compile {what}, they will not match any paths");
+            None
+        }
+    }
+}
+
+/// Delete `keys` from `bucket` with as few DeleteObjects requests as possible. Returns the number
+/// of objects deleted, along with an error describing any keys that couldn't be deleted. Fails
+/// outright if a request as a whole fails.
+async fn delete_keys<Client: ObjectClient>(
+    client: &Client,
+    bucket: &str,
+    keys: &[String],
+) -> Result<(usize, Option<Error>), Error> {
+    let mut deleted = 0;
+    let mut failures = Vec::new();
+    for batch in keys.chunks(MAX_DELETE_OBJECTS_KEYS) {
+        let result = client
+            .delete_objects(bucket, batch)
+            .await
+            .map_err(|e| err!(libc::EIO, source:e, "DeleteObjects request failed"))?;
+        deleted += batch.len().saturating_sub(result.errors.len());
+        failures.extend(result.errors);
+    }
+    for failure in &failures {
+        warn!(key = ?failure.key, code = ?failure.code, message = ?failure.message, "failed to delete object");
+    }
+    let error = failures.first().map(|failure| {
+        err!(
+            libc::EIO,
+            "failed to delete {} of {} objects, including {:?}: {} ({})",
+            failures.len(),
+            keys.len(),
+            failure.key,
+            failure.message,
+            failure.code
+        )
+    });
+    Ok((deleted, error))
+}
+
+/// The error for a lookup of a name that doesn't exist
+fn file_does_not_exist(err: InodeError) -> Error {
+    // Lookup returning ENOENT is common case, and we dont want to warn in case `FileDoesNotExist` within ENOENT
+    err!(libc::ENOENT, source: err, Level::DEBUG, "file does not exist")
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct CacheConfig {
     /// Should the file system serve lookup requests including open from cached entries,
     /// or instead check S3 even when a valid cached entry may be available?
@@ -298,11 +822,51 @@ pub struct CacheConfig {
     /// immediately `getattr` every entry returned from `readdir`.
     pub serve_lookup_from_cache: bool,
     /// How long the kernel will cache metadata for files
+    #[serde(deserialize_with = "config::deserialize_duration")]
     pub file_ttl: Duration,
     /// How long the kernel will cache metadata for directories
+    #[serde(deserialize_with = "config::deserialize_duration")]
     pub dir_ttl: Duration,
     /// Maximum number of negative entries to cache.
     pub negative_cache_size: usize,
+    /// Key prefixes in the bucket (like `--prefix`, not relative to the mount point) whose metadata
+    /// is cached without expiry. They match whole path components, so `data` or `data/` covers
+    /// `data/` but not `database/`. Directories under these prefixes are listed only once, and their
+    /// entries are never looked up again, until a local change or the directory poller invalidates
+    /// them. Only use this for data that doesn't change remotely.
+    pub pinned_prefixes: Vec<String>,
+    /// How long the kernel will cache metadata for files that are open for writing, or whose
+    /// object was modified less than [recently_modified_window](Self::recently_modified_window)
+    /// ago, if that's shorter than the usual TTL. Their size and timestamps are still changing.
+    #[serde(deserialize_with = "config::deserialize_duration")]
+    pub recently_written_ttl: Duration,
+    /// How recently an object must have been modified in S3 for its file to be cached for
+    /// [recently_written_ttl](Self::recently_written_ttl) rather than the usual TTL. Zero disables
+    /// this, so only files open for writing are affected.
+    #[serde(deserialize_with = "config::deserialize_duration")]
+    pub recently_modified_window: Duration,
+    /// How long a completed HeadObject/ListObjectsV2 lookup of a name is reused by later lookups
+    /// of it that would otherwise ask S3, like a `lookup`, `getattr`, and `getxattr` of the same
+    /// file in quick succession. Lookups in flight are always shared by concurrent callers. Local
+    /// changes to the name end the window early, and `O_DIRECT` opens never reuse a lookup. Zero
+    /// (the default) only shares lookups in flight.
+    #[serde(deserialize_with = "config::deserialize_duration")]
+    pub lookup_coalesce_window: Duration,
+    /// Maximum approximate memory, in bytes, used by negative cache entries, in addition to the
+    /// [negative_cache_size](Self::negative_cache_size) limit on their number. The least recently
+    /// used entries are evicted first. `None` for no limit.
+    pub negative_cache_max_bytes: Option<usize>,
+    /// Maximum total number of entries in the cached listings of directories under
+    /// [pinned_prefixes](Self::pinned_prefixes). The least recently used listings are evicted, and
+    /// their directories listed again, when this is exceeded. `None` for no limit.
+    pub pinned_listings_max_entries: Option<usize>,
+    /// Maximum approximate memory, in bytes, used by the cached listings of pinned directories.
+    /// `None` for no limit.
+    pub pinned_listings_max_bytes: Option<usize>,
+    /// How often to remove expired entries from the negative cache in the background, or `None`
+    /// to only remove them as they're looked up or pushed out by new entries.
+    #[serde(deserialize_with = "config::deserialize_optional_duration")]
+    pub cache_sweep_interval: Option<Duration>,
 }
 
 impl Default for CacheConfig {
@@ -331,16 +895,133 @@ impl Default for CacheConfig {
             file_ttl,
             dir_ttl,
             negative_cache_size,
+            pinned_prefixes: Vec::new(),
+            // Files being written are served from local state anyway, so not caching them in the
+            // kernel is cheap and means `stat` always shows their current size
+            recently_written_ttl: Duration::ZERO,
+            recently_modified_window: Duration::ZERO,
+            lookup_coalesce_window: Duration::ZERO,
+            negative_cache_max_bytes: None,
+            pinned_listings_max_entries: None,
+            pinned_listings_max_bytes: None,
+            cache_sweep_interval: None,
         }
     }
 }
 
-#[derive(Debug)]
+/// Configuration of the memory of keys whose lookups keep failing, see
+/// [S3FilesystemConfig::key_failures]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyFailureConfig {
+    /// How many lookups of a key in a row must fail permanently before it's no longer looked up
+    pub failure_threshold: u32,
+    /// How close together those failures must be. Failures spread out over longer than this don't
+    /// add up, so a key that's looked up rarely is always looked up in S3.
+    #[serde(deserialize_with = "config::deserialize_duration")]
+    pub window: Duration,
+    /// How long lookups of the key fail with the cached error before S3 is asked again
+    #[serde(deserialize_with = "config::deserialize_duration")]
+    pub cooldown: Duration,
+    /// Most keys whose failures are remembered. The least recently failed keys are forgotten first.
+    pub max_keys: usize,
+}
+
+impl Default for KeyFailureConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 3,
+            window: Duration::from_secs(10),
+            cooldown: Duration::from_secs(30),
+            max_keys: 10_000,
+        }
+    }
+}
+
+/// How a directory handle behaves when `readdir` rewinds it to offset 0 (i.e. `rewinddir`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RewindMode {
+    /// Discard the directory listing and list the directory again, so that changes made since the
+    /// handle was opened are visible.
+    #[default]
+    Refresh,
+    /// Replay the entries already listed by the handle rather than listing the directory again.
+    /// Local changes (e.g. new files being written) are still visible.
+    Snapshot,
+}
+
+/// How `setattr` handles requests to change the mode or owner of a file or directory (i.e. `chmod`
+/// and `chown`). S3 has nowhere to store them, so they can't actually be changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PermissionChangeMode {
+    /// Succeed without changing anything, replying with the requested mode and owner, so that
+    /// tools that set them (e.g. when extracting archives) can carry on.
+    #[default]
+    Ignore,
+    /// Fail with `EPERM`.
+    Reject,
+}
+
+/// Where to load a listing manifest from, to bootstrap the directory listings of a new
+/// [S3Filesystem]. Manifests are exported with [S3Filesystem::export_listing].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ListingBootstrap {
+    /// A local file
+    File(PathBuf),
+    /// An object with this key in the mounted bucket
+    Object(String),
+}
+
+/// Options for a directory handle opened with [S3Filesystem::opendir_with_options]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DirOptions {
+    /// Only list subdirectories, skipping files entirely. Useful for tools that only need the
+    /// directory tree: the files are dropped from each ListObjectsV2 page as it arrives, so they're
+    /// never buffered and no inodes are created for them, and pages are always full-sized.
+    pub dirs_only: bool,
+}
+
+/// Configuration of a [S3Filesystem]. Can also be loaded from a TOML or JSON file with
+/// [S3FilesystemConfig::from_toml_str] or [S3FilesystemConfig::from_json_str].
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct S3FilesystemConfig {
     /// Kernel cache config
     pub cache_config: CacheConfig,
     /// Readdir page size
     pub readdir_size: usize,
+    /// Behavior of directory handles rewound to offset 0
+    pub readdir_rewind_mode: RewindMode,
+    /// Behavior of `chmod` and `chown`
+    pub permission_change_mode: PermissionChangeMode,
+    /// How often to poll opened directories for remote changes, or `None` to disable polling.
+    /// Changes are also reported to the kernel, if a [KernelNotifier] is available.
+    #[serde(deserialize_with = "config::deserialize_optional_duration")]
+    pub directory_poll_interval: Option<Duration>,
+    /// Invalidate the kernel's cached entries after removing them, so other processes on this mount
+    /// see the change immediately. Only takes effect if a [KernelNotifier] is available.
+    pub invalidate_kernel_entries: bool,
+    /// Reply to lookups of names that don't exist with a negative entry, which the kernel caches for
+    /// the name's negative cache TTL (the file TTL, unless overridden by a path rule) instead of
+    /// asking again on every access. Names whose TTL is zero are still reported as `ENOENT`.
+    /// Creating a name on this mount invalidates its negative entry, if a [KernelNotifier] is
+    /// available; names created elsewhere aren't seen until the entry expires, or directory polling
+    /// finds them.
+    pub negative_entry_replies: bool,
+    /// Directories nested more than this many levels below the mount point are listed as empty,
+    /// to stop tools like `find` recursing through pathologically deep prefixes. `None` for no limit.
+    pub max_listing_depth: Option<usize>,
+    /// Most entries a directory handle buffers in memory while they wait to be returned by
+    /// `readdir`. Each ListObjectsV2 page is limited to this many keys, and the next page isn't
+    /// requested until the application has read every entry of the last one, so huge directories
+    /// don't pile up entries faster than they're read. Doesn't cover the entries kept to replay
+    /// with [RewindMode::Snapshot], or handles opened with [DirOptions::dirs_only], which drop the
+    /// files of each page as it's listed and so list full pages. `None` for no limit beyond the
+    /// usual page size.
+    pub max_buffered_dir_entries: Option<usize>,
     /// User id
     pub uid: u32,
     /// Group id
@@ -351,8 +1032,27 @@ pub struct S3FilesystemConfig {
     pub file_mode: u16,
     /// Allow delete
     pub allow_delete: bool,
+    /// Rather than deleting each unlinked file's object straight away, delete them in batches with
+    /// DeleteObjects requests, which makes removing many files, like with `rm -r`, much faster.
+    /// Unlinked files disappear from the file system immediately, but their objects are deleted
+    /// once this long has passed since the oldest was unlinked, once a full batch is waiting, or
+    /// before the file system lists or creates a directory, creates a file, or looks up a
+    /// directory that could contain them. `None` to delete each object as its file is unlinked.
+    ///
+    /// This trades durability for speed: unlink succeeds before the object is deleted, so an
+    /// object whose delete fails, or that's still waiting when Mountpoint is killed, stays in S3
+    /// and reappears in the file system. Failures to delete are only reported by `rmdir`, and
+    /// otherwise logged and counted by the `fs.unlink_batch.failed_deletes` metric.
+    #[serde(deserialize_with = "config::deserialize_optional_duration")]
+    pub unlink_batch_delay: Option<Duration>,
     /// Allow overwrite
     pub allow_overwrite: bool,
+    /// Let files opened write-only without `O_TRUNC` overwrite part of their existing object in
+    /// place, rather than failing to open. The data written is kept in memory until the file is
+    /// flushed, and must be a single range that starts on a part boundary and doesn't extend the
+    /// file. Only the parts it touches are uploaded again; the others are copied server-side.
+    /// Needs [allow_overwrite](Self::allow_overwrite).
+    pub allow_partial_writes: bool,
     /// Storage class to be used for new object uploads
     pub storage_class: Option<String>,
     /// S3 personality (for different S3 semantics)
@@ -361,6 +1061,99 @@ pub struct S3FilesystemConfig {
     pub server_side_encryption: ServerSideEncryption,
     /// Use additional checksums for uploads
     pub use_upload_checksums: bool,
+    /// Send a `Content-MD5` header with uploads. The client must be configured to compute it.
+    pub require_content_md5: bool,
+    /// Upload new files under this directory at the root of the mount, and only publish them to
+    /// their final key once the upload succeeds. The directory is hidden from the file system, and
+    /// failed uploads left in it can be removed with [S3Filesystem::cleanup_staging].
+    /// `None` to upload directly to the final key.
+    pub upload_staging_directory: Option<String>,
+    /// Serve the first listing of each directory, and the first lookup of each entry, from a listing
+    /// manifest exported by an earlier mount of the same prefix, rather than from S3. If the
+    /// manifest can't be loaded, directories are listed from S3 as usual.
+    pub listing_bootstrap: Option<ListingBootstrap>,
+    /// Paths, relative to the mount point, that are presented as empty, readable files when there's
+    /// no object for them, for applications that can't cope with some files not existing
+    #[serde(deserialize_with = "config::deserialize_path_globs")]
+    pub soft_missing_paths: Vec<Glob>,
+    /// Overrides of [CacheConfig] for everything under particular paths, relative to the mount
+    /// point. Where several prefixes match a path, the longest one applies.
+    #[serde(deserialize_with = "config::deserialize_path_rules")]
+    pub path_rules: Vec<(PrefixPattern, PathOverrides)>,
+    /// Paths, relative to the mount point, of files whose objects never change once written, like
+    /// content-addressed data. Their metadata is cached without expiry, and opening them doesn't
+    /// check S3 for a newer object. If one turns out to have changed after all, an error is logged
+    /// and the file's metadata is revalidated as usual from then on.
+    pub immutable_key_patterns: Vec<PrefixPattern>,
+    /// Paths, relative to the mount point, of files that are reported as executable: their mode is
+    /// [file_mode](Self::file_mode) with the execute bit set wherever the read bit is, so that
+    /// scripts stored in S3 can be run from the mount.
+    #[serde(deserialize_with = "config::deserialize_path_globs")]
+    pub executable_paths: Vec<Glob>,
+    /// Fail new requests fast with `EAGAIN`, rather than sending them to S3, while too many recent
+    /// requests have failed. `None` to always send requests to S3.
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    /// Track failures of requests that only fetch metadata (lookups and attributes) separately from
+    /// [Self::circuit_breaker], so that S3's metadata APIs failing doesn't stop reads. While too
+    /// many of them are failing, lookups and attributes are served from cached metadata even if it
+    /// has expired, and fail with `EAGAIN` only if nothing is cached. `None` to count metadata
+    /// requests with every other request.
+    pub metadata_circuit_breaker: Option<CircuitBreakerConfig>,
+    /// Stop looking up keys whose lookups keep failing with errors that retrying won't fix: S3
+    /// denying access to them, or them not existing. Once enough lookups of a key in a row have
+    /// failed, lookups and opens of it fail with the same error for a cooldown without asking S3.
+    /// Other errors, like timeouts or throttling, are never remembered. `None` to always ask S3.
+    pub key_failures: Option<KeyFailureConfig>,
+    /// Size reported for files whose object's size isn't known until it's read, like objects
+    /// served through an S3 Object Lambda access point. Reads stream these objects to their end
+    /// whatever this says, but only sequentially.
+    pub unknown_object_size: u64,
+    /// Decompress objects stored with a `Content-Encoding` we understand (currently only `zstd`)
+    /// as they're read. Their decompressed size isn't known until they've been read, so they're
+    /// treated like objects of unknown size, and report [unknown_object_size](Self::unknown_object_size).
+    pub transparent_decompress: bool,
+    /// Expose each file's ETag as the [ETAG_XATTR] extended attribute, so tools can tell when an
+    /// object's content has changed. Without this, extended attributes aren't supported at all.
+    pub etag_xattr: bool,
+    /// Report each entry's type (file or directory) in `readdir` replies. When disabled, entries
+    /// are reported as `DT_UNKNOWN`, and callers that care have to `stat` them. `readdirplus`
+    /// replies always include each entry's full attributes.
+    pub readdir_report_types: bool,
+    /// How many of the most recently written bytes of each file being written to keep in memory,
+    /// so that reads through this mount see the file grow before its upload completes. Reads of
+    /// older data wait for the upload to complete, and then read it from S3. 0 to refuse reads of
+    /// files being written.
+    pub local_read_window: usize,
+    /// Preferred I/O size reported as every file's `st_blksize`, which applications like `cp` size
+    /// their reads by. Capped at the largest readahead the kernel offers when the file system is
+    /// mounted, since reads bigger than that are split up anyway.
+    pub block_size: u32,
+    /// Ask the kernel to cache writes in its page cache and flush them to us in the background,
+    /// which makes small writes much faster. The kernel flushes pages in roughly sequential order,
+    /// but not exactly, so writes that arrive out of order are held back, up to one part size past
+    /// the end of the data written so far, until the gap before them is filled. Writes further
+    /// ahead than that still fail, as they do without the cache, and so do writes held back when
+    /// `memory_limiter` is full. The partial page at the end of a file is also held back until it's
+    /// complete or the file is closed, because the kernel sends it again each time it's extended.
+    /// The kernel may also open files that were opened write-only for reading and writing.
+    pub writeback_cache: bool,
+    /// Budget for the memory file data is kept in, which writes held back by the writeback cache
+    /// are charged to. Share it with the client's read buffer pool, if it has one, to bound both
+    /// together. Can't be set from a config file.
+    #[serde(skip)]
+    pub memory_limiter: Arc<MemoryLimiter>,
+    /// What to do with the data prefetched for a stream of reads once it has gone
+    /// [idle_read_buffer_timeout](Self::idle_read_buffer_timeout) without reads, for applications
+    /// that read in bursts with long pauses in between. The default keeps it all.
+    pub idle_read_buffer_policy: IdleBufferPolicy,
+    /// How long a stream of reads goes without reads before
+    /// [idle_read_buffer_policy](Self::idle_read_buffer_policy) applies
+    #[serde(deserialize_with = "config::deserialize_duration")]
+    pub idle_read_buffer_timeout: Duration,
+    /// Source of the current time that cached metadata expires by, and that idle reads are timed
+    /// by. Tests can replace it to control expiry. Can't be set from a config file.
+    #[serde(skip)]
+    pub clock: Arc<dyn Clock>,
 }
 
 impl Default for S3FilesystemConfig {
@@ -371,22 +1164,53 @@ impl Default for S3FilesystemConfig {
         Self {
             cache_config: Default::default(),
             readdir_size: 100,
+            readdir_rewind_mode: Default::default(),
+            permission_change_mode: Default::default(),
+            directory_poll_interval: None,
+            invalidate_kernel_entries: true,
+            negative_entry_replies: true,
+            max_listing_depth: None,
+            max_buffered_dir_entries: None,
             uid,
             gid,
             dir_mode: 0o755,
             file_mode: 0o644,
             allow_delete: false,
+            unlink_batch_delay: None,
             allow_overwrite: false,
+            allow_partial_writes: false,
             storage_class: None,
             s3_personality: S3Personality::default(),
             server_side_encryption: Default::default(),
             use_upload_checksums: true,
+            require_content_md5: false,
+            upload_staging_directory: None,
+            listing_bootstrap: None,
+            soft_missing_paths: Vec::new(),
+            path_rules: Vec::new(),
+            immutable_key_patterns: Vec::new(),
+            executable_paths: Vec::new(),
+            circuit_breaker: None,
+            metadata_circuit_breaker: None,
+            key_failures: None,
+            unknown_object_size: 0,
+            transparent_decompress: false,
+            etag_xattr: false,
+            readdir_report_types: true,
+            local_read_window: 8 * 1024 * 1024,
+            block_size: 128 * 1024,
+            writeback_cache: false,
+            memory_limiter: Default::default(),
+            idle_read_buffer_policy: IdleBufferPolicy::Retain,
+            idle_read_buffer_timeout: Duration::from_secs(10),
+            clock: Arc::new(SystemClock),
         }
     }
 }
 
 /// Server-side encryption configuration for newly created objects
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(from = "config::ServerSideEncryptionFile")]
 pub struct ServerSideEncryption {
     sse_type: Option<String>,
     sse_kms_key_id: Option<String>,
@@ -498,11 +1322,43 @@ where
     prefetcher: Prefetcher,
     uploader: Uploader<Client>,
     bucket: String,
-    #[allow(unused)]
     prefix: Prefix,
+    /// Files reported as executable, see [S3FilesystemConfig::executable_paths]
+    executable_paths: Option<GlobSet>,
     next_handle: AtomicU64,
-    dir_handles: AsyncRwLock<HashMap<u64, Arc<DirHandle>>>,
-    file_handles: AsyncRwLock<HashMap<u64, Arc<FileHandle<Client, Prefetcher>>>>,
+    dir_handles: InstrumentedAsyncRwLock<HashMap<u64, Arc<DirHandle>>>,
+    file_handles: InstrumentedAsyncRwLock<HashMap<u64, Arc<FileHandle<Client, Prefetcher>>>>,
+    /// The number of entries in [Self::dir_handles] and [Self::file_handles]
+    open_handles: OpenHandles,
+    directory_poller: Option<DirectoryPoller>,
+    notifier: NotifierSlot,
+    /// Names the kernel may be caching as missing, see [S3FilesystemConfig::negative_entry_replies]
+    negative_replies: Arc<NegativeReplies>,
+    circuit_breaker: Arc<CircuitBreaker>,
+    /// Breaker for metadata requests, which is [Self::circuit_breaker] unless they're tracked
+    /// separately. See [S3FilesystemConfig::metadata_circuit_breaker].
+    metadata_breaker: Arc<CircuitBreaker>,
+    /// Listing manifest still to be loaded, on the first operation that could use it
+    pending_bootstrap: AsyncMutex<Option<ListingBootstrap>>,
+    bootstrap_pending: AtomicBool,
+    /// Read state shared by the read handles open on each inode
+    shared_reads: Mutex<HashMap<InodeNo, SharedReadEntry<Client, Prefetcher>>>,
+    /// The read streams the [S3FilesystemConfig::idle_read_buffer_policy] applies to
+    idle_reads: Arc<IdleReads>,
+    /// Releases the prefetched data of read streams that have paused, unless the policy is to keep it
+    idle_read_sweeper: Option<IdleReadSweeper>,
+    /// Data recently written to each inode that's being uploaded, see
+    /// [S3FilesystemConfig::local_read_window]
+    local_writes: Mutex<HashMap<InodeNo, Arc<LocalWrite>>>,
+    /// Block size reported in attributes: [S3FilesystemConfig::block_size], capped once the
+    /// kernel's limits are known
+    block_size: AtomicU32,
+    /// Reads served through the read handles released so far
+    read_handle_stats: Mutex<ReadHandleStats>,
+    /// Keys of unlinked files still to be deleted, see [S3FilesystemConfig::unlink_batch_delay]
+    pending_unlinks: Option<Arc<PendingUnlinks>>,
+    /// Deletes the objects of unlinked files once they've waited long enough
+    unlink_flusher: Option<UnlinkFlusher>,
 }
 
 impl<Client, Prefetcher> S3Filesystem<Client, Prefetcher>
@@ -510,6 +1366,9 @@ where
     Client: ObjectClient + Send + Sync + 'static,
     Prefetcher: Prefetch,
 {
+    /// Create a new file system. This makes no requests to S3: the root directory is created
+    /// locally, and anything that needs S3 (like loading a listing manifest) is deferred until the
+    /// first file system operation.
     pub fn new(
         client: Client,
         prefetcher: Prefetcher,
@@ -519,20 +1378,110 @@ where
     ) -> Self {
         trace!(?bucket, ?prefix, ?config, "new filesystem");
 
+        let staging_prefix = config
+            .upload_staging_directory
+            .as_ref()
+            .map(|dir| format!("{prefix}{dir}/"));
+
+        let soft_missing_paths = build_glob_set(&config.soft_missing_paths, "soft missing paths");
+        let executable_paths = build_glob_set(&config.executable_paths, "executable paths");
+
+        let circuit_breaker = Arc::new(CircuitBreaker::new(
+            config.circuit_breaker.clone(),
+            config.clock.clone(),
+        ));
+        let metadata_breaker = match &config.metadata_circuit_breaker {
+            Some(metadata_config) => Arc::new(CircuitBreaker::new(Some(metadata_config.clone()), config.clock.clone())),
+            None => circuit_breaker.clone(),
+        };
+
         let superblock_config = SuperblockConfig {
             cache_config: config.cache_config.clone(),
             s3_personality: config.s3_personality,
+            readdir_rewind_mode: config.readdir_rewind_mode,
+            max_listing_depth: config.max_listing_depth,
+            max_buffered_dir_entries: config.max_buffered_dir_entries,
+            hidden_prefix: staging_prefix.clone(),
+            hide_unpublished_files: staging_prefix.is_some(),
+            soft_missing_paths,
+            path_rules: PathRules::new(
+                config
+                    .path_rules
+                    .iter()
+                    .map(|(pattern, overrides)| (format!("{prefix}{}", pattern.as_str()), *overrides)),
+            ),
+            immutable_prefixes: config
+                .immutable_key_patterns
+                .iter()
+                .map(|pattern| format!("{prefix}{}", pattern.as_str()))
+                .collect(),
+            key_failures: config.key_failures.clone(),
+            clock: config.clock.clone(),
+            metadata_breaker: metadata_breaker.clone(),
+            serve_stale_metadata: config.metadata_circuit_breaker.is_some(),
         };
         let superblock = Superblock::new(bucket, prefix, superblock_config);
 
         let client = Arc::new(client);
 
+        let pending_bootstrap = config.listing_bootstrap.clone();
+        let bootstrap_pending = AtomicBool::new(pending_bootstrap.is_some());
+
+        let notifier = NotifierSlot::default();
+        let negative_replies = Arc::new(NegativeReplies::new(config.cache_config.negative_cache_size));
+
+        let sweep_interval = config.cache_config.cache_sweep_interval;
+        let directory_poller = (config.directory_poll_interval.is_some() || sweep_interval.is_some()).then(|| {
+            let on_change = {
+                let notifier = notifier.clone();
+                let negative_replies = negative_replies.clone();
+                move |dir, expired: &[String]| invalidate_changed_directory(&notifier, &negative_replies, dir, expired)
+            };
+            superblock.start_directory_poller(
+                client.clone(),
+                config.directory_poll_interval,
+                sweep_interval,
+                on_change,
+            )
+        });
+
         let uploader = Uploader::new(
             client.clone(),
             config.storage_class.to_owned(),
             config.server_side_encryption.clone(),
             config.use_upload_checksums,
-        );
+            config.require_content_md5,
+        )
+        .with_staging_prefix(staging_prefix)
+        .with_memory_limiter(config.memory_limiter.clone());
+        // The kernel's writeback cache can flush pages out of order, but not by much
+        let uploader = if config.writeback_cache {
+            let part_size = client.part_size().unwrap_or(DEFAULT_WRITEBACK_REORDER_WINDOW);
+            uploader.with_reorder_window(part_size as u64)
+        } else {
+            uploader
+        };
+
+        let block_size = AtomicU32::new(config.block_size);
+
+        let idle_reads: Arc<IdleReads> = Default::default();
+        let idle_read_sweeper = (config.idle_read_buffer_policy != IdleBufferPolicy::Retain).then(|| {
+            IdleReadSweeper::start(
+                idle_reads.clone(),
+                config.idle_read_buffer_policy,
+                config.idle_read_buffer_timeout,
+                config.clock.clone(),
+            )
+        });
+
+        let (pending_unlinks, unlink_flusher) = match config.unlink_batch_delay {
+            Some(delay) => {
+                let pending = Arc::new(PendingUnlinks::new(config.clock.clone()));
+                let flusher = UnlinkFlusher::start(pending.clone(), client.clone(), bucket.to_owned(), delay);
+                (Some(pending), Some(flusher))
+            }
+            None => (None, None),
+        };
 
         Self {
             config,
@@ -542,62 +1491,387 @@ where
             uploader,
             bucket: bucket.to_string(),
             prefix: prefix.clone(),
+            executable_paths,
             next_handle: AtomicU64::new(1),
-            dir_handles: AsyncRwLock::new(HashMap::new()),
-            file_handles: AsyncRwLock::new(HashMap::new()),
+            dir_handles: InstrumentedAsyncRwLock::new("dir_handles", HashMap::new()),
+            file_handles: InstrumentedAsyncRwLock::new("file_handles", HashMap::new()),
+            open_handles: Default::default(),
+            directory_poller,
+            notifier,
+            negative_replies,
+            circuit_breaker,
+            metadata_breaker,
+            pending_bootstrap: AsyncMutex::new(pending_bootstrap),
+            bootstrap_pending,
+            shared_reads: Default::default(),
+            idle_reads,
+            idle_read_sweeper,
+            local_writes: Default::default(),
+            block_size,
+            read_handle_stats: Default::default(),
+            pending_unlinks,
+            unlink_flusher,
         }
     }
 
-    fn next_handle(&self) -> u64 {
-        self.next_handle.fetch_add(1, Ordering::SeqCst)
+    /// Load the listing manifest, if there is one and we haven't already. Loading can fail, in
+    /// which case we fall back to listing from S3 as if there were no manifest.
+    async fn ensure_bootstrapped(&self) {
+        if !self.bootstrap_pending.load(Ordering::Acquire) {
+            return;
+        }
+        let mut pending = self.pending_bootstrap.lock().await;
+        let Some(bootstrap) = pending.take() else {
+            return;
+        };
+        match manifest::load_manifest(&*self.client, &self.bucket, &bootstrap).await {
+            Ok(objects) => self.superblock.bootstrap_listings(objects),
+            Err(e) => warn!(
+                ?bootstrap,
+                "failed to load listing manifest, listing from S3 instead: {e:?}"
+            ),
+        }
+        self.bootstrap_pending.store(false, Ordering::Release);
     }
-}
 
-/// Reply to a `lookup` call
-#[derive(Debug)]
-pub struct Entry {
-    pub ttl: Duration,
-    pub attr: FileAttr,
-    pub generation: u64,
-}
+    /// Get the read state for a new read handle on an inode, starting a new one unless another
+    /// handle is already reading the same version of the object
+    fn acquire_shared_read(&self, ino: InodeNo, object_size: u64, etag: String) -> Arc<SharedRead<Client, Prefetcher>> {
+        let mut shared_reads = self.shared_reads.lock().unwrap();
+        if let Some(entry) = shared_reads.get_mut(&ino) {
+            if entry.etag == etag {
+                entry.handles += 1;
+                trace!(ino, handles = entry.handles, "sharing read state with open handles");
+                return entry.read.clone();
+            }
+        }
 
-/// Reply to a `getattr` call
-#[derive(Debug)]
-pub struct Attr {
-    pub ttl: Duration,
-    pub attr: FileAttr,
-}
+        let read = Arc::new(SharedRead::new(etag.clone(), object_size));
+        // Handles still reading an older version of the object keep their own state, but new
+        // handles won't share it
+        shared_reads.insert(
+            ino,
+            SharedReadEntry {
+                read: read.clone(),
+                etag,
+                handles: 1,
+            },
+        );
+        read
+    }
 
-/// Reply to a `open` or `opendir` call
-#[derive(Debug)]
-pub struct Opened {
-    pub fh: u64,
-    pub flags: u32,
-}
+    /// Give up a read handle's reference to its read state, freeing the state if it was the last
+    fn release_shared_read(&self, ino: InodeNo, read: Arc<SharedRead<Client, Prefetcher>>) {
+        let mut shared_reads = self.shared_reads.lock().unwrap();
+        let Some(entry) = shared_reads.get_mut(&ino) else {
+            return;
+        };
+        if !Arc::ptr_eq(&entry.read, &read) {
+            return;
+        }
+        entry.handles -= 1;
+        if entry.handles == 0 {
+            shared_reads.remove(&ino);
+        }
+    }
 
-/// Reply to a `readdir` or `readdirplus` call
-pub trait DirectoryReplier {
-    /// Add a new dentry to the reply. Returns true if the buffer was full and so the entry was not
-    /// added.
-    fn add(&mut self, entry: DirectoryEntry) -> bool;
-}
+    /// The data recently written to an inode that's being uploaded, if we're keeping it
+    fn local_write(&self, ino: InodeNo) -> Option<Arc<LocalWrite>> {
+        let local_writes = self.local_writes.lock().unwrap();
+        local_writes.get(&ino).cloned()
+    }
 
-#[derive(Debug, Clone)]
-pub struct DirectoryEntry {
-    pub ino: u64,
-    pub offset: i64,
-    pub name: OsString,
-    pub attr: FileAttr,
-    pub generation: u64,
-    pub ttl: Duration,
-    lookup: LookedUp,
-}
+    fn next_handle(&self) -> u64 {
+        self.next_handle.fetch_add(1, Ordering::SeqCst)
+    }
 
-impl<Client, Prefetcher> S3Filesystem<Client, Prefetcher>
-where
-    Client: ObjectClient + Send + Sync + 'static,
-    Prefetcher: Prefetch,
-{
+    /// How much this file system's metadata caches currently hold. Each file system has its own
+    /// caches, even if it shares its client with others.
+    pub fn metadata_cache_stats(&self) -> MetadataCacheStats {
+        self.superblock.cache_stats()
+    }
+
+    /// How many reads reached this file system through the read handles it has released, split
+    /// by whether the handles bypassed the kernel's page cache. Comparing the two shows how many
+    /// reads the page cache absorbed.
+    pub fn read_handle_stats(&self) -> ReadHandleStats {
+        *self.read_handle_stats.lock().unwrap()
+    }
+
+    /// How much data has been prefetched for an open read handle, and how much it has fetched from
+    /// S3. Read handles open on the same file share their prefetched data, and so their stats.
+    /// Handles of files being written, or of objects of unknown size, don't prefetch.
+    pub async fn prefetch_stats(&self, fh: u64) -> Result<PrefetchStats, Error> {
+        let handle = {
+            let file_handles = self.file_handles.read().await;
+            match file_handles.get(&fh) {
+                Some(handle) => handle.clone(),
+                None => return Err(err!(libc::EBADF, "invalid file handle")),
+            }
+        };
+        let shared = match &*handle.state.lock().await {
+            FileHandleState::Read(shared) => shared.clone(),
+            FileHandleState::ReadUnknownLength(_) | FileHandleState::ReadLocal(_) => {
+                return Ok(PrefetchStats::default())
+            }
+            FileHandleState::Write(_) | FileHandleState::PartialWrite(_) | FileHandleState::Path => {
+                return Err(err!(libc::EBADF, "file handle is not open for reads"))
+            }
+        };
+        Ok(shared.stats().await)
+    }
+
+    /// Apply the [S3FilesystemConfig::idle_read_buffer_policy] to the read streams that have paused
+    /// for long enough, as the background sweeper does. Returns how many released data.
+    pub fn release_idle_reads(&self) -> usize {
+        if self.idle_read_sweeper.is_none() {
+            return 0;
+        }
+        self.idle_reads.release(
+            self.config.idle_read_buffer_policy,
+            self.config.idle_read_buffer_timeout,
+            self.config.clock.now(),
+        )
+    }
+
+    /// Report how many bytes the application read through an open handle, including the reads the
+    /// kernel served from its page cache, which we never see. When the handle is released, the
+    /// difference from the bytes we served counts towards [ReadCounters::page_cache_bytes]. Without
+    /// a report, we assume the application read everything up to the furthest offset we served.
+    pub async fn report_application_reads(&self, fh: u64, bytes: u64) -> Result<(), Error> {
+        let handle = {
+            let file_handles = self.file_handles.read().await;
+            match file_handles.get(&fh) {
+                Some(handle) => handle.clone(),
+                None => return Err(err!(libc::EBADF, "invalid file handle")),
+            }
+        };
+        handle.reads.lock().unwrap().reported_bytes = Some(bytes);
+        Ok(())
+    }
+
+    /// Add a released read handle's reads to the file system's [ReadHandleStats] and metrics.
+    ///
+    /// We can't see the reads the kernel served from its page cache, but can estimate them: the
+    /// bytes the application read through a page-cache handle that we didn't serve came from the
+    /// cache.
+    fn record_handle_reads(&self, ino: InodeNo, direct_io: bool, reads: &HandleReads) {
+        let cache = if direct_io { "direct_io" } else { "page_cache" };
+        let application_bytes = reads.application_bytes();
+        let page_cache_bytes = if direct_io {
+            0
+        } else {
+            application_bytes.saturating_sub(reads.bytes)
+        };
+        debug!(
+            ino,
+            cache,
+            reads = reads.count,
+            bytes = reads.bytes,
+            max_end = reads.max_end,
+            application_bytes,
+            page_cache_bytes,
+            "read handle released"
+        );
+        metrics::histogram!("fs.read_handle.reads", "cache" => cache).record(reads.count as f64);
+        metrics::counter!("fs.read_handle.bytes", "cache" => cache).increment(reads.bytes);
+        metrics::counter!("fs.read_handle.page_cache_bytes").increment(page_cache_bytes);
+        if !direct_io && application_bytes > 0 {
+            metrics::histogram!("fs.read_handle.likely_page_cache_pct")
+                .record(page_cache_bytes as f64 * 100.0 / application_bytes as f64);
+        }
+
+        let mut stats = self.read_handle_stats.lock().unwrap();
+        let counters = if direct_io {
+            &mut stats.direct_io
+        } else {
+            &mut stats.page_cache
+        };
+        counters.handles += 1;
+        counters.reads += reads.count;
+        counters.bytes += reads.bytes;
+        counters.spanned_bytes += reads.max_end;
+        counters.application_bytes += application_bytes;
+        counters.page_cache_bytes += page_cache_bytes;
+    }
+
+    /// The slot for this file system's [KernelNotifier], to be set once the FUSE session exists
+    pub fn notifier_slot(&self) -> NotifierSlot {
+        self.notifier.clone()
+    }
+
+    /// The count of handles this file system has open, which stays up to date
+    pub fn open_handles(&self) -> OpenHandles {
+        self.open_handles.clone()
+    }
+
+    /// Tell the kernel to drop a negative entry for `name` in `parent`, which has just been created,
+    /// if it may still be caching one
+    fn invalidate_negative_entry(&self, parent: InodeNo, name: &OsStr) {
+        if !self.negative_replies.remove(parent, name) {
+            return;
+        }
+        if let Some(notifier) = self.notifier.get() {
+            trace!(?parent, ?name, "invalidating negative kernel entry");
+            notifier.invalidate_entry(parent, name);
+        }
+    }
+
+    /// Tell the kernel to drop its cached entry for `name` in `parent`. Must only be called after
+    /// the change to the entry has succeeded.
+    fn invalidate_kernel_entry(&self, parent: InodeNo, name: &OsStr) {
+        if !self.config.invalidate_kernel_entries {
+            return;
+        }
+        if let Some(notifier) = self.notifier.get() {
+            trace!(?parent, ?name, "invalidating kernel entry");
+            notifier.invalidate_entry(parent, name);
+        }
+    }
+}
+
+/// What a file system's metadata caches currently hold, see [S3Filesystem::metadata_cache_stats]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetadataCacheStats {
+    /// Number of inodes the file system knows about, including the root
+    pub inodes: usize,
+    /// Number of entries in the negative cache, including expired ones not yet removed
+    pub negative_cache_entries: usize,
+    /// Approximate memory used by the negative cache's entries
+    pub negative_cache_bytes: usize,
+    /// Total number of entries across the pinned directory listings
+    pub pinned_listing_entries: usize,
+    /// Approximate memory used by the pinned directory listings
+    pub pinned_listing_bytes: usize,
+}
+
+/// Reads served through a file system's released read handles, see
+/// [S3Filesystem::read_handle_stats]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReadHandleStats {
+    /// Handles opened with `FOPEN_DIRECT_IO`, which see every read the application makes
+    pub direct_io: ReadCounters,
+    /// Handles whose reads go through the kernel's page cache, which only see the reads it missed
+    pub page_cache: ReadCounters,
+}
+
+/// Totals over a set of released read handles, see [ReadHandleStats]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReadCounters {
+    /// Number of handles
+    pub handles: u64,
+    /// Number of reads that reached the file system
+    pub reads: u64,
+    /// Bytes returned by those reads
+    pub bytes: u64,
+    /// Sum over the handles of the furthest offset any of their reads reached
+    pub spanned_bytes: u64,
+    /// Bytes the application read through the handles, as reported with
+    /// [S3Filesystem::report_application_reads], or the furthest offset reached for handles
+    /// without a report
+    pub application_bytes: u64,
+    /// Bytes of [application_bytes](Self::application_bytes) the kernel likely served from its
+    /// page cache, because they never reached the file system. Always 0 for direct I/O handles.
+    pub page_cache_bytes: u64,
+}
+
+/// Reply to a `lookup` call
+#[derive(Debug)]
+pub struct Entry {
+    pub ttl: Duration,
+    pub attr: FileAttr,
+    pub generation: u64,
+}
+
+/// Outcome of a [S3Filesystem::lookup_entry] of a name, if the lookup didn't fail
+#[derive(Debug)]
+pub enum LookupResult {
+    /// The name exists
+    Found(Entry),
+    /// The name doesn't exist, and the kernel may cache that for this long
+    NegativeCached(Duration),
+}
+
+/// Reply to a `getattr` call
+#[derive(Debug)]
+pub struct Attr {
+    pub ttl: Duration,
+    pub attr: FileAttr,
+}
+
+/// The number of file and directory handles a [S3Filesystem] has open. Clones share the count.
+#[derive(Debug, Clone, Default)]
+pub struct OpenHandles(Arc<AtomicU64>);
+
+impl OpenHandles {
+    /// Handles opened and not released yet
+    pub fn count(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn opened(&self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(crate) fn released(&self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Reply to a `open` or `opendir` call
+#[derive(Debug)]
+pub struct Opened {
+    pub fh: u64,
+    pub flags: u32,
+}
+
+/// Reply to a `statfs` call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatFs {
+    /// Total data blocks in the file system
+    pub total_blocks: u64,
+    /// Free blocks in the file system
+    pub free_blocks: u64,
+    /// Free blocks available to unprivileged users
+    pub available_blocks: u64,
+    /// Total inodes in the file system
+    pub total_inodes: u64,
+    /// Free inodes in the file system
+    pub free_inodes: u64,
+    /// Optimal transfer block size
+    pub block_size: u32,
+    /// Maximum length of file names, in bytes
+    pub maximum_name_length: u32,
+    /// Fragment size
+    pub fragment_size: u32,
+}
+
+/// Reply to a `readdir` or `readdirplus` call
+pub trait DirectoryReplier {
+    /// Add a new dentry to the reply. Returns true if the buffer was full and so the entry was not
+    /// added.
+    fn add(&mut self, entry: DirectoryEntry) -> bool;
+}
+
+#[derive(Debug, Clone)]
+pub struct DirectoryEntry {
+    pub ino: u64,
+    pub offset: i64,
+    pub name: OsString,
+    /// Type to report for the entry in `readdir` replies, or `None` for `DT_UNKNOWN`
+    pub dtype: Option<FileType>,
+    pub attr: FileAttr,
+    pub generation: u64,
+    pub ttl: Duration,
+    lookup: LookedUp,
+}
+
+impl<Client, Prefetcher> S3Filesystem<Client, Prefetcher>
+where
+    Client: ObjectClient + Send + Sync + 'static,
+    Prefetcher: Prefetch,
+{
+    #[cfg(feature = "fuse")]
     pub async fn init(&self, config: &mut KernelConfig) -> Result<(), libc::c_int> {
         let _ = config.add_capabilities(fuser::consts::FUSE_DO_READDIRPLUS);
         if self.config.allow_overwrite {
@@ -610,16 +1884,61 @@ where
                 .add_capabilities(fuser::consts::FUSE_ATOMIC_O_TRUNC)
                 .expect("The host must support FUSE_ATOMIC_O_TRUNC capability in order to allow overwrites");
         }
+        if self.config.writeback_cache {
+            if let Err(unsupported) = config.add_capabilities(fuser::consts::FUSE_WRITEBACK_CACHE) {
+                warn!(
+                    ?unsupported,
+                    "the kernel doesn't support the writeback cache, writes won't be cached"
+                );
+            }
+        }
+        self.limit_block_size(config.max_readahead());
         Ok(())
     }
 
+    /// Cap the block size reported in attributes at the largest read the kernel will send
+    #[cfg(feature = "fuse")]
+    fn limit_block_size(&self, max_read: u32) {
+        let max_read = max_read.max(1);
+        let previous = self.block_size.fetch_min(max_read, Ordering::Relaxed);
+        if previous > max_read {
+            debug!(
+                block_size = previous,
+                max_read, "limiting reported block size to kernel's max read"
+            );
+        }
+    }
+
+    /// Permissions of a readable file: the configured file mode, made executable if the file
+    /// matches [S3FilesystemConfig::executable_paths]
+    fn file_mode(&self, inode: &Inode) -> u16 {
+        let mode = self.config.file_mode;
+        let Some(executable_paths) = &self.executable_paths else {
+            return mode;
+        };
+        let path = inode
+            .full_key()
+            .strip_prefix(self.prefix.as_str())
+            .unwrap_or(inode.full_key());
+        if executable_paths.is_match(path) {
+            mode | ((mode & 0o444) >> 2)
+        } else {
+            mode
+        }
+    }
+
+    /// The encoding reads of this object should be decompressed from, if any
+    fn decompressed_encoding(&self, stat: &InodeStat) -> Option<ContentEncoding> {
+        if !self.config.transparent_decompress {
+            return None;
+        }
+        stat.content_encoding.as_deref().and_then(ContentEncoding::from_header)
+    }
+
     fn make_attr(&self, lookup: &LookedUp) -> FileAttr {
         /// From man stat(2): `st_blocks`: "This field indicates the number of blocks allocated to
         /// the file, in 512-byte units."
         const STAT_BLOCK_SIZE: u64 = 512;
-        /// From man stat(2): `st_blksize`: "This field gives the "preferred" block size for
-        /// efficient filesystem I/O."
-        const PREFERRED_IO_BLOCK_SIZE: u32 = 4096;
 
         // We don't implement hard links, and don't want to have to list a directory to count its
         // hard links, so we just assume one link for files (itself) and two links for directories
@@ -627,7 +1946,7 @@ where
         let (perm, nlink) = match lookup.inode.kind() {
             InodeKind::File => {
                 if lookup.stat.is_readable {
-                    (self.config.file_mode, 1)
+                    (self.file_mode(&lookup.inode), 1)
                 } else {
                     (0o000, 1)
                 }
@@ -635,10 +1954,16 @@ where
             InodeKind::Directory => (self.config.dir_mode, 2),
         };
 
+        let size = if lookup.stat.unknown_size || self.decompressed_encoding(&lookup.stat).is_some() {
+            self.config.unknown_object_size
+        } else {
+            lookup.stat.size as u64
+        };
+
         FileAttr {
             ino: lookup.inode.ino(),
-            size: lookup.stat.size as u64,
-            blocks: (lookup.stat.size as u64 + STAT_BLOCK_SIZE - 1) / STAT_BLOCK_SIZE,
+            size,
+            blocks: (size + STAT_BLOCK_SIZE - 1) / STAT_BLOCK_SIZE,
             atime: lookup.stat.atime.into(),
             mtime: lookup.stat.mtime.into(),
             ctime: lookup.stat.ctime.into(),
@@ -650,61 +1975,231 @@ where
             gid: self.config.gid,
             rdev: 0,
             flags: 0,
-            blksize: PREFERRED_IO_BLOCK_SIZE,
+            // From man stat(2): `st_blksize`: "This field gives the "preferred" block size for
+            // efficient filesystem I/O."
+            blksize: self.block_size.load(Ordering::Relaxed),
         }
     }
 
+    /// Whether a metadata request refused by the breaker should be served from the cache, even if
+    /// what's cached has expired. Only when [S3FilesystemConfig::metadata_circuit_breaker] is set;
+    /// otherwise the request fails with `EAGAIN` like any other.
+    fn serves_stale(&self, result: &Result<LookedUp, InodeError>) -> bool {
+        matches!(result, Err(InodeError::MetadataUnavailable(_))) && self.config.metadata_circuit_breaker.is_some()
+    }
+
+    /// Serve cached metadata that may have expired, while metadata requests to S3 are failing
+    fn serve_stale(&self, lookup: LookedUp) -> LookedUp {
+        let stale = lookup.validity(self.superblock.now()).is_zero();
+        trace!(
+            ino = lookup.inode.ino(),
+            stale,
+            "serving cached metadata while S3 metadata requests are failing"
+        );
+        metrics::counter!("fs.metadata_circuit_breaker.stale_served").increment(stale.into());
+        lookup
+    }
+
     pub async fn lookup(&self, parent: InodeNo, name: &OsStr) -> Result<Entry, Error> {
         trace!("fs:lookup with parent {:?} name {:?}", parent, name);
 
-        let lookup = self
-            .superblock
-            .lookup(&self.client, parent, name)
-            .await
-            .map_err(|err| match err {
-                InodeError::FileDoesNotExist(_, _) => {
-                    // Lookup returning ENOENT is common case, and we dont want to warn in case `FileDoesNotExist` within ENOENT
-                    err!(libc::ENOENT, source: err, Level::DEBUG, "file does not exist")
+        self.lookup_or_missing(parent, name).await?.map_err(file_does_not_exist)
+    }
+
+    /// Look up `name` in `parent` like [lookup](Self::lookup), but report a name that doesn't exist
+    /// as [LookupResult::NegativeCached] rather than `ENOENT`, when
+    /// [negative_entry_replies](S3FilesystemConfig::negative_entry_replies) are enabled. The caller
+    /// must pass that on to the kernel as a negative entry with the given TTL.
+    pub async fn lookup_entry(&self, parent: InodeNo, name: &OsStr) -> Result<LookupResult, Error> {
+        trace!("fs:lookup_entry with parent {:?} name {:?}", parent, name);
+
+        let err = match self.lookup_or_missing(parent, name).await? {
+            Ok(entry) => return Ok(LookupResult::Found(entry)),
+            Err(err) => err,
+        };
+        if !self.config.negative_entry_replies {
+            return Err(file_does_not_exist(err));
+        }
+        let ttl = self.superblock.negative_entry_ttl(parent, name)?;
+        if ttl.is_zero() {
+            return Err(file_does_not_exist(err));
+        }
+        self.negative_replies.insert(parent, name, ttl);
+        Ok(LookupResult::NegativeCached(ttl))
+    }
+
+    /// Look up `name` in `parent`. The inner result is the lookup's error if the name doesn't exist.
+    async fn lookup_or_missing(&self, parent: InodeNo, name: &OsStr) -> Result<Result<Entry, InodeError>, Error> {
+        self.ensure_bootstrapped().await;
+        if let Some(pending) = self.pending_unlinks.as_ref().filter(|pending| !pending.is_empty()) {
+            let parent_inode = self.superblock.inode(parent)?;
+            let key = format!("{}{}", parent_inode.full_key(), name.to_string_lossy());
+            if pending.contains(&key) {
+                let name = name.to_string_lossy().into_owned();
+                return Ok(Err(InodeError::FileDoesNotExist(name, parent_inode.err())));
+            }
+            // A directory whose files were all unlinked is only gone once their objects are
+            if pending.contains_prefix(&format!("{key}/")) {
+                self.flush_unlinks().await;
+            }
+        }
+        let result = self.superblock.lookup(&self.client, parent, name).await;
+        let lookup = match result {
+            Ok(lookup) => lookup,
+            Err(err @ InodeError::FileDoesNotExist(_, _)) => return Ok(Err(err)),
+            result if self.serves_stale(&result) => match self.superblock.stale_lookup(parent, name) {
+                Some(Ok(lookup)) => self.serve_stale(lookup),
+                // The name didn't exist when we last looked it up, so it's still missing for now
+                Some(Err(err)) => return Ok(Err(err)),
+                None => {
+                    return Err(err!(
+                        libc::EAGAIN,
+                        source: CircuitBreakerOpen,
+                        Level::DEBUG,
+                        "metadata requests to S3 are failing and {name:?} isn't cached"
+                    ))
                 }
-                _ => err.into(),
-            })?;
+            },
+            Err(err) => return Err(err.into()),
+        };
         let attr = self.make_attr(&lookup);
-        Ok(Entry {
-            ttl: lookup.validity(),
+        Ok(Ok(Entry {
+            ttl: lookup.validity(self.superblock.now()),
             attr,
             generation: 0,
-        })
+        }))
     }
 
     pub async fn getattr(&self, ino: InodeNo) -> Result<Attr, Error> {
         trace!("fs:getattr with ino {:?}", ino);
 
-        let lookup = self.superblock.getattr(&self.client, ino, false).await?;
+        let result = self.superblock.getattr(&self.client, ino, false).await;
+        let lookup = match result {
+            Ok(lookup) => lookup,
+            result if self.serves_stale(&result) => self.serve_stale(self.superblock.stale_getattr(ino)?),
+            Err(err) => {
+                // The inode was removed from the bucket, so the kernel shouldn't keep resolving
+                // its name to it
+                if let InodeError::FileDoesNotExist(name, parent) = &err {
+                    self.invalidate_kernel_entry(parent.0.ino(), name.as_ref());
+                }
+                return Err(err.into());
+            }
+        };
         let attr = self.make_attr(&lookup);
 
         Ok(Attr {
-            ttl: lookup.validity(),
+            ttl: lookup.validity(self.superblock.now()),
             attr,
         })
     }
 
+    /// Get file system statistics. S3 doesn't have a capacity to report, so the block and inode
+    /// counts are zero. The maximum name length is the one every operation enforces, for tools
+    /// that decide how to split up long names by it.
+    pub async fn statfs(&self, ino: InodeNo) -> Result<StatFs, Error> {
+        trace!("fs:statfs with ino {:?}", ino);
+
+        Ok(StatFs {
+            total_blocks: 0,
+            free_blocks: 0,
+            available_blocks: 0,
+            total_inodes: 0,
+            free_inodes: 0,
+            block_size: 512,
+            maximum_name_length: MAX_NAME_LEN as u32,
+            fragment_size: 0,
+        })
+    }
+
+    /// Get the value of an extended attribute. The only attribute is [ETAG_XATTR], on files that
+    /// have been uploaded to S3, and only if [S3FilesystemConfig::etag_xattr] is enabled.
+    pub async fn getxattr(&self, ino: InodeNo, name: &OsStr) -> Result<Vec<u8>, Error> {
+        trace!("fs:getxattr with ino {:?} name {:?}", ino, name);
+
+        let etag = self.etag_for_xattr(ino).await?;
+        match etag {
+            Some(etag) if name == ETAG_XATTR => Ok(etag.into_bytes()),
+            _ => Err(err!(ENOATTR, Level::DEBUG, "no extended attribute {:?}", name)),
+        }
+    }
+
+    /// List the names of the extended attributes of an inode, each followed by a NUL byte
+    pub async fn listxattr(&self, ino: InodeNo) -> Result<Vec<u8>, Error> {
+        trace!("fs:listxattr with ino {:?}", ino);
+
+        let mut names = Vec::new();
+        if self.etag_for_xattr(ino).await?.is_some() {
+            names.extend_from_slice(ETAG_XATTR.as_bytes());
+            names.push(0);
+        }
+        Ok(names)
+    }
+
+    /// The ETag to report in [ETAG_XATTR], if the inode has one. Like `open`, this asks S3 unless
+    /// lookups can be served from the cache, so that a changed object is noticed straight away.
+    async fn etag_for_xattr(&self, ino: InodeNo) -> Result<Option<String>, Error> {
+        if !self.config.etag_xattr {
+            return Err(err!(libc::ENOSYS, "extended attributes are not enabled"));
+        }
+        let force_revalidate = !self.superblock.serve_lookup_from_cache(ino);
+        let lookup = match self.superblock.getattr(&self.client, ino, force_revalidate).await {
+            result if self.serves_stale(&result) => self.serve_stale(self.superblock.stale_getattr(ino)?),
+            result => result?,
+        };
+        if lookup.inode.kind() != InodeKind::File || !lookup.inode.is_remote()? {
+            return Ok(None);
+        }
+        Ok(lookup.stat.etag)
+    }
+
+    #[allow(clippy::too_many_arguments)] // We don't get to choose this interface
     pub async fn setattr(
         &self,
         ino: InodeNo,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
         atime: Option<OffsetDateTime>,
         mtime: Option<OffsetDateTime>,
         size: Option<u64>,
         _flags: Option<u32>,
     ) -> Result<Attr, Error> {
         tracing::info!(
-            "fs:setattr with ino {:?} flags {:?} atime {:?} mtime {:?} size {:?}",
+            "fs:setattr with ino {:?} flags {:?} mode {:?} uid {:?} gid {:?} atime {:?} mtime {:?} size {:?}",
             ino,
             _flags,
+            mode,
+            uid,
+            gid,
             atime,
             mtime,
             size
         );
-        let setattr_result = self.superblock.setattr(&self.client, ino, atime, mtime).await;
+
+        let changes_permissions = mode.is_some() || uid.is_some() || gid.is_some();
+        if changes_permissions && self.config.permission_change_mode == PermissionChangeMode::Reject {
+            return Err(err!(
+                libc::EPERM,
+                "changing the mode or owner of files is not supported"
+            ));
+        }
+
+        let mut extended_remote = false;
+        if let Some(size) = size {
+            if !self.resize_written_file(ino, size).await? {
+                extended_remote = self.extend_remote_file(ino, size).await?;
+            }
+        }
+
+        let times_unchanged = atime.is_none() && mtime.is_none();
+        let setattr_result = if times_unchanged && (extended_remote || (changes_permissions && size.is_none())) {
+            // Only the mode or owner is changing, which we don't keep, or the object has already
+            // been extended, so there's nothing to update
+            self.superblock.getattr(&self.client, ino, false).await
+        } else {
+            self.superblock.setattr(&self.client, ino, atime, mtime).await
+        };
         let lookup = match (setattr_result, size) {
             (Ok(lookup), _) => lookup,
             (Err(InodeError::SetAttrNotPermittedOnRemoteInode(_)), Some(0)) if !self.config.allow_overwrite => {
@@ -716,10 +2211,20 @@ where
             }
             (Err(e), _) => return Err(e.into()),
         };
-        let attr = self.make_attr(&lookup);
+        let mut attr = self.make_attr(&lookup);
+        // Report the requested mode and owner back, as if we'd changed them
+        if let Some(mode) = mode {
+            attr.perm = (mode & 0o7777) as u16;
+        }
+        if let Some(uid) = uid {
+            attr.uid = uid;
+        }
+        if let Some(gid) = gid {
+            attr.gid = gid;
+        }
 
         Ok(Attr {
-            ttl: lookup.validity(),
+            ttl: lookup.validity(self.superblock.now()),
             attr,
         })
     }
@@ -729,6 +2234,11 @@ where
         self.superblock.forget(ino, n);
     }
 
+    pub async fn forget_multi(&self, nodes: &[(InodeNo, u64)]) {
+        trace!("fs:forget_multi with {} inodes", nodes.len());
+        self.superblock.forget_multi(nodes);
+    }
+
     pub async fn open(&self, ino: InodeNo, flags: i32, pid: u32) -> Result<Opened, Error> {
         trace!("fs:open with ino {:?} flags {:#b} pid {:?}", ino, flags, pid);
 
@@ -737,8 +2247,42 @@ where
         #[cfg(target_os = "linux")]
         let direct_io = flags & libc::O_DIRECT != 0;
 
-        let force_revalidate = !self.config.cache_config.serve_lookup_from_cache || direct_io;
-        let lookup = self.superblock.getattr(&self.client, ino, force_revalidate).await?;
+        #[cfg(not(target_os = "linux"))]
+        let path_only = false;
+        #[cfg(target_os = "linux")]
+        let path_only = flags & libc::O_PATH != 0;
+
+        // Only HeadObject tells us an object's `Content-Encoding`, so don't trust a cached stat that
+        // might have come from a listing if we might need to decompress it
+        let force_revalidate = !self.superblock.serve_lookup_from_cache(ino) || self.config.transparent_decompress;
+        let result = if direct_io {
+            self.superblock.getattr_fresh(&self.client, ino).await
+        } else {
+            self.superblock.getattr(&self.client, ino, force_revalidate).await
+        };
+        let lookup = match result {
+            // Reads still go to S3, and fail if the object changed since it was last looked up
+            result if self.serves_stale(&result) => self.serve_stale(self.superblock.stale_getattr(ino)?),
+            result => result?,
+        };
+
+        // An `O_PATH` handle only refers to the file (or directory), so ignores every other flag
+        if path_only {
+            let fh = self.next_handle();
+            let handle = FileHandle {
+                inode: lookup.inode.clone(),
+                full_key: lookup.inode.full_key().to_owned(),
+                state: AsyncMutex::new(FileHandleState::Path),
+                direct_io: false,
+                reads: Default::default(),
+                local_write: None,
+            };
+            debug!(fh, ino, "new O_PATH file handle created");
+            metrics::gauge!("fs.current_handles", "type" => "path").increment(1.0);
+            self.file_handles.write().await.insert(fh, Arc::new(handle));
+            self.open_handles.opened();
+            return Ok(Opened { fh, flags: 0 });
+        }
 
         match lookup.inode.kind() {
             InodeKind::Directory => return Err(InodeError::IsDirectory(lookup.inode.err()).into()),
@@ -773,19 +2317,42 @@ where
                 FileHandleState::new_read_handle(&lookup, self).await?
             }
         } else if flags & libc::O_WRONLY != 0 {
-            FileHandleState::new_write_handle(&lookup, lookup.inode.ino(), flags, pid, self).await?
+            if remote_file && flags & libc::O_TRUNC == 0 && self.config.allow_partial_writes {
+                debug!("fs:open choosing partial write handle for O_WRONLY without O_TRUNC");
+                FileHandleState::new_partial_write_handle(&lookup, lookup.inode.ino(), pid, self).await?
+            } else {
+                FileHandleState::new_write_handle(&lookup, lookup.inode.ino(), flags, pid, self).await?
+            }
         } else {
             FileHandleState::new_read_handle(&lookup, self).await?
         };
 
+        // The kernel would stop reading at the placeholder size of an object of unknown length, so
+        // send all reads to us instead, to find its end
+        let direct_io = direct_io || matches!(state, FileHandleState::ReadUnknownLength(_));
+        // Likewise for files being written, which the kernel would otherwise stop reading at the
+        // size they had when opened
+        let direct_io = direct_io || matches!(state, FileHandleState::ReadLocal(_));
+
         let fh = self.next_handle();
+        let local_write = if matches!(state, FileHandleState::Write(_)) && self.config.local_read_window > 0 {
+            let local_write = Arc::new(LocalWrite::new(self.config.local_read_window));
+            self.local_writes.lock().unwrap().insert(ino, local_write.clone());
+            Some(local_write)
+        } else {
+            None
+        };
         let handle = FileHandle {
             inode,
             full_key,
             state: AsyncMutex::new(state),
+            direct_io,
+            reads: Default::default(),
+            local_write,
         };
         debug!(fh, ino, "new file handle created");
         self.file_handles.write().await.insert(fh, Arc::new(handle));
+        self.open_handles.opened();
 
         let reply_flags = if direct_io { FOPEN_DIRECT_IO } else { 0 };
 
@@ -794,6 +2361,49 @@ where
 
     #[allow(clippy::too_many_arguments)] // We don't get to choose this interface
     pub async fn read(
+        &self,
+        ino: InodeNo,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        flags: i32,
+        lock: Option<u64>,
+    ) -> Result<Bytes, Error> {
+        let parts = self.read_vectored(ino, fh, offset, size, flags, lock).await?;
+        match <[Bytes; 1]>::try_from(parts) {
+            Ok([bytes]) => Ok(bytes),
+            Err(parts) => {
+                let mut bytes = BytesMut::with_capacity(parts.iter().map(Bytes::len).sum());
+                for part in parts {
+                    bytes.extend_from_slice(&part);
+                }
+                Ok(bytes.freeze())
+            }
+        }
+    }
+
+    /// Like [read](Self::read), but returns the data as the sequence of buffers it was prefetched
+    /// into, without copying it into a single buffer.
+    #[allow(clippy::too_many_arguments)] // We don't get to choose this interface
+    pub async fn read_vectored(
+        &self,
+        ino: InodeNo,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        flags: i32,
+        lock: Option<u64>,
+    ) -> Result<Vec<Bytes>, Error> {
+        let (parts, _source) = self
+            .read_vectored_with_source(ino, fh, offset, size, flags, lock)
+            .await?;
+        Ok(parts)
+    }
+
+    /// Like [read_vectored](Self::read_vectored), but also reports how much of the data was served
+    /// from the data cache, was already prefetched, or had to be fetched from S3 for this read.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn read_vectored_with_source(
         &self,
         ino: InodeNo,
         fh: u64,
@@ -801,7 +2411,7 @@ where
         size: u32,
         _flags: i32,
         _lock: Option<u64>,
-    ) -> Result<Bytes, Error> {
+    ) -> Result<(Vec<Bytes>, ReadSource), Error> {
         trace!(
             "fs:read with ino {:?} fh {:?} offset {:?} size {:?}",
             ino,
@@ -810,6 +2420,10 @@ where
             size
         );
 
+        if offset < 0 {
+            return Err(err!(libc::EINVAL, "negative offset"));
+        }
+
         let handle = {
             let file_handles = self.file_handles.read().await;
             match file_handles.get(&fh) {
@@ -818,26 +2432,160 @@ where
             }
         };
         logging::record_name(handle.inode.name());
+        let result = self.read_from_handle(&handle, offset, size).await;
+        if let Ok((parts, _)) = &result {
+            let len = parts.iter().map(Bytes::len).sum();
+            handle.reads.lock().unwrap().record(offset as u64, len);
+        }
+        result
+    }
+
+    /// Serve a read of `size` bytes at `offset` (which must not be negative) through `handle`
+    async fn read_from_handle(
+        &self,
+        handle: &FileHandle<Client, Prefetcher>,
+        offset: i64,
+        size: u32,
+    ) -> Result<(Vec<Bytes>, ReadSource), Error> {
         let mut state = handle.state.lock().await;
-        let request = match &mut *state {
-            FileHandleState::Read(request) => request,
-            FileHandleState::Write(_) => return Err(err!(libc::EBADF, "file handle is not open for reads")),
+        let shared = match &mut *state {
+            FileHandleState::Read(shared) => shared.clone(),
+            FileHandleState::ReadUnknownLength(request) => {
+                let permit = self.circuit_breaker.admit()?;
+                let result = request.read(offset as u64, size as usize).await;
+                permit.complete(&result);
+                let data = result?;
+                let source = ReadSource {
+                    fetched_bytes: data.len(),
+                    ..Default::default()
+                };
+                return Ok((vec![data], source));
+            }
+            FileHandleState::ReadLocal(local_write) => {
+                let local_write = local_write.clone();
+                drop(state);
+                return self.read_local(handle, &local_write, offset as u64, size).await;
+            }
+            FileHandleState::Write(_) | FileHandleState::PartialWrite(_) | FileHandleState::Path => {
+                return Err(err!(libc::EBADF, "file handle is not open for reads"))
+            }
         };
+        drop(state);
+        let etag = &shared.etag;
+        let stream = shared.stream_for_read(offset as u64, size as u64, |object_size| {
+            let request = self.prefetcher.prefetch(
+                self.client.clone(),
+                &self.bucket,
+                &handle.full_key,
+                object_size,
+                ETag::from_str(etag).expect("E-Tag should be set"),
+            );
+            let stream = Arc::new(AsyncMutex::new(ReadStream::new(
+                request,
+                object_size,
+                self.config.clock.now(),
+            )));
+            if self.idle_read_sweeper.is_some() {
+                self.idle_reads.register(stream.clone());
+            }
+            stream
+        });
+        let mut stream = stream.lock().await;
+        // The sweeper may not have got to this stream during a pause, so apply the policy now if it
+        // should have
+        let now = self.config.clock.now();
+        stream.release_if_idle(
+            self.config.idle_read_buffer_policy,
+            self.config.idle_read_buffer_timeout,
+            now,
+        );
+        stream.start_read(now);
+        let ReadStream {
+            request, object_size, ..
+        } = &mut *stream;
+
+        // Reads at or past the end of the object are at EOF. Check against the handle's size rather
+        // than leaving it to the prefetcher, so the answer doesn't depend on what it's fetched yet.
+        if offset as u64 >= *object_size {
+            return Ok((Vec::new(), ReadSource::default()));
+        }
 
-        match request.read(offset as u64, size as usize).await {
-            Ok(checksummed_bytes) => checksummed_bytes
-                .into_bytes()
-                .map_err(|e| err!(libc::EIO, source:e, "integrity error")),
+        let permit = self.circuit_breaker.admit()?;
+        let mut result = request.read_vectored_with_source(offset as u64, size as usize).await;
+        // The object is smaller than when the handle was opened, and this read reached past its new
+        // end: either S3 refused the range, maybe telling us the new size, or it returned fewer
+        // bytes than we asked for. Reads past the end are at EOF; others are retried up to it.
+        let shrunk_size = match &result {
+            Err(PrefetchReadError::GetRequestFailed(ObjectClientError::ServiceError(
+                GetObjectError::InvalidRange {
+                    object_size: actual_size,
+                },
+            ))) => Some(*actual_size),
+            Err(PrefetchReadError::GetRequestEndedEarly { .. }) => Some(None),
+            _ => None,
+        };
+        if let Some(actual_size) = shrunk_size {
+            let shrunk = self
+                .shrink_read_handle(handle, request, etag, object_size, actual_size)
+                .await;
+            if let Ok(new_size) = shrunk {
+                shared.shrink(new_size);
+            }
+            match shrunk {
+                Ok(new_size) if offset as u64 >= new_size => {
+                    let result = Ok((Vec::new(), ReadSource::default()));
+                    permit.complete(&result);
+                    return result;
+                }
+                Ok(_) => result = request.read_vectored_with_source(offset as u64, size as usize).await,
+                Err(e) => {
+                    let result = Err(e);
+                    permit.complete(&result);
+                    return result;
+                }
+            }
+        }
+        let result = match result {
+            Ok((parts, source)) => {
+                self.superblock.confirm_read(&handle.inode, etag);
+                parts
+                    .into_iter()
+                    .map(|part| part.into_bytes())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(|parts| (parts, source))
+                    .map_err(|e| err!(libc::EIO, source:e, "integrity error"))
+            }
             Err(PrefetchReadError::GetRequestFailed(ObjectClientError::ServiceError(
                 GetObjectError::PreconditionFailed,
-            ))) => Err(err!(libc::ESTALE, "object was mutated remotely")),
+            ))) => {
+                // Don't let a recent lookup of the old object hide the change
+                self.superblock.expire(&handle.inode);
+                Err(err!(libc::ESTALE, "object was mutated remotely"))
+            }
+            Err(PrefetchReadError::GetRequestFailed(ObjectClientError::ClientError(e)))
+                if self.client.is_access_denied(&e) =>
+            {
+                Err(err!(libc::EACCES, source:e, "get request was denied"))
+            }
+            Err(
+                e @ PrefetchReadError::GetRequestFailed(ObjectClientError::ServiceError(GetObjectError::NoSuchKey)),
+            ) => {
+                if self.replaced_by_directory(&handle.inode).await {
+                    Err(err!(libc::ESTALE, "object was replaced by a directory"))
+                } else {
+                    Err(err!(libc::EIO, source:e, "get request failed"))
+                }
+            }
             Err(PrefetchReadError::Integrity(e)) => Err(err!(libc::EIO, source:e, "integrity error")),
             Err(e @ PrefetchReadError::GetRequestFailed(_))
             | Err(e @ PrefetchReadError::GetRequestTerminatedUnexpectedly)
-            | Err(e @ PrefetchReadError::GetRequestReturnedWrongOffset { .. }) => {
-                Err(err!(libc::EIO, source:e, "get request failed"))
-            }
-        }
+            | Err(e @ PrefetchReadError::GetRequestEndedEarly { .. })
+            | Err(e @ PrefetchReadError::GetRequestPanicked(_))
+            | Err(e @ PrefetchReadError::GetRequestReturnedWrongOffset { .. })
+            | Err(e @ PrefetchReadError::PartMismatch(_)) => Err(err!(libc::EIO, source:e, "get request failed")),
+        };
+        permit.complete(&result);
+        result
     }
 
     pub async fn mknod(
@@ -856,26 +2604,30 @@ where
             ));
         }
 
+        self.flush_unlinks().await;
         let lookup = self
             .superblock
             .create(&self.client, parent, name, InodeKind::File)
             .await?;
+        self.invalidate_negative_entry(parent, name);
         let attr = self.make_attr(&lookup);
         Ok(Entry {
-            ttl: lookup.validity(),
+            ttl: lookup.validity(self.superblock.now()),
             attr,
             generation: 0,
         })
     }
 
     pub async fn mkdir(&self, parent: InodeNo, name: &OsStr, _mode: libc::mode_t, _umask: u32) -> Result<Entry, Error> {
+        self.flush_unlinks().await;
         let lookup = self
             .superblock
             .create(&self.client, parent, name, InodeKind::Directory)
             .await?;
+        self.invalidate_negative_entry(parent, name);
         let attr = self.make_attr(&lookup);
         Ok(Entry {
-            ttl: lookup.validity(),
+            ttl: lookup.validity(self.superblock.now()),
             attr,
             generation: 0,
         })
@@ -908,41 +2660,376 @@ where
                 None => return Err(err!(libc::EBADF, "invalid file handle")),
             }
         };
-        logging::record_name(handle.inode.name());
+        logging::record_name(handle.inode.name());
+
+        let (len, grown) = {
+            let mut state = handle.state.lock().await;
+            let request = match &mut *state {
+                FileHandleState::Read(_)
+                | FileHandleState::ReadUnknownLength(_)
+                | FileHandleState::ReadLocal(_)
+                | FileHandleState::Path => return Err(err!(libc::EBADF, "file handle is not open for writes")),
+                FileHandleState::PartialWrite(write) => return write.write(offset, data, &handle.full_key),
+                FileHandleState::Write(request) => request,
+            };
+
+            // Writing into zeros the file was extended with doesn't make it any bigger. Writes that
+            // are held back only reach the local write buffer once the writes before them arrive.
+            let size = request.size();
+            let committed = |offset: u64, data: &[u8]| {
+                if let Some(local_write) = &handle.local_write {
+                    local_write.buffer().write(offset, data);
+                }
+            };
+            let len = request.write(offset, data, &handle.full_key, committed).await?;
+            (len, request.size() - size)
+        };
+        handle.inode.inc_file_size(grown as usize);
+        Ok(len)
+    }
+
+    /// Preallocate space for a file that's open for writing, as `posix_fallocate` does before a
+    /// large sequential write. S3 has nothing to preallocate, so this just extends the file to
+    /// `offset + length` bytes if it's shorter, like [setattr](Self::setattr) does for `truncate`.
+    /// Later writes replace the zeros it's extended with as long as they stay sequential. Only
+    /// the default mode (0) is supported.
+    pub async fn fallocate(&self, ino: InodeNo, fh: u64, offset: i64, length: i64, mode: i32) -> Result<(), Error> {
+        trace!(ino, fh, offset, length, mode, "fs:fallocate");
+        if mode != 0 {
+            return Err(err!(libc::EOPNOTSUPP, "fallocate mode {mode:#x} is not supported"));
+        }
+        if offset < 0 || length <= 0 {
+            return Err(err!(libc::EINVAL, "invalid fallocate range"));
+        }
+        let size = (offset as u64)
+            .checked_add(length as u64)
+            .ok_or_else(|| err!(libc::EFBIG, "fallocate range is too large"))?;
+
+        let handle = {
+            let file_handles = self.file_handles.read().await;
+            match file_handles.get(&fh) {
+                Some(handle) => handle.clone(),
+                None => return Err(err!(libc::EBADF, "invalid file handle")),
+            }
+        };
+        logging::record_name(handle.inode.name());
+
+        let mut state = handle.state.lock().await;
+        let request = match &mut *state {
+            FileHandleState::Read(_)
+            | FileHandleState::ReadUnknownLength(_)
+            | FileHandleState::ReadLocal(_)
+            | FileHandleState::Path => return Err(err!(libc::EBADF, "file handle is not open for writes")),
+            FileHandleState::Write(UploadState::InProgress { request, .. }) => request,
+            FileHandleState::Write(UploadState::Completed) => {
+                return Err(err!(
+                    libc::EIO,
+                    "upload already completed for key {:?}",
+                    handle.full_key
+                ))
+            }
+            FileHandleState::Write(UploadState::Failed(e)) => {
+                return Err(err!(*e, "upload already aborted for key {:?}", handle.full_key))
+            }
+            FileHandleState::PartialWrite(_) => {
+                return Err(err!(libc::EOPNOTSUPP, "writes into existing files can't extend them"))
+            }
+        };
+        let current_size = request.size();
+        if size > current_size {
+            debug!(ino, size, current_size, "preallocating file with zeros");
+            request.extend_to(size)?;
+            if let Some(local_write) = &handle.local_write {
+                local_write.buffer().extend_to(size);
+            }
+            handle.inode.inc_file_size((size - current_size) as usize);
+        }
+        Ok(())
+    }
+
+    /// Change the size of a file that's open for writing to `size` bytes, as `truncate` does. Files
+    /// are extended with zeros, which are uploaded as the file is written or when it's flushed.
+    /// Files can only be shrunk as far as the data already uploaded, since that can't be taken
+    /// back. Returns whether the file is being written; if it isn't, this does nothing.
+    async fn resize_written_file(&self, ino: InodeNo, size: u64) -> Result<bool, Error> {
+        let handles: Vec<_> = self
+            .file_handles
+            .read()
+            .await
+            .values()
+            .filter(|handle| handle.inode.ino() == ino)
+            .cloned()
+            .collect();
+        for handle in handles {
+            let mut state = handle.state.lock().await;
+            if let FileHandleState::Write(UploadState::InProgress { request, .. }) = &mut *state {
+                let current_size = request.size();
+                if size > current_size {
+                    debug!(ino, size, current_size, "extending file with zeros");
+                    request.extend_to(size)?;
+                    if let Some(local_write) = &handle.local_write {
+                        local_write.buffer().extend_to(size);
+                    }
+                    handle.inode.inc_file_size((size - current_size) as usize);
+                } else if size < current_size && request.truncate_to(size) {
+                    debug!(ino, size, current_size, "truncating file being written");
+                    if let Some(local_write) = &handle.local_write {
+                        local_write.buffer().truncate_to(size);
+                    }
+                    handle.inode.dec_file_size((current_size - size) as usize);
+                }
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Extend the object of a remote file that isn't being written to `size` bytes with zeros, as
+    /// `truncate` does, if it's shorter. Read handles open on the file read the extended object from
+    /// then on. Returns whether the object was extended. Objects can't be shrunk, so otherwise this
+    /// does nothing and leaves it to the superblock to reject the change if it has to.
+    async fn extend_remote_file(&self, ino: InodeNo, size: u64) -> Result<bool, Error> {
+        let lookup = self.superblock.getattr(&self.client, ino, false).await?;
+        let object_size = lookup.stat.size as u64;
+        if lookup.inode.kind() != InodeKind::File
+            || !lookup.inode.is_remote()?
+            || lookup.stat.unknown_size
+            || size <= object_size
+        {
+            return Ok(false);
+        }
+        if !self.config.allow_overwrite {
+            return Err(err!(
+                libc::EPERM,
+                "file overwrite is disabled by default, you need to remount with --allow-overwrite flag to extend existing files"
+            ));
+        }
+        let Some(etag) = lookup.stat.etag.as_deref() else {
+            return Err(err!(libc::EBADF, "no E-Tag for inode {}", ino));
+        };
+        let etag = ETag::from_str(etag).expect("E-Tag should be set");
+
+        debug!(ino, size, object_size, "extending object with zeros");
+        self.uploader
+            .extend_object(&self.bucket, lookup.inode.full_key(), &etag, object_size, size)
+            .await?;
+        let lookup = self.superblock.getattr_fresh(&self.client, ino).await?;
+        if let Some(etag) = lookup.stat.etag.clone() {
+            self.switch_read_handles(ino, lookup.stat.size as u64, etag).await;
+        }
+        Ok(true)
+    }
+
+    /// Point the read handles open on an inode at a new version of its object that we uploaded
+    /// ourselves, so they don't fail as if it was changed remotely
+    async fn switch_read_handles(&self, ino: InodeNo, object_size: u64, etag: String) {
+        let handles: Vec<_> = self
+            .file_handles
+            .read()
+            .await
+            .values()
+            .filter(|handle| handle.inode.ino() == ino)
+            .cloned()
+            .collect();
+        for handle in handles {
+            let mut state = handle.state.lock().await;
+            if let FileHandleState::Read(shared) = &mut *state {
+                if shared.etag != etag {
+                    let read = self.acquire_shared_read(ino, object_size, etag.clone());
+                    let old = std::mem::replace(shared, read);
+                    self.release_shared_read(ino, old);
+                }
+            }
+        }
+    }
+
+    /// Called when a read of an open file found its object gone. Another writer may have deleted
+    /// `data.bin` and created `data.bin/part-0001`, in which case the key is now a directory and
+    /// the file handle can never read again. Probe for that, and if so expire the file's inode so
+    /// the next lookup finds the directory in its place.
+    async fn replaced_by_directory(&self, inode: &Inode) -> bool {
+        let prefix = format!("{}/", inode.full_key());
+        let Ok(permit) = self.metadata_breaker.admit() else {
+            return false;
+        };
+        let result = self
+            .client
+            .list_objects(&self.bucket, None, "/", 1, &prefix)
+            .await
+            .map_err(|e| err!(libc::EIO, source:e, "ListObjectsV2 failed"));
+        permit.complete(&result);
+        let result = match result {
+            Ok(result) => result,
+            Err(e) => {
+                debug!(
+                    key = ?inode.full_key(),
+                    error = ?e,
+                    "failed to check whether a missing object became a directory"
+                );
+                return false;
+            }
+        };
+        if result.objects.is_empty() && result.common_prefixes.is_empty() {
+            return false;
+        }
+        warn!(
+            key = ?inode.full_key(),
+            "object was replaced by a directory while open for reading; reopen it as a directory"
+        );
+        self.superblock.expire(inode);
+        true
+    }
 
-        let len = {
-            let mut state = handle.state.lock().await;
-            let request = match &mut *state {
-                FileHandleState::Read { .. } => return Err(err!(libc::EBADF, "file handle is not open for writes")),
-                FileHandleState::Write(request) => request,
+    /// Serve a read of a file that's being written through this mount. Reads within the window of
+    /// recently written data are served from it. Older data has already been uploaded, and S3 won't
+    /// serve the parts of an incomplete upload, so those reads wait for the upload to complete and
+    /// then fetch the range from the new object.
+    async fn read_local(
+        &self,
+        handle: &FileHandle<Client, Prefetcher>,
+        local_write: &LocalWrite,
+        offset: u64,
+        size: u32,
+    ) -> Result<(Vec<Bytes>, ReadSource), Error> {
+        let (data, file_size) = {
+            let buffer = local_write.buffer();
+            (buffer.read(offset, size as usize), buffer.size())
+        };
+        if let Some(data) = data {
+            let source = ReadSource {
+                buffered_bytes: data.len(),
+                ..Default::default()
             };
+            return Ok((vec![data], source));
+        }
+
+        debug!(key = ?handle.full_key, offset, "waiting for upload to read data outside the local read window");
+        if !local_write.wait_for_upload().await {
+            return Err(err!(
+                libc::EIO,
+                "upload failed, so data at offset {offset} can't be read"
+            ));
+        }
+        let end = offset.saturating_add(size as u64).min(file_size);
+        let permit = self.circuit_breaker.admit()?;
+        let result = self.get_range(&handle.full_key, offset..end).await;
+        permit.complete(&result);
+        let data = result?;
+        let source = ReadSource {
+            fetched_bytes: data.len(),
+            ..Default::default()
+        };
+        Ok((vec![data], source))
+    }
+
+    /// Download the given range of an object
+    async fn get_range(&self, key: &str, range: Range<u64>) -> Result<Bytes, Error> {
+        let mut data = BytesMut::with_capacity((range.end - range.start) as usize);
+        let request = self
+            .client
+            .get_object(&self.bucket, key, Some(range), None)
+            .await
+            .map_err(|e| err!(libc::EIO, source:e, "get request failed"))?;
+        pin_mut!(request);
+        while let Some((_offset, body)) = request
+            .try_next()
+            .await
+            .map_err(|e| err!(libc::EIO, source:e, "get request failed"))?
+        {
+            data.extend_from_slice(&body);
+        }
+        Ok(data.freeze())
+    }
 
-            request.write(offset, data, &handle.full_key).await?
+    /// Called when a read of an open file asked for a range past the end of its object, which must
+    /// have shrunk since the file was opened, without its ETag changing. Updates the handle and the
+    /// inode to the object's new size (`actual_size`, or from a new HeadObject if that's `None`),
+    /// and restarts the handle's prefetching within it. Returns the new size.
+    async fn shrink_read_handle(
+        &self,
+        handle: &FileHandle<Client, Prefetcher>,
+        request: &mut Prefetcher::PrefetchResult<Client>,
+        etag: &str,
+        object_size: &mut u64,
+        actual_size: Option<u64>,
+    ) -> Result<u64, Error> {
+        let new_size = match actual_size {
+            Some(size) => size,
+            None => {
+                // S3 didn't say how big the object is now, so ask
+                let lookup = self.superblock.getattr_fresh(&self.client, handle.inode.ino()).await?;
+                if lookup.stat.etag.as_deref() != Some(etag) {
+                    return Err(err!(libc::ESTALE, "object was mutated remotely"));
+                }
+                lookup.stat.size as u64
+            }
         };
-        handle.inode.inc_file_size(len as usize);
-        Ok(len)
+        if new_size >= *object_size {
+            return Err(err!(
+                libc::EIO,
+                "get request failed with an invalid range, but object is still {} bytes",
+                new_size
+            ));
+        }
+
+        warn!(
+            key = ?handle.full_key,
+            old_size = *object_size,
+            new_size,
+            "object shrank while open for reading"
+        );
+        *object_size = new_size;
+        *request = self.prefetcher.prefetch(
+            self.client.clone(),
+            &self.bucket,
+            &handle.full_key,
+            new_size,
+            ETag::from_str(etag).expect("E-Tag should be set"),
+        );
+        self.superblock.update_size(&handle.inode, etag, new_size);
+        Ok(new_size)
     }
 
     /// Creates a new ReaddirHandle for the provided parent and default page size
-    async fn readdir_handle(&self, parent: InodeNo) -> Result<ReaddirHandle, InodeError> {
-        self.superblock.readdir(&self.client, parent, 1000).await
+    async fn readdir_handle(&self, parent: InodeNo, options: DirOptions) -> Result<ReaddirHandle, InodeError> {
+        self.ensure_bootstrapped().await;
+        self.flush_unlinks().await;
+        self.superblock
+            .readdir_with_options(&self.client, parent, 1000, options.dirs_only)
+            .await
     }
 
+    /// Open a directory handle. Fails with `ENOTDIR`, without listing anything, if `parent` is a
+    /// file.
     pub async fn opendir(&self, parent: InodeNo, _flags: i32) -> Result<Opened, Error> {
         trace!("fs:opendir with parent {:?} flags {:#b}", parent, _flags);
+        self.opendir_with_options(parent, Default::default()).await
+    }
+
+    /// Open a directory handle with non-default [DirOptions]. FUSE `opendir` requests always use
+    /// the defaults.
+    pub async fn opendir_with_options(&self, parent: InodeNo, options: DirOptions) -> Result<Opened, Error> {
+        trace!("fs:opendir_with_options with parent {:?} options {:?}", parent, options);
+
+        let inode_handle = self.readdir_handle(parent, options).await?;
 
-        let inode_handle = self.readdir_handle(parent).await?;
+        if self.config.directory_poll_interval.is_some() {
+            self.superblock.watch_directory(parent)?;
+        }
 
         let fh = self.next_handle();
         let handle = DirHandle {
             ino: parent,
             handle: AsyncMutex::new(inode_handle),
+            options,
             offset: AtomicI64::new(0),
+            end_offset: AtomicI64::new(i64::MAX),
             last_response: AsyncMutex::new(None),
         };
 
         let mut dir_handles = self.dir_handles.write().await;
         dir_handles.insert(fh, Arc::new(handle));
+        self.open_handles.opened();
 
         Ok(Opened { fh, flags: 0 })
     }
@@ -958,6 +3045,9 @@ where
         self.readdir_impl(parent, fh, offset, false, reply).await
     }
 
+    /// Like [readdir](Self::readdir), but each entry also counts as a lookup of its inode, which
+    /// the kernel will later [forget](Self::forget). Only entries the reply accepts are counted,
+    /// not ones dropped because it was full, and `.` and `..` are never counted.
     pub async fn readdirplus<R: DirectoryReplier>(
         &self,
         parent: InodeNo,
@@ -969,6 +3059,82 @@ where
         self.readdir_impl(parent, fh, offset, true, reply).await
     }
 
+    /// An opaque token for the position just after the entry `name` in directory `ino`, from which
+    /// [readdir_from_token](Self::readdir_from_token) resumes listing the directory, or the position
+    /// before its first entry if `name` is empty. Unlike the offsets of `readdir` replies, which only
+    /// mean something to the handle that returned them, tokens depend only on the directory's key
+    /// and the name, so they stay valid in a new process mounting the same bucket. Long enumerations
+    /// can checkpoint them and resume after a remount.
+    pub fn encode_dir_position(&self, ino: InodeNo, name: &OsStr) -> Result<String, Error> {
+        let dir = self.superblock.inode(ino)?;
+        if dir.kind() != InodeKind::Directory {
+            return Err(InodeError::NotADirectory(dir.err()).into());
+        }
+        let name = name
+            .to_str()
+            .ok_or_else(|| err!(libc::EINVAL, "name {:?} is not valid UTF-8", name))?;
+        Ok(DirPosition::new(dir.full_key(), name).encode())
+    }
+
+    /// Add the entries of directory `ino` that come after the position in `token` to `reply`, until
+    /// it's full or the directory ends. `token` must have been returned by
+    /// [encode_dir_position](Self::encode_dir_position) for the same directory, or this fails with
+    /// `EINVAL`. To continue, encode the position of the last entry the reply accepted.
+    ///
+    /// Entries come in the same order as `readdir`, without `.` and `..`, and aren't counted as
+    /// lookups. Each call lists the directory from S3 starting after the position, so only buckets
+    /// with ordered listings are supported.
+    pub async fn readdir_from_token<R: DirectoryReplier>(
+        &self,
+        ino: InodeNo,
+        token: &str,
+        mut reply: R,
+    ) -> Result<R, Error> {
+        trace!("fs:readdir_from_token with ino {:?} token {:?}", ino, token);
+
+        let position = DirPosition::decode(token).ok_or_else(|| err!(libc::EINVAL, "invalid directory position"))?;
+        let dir = self.superblock.inode(ino)?;
+        if dir.kind() != InodeKind::Directory {
+            return Err(InodeError::NotADirectory(dir.err()).into());
+        }
+        if position.dir_key != dir.full_key() {
+            return Err(err!(
+                libc::EINVAL,
+                "directory position is in {:?}, not {:?}",
+                position.dir_key,
+                dir.full_key()
+            ));
+        }
+        if !self.config.s3_personality.is_list_ordered() {
+            return Err(err!(
+                libc::EOPNOTSUPP,
+                "can't resume listings of buckets without ordered listings"
+            ));
+        }
+
+        self.ensure_bootstrapped().await;
+        let readdir_handle = self.superblock.readdir_after(ino, 1000, &position.name).await?;
+        let mut offset = 0;
+        while let Some(next) = readdir_handle.next(&self.client).await? {
+            offset += 1;
+            let attr = self.make_attr(&next);
+            let entry = DirectoryEntry {
+                ino: attr.ino,
+                offset,
+                name: next.inode.name().into(),
+                dtype: self.config.readdir_report_types.then_some(attr.kind),
+                attr,
+                generation: 0,
+                ttl: next.validity(self.superblock.now()),
+                lookup: next,
+            };
+            if reply.add(entry) {
+                break;
+            }
+        }
+        Ok(reply)
+    }
+
     async fn readdir_impl<R: DirectoryReplier>(
         &self,
         parent: InodeNo,
@@ -987,8 +3153,13 @@ where
 
         // special case where we need to rewind and restart the streaming but only when it is not the first time we see offset 0
         if offset == 0 && dir_handle.offset() != 0 {
-            let new_handle = self.readdir_handle(parent).await?;
-            *dir_handle.handle.lock().await = new_handle;
+            match self.config.readdir_rewind_mode {
+                RewindMode::Refresh => {
+                    let new_handle = self.readdir_handle(parent, dir_handle.options).await?;
+                    *dir_handle.handle.lock().await = new_handle;
+                }
+                RewindMode::Snapshot => dir_handle.handle.lock().await.rewind().await?,
+            }
             dir_handle.rewind_offset();
         }
 
@@ -1020,12 +3191,46 @@ where
                 }
             }
 
-            return Err(err!(
-                libc::EINVAL,
-                "out-of-order readdir, expected={}, actual={}",
-                dir_handle.offset(),
-                offset
-            ));
+            if offset < dir_handle.offset() {
+                return Err(err!(
+                    libc::EINVAL,
+                    "out-of-order readdir, expected={}, actual={}",
+                    dir_handle.offset(),
+                    offset
+                ));
+            }
+
+            // A later offset than we expected, for example one handed out before the directory
+            // shrank and was listed again. Skip entries up to it. If the directory ends first, the
+            // offset is past its end, so there's nothing to return: reply with no entries (EOF),
+            // and keep replying that way, rather than starting over from an earlier entry. Give up
+            // if it's more than MAX_READDIR_SKIP entries ahead.
+            if offset >= dir_handle.end_offset() {
+                trace!(
+                    end = dir_handle.end_offset(),
+                    offset,
+                    "readdir offset is past the end of the directory"
+                );
+                return Ok(reply);
+            }
+            trace!(expected = dir_handle.offset(), offset, "skipping ahead in readdir");
+            let skip_limit = dir_handle.offset().saturating_add(MAX_READDIR_SKIP);
+            while dir_handle.offset() < offset {
+                if dir_handle.offset() >= skip_limit {
+                    return Err(err!(
+                        libc::EINVAL,
+                        "readdir offset too far ahead, limit={}, actual={}",
+                        skip_limit,
+                        offset
+                    ));
+                }
+                // The first two offsets are . and .., which are always there
+                if dir_handle.offset() >= 2 && readdir_handle.next(&self.client).await?.is_none() {
+                    dir_handle.reached_end();
+                    return Ok(reply);
+                }
+                dir_handle.next_offset();
+            }
         }
 
         /// Wrap a replier to duplicate the entries and store them in `dir_handle.last_response` so
@@ -1061,9 +3266,10 @@ where
                 ino: parent,
                 offset: dir_handle.offset() + 1,
                 name: ".".into(),
+                dtype: self.config.readdir_report_types.then_some(attr.kind),
                 attr,
                 generation: 0,
-                ttl: lookup.validity(),
+                ttl: lookup.validity(self.superblock.now()),
                 lookup,
             };
             if reply.add(entry) {
@@ -1081,9 +3287,10 @@ where
                 ino: readdir_handle.parent(),
                 offset: dir_handle.offset() + 1,
                 name: "..".into(),
+                dtype: self.config.readdir_report_types.then_some(attr.kind),
                 attr,
                 generation: 0,
-                ttl: lookup.validity(),
+                ttl: lookup.validity(self.superblock.now()),
                 lookup,
             };
             if reply.add(entry) {
@@ -1094,7 +3301,10 @@ where
 
         loop {
             let next = match readdir_handle.next(&self.client).await? {
-                None => return Ok(reply.finish(offset, &dir_handle).await),
+                None => {
+                    dir_handle.reached_end();
+                    return Ok(reply.finish(offset, &dir_handle).await);
+                }
                 Some(next) => next,
             };
 
@@ -1103,9 +3313,10 @@ where
                 ino: attr.ino,
                 offset: dir_handle.offset() + 1,
                 name: next.inode.name().into(),
+                dtype: self.config.readdir_report_types.then_some(attr.kind),
                 attr,
                 generation: 0,
-                ttl: next.validity(),
+                ttl: next.validity(self.superblock.now()),
                 lookup: next.clone(),
             };
 
@@ -1146,7 +3357,15 @@ where
         logging::record_name(file_handle.inode.name());
         let mut state = file_handle.state.lock().await;
         let request = match &mut *state {
-            FileHandleState::Read { .. } => return Ok(()),
+            FileHandleState::Read(_)
+            | FileHandleState::ReadUnknownLength(_)
+            | FileHandleState::ReadLocal(_)
+            | FileHandleState::Path => return Ok(()),
+            FileHandleState::PartialWrite(write) => {
+                return write
+                    .complete(&self.uploader, &self.bucket, &file_handle.full_key, false, None)
+                    .await
+            }
             FileHandleState::Write(request) => request,
         };
         self.complete_upload(request, &file_handle.full_key, false, None).await
@@ -1176,14 +3395,24 @@ where
         logging::record_name(file_handle.inode.name());
         let mut state = file_handle.state.lock().await;
         match &mut *state {
-            FileHandleState::Read { .. } => Ok(()),
+            FileHandleState::Read(_)
+            | FileHandleState::ReadUnknownLength(_)
+            | FileHandleState::ReadLocal(_)
+            | FileHandleState::Path => Ok(()),
             FileHandleState::Write(request) => {
                 self.complete_upload(request, &file_handle.full_key, true, Some(pid))
                     .await
             }
+            FileHandleState::PartialWrite(write) => {
+                write
+                    .complete(&self.uploader, &self.bucket, &file_handle.full_key, true, Some(pid))
+                    .await
+            }
         }
     }
 
+    /// Release a file handle. Releasing a handle that is unknown or was already released fails with
+    /// `EBADF`, the same as any other use of a handle after its release.
     pub async fn release(
         &self,
         ino: InodeNo,
@@ -1211,18 +3440,65 @@ where
                 return Err(err!(libc::EINVAL, "unable to unwrap file handle reference"));
             }
         };
-
-        let request = match file_handle.state.into_inner() {
-            FileHandleState::Read { .. } => {
+        self.open_handles.released();
+
+        let state = file_handle.state.into_inner();
+        if matches!(
+            state,
+            FileHandleState::Read(_) | FileHandleState::ReadUnknownLength(_) | FileHandleState::ReadLocal(_)
+        ) {
+            let reads = file_handle.reads.into_inner().unwrap();
+            self.record_handle_reads(ino, file_handle.direct_io, &reads);
+        }
+        let request = match state {
+            FileHandleState::Read(read) => {
                 // TODO make sure we cancel the inflight PrefetchingGetRequest. is just dropping enough?
+                self.release_shared_read(file_handle.inode.ino(), read);
+                metrics::gauge!("fs.current_handles", "type" => "read").decrement(1.0);
+                file_handle.inode.finish_reading()?;
+                return Ok(());
+            }
+            FileHandleState::ReadUnknownLength(_) => {
                 metrics::gauge!("fs.current_handles", "type" => "read").decrement(1.0);
                 file_handle.inode.finish_reading()?;
                 return Ok(());
             }
+            FileHandleState::ReadLocal(_) => {
+                // Local reads don't count as readers, since the inode is already being written
+                metrics::gauge!("fs.current_handles", "type" => "read").decrement(1.0);
+                return Ok(());
+            }
+            FileHandleState::Path => {
+                metrics::gauge!("fs.current_handles", "type" => "path").decrement(1.0);
+                return Ok(());
+            }
+            FileHandleState::PartialWrite(write) => {
+                let result = write
+                    .complete_if_in_progress(&self.uploader, &self.bucket, &file_handle.full_key)
+                    .await;
+                metrics::gauge!("fs.current_handles", "type" => "write").decrement(1.0);
+                return result;
+            }
             FileHandleState::Write(request) => request,
         };
 
+        if let Some(local_write) = &file_handle.local_write {
+            // A newer write handle may have taken over the inode since this one finished
+            let mut local_writes = self.local_writes.lock().unwrap();
+            if local_writes
+                .get(&ino)
+                .is_some_and(|entry| Arc::ptr_eq(entry, local_write))
+            {
+                local_writes.remove(&ino);
+            }
+        }
+
+        let failed = matches!(request, UploadState::Failed(_));
         let result = request.complete_if_in_progress(&file_handle.full_key).await;
+        if let Some(local_write) = &file_handle.local_write {
+            // Reads of data that had dropped out of the window can now get it from the new object
+            local_write.finish(!failed && result.is_ok()).await;
+        }
         metrics::gauge!("fs.current_handles", "type" => "write").decrement(1.0);
         // Errors won't actually be seen by the user because `release` is async,
         // but it's the right thing to do.
@@ -1230,15 +3506,23 @@ where
     }
 
     pub async fn rmdir(&self, parent_ino: InodeNo, name: &OsStr) -> Result<(), Error> {
+        // This is where failures to delete the objects of files unlinked from the directory are
+        // reported, since that's usually the end of removing a directory tree
+        if let Some(pending) = &self.pending_unlinks {
+            pending.flush(self.client.as_ref(), &self.bucket).await?;
+        }
         self.superblock.rmdir(&self.client, parent_ino, name).await?;
+        self.invalidate_kernel_entry(parent_ino, name);
         Ok(())
     }
 
+    /// Release a directory handle. Like [release](Self::release), this fails with `EBADF` if the
+    /// handle is unknown or was already released.
     pub async fn releasedir(&self, _ino: InodeNo, fh: u64, _flags: i32) -> Result<(), Error> {
         let mut dir_handles = self.dir_handles.write().await;
         dir_handles
             .remove(&fh)
-            .map(|_| ())
+            .map(|_| self.open_handles.released())
             .ok_or_else(|| err!(libc::EBADF, "invalid directory handle"))
     }
 
@@ -1249,7 +3533,381 @@ where
                 "Deletes are disabled. Use '--allow-delete' mount option to enable it."
             ));
         }
-        Ok(self.superblock.unlink(&self.client, parent_ino, name).await?)
+        match &self.pending_unlinks {
+            Some(pending) => {
+                let key = self.superblock.unlink_deferred(&self.client, parent_ino, name).await?;
+                if pending.push(key) >= MAX_DELETE_OBJECTS_KEYS {
+                    self.flush_unlinks().await;
+                }
+            }
+            None => self.superblock.unlink(&self.client, parent_ino, name).await?,
+        }
+        self.invalidate_kernel_entry(parent_ino, name);
+        Ok(())
+    }
+
+    /// Delete the objects of the files unlinked so far, before an operation that could otherwise
+    /// still see them in S3. Failures are only logged: the objects that weren't deleted are
+    /// visible again. See [S3FilesystemConfig::unlink_batch_delay].
+    async fn flush_unlinks(&self) {
+        let Some(pending) = &self.pending_unlinks else {
+            return;
+        };
+        if let Err(error) = pending.flush(self.client.as_ref(), &self.bucket).await {
+            warn!(?error, "failed to delete objects of unlinked files");
+        }
+    }
+
+    /// Copy `len` bytes between two open files. The only copy we support is of a whole object into
+    /// a file that hasn't been written to yet, which is what `cp` asks for when copying a file
+    /// within this file system. That copy is done server-side, rather than downloading the object
+    /// and uploading it again. Other copies fail with `EOPNOTSUPP`, so the caller falls back to
+    /// reading and writing the data itself.
+    #[allow(clippy::too_many_arguments)] // We don't get to choose this interface
+    pub async fn copy_file_range(
+        &self,
+        ino_in: InodeNo,
+        fh_in: u64,
+        offset_in: i64,
+        ino_out: InodeNo,
+        fh_out: u64,
+        offset_out: i64,
+        len: u64,
+        _flags: u32,
+    ) -> Result<u32, Error> {
+        trace!(
+            ino_in,
+            fh_in,
+            offset_in,
+            ino_out,
+            fh_out,
+            offset_out,
+            len,
+            "fs:copy_file_range"
+        );
+
+        let (handle_in, handle_out) = {
+            let file_handles = self.file_handles.read().await;
+            match (file_handles.get(&fh_in), file_handles.get(&fh_out)) {
+                (Some(handle_in), Some(handle_out)) => (handle_in.clone(), handle_out.clone()),
+                _ => return Err(err!(libc::EBADF, "invalid file handle")),
+            }
+        };
+        logging::record_name(handle_out.inode.name());
+
+        let (object_size, etag) = match &*handle_in.state.lock().await {
+            FileHandleState::Read(shared) => (
+                shared.object_size.load(Ordering::SeqCst),
+                ETag::from_str(&shared.etag).expect("E-Tag should be set"),
+            ),
+            FileHandleState::ReadUnknownLength(_) => {
+                return Err(err!(libc::EOPNOTSUPP, "objects of unknown length can't be copied"))
+            }
+            FileHandleState::ReadLocal(_) => return Err(err!(libc::EOPNOTSUPP, "files being written can't be copied")),
+            FileHandleState::Write(_) | FileHandleState::PartialWrite(_) | FileHandleState::Path => {
+                return Err(err!(libc::EBADF, "file handle is not open for reads"))
+            }
+        };
+        if offset_in < 0 || offset_out < 0 {
+            return Err(err!(libc::EINVAL, "negative offset"));
+        }
+        if offset_in as u64 >= object_size {
+            // Nothing left to copy, which is how `cp` finds out the copy is done
+            return Ok(0);
+        }
+        // The reply can only report up to u32::MAX bytes copied
+        if offset_in != 0 || offset_out != 0 || len < object_size || object_size > u32::MAX as u64 {
+            return Err(err!(
+                libc::EOPNOTSUPP,
+                "only copies of whole objects into new files are supported"
+            ));
+        }
+
+        let mut state = handle_out.state.lock().await;
+        let FileHandleState::Write(request) = &mut *state else {
+            return Err(err!(libc::EBADF, "file handle is not open for writes"));
+        };
+        if !request
+            .complete_with_copy(&handle_out.full_key, &handle_in.full_key, &etag)
+            .await?
+        {
+            return Err(err!(
+                libc::EOPNOTSUPP,
+                "only copies of whole objects into new files are supported"
+            ));
+        }
+        handle_out.inode.inc_file_size(object_size as usize);
+        Ok(object_size as u32)
+    }
+
+    /// Renaming isn't supported, and otherwise fails with `ENOSYS`. Invalid names are still
+    /// reported first, with the same error any other operation would return for them, followed by
+    /// the errors POSIX requires for the types of the source and target: `EISDIR` for a file onto
+    /// a directory, `ENOTDIR` for a directory onto a file, and `ENOTEMPTY` for a directory onto a
+    /// non-empty directory.
+    pub async fn rename(
+        &self,
+        parent: InodeNo,
+        name: &OsStr,
+        newparent: InodeNo,
+        newname: &OsStr,
+        _flags: u32,
+    ) -> Result<(), Error> {
+        validate_inode_name(name)?;
+        validate_inode_name(newname)?;
+        self.ensure_bootstrapped().await;
+        self.superblock
+            .check_rename(&self.client, parent, name, newparent, newname)
+            .await?;
+        Err(err!(libc::ENOSYS, "rename is not supported"))
+    }
+
+    /// Symbolic links aren't supported, and creating one always fails with `EPERM`, which is what
+    /// userspace expects. Like [rename](Self::rename), invalid names are reported first.
+    pub async fn symlink(&self, _parent: InodeNo, name: &OsStr, _link: &Path) -> Result<Entry, Error> {
+        validate_inode_name(name)?;
+        Err(err!(libc::EPERM, "symlinks are not supported"))
+    }
+
+    /// Delete the objects left in the [upload staging directory](S3FilesystemConfig::upload_staging_directory)
+    /// by uploads that failed to publish, if they were last modified more than `older_than` ago.
+    /// Uploads still in progress are not affected, as they don't create an object until they
+    /// complete. Returns the number of objects deleted.
+    pub async fn cleanup_staging(&self, older_than: Duration) -> Result<usize, Error> {
+        let Some(dir) = &self.config.upload_staging_directory else {
+            return Ok(0);
+        };
+        let staging_prefix = format!("{}{dir}/", self.prefix);
+        let cutoff = OffsetDateTime::now_utc() - older_than;
+
+        let mut deleted = 0;
+        let mut continuation_token = None;
+        loop {
+            let result = self
+                .client
+                .list_objects(&self.bucket, continuation_token.as_deref(), "", 1000, &staging_prefix)
+                .await
+                .map_err(|e| err!(libc::EIO, source:e, "failed to list staged objects"))?;
+            let stale: Vec<String> = result
+                .objects
+                .into_iter()
+                .filter(|object| object.last_modified < cutoff)
+                .map(|object| object.key)
+                .collect();
+            let (count, failed) = delete_keys(self.client.as_ref(), &self.bucket, &stale).await?;
+            deleted += count;
+            debug!(deleted = count, "deleted stale staged objects");
+            if let Some(error) = failed {
+                return Err(error);
+            }
+            continuation_token = result.next_continuation_token;
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Remove the directory at `path`, relative to the mount point, and everything below it, like
+    /// `rm -r`. Rather than a DeleteObject request for each file, the objects under the directory
+    /// are deleted in batches of up to [MAX_DELETE_OBJECTS_KEYS] with DeleteObjects requests. Files
+    /// still being written are left in place. Returns the number of objects deleted.
+    pub async fn remove_dir_all(&self, path: impl AsRef<Path>) -> Result<usize, Error> {
+        let path = path.as_ref();
+        if !self.config.allow_delete {
+            return Err(err!(
+                libc::EPERM,
+                "Deletes are disabled. Use '--allow-delete' mount option to enable it."
+            ));
+        }
+        let mut looked_up = Vec::new();
+        let result = self.remove_path_all(path, &mut looked_up).await;
+        // Nothing else holds on to the inodes we looked up, so drop them as the kernel would
+        for ino in looked_up.into_iter().rev() {
+            self.superblock.forget(ino, 1);
+        }
+        result
+    }
+
+    async fn remove_path_all(&self, path: &Path, looked_up: &mut Vec<InodeNo>) -> Result<usize, Error> {
+        let entry = self.lookup_path(path, looked_up).await?;
+        let ino = entry.attr.ino;
+        if ino == FUSE_ROOT_INODE {
+            return Err(err!(libc::EBUSY, "the mount point can't be removed"));
+        }
+        if entry.attr.kind != FileType::Directory {
+            return Err(err!(libc::ENOTDIR, "{:?} is not a directory", path));
+        }
+        let dir = self.superblock.inode(ino)?;
+        let dir_key = dir.full_key().to_owned();
+
+        let mut deleted = 0;
+        let mut failed = None;
+        let mut continuation_token = None;
+        let result = loop {
+            let result = self
+                .client
+                .list_objects(&self.bucket, continuation_token.as_deref(), "", 1000, &dir_key)
+                .await;
+            let result = match result {
+                Ok(result) => result,
+                Err(e) => break Err(err!(libc::EIO, source:e, "failed to list objects under {:?}", dir_key)),
+            };
+            let keys: Vec<String> = result.objects.into_iter().map(|object| object.key).collect();
+            match delete_keys(self.client.as_ref(), &self.bucket, &keys).await {
+                Ok((count, error)) => {
+                    deleted += count;
+                    failed = failed.or(error);
+                }
+                Err(e) => break Err(e),
+            }
+            continuation_token = result.next_continuation_token;
+            if continuation_token.is_none() {
+                break Ok(deleted);
+            }
+        };
+        debug!(?dir_key, deleted, "removed directory");
+
+        // Whatever was deleted before any failure is gone, so don't keep serving it from the cache
+        if deleted > 0 {
+            self.superblock.remove_subtree(ino)?;
+            self.invalidate_kernel_entry(dir.parent(), OsStr::new(dir.name()));
+        }
+        match failed {
+            Some(error) => Err(error),
+            None => result,
+        }
+    }
+
+    /// Write a listing manifest of every object under the mount point to `path`, for a later mount
+    /// of the same prefix to load with [S3FilesystemConfig::listing_bootstrap]. The manifest is
+    /// gzip-compressed if `path` ends in `.gz`. Returns the number of objects written.
+    pub async fn export_listing(&self, path: impl AsRef<Path>) -> Result<usize, Error> {
+        let path = path.as_ref();
+        self.flush_unlinks().await;
+        let staging_prefix = self
+            .config
+            .upload_staging_directory
+            .as_ref()
+            .map(|dir| format!("{}{dir}/", self.prefix));
+
+        let mut objects = Vec::new();
+        let mut continuation_token = None;
+        loop {
+            let result = self
+                .client
+                .list_objects(
+                    &self.bucket,
+                    continuation_token.as_deref(),
+                    "",
+                    1000,
+                    self.prefix.as_str(),
+                )
+                .await
+                .map_err(|e| err!(libc::EIO, source:e, "failed to list objects for listing manifest"))?;
+            let is_staged = |key: &str| staging_prefix.as_deref().map(|p| key.starts_with(p)).unwrap_or(false);
+            objects.extend(result.objects.into_iter().filter(|object| !is_staged(&object.key)));
+            continuation_token = result.next_continuation_token;
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+
+        let file = std::fs::File::create(path)
+            .map_err(|e| err!(libc::EIO, source:e, "failed to create listing manifest {:?}", path))?;
+        let gzip = path.extension() == Some(OsStr::new("gz"));
+        manifest::write_manifest(BufWriter::new(file), &objects, gzip)
+            .map_err(|e| err!(libc::EIO, source:e, "failed to write listing manifest {:?}", path))?;
+        debug!(?path, objects = objects.len(), "exported listing manifest");
+        Ok(objects.len())
+    }
+
+    /// Download the file at `path`, relative to the mount point, to `writer`. The data comes from
+    /// the same prefetcher as reads through the file system, and is written straight from the
+    /// prefetched parts without being copied into read buffers. Returns the number of bytes written.
+    pub async fn download_to<W>(&self, path: impl AsRef<Path>, writer: &mut W) -> Result<u64, Error>
+    where
+        W: AsyncWrite + Unpin + ?Sized,
+    {
+        let path = path.as_ref();
+        let mut looked_up = Vec::new();
+        let result = self.download_path_to(path, writer, &mut looked_up).await;
+        // Nothing else holds on to the inodes we looked up, so drop them as the kernel would
+        for ino in looked_up.into_iter().rev() {
+            self.superblock.forget(ino, 1);
+        }
+        result
+    }
+
+    /// Look up each component of `path`, relative to the mount point, and return the entry for the
+    /// last one. Every inode looked up is pushed onto `looked_up`, for the caller to forget once
+    /// it's done with them.
+    ///
+    /// Paths are never normalized: `.` and `..` components are rejected like any other invalid
+    /// name, rather than navigating to a different directory than the keys they spell out.
+    pub(crate) async fn lookup_path(&self, path: &Path, looked_up: &mut Vec<InodeNo>) -> Result<Entry, Error> {
+        if path.has_root() {
+            return Err(err!(
+                libc::EINVAL,
+                "path {:?} must be relative to the mount point",
+                path
+            ));
+        }
+        let mut entry = None;
+        // Unlike [Path::components], splitting the path ourselves keeps inner `.` components
+        let components = path.as_os_str().as_bytes().split(|&b| b == b'/');
+        for name in components.filter(|name| !name.is_empty()).map(OsStr::from_bytes) {
+            let parent = entry.as_ref().map_or(FUSE_ROOT_INODE, |entry: &Entry| entry.attr.ino);
+            let next = self.lookup(parent, name).await?;
+            looked_up.push(next.attr.ino);
+            entry = Some(next);
+        }
+        match entry {
+            Some(entry) => Ok(entry),
+            None => {
+                let attr = self.getattr(FUSE_ROOT_INODE).await?;
+                Ok(Entry {
+                    ttl: attr.ttl,
+                    attr: attr.attr,
+                    generation: 0,
+                })
+            }
+        }
+    }
+
+    async fn download_path_to<W>(&self, path: &Path, writer: &mut W, looked_up: &mut Vec<InodeNo>) -> Result<u64, Error>
+    where
+        W: AsyncWrite + Unpin + ?Sized,
+    {
+        let ino = self.lookup_path(path, looked_up).await?.attr.ino;
+        let fh = self.open(ino, libc::O_RDONLY, 0).await?.fh;
+        let result = async {
+            let mut offset = 0u64;
+            loop {
+                let parts = self
+                    .read_vectored(ino, fh, offset as i64, DOWNLOAD_READ_SIZE, 0, None)
+                    .await?;
+                let read_size: usize = parts.iter().map(Bytes::len).sum();
+                if read_size == 0 {
+                    break;
+                }
+                for part in parts {
+                    writer
+                        .write_all(&part)
+                        .await
+                        .map_err(|e| err!(libc::EIO, source:e, "failed to write downloaded data"))?;
+                }
+                offset += read_size as u64;
+            }
+            writer
+                .flush()
+                .await
+                .map_err(|e| err!(libc::EIO, source:e, "failed to write downloaded data"))?;
+            Ok(offset)
+        }
+        .await;
+        self.release(ino, fh, 0, None, false).await?;
+        result
     }
 }
 
@@ -1257,11 +3915,34 @@ where
 mod tests {
     use super::*;
     use crate::prefetch::default_prefetch;
-    use fuser::FileType;
     use futures::executor::ThreadPool;
     use mountpoint_s3_client::mock_client::{MockClient, MockClientConfig, MockObject};
     use test_case::test_case;
 
+    #[test_case("", ""; "root start")]
+    #[test_case("prefix/dir/", "file.txt"; "nested")]
+    #[test_case("dir.v2/", "name.with.dots"; "dots")]
+    #[test_case("こんにちは/", "😄"; "unicode")]
+    fn test_dir_position_round_trip(dir_key: &str, name: &str) {
+        let position = DirPosition::new(dir_key, name);
+        let token = position.encode();
+        assert_eq!(
+            token.matches('.').count(),
+            1,
+            "token {token} should only have one separator"
+        );
+        assert_eq!(DirPosition::decode(&token), Some(position));
+    }
+
+    #[test_case(""; "empty")]
+    #[test_case("6469722f"; "no separator")]
+    #[test_case("6469722f.zz"; "not hex")]
+    #[test_case("6469722f.ff"; "not utf8")]
+    #[test_case("6469722f.61.62"; "extra separator")]
+    fn test_dir_position_invalid(token: &str) {
+        assert_eq!(DirPosition::decode(token), None);
+    }
+
     #[test_case(Some("aws:kms"), Some("some_key_alias"), Some("aws:kmr"), Some("some_key_alias"))]
     #[test_case(Some("aws:kms"), Some("some_key_alias"), Some("aws:kms"), Some("some_key_ali`s"))]
     #[test_case(Some("aws:kms"), Some("some_key_alias"), None, Some("some_key_alias"))]
@@ -1322,6 +4003,42 @@ mod tests {
             .expect("verify_response() should return Ok(()) when values match the checksum")
     }
 
+    #[test_case(64 * 1024, 64 * 1024; "clamped by kernel")]
+    #[test_case(1024 * 1024, 128 * 1024; "below kernel limit")]
+    #[test_case(0, 1; "kernel limit of zero")]
+    #[cfg(feature = "fuse")]
+    #[tokio::test]
+    async fn test_block_size_limited_by_kernel(max_read: u32, expected: u32) {
+        let bucket = "bucket";
+        let client = Arc::new(MockClient::new(MockClientConfig {
+            bucket: bucket.to_owned(),
+            part_size: 1024 * 1024,
+            ..Default::default()
+        }));
+        client.add_object("dir/file.txt", MockObject::from_bytes(b"hello", ETag::for_tests()));
+
+        let runtime = ThreadPool::builder().pool_size(1).create().unwrap();
+        let prefetcher = default_prefetch(runtime, Default::default());
+        let fs = S3Filesystem::new(
+            client.clone(),
+            prefetcher,
+            bucket,
+            &Default::default(),
+            Default::default(),
+        );
+
+        let dir = fs.lookup(FUSE_ROOT_INODE, "dir".as_ref()).await.unwrap();
+        assert_eq!(dir.attr.blksize, 128 * 1024);
+
+        // What `init` does with the kernel's limit
+        fs.limit_block_size(max_read);
+
+        let file = fs.lookup(dir.attr.ino, "file.txt".as_ref()).await.unwrap();
+        assert_eq!(file.attr.blksize, expected);
+        let attr = fs.getattr(dir.attr.ino).await.unwrap().attr;
+        assert_eq!(attr.blksize, expected);
+    }
+
     #[tokio::test]
     async fn test_open_with_corrupted_sse() {
         let bucket = "bucket";
@@ -1373,3 +4090,72 @@ mod tests {
         assert_eq!(format!("{}", err), "put failed to start: SSE settings corrupted: Checksum mismatch. expected: Crc32c(752912206), actual: Crc32c(1265531471)");
     }
 }
+
+/// Checks that the file system works as a library when built without the `fuse` feature (and so
+/// without _fuser_), using only the types this module defines.
+#[cfg(all(test, not(feature = "fuse")))]
+mod standalone_tests {
+    use super::*;
+    use crate::prefetch::default_prefetch;
+    use futures::executor::ThreadPool;
+    use mountpoint_s3_client::mock_client::{MockClient, MockClientConfig, MockObject};
+
+    #[derive(Default)]
+    struct Entries(Vec<DirectoryEntry>);
+
+    impl DirectoryReplier for &mut Entries {
+        fn add(&mut self, entry: DirectoryEntry) -> bool {
+            self.0.push(entry);
+            false
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fs_operations_without_fuse() {
+        let bucket = "bucket";
+        let client = Arc::new(MockClient::new(MockClientConfig {
+            bucket: bucket.to_owned(),
+            part_size: 1024 * 1024,
+            ..Default::default()
+        }));
+        client.add_object("dir/file1.txt", MockObject::from_bytes(b"hello", ETag::for_tests()));
+
+        let runtime = ThreadPool::builder().pool_size(1).create().unwrap();
+        let prefetcher = default_prefetch(runtime, Default::default());
+        let fs = S3Filesystem::new(
+            client.clone(),
+            prefetcher,
+            bucket,
+            &Default::default(),
+            Default::default(),
+        );
+
+        let dir = fs.lookup(FUSE_ROOT_INODE, "dir".as_ref()).await.unwrap();
+        assert_eq!(dir.attr.kind, FileType::Directory);
+        let file = fs.lookup(dir.attr.ino, "file1.txt".as_ref()).await.unwrap();
+        let attr: FileAttr = fs.getattr(file.attr.ino).await.unwrap().attr;
+        assert_eq!(attr.kind, FileType::RegularFile);
+        assert_eq!(attr.size, 5);
+
+        let fh = fs.open(file.attr.ino, libc::O_RDONLY, 0).await.unwrap().fh;
+        let data = fs.read(file.attr.ino, fh, 0, 4096, 0, None).await.unwrap();
+        assert_eq!(&data[..], b"hello");
+        fs.release(file.attr.ino, fh, 0, None, true).await.unwrap();
+
+        let new_file = fs
+            .mknod(dir.attr.ino, "file2.txt".as_ref(), libc::S_IFREG | libc::S_IRWXU, 0, 0)
+            .await
+            .unwrap();
+        let fh = fs.open(new_file.attr.ino, libc::O_WRONLY, 0).await.unwrap().fh;
+        fs.write(new_file.attr.ino, fh, 0, b"world", 0, 0, None).await.unwrap();
+        fs.release(new_file.attr.ino, fh, 0, None, true).await.unwrap();
+        assert!(client.contains_key("dir/file2.txt"));
+
+        let dh = fs.opendir(dir.attr.ino, 0).await.unwrap().fh;
+        let mut entries = Entries::default();
+        let _ = fs.readdir(dir.attr.ino, dh, 0, &mut entries).await.unwrap();
+        let names: Vec<_> = entries.0.iter().map(|entry| entry.name.clone()).collect();
+        assert_eq!(names, vec![".", "..", "file1.txt", "file2.txt"]);
+        fs.releasedir(dir.attr.ino, dh, 0).await.unwrap();
+    }
+}
@@ -0,0 +1,320 @@
+//! A circuit breaker that stops a mount from hammering S3 while it's failing.
+//!
+//! Every operation that talks to S3 already retries failed requests. When S3 (or the network to
+//! it) is having a bad time, those retries pile up and every application on the mount hangs until
+//! they're exhausted. The [CircuitBreaker] keeps a budget of failures shared by the whole mount: if
+//! too many operations in a window fail, it opens, and new operations fail fast with `EAGAIN`
+//! instead of being sent to S3. After a cooldown it lets a single probe through, and closes again
+//! if the probe succeeds.
+
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use tracing::{debug, warn, Level};
+
+use crate::clock::Clock;
+use crate::sync::{Arc, Mutex};
+
+use super::{config, Error, ToErrno};
+
+/// Configuration of the mount's [CircuitBreaker]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CircuitBreakerConfig {
+    /// How long a window the failure rate is measured over
+    #[serde(deserialize_with = "config::deserialize_duration")]
+    pub window: Duration,
+    /// The fraction of operations in a window that must fail for the breaker to open
+    pub failure_threshold: f64,
+    /// The fewest operations a window must see before the breaker can open, so that a handful of
+    /// failures on an idle mount don't open it
+    pub min_requests: u32,
+    /// How long the breaker fails operations fast once open, before letting a probe through
+    #[serde(deserialize_with = "config::deserialize_duration")]
+    pub cooldown: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(10),
+            failure_threshold: 0.5,
+            min_requests: 20,
+            cooldown: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum State {
+    /// Operations go through, and their outcomes are counted over the current window
+    Closed {
+        window_start: Instant,
+        requests: u32,
+        failures: u32,
+    },
+    /// Operations fail fast until the cooldown is over
+    Open { until: Instant },
+    /// The cooldown is over and a single probe has been let through, so other operations keep
+    /// failing fast until it completes
+    HalfOpen,
+}
+
+impl State {
+    fn closed(now: Instant) -> Self {
+        State::Closed {
+            window_start: now,
+            requests: 0,
+            failures: 0,
+        }
+    }
+}
+
+/// Tracks the outcome of operations that go to S3 across the whole mount, and fails new ones fast
+/// with `EAGAIN` while too many of them are failing. See the [module docs](self).
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    config: Option<CircuitBreakerConfig>,
+    state: Mutex<State>,
+    clock: Arc<dyn Clock>,
+}
+
+impl CircuitBreaker {
+    /// Create a breaker with the given config, or one that's always closed if `None`. Windows
+    /// and cooldowns are measured by `clock`.
+    pub fn new(config: Option<CircuitBreakerConfig>, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            config,
+            state: Mutex::new(State::closed(clock.now())),
+            clock,
+        }
+    }
+
+    /// Ask to run an operation. Fails with `EAGAIN` if the breaker is open. Otherwise, the
+    /// operation's outcome must be reported to the returned [Permit].
+    pub fn admit(&self) -> Result<Permit<'_>, Error> {
+        let Some(config) = &self.config else {
+            return Ok(Permit {
+                breaker: self,
+                probe: false,
+            });
+        };
+        let now = self.clock.now();
+        let mut state = self.state.lock().unwrap();
+        let probe = match &mut *state {
+            State::Closed {
+                window_start,
+                requests,
+                failures,
+            } => {
+                if now.saturating_duration_since(*window_start) >= config.window {
+                    *window_start = now;
+                    *requests = 0;
+                    *failures = 0;
+                }
+                false
+            }
+            State::Open { until } if now >= *until => {
+                debug!("circuit breaker cooldown over, sending a probe");
+                *state = State::HalfOpen;
+                true
+            }
+            State::Open { .. } | State::HalfOpen => {
+                metrics::counter!("fs.circuit_breaker.rejected").increment(1);
+                return Err(err!(
+                    libc::EAGAIN,
+                    source: CircuitBreakerOpen,
+                    Level::DEBUG,
+                    "too many recent requests to S3 failed"
+                ));
+            }
+        };
+        Ok(Permit { breaker: self, probe })
+    }
+
+    fn record(&self, probe: bool, failed: bool) {
+        let Some(config) = &self.config else {
+            return;
+        };
+        let now = self.clock.now();
+        let mut state = self.state.lock().unwrap();
+        match &mut *state {
+            State::Closed { requests, failures, .. } if !probe => {
+                *requests += 1;
+                if failed {
+                    *failures += 1;
+                }
+                let failure_rate = *failures as f64 / *requests as f64;
+                if *requests >= config.min_requests && failure_rate >= config.failure_threshold {
+                    warn!(
+                        requests,
+                        failures,
+                        cooldown = ?config.cooldown,
+                        "too many requests to S3 are failing, failing new requests fast"
+                    );
+                    metrics::counter!("fs.circuit_breaker.opened").increment(1);
+                    *state = State::Open {
+                        until: now + config.cooldown,
+                    };
+                }
+            }
+            State::HalfOpen if probe => {
+                if failed {
+                    debug!("circuit breaker probe failed, staying open");
+                    *state = State::Open {
+                        until: now + config.cooldown,
+                    };
+                } else {
+                    warn!("requests to S3 are succeeding again, closing the circuit breaker");
+                    *state = State::closed(now);
+                }
+            }
+            // Operations admitted before the breaker changed state don't count towards it
+            _ => {}
+        }
+    }
+}
+
+/// Permission from the [CircuitBreaker] to run one operation
+#[derive(Debug)]
+#[must_use]
+pub struct Permit<'a> {
+    breaker: &'a CircuitBreaker,
+    probe: bool,
+}
+
+impl Permit<'_> {
+    /// Report the outcome of the operation. Only `EIO` counts as a failure: other errors, like
+    /// `ENOENT`, are S3 working as intended.
+    pub fn complete<T, E: ToErrno>(self, result: &Result<T, E>) {
+        let failed = matches!(result, Err(e) if e.to_errno() == libc::EIO);
+        let permit = std::mem::ManuallyDrop::new(self);
+        permit.breaker.record(permit.probe, failed);
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        // The operation was cancelled before it completed. If it was the probe, let the next
+        // operation probe instead, so the breaker doesn't stay half-open forever.
+        if self.probe {
+            let mut state = self.breaker.state.lock().unwrap();
+            if matches!(*state, State::HalfOpen) {
+                *state = State::Open {
+                    until: self.breaker.clock.now(),
+                };
+            }
+        }
+    }
+}
+
+/// The source of the errors returned while the [CircuitBreaker] is open
+#[derive(Debug, thiserror::Error)]
+#[error("circuit breaker is open")]
+pub struct CircuitBreakerOpen;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{MockClock, SystemClock};
+
+    fn eio() -> Result<(), Error> {
+        Err(err!(libc::EIO, "request failed"))
+    }
+
+    fn test_config(cooldown: Duration) -> CircuitBreakerConfig {
+        CircuitBreakerConfig {
+            window: Duration::from_secs(60),
+            failure_threshold: 0.5,
+            min_requests: 4,
+            cooldown,
+        }
+    }
+
+    #[test]
+    fn test_disabled_never_opens() {
+        let breaker = CircuitBreaker::new(None, Arc::new(SystemClock));
+        for _ in 0..100 {
+            breaker.admit().unwrap().complete(&eio());
+        }
+        breaker.admit().expect("disabled breaker should admit everything");
+    }
+
+    #[test]
+    fn test_opens_above_threshold() {
+        let breaker = CircuitBreaker::new(Some(test_config(Duration::from_secs(3600))), Arc::new(SystemClock));
+        // Below the minimum number of requests, even all failures don't open the breaker
+        for _ in 0..3 {
+            breaker.admit().unwrap().complete(&eio());
+        }
+        breaker.admit().unwrap().complete(&Ok(()));
+        // 3 of 4 failed
+        let err = breaker.admit().expect_err("breaker should be open");
+        assert_eq!(err.errno, libc::EAGAIN);
+    }
+
+    #[test]
+    fn test_other_errors_are_not_failures() {
+        let breaker = CircuitBreaker::new(Some(test_config(Duration::from_secs(3600))), Arc::new(SystemClock));
+        for _ in 0..10 {
+            let result: Result<(), Error> = Err(err!(libc::ENOENT, "no such file"));
+            breaker.admit().unwrap().complete(&result);
+        }
+        breaker.admit().expect("ENOENT shouldn't open the breaker");
+    }
+
+    #[test]
+    fn test_window_resets() {
+        let clock = Arc::new(MockClock::new());
+        let breaker = CircuitBreaker::new(Some(test_config(Duration::from_secs(3600))), clock.clone());
+        for _ in 0..3 {
+            breaker.admit().unwrap().complete(&eio());
+        }
+        // The failures so far are forgotten once the window is over
+        clock.advance(Duration::from_secs(60));
+        for _ in 0..3 {
+            breaker.admit().unwrap().complete(&Ok(()));
+        }
+        breaker.admit().unwrap().complete(&eio());
+        breaker.admit().expect("1 of 4 failed in this window");
+    }
+
+    #[test]
+    fn test_probe_closes_or_reopens() {
+        let clock = Arc::new(MockClock::new());
+        let cooldown = Duration::from_secs(5);
+        let breaker = CircuitBreaker::new(Some(test_config(cooldown)), clock.clone());
+        for _ in 0..4 {
+            breaker.admit().unwrap().complete(&eio());
+        }
+        breaker.admit().expect_err("breaker should be open during the cooldown");
+
+        // The cooldown is over, so one probe goes through and everything else fails fast
+        clock.advance(cooldown);
+        let probe = breaker.admit().expect("probe should be admitted");
+        breaker.admit().expect_err("only one probe at a time");
+        probe.complete(&eio());
+
+        // A failed probe starts another cooldown
+        breaker.admit().expect_err("breaker should be open again");
+        clock.advance(cooldown);
+        let probe = breaker.admit().expect("probe should be admitted");
+        probe.complete(&Ok(()));
+        for _ in 0..3 {
+            breaker.admit().expect("breaker should be closed").complete(&eio());
+        }
+    }
+
+    #[test]
+    fn test_cancelled_probe() {
+        let clock = Arc::new(MockClock::new());
+        let cooldown = Duration::from_secs(5);
+        let breaker = CircuitBreaker::new(Some(test_config(cooldown)), clock.clone());
+        for _ in 0..4 {
+            breaker.admit().unwrap().complete(&eio());
+        }
+        clock.advance(cooldown);
+        drop(breaker.admit().expect("probe should be admitted"));
+        breaker.admit().expect("a new probe should be admitted");
+    }
+}
@@ -0,0 +1,1057 @@
+//! Loading [S3FilesystemConfig] from structured configuration files, and building it in code
+//! with [S3FilesystemConfigBuilder].
+//!
+//! Every field of a configuration file is optional, and omitted fields take the same value as in
+//! [S3FilesystemConfig::default]. Unknown fields are rejected, so that typos don't silently fall
+//! back to a default. Durations are written in a human-readable form like `"1s"` or `"500ms"`.
+//!
+//! The file format is derived from the configuration structs themselves, so fields added to them
+//! can be set from a file without any changes here, unless they need a custom format. Fields that
+//! can't be written in a file, like [S3FilesystemConfig::clock], are skipped.
+
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Display};
+use std::time::Duration;
+
+use globset::{Glob, GlobBuilder};
+use serde::{de, Deserialize, Deserializer};
+use thiserror::Error;
+
+use crate::inode::valid_inode_name;
+use crate::mem_limiter::MemoryLimiter;
+use crate::prefetch::IdleBufferPolicy;
+use crate::s3::S3Personality;
+use crate::sync::Arc;
+
+use super::{
+    CacheConfig, CircuitBreakerConfig, KeyFailureConfig, ListingBootstrap, PathOverrides, PermissionChangeMode,
+    PrefixPattern, RewindMode, S3FilesystemConfig, ServerSideEncryption,
+};
+
+/// Error returned when loading a [S3FilesystemConfig] from a configuration file
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("invalid TOML config: {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("invalid JSON config: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid config: {0}")]
+    Invalid(#[from] InvalidConfigValue),
+}
+
+/// A field in a configuration file has a value that isn't valid for it
+#[derive(Debug, Error)]
+#[error("invalid value {value} for `{field}`: {reason}")]
+pub struct InvalidConfigValue {
+    field: &'static str,
+    value: String,
+    reason: String,
+}
+
+impl InvalidConfigValue {
+    fn new(field: &'static str, value: impl Debug, reason: impl Display) -> Self {
+        Self {
+            field,
+            value: format!("{value:?}"),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl S3FilesystemConfig {
+    /// Load a configuration from the contents of a TOML file, and [validate](Self::validate) it
+    pub fn from_toml_str(s: &str) -> Result<Self, ConfigError> {
+        let config: Self = toml::from_str(s)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Load a configuration from the contents of a JSON file, and [validate](Self::validate) it
+    pub fn from_json_str(s: &str) -> Result<Self, ConfigError> {
+        let config: Self = serde_json::from_str(s)?;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+impl S3FilesystemConfig {
+    /// Start building a configuration from the defaults, checked by [S3FilesystemConfigBuilder::build]
+    pub fn builder() -> S3FilesystemConfigBuilder {
+        S3FilesystemConfigBuilder {
+            config: S3FilesystemConfig::default(),
+        }
+    }
+
+    /// Check that the values of this configuration are valid, on their own and together.
+    /// Configurations loaded from a file or built with [S3FilesystemConfigBuilder] are always
+    /// valid, but those constructed directly aren't checked until this is called.
+    pub fn validate(&self) -> Result<(), InvalidConfigValue> {
+        if self.readdir_size == 0 {
+            return Err(InvalidConfigValue::new(
+                "readdir_size",
+                self.readdir_size,
+                "must be greater than zero",
+            ));
+        }
+        if let Some(interval) = self.directory_poll_interval {
+            if interval.is_zero() {
+                return Err(InvalidConfigValue::new(
+                    "directory_poll_interval",
+                    interval,
+                    "must be greater than zero",
+                ));
+            }
+        }
+        if self.max_buffered_dir_entries == Some(0) {
+            return Err(InvalidConfigValue::new(
+                "max_buffered_dir_entries",
+                0,
+                "must be greater than zero",
+            ));
+        }
+        if self.block_size == 0 {
+            return Err(InvalidConfigValue::new(
+                "block_size",
+                self.block_size,
+                "must be greater than zero",
+            ));
+        }
+        if self.allow_partial_writes && !self.allow_overwrite {
+            return Err(InvalidConfigValue::new(
+                "allow_partial_writes",
+                self.allow_partial_writes,
+                "needs allow_overwrite",
+            ));
+        }
+        validate_mode("dir_mode", self.dir_mode)?;
+        validate_mode("file_mode", self.file_mode)?;
+        if let Some(storage_class) = &self.storage_class {
+            if storage_class.is_empty() {
+                return Err(InvalidConfigValue::new(
+                    "storage_class",
+                    storage_class,
+                    "must not be empty",
+                ));
+            }
+        }
+        validate_sse(
+            self.server_side_encryption.sse_type.as_deref(),
+            self.server_side_encryption.sse_kms_key_id.as_deref(),
+        )?;
+        if let Some(upload_staging_directory) = &self.upload_staging_directory {
+            if !valid_inode_name(upload_staging_directory) {
+                return Err(InvalidConfigValue::new(
+                    "upload_staging_directory",
+                    upload_staging_directory,
+                    "must be a valid directory name",
+                ));
+            }
+        }
+        if let Some(circuit_breaker) = &self.circuit_breaker {
+            validate_circuit_breaker(
+                circuit_breaker,
+                "circuit_breaker.window",
+                "circuit_breaker.failure_threshold",
+            )?;
+        }
+        if let Some(circuit_breaker) = &self.metadata_circuit_breaker {
+            validate_circuit_breaker(
+                circuit_breaker,
+                "metadata_circuit_breaker.window",
+                "metadata_circuit_breaker.failure_threshold",
+            )?;
+        }
+        if let Some(key_failures) = &self.key_failures {
+            validate_key_failures(key_failures, "key_failures.failure_threshold", "key_failures.max_keys")?;
+        }
+        Ok(())
+    }
+}
+
+/// Builds a [S3FilesystemConfig] in code, starting from [S3FilesystemConfig::default], and checks
+/// it with [S3FilesystemConfig::validate] so that invalid values are reported up front rather than
+/// when the file system first uses them.
+#[derive(Debug)]
+pub struct S3FilesystemConfigBuilder {
+    config: S3FilesystemConfig,
+}
+
+impl S3FilesystemConfigBuilder {
+    /// Kernel cache config
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn cache_config(mut self, cache_config: CacheConfig) -> Self {
+        self.config.cache_config = cache_config;
+        self
+    }
+
+    /// Readdir page size. Must be greater than zero.
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn readdir_size(mut self, readdir_size: usize) -> Self {
+        self.config.readdir_size = readdir_size;
+        self
+    }
+
+    /// Behavior of directory handles rewound to offset 0
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn readdir_rewind_mode(mut self, readdir_rewind_mode: RewindMode) -> Self {
+        self.config.readdir_rewind_mode = readdir_rewind_mode;
+        self
+    }
+
+    /// Behavior of `chmod` and `chown`
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn permission_change_mode(mut self, permission_change_mode: PermissionChangeMode) -> Self {
+        self.config.permission_change_mode = permission_change_mode;
+        self
+    }
+
+    /// How often to poll opened directories for remote changes. Must be greater than zero.
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn directory_poll_interval(mut self, directory_poll_interval: Option<Duration>) -> Self {
+        self.config.directory_poll_interval = directory_poll_interval;
+        self
+    }
+
+    /// Invalidate the kernel's cached entries after removing them
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn invalidate_kernel_entries(mut self, invalidate_kernel_entries: bool) -> Self {
+        self.config.invalidate_kernel_entries = invalidate_kernel_entries;
+        self
+    }
+
+    /// Reply to lookups of names that don't exist with negative entries for the kernel to cache
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn negative_entry_replies(mut self, negative_entry_replies: bool) -> Self {
+        self.config.negative_entry_replies = negative_entry_replies;
+        self
+    }
+
+    /// Deepest directory level to list
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn max_listing_depth(mut self, max_listing_depth: Option<usize>) -> Self {
+        self.config.max_listing_depth = max_listing_depth;
+        self
+    }
+
+    /// Most entries a directory handle buffers in memory. Must be greater than zero.
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn max_buffered_dir_entries(mut self, max_buffered_dir_entries: Option<usize>) -> Self {
+        self.config.max_buffered_dir_entries = max_buffered_dir_entries;
+        self
+    }
+
+    /// User id
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn uid(mut self, uid: u32) -> Self {
+        self.config.uid = uid;
+        self
+    }
+
+    /// Group id
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn gid(mut self, gid: u32) -> Self {
+        self.config.gid = gid;
+        self
+    }
+
+    /// Directory permissions. Must be no greater than `0o777`.
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn dir_mode(mut self, dir_mode: u16) -> Self {
+        self.config.dir_mode = dir_mode;
+        self
+    }
+
+    /// File permissions. Must be no greater than `0o777`.
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn file_mode(mut self, file_mode: u16) -> Self {
+        self.config.file_mode = file_mode;
+        self
+    }
+
+    /// Allow delete
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn allow_delete(mut self, allow_delete: bool) -> Self {
+        self.config.allow_delete = allow_delete;
+        self
+    }
+
+    /// Delete the objects of unlinked files in batches, see [S3FilesystemConfig::unlink_batch_delay]
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn unlink_batch_delay(mut self, unlink_batch_delay: Option<Duration>) -> Self {
+        self.config.unlink_batch_delay = unlink_batch_delay;
+        self
+    }
+
+    /// Allow overwrite
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn allow_overwrite(mut self, allow_overwrite: bool) -> Self {
+        self.config.allow_overwrite = allow_overwrite;
+        self
+    }
+
+    /// Let files opened write-only without `O_TRUNC` overwrite part of their existing object, see
+    /// [S3FilesystemConfig::allow_partial_writes]. Needs [allow_overwrite](Self::allow_overwrite).
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn allow_partial_writes(mut self, allow_partial_writes: bool) -> Self {
+        self.config.allow_partial_writes = allow_partial_writes;
+        self
+    }
+
+    /// Storage class to be used for new object uploads. Must not be empty.
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn storage_class(mut self, storage_class: Option<String>) -> Self {
+        self.config.storage_class = storage_class;
+        self
+    }
+
+    /// S3 personality (for different S3 semantics)
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn s3_personality(mut self, s3_personality: S3Personality) -> Self {
+        self.config.s3_personality = s3_personality;
+        self
+    }
+
+    /// Server side encryption configuration. A KMS key can only be set with a KMS `sse_type`.
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn server_side_encryption(mut self, server_side_encryption: ServerSideEncryption) -> Self {
+        self.config.server_side_encryption = server_side_encryption;
+        self
+    }
+
+    /// Use additional checksums for uploads
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn use_upload_checksums(mut self, use_upload_checksums: bool) -> Self {
+        self.config.use_upload_checksums = use_upload_checksums;
+        self
+    }
+
+    /// Send a `Content-MD5` header with uploads
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn require_content_md5(mut self, require_content_md5: bool) -> Self {
+        self.config.require_content_md5 = require_content_md5;
+        self
+    }
+
+    /// Directory to stage new uploads under. Must be a valid directory name.
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn upload_staging_directory(mut self, upload_staging_directory: Option<String>) -> Self {
+        self.config.upload_staging_directory = upload_staging_directory;
+        self
+    }
+
+    /// Where to load a listing manifest from
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn listing_bootstrap(mut self, listing_bootstrap: Option<ListingBootstrap>) -> Self {
+        self.config.listing_bootstrap = listing_bootstrap;
+        self
+    }
+
+    /// Paths presented as empty files when there's no object for them
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn soft_missing_paths(mut self, soft_missing_paths: Vec<Glob>) -> Self {
+        self.config.soft_missing_paths = soft_missing_paths;
+        self
+    }
+
+    /// Paths of files reported as executable
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn executable_paths(mut self, executable_paths: Vec<Glob>) -> Self {
+        self.config.executable_paths = executable_paths;
+        self
+    }
+
+    /// Overrides of the cache config under particular paths
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn path_rules(mut self, path_rules: Vec<(PrefixPattern, PathOverrides)>) -> Self {
+        self.config.path_rules = path_rules;
+        self
+    }
+
+    /// Paths of files whose objects never change
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn immutable_key_patterns(mut self, immutable_key_patterns: Vec<PrefixPattern>) -> Self {
+        self.config.immutable_key_patterns = immutable_key_patterns;
+        self
+    }
+
+    /// Circuit breaker for requests to S3
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn circuit_breaker(mut self, circuit_breaker: Option<CircuitBreakerConfig>) -> Self {
+        self.config.circuit_breaker = circuit_breaker;
+        self
+    }
+
+    /// Separate circuit breaker for metadata requests to S3
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn metadata_circuit_breaker(mut self, metadata_circuit_breaker: Option<CircuitBreakerConfig>) -> Self {
+        self.config.metadata_circuit_breaker = metadata_circuit_breaker;
+        self
+    }
+
+    /// Memory of keys whose lookups keep failing
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn key_failures(mut self, key_failures: Option<KeyFailureConfig>) -> Self {
+        self.config.key_failures = key_failures;
+        self
+    }
+
+    /// Size reported for files whose object's size isn't known
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn unknown_object_size(mut self, unknown_object_size: u64) -> Self {
+        self.config.unknown_object_size = unknown_object_size;
+        self
+    }
+
+    /// Decompress objects with a supported `Content-Encoding` as they're read
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn transparent_decompress(mut self, transparent_decompress: bool) -> Self {
+        self.config.transparent_decompress = transparent_decompress;
+        self
+    }
+
+    /// Expose each file's ETag as an extended attribute
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn etag_xattr(mut self, etag_xattr: bool) -> Self {
+        self.config.etag_xattr = etag_xattr;
+        self
+    }
+
+    /// Report each entry's type in `readdir` replies
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn readdir_report_types(mut self, readdir_report_types: bool) -> Self {
+        self.config.readdir_report_types = readdir_report_types;
+        self
+    }
+
+    /// How many recently written bytes of each file being written to keep in memory
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn local_read_window(mut self, local_read_window: usize) -> Self {
+        self.config.local_read_window = local_read_window;
+        self
+    }
+
+    /// Preferred I/O size reported as every file's `st_blksize`. Must be greater than zero.
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn block_size(mut self, block_size: u32) -> Self {
+        self.config.block_size = block_size;
+        self
+    }
+
+    /// Let the kernel cache writes and send them to us in the background
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn writeback_cache(mut self, writeback_cache: bool) -> Self {
+        self.config.writeback_cache = writeback_cache;
+        self
+    }
+
+    /// Budget for the memory file data is kept in, shared with whatever else should count against it
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn memory_limiter(mut self, memory_limiter: Arc<MemoryLimiter>) -> Self {
+        self.config.memory_limiter = memory_limiter;
+        self
+    }
+
+    /// What to do with the data prefetched for a stream of reads once it pauses
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn idle_read_buffer_policy(mut self, idle_read_buffer_policy: IdleBufferPolicy) -> Self {
+        self.config.idle_read_buffer_policy = idle_read_buffer_policy;
+        self
+    }
+
+    /// How long a stream of reads goes without reads before the idle read buffer policy applies
+    #[must_use = "S3FilesystemConfigBuilder follows a builder pattern"]
+    pub fn idle_read_buffer_timeout(mut self, idle_read_buffer_timeout: Duration) -> Self {
+        self.config.idle_read_buffer_timeout = idle_read_buffer_timeout;
+        self
+    }
+
+    /// Check the configuration and return it, or the first invalid value found
+    pub fn build(self) -> Result<S3FilesystemConfig, InvalidConfigValue> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+/// The fields of [ServerSideEncryption] that can be set in a configuration file. The checksum
+/// that guards them is computed when they're loaded.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(super) struct ServerSideEncryptionFile {
+    sse_type: Option<String>,
+    sse_kms_key_id: Option<String>,
+}
+
+impl From<ServerSideEncryptionFile> for ServerSideEncryption {
+    fn from(file: ServerSideEncryptionFile) -> Self {
+        ServerSideEncryption::new(file.sse_type, file.sse_kms_key_id)
+    }
+}
+
+pub(crate) fn deserialize_duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    humantime::parse_duration(&value).map_err(|e| de::Error::custom(format_args!("invalid duration {value:?}: {e}")))
+}
+
+pub(super) fn deserialize_optional_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    deserialize_duration(deserializer).map(Some)
+}
+
+/// Parse globs matched against paths, where `*` and `?` don't match `/` but `**` does
+pub(super) fn deserialize_path_globs<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Glob>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|glob| {
+            GlobBuilder::new(&glob)
+                .literal_separator(true)
+                .build()
+                .map_err(|e| de::Error::custom(format_args!("invalid path glob {glob:?}: {}", e.kind())))
+        })
+        .collect()
+}
+
+/// Parse the `path_rules` table, which maps each prefix to the settings overridden under it
+pub(super) fn deserialize_path_rules<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<(PrefixPattern, PathOverrides)>, D::Error> {
+    BTreeMap::<String, PathOverrides>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(prefix, overrides)| {
+            PrefixPattern::new(&prefix)
+                .map(|pattern| (pattern, overrides))
+                .map_err(de::Error::custom)
+        })
+        .collect()
+}
+
+fn validate_sse(sse_type: Option<&str>, sse_kms_key_id: Option<&str>) -> Result<(), InvalidConfigValue> {
+    const SSE_TYPES: [&str; 3] = ["aws:kms", "aws:kms:dsse", "AES256"];
+
+    if let Some(sse_type) = sse_type {
+        if !SSE_TYPES.contains(&sse_type) {
+            return Err(InvalidConfigValue::new(
+                "sse_type",
+                sse_type,
+                format_args!("must be one of {SSE_TYPES:?}"),
+            ));
+        }
+    }
+    if let Some(sse_kms_key_id) = sse_kms_key_id {
+        // Same restrictions as the `--sse-kms-key-id` command-line argument
+        match sse_type {
+            None => {
+                return Err(InvalidConfigValue::new(
+                    "sse_kms_key_id",
+                    sse_kms_key_id,
+                    "requires `sse_type` to be set",
+                ))
+            }
+            Some("AES256") => {
+                return Err(InvalidConfigValue::new(
+                    "sse_kms_key_id",
+                    sse_kms_key_id,
+                    "can not be used with `sse_type` AES256",
+                ))
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(())
+}
+
+fn validate_circuit_breaker(
+    config: &CircuitBreakerConfig,
+    window_field: &'static str,
+    failure_threshold_field: &'static str,
+) -> Result<(), InvalidConfigValue> {
+    if config.window.is_zero() {
+        return Err(InvalidConfigValue::new(
+            window_field,
+            config.window,
+            "must be greater than zero",
+        ));
+    }
+    if !(config.failure_threshold > 0.0 && config.failure_threshold <= 1.0) {
+        return Err(InvalidConfigValue::new(
+            failure_threshold_field,
+            config.failure_threshold,
+            "must be greater than 0 and at most 1",
+        ));
+    }
+    Ok(())
+}
+
+fn validate_key_failures(
+    config: &KeyFailureConfig,
+    failure_threshold_field: &'static str,
+    max_keys_field: &'static str,
+) -> Result<(), InvalidConfigValue> {
+    if config.failure_threshold == 0 {
+        return Err(InvalidConfigValue::new(
+            failure_threshold_field,
+            config.failure_threshold,
+            "must be greater than zero",
+        ));
+    }
+    if config.max_keys == 0 {
+        return Err(InvalidConfigValue::new(
+            max_keys_field,
+            config.max_keys,
+            "must be greater than zero",
+        ));
+    }
+    Ok(())
+}
+
+fn validate_mode(field: &'static str, mode: u16) -> Result<(), InvalidConfigValue> {
+    if mode > 0o777 {
+        return Err(InvalidConfigValue::new(
+            field,
+            format_args!("{mode:#o}"),
+            "must be a permission mode no greater than 0o777",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test]
+    fn test_full_toml_config() {
+        let toml = r#"
+            readdir_size = 500
+            readdir_rewind_mode = "snapshot"
+            permission_change_mode = "reject"
+            directory_poll_interval = "30s"
+            negative_entry_replies = false
+            max_listing_depth = 8
+            max_buffered_dir_entries = 500
+            uid = 1000
+            gid = 1001
+            dir_mode = 0o750
+            file_mode = 0o640
+            allow_delete = true
+            unlink_batch_delay = "500ms"
+            allow_overwrite = true
+            storage_class = "INTELLIGENT_TIERING"
+            s3_personality = "express_one_zone"
+            use_upload_checksums = false
+            require_content_md5 = true
+            upload_staging_directory = ".inprogress"
+            listing_bootstrap = { file = "/var/cache/listing.jsonl.gz" }
+            soft_missing_paths = ["**/_SUCCESS", "config/*.json"]
+            executable_paths = ["**/*.sh", "bin/*"]
+            immutable_key_patterns = ["objects/sha256", "releases/"]
+            unknown_object_size = 4096
+            transparent_decompress = true
+            etag_xattr = true
+            readdir_report_types = false
+            local_read_window = 1048576
+            block_size = 1048576
+            writeback_cache = true
+            idle_read_buffer_policy = { shrink = { floor = 2097152 } }
+            idle_read_buffer_timeout = "30s"
+
+            [cache_config]
+            serve_lookup_from_cache = true
+            file_ttl = "5s"
+            dir_ttl = "1m"
+            negative_cache_size = 1000
+            pinned_prefixes = ["reference/", "static/"]
+            recently_written_ttl = "10ms"
+            recently_modified_window = "30s"
+            lookup_coalesce_window = "250ms"
+            negative_cache_max_bytes = 1048576
+            pinned_listings_max_entries = 100000
+            pinned_listings_max_bytes = 67108864
+            cache_sweep_interval = "30s"
+
+            [path_rules.archive]
+            serve_lookup_from_cache = true
+            file_ttl = "1h"
+
+            [path_rules."incoming/"]
+            serve_lookup_from_cache = false
+            dir_ttl = "0s"
+            negative_cache_ttl = "100ms"
+
+            [circuit_breaker]
+            window = "30s"
+            failure_threshold = 0.8
+            min_requests = 50
+            cooldown = "2s"
+
+            [metadata_circuit_breaker]
+            min_requests = 5
+            cooldown = "1s"
+
+            [key_failures]
+            failure_threshold = 5
+            cooldown = "1m"
+
+            [server_side_encryption]
+            sse_type = "aws:kms"
+            sse_kms_key_id = "some-key"
+        "#;
+        let config = S3FilesystemConfig::from_toml_str(toml).expect("config should be valid");
+        assert_full_config(config);
+    }
+
+    #[test]
+    fn test_full_json_config() {
+        let json = r#"{
+            "readdir_size": 500,
+            "readdir_rewind_mode": "snapshot",
+            "permission_change_mode": "reject",
+            "directory_poll_interval": "30s",
+            "negative_entry_replies": false,
+            "max_listing_depth": 8,
+            "max_buffered_dir_entries": 500,
+            "uid": 1000,
+            "gid": 1001,
+            "dir_mode": 488,
+            "file_mode": 416,
+            "allow_delete": true,
+            "unlink_batch_delay": "500ms",
+            "allow_overwrite": true,
+            "storage_class": "INTELLIGENT_TIERING",
+            "s3_personality": "express_one_zone",
+            "use_upload_checksums": false,
+            "require_content_md5": true,
+            "upload_staging_directory": ".inprogress",
+            "listing_bootstrap": { "file": "/var/cache/listing.jsonl.gz" },
+            "soft_missing_paths": ["**/_SUCCESS", "config/*.json"],
+            "executable_paths": ["**/*.sh", "bin/*"],
+            "immutable_key_patterns": ["objects/sha256", "releases/"],
+            "unknown_object_size": 4096,
+            "transparent_decompress": true,
+            "etag_xattr": true,
+            "readdir_report_types": false,
+            "local_read_window": 1048576,
+            "block_size": 1048576,
+            "writeback_cache": true,
+            "idle_read_buffer_policy": { "shrink": { "floor": 2097152 } },
+            "idle_read_buffer_timeout": "30s",
+            "cache_config": {
+                "serve_lookup_from_cache": true,
+                "file_ttl": "5s",
+                "dir_ttl": "1m",
+                "negative_cache_size": 1000,
+                "pinned_prefixes": ["reference/", "static/"],
+                "recently_written_ttl": "10ms",
+                "recently_modified_window": "30s",
+                "lookup_coalesce_window": "250ms",
+                "negative_cache_max_bytes": 1048576,
+                "pinned_listings_max_entries": 100000,
+                "pinned_listings_max_bytes": 67108864,
+                "cache_sweep_interval": "30s"
+            },
+            "path_rules": {
+                "archive": { "serve_lookup_from_cache": true, "file_ttl": "1h" },
+                "incoming/": {
+                    "serve_lookup_from_cache": false,
+                    "dir_ttl": "0s",
+                    "negative_cache_ttl": "100ms"
+                }
+            },
+            "circuit_breaker": {
+                "window": "30s",
+                "failure_threshold": 0.8,
+                "min_requests": 50,
+                "cooldown": "2s"
+            },
+            "metadata_circuit_breaker": {
+                "min_requests": 5,
+                "cooldown": "1s"
+            },
+            "key_failures": {
+                "failure_threshold": 5,
+                "cooldown": "1m"
+            },
+            "server_side_encryption": {
+                "sse_type": "aws:kms",
+                "sse_kms_key_id": "some-key"
+            }
+        }"#;
+        let config = S3FilesystemConfig::from_json_str(json).expect("config should be valid");
+        assert_full_config(config);
+    }
+
+    fn assert_full_config(config: S3FilesystemConfig) {
+        assert_eq!(config.readdir_size, 500);
+        assert_eq!(config.readdir_rewind_mode, RewindMode::Snapshot);
+        assert_eq!(config.permission_change_mode, PermissionChangeMode::Reject);
+        assert_eq!(config.directory_poll_interval, Some(Duration::from_secs(30)));
+        assert!(!config.negative_entry_replies);
+        assert_eq!(config.max_listing_depth, Some(8));
+        assert_eq!(config.max_buffered_dir_entries, Some(500));
+        assert_eq!(config.uid, 1000);
+        assert_eq!(config.gid, 1001);
+        assert_eq!(config.dir_mode, 0o750);
+        assert_eq!(config.file_mode, 0o640);
+        assert!(config.allow_delete);
+        assert_eq!(config.unlink_batch_delay, Some(Duration::from_millis(500)));
+        assert!(config.allow_overwrite);
+        assert_eq!(config.storage_class.as_deref(), Some("INTELLIGENT_TIERING"));
+        assert!(matches!(config.s3_personality, S3Personality::ExpressOneZone));
+        assert!(!config.use_upload_checksums);
+        assert!(config.require_content_md5);
+        assert_eq!(config.upload_staging_directory.as_deref(), Some(".inprogress"));
+        assert_eq!(
+            config.listing_bootstrap,
+            Some(ListingBootstrap::File("/var/cache/listing.jsonl.gz".into()))
+        );
+        assert_eq!(config.unknown_object_size, 4096);
+        assert!(config.transparent_decompress);
+        assert!(config.etag_xattr);
+        assert!(!config.readdir_report_types);
+        assert_eq!(config.local_read_window, 1024 * 1024);
+        assert_eq!(config.block_size, 1024 * 1024);
+        assert!(config.writeback_cache);
+        assert_eq!(
+            config.idle_read_buffer_policy,
+            IdleBufferPolicy::Shrink { floor: 2 * 1024 * 1024 }
+        );
+        assert_eq!(config.idle_read_buffer_timeout, Duration::from_secs(30));
+        let soft_missing_paths: Vec<_> = config.soft_missing_paths.iter().map(Glob::glob).collect();
+        assert_eq!(soft_missing_paths, ["**/_SUCCESS", "config/*.json"]);
+        assert!(config.soft_missing_paths[1].compile_matcher().is_match("config/a.json"));
+        assert!(!config.soft_missing_paths[1]
+            .compile_matcher()
+            .is_match("config/sub/a.json"));
+        let executable_paths: Vec<_> = config.executable_paths.iter().map(Glob::glob).collect();
+        assert_eq!(executable_paths, ["**/*.sh", "bin/*"]);
+        let immutable_key_patterns: Vec<_> = config
+            .immutable_key_patterns
+            .iter()
+            .map(PrefixPattern::as_str)
+            .collect();
+        assert_eq!(immutable_key_patterns, ["objects/sha256/", "releases/"]);
+        assert!(config.cache_config.serve_lookup_from_cache);
+        assert_eq!(config.cache_config.file_ttl, Duration::from_secs(5));
+        assert_eq!(config.cache_config.dir_ttl, Duration::from_secs(60));
+        assert_eq!(config.cache_config.negative_cache_size, 1000);
+        assert_eq!(config.cache_config.pinned_prefixes, ["reference/", "static/"]);
+        assert_eq!(config.cache_config.recently_written_ttl, Duration::from_millis(10));
+        assert_eq!(config.cache_config.recently_modified_window, Duration::from_secs(30));
+        assert_eq!(config.cache_config.lookup_coalesce_window, Duration::from_millis(250));
+        assert_eq!(config.cache_config.negative_cache_max_bytes, Some(1024 * 1024));
+        assert_eq!(config.cache_config.pinned_listings_max_entries, Some(100_000));
+        assert_eq!(config.cache_config.pinned_listings_max_bytes, Some(64 * 1024 * 1024));
+        assert_eq!(config.cache_config.cache_sweep_interval, Some(Duration::from_secs(30)));
+        let path_rules: Vec<_> = config
+            .path_rules
+            .iter()
+            .map(|(pattern, overrides)| (pattern.as_str(), *overrides))
+            .collect();
+        assert_eq!(
+            path_rules,
+            [
+                (
+                    "archive/",
+                    PathOverrides {
+                        serve_lookup_from_cache: Some(true),
+                        file_ttl: Some(Duration::from_secs(3600)),
+                        ..Default::default()
+                    }
+                ),
+                (
+                    "incoming/",
+                    PathOverrides {
+                        serve_lookup_from_cache: Some(false),
+                        dir_ttl: Some(Duration::ZERO),
+                        negative_cache_ttl: Some(Duration::from_millis(100)),
+                        ..Default::default()
+                    }
+                ),
+            ]
+        );
+        assert_eq!(
+            config.circuit_breaker,
+            Some(CircuitBreakerConfig {
+                window: Duration::from_secs(30),
+                failure_threshold: 0.8,
+                min_requests: 50,
+                cooldown: Duration::from_secs(2),
+            })
+        );
+        assert_eq!(
+            config.metadata_circuit_breaker,
+            Some(CircuitBreakerConfig {
+                min_requests: 5,
+                cooldown: Duration::from_secs(1),
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            config.key_failures,
+            Some(KeyFailureConfig {
+                failure_threshold: 5,
+                cooldown: Duration::from_secs(60),
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            config.server_side_encryption.into_inner().unwrap(),
+            (Some("aws:kms".to_owned()), Some("some-key".to_owned()))
+        );
+    }
+
+    #[test_case(""; "empty toml")]
+    #[test_case("[cache_config]"; "empty nested table")]
+    fn test_minimal_toml_config(toml: &str) {
+        let config = S3FilesystemConfig::from_toml_str(toml).expect("config should be valid");
+        assert_eq!(format!("{config:?}"), format!("{:?}", S3FilesystemConfig::default()));
+    }
+
+    #[test]
+    fn test_minimal_json_config() {
+        let config = S3FilesystemConfig::from_json_str("{}").expect("config should be valid");
+        assert_eq!(format!("{config:?}"), format!("{:?}", S3FilesystemConfig::default()));
+    }
+
+    #[test]
+    fn test_partial_config_keeps_defaults() {
+        let config = S3FilesystemConfig::from_toml_str("[cache_config]\nfile_ttl = \"1s\"").unwrap();
+        let default = S3FilesystemConfig::default();
+        assert_eq!(config.cache_config.file_ttl, Duration::from_secs(1));
+        assert_eq!(config.cache_config.dir_ttl, default.cache_config.dir_ttl);
+        assert_eq!(config.readdir_size, default.readdir_size);
+        assert_eq!(config.dir_mode, default.dir_mode);
+    }
+
+    #[test]
+    fn test_empty_circuit_breaker_uses_defaults() {
+        let config = S3FilesystemConfig::from_toml_str("[circuit_breaker]").unwrap();
+        assert_eq!(config.circuit_breaker, Some(CircuitBreakerConfig::default()));
+    }
+
+    #[test_case("readdir_sizee = 10", "unknown field `readdir_sizee`"; "unknown field")]
+    #[test_case("[cache_config]\nttl = \"1s\"", "unknown field `ttl`"; "unknown nested field")]
+    #[test_case("clock = \"system\"", "unknown field `clock`"; "field that can't be set from a file")]
+    #[test_case("readdir_size = \"ten\"", "invalid type: string \"ten\""; "wrong type")]
+    #[test_case("[cache_config]\nfile_ttl = \"soon\"", "invalid duration \"soon\""; "invalid duration")]
+    #[test_case("s3_personality = \"glacier\"", "unknown variant `glacier`"; "unknown personality")]
+    #[test_case("readdir_rewind_mode = \"replay\"", "unknown variant `replay`"; "unknown rewind mode")]
+    #[test_case("listing_bootstrap = { url = \"x\" }", "unknown variant `url`"; "unknown listing bootstrap source")]
+    #[test_case("soft_missing_paths = [\"a/[b\"]", "invalid path glob \"a/[b\""; "invalid glob")]
+    #[test_case("executable_paths = [\"bin/[\"]", "invalid path glob \"bin/[\""; "invalid executable glob")]
+    #[test_case("[path_rules.\"a/../b\"]", "invalid path prefix \"a/../b\""; "invalid path rule prefix")]
+    #[test_case("[path_rules.a]\nfile_ttl = \"later\"", "invalid duration \"later\""; "invalid path rule ttl")]
+    #[test_case("[path_rules.a]\nttl = \"1s\"", "unknown field `ttl`"; "unknown path rule field")]
+    #[test_case("immutable_key_patterns = [\"/objects\"]", "invalid path prefix \"/objects\""; "invalid immutable key pattern")]
+    #[test_case("[server_side_encryption]\nsse_key = \"key\"", "unknown field `sse_key`"; "unknown sse field")]
+    fn test_invalid_toml_config(toml: &str, expected_message: &str) {
+        let err = S3FilesystemConfig::from_toml_str(toml).expect_err("config should be invalid");
+        assert!(matches!(err, ConfigError::Toml(_)));
+        let message = err.to_string();
+        assert!(
+            message.contains(expected_message),
+            "expected {message:?} to contain {expected_message:?}"
+        );
+    }
+
+    #[test_case("readdir_size = 0", "invalid value 0 for `readdir_size`: must be greater than zero"; "zero readdir size")]
+    #[test_case("block_size = 0", "invalid value 0 for `block_size`: must be greater than zero"; "zero block size")]
+    #[test_case("max_buffered_dir_entries = 0", "invalid value 0 for `max_buffered_dir_entries`: must be greater than zero"; "zero max buffered dir entries")]
+    #[test_case("directory_poll_interval = \"0s\"", "invalid value 0ns for `directory_poll_interval`"; "zero poll interval")]
+    #[test_case("dir_mode = 0o1777", "invalid value 0o1777 for `dir_mode`"; "invalid mode")]
+    #[test_case("storage_class = \"\"", "invalid value \"\" for `storage_class`: must not be empty"; "empty storage class")]
+    #[test_case("upload_staging_directory = \"a/b\"", "invalid value \"a/b\" for `upload_staging_directory`"; "nested staging directory")]
+    #[test_case("allow_partial_writes = true", "invalid value true for `allow_partial_writes`: needs allow_overwrite"; "partial writes without overwrite")]
+    #[test_case("[circuit_breaker]\nfailure_threshold = 1.5", "invalid value 1.5 for `circuit_breaker.failure_threshold`"; "circuit breaker threshold above one")]
+    #[test_case("[metadata_circuit_breaker]\nwindow = \"0s\"", "invalid value 0ns for `metadata_circuit_breaker.window`"; "zero metadata circuit breaker window")]
+    #[test_case("[key_failures]\nfailure_threshold = 0", "invalid value 0 for `key_failures.failure_threshold`"; "zero key failure threshold")]
+    #[test_case("[server_side_encryption]\nsse_type = \"aws:foo\"", "invalid value \"aws:foo\" for `sse_type`"; "unknown sse type")]
+    #[test_case("[server_side_encryption]\nsse_type = \"AES256\"\nsse_kms_key_id = \"key\"", "invalid value \"key\" for `sse_kms_key_id`: can not be used with `sse_type` AES256"; "kms key with AES256")]
+    #[test_case("[server_side_encryption]\nsse_kms_key_id = \"key\"", "invalid value \"key\" for `sse_kms_key_id`: requires `sse_type` to be set"; "kms key without type")]
+    fn test_invalid_toml_config_value(toml: &str, expected_message: &str) {
+        let err = S3FilesystemConfig::from_toml_str(toml).expect_err("config should be invalid");
+        assert!(matches!(err, ConfigError::Invalid(_)));
+        let message = err.to_string();
+        assert!(
+            message.contains(expected_message),
+            "expected {message:?} to contain {expected_message:?}"
+        );
+    }
+
+    #[test_case(r#"{"uid": 1, "foo": 2}"#, "unknown field `foo`"; "unknown field")]
+    #[test_case(r#"{"cache_config": {"dir_ttl": "1 fortnight"}}"#, "invalid duration \"1 fortnight\""; "invalid duration")]
+    #[test_case(r#"{"path_rules": [{"prefix": "a"}]}"#, "invalid type: sequence"; "path rules as a list")]
+    #[test_case(r#""config""#, "invalid type: string \"config\""; "not an object")]
+    fn test_invalid_json_config(json: &str, expected_message: &str) {
+        let err = S3FilesystemConfig::from_json_str(json).expect_err("config should be invalid");
+        assert!(matches!(err, ConfigError::Json(_)));
+        let message = err.to_string();
+        assert!(
+            message.contains(expected_message),
+            "expected {message:?} to contain {expected_message:?}"
+        );
+    }
+
+    #[test]
+    fn test_invalid_json_config_value() {
+        let err = S3FilesystemConfig::from_json_str(r#"{"file_mode": 4096}"#).expect_err("config should be invalid");
+        assert!(matches!(err, ConfigError::Invalid(_)));
+        assert!(err.to_string().contains("invalid value 0o10000 for `file_mode`"));
+    }
+
+    #[test]
+    fn test_builder_accepts_valid_config() {
+        let config = S3FilesystemConfig::builder()
+            .readdir_size(500)
+            .directory_poll_interval(Some(Duration::from_secs(30)))
+            .max_buffered_dir_entries(Some(500))
+            .dir_mode(0o750)
+            .file_mode(0o640)
+            .storage_class(Some("STANDARD_IA".to_owned()))
+            .server_side_encryption(ServerSideEncryption::new(
+                Some("aws:kms".to_owned()),
+                Some("key".to_owned()),
+            ))
+            .upload_staging_directory(Some(".staging".to_owned()))
+            .circuit_breaker(Some(CircuitBreakerConfig {
+                failure_threshold: 1.0,
+                ..Default::default()
+            }))
+            .build()
+            .expect("config should be valid");
+        assert_eq!(config.readdir_size, 500);
+        assert_eq!(config.directory_poll_interval, Some(Duration::from_secs(30)));
+        assert_eq!(config.dir_mode, 0o750);
+        assert_eq!(config.storage_class.as_deref(), Some("STANDARD_IA"));
+        assert_eq!(config.upload_staging_directory.as_deref(), Some(".staging"));
+
+        S3FilesystemConfig::builder()
+            .build()
+            .expect("default config should be valid");
+    }
+
+    #[test_case(S3FilesystemConfig::builder().readdir_size(0), "invalid value 0 for `readdir_size`"; "zero readdir size")]
+    #[test_case(S3FilesystemConfig::builder().directory_poll_interval(Some(Duration::ZERO)), "invalid value 0ns for `directory_poll_interval`"; "zero poll interval")]
+    #[test_case(S3FilesystemConfig::builder().max_buffered_dir_entries(Some(0)), "invalid value 0 for `max_buffered_dir_entries`"; "zero max buffered dir entries")]
+    #[test_case(S3FilesystemConfig::builder().file_mode(0o4755), "invalid value 0o4755 for `file_mode`"; "invalid mode")]
+    #[test_case(S3FilesystemConfig::builder().storage_class(Some(String::new())), "invalid value \"\" for `storage_class`"; "empty storage class")]
+    #[test_case(S3FilesystemConfig::builder().upload_staging_directory(Some("..".to_owned())), "invalid value \"..\" for `upload_staging_directory`"; "invalid staging directory")]
+    #[test_case(S3FilesystemConfig::builder().server_side_encryption(ServerSideEncryption::new(Some("AES256".to_owned()), Some("key".to_owned()))), "can not be used with `sse_type` AES256"; "kms key with AES256")]
+    #[test_case(S3FilesystemConfig::builder().server_side_encryption(ServerSideEncryption::new(None, Some("key".to_owned()))), "requires `sse_type` to be set"; "kms key without type")]
+    #[test_case(S3FilesystemConfig::builder().server_side_encryption(ServerSideEncryption::new(Some("aws:foo".to_owned()), None)), "invalid value \"aws:foo\" for `sse_type`"; "unknown sse type")]
+    #[test_case(S3FilesystemConfig::builder().circuit_breaker(Some(CircuitBreakerConfig { failure_threshold: 0.0, ..Default::default() })), "invalid value 0.0 for `circuit_breaker.failure_threshold`"; "zero circuit breaker threshold")]
+    #[test_case(S3FilesystemConfig::builder().metadata_circuit_breaker(Some(CircuitBreakerConfig { window: Duration::ZERO, ..Default::default() })), "invalid value 0ns for `metadata_circuit_breaker.window`"; "zero metadata circuit breaker window")]
+    #[test_case(S3FilesystemConfig::builder().key_failures(Some(KeyFailureConfig { max_keys: 0, ..Default::default() })), "invalid value 0 for `key_failures.max_keys`"; "no remembered key failures")]
+    #[test_case(S3FilesystemConfig::builder().allow_partial_writes(true), "invalid value true for `allow_partial_writes`"; "partial writes without overwrite")]
+    fn test_builder_rejects_invalid_config(builder: S3FilesystemConfigBuilder, expected_message: &str) {
+        let message = builder.build().expect_err("config should be invalid").to_string();
+        assert!(
+            message.contains(expected_message),
+            "expected {message:?} to contain {expected_message:?}"
+        );
+    }
+}
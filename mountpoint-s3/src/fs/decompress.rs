@@ -0,0 +1,177 @@
+//! Transparent decompression of objects stored with a `Content-Encoding`.
+//!
+//! Objects are decompressed as they're streamed from S3, so the decompressed data is all we ever
+//! hold in memory. Reads of these objects go through [UnknownLengthRead](super::UnknownLengthRead),
+//! since we don't know how big the decompressed object is until we've reached its end.
+
+use std::io;
+
+use bytes::Bytes;
+use zstd::stream::raw::{Decoder as ZstdDecoder, InBuffer, Operation, OutBuffer};
+
+/// Size of the buffer decompressed data is written into before it's copied out
+const OUTPUT_CHUNK_SIZE: usize = 128 * 1024;
+
+/// A `Content-Encoding` we know how to decompress
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentEncoding {
+    Zstd,
+}
+
+impl ContentEncoding {
+    /// Parse a `Content-Encoding` header. Returns `None` for encodings we can't decompress,
+    /// including several encodings applied in sequence.
+    pub fn from_header(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "zstd" => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Create a decoder for a new stream in this encoding
+    pub fn decoder(self) -> io::Result<Decoder> {
+        match self {
+            Self::Zstd => Ok(Decoder {
+                zstd: ZstdDecoder::new()?,
+                output: vec![0u8; OUTPUT_CHUNK_SIZE].into_boxed_slice(),
+                input: Bytes::new(),
+                output_pending: false,
+                frame_complete: true,
+            }),
+        }
+    }
+}
+
+/// Decompresses a single stream, fed to it in order. Input is decompressed only as fast as its
+/// output is asked for, so a small input that decompresses to a lot of data is never decompressed
+/// all at once.
+pub struct Decoder {
+    zstd: ZstdDecoder<'static>,
+    output: Box<[u8]>,
+    /// Input received but not decompressed yet
+    input: Bytes,
+    /// Whether the last call filled the output buffer, so the decoder may have more output even
+    /// once it's consumed all the input
+    output_pending: bool,
+    /// Whether the data so far ends at the end of a frame, so the stream can end here
+    frame_complete: bool,
+}
+
+impl Decoder {
+    /// Add the next part of the stream. Must only be called once the decoder
+    /// [is drained](Self::is_drained).
+    pub fn push(&mut self, input: Bytes) {
+        debug_assert!(self.is_drained(), "decoder still holds input");
+        self.input = input;
+    }
+
+    /// Whether all the input so far has been decompressed and returned
+    pub fn is_drained(&self) -> bool {
+        self.input.is_empty() && !self.output_pending
+    }
+
+    /// Decompress the input received so far, stopping once at least `max_output` bytes (rounded
+    /// up to the decoder's output buffer) have been decompressed. Input left over is decompressed
+    /// by the next call.
+    pub fn decode(&mut self, max_output: usize) -> io::Result<Vec<u8>> {
+        let mut decoded = Vec::new();
+        let mut consumed = 0;
+        while decoded.len() < max_output {
+            let (hint, read, written) = {
+                let mut input = InBuffer::around(&self.input[consumed..]);
+                let mut output = OutBuffer::around(&mut self.output[..]);
+                let hint = self.zstd.run(&mut input, &mut output)?;
+                (hint, input.pos(), output.pos())
+            };
+            consumed += read;
+            decoded.extend_from_slice(&self.output[..written]);
+            self.frame_complete = hint == 0;
+            self.output_pending = written == self.output.len();
+            if consumed == self.input.len() && !self.output_pending {
+                break;
+            }
+        }
+        self.input = self.input.slice(consumed..);
+        Ok(decoded)
+    }
+
+    /// Check the stream ended at the end of a frame, rather than being truncated
+    pub fn finish(&self) -> io::Result<()> {
+        if self.frame_complete {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "compressed stream ended mid-frame",
+            ))
+        }
+    }
+}
+
+impl std::fmt::Debug for Decoder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Decoder")
+            .field("input_len", &self.input.len())
+            .field("frame_complete", &self.frame_complete)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_header() {
+        assert_eq!(ContentEncoding::from_header("zstd"), Some(ContentEncoding::Zstd));
+        assert_eq!(ContentEncoding::from_header(" ZSTD "), Some(ContentEncoding::Zstd));
+        assert_eq!(ContentEncoding::from_header("identity"), None);
+        assert_eq!(ContentEncoding::from_header("zstd, zstd"), None);
+    }
+
+    #[test]
+    fn test_decode_in_pieces() {
+        let data: Vec<u8> = (0..1_000_000u32).map(|i| (i % 251) as u8).collect();
+        let compressed = zstd::encode_all(&data[..], 3).unwrap();
+
+        let mut decoder = ContentEncoding::Zstd.decoder().unwrap();
+        let mut decoded = Vec::new();
+        for chunk in compressed.chunks(100) {
+            decoder.push(Bytes::copy_from_slice(chunk));
+            decoded.extend(decoder.decode(usize::MAX).unwrap());
+            assert!(decoder.is_drained());
+        }
+        decoder.finish().unwrap();
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn test_truncated_stream() {
+        let data = vec![42u8; 100_000];
+        let compressed = zstd::encode_all(&data[..], 3).unwrap();
+
+        let mut decoder = ContentEncoding::Zstd.decoder().unwrap();
+        decoder.push(Bytes::copy_from_slice(&compressed[..compressed.len() - 4]));
+        decoder.decode(usize::MAX).unwrap();
+        decoder.finish().expect_err("truncated stream should fail");
+    }
+
+    #[test]
+    fn test_decode_bounded_output() {
+        let data = vec![0u8; 64 * 1024 * 1024];
+        let compressed = zstd::encode_all(&data[..], 3).unwrap();
+        assert!(compressed.len() < OUTPUT_CHUNK_SIZE);
+
+        let mut decoder = ContentEncoding::Zstd.decoder().unwrap();
+        decoder.push(compressed.into());
+        let mut total = 0;
+        while !decoder.is_drained() {
+            let decoded = decoder.decode(1).unwrap();
+            assert!(decoded.len() <= OUTPUT_CHUNK_SIZE);
+            assert!(decoded.iter().all(|&b| b == 0));
+            total += decoded.len();
+        }
+        decoder.finish().unwrap();
+        assert_eq!(total, data.len());
+    }
+}
@@ -2,8 +2,10 @@
 
 use tracing::Level;
 
+use mountpoint_s3_client::error::{GetObjectError, ObjectClientError, PutObjectError};
+
 use crate::inode::InodeError;
-use crate::upload::UploadWriteError;
+use crate::upload::{PartialWriteError, UploadWriteError};
 
 /// Generate an error that includes a conversion to a libc errno for use in replies to FUSE.
 ///
@@ -31,6 +33,12 @@ use crate::upload::UploadWriteError;
 /// ```ignore
 /// return Err(err!(libc::EINVAL, "cannot use O_SYNC on file handle {:?}", fh));
 /// ```
+///
+/// Errors are logged at `WARN` level unless a level is given after the errno (and source, if any):
+///
+/// ```ignore
+/// return Err(err!(libc::ENODATA, Level::DEBUG, "no extended attribute {:?}", name));
+/// ```
 #[macro_export]
 macro_rules! err {
     // Base case -- don't use directly
@@ -61,6 +69,12 @@ macro_rules! err {
     ($errno:expr, $message:literal) => {
         err!($errno, __source:None, ::tracing::Level::WARN, $message,)
     };
+    ($errno:expr, $level:expr, $message:literal, $($args:tt)*) => {
+        err!($errno, __source:None, $level, $message, $($args)*)
+    };
+    ($errno:expr, $level:expr, $message:literal) => {
+        err!($errno, __source:None, $level, $message,)
+    };
 }
 
 /// A dynamic error type returned by the Mountpoint filesystem. See the [err!] macro for more
@@ -87,12 +101,17 @@ impl std::fmt::Display for Error {
 impl From<InodeError> for Error {
     fn from(err: InodeError) -> Self {
         let errno = err.to_errno();
+        // The circuit breaker already warned when it opened, so don't warn for every request it fails
+        let level = match err {
+            InodeError::MetadataUnavailable(_) => Level::DEBUG,
+            // We are having WARN as the default level of logging for fuse errors
+            _ => Level::WARN,
+        };
         Error {
             errno,
             message: String::from("inode error"),
             source: Some(anyhow::anyhow!(err)),
-            // We are having WARN as the default level of logging for fuse errors
-            level: Level::WARN,
+            level,
         }
     }
 }
@@ -110,6 +129,19 @@ impl<E: std::error::Error + Send + Sync + 'static> From<UploadWriteError<E>> for
     }
 }
 
+impl<E: std::error::Error + Send + Sync + 'static> From<PartialWriteError<E>> for Error {
+    fn from(err: PartialWriteError<E>) -> Self {
+        let errno = err.to_errno();
+        Error {
+            errno,
+            message: String::from("partial write error"),
+            source: Some(anyhow::anyhow!(err)),
+            // We are having WARN as the default level of logging for fuse errors
+            level: Level::WARN,
+        }
+    }
+}
+
 /// Errors that can be converted to a raw OS error (errno)
 pub trait ToErrno {
     fn to_errno(&self) -> libc::c_int;
@@ -125,9 +157,12 @@ impl ToErrno for InodeError {
     fn to_errno(&self) -> libc::c_int {
         match self {
             InodeError::ClientError(_) => libc::EIO,
+            InodeError::AccessDenied(_) => libc::EACCES,
+            InodeError::MetadataUnavailable(_) => libc::EAGAIN,
             InodeError::FileDoesNotExist(_, _) => libc::ENOENT,
             InodeError::InodeDoesNotExist(_) => libc::ENOENT,
             InodeError::InvalidFileName(_) => libc::EINVAL,
+            InodeError::NameTooLong(_) => libc::ENAMETOOLONG,
             InodeError::NotADirectory(_) => libc::ENOTDIR,
             InodeError::IsDirectory(_) => libc::EISDIR,
             InodeError::FileAlreadyExists(_) => libc::EEXIST,
@@ -154,6 +189,25 @@ impl<E: std::error::Error> ToErrno for UploadWriteError<E> {
             UploadWriteError::PutRequestFailed(_) => libc::EIO,
             UploadWriteError::OutOfOrderWrite { .. } => libc::EINVAL,
             UploadWriteError::ObjectTooBig { .. } => libc::EFBIG,
+            UploadWriteError::OutOfMemory { .. } => libc::ENOMEM,
+        }
+    }
+}
+
+impl<E: std::error::Error> ToErrno for PartialWriteError<E> {
+    fn to_errno(&self) -> libc::c_int {
+        match self {
+            PartialWriteError::Unsupported => libc::EOPNOTSUPP,
+            PartialWriteError::UnalignedWrite { .. } => libc::EINVAL,
+            PartialWriteError::ExtendsObject { .. } => libc::EINVAL,
+            // The object changed since the file was opened
+            PartialWriteError::GetFailed(ObjectClientError::ServiceError(GetObjectError::PreconditionFailed))
+            | PartialWriteError::PutFailed(ObjectClientError::ServiceError(PutObjectError::PreconditionFailed)) => {
+                libc::ESTALE
+            }
+            PartialWriteError::GetFailed(_) => libc::EIO,
+            PartialWriteError::PutFailed(_) => libc::EIO,
+            PartialWriteError::SseCorruptedError(_) => libc::EIO,
         }
     }
 }
@@ -0,0 +1,75 @@
+//! Types shared between the file system and the _fuser_ bindings.
+//!
+//! With the `fuse` feature, these are _fuser_'s own types, so that [crate::fuse] can hand them to
+//! the kernel directly. Without it, they're identical stand-ins, so that [super::S3Filesystem] can
+//! be used as a library without depending on _fuser_ (and so on libfuse).
+
+#[cfg(feature = "fuse")]
+pub use fuser::consts::FOPEN_DIRECT_IO;
+#[cfg(feature = "fuse")]
+pub use fuser::{FileAttr, FileType};
+
+#[cfg(not(feature = "fuse"))]
+pub use self::standalone::*;
+
+#[cfg(not(feature = "fuse"))]
+mod standalone {
+    use std::time::SystemTime;
+
+    /// Bypass the page cache for this open file
+    pub const FOPEN_DIRECT_IO: u32 = 1 << 0;
+
+    /// File types
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    pub enum FileType {
+        /// Named pipe (S_IFIFO)
+        NamedPipe,
+        /// Character device (S_IFCHR)
+        CharDevice,
+        /// Block device (S_IFBLK)
+        BlockDevice,
+        /// Directory (S_IFDIR)
+        Directory,
+        /// Regular file (S_IFREG)
+        RegularFile,
+        /// Symbolic link (S_IFLNK)
+        Symlink,
+        /// Unix domain socket (S_IFSOCK)
+        Socket,
+    }
+
+    /// File attributes
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    pub struct FileAttr {
+        /// Inode number
+        pub ino: u64,
+        /// Size in bytes
+        pub size: u64,
+        /// Size in blocks
+        pub blocks: u64,
+        /// Time of last access
+        pub atime: SystemTime,
+        /// Time of last modification
+        pub mtime: SystemTime,
+        /// Time of last change
+        pub ctime: SystemTime,
+        /// Time of creation (macOS only)
+        pub crtime: SystemTime,
+        /// Kind of file (directory, file, pipe, etc)
+        pub kind: FileType,
+        /// Permissions
+        pub perm: u16,
+        /// Number of hard links
+        pub nlink: u32,
+        /// User id
+        pub uid: u32,
+        /// Group id
+        pub gid: u32,
+        /// Rdev
+        pub rdev: u32,
+        /// Block size
+        pub blksize: u32,
+        /// Flags (macOS only, see chflags(2))
+        pub flags: u32,
+    }
+}
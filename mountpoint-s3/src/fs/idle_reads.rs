@@ -0,0 +1,180 @@
+//! Background release of the data prefetched for read handles that have stopped reading.
+//!
+//! Once a stream of reads has gone [S3FilesystemConfig::idle_read_buffer_timeout] without reads,
+//! the [IdleBufferPolicy] in [S3FilesystemConfig::idle_read_buffer_policy] decides what happens to
+//! the data prefetched for it. A read applies the policy itself if it arrives after a pause, but
+//! until then the stream would keep everything it prefetched. The [IdleReadSweeper] applies the
+//! policy to every stream that's paused instead, so the memory is released during the pause.
+//!
+//! [S3FilesystemConfig::idle_read_buffer_timeout]: super::S3FilesystemConfig::idle_read_buffer_timeout
+//! [S3FilesystemConfig::idle_read_buffer_policy]: super::S3FilesystemConfig::idle_read_buffer_policy
+
+use std::ops::ControlFlow;
+use std::time::{Duration, Instant};
+
+use tracing::trace;
+
+use crate::clock::Clock;
+use crate::periodic::PeriodicTask;
+use crate::prefetch::IdleBufferPolicy;
+use crate::sync::{Arc, Mutex};
+
+/// Longest time between sweeps. Shorter timeouts are swept more often.
+const MAX_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A stream of reads whose prefetched data can be released while it's paused
+pub trait IdleRead: Send + Sync {
+    /// Apply `policy` if the stream has gone `timeout` without reads by `now`, unless it already
+    /// has during this pause or a read is in progress. Returns whether any data was released.
+    fn release_if_idle(&self, policy: IdleBufferPolicy, timeout: Duration, now: Instant) -> bool;
+}
+
+/// The read streams of a file system, for the [IdleReadSweeper] to release
+#[derive(Default)]
+pub struct IdleReads {
+    streams: Mutex<Vec<Arc<dyn IdleRead>>>,
+}
+
+impl IdleReads {
+    pub fn register(&self, stream: Arc<dyn IdleRead>) {
+        self.streams.lock().unwrap().push(stream);
+    }
+
+    /// Apply `policy` to every stream that has gone `timeout` without reads by `now`, and forget
+    /// the streams nothing else uses any more. Returns how many streams released data.
+    pub fn release(&self, policy: IdleBufferPolicy, timeout: Duration, now: Instant) -> usize {
+        let streams = {
+            let mut streams = self.streams.lock().unwrap();
+            streams.retain(|stream| Arc::strong_count(stream) > 1);
+            streams.clone()
+        };
+        streams
+            .iter()
+            .filter(|stream| stream.release_if_idle(policy, timeout, now))
+            .count()
+    }
+
+    /// Number of streams registered
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.streams.lock().unwrap().len()
+    }
+}
+
+impl std::fmt::Debug for IdleReads {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IdleReads")
+            .field("streams", &self.streams.lock().unwrap().len())
+            .finish()
+    }
+}
+
+/// Handle to a background thread that periodically releases the prefetched data of idle reads.
+/// The thread is shut down when the handle is dropped.
+#[derive(Debug)]
+pub struct IdleReadSweeper {
+    _task: PeriodicTask,
+}
+
+impl IdleReadSweeper {
+    /// Start applying `policy` to the streams in `reads`, often enough to release a stream's data
+    /// soon after it's gone `idle_timeout` without reads
+    pub fn start(
+        reads: Arc<IdleReads>,
+        policy: IdleBufferPolicy,
+        idle_timeout: Duration,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        let interval = idle_timeout.min(MAX_SWEEP_INTERVAL);
+        let task = PeriodicTask::start("idle-read-sweeper", interval, move || {
+            let released = reads.release(policy, idle_timeout, clock.now());
+            trace!(released, "released prefetched data of idle reads");
+            ControlFlow::Continue(())
+        })
+        .expect("failed to spawn idle read sweeper thread");
+        Self { _task: task }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    /// A stream that counts the times it's released, and is idle once `last_read` is `timeout` ago
+    #[derive(Debug)]
+    struct TestStream {
+        last_read: Instant,
+        releases: AtomicUsize,
+    }
+
+    impl IdleRead for TestStream {
+        fn release_if_idle(&self, _policy: IdleBufferPolicy, timeout: Duration, now: Instant) -> bool {
+            if now.saturating_duration_since(self.last_read) < timeout {
+                return false;
+            }
+            self.releases.fetch_add(1, Ordering::SeqCst);
+            true
+        }
+    }
+
+    fn test_stream(clock: &MockClock) -> Arc<TestStream> {
+        Arc::new(TestStream {
+            last_read: clock.now(),
+            releases: AtomicUsize::new(0),
+        })
+    }
+
+    #[test]
+    fn releases_idle_streams() {
+        const TIMEOUT: Duration = Duration::from_secs(30);
+        let clock = MockClock::new();
+        let reads = IdleReads::default();
+        let first = test_stream(&clock);
+        reads.register(first.clone());
+        clock.advance(Duration::from_secs(10));
+        let second = test_stream(&clock);
+        reads.register(second.clone());
+
+        clock.advance(TIMEOUT - Duration::from_secs(10) - Duration::from_millis(1));
+        assert_eq!(reads.release(IdleBufferPolicy::Reset, TIMEOUT, clock.now()), 0);
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(reads.release(IdleBufferPolicy::Reset, TIMEOUT, clock.now()), 1);
+        assert_eq!(first.releases.load(Ordering::SeqCst), 1);
+        assert_eq!(second.releases.load(Ordering::SeqCst), 0);
+
+        // Streams are forgotten once they're closed
+        drop(first);
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(reads.release(IdleBufferPolicy::Reset, TIMEOUT, clock.now()), 1);
+        assert_eq!(second.releases.load(Ordering::SeqCst), 1);
+        assert_eq!(reads.len(), 1);
+    }
+
+    #[test]
+    fn sweeper_releases_in_background() {
+        let clock = Arc::new(MockClock::new());
+        let reads = Arc::new(IdleReads::default());
+        let stream = test_stream(&clock);
+        reads.register(stream.clone());
+        clock.advance(Duration::from_secs(1));
+
+        let sweeper = IdleReadSweeper::start(
+            reads.clone(),
+            IdleBufferPolicy::Reset,
+            Duration::from_millis(1),
+            clock.clone(),
+        );
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while stream.releases.load(Ordering::SeqCst) == 0 {
+            assert!(Instant::now() < deadline, "sweeper should release the idle stream");
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        // Dropping the sweeper stops its thread
+        drop(sweeper);
+        assert_eq!(Arc::strong_count(&reads), 1);
+    }
+}
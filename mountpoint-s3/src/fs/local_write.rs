@@ -0,0 +1,212 @@
+//! Reads of files that are being written through this mount.
+//!
+//! Uploads stream written data straight into a PutObject request, and S3 won't serve any of it
+//! until the upload completes. So that local readers (like `tail -f` on a log being written) can
+//! still see a file grow, the writer keeps a copy of the most recent data it wrote, and read
+//! handles opened on the file while it's being written are served from that copy. S3 doesn't allow
+//! reading the parts of an incomplete multipart upload, so reads of data that has dropped out of
+//! the window wait for the upload to complete, and then read it back from the new object.
+
+use std::collections::VecDeque;
+
+use bytes::{Bytes, BytesMut};
+
+use crate::sync::{AsyncOnceCell, Mutex, MutexGuard};
+
+/// A file being written through this mount, shared by its write handle and the read handles opened
+/// on it while it's being written
+#[derive(Debug)]
+pub struct LocalWrite {
+    buffer: Mutex<LocalWriteBuffer>,
+    /// Whether the upload succeeded, set once it's finished
+    uploaded: AsyncOnceCell<bool>,
+}
+
+impl LocalWrite {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            buffer: Mutex::new(LocalWriteBuffer::new(capacity)),
+            uploaded: AsyncOnceCell::new(),
+        }
+    }
+
+    /// The most recently written data
+    pub fn buffer(&self) -> MutexGuard<'_, LocalWriteBuffer> {
+        self.buffer.lock().unwrap()
+    }
+
+    /// Record that the upload finished, successfully or not, and wake the reads waiting for it
+    pub async fn finish(&self, uploaded: bool) {
+        let _ = self.uploaded.set(uploaded).await;
+    }
+
+    /// Wait for the upload to finish, and return whether it succeeded
+    pub async fn wait_for_upload(&self) -> bool {
+        *self.uploaded.wait().await
+    }
+}
+
+/// The most recently written data of a file being uploaded, up to a fixed number of bytes
+#[derive(Debug)]
+pub struct LocalWriteBuffer {
+    /// Most bytes of written data to keep
+    capacity: usize,
+    /// Offset in the file of the first byte of `chunks`
+    start: u64,
+    /// The data, in the order it was written
+    chunks: VecDeque<Bytes>,
+    /// Total length of `chunks`
+    len: usize,
+    /// Size of the file, which can be past the end of the data if it was extended with zeros
+    size: u64,
+}
+
+impl LocalWriteBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            start: 0,
+            chunks: VecDeque::new(),
+            len: 0,
+            size: 0,
+        }
+    }
+
+    /// Size of the file written so far
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Offset just past the last byte of written data we hold
+    fn end(&self) -> u64 {
+        self.start + self.len as u64
+    }
+
+    /// Record a write at `offset`. Writes are sequential, but may skip over zeros the file was
+    /// extended with.
+    pub fn write(&mut self, offset: u64, data: &[u8]) {
+        if offset > self.end() {
+            let zeros = offset - self.end();
+            if zeros >= self.capacity as u64 {
+                self.chunks.clear();
+                self.len = 0;
+                self.start = offset;
+            } else {
+                self.push(Bytes::from(vec![0u8; zeros as usize]));
+            }
+        }
+        debug_assert_eq!(offset, self.end(), "writes must be sequential");
+        self.push(Bytes::copy_from_slice(data));
+        self.size = self.size.max(self.end());
+    }
+
+    /// Record that the file was extended to `size` bytes with zeros
+    pub fn extend_to(&mut self, size: u64) {
+        self.size = self.size.max(size);
+    }
+
+    /// Record that the file was truncated to `size` bytes, which can't be before the end of the
+    /// written data
+    pub fn truncate_to(&mut self, size: u64) {
+        debug_assert!(size >= self.end(), "can't truncate written data");
+        self.size = size.max(self.end());
+    }
+
+    fn push(&mut self, chunk: Bytes) {
+        self.len += chunk.len();
+        self.chunks.push_back(chunk);
+        while self.len > self.capacity {
+            let excess = self.len - self.capacity;
+            let front = self.chunks.front_mut().expect("buffer holds more than its capacity");
+            if front.len() <= excess {
+                self.start += front.len() as u64;
+                self.len -= front.len();
+                self.chunks.pop_front();
+            } else {
+                let _ = front.split_to(excess);
+                self.start += excess as u64;
+                self.len -= excess;
+            }
+        }
+    }
+
+    /// Read up to `size` bytes at `offset`, as far as the file has been written so far. Returns
+    /// `None` if some of the data has already dropped out of the window.
+    pub fn read(&self, offset: u64, size: usize) -> Option<Bytes> {
+        let end = offset.saturating_add(size as u64).min(self.size);
+        if offset >= end {
+            return Some(Bytes::new());
+        }
+        if offset < self.start {
+            return None;
+        }
+
+        let mut bytes = BytesMut::with_capacity((end - offset) as usize);
+        let mut chunk_start = self.start;
+        for chunk in &self.chunks {
+            let chunk_end = chunk_start + chunk.len() as u64;
+            if chunk_end > offset && chunk_start < end {
+                let from = offset.saturating_sub(chunk_start) as usize;
+                let to = (end.min(chunk_end) - chunk_start) as usize;
+                bytes.extend_from_slice(&chunk[from..to]);
+            }
+            chunk_start = chunk_end;
+        }
+        // Anything past the written data is zeros the file was extended with
+        bytes.resize((end - offset) as usize, 0);
+        Some(bytes.freeze())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_written_data() {
+        let mut buffer = LocalWriteBuffer::new(1024);
+        buffer.write(0, b"hello ");
+        buffer.write(6, b"world");
+        assert_eq!(buffer.size(), 11);
+        assert_eq!(&buffer.read(0, 100).unwrap()[..], b"hello world");
+        assert_eq!(&buffer.read(4, 4).unwrap()[..], b"o wo");
+        assert!(buffer.read(11, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn read_extended_zeros() {
+        let mut buffer = LocalWriteBuffer::new(1024);
+        buffer.write(0, b"abc");
+        buffer.extend_to(6);
+        assert_eq!(&buffer.read(0, 10).unwrap()[..], b"abc\0\0\0");
+        // A write after the zeros fills them in
+        buffer.write(8, b"de");
+        assert_eq!(&buffer.read(0, 10).unwrap()[..], b"abc\0\0\0\0\0de");
+    }
+
+    #[test]
+    fn evicts_oldest_data() {
+        let mut buffer = LocalWriteBuffer::new(8);
+        buffer.write(0, b"0123456");
+        buffer.write(7, b"789ab");
+        assert_eq!(buffer.size(), 12);
+        assert_eq!(&buffer.read(4, 100).unwrap()[..], b"456789ab");
+        assert!(buffer.read(3, 2).is_none(), "evicted data can't be read");
+    }
+
+    #[tokio::test]
+    async fn reads_wait_for_upload() {
+        let write = std::sync::Arc::new(LocalWrite::new(8));
+        let waiting = tokio::spawn({
+            let write = write.clone();
+            async move { write.wait_for_upload().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+        write.finish(true).await;
+        assert!(waiting.await.unwrap());
+        // Later reads don't wait, and the outcome can't change
+        write.finish(false).await;
+        assert!(write.wait_for_upload().await);
+    }
+}
@@ -0,0 +1,342 @@
+//! Listing manifests, which record every object under a mount's prefix so that a later mount of
+//! the same prefix can serve its first directory listings without asking S3.
+//!
+//! A manifest has one JSON object per line, holding the key, size, ETag, and last modified time (in
+//! milliseconds since the Unix epoch) of a single object, and optionally its storage class:
+//!
+//! ```text
+//! {"key":"data/a.csv","size":1024,"etag":"\"abc\"","mtime":1712000000000}
+//! ```
+//!
+//! Manifests may be gzip-compressed; compressed manifests are recognized by their contents.
+
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+
+use flate2::write::{GzDecoder, GzEncoder};
+use flate2::Compression;
+use futures::{pin_mut, StreamExt};
+use mountpoint_s3_client::types::ObjectInfo;
+use mountpoint_s3_client::ObjectClient;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use time::OffsetDateTime;
+
+use super::ListingBootstrap;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Error returned when reading or writing a listing manifest
+#[derive(Debug, Error)]
+pub enum ManifestError {
+    #[error("manifest IO failed")]
+    Io(#[from] std::io::Error),
+    #[error("failed to download manifest object")]
+    Download(#[source] anyhow::Error),
+    #[error("invalid manifest entry on line {line}")]
+    InvalidEntry {
+        line: usize,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("invalid last modified time {mtime} on line {line}")]
+    InvalidMtime { line: usize, mtime: i64 },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestEntry {
+    key: String,
+    size: u64,
+    etag: String,
+    /// Milliseconds since the Unix epoch
+    mtime: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    storage_class: Option<String>,
+}
+
+/// How much of a manifest file is read at a time
+const FILE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Load the manifest a [ListingBootstrap] points at. The manifest is parsed as it's read, so only
+/// the objects it lists are held in memory, not its contents.
+pub(super) async fn load_manifest<Client: ObjectClient>(
+    client: &Client,
+    bucket: &str,
+    source: &ListingBootstrap,
+) -> Result<Vec<ObjectInfo>, ManifestError> {
+    let mut reader = ManifestReader::default();
+    match source {
+        ListingBootstrap::File(path) => {
+            let mut file = File::open(path)?;
+            let mut buf = vec![0u8; FILE_CHUNK_SIZE];
+            loop {
+                let n = match file.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e.into()),
+                };
+                reader.push(&buf[..n])?;
+            }
+        }
+        ListingBootstrap::Object(key) => {
+            let request = client
+                .get_object(bucket, key, None, None)
+                .await
+                .map_err(|e| ManifestError::Download(anyhow::Error::new(e)))?;
+            pin_mut!(request);
+            while let Some(part) = request.next().await {
+                let (_offset, body) = part.map_err(|e| ManifestError::Download(anyhow::Error::new(e)))?;
+                reader.push(&body)?;
+            }
+        }
+    }
+    reader.finish()
+}
+
+/// Parse a manifest, which may be gzip-compressed
+pub fn read_manifest(data: &[u8]) -> Result<Vec<ObjectInfo>, ManifestError> {
+    let mut reader = ManifestReader::default();
+    reader.push(data)?;
+    reader.finish()
+}
+
+/// Parses a manifest from the chunks of its contents, as they arrive
+#[derive(Default)]
+struct ManifestReader {
+    decoder: Decoder,
+    lines: LineParser,
+}
+
+enum Decoder {
+    /// Not enough of the manifest has arrived yet to tell whether it's compressed
+    Unknown(Vec<u8>),
+    Plain,
+    Gzip(GzDecoder<Vec<u8>>),
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::Unknown(Vec::new())
+    }
+}
+
+impl ManifestReader {
+    /// Parse the next chunk of the manifest
+    fn push(&mut self, data: &[u8]) -> Result<(), ManifestError> {
+        if let Decoder::Unknown(prefix) = &mut self.decoder {
+            prefix.extend_from_slice(data);
+            if prefix.len() < GZIP_MAGIC.len() {
+                return Ok(());
+            }
+            let prefix = std::mem::take(prefix);
+            self.decoder = if prefix.starts_with(&GZIP_MAGIC) {
+                Decoder::Gzip(GzDecoder::new(Vec::new()))
+            } else {
+                Decoder::Plain
+            };
+            return self.push(&prefix);
+        }
+        match &mut self.decoder {
+            Decoder::Unknown(_) => unreachable!("handled above"),
+            Decoder::Plain => self.lines.push(data),
+            Decoder::Gzip(decoder) => {
+                decoder.write_all(data)?;
+                let decompressed = std::mem::take(decoder.get_mut());
+                self.lines.push(&decompressed)
+            }
+        }
+    }
+
+    /// Parse whatever is left of the manifest once all of it has arrived
+    fn finish(mut self) -> Result<Vec<ObjectInfo>, ManifestError> {
+        match self.decoder {
+            Decoder::Unknown(prefix) => self.lines.push(&prefix)?,
+            Decoder::Plain => {}
+            Decoder::Gzip(decoder) => {
+                let decompressed = decoder.finish()?;
+                self.lines.push(&decompressed)?;
+            }
+        }
+        self.lines.finish()
+    }
+}
+
+/// Splits (uncompressed) manifest data into lines, and parses each one as it's completed
+#[derive(Default)]
+struct LineParser {
+    /// The start of a line whose end hasn't arrived yet
+    partial: Vec<u8>,
+    /// Number of the last line parsed
+    line: usize,
+    objects: Vec<ObjectInfo>,
+}
+
+impl LineParser {
+    fn push(&mut self, mut data: &[u8]) -> Result<(), ManifestError> {
+        while let Some(end) = data.iter().position(|&b| b == b'\n') {
+            if self.partial.is_empty() {
+                self.parse_line(&data[..end])?;
+            } else {
+                let mut line = std::mem::take(&mut self.partial);
+                line.extend_from_slice(&data[..end]);
+                self.parse_line(&line)?;
+            }
+            data = &data[end + 1..];
+        }
+        self.partial.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Vec<ObjectInfo>, ManifestError> {
+        if !self.partial.is_empty() {
+            let line = std::mem::take(&mut self.partial);
+            self.parse_line(&line)?;
+        }
+        Ok(self.objects)
+    }
+
+    fn parse_line(&mut self, line: &[u8]) -> Result<(), ManifestError> {
+        self.line += 1;
+        if line.iter().all(u8::is_ascii_whitespace) {
+            return Ok(());
+        }
+        let entry: ManifestEntry = serde_json::from_slice(line).map_err(|source| ManifestError::InvalidEntry {
+            line: self.line,
+            source,
+        })?;
+        let last_modified =
+            OffsetDateTime::from_unix_timestamp_nanos(entry.mtime as i128 * 1_000_000).map_err(|_| {
+                ManifestError::InvalidMtime {
+                    line: self.line,
+                    mtime: entry.mtime,
+                }
+            })?;
+        self.objects.push(ObjectInfo {
+            key: entry.key,
+            size: entry.size,
+            unknown_size: false,
+            last_modified,
+            storage_class: entry.storage_class,
+            restore_status: None,
+            etag: entry.etag,
+        });
+        Ok(())
+    }
+}
+
+/// Write a manifest of the given objects, gzip-compressing it if `gzip` is set
+pub fn write_manifest<'a>(
+    writer: impl Write,
+    objects: impl IntoIterator<Item = &'a ObjectInfo>,
+    gzip: bool,
+) -> Result<(), ManifestError> {
+    if gzip {
+        let mut encoder = GzEncoder::new(writer, Compression::default());
+        write_entries(&mut encoder, objects)?;
+        encoder.finish()?.flush()?;
+    } else {
+        let mut writer = writer;
+        write_entries(&mut writer, objects)?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+fn write_entries<'a>(
+    writer: &mut impl Write,
+    objects: impl IntoIterator<Item = &'a ObjectInfo>,
+) -> Result<(), ManifestError> {
+    for object in objects {
+        let entry = ManifestEntry {
+            key: object.key.clone(),
+            size: object.size,
+            etag: object.etag.clone(),
+            mtime: (object.last_modified.unix_timestamp_nanos() / 1_000_000) as i64,
+            storage_class: object.storage_class.clone(),
+        };
+        serde_json::to_writer(&mut *writer, &entry).map_err(std::io::Error::from)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    fn object(key: &str, size: u64, storage_class: Option<&str>) -> ObjectInfo {
+        ObjectInfo {
+            key: key.to_owned(),
+            size,
+            unknown_size: false,
+            last_modified: OffsetDateTime::from_unix_timestamp(1_712_000_000).unwrap(),
+            storage_class: storage_class.map(str::to_owned),
+            restore_status: None,
+            etag: format!("\"etag-{key}\""),
+        }
+    }
+
+    #[test_case(false; "plain")]
+    #[test_case(true; "gzip")]
+    fn test_manifest_round_trip(gzip: bool) {
+        let objects = vec![
+            object("a.txt", 10, None),
+            object("dir/b\ttab, comma.txt", 0, Some("GLACIER")),
+            object("dir/sub/", 0, None),
+        ];
+        let mut data = Vec::new();
+        write_manifest(&mut data, &objects, gzip).unwrap();
+        assert_eq!(data.starts_with(&GZIP_MAGIC), gzip);
+
+        let read = read_manifest(&data).unwrap();
+        assert_eq!(read.len(), objects.len());
+        for (read, expected) in read.iter().zip(&objects) {
+            assert_eq!(read.key, expected.key);
+            assert_eq!(read.size, expected.size);
+            assert_eq!(read.etag, expected.etag);
+            assert_eq!(read.last_modified, expected.last_modified);
+            assert_eq!(read.storage_class, expected.storage_class);
+        }
+    }
+
+    #[test_case(false, 1; "plain, one byte at a time")]
+    #[test_case(false, 7; "plain, chunks that split lines")]
+    #[test_case(true, 1; "gzip, one byte at a time")]
+    #[test_case(true, 7; "gzip, chunks that split lines")]
+    fn test_manifest_read_in_chunks(gzip: bool, chunk_size: usize) {
+        let objects: Vec<_> = (0..50).map(|i| object(&format!("dir/file{i}"), i, None)).collect();
+        let mut data = Vec::new();
+        write_manifest(&mut data, &objects, gzip).unwrap();
+
+        let mut reader = ManifestReader::default();
+        for chunk in data.chunks(chunk_size) {
+            reader.push(chunk).unwrap();
+        }
+        let read = reader.finish().unwrap();
+        let keys: Vec<_> = read.iter().map(|object| &object.key).collect();
+        let expected: Vec<_> = objects.iter().map(|object| &object.key).collect();
+        assert_eq!(keys, expected);
+    }
+
+    #[test]
+    fn test_manifest_skips_blank_lines() {
+        let data = b"{\"key\":\"a\",\"size\":1,\"etag\":\"e\",\"mtime\":0}\n\n";
+        let read = read_manifest(data).unwrap();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].last_modified, OffsetDateTime::UNIX_EPOCH);
+    }
+
+    #[test_case(b"{\"key\":\"a\",\"size\":1,\"etag\":\"e\",\"mtime\":0}\nnot json\n", 2; "invalid json")]
+    #[test_case(b"{\"key\":\"a\",\"size\":1,\"mtime\":0}\n", 1; "missing field")]
+    fn test_manifest_invalid_entry(data: &[u8], expected_line: usize) {
+        let err = read_manifest(data).expect_err("manifest should be invalid");
+        assert!(
+            matches!(err, ManifestError::InvalidEntry { line, .. } if line == expected_line),
+            "unexpected error {err:?}"
+        );
+    }
+}
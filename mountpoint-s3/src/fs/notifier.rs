@@ -0,0 +1,201 @@
+//! Notifications to the kernel about entries it should drop from its caches.
+//!
+//! The kernel caches directory entries for their TTL, so without notifications, other processes
+//! on the same mount can keep resolving a name after it's been removed, or keep reporting a name
+//! as missing after it's been created.
+
+use std::ffi::{OsStr, OsString};
+use std::fmt::Debug;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use linked_hash_map::LinkedHashMap;
+use tracing::{trace, warn};
+
+use crate::sync::{Arc, Mutex};
+
+use super::InodeNo;
+
+#[cfg(feature = "fuse")]
+pub use fuse::FuseNotifier;
+
+/// Sends notifications to the kernel. Implemented by [FuseNotifier] for real mounts, and
+/// abstracted so tests can observe the notifications.
+pub trait KernelNotifier: Send + Sync {
+    /// Invalidate the kernel's cached directory entry for `name` in the directory `parent`
+    fn invalidate_entry(&self, parent: InodeNo, name: &OsStr);
+
+    /// Invalidate the kernel's cached attributes and contents of the inode `ino`
+    fn invalidate_inode(&self, ino: InodeNo);
+}
+
+/// Slot for the [KernelNotifier] of a [S3Filesystem](super::S3Filesystem). Notifiers can only be
+/// created once the FUSE session exists, which is after the file system itself is created.
+#[derive(Clone, Default)]
+pub struct NotifierSlot(Arc<OnceLock<Box<dyn KernelNotifier>>>);
+
+impl NotifierSlot {
+    /// Set the notifier. A slot can only be set once.
+    pub fn set(&self, notifier: impl KernelNotifier + 'static) {
+        if self.0.set(Box::new(notifier)).is_err() {
+            warn!("kernel notifier was already set");
+        }
+    }
+
+    pub(super) fn get(&self) -> Option<&dyn KernelNotifier> {
+        self.0.get().map(|notifier| notifier.as_ref())
+    }
+}
+
+impl Debug for NotifierSlot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("NotifierSlot").field(&self.0.get().is_some()).finish()
+    }
+}
+
+/// The names the kernel was told don't exist, until those negative entries expire. Creating one of
+/// these names must invalidate its negative entry, or the kernel keeps reporting it as missing.
+#[derive(Debug)]
+pub(super) struct NegativeReplies {
+    max_entries: usize,
+    /// When each negative entry expires, in order from least to most recently replied
+    entries: Mutex<LinkedHashMap<(InodeNo, OsString), Instant>>,
+}
+
+impl NegativeReplies {
+    /// Remember at most `max_entries` negative entries. Once there are more, the oldest ones are
+    /// forgotten, and won't be invalidated if their names are created.
+    pub(super) fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            entries: Mutex::new(LinkedHashMap::new()),
+        }
+    }
+
+    /// Remember that the kernel was told `name` doesn't exist in `parent`, for `ttl`
+    pub(super) fn insert(&self, parent: InodeNo, name: &OsStr, ttl: Duration) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert((parent, name.to_owned()), Instant::now() + ttl);
+        while entries.len() > self.max_entries {
+            entries.pop_front();
+        }
+    }
+
+    /// Forget the negative entry for `name` in `parent`, returning whether the kernel may still
+    /// have it cached
+    pub(super) fn remove(&self, parent: InodeNo, name: &OsStr) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        entries
+            .remove(&(parent, name.to_owned()))
+            .is_some_and(|expiry| expiry > Instant::now())
+    }
+
+    /// Forget every negative entry in `parent`, returning the names the kernel may still have
+    /// cached
+    pub(super) fn remove_parent(&self, parent: InodeNo) -> Vec<OsString> {
+        let mut entries = self.entries.lock().unwrap();
+        let keys = entries
+            .keys()
+            .filter(|(entry_parent, _)| *entry_parent == parent)
+            .cloned()
+            .collect::<Vec<_>>();
+        let now = Instant::now();
+        keys.into_iter()
+            .filter_map(|key| {
+                let expiry = entries.remove(&key)?;
+                (expiry > now).then_some(key.1)
+            })
+            .collect()
+    }
+}
+
+/// Tell the kernel to drop what it cached about a directory that changed remotely: its attributes
+/// and contents, the entries for its `expired` children, and any negative entries in it
+pub(super) fn invalidate_changed_directory(
+    notifier: &NotifierSlot,
+    negative_replies: &NegativeReplies,
+    dir: InodeNo,
+    expired: &[String],
+) {
+    let negative_names = negative_replies.remove_parent(dir);
+    let Some(notifier) = notifier.get() else {
+        return;
+    };
+    trace!(
+        ?dir,
+        expired = expired.len(),
+        negative = negative_names.len(),
+        "invalidating changed directory"
+    );
+    notifier.invalidate_inode(dir);
+    for name in expired {
+        notifier.invalidate_entry(dir, OsStr::new(name));
+    }
+    for name in negative_names {
+        notifier.invalidate_entry(dir, &name);
+    }
+}
+
+#[cfg(feature = "fuse")]
+mod fuse {
+    use std::ffi::{OsStr, OsString};
+    use std::thread;
+
+    use tracing::debug;
+
+    use crate::sync::async_channel::{unbounded, Sender};
+
+    use super::{InodeNo, KernelNotifier};
+
+    /// [KernelNotifier] that sends notifications through a FUSE session.
+    ///
+    /// The kernel can block a notification until the request that caused it completes (for example,
+    /// it holds the parent directory's lock during `unlink`), so we can't notify from inside a request
+    /// handler. Instead, notifications are queued and sent from a background thread.
+    #[derive(Debug)]
+    pub struct FuseNotifier {
+        sender: Sender<Notification>,
+    }
+
+    #[derive(Debug)]
+    enum Notification {
+        Entry(InodeNo, OsString),
+        Inode(InodeNo),
+    }
+
+    impl FuseNotifier {
+        pub fn new(notifier: fuser::Notifier) -> Self {
+            let (sender, receiver) = unbounded::<Notification>();
+            // The thread exits when the sender is dropped and the queue is drained
+            thread::spawn(move || {
+                while let Ok(notification) = receiver.recv_blocking() {
+                    // ENOENT just means the kernel didn't have the entry or inode cached
+                    match notification {
+                        Notification::Entry(parent, name) => {
+                            if let Err(error) = notifier.inval_entry(parent, &name) {
+                                debug!(parent, ?name, ?error, "failed to invalidate kernel entry");
+                            }
+                        }
+                        Notification::Inode(ino) => {
+                            if let Err(error) = notifier.inval_inode(ino, 0, 0) {
+                                debug!(ino, ?error, "failed to invalidate kernel inode");
+                            }
+                        }
+                    }
+                }
+            });
+            Self { sender }
+        }
+    }
+
+    impl KernelNotifier for FuseNotifier {
+        fn invalidate_entry(&self, parent: InodeNo, name: &OsStr) {
+            // The queue is unbounded, so this never blocks
+            let _ = self.sender.send_blocking(Notification::Entry(parent, name.to_owned()));
+        }
+
+        fn invalidate_inode(&self, ino: InodeNo) {
+            let _ = self.sender.send_blocking(Notification::Inode(ino));
+        }
+    }
+}
@@ -0,0 +1,201 @@
+//! Writes that overwrite part of an existing object in place.
+//!
+//! S3 objects can't be modified, only replaced, so a write into the middle of an object has to
+//! upload a new version of it. With [allow_partial_writes](super::S3FilesystemConfig::allow_partial_writes),
+//! a file opened for writing without `O_TRUNC` keeps its contents, and the data written to it is
+//! kept in memory until the file is flushed. The new version is then built with
+//! [Uploader::write_parts], which uploads only the parts the write touched and copies the others
+//! server-side from the old version, as long as it hasn't changed since the file was opened.
+//!
+//! For now, the data written must be a single range that starts on a part boundary and doesn't
+//! extend the file.
+
+use mountpoint_s3_client::types::ETag;
+use mountpoint_s3_client::ObjectClient;
+use tracing::{debug, error, trace};
+
+use crate::inode::WriteHandle;
+use crate::upload::Uploader;
+
+use super::{are_from_same_process, Error, ToErrno};
+
+/// The state of a write handle that overwrites part of an existing object
+#[derive(Debug)]
+pub enum PartialWriteState {
+    InProgress { write: PartialWrite, handle: WriteHandle },
+    Completed,
+    // Remember the failure reason to respond to retries
+    Failed(libc::c_int),
+}
+
+/// Data written over part of an existing object, waiting to be uploaded
+#[derive(Debug)]
+pub struct PartialWrite {
+    /// ETag of the object when the file was opened, which the write is conditional on
+    etag: ETag,
+    object_size: u64,
+    part_size: u64,
+    /// Offset in the object of the first byte of `data`, once something has been written
+    offset: Option<u64>,
+    data: Vec<u8>,
+}
+
+impl PartialWrite {
+    pub fn new(etag: ETag, object_size: u64, part_size: u64) -> Self {
+        Self {
+            etag,
+            object_size,
+            part_size,
+            offset: None,
+            data: Vec::new(),
+        }
+    }
+
+    /// Write `data` at `offset`. Writes can replace data written earlier or continue where it
+    /// ends, but the data written must stay one range.
+    fn write(&mut self, offset: i64, data: &[u8]) -> Result<u32, Error> {
+        if offset < 0 {
+            return Err(err!(libc::EINVAL, "negative offset"));
+        }
+        let offset = offset as u64;
+        let end = offset + data.len() as u64;
+        if end > self.object_size {
+            return Err(err!(
+                libc::EINVAL,
+                "writes into an existing file can't extend it past its size {}",
+                self.object_size
+            ));
+        }
+        if data.is_empty() {
+            return Ok(0);
+        }
+
+        let start = match self.offset {
+            Some(start) if offset >= start && offset <= start + self.data.len() as u64 => start,
+            Some(start) => {
+                return Err(err!(
+                    libc::EINVAL,
+                    "out-of-order write at offset {}; only the range starting at {} can be written",
+                    offset,
+                    start
+                ))
+            }
+            None if offset % self.part_size != 0 => {
+                return Err(err!(
+                    libc::EINVAL,
+                    "writes into an existing file must start on a part boundary, but offset {} isn't a multiple of {}",
+                    offset,
+                    self.part_size
+                ))
+            }
+            None => *self.offset.insert(offset),
+        };
+        let range_start = (offset - start) as usize;
+        let range_end = range_start + data.len();
+        if range_end > self.data.len() {
+            self.data.resize(range_end, 0);
+        }
+        self.data[range_start..range_end].copy_from_slice(data);
+        Ok(data.len() as u32)
+    }
+}
+
+impl PartialWriteState {
+    pub fn new(write: PartialWrite, handle: WriteHandle) -> Self {
+        Self::InProgress { write, handle }
+    }
+
+    pub fn write(&mut self, offset: i64, data: &[u8], key: &str) -> Result<u32, Error> {
+        match self {
+            Self::InProgress { write, .. } => write.write(offset, data),
+            Self::Completed => Err(err!(libc::EIO, "partial write already completed for key {:?}", key)),
+            Self::Failed(e) => Err(err!(*e, "partial write already aborted for key {:?}", key)),
+        }
+    }
+
+    /// Upload the data written so far. As when completing any other upload, nothing happens if
+    /// `ignore_if_empty` is set and nothing was written, or if `pid` is from a different process
+    /// than the one that opened the file.
+    pub async fn complete<Client>(
+        &mut self,
+        uploader: &Uploader<Client>,
+        bucket: &str,
+        key: &str,
+        ignore_if_empty: bool,
+        pid: Option<u32>,
+    ) -> Result<(), Error>
+    where
+        Client: ObjectClient + Send + Sync + 'static,
+    {
+        let (written, open_pid) = match self {
+            Self::InProgress { write, handle } => (write.offset.is_some(), handle.pid()),
+            Self::Completed => return Ok(()),
+            Self::Failed(e) => return Err(err!(*e, "partial write already aborted for key {:?}", key)),
+        };
+
+        if ignore_if_empty && !written {
+            trace!(key, "not completing partial write because nothing was written");
+            return Ok(());
+        }
+        if let Some(pid) = pid {
+            if !are_from_same_process(open_pid, pid) {
+                trace!(
+                    key,
+                    pid,
+                    open_pid,
+                    "not completing partial write because current pid differs from pid at open"
+                );
+                return Ok(());
+            }
+        }
+
+        let Self::InProgress { write, handle } = std::mem::replace(self, Self::Completed) else {
+            unreachable!("checked above");
+        };
+        let result = Self::complete_write(uploader, bucket, key, write, handle).await;
+        if let Err(e) = &result {
+            *self = Self::Failed(e.to_errno());
+        }
+        result
+    }
+
+    pub async fn complete_if_in_progress<Client>(
+        self,
+        uploader: &Uploader<Client>,
+        bucket: &str,
+        key: &str,
+    ) -> Result<(), Error>
+    where
+        Client: ObjectClient + Send + Sync + 'static,
+    {
+        match self {
+            Self::InProgress { write, handle } => Self::complete_write(uploader, bucket, key, write, handle).await,
+            Self::Failed(_) | Self::Completed => Ok(()),
+        }
+    }
+
+    async fn complete_write<Client>(
+        uploader: &Uploader<Client>,
+        bucket: &str,
+        key: &str,
+        write: PartialWrite,
+        handle: WriteHandle,
+    ) -> Result<(), Error>
+    where
+        Client: ObjectClient + Send + Sync + 'static,
+    {
+        let result = match write.offset {
+            Some(offset) => uploader
+                .write_parts(bucket, key, &write.etag, write.object_size, offset, &write.data)
+                .await
+                .map(|()| debug!(key, offset, length = write.data.len(), "partial write succeeded"))
+                .map_err(Error::from),
+            None => Ok(()),
+        };
+        if let Err(err) = handle.finish_writing() {
+            // Log the issue but still return the write result.
+            error!(?err, ?key, "error updating the inode status");
+        }
+        result
+    }
+}
@@ -0,0 +1,164 @@
+//! Overrides of the metadata cache settings for parts of the file system.
+//!
+//! A mount can hold data with very different consistency needs, like an archive that never changes
+//! next to an `incoming/` directory that changes all the time. [S3FilesystemConfig::path_rules]
+//! maps prefixes of paths within the mount to [PathOverrides] of the global [CacheConfig] for
+//! everything under them. When several prefixes match a path, the longest one wins.
+//!
+//! [S3FilesystemConfig::path_rules]: super::S3FilesystemConfig::path_rules
+//! [CacheConfig]: super::CacheConfig
+
+use std::time::Duration;
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use super::config;
+
+/// A prefix of paths relative to the mount point, made up of whole path components like
+/// `archive/2024`. It matches the entry it names and everything below it. The empty prefix matches
+/// every path.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct PrefixPattern {
+    /// The prefix with a trailing `/`, or empty
+    prefix: String,
+}
+
+/// Error returned when a [PrefixPattern] isn't a valid relative path
+#[derive(Debug, Error)]
+#[error("invalid path prefix {0:?}: must be a relative path without empty, `.` or `..` components")]
+pub struct InvalidPrefixPattern(String);
+
+impl PrefixPattern {
+    /// Create a pattern that matches paths under the relative path `prefix`. A trailing `/` is
+    /// optional.
+    pub fn new(prefix: &str) -> Result<Self, InvalidPrefixPattern> {
+        let trimmed = prefix.strip_suffix('/').unwrap_or(prefix);
+        if trimmed.is_empty() {
+            return Ok(Self::default());
+        }
+        let valid = trimmed
+            .split('/')
+            .all(|component| !component.is_empty() && component != "." && component != "..");
+        if !valid {
+            return Err(InvalidPrefixPattern(prefix.to_owned()));
+        }
+        Ok(Self {
+            prefix: format!("{trimmed}/"),
+        })
+    }
+
+    /// The prefix of the paths this pattern matches: empty, or ending in `/`
+    pub fn as_str(&self) -> &str {
+        &self.prefix
+    }
+}
+
+impl TryFrom<String> for PrefixPattern {
+    type Error = InvalidPrefixPattern;
+
+    fn try_from(prefix: String) -> Result<Self, Self::Error> {
+        Self::new(&prefix)
+    }
+}
+
+/// Cache settings that override those of the global [CacheConfig](super::CacheConfig) for paths
+/// under a [PrefixPattern]. Settings left as `None` take the global value.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathOverrides {
+    /// Should lookups be served from cached entries rather than checking S3?
+    pub serve_lookup_from_cache: Option<bool>,
+    /// How long the kernel will cache metadata for files
+    #[serde(deserialize_with = "config::deserialize_optional_duration")]
+    pub file_ttl: Option<Duration>,
+    /// How long the kernel will cache metadata for directories
+    #[serde(deserialize_with = "config::deserialize_optional_duration")]
+    pub dir_ttl: Option<Duration>,
+    /// How long a name is remembered as not existing, when lookups are served from the cache.
+    /// Defaults to the file TTL.
+    #[serde(deserialize_with = "config::deserialize_optional_duration")]
+    pub negative_cache_ttl: Option<Duration>,
+}
+
+/// A table of [PathOverrides] by S3 key prefix
+#[derive(Debug, Clone, Default)]
+pub struct PathRules {
+    /// Rules sorted by descending prefix length, so the first match is the longest
+    rules: Vec<(String, PathOverrides)>,
+}
+
+impl PathRules {
+    /// Create a table of overrides for keys under the given key prefixes, which are either empty or
+    /// end in `/`
+    pub fn new(rules: impl IntoIterator<Item = (String, PathOverrides)>) -> Self {
+        let mut rules: Vec<_> = rules.into_iter().collect();
+        rules.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+        Self { rules }
+    }
+
+    /// The overrides for the longest key prefix that matches `key`, which may name either a file
+    /// or a directory (with or without its trailing `/`)
+    pub fn resolve(&self, key: &str) -> Option<&PathOverrides> {
+        self.rules
+            .iter()
+            .find(|(prefix, _)| match prefix.strip_suffix('/') {
+                Some(dir) => key.starts_with(prefix.as_str()) || key == dir,
+                None => key.starts_with(prefix.as_str()),
+            })
+            .map(|(_, overrides)| overrides)
+    }
+
+    /// Whether the table has no rules, and so nothing overrides the global settings
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("", ""; "empty")]
+    #[test_case("/", ""; "root")]
+    #[test_case("archive", "archive/"; "no trailing slash")]
+    #[test_case("archive/", "archive/"; "trailing slash")]
+    #[test_case("data/2024", "data/2024/"; "nested")]
+    fn test_valid_prefix_pattern(prefix: &str, expected: &str) {
+        let pattern = PrefixPattern::new(prefix).expect("pattern should be valid");
+        assert_eq!(pattern.as_str(), expected);
+    }
+
+    #[test_case("/archive"; "absolute")]
+    #[test_case("archive//2024"; "empty component")]
+    #[test_case("./archive"; "dot")]
+    #[test_case("archive/../secrets"; "dot dot")]
+    fn test_invalid_prefix_pattern(prefix: &str) {
+        PrefixPattern::new(prefix).expect_err("pattern should be invalid");
+    }
+
+    #[test]
+    fn test_longest_match_wins() {
+        let overrides = |secs| PathOverrides {
+            file_ttl: Some(Duration::from_secs(secs)),
+            ..Default::default()
+        };
+        let rules = PathRules::new([
+            ("mnt/".to_owned(), overrides(1)),
+            ("mnt/data/archive/".to_owned(), overrides(3)),
+            ("mnt/data/".to_owned(), overrides(2)),
+        ]);
+
+        let ttl = |key| rules.resolve(key).and_then(|o| o.file_ttl).map(|ttl| ttl.as_secs());
+        assert_eq!(ttl("mnt/file"), Some(1));
+        assert_eq!(ttl("mnt/data"), Some(2));
+        assert_eq!(ttl("mnt/data/"), Some(2));
+        assert_eq!(ttl("mnt/data/archive"), Some(3));
+        assert_eq!(ttl("mnt/data/archive/2024/file"), Some(3));
+        assert_eq!(ttl("mnt/data/archived"), Some(2));
+        assert_eq!(ttl("other/file"), None);
+    }
+}
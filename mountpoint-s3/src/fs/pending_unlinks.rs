@@ -0,0 +1,205 @@
+//! Batched deletes of the objects of unlinked files.
+//!
+//! Removing a directory tree through the file system, like `rm -r`, unlinks one file at a time,
+//! and the kernel waits for each unlink before sending the next. Deleting each file's object as
+//! it's unlinked takes a DeleteObject request per file. When
+//! [S3FilesystemConfig::unlink_batch_delay] is set, unlink only removes the file from the file
+//! system and queues its key in [PendingUnlinks]. The queued keys are deleted together, with as
+//! few DeleteObjects requests as possible, once [MAX_DELETE_OBJECTS_KEYS] of them are queued,
+//! once the oldest has waited the delay (see [UnlinkFlusher]), or before an operation that could
+//! otherwise still see them in S3, like listing a directory.
+//!
+//! [S3FilesystemConfig::unlink_batch_delay]: super::S3FilesystemConfig::unlink_batch_delay
+//! [MAX_DELETE_OBJECTS_KEYS]: mountpoint_s3_client::types::MAX_DELETE_OBJECTS_KEYS
+
+use std::collections::BTreeSet;
+use std::ops::{Bound, ControlFlow};
+use std::time::{Duration, Instant};
+
+use futures::executor::block_on;
+use mountpoint_s3_client::ObjectClient;
+use tracing::{debug, warn};
+
+use crate::clock::Clock;
+use crate::periodic::PeriodicTask;
+use crate::sync::{Arc, AsyncMutex, Mutex};
+
+use super::{delete_keys, Error};
+
+/// Longest time between checks for keys that have waited long enough. Shorter delays are checked
+/// more often.
+const MAX_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Keys of unlinked files whose objects haven't been deleted yet
+#[derive(Debug)]
+pub struct PendingUnlinks {
+    state: Mutex<PendingState>,
+    /// Held while queued keys are deleted, so that a flush waits for the deletes already in
+    /// progress before it returns
+    flushing: AsyncMutex<()>,
+    clock: Arc<dyn Clock>,
+}
+
+#[derive(Debug, Default)]
+struct PendingState {
+    queued: BTreeSet<String>,
+    /// Keys taken from the queue whose DeleteObjects requests haven't completed
+    deleting: BTreeSet<String>,
+    /// When the oldest key in [Self::queued] was queued
+    oldest: Option<Instant>,
+}
+
+impl PendingState {
+    fn keys(&self) -> impl Iterator<Item = &BTreeSet<String>> {
+        [&self.queued, &self.deleting].into_iter()
+    }
+}
+
+impl PendingUnlinks {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            state: Default::default(),
+            flushing: AsyncMutex::new(()),
+            clock,
+        }
+    }
+
+    /// Queue `key` for deletion. Returns how many keys are queued.
+    pub fn push(&self, key: String) -> usize {
+        let mut state = self.state.lock().unwrap();
+        state.oldest.get_or_insert_with(|| self.clock.now());
+        state.queued.insert(key);
+        state.queued.len()
+    }
+
+    /// Whether no keys are waiting to be deleted
+    pub fn is_empty(&self) -> bool {
+        self.state.lock().unwrap().keys().all(BTreeSet::is_empty)
+    }
+
+    /// Whether the object at `key` was unlinked but may not be deleted yet
+    pub fn contains(&self, key: &str) -> bool {
+        self.state.lock().unwrap().keys().any(|keys| keys.contains(key))
+    }
+
+    /// Whether any object under `prefix` was unlinked but may not be deleted yet
+    pub fn contains_prefix(&self, prefix: &str) -> bool {
+        self.state.lock().unwrap().keys().any(|keys| {
+            keys.range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+                .next()
+                .is_some_and(|key| key.starts_with(prefix))
+        })
+    }
+
+    /// Whether the oldest queued key has waited `delay` by now
+    fn is_due(&self, delay: Duration) -> bool {
+        let oldest = self.state.lock().unwrap().oldest;
+        oldest.is_some_and(|oldest| self.clock.now().saturating_duration_since(oldest) >= delay)
+    }
+
+    /// Delete the objects of every queued key, once any deletes already in progress complete.
+    /// Returns the number of objects deleted, or an error describing the keys that couldn't be.
+    /// Either way, the keys are no longer pending: those that failed are visible again.
+    pub async fn flush<Client: ObjectClient>(&self, client: &Client, bucket: &str) -> Result<usize, Error> {
+        if self.is_empty() {
+            return Ok(0);
+        }
+        let _flushing = self.flushing.lock().await;
+        let keys: Vec<String> = {
+            let mut state = self.state.lock().unwrap();
+            state.oldest = None;
+            let keys = std::mem::take(&mut state.queued);
+            state.deleting.extend(keys.iter().cloned());
+            keys.into_iter().collect()
+        };
+        if keys.is_empty() {
+            return Ok(0);
+        }
+        let result = delete_keys(client, bucket, &keys).await;
+        self.state.lock().unwrap().deleting.clear();
+        // Unlink already succeeded for these files, so this is the only record that their objects
+        // are still there. If a request failed outright, count all of them as possibly not deleted.
+        let deleted = result.as_ref().map_or(0, |(deleted, _)| *deleted);
+        let not_deleted = keys.len().saturating_sub(deleted);
+        if not_deleted > 0 {
+            metrics::counter!("fs.unlink_batch.failed_deletes").increment(not_deleted as u64);
+        }
+        let (deleted, failed) = result?;
+        debug!(deleted, "deleted objects of unlinked files");
+        match failed {
+            Some(error) => Err(error),
+            None => Ok(deleted),
+        }
+    }
+}
+
+/// Handle to a background thread that deletes the objects of unlinked files once they've waited
+/// long enough. Whatever is still queued is deleted when the handle is dropped.
+#[derive(Debug)]
+pub struct UnlinkFlusher {
+    _task: PeriodicTask,
+}
+
+impl UnlinkFlusher {
+    /// Start deleting the keys in `pending` from `bucket` soon after they've waited `delay`
+    pub fn start<Client>(pending: Arc<PendingUnlinks>, client: Arc<Client>, bucket: String, delay: Duration) -> Self
+    where
+        Client: ObjectClient + Send + Sync + 'static,
+    {
+        let interval = delay.min(MAX_FLUSH_INTERVAL);
+        let flush = {
+            let pending = pending.clone();
+            move || {
+                if let Err(error) = block_on(pending.flush(client.as_ref(), &bucket)) {
+                    warn!(?error, "failed to delete objects of unlinked files");
+                }
+            }
+        };
+        let task = PeriodicTask::start_with_stop(
+            "unlink-flusher",
+            interval,
+            {
+                let flush = flush.clone();
+                move || {
+                    if pending.is_due(delay) {
+                        flush();
+                    }
+                    ControlFlow::Continue(())
+                }
+            },
+            flush,
+        )
+        .expect("failed to spawn unlink flusher thread");
+        Self { _task: task }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn tracks_pending_keys() {
+        let clock = Arc::new(MockClock::new());
+        let pending = PendingUnlinks::new(clock.clone());
+        assert!(pending.is_empty());
+        assert!(!pending.is_due(Duration::ZERO));
+
+        assert_eq!(pending.push("dir/a.txt".to_owned()), 1);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(pending.push("dir/sub/b.txt".to_owned()), 2);
+        assert!(!pending.is_empty());
+        assert!(pending.contains("dir/a.txt"));
+        assert!(!pending.contains("dir/b.txt"));
+        assert!(pending.contains_prefix("dir/"));
+        assert!(pending.contains_prefix("dir/sub/"));
+        assert!(!pending.contains_prefix("dir/a/"));
+        assert!(!pending.contains_prefix("other/"));
+
+        // The delay counts from the oldest key
+        assert!(!pending.is_due(Duration::from_secs(2)));
+        clock.advance(Duration::from_secs(1));
+        assert!(pending.is_due(Duration::from_secs(2)));
+    }
+}
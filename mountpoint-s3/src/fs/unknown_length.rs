@@ -0,0 +1,237 @@
+//! Reads of objects whose size isn't known until they've been read to the end.
+//!
+//! Objects served through an S3 Object Lambda access point are transformed as they're read, so
+//! HeadObject may not be able to say how big they are. The prefetcher needs the size to know how
+//! much to request, so instead these objects are read in order as the application reads them, with
+//! ranged GetObject requests of [REQUEST_SIZE] bytes each, one after the other. Their end is
+//! discovered when a request comes back short, or finds nothing left to return. Bounding each
+//! request bounds how much of the object is held in memory when the application reads slowly.
+//! Only sequential reads are supported.
+//!
+//! Objects that are decompressed as they're read (see [super::decompress]) are read the same way,
+//! since their decompressed size isn't known either.
+
+use std::pin::Pin;
+
+use bytes::{Bytes, BytesMut};
+use futures::StreamExt;
+use mountpoint_s3_client::error::{GetObjectError, ObjectClientError};
+use mountpoint_s3_client::types::ETag;
+use mountpoint_s3_client::ObjectClient;
+use tracing::{debug, trace};
+
+use crate::sync::Arc;
+
+use super::decompress::{ContentEncoding, Decoder};
+use super::Error;
+
+/// Size of each GetObject request for an object of unknown length
+const REQUEST_SIZE: u64 = 8 * 1024 * 1024;
+
+/// A read handle for an object of unknown length
+pub struct UnknownLengthRead<Client: ObjectClient> {
+    client: Arc<Client>,
+    bucket: String,
+    key: String,
+    etag: ETag,
+    /// The encoding to decompress the object from, if any
+    encoding: Option<ContentEncoding>,
+    /// The current GetObject request, if one is in progress
+    stream: Option<Pin<Box<Client::GetObjectResult>>>,
+    /// Decoder for the object's stream, if the object is being decompressed
+    decoder: Option<Decoder>,
+    /// Offset in the object the next part received should start at
+    stream_offset: u64,
+    /// End of the range the current request asked for
+    request_end: u64,
+    /// Whether the current request's range is known to end where the object does
+    reaches_end: bool,
+    /// Offset the next read must start at
+    next_offset: u64,
+    /// Data received from the stream but not read yet, starting at `next_offset`
+    buffered: BytesMut,
+    /// The size of the object, once the stream has ended
+    size: Option<u64>,
+}
+
+impl<Client: ObjectClient> UnknownLengthRead<Client> {
+    pub fn new(client: Arc<Client>, bucket: &str, key: &str, etag: ETag, encoding: Option<ContentEncoding>) -> Self {
+        Self {
+            client,
+            bucket: bucket.to_owned(),
+            key: key.to_owned(),
+            etag,
+            encoding,
+            stream: None,
+            decoder: None,
+            stream_offset: 0,
+            request_end: 0,
+            reaches_end: false,
+            next_offset: 0,
+            buffered: BytesMut::new(),
+            size: None,
+        }
+    }
+
+    /// Read up to `size` bytes at `offset`, which must be where the previous read ended. Reads at
+    /// or past the end of the object, once it's been found, return no data.
+    pub async fn read(&mut self, offset: u64, size: usize) -> Result<Bytes, Error> {
+        if let Some(object_size) = self.size {
+            if offset >= object_size {
+                return Ok(Bytes::new());
+            }
+        }
+        if offset != self.next_offset {
+            return Err(err!(
+                libc::EINVAL,
+                "objects of unknown length can only be read sequentially: expected offset {} but got {}",
+                self.next_offset,
+                offset
+            ));
+        }
+
+        while self.buffered.len() < size && self.size.is_none() {
+            self.fill(size - self.buffered.len()).await?;
+        }
+
+        let data = self.buffered.split_to(size.min(self.buffered.len())).freeze();
+        self.next_offset += data.len() as u64;
+        Ok(data)
+    }
+
+    /// Receive the next part of the object, starting a request for the next range if there's none
+    /// in progress. If a request fails, the next one starts where it stopped. Compressed data is
+    /// decompressed only until `wanted` more bytes are buffered; the rest is kept for later.
+    async fn fill(&mut self, wanted: usize) -> Result<(), Error> {
+        if self.decoder.as_ref().is_some_and(|decoder| !decoder.is_drained()) {
+            return self.decode(wanted);
+        }
+        if self.stream.is_none() {
+            if self.decoder.is_none() && self.stream_offset == 0 {
+                self.decoder = match self.encoding {
+                    Some(encoding) => Some(
+                        encoding
+                            .decoder()
+                            .map_err(|e| err!(libc::EIO, source:e, "failed to create decoder"))?,
+                    ),
+                    None => None,
+                };
+            }
+            let mut range = self.stream_offset..self.stream_offset + REQUEST_SIZE;
+            trace!(key = ?self.key, ?range, encoding = ?self.encoding, "starting GetObject for object of unknown length");
+            let mut request = self
+                .client
+                .get_object(&self.bucket, &self.key, Some(range.clone()), Some(self.etag.clone()))
+                .await;
+            // Some implementations refuse ranges that reach past the end of the object, rather than
+            // returning what's there, but tell us where it ends
+            if let Err(ObjectClientError::ServiceError(GetObjectError::InvalidRange {
+                object_size: Some(object_size),
+            })) = &request
+            {
+                if *object_size > range.start && *object_size < range.end {
+                    range.end = *object_size;
+                    request = self
+                        .client
+                        .get_object(&self.bucket, &self.key, Some(range.clone()), Some(self.etag.clone()))
+                        .await;
+                }
+            }
+            match request {
+                Ok(request) => {
+                    self.stream = Some(Box::pin(request));
+                    self.reaches_end = range.end - range.start < REQUEST_SIZE;
+                    self.request_end = range.end;
+                }
+                // Nothing is left past the data we've already received
+                Err(ObjectClientError::ServiceError(GetObjectError::InvalidRange { .. })) => return self.finish(),
+                Err(e) => return Err(map_get_error(e)),
+            }
+        }
+        let stream = self.stream.as_mut().expect("request was just started");
+
+        match stream.next().await {
+            Some(Ok((offset, body))) => {
+                if offset != self.stream_offset {
+                    self.stream = None;
+                    return Err(err!(
+                        libc::EIO,
+                        "GetObject returned data at offset {} but expected {}",
+                        offset,
+                        self.stream_offset
+                    ));
+                }
+                self.stream_offset += body.len() as u64;
+                match &mut self.decoder {
+                    Some(decoder) => {
+                        decoder.push(body);
+                        return self.decode(wanted);
+                    }
+                    None => self.buffered.extend_from_slice(&body),
+                }
+            }
+            Some(Err(e)) => {
+                self.stream = None;
+                return Err(map_get_error(e));
+            }
+            None => {
+                self.stream = None;
+                // A request that came back short reached the end of the object
+                if self.reaches_end || self.stream_offset < self.request_end {
+                    return self.finish();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Decompress up to `wanted` more bytes of what's been received into the buffer
+    fn decode(&mut self, wanted: usize) -> Result<(), Error> {
+        let decoder = self.decoder.as_mut().expect("object should be decompressed");
+        match decoder.decode(wanted) {
+            Ok(decoded) => {
+                self.buffered.extend_from_slice(&decoded);
+                Ok(())
+            }
+            Err(e) => {
+                self.stream = None;
+                Err(err!(libc::EIO, source:e, "failed to decompress object"))
+            }
+        }
+    }
+
+    /// Record that the whole object has been received
+    fn finish(&mut self) -> Result<(), Error> {
+        if let Some(decoder) = &self.decoder {
+            decoder
+                .finish()
+                .map_err(|e| err!(libc::EIO, source:e, "failed to decompress object"))?;
+        }
+        self.decoder = None;
+        let size = self.next_offset + self.buffered.len() as u64;
+        debug!(key = ?self.key, size, "found end of object of unknown length");
+        self.size = Some(size);
+        Ok(())
+    }
+}
+
+fn map_get_error<E: std::error::Error + Send + Sync + 'static>(e: ObjectClientError<GetObjectError, E>) -> Error {
+    match e {
+        ObjectClientError::ServiceError(GetObjectError::PreconditionFailed) => {
+            err!(libc::ESTALE, "object was mutated remotely")
+        }
+        e => err!(libc::EIO, source:e, "get request failed"),
+    }
+}
+
+impl<Client: ObjectClient> std::fmt::Debug for UnknownLengthRead<Client> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UnknownLengthRead")
+            .field("key", &self.key)
+            .field("etag", &self.etag)
+            .field("encoding", &self.encoding)
+            .field("next_offset", &self.next_offset)
+            .field("size", &self.size)
+            .finish()
+    }
+}
@@ -0,0 +1,255 @@
+//! Read-only views of part of a file system.
+//!
+//! A [S3FilesystemView] presents one directory of an [S3Filesystem] as if it were the root of a
+//! file system of its own, for handing different parts of one mount to different users. Views
+//! share everything with the file system they're created from (its client, caches, prefetcher,
+//! and circuit breaker), so they're cheap to create, and reads through one view can be served
+//! from data cached by another.
+//!
+//! A view's root directory is [FUSE_ROOT_INODE], and every other inode keeps the number the file
+//! system gave it. Inodes outside the view's root, and handles opened by anything other than the
+//! view itself, are reported as not existing. Nothing can be written through a view.
+
+use std::collections::HashSet;
+use std::ffi::OsStr;
+
+use bytes::Bytes;
+use mountpoint_s3_client::ObjectClient;
+use tracing::trace;
+
+use crate::prefetch::Prefetch;
+use crate::sync::Mutex;
+
+use super::{
+    Attr, DirectoryEntry, DirectoryReplier, Entry, Error, FileType, InodeNo, Opened, S3Filesystem, FUSE_ROOT_INODE,
+};
+
+impl<Client, Prefetcher> S3Filesystem<Client, Prefetcher>
+where
+    Client: ObjectClient + Send + Sync + 'static,
+    Prefetcher: Prefetch,
+{
+    /// Create a read-only view of the directory `prefix` (like `tenants/a/`), relative to the
+    /// mount point. Fails if there's no such directory.
+    pub async fn subview(&self, prefix: &str) -> Result<S3FilesystemView<'_, Client, Prefetcher>, Error> {
+        trace!(?prefix, "fs:subview");
+
+        // Each lookup is remembered until the view is dropped, so the view's root (and the
+        // directories above it, which it hangs off) stay in the superblock while it's in use
+        let mut view = S3FilesystemView {
+            fs: self,
+            root: FUSE_ROOT_INODE,
+            root_key: self.superblock.inode(FUSE_ROOT_INODE)?.full_key().to_owned(),
+            remembered: Vec::new(),
+            handles: Default::default(),
+        };
+        for name in prefix.split('/').filter(|name| !name.is_empty()) {
+            let entry = self.lookup(view.root, name.as_ref()).await?;
+            view.remembered.push(entry.attr.ino);
+            if entry.attr.kind != FileType::Directory {
+                return Err(err!(libc::ENOTDIR, "subview prefix {prefix:?} is not a directory"));
+            }
+            view.root = entry.attr.ino;
+        }
+        view.root_key = self.superblock.inode(view.root)?.full_key().to_owned();
+        Ok(view)
+    }
+}
+
+/// A read-only view of one directory of an [S3Filesystem], created by [S3Filesystem::subview]
+pub struct S3FilesystemView<'a, Client, Prefetcher>
+where
+    Client: ObjectClient + Send + Sync + 'static,
+    Prefetcher: Prefetch,
+{
+    fs: &'a S3Filesystem<Client, Prefetcher>,
+    /// The file system's inode for the view's root directory
+    root: InodeNo,
+    /// Full key of the root directory, which every inode in the view's key starts with
+    root_key: String,
+    /// Inodes looked up to find the root, to forget when we're dropped
+    remembered: Vec<InodeNo>,
+    /// File and directory handles opened through this view
+    handles: Mutex<HashSet<u64>>,
+}
+
+impl<Client, Prefetcher> S3FilesystemView<'_, Client, Prefetcher>
+where
+    Client: ObjectClient + Send + Sync + 'static,
+    Prefetcher: Prefetch,
+{
+    /// The file system's inode for one of ours, failing if it's outside the view
+    fn inner_ino(&self, ino: InodeNo) -> Result<InodeNo, Error> {
+        if ino == FUSE_ROOT_INODE {
+            return Ok(self.root);
+        }
+        // The root is only visible as FUSE_ROOT_INODE, and its ancestors not at all
+        let visible = ino != self.root
+            && self
+                .fs
+                .superblock
+                .inode(ino)
+                .is_ok_and(|inode| inode.full_key().starts_with(&self.root_key));
+        if !visible {
+            return Err(err!(libc::ENOENT, "inode {ino} is not in this view"));
+        }
+        Ok(ino)
+    }
+
+    /// Our inode for one of the file system's
+    fn outer_ino(&self, ino: InodeNo) -> InodeNo {
+        if ino == self.root {
+            FUSE_ROOT_INODE
+        } else {
+            ino
+        }
+    }
+
+    /// Check that a handle was opened through this view
+    fn check_handle(&self, fh: u64) -> Result<(), Error> {
+        if !self.handles.lock().unwrap().contains(&fh) {
+            return Err(err!(libc::EBADF, "handle {fh} was not opened through this view"));
+        }
+        Ok(())
+    }
+
+    pub async fn lookup(&self, parent: InodeNo, name: &OsStr) -> Result<Entry, Error> {
+        let mut entry = self.fs.lookup(self.inner_ino(parent)?, name).await?;
+        entry.attr.ino = self.outer_ino(entry.attr.ino);
+        Ok(entry)
+    }
+
+    pub async fn getattr(&self, ino: InodeNo) -> Result<Attr, Error> {
+        let mut attr = self.fs.getattr(self.inner_ino(ino)?).await?;
+        attr.attr.ino = self.outer_ino(attr.attr.ino);
+        Ok(attr)
+    }
+
+    pub async fn forget(&self, ino: InodeNo, n: u64) {
+        // Like the file system's root, the view's root is never forgotten
+        if ino == FUSE_ROOT_INODE {
+            return;
+        }
+        if let Ok(ino) = self.inner_ino(ino) {
+            self.fs.forget(ino, n).await;
+        }
+    }
+
+    /// Open a file for reading. Opening for writing fails with `EROFS`.
+    pub async fn open(&self, ino: InodeNo, flags: i32, pid: u32) -> Result<Opened, Error> {
+        if flags & (libc::O_WRONLY | libc::O_RDWR | libc::O_TRUNC | libc::O_APPEND) != 0 {
+            return Err(err!(libc::EROFS, "views are read-only"));
+        }
+        let opened = self.fs.open(self.inner_ino(ino)?, flags, pid).await?;
+        self.handles.lock().unwrap().insert(opened.fh);
+        Ok(opened)
+    }
+
+    #[allow(clippy::too_many_arguments)] // We don't get to choose this interface
+    pub async fn read(
+        &self,
+        ino: InodeNo,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        flags: i32,
+        lock: Option<u64>,
+    ) -> Result<Bytes, Error> {
+        self.check_handle(fh)?;
+        self.fs.read(self.inner_ino(ino)?, fh, offset, size, flags, lock).await
+    }
+
+    pub async fn release(
+        &self,
+        ino: InodeNo,
+        fh: u64,
+        flags: i32,
+        lock_owner: Option<u64>,
+        flush: bool,
+    ) -> Result<(), Error> {
+        self.check_handle(fh)?;
+        self.fs
+            .release(self.inner_ino(ino)?, fh, flags, lock_owner, flush)
+            .await?;
+        self.handles.lock().unwrap().remove(&fh);
+        Ok(())
+    }
+
+    pub async fn opendir(&self, parent: InodeNo, flags: i32) -> Result<Opened, Error> {
+        let opened = self.fs.opendir(self.inner_ino(parent)?, flags).await?;
+        self.handles.lock().unwrap().insert(opened.fh);
+        Ok(opened)
+    }
+
+    pub async fn readdir<R: DirectoryReplier>(
+        &self,
+        parent: InodeNo,
+        fh: u64,
+        offset: i64,
+        reply: R,
+    ) -> Result<R, Error> {
+        self.check_handle(fh)?;
+        let parent = self.inner_ino(parent)?;
+        let reply = self.reply(parent, reply);
+        Ok(self.fs.readdir(parent, fh, offset, reply).await?.reply)
+    }
+
+    pub async fn readdirplus<R: DirectoryReplier>(
+        &self,
+        parent: InodeNo,
+        fh: u64,
+        offset: i64,
+        reply: R,
+    ) -> Result<R, Error> {
+        self.check_handle(fh)?;
+        let parent = self.inner_ino(parent)?;
+        let reply = self.reply(parent, reply);
+        Ok(self.fs.readdirplus(parent, fh, offset, reply).await?.reply)
+    }
+
+    pub async fn releasedir(&self, ino: InodeNo, fh: u64, flags: i32) -> Result<(), Error> {
+        self.check_handle(fh)?;
+        self.fs.releasedir(self.inner_ino(ino)?, fh, flags).await?;
+        self.handles.lock().unwrap().remove(&fh);
+        Ok(())
+    }
+
+    /// Wrap a replier to renumber the entries of the directory `parent` (a file system inode)
+    fn reply<R: DirectoryReplier>(&self, parent: InodeNo, reply: R) -> ViewReply<R> {
+        ViewReply {
+            reply,
+            root: self.root,
+            at_root: parent == self.root,
+        }
+    }
+}
+
+impl<Client, Prefetcher> Drop for S3FilesystemView<'_, Client, Prefetcher>
+where
+    Client: ObjectClient + Send + Sync + 'static,
+    Prefetcher: Prefetch,
+{
+    fn drop(&mut self) {
+        let nodes: Vec<_> = self.remembered.iter().map(|&ino| (ino, 1)).collect();
+        self.fs.superblock.forget_multi(&nodes);
+    }
+}
+
+/// A [DirectoryReplier] that gives the entries of a directory in a view the view's inode numbers
+struct ViewReply<R: DirectoryReplier> {
+    reply: R,
+    /// The file system's inode for the view's root
+    root: InodeNo,
+    /// Whether the directory being listed is the view's root, whose `..` is itself
+    at_root: bool,
+}
+
+impl<R: DirectoryReplier> DirectoryReplier for ViewReply<R> {
+    fn add(&mut self, mut entry: DirectoryEntry) -> bool {
+        if entry.ino == self.root || (self.at_root && entry.name == "..") {
+            entry.ino = FUSE_ROOT_INODE;
+            entry.attr.ino = FUSE_ROOT_INODE;
+        }
+        self.reply.add(entry)
+    }
+}
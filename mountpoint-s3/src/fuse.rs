@@ -1,25 +1,37 @@
 //! Links _fuser_ method calls into Mountpoint's filesystem code in [crate::fs].
 
-use futures::executor::block_on;
 use mountpoint_s3_client::ObjectClient;
 use std::ffi::OsStr;
+use std::future::Future;
+use std::io::IoSlice;
 use std::path::Path;
-use std::time::SystemTime;
+use std::time::{SystemTime, UNIX_EPOCH};
 use time::OffsetDateTime;
 use tracing::{field, instrument, Instrument};
 
-use crate::fs::{DirectoryEntry, DirectoryReplier, InodeNo, S3Filesystem, S3FilesystemConfig, ToErrno};
+use crate::fs::{
+    DirectoryEntry, DirectoryReplier, Entry, FileAttr, FileType, InodeNo, LookupResult, NotifierSlot, OpenHandles,
+    S3Filesystem, S3FilesystemConfig, ToErrno,
+};
 use crate::prefetch::Prefetch;
 use crate::prefix::Prefix;
+use crate::sync::check_locks_not_held_across_await;
 #[cfg(target_os = "macos")]
 use fuser::ReplyXTimes;
 use fuser::{
-    Filesystem, KernelConfig, ReplyAttr, ReplyBmap, ReplyCreate, ReplyData, ReplyEmpty, ReplyEntry, ReplyIoctl,
-    ReplyLock, ReplyLseek, ReplyOpen, ReplyWrite, ReplyXattr, Request, TimeOrNow,
+    fuse_forget_one, Filesystem, KernelConfig, ReplyAttr, ReplyBmap, ReplyCreate, ReplyData, ReplyEmpty, ReplyEntry,
+    ReplyIoctl, ReplyLock, ReplyLseek, ReplyOpen, ReplyStatfs, ReplyWrite, ReplyXattr, Request, TimeOrNow,
 };
 
+pub mod idle;
 pub mod session;
 
+/// Run a file system operation to completion on the current thread. In debug builds, this also
+/// checks that the operation never holds the inode table's lock across an `.await`.
+fn block_on<F: Future>(future: F) -> F::Output {
+    futures::executor::block_on(check_locks_not_held_across_await(future))
+}
+
 /// `tracing` doesn't allow dynamic levels but we want to dynamically choose the log level for
 /// requests based on their response status. https://github.com/tokio-rs/tracing/issues/372
 macro_rules! event {
@@ -61,6 +73,52 @@ macro_rules! fuse_unsupported {
     };
 }
 
+/// Reply to `getxattr` or `listxattr`. A `size` of 0 asks how big the value is, rather than for
+/// the value itself.
+fn reply_xattr(value: &[u8], size: u32, reply: ReplyXattr) {
+    if size == 0 {
+        reply.size(value.len() as u32);
+    } else if value.len() > size as usize {
+        reply.error(libc::ERANGE);
+    } else {
+        reply.data(value);
+    }
+}
+
+/// The entry to reply to `lookup` with. A name that doesn't exist becomes a negative entry, with
+/// inode number 0, which the kernel caches for the entry's TTL instead of asking again. The kernel
+/// ignores the other attributes of a negative entry.
+fn lookup_reply(result: LookupResult) -> Entry {
+    match result {
+        LookupResult::Found(entry) => entry,
+        LookupResult::NegativeCached(ttl) => Entry {
+            ttl,
+            attr: negative_entry_attr(),
+            generation: 0,
+        },
+    }
+}
+
+fn negative_entry_attr() -> FileAttr {
+    FileAttr {
+        ino: 0,
+        size: 0,
+        blocks: 0,
+        atime: UNIX_EPOCH,
+        mtime: UNIX_EPOCH,
+        ctime: UNIX_EPOCH,
+        crtime: UNIX_EPOCH,
+        kind: FileType::RegularFile,
+        perm: 0,
+        nlink: 0,
+        uid: 0,
+        gid: 0,
+        rdev: 0,
+        blksize: 0,
+        flags: 0,
+    }
+}
+
 /// This is just a thin wrapper around [S3Filesystem] that implements the actual `fuser` protocol,
 /// so that we can test our actual filesystem implementation without having actual FUSE in the loop.
 pub struct S3FuseFilesystem<Client, Prefetcher>
@@ -87,6 +145,16 @@ where
 
         Self { fs }
     }
+
+    /// The slot for the file system's kernel notifier, to be set once the FUSE session exists
+    pub fn notifier_slot(&self) -> NotifierSlot {
+        self.fs.notifier_slot()
+    }
+
+    /// The count of handles the file system has open, for the FUSE session to track
+    pub fn open_handles(&self) -> OpenHandles {
+        self.fs.open_handles()
+    }
 }
 
 impl<Client, Prefetcher> Filesystem for S3FuseFilesystem<Client, Prefetcher>
@@ -101,8 +169,11 @@ where
 
     #[instrument(level="warn", skip_all, fields(req=_req.unique(), ino=parent, name=?name))]
     fn lookup(&self, _req: &Request<'_>, parent: InodeNo, name: &OsStr, reply: ReplyEntry) {
-        match block_on(self.fs.lookup(parent, name).in_current_span()) {
-            Ok(entry) => reply.entry(&entry.ttl, &entry.attr, entry.generation),
+        match block_on(self.fs.lookup_entry(parent, name).in_current_span()) {
+            Ok(result) => {
+                let entry = lookup_reply(result);
+                reply.entry(&entry.ttl, &entry.attr, entry.generation)
+            }
             Err(e) => fuse_error!("lookup", reply, e),
         }
     }
@@ -120,6 +191,12 @@ where
         block_on(self.fs.forget(ino, nlookup));
     }
 
+    #[instrument(level="warn", skip_all, fields(req=_req.unique(), count=nodes.len(), name=field::Empty))]
+    fn batch_forget(&self, _req: &Request<'_>, nodes: &[fuse_forget_one]) {
+        let nodes: Vec<_> = nodes.iter().map(|node| (node.nodeid, node.nlookup)).collect();
+        block_on(self.fs.forget_multi(&nodes));
+    }
+
     #[instrument(level="warn", skip_all, fields(req=req.unique(), ino=ino, pid=req.pid(), name=field::Empty))]
     fn open(&self, req: &Request<'_>, ino: InodeNo, flags: i32, reply: ReplyOpen) {
         match block_on(self.fs.open(ino, flags, req.pid()).in_current_span()) {
@@ -142,10 +219,16 @@ where
     ) {
         let mut bytes_sent = 0;
 
-        match block_on(self.fs.read(ino, fh, offset, size, flags, lock).in_current_span()) {
-            Ok(data) => {
-                bytes_sent = data.len();
-                reply.data(&data);
+        match block_on(
+            self.fs
+                .read_vectored(ino, fh, offset, size, flags, lock)
+                .in_current_span(),
+        ) {
+            Ok(parts) => {
+                bytes_sent = parts.iter().map(|part| part.len()).sum();
+                // The data is only copied once, by the kernel, from the prefetched buffers
+                let slices: Vec<_> = parts.iter().map(|part| IoSlice::new(part)).collect();
+                reply.data_vectored(&slices);
             }
             Err(err) => fuse_error!("read", reply, err),
         }
@@ -171,7 +254,10 @@ where
 
         impl<'a> DirectoryReplier for ReplyDirectory<'a> {
             fn add(&mut self, entry: DirectoryEntry) -> bool {
-                let result = self.inner.add(entry.ino, entry.offset, entry.attr.kind, entry.name);
+                let result = match entry.dtype {
+                    Some(kind) => self.inner.add(entry.ino, entry.offset, kind, entry.name),
+                    None => self.inner.add_unknown_type(entry.ino, entry.offset, entry.name),
+                };
                 if !result {
                     *self.count += 1;
                 }
@@ -360,9 +446,9 @@ where
         &self,
         _req: &Request<'_>,
         ino: u64,
-        _mode: Option<u32>,
-        _uid: Option<u32>,
-        _gid: Option<u32>,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
         size: Option<u64>,
         atime: Option<TimeOrNow>,
         mtime: Option<TimeOrNow>,
@@ -382,12 +468,75 @@ where
             TimeOrNow::SpecificTime(st) => OffsetDateTime::from(st),
             TimeOrNow::Now => OffsetDateTime::now_utc(),
         });
-        match block_on(self.fs.setattr(ino, atime, mtime, size, flags).in_current_span()) {
+        match block_on(
+            self.fs
+                .setattr(ino, mode, uid, gid, atime, mtime, size, flags)
+                .in_current_span(),
+        ) {
             Ok(attr) => reply.attr(&attr.ttl, &attr.attr),
             Err(e) => fuse_error!("setattr", reply, e),
         }
     }
 
+    #[instrument(level="warn", skip_all, fields(req=_req.unique(), ino_in=ino_in, fh_in=fh_in, offset_in=offset_in, ino_out=ino_out, fh_out=fh_out, offset_out=offset_out, len=len, name=field::Empty))]
+    fn copy_file_range(
+        &self,
+        _req: &Request<'_>,
+        ino_in: u64,
+        fh_in: u64,
+        offset_in: i64,
+        ino_out: u64,
+        fh_out: u64,
+        offset_out: i64,
+        len: u64,
+        flags: u32,
+        reply: ReplyWrite,
+    ) {
+        match block_on(
+            self.fs
+                .copy_file_range(ino_in, fh_in, offset_in, ino_out, fh_out, offset_out, len, flags)
+                .in_current_span(),
+        ) {
+            Ok(bytes_copied) => reply.written(bytes_copied),
+            Err(e) => fuse_error!("copy_file_range", reply, e),
+        }
+    }
+
+    #[instrument(level="warn", skip_all, fields(req=_req.unique(), ino=ino))]
+    fn statfs(&self, _req: &Request<'_>, ino: u64, reply: ReplyStatfs) {
+        match block_on(self.fs.statfs(ino).in_current_span()) {
+            Ok(statfs) => reply.statfs(
+                statfs.total_blocks,
+                statfs.free_blocks,
+                statfs.available_blocks,
+                statfs.total_inodes,
+                statfs.free_inodes,
+                statfs.block_size,
+                statfs.maximum_name_length,
+                statfs.fragment_size,
+            ),
+            Err(e) => fuse_error!("statfs", reply, e),
+        }
+    }
+
+    #[instrument(level="warn", skip_all, fields(req=_req.unique(), ino=ino, name=?name))]
+    fn getxattr(&self, _req: &Request<'_>, ino: u64, name: &OsStr, size: u32, reply: ReplyXattr) {
+        match block_on(self.fs.getxattr(ino, name).in_current_span()) {
+            Ok(value) => reply_xattr(&value, size, reply),
+            Err(e) if e.to_errno() == libc::ENOSYS => fuse_unsupported!("getxattr", reply),
+            Err(e) => fuse_error!("getxattr", reply, e),
+        }
+    }
+
+    #[instrument(level="warn", skip_all, fields(req=_req.unique(), ino=ino))]
+    fn listxattr(&self, _req: &Request<'_>, ino: u64, size: u32, reply: ReplyXattr) {
+        match block_on(self.fs.listxattr(ino).in_current_span()) {
+            Ok(names) => reply_xattr(&names, size, reply),
+            Err(e) if e.to_errno() == libc::ENOSYS => fuse_unsupported!("listxattr", reply),
+            Err(e) => fuse_error!("listxattr", reply, e),
+        }
+    }
+
     // Everything below here is stubs for unsupported functions so we log them correctly
 
     #[instrument(level="warn", skip_all, fields(req=_req.unique(), ino=ino))]
@@ -397,8 +546,12 @@ where
 
     #[instrument(level="warn", skip_all, fields(req=_req.unique(), parent=parent, name=?name, link=?link))]
     fn symlink(&self, _req: &Request<'_>, parent: u64, name: &OsStr, link: &Path, reply: ReplyEntry) {
-        // Userspace expects EPERM for link/symlink if unsupported
-        fuse_unsupported!("symlink", reply, libc::EPERM);
+        match block_on(self.fs.symlink(parent, name, link).in_current_span()) {
+            Ok(entry) => reply.entry(&entry.ttl, &entry.attr, entry.generation),
+            // Userspace expects EPERM for link/symlink if unsupported
+            Err(e) if e.to_errno() == libc::EPERM => fuse_unsupported!("symlink", reply, libc::EPERM),
+            Err(e) => fuse_error!("symlink", reply, e),
+        }
     }
 
     #[instrument(level="warn", skip_all, fields(req=_req.unique(), parent=parent, name=?name, newparent=newparent, newname=?newname))]
@@ -409,10 +562,18 @@ where
         name: &OsStr,
         newparent: u64,
         newname: &OsStr,
-        _flags: u32,
+        flags: u32,
         reply: ReplyEmpty,
     ) {
-        fuse_unsupported!("rename", reply);
+        match block_on(
+            self.fs
+                .rename(parent, name, newparent, newname, flags)
+                .in_current_span(),
+        ) {
+            Ok(()) => reply.ok(),
+            Err(e) if e.to_errno() == libc::ENOSYS => fuse_unsupported!("rename", reply),
+            Err(e) => fuse_error!("rename", reply, e),
+        }
     }
 
     #[instrument(level="warn", skip_all, fields(req=_req.unique(), ino=ino, newparent=newparent, newname=?newname))]
@@ -440,16 +601,6 @@ where
         fuse_unsupported!("setxattr", reply);
     }
 
-    #[instrument(level="warn", skip_all, fields(req=_req.unique(), ino=ino, name=?name))]
-    fn getxattr(&self, _req: &Request<'_>, ino: u64, name: &OsStr, _size: u32, reply: ReplyXattr) {
-        fuse_unsupported!("getxattr", reply);
-    }
-
-    #[instrument(level="warn", skip_all, fields(req=_req.unique(), ino=ino))]
-    fn listxattr(&self, _req: &Request<'_>, ino: u64, _size: u32, reply: ReplyXattr) {
-        fuse_unsupported!("listxattr", reply);
-    }
-
     #[instrument(level="warn", skip_all, fields(req=_req.unique(), ino=ino, name=?name))]
     fn removexattr(&self, _req: &Request<'_>, ino: u64, name: &OsStr, reply: ReplyEmpty) {
         fuse_unsupported!("removexattr", reply);
@@ -527,18 +678,12 @@ where
         fuse_unsupported!("ioctl", reply);
     }
 
-    #[instrument(level="warn", skip_all, fields(req=_req.unique(), ino=ino, fh=fh, offset=offset, length=length))]
-    fn fallocate(
-        &self,
-        _req: &Request<'_>,
-        ino: u64,
-        fh: u64,
-        offset: i64,
-        length: i64,
-        _mode: i32,
-        reply: ReplyEmpty,
-    ) {
-        fuse_unsupported!("fallocate", reply);
+    #[instrument(level="warn", skip_all, fields(req=_req.unique(), ino=ino, fh=fh, offset=offset, length=length, name=field::Empty))]
+    fn fallocate(&self, _req: &Request<'_>, ino: u64, fh: u64, offset: i64, length: i64, mode: i32, reply: ReplyEmpty) {
+        match block_on(self.fs.fallocate(ino, fh, offset, length, mode).in_current_span()) {
+            Ok(()) => reply.ok(),
+            Err(e) => fuse_error!("fallocate", reply, e),
+        }
     }
 
     #[instrument(level="warn", skip_all, fields(req=_req.unique(), ino=ino, fh=fh, offset=offset, whence=whence))]
@@ -590,3 +735,32 @@ where
         fuse_unsupported!("getxtimes", reply);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_negative_lookup_reply() {
+        let ttl = Duration::from_secs(5);
+        let entry = lookup_reply(LookupResult::NegativeCached(ttl));
+        assert_eq!(entry.ttl, ttl);
+        assert_eq!(entry.attr.ino, 0, "negative entries must have inode number 0");
+        assert_eq!(entry.generation, 0);
+
+        let attr = FileAttr {
+            ino: 42,
+            ..negative_entry_attr()
+        };
+        let found = Entry {
+            ttl,
+            attr,
+            generation: 0,
+        };
+        let entry = lookup_reply(LookupResult::Found(found));
+        assert_eq!(entry.attr.ino, 42);
+        assert_eq!(entry.ttl, ttl);
+    }
+}
@@ -0,0 +1,275 @@
+//! Detecting when a FUSE session has gone idle, for ephemeral mounts that should release their
+//! resources (or unmount themselves) once nothing is using them.
+
+use std::io;
+use std::ops::ControlFlow;
+use std::time::{Duration, Instant};
+
+// The monitor waits on real time, which Shuttle can't model, so this uses std's primitives
+// rather than [crate::sync]'s.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use tracing::{debug, trace};
+
+use crate::fs::OpenHandles;
+use crate::periodic::PeriodicTask;
+
+/// How often an [IdleMonitor] checks for activity, at most. Shorter timeouts are checked more often.
+const MAX_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+pub use crate::clock::{Clock, SystemClock};
+
+/// Tracks when a FUSE session last handled an operation, and whether it's still in use. A session
+/// isn't idle while it's handling a request or while the file system has handles open, however
+/// long ago the last request started.
+#[derive(Debug)]
+pub struct ActivityTracker {
+    clock: Arc<dyn Clock>,
+    /// When the tracker was created. Activity is recorded relative to this so it fits an atomic.
+    start: Instant,
+    /// Nanoseconds after `start` of the latest activity
+    last_activity: AtomicU64,
+    /// Requests started and not finished yet
+    in_flight: AtomicU64,
+    /// The file system's open handles, once they're tracked
+    open_handles: OnceLock<OpenHandles>,
+}
+
+impl ActivityTracker {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        let start = clock.now();
+        Self {
+            clock,
+            start,
+            last_activity: AtomicU64::new(0),
+            in_flight: AtomicU64::new(0),
+            open_handles: OnceLock::new(),
+        }
+    }
+
+    /// Record that a request started now. It's in flight until [Self::finish_request].
+    pub fn start_request(&self) {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        self.record();
+    }
+
+    /// Record that a request started with [Self::start_request] finished now
+    pub fn finish_request(&self) {
+        self.record();
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Count the file system's open handles as activity. Can only be set once.
+    pub fn track_open_handles(&self, open_handles: OpenHandles) {
+        if self.open_handles.set(open_handles).is_err() {
+            debug!("open handles were already tracked");
+        }
+    }
+
+    /// Whether a request is in flight or the file system has a handle open
+    fn in_use(&self) -> bool {
+        self.in_flight.load(Ordering::SeqCst) > 0 || self.open_handles.get().is_some_and(|handles| handles.count() > 0)
+    }
+
+    /// Record that an operation happened now
+    pub fn record(&self) {
+        let nanos = self.clock.now().saturating_duration_since(self.start).as_nanos();
+        self.last_activity
+            .fetch_max(nanos.try_into().unwrap_or(u64::MAX), Ordering::Relaxed);
+    }
+
+    /// How long it's been since the latest operation, or since the tracker was created if there
+    /// hasn't been one. Always zero while the session is in use.
+    pub fn idle_for(&self) -> Duration {
+        if self.in_use() {
+            return Duration::ZERO;
+        }
+        let last_activity = self.start + Duration::from_nanos(self.last_activity.load(Ordering::Relaxed));
+        self.clock.now().saturating_duration_since(last_activity)
+    }
+}
+
+/// Calls a function, once, when an [ActivityTracker] has seen no activity for a timeout. The
+/// monitor stops when dropped, without calling the function if it hadn't already.
+#[derive(Debug)]
+pub struct IdleMonitor {
+    _task: PeriodicTask,
+}
+
+impl IdleMonitor {
+    pub fn start<F>(tracker: Arc<ActivityTracker>, timeout: Duration, on_idle: F) -> io::Result<Self>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let check_interval = timeout.min(MAX_CHECK_INTERVAL);
+        Self::start_with_interval(tracker, timeout, check_interval, on_idle)
+    }
+
+    fn start_with_interval<F>(
+        tracker: Arc<ActivityTracker>,
+        timeout: Duration,
+        check_interval: Duration,
+        on_idle: F,
+    ) -> io::Result<Self>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let mut on_idle = Some(on_idle);
+        let task = PeriodicTask::start("idle-monitor", check_interval, move || {
+            let idle_for = tracker.idle_for();
+            trace!(?idle_for, "checking for activity");
+            if idle_for < timeout {
+                return ControlFlow::Continue(());
+            }
+            debug!(?idle_for, "no activity for the idle timeout");
+            if let Some(on_idle) = on_idle.take() {
+                on_idle();
+            }
+            ControlFlow::Break(())
+        })?;
+        Ok(Self { _task: task })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use std::sync::mpsc::{self, RecvTimeoutError};
+
+    const TIMEOUT: Duration = Duration::from_secs(600);
+    const CHECK_INTERVAL: Duration = Duration::from_millis(5);
+
+    fn start_monitor(clock: &Arc<MockClock>) -> (Arc<ActivityTracker>, IdleMonitor, mpsc::Receiver<()>) {
+        let tracker = Arc::new(ActivityTracker::new(clock.clone()));
+        let (idle_tx, idle_rx) = mpsc::channel();
+        let monitor = IdleMonitor::start_with_interval(tracker.clone(), TIMEOUT, CHECK_INTERVAL, move || {
+            idle_tx.send(()).unwrap();
+        })
+        .unwrap();
+        (tracker, monitor, idle_rx)
+    }
+
+    #[test]
+    fn fires_after_timeout() {
+        let clock = Arc::new(MockClock::new());
+        let (_tracker, _monitor, idle_rx) = start_monitor(&clock);
+
+        clock.advance(TIMEOUT - Duration::from_secs(1));
+        assert_eq!(
+            idle_rx.recv_timeout(20 * CHECK_INTERVAL),
+            Err(RecvTimeoutError::Timeout),
+            "should not fire before the timeout"
+        );
+
+        clock.advance(Duration::from_secs(1));
+        idle_rx
+            .recv_timeout(Duration::from_secs(10))
+            .expect("should fire after the timeout");
+        // Only once
+        assert_eq!(
+            idle_rx.recv_timeout(20 * CHECK_INTERVAL),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn activity_resets_timeout() {
+        let clock = Arc::new(MockClock::new());
+        let (tracker, _monitor, idle_rx) = start_monitor(&clock);
+
+        for _ in 0..3 {
+            clock.advance(TIMEOUT - Duration::from_secs(1));
+            tracker.record();
+        }
+        clock.advance(TIMEOUT - Duration::from_secs(1));
+        assert_eq!(
+            idle_rx.recv_timeout(20 * CHECK_INTERVAL),
+            Err(RecvTimeoutError::Timeout),
+            "activity should have kept the monitor from firing"
+        );
+
+        clock.advance(Duration::from_secs(1));
+        idle_rx
+            .recv_timeout(Duration::from_secs(10))
+            .expect("should fire once activity stops");
+    }
+
+    #[test]
+    fn in_flight_request_keeps_session_busy() {
+        let clock = Arc::new(MockClock::new());
+        let (tracker, _monitor, idle_rx) = start_monitor(&clock);
+
+        // A request that takes longer than the timeout, like a large read
+        tracker.start_request();
+        clock.advance(2 * TIMEOUT);
+        assert_eq!(
+            idle_rx.recv_timeout(20 * CHECK_INTERVAL),
+            Err(RecvTimeoutError::Timeout),
+            "should not fire while a request is in flight"
+        );
+
+        // The timeout starts again from when the request finished
+        tracker.finish_request();
+        clock.advance(TIMEOUT - Duration::from_secs(1));
+        assert_eq!(
+            idle_rx.recv_timeout(20 * CHECK_INTERVAL),
+            Err(RecvTimeoutError::Timeout),
+            "should not fire before the timeout after the request finished"
+        );
+
+        clock.advance(Duration::from_secs(1));
+        idle_rx
+            .recv_timeout(Duration::from_secs(10))
+            .expect("should fire once the request has finished for the timeout");
+    }
+
+    #[test]
+    fn open_handles_keep_session_busy() {
+        let clock = Arc::new(MockClock::new());
+        let (tracker, _monitor, idle_rx) = start_monitor(&clock);
+        let open_handles = OpenHandles::default();
+        tracker.track_open_handles(open_handles.clone());
+
+        // A file held open without requests, like a process that tails a log
+        tracker.start_request();
+        open_handles.opened();
+        tracker.finish_request();
+        clock.advance(2 * TIMEOUT);
+        assert_eq!(
+            idle_rx.recv_timeout(20 * CHECK_INTERVAL),
+            Err(RecvTimeoutError::Timeout),
+            "should not fire while a handle is open"
+        );
+
+        tracker.start_request();
+        open_handles.released();
+        tracker.finish_request();
+        clock.advance(TIMEOUT - Duration::from_secs(1));
+        assert_eq!(
+            idle_rx.recv_timeout(20 * CHECK_INTERVAL),
+            Err(RecvTimeoutError::Timeout),
+            "should not fire before the timeout after the handle was released"
+        );
+
+        clock.advance(Duration::from_secs(1));
+        idle_rx
+            .recv_timeout(Duration::from_secs(10))
+            .expect("should fire once the handle has been released for the timeout");
+    }
+
+    #[test]
+    fn stops_when_dropped() {
+        let clock = Arc::new(MockClock::new());
+        let (_tracker, monitor, idle_rx) = start_monitor(&clock);
+
+        drop(monitor);
+        clock.advance(2 * TIMEOUT);
+        assert_eq!(
+            idle_rx.recv_timeout(Duration::from_secs(10)),
+            Err(RecvTimeoutError::Disconnected),
+            "a stopped monitor should never fire"
+        );
+    }
+}
@@ -1,29 +1,59 @@
 use std::io;
+use std::time::Duration;
 
 use anyhow::Context;
 use fuser::{Filesystem, Session, SessionUnmounter};
-use tracing::{debug, error, trace, warn};
+use tracing::{debug, error, info, trace, warn};
 
 use crate::sync::atomic::{AtomicUsize, Ordering};
 use crate::sync::mpsc::{self, Sender};
 use crate::sync::thread::{self, JoinHandle};
 use crate::sync::Arc;
 
+use super::idle::{ActivityTracker, IdleMonitor, SystemClock};
+use crate::fs::OpenHandles;
+use crate::logging::panic_message;
+
 /// A multi-threaded FUSE session that can be joined to wait for the FUSE filesystem to unmount or
 /// this process to be interrupted.
 pub struct FuseSession {
     unmounter: SessionUnmounter,
     /// Waits for messages from threads or signal handler.
     receiver: mpsc::Receiver<Message>,
+    /// Sends messages to [Self::receiver], for [ShutdownHandle]s.
+    sender: Sender<Message>,
     /// List of closures or functions to call when session is exiting.
     on_close: Vec<OnClose>,
+    /// When the session last handled a request, for [Self::on_idle].
+    activity: Arc<ActivityTracker>,
+    /// Watches [Self::activity] once an idle timeout is set.
+    idle_monitor: Option<IdleMonitor>,
 }
 
 type OnClose = Box<dyn FnOnce()>;
 
 impl FuseSession {
-    /// Create worker threads to dispatch requests for a FUSE session.
+    /// Create worker threads to dispatch requests for a FUSE session, and handle SIGINT and SIGTERM
+    /// by closing the session.
     pub fn new<FS: Filesystem + Send + Sync + 'static>(
+        session: Session<FS>,
+        max_worker_threads: usize,
+    ) -> anyhow::Result<Self> {
+        let fuse_session = Self::new_without_signal_handler(session, max_worker_threads)?;
+
+        let tx = fuse_session.sender.clone();
+        ctrlc::set_handler(move || {
+            let _ = tx.send(Message::Interrupted);
+        })
+        .context("failed to set interrupt handler")?;
+
+        Ok(fuse_session)
+    }
+
+    /// Create worker threads to dispatch requests for a FUSE session, without handling signals.
+    /// A process can only have one signal handler, so this is for running several sessions in one
+    /// process, like tests do. Use a [ShutdownHandle] to close the session instead.
+    pub fn new_without_signal_handler<FS: Filesystem + Send + Sync + 'static>(
         mut session: Session<FS>,
         max_worker_threads: usize,
     ) -> anyhow::Result<Self> {
@@ -45,11 +75,7 @@ impl FuseSession {
                         let thread_name = thd.thread().name().map(ToOwned::to_owned);
                         match thd.join() {
                             Err(panic_param) => {
-                                // Try to downcast as &str or String to log
-                                let panic_msg = match panic_param.downcast_ref::<&str>() {
-                                    Some(s) => Some(*s),
-                                    None => panic_param.downcast_ref::<String>().map(AsRef::as_ref),
-                                };
+                                let panic_msg = panic_message(panic_param.as_ref());
                                 error!(thread_name, panic_msg, "worker thread panicked");
                             }
                             Ok(thd_result) => {
@@ -67,30 +93,75 @@ impl FuseSession {
                 .context("failed to spawn waiter thread")?
         };
 
-        ctrlc::set_handler(move || {
-            let _ = tx.send(Message::Interrupted);
-        })
-        .context("failed to set interrupt handler")?;
-
-        WorkerPool::start(session, workers_tx, max_worker_threads).context("failed to start worker thread pool")?;
+        let activity = Arc::new(ActivityTracker::new(Arc::new(SystemClock)));
+        let work = TrackedWork {
+            work: session,
+            activity: activity.clone(),
+        };
+        WorkerPool::start(work, workers_tx, max_worker_threads).context("failed to start worker thread pool")?;
 
         Ok(Self {
             unmounter,
             receiver: rx,
+            sender: tx,
             on_close: Default::default(),
+            activity,
+            idle_monitor: None,
         })
     }
 
+    /// A handle to close this session from another thread, as if the process had been interrupted
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            sender: self.sender.clone(),
+        }
+    }
+
     /// Add a new handler which is executed when this session is shutting down.
     pub fn run_on_close(&mut self, handler: OnClose) {
         self.on_close.push(handler);
     }
 
-    /// Block until the file system is unmounted or this process is interrupted via SIGTERM/SIGINT.
-    /// When that happens, unmount the file system (if it hasn't been already unmounted).
+    /// Count the file system's open handles as activity, so the session doesn't go idle while
+    /// anything has a file or directory open
+    pub fn track_open_handles(&self, open_handles: OpenHandles) {
+        self.activity.track_open_handles(open_handles);
+    }
+
+    /// Call `handler`, once, when the session has handled no requests for `timeout`, and none is
+    /// in flight or holding a handle open (see [Self::track_open_handles]). Replaces any handler
+    /// set before. Forget requests don't count as activity, since the kernel sends them on its own
+    /// schedule rather than because something is using the file system.
+    pub fn on_idle<F>(&mut self, timeout: Duration, handler: F) -> anyhow::Result<()>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let monitor = IdleMonitor::start(self.activity.clone(), timeout, handler)
+            .context("failed to spawn idle monitor thread")?;
+        self.idle_monitor = Some(monitor);
+        Ok(())
+    }
+
+    /// Close the session, making [Self::join] unmount the file system, when it has handled no
+    /// requests for `timeout`.
+    pub fn unmount_on_idle(&mut self, timeout: Duration) -> anyhow::Result<()> {
+        let tx = self.sender.clone();
+        self.on_idle(timeout, move || {
+            let _ = tx.send(Message::Idle);
+        })
+    }
+
+    /// Block until the file system is unmounted, this process is interrupted via SIGTERM/SIGINT, or
+    /// the session goes idle (see [Self::unmount_on_idle]). When that happens, unmount the file
+    /// system (if it hasn't been already unmounted).
     pub fn join(mut self) -> anyhow::Result<()> {
         let msg = self.receiver.recv();
         trace!("received message {msg:?}, closing filesystem session");
+        if matches!(msg, Ok(Message::Idle)) {
+            info!("no file system activity for the idle timeout, unmounting");
+        }
+        // Stop watching for activity, so a handler can't fire while we're closing
+        drop(self.idle_monitor.take());
 
         trace!("executing {} handler(s) on close", self.on_close.len());
         for handler in self.on_close {
@@ -102,10 +173,24 @@ impl FuseSession {
     }
 }
 
+/// Closes a [FuseSession], making [FuseSession::join] unmount the file system and return
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    sender: Sender<Message>,
+}
+
+impl ShutdownHandle {
+    /// Close the session. Does nothing if it has already closed.
+    pub fn shutdown(&self) {
+        let _ = self.sender.send(Message::Interrupted);
+    }
+}
+
 #[derive(Debug)]
 enum Message {
     WorkersExited,
     Interrupted,
+    Idle,
 }
 
 trait Work: Send + Sync + 'static {
@@ -239,6 +324,33 @@ where
     }
 }
 
+/// [Work] that records each unit of work it starts and finishes with an [ActivityTracker]
+struct TrackedWork<W: Work> {
+    work: W,
+    activity: Arc<ActivityTracker>,
+}
+
+impl<W: Work> Work for TrackedWork<W> {
+    type Result = W::Result;
+
+    fn run<FB, FA>(&self, mut before: FB, mut after: FA) -> Self::Result
+    where
+        FB: FnMut(),
+        FA: FnMut(),
+    {
+        self.work.run(
+            || {
+                self.activity.start_request();
+                before();
+            },
+            || {
+                self.activity.finish_request();
+                after();
+            },
+        )
+    }
+}
+
 #[cfg(target_os = "linux")]
 fn get_thread_id_string() -> String {
     // SAFETY: this syscall is available since Linux 2.4.11 but glibc didn't
@@ -21,60 +21,97 @@
 //! Some cached state is dependent on the inode kind; that state is hidden behind a [InodeStatKind]
 //! enum.
 
+use std::collections::hash_map::DefaultHasher;
 use std::collections::{HashMap, HashSet};
 use std::ffi::{OsStr, OsString};
 use std::fmt::{Debug, Display};
+use std::hash::{Hash, Hasher};
 use std::os::unix::prelude::OsStrExt;
 use std::sync::atomic::AtomicBool;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::anyhow;
-use fuser::FileType;
 use futures::{select_biased, FutureExt};
+use globset::GlobSet;
 use mountpoint_s3_client::error::{HeadObjectError, ObjectClientError};
-use mountpoint_s3_client::types::{HeadObjectResult, RestoreStatus};
+use mountpoint_s3_client::types::{HeadObjectResult, ListObjectsResult, ObjectInfo, RestoreStatus};
 use mountpoint_s3_client::ObjectClient;
 use mountpoint_s3_crt::checksums::crc32c::{self, Crc32c};
 use thiserror::Error;
 use time::OffsetDateTime;
 use tracing::{debug, error, trace, warn};
 
-use crate::fs::CacheConfig;
+use crate::clock::{Clock, SystemClock};
+use crate::fs::{
+    CacheConfig, CircuitBreaker, CircuitBreakerOpen, FileType, KeyFailureConfig, MetadataCacheStats, PathRules, Permit,
+    RewindMode,
+};
 use crate::logging;
 use crate::prefix::Prefix;
 use crate::s3::S3Personality;
 use crate::sync::atomic::{AtomicU64, Ordering};
 use crate::sync::RwLockReadGuard;
 use crate::sync::RwLockWriteGuard;
-use crate::sync::{Arc, RwLock};
+use crate::sync::{Arc, AsyncOnceCell, InstrumentedRwLock, Mutex, RwLock};
 
 mod expiry;
 use expiry::Expiry;
 
+mod key_failures;
+use key_failures::{KeyFailures, PermanentFailure};
+
 mod negative_cache;
 use negative_cache::NegativeCache;
 
+mod poller;
+pub use poller::DirectoryPoller;
+
 mod readdir;
 pub use readdir::ReaddirHandle;
+use readdir::{BootstrapListings, PinnedListings};
 
 pub type InodeNo = u64;
 
 pub const ROOT_INODE_NO: InodeNo = 1;
 
-// 200 years seems long enough
-const NEVER_EXPIRE_TTL: Duration = Duration::from_secs(200 * 365 * 24 * 60 * 60);
+// 200 years seems long enough, and is the longest TTL we track anyway
+const NEVER_EXPIRE_TTL: Duration = expiry::MAX_TTL;
+
+/// ETag of the empty files that stand in for missing objects, see [SuperblockConfig::soft_missing_paths]
+const SOFT_MISSING_ETAG: &str = "\"soft-missing\"";
+
+/// Maximum length of a single path component, in bytes. The kernel rejects longer names itself
+/// for most operations, but we check anyway so every operation fails the same way.
+pub const MAX_NAME_LEN: usize = 255;
 
 pub fn valid_inode_name<T: AsRef<OsStr>>(name: T) -> bool {
-    let name = name.as_ref();
-    // Names cannot be empty
-    !name.is_empty() &&
-    // "." and ".." are reserved names (presented by the filesystem layer)
-    name != "." &&
-    name != ".." &&
-    // The delimiter / can never appear in a name
-    !name.as_bytes().contains(&b'/') &&
-    // NUL is invalid in POSIX names
-    !name.as_bytes().contains(&b'\0')
+    validate_inode_name(name.as_ref()).is_ok()
+}
+
+/// Check that `name` can be used as a single path component, returning it as a `&str` if so.
+/// Fails with [InodeError::NameTooLong] if it's longer than [MAX_NAME_LEN] bytes, or
+/// [InodeError::InvalidFileName] if it can't be a name at all.
+pub fn validate_inode_name(name: &OsStr) -> Result<&str, InodeError> {
+    let bytes = name.as_bytes();
+    if bytes.len() > MAX_NAME_LEN {
+        return Err(InodeError::NameTooLong(name.to_owned()));
+    }
+    let valid =
+        // Names cannot be empty
+        !bytes.is_empty() &&
+        // "." and ".." are reserved names (presented by the filesystem layer)
+        name != "." &&
+        name != ".." &&
+        // The delimiter / can never appear in a name
+        !bytes.contains(&b'/') &&
+        // NUL is invalid in POSIX names
+        !bytes.contains(&b'\0');
+    if !valid {
+        return Err(InodeError::InvalidFileName(name.to_owned()));
+    }
+    // S3 keys must be valid UTF-8
+    name.to_str()
+        .ok_or_else(|| InodeError::InvalidFileName(name.to_owned()))
 }
 
 /// Superblock is the root object of the file system
@@ -86,18 +123,94 @@ pub struct Superblock {
 #[derive(Debug)]
 struct SuperblockInner {
     bucket: String,
-    inodes: RwLock<InodeMap>,
+    inodes: InstrumentedRwLock<InodeMap>,
     negative_cache: NegativeCache,
+    /// Keys whose lookups keep failing permanently, see [SuperblockConfig::key_failures]
+    key_failures: KeyFailures,
+    /// Directories being polled for remote changes, and the fingerprint of their last listing, once
+    /// they've been listed
+    watched_directories: Mutex<HashMap<InodeNo, Option<u64>>>,
+    /// Remote lookups in flight, or completed within [CacheConfig::lookup_coalesce_window], by
+    /// parent inode and name
+    pending_lookups: Mutex<HashMap<(InodeNo, String), Arc<PendingLookup>>>,
+    /// Count of local changes (and remote changes we've been told about) that could make a recent
+    /// remote lookup's result out of date. Lookups made before a change aren't reused after it.
+    local_changes: AtomicU64,
+    /// Complete listings of directories under [CacheConfig::pinned_prefixes]
+    pinned_listings: PinnedListings,
+    /// Listings of directories loaded from a listing manifest, see [Superblock::bootstrap_listings]
+    bootstrap_listings: BootstrapListings,
+    /// Keys under [SuperblockConfig::immutable_prefixes] whose objects changed anyway, and so are
+    /// no longer treated as immutable
+    changed_immutable_keys: Mutex<HashSet<String>>,
     next_ino: AtomicU64,
     mount_time: OffsetDateTime,
     config: SuperblockConfig,
 }
 
+/// The cache settings that apply to a particular key, see [SuperblockInner::cache_settings]
+#[derive(Debug, Clone, Copy)]
+struct CacheSettings {
+    serve_lookup_from_cache: bool,
+    file_ttl: Duration,
+    dir_ttl: Duration,
+    negative_cache_ttl: Duration,
+}
+
 /// Configuration for superblock operations
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone)]
 pub struct SuperblockConfig {
     pub cache_config: CacheConfig,
     pub s3_personality: S3Personality,
+    pub readdir_rewind_mode: RewindMode,
+    pub max_listing_depth: Option<usize>,
+    /// Most remote entries a directory handle holds in memory waiting to be returned by `readdir`.
+    /// See [S3FilesystemConfig::max_buffered_dir_entries](crate::S3FilesystemConfig::max_buffered_dir_entries).
+    pub max_buffered_dir_entries: Option<usize>,
+    /// Key (ending in `/`) of a directory that's hidden from the file system, as if it didn't exist
+    pub hidden_prefix: Option<String>,
+    /// Hide new files from lookups and `readdir` until their upload completes, so that they only
+    /// appear under their name once the object is published there
+    pub hide_unpublished_files: bool,
+    /// Paths, relative to the mount point, that are presented as empty files rather than not found
+    /// when there's no object for them
+    pub soft_missing_paths: Option<GlobSet>,
+    /// Overrides of [CacheConfig] for keys under particular prefixes
+    pub path_rules: PathRules,
+    /// Key prefixes (empty or ending in `/`) of files whose objects never change, so whose metadata
+    /// never expires
+    pub immutable_prefixes: Vec<String>,
+    /// Stop looking up keys in S3 for a while once their lookups keep failing permanently, or
+    /// `None` to always look them up
+    pub key_failures: Option<KeyFailureConfig>,
+    /// Source of the current time that cached metadata expires by
+    pub clock: Arc<dyn Clock>,
+    /// Circuit breaker every metadata request to S3 (lookups, revalidations, and listings) goes
+    /// through. Requests served from the cache don't touch it.
+    pub metadata_breaker: Arc<CircuitBreaker>,
+    /// List what's cached of a directory, even if it's expired, when the breaker refuses to list it
+    pub serve_stale_metadata: bool,
+}
+
+impl Default for SuperblockConfig {
+    fn default() -> Self {
+        Self {
+            cache_config: Default::default(),
+            s3_personality: Default::default(),
+            readdir_rewind_mode: Default::default(),
+            max_listing_depth: None,
+            max_buffered_dir_entries: None,
+            hidden_prefix: None,
+            hide_unpublished_files: false,
+            soft_missing_paths: None,
+            path_rules: Default::default(),
+            immutable_prefixes: Vec::new(),
+            key_failures: None,
+            clock: Arc::new(SystemClock),
+            metadata_breaker: Arc::new(CircuitBreaker::new(None, Arc::new(SystemClock))),
+            serve_stale_metadata: false,
+        }
+    }
 }
 
 impl Superblock {
@@ -114,7 +227,7 @@ impl Superblock {
             InodeState {
                 // The root inode never expires because there's no remote to consult for its
                 // metadata, and it always exists.
-                stat: InodeStat::for_directory(mount_time, NEVER_EXPIRE_TTL),
+                stat: InodeStat::for_directory(mount_time, NEVER_EXPIRE_TTL, config.clock.now()),
                 write_status: WriteStatus::Remote,
                 kind_data: InodeKindData::default_for(InodeKind::Directory),
                 lookup_count: 1,
@@ -125,12 +238,30 @@ impl Superblock {
         let mut inodes = InodeMap::default();
         inodes.insert(ROOT_INODE_NO, root);
 
-        let negative_cache = NegativeCache::new(config.cache_config.negative_cache_size, config.cache_config.file_ttl);
+        let cache_config = &config.cache_config;
+        let negative_cache = NegativeCache::new(
+            cache_config.negative_cache_size,
+            cache_config.negative_cache_max_bytes,
+            config.clock.clone(),
+        );
+        let pinned_listings = PinnedListings::new(
+            cache_config.pinned_listings_max_entries,
+            cache_config.pinned_listings_max_bytes,
+        );
+
+        let key_failures = KeyFailures::new(config.key_failures.clone(), config.clock.clone());
 
         let inner = SuperblockInner {
             bucket: bucket.to_owned(),
-            inodes: RwLock::new(inodes),
+            inodes: InstrumentedRwLock::new("inodes", inodes),
             negative_cache,
+            key_failures,
+            watched_directories: Default::default(),
+            pending_lookups: Default::default(),
+            local_changes: AtomicU64::new(0),
+            pinned_listings,
+            bootstrap_listings: Default::default(),
+            changed_immutable_keys: Default::default(),
             next_ino: AtomicU64::new(2),
             mount_time,
             config,
@@ -142,39 +273,75 @@ impl Superblock {
     /// The kernel may forget a number of references (`n`) in one forget message to our FUSE implementation.
     /// If the lookup count reaches zero, it is safe for the [Superblock] to delete the [Inode].
     pub fn forget(&self, ino: InodeNo, n: u64) {
-        let inode = {
-            if let Some(inode) = self.inner.inodes.read().unwrap().get(&ino).cloned() {
-                inode
-            } else {
-                debug_assert!(
-                    false,
-                    "forget should not be called on inode already removed from superblock"
-                );
-                error!("forget called on inode {ino} already removed from the superblock");
-                return;
-            }
+        self.forget_multi(&[(ino, n)]);
+    }
+
+    /// Forget a batch of inodes, as the kernel does with a `batch_forget` call. This has the same
+    /// effect as calling [forget](Self::forget) for each `(ino, n)` pair, but only locks the inode
+    /// table once to find the inodes, and once more to remove those whose lookup count reached zero.
+    pub fn forget_multi(&self, nodes: &[(InodeNo, u64)]) {
+        let found: Vec<(Inode, u64)> = {
+            let inodes = self.inner.inodes.read().unwrap();
+            nodes
+                .iter()
+                .filter_map(|&(ino, n)| {
+                    let inode = inodes.get(&ino).cloned();
+                    if inode.is_none() {
+                        debug_assert!(
+                            false,
+                            "forget should not be called on inode already removed from superblock"
+                        );
+                        error!("forget called on inode {ino} already removed from the superblock");
+                    }
+                    inode.map(|inode| (inode, n))
+                })
+                .collect()
         };
+        if let [(inode, _)] = found.as_slice() {
+            logging::record_name(inode.name());
+        }
 
-        logging::record_name(inode.name());
-        let new_lookup_count = inode.dec_lookup_count(n);
-        if new_lookup_count == 0 {
-            // Safe to remove, kernel no longer has a reference to it.
-            trace!(ino, "removing inode from superblock");
-            let Some(inode) = self.inner.inodes.write().unwrap().remove(&ino) else {
-                error!("forget called on inode {ino} already removed from the superblock");
-                return;
-            };
+        let forgotten: Vec<Inode> = found
+            .into_iter()
+            .filter_map(|(inode, n)| (inode.dec_lookup_count(n) == 0).then_some(inode))
+            .collect();
+        if forgotten.is_empty() {
+            return;
+        }
 
-            let parent = {
-                if let Some(parent) = self.inner.inodes.read().unwrap().get(&inode.parent()).cloned() {
-                    parent
-                } else {
-                    // Should be impossible for this to fail (VFS inodes reference their parent, so
-                    // children need to be freed first), but let's not crash in a `forget` function...
-                    debug_assert!(false, "children should be forgotten before parents");
-                    return;
-                }
+        // Safe to remove, kernel no longer has a reference to them. Find their parents while we
+        // hold the lock too, including parents forgotten in the same batch.
+        let removed: Vec<(Inode, Option<Inode>)> = {
+            let mut inodes = self.inner.inodes.write().unwrap();
+            let removed: HashMap<InodeNo, Inode> = forgotten
+                .iter()
+                .filter_map(|inode| {
+                    let ino = inode.ino();
+                    trace!(ino, "removing inode from superblock");
+                    let removed = inodes.remove(&ino);
+                    if removed.is_none() {
+                        error!("forget called on inode {ino} already removed from the superblock");
+                    }
+                    removed.map(|inode| (ino, inode))
+                })
+                .collect();
+            removed
+                .values()
+                .map(|inode| {
+                    let parent = inodes.get(&inode.parent()).or_else(|| removed.get(&inode.parent()));
+                    (inode.clone(), parent.cloned())
+                })
+                .collect()
+        };
+
+        for (inode, parent) in removed {
+            let Some(parent) = parent else {
+                // Should be impossible for this to fail (VFS inodes reference their parent, so
+                // children need to be freed first), but let's not crash in a `forget` function...
+                debug_assert!(false, "children should be forgotten before parents");
+                continue;
             };
+            let ino = inode.ino();
             let mut parent_state = parent.inner.sync.write().unwrap();
             let InodeKindData::Directory {
                 children,
@@ -191,14 +358,81 @@ impl Superblock {
                 }
             }
             writing_children.remove(&ino);
+            drop(parent_state);
+
+            self.inner.watched_directories.lock().unwrap().remove(&ino);
 
             if let Ok(state) = inode.get_inode_state() {
                 metrics::counter!("metadata_cache.inode_forgotten_before_expiry")
-                    .increment(state.stat.is_valid().into());
+                    .increment(state.stat.is_valid(self.inner.now()).into());
             };
         }
     }
 
+    /// The current time, as cached metadata expires by
+    pub fn now(&self) -> Instant {
+        self.inner.now()
+    }
+
+    /// How much this file system's metadata caches currently hold
+    pub fn cache_stats(&self) -> MetadataCacheStats {
+        let (pinned_listing_entries, pinned_listing_bytes) = self.inner.pinned_listings.usage();
+        MetadataCacheStats {
+            inodes: self.inner.inodes.read().unwrap().map.len(),
+            negative_cache_entries: self.inner.negative_cache.len(),
+            negative_cache_bytes: self.inner.negative_cache.bytes(),
+            pinned_listing_entries,
+            pinned_listing_bytes,
+        }
+    }
+
+    /// Load listings of the directories under the mount point from a previously exported listing of
+    /// their objects (see [S3Filesystem::export_listing](crate::fs::S3Filesystem::export_listing)).
+    /// The first `readdir` of each directory in the listing, and the first lookup of each entry, are
+    /// served from it rather than S3. Objects outside the mount point are ignored.
+    pub fn bootstrap_listings(&self, objects: impl IntoIterator<Item = ObjectInfo>) {
+        let root = self.inner.get(ROOT_INODE_NO).expect("root inode always exists");
+        let directories = self.inner.bootstrap_listings.load(root.full_key(), objects);
+        debug!(directories, "loaded bootstrap listings");
+    }
+
+    /// Start watching a directory for remote changes. Watched directories are polled by the
+    /// [DirectoryPoller] until the kernel forgets them. Changes are found by comparing each poll
+    /// with the previous listing, so the first complete listing (by `readdir` or a poll) is the
+    /// baseline and isn't reported as a change.
+    pub fn watch_directory(&self, dir_ino: InodeNo) -> Result<(), InodeError> {
+        let dir = self.inner.get(dir_ino)?;
+        if dir.kind() != InodeKind::Directory {
+            return Err(InodeError::NotADirectory(dir.err()));
+        }
+
+        let mut watched_directories = self.inner.watched_directories.lock().unwrap();
+        if !watched_directories.contains_key(&dir_ino) {
+            trace!(dir=?dir_ino, "watching directory for remote changes");
+            watched_directories.insert(dir_ino, None);
+        }
+        Ok(())
+    }
+
+    /// Start background threads that poll the watched directories for remote changes every
+    /// `poll_interval`, and sweep expired entries out of the metadata caches every
+    /// `sweep_interval`, if they're set. Both stop when the returned [DirectoryPoller] is dropped.
+    ///
+    /// When a poll finds a directory changed, `on_change` is called with the directory and the
+    /// names of its children whose cached metadata was expired.
+    pub fn start_directory_poller<OC>(
+        &self,
+        client: Arc<OC>,
+        poll_interval: Option<Duration>,
+        sweep_interval: Option<Duration>,
+        on_change: impl Fn(InodeNo, &[String]) + Send + 'static,
+    ) -> DirectoryPoller
+    where
+        OC: ObjectClient + Send + Sync + 'static,
+    {
+        DirectoryPoller::new(self.inner.clone(), client, poll_interval, sweep_interval, on_change)
+    }
+
     /// Lookup an inode in the parent directory with the given name and
     /// increments its lookup count.
     pub async fn lookup<OC: ObjectClient>(
@@ -208,42 +442,201 @@ impl Superblock {
         name: &OsStr,
     ) -> Result<LookedUp, InodeError> {
         trace!(parent=?parent_ino, ?name, "lookup");
-        let lookup = self
-            .inner
-            .lookup_by_name(
-                client,
-                parent_ino,
-                name,
-                self.inner.config.cache_config.serve_lookup_from_cache,
-            )
-            .await?;
+        validate_inode_name(name)?;
+        let serve_from_cache = name
+            .to_str()
+            .is_some_and(|name| self.inner.serve_lookup_from_cache(parent_ino, name));
+        let cached = serve_from_cache
+            .then(|| self.inner.confirmed_by_read_lookup(parent_ino, name))
+            .flatten()
+            .or_else(|| self.inner.pinned_lookup(parent_ino, name));
+        let lookup = match cached {
+            Some(lookup) => lookup,
+            None => {
+                self.inner
+                    .lookup_by_name(client, parent_ino, name, LookupSource::Cache)
+                    .await?
+            }
+        };
+        if self.inner.is_unpublished(&lookup.inode) {
+            let parent = self.inner.get(parent_ino)?;
+            return Err(InodeError::FileDoesNotExist(
+                name.to_string_lossy().into_owned(),
+                parent.err(),
+            ));
+        }
         self.inner.remember(&lookup.inode);
         Ok(lookup)
     }
 
+    /// The inode with the given number, if the kernel (or anything else) still remembers it
+    pub fn inode(&self, ino: InodeNo) -> Result<Inode, InodeError> {
+        self.inner.get(ino)
+    }
+
+    /// Look up a name from whatever its parent directory last learned about it, however long ago
+    /// that was, without asking S3. For when S3 can't be asked. Returns
+    /// [InodeError::FileDoesNotExist] if the name was last known not to exist, or `None` if nothing
+    /// is known about it.
+    pub fn stale_lookup(&self, parent_ino: InodeNo, name: &OsStr) -> Option<Result<LookedUp, InodeError>> {
+        let name = name.to_str()?;
+        let parent = self.inner.get(parent_ino).ok()?;
+        let inode = {
+            let parent_state = parent.get_inode_state().ok()?;
+            let InodeKindData::Directory { children, .. } = &parent_state.kind_data else {
+                return None;
+            };
+            children.get(name).cloned()
+        };
+        let Some(inode) = inode else {
+            return self
+                .inner
+                .negative_cache
+                .remembers(parent_ino, name)
+                .then(|| Err(InodeError::FileDoesNotExist(name.to_owned(), parent.err())));
+        };
+        let stat = inode.get_inode_state().ok()?.stat.clone();
+        self.inner.remember(&inode);
+        Some(Ok(LookedUp { inode, stat }))
+    }
+
+    /// How long the kernel may remember that `name` doesn't exist in the directory `parent_ino`,
+    /// which is the negative cache TTL for the name's key
+    pub fn negative_entry_ttl(&self, parent_ino: InodeNo, name: &OsStr) -> Result<Duration, InodeError> {
+        let parent = self.inner.get(parent_ino)?;
+        let key = format!("{}{}", parent.full_key(), name.to_string_lossy());
+        Ok(self.inner.cache_settings(&key).negative_cache_ttl)
+    }
+
+    /// Retrieve the attributes for an inode as they were last known, even if they've expired,
+    /// without asking S3. For when S3 can't be asked.
+    pub fn stale_getattr(&self, ino: InodeNo) -> Result<LookedUp, InodeError> {
+        let inode = self.inner.get(ino)?;
+        let stat = inode.get_inode_state()?.stat.clone();
+        Ok(LookedUp { inode, stat })
+    }
+
+    /// Whether lookups and opens of the given inode may be served from cached metadata, according
+    /// to the cache settings for its key
+    pub fn serve_lookup_from_cache(&self, ino: InodeNo) -> bool {
+        match self.inner.get(ino) {
+            Ok(inode) if inode.kind() == InodeKind::File && self.inner.is_immutable(inode.full_key()) => true,
+            Ok(inode) => self.inner.cache_settings(inode.full_key()).serve_lookup_from_cache,
+            Err(_) => self.inner.config.cache_config.serve_lookup_from_cache,
+        }
+    }
+
+    /// Expire the stat of the given inode, so the next `getattr` or `lookup` of it asks S3 rather
+    /// than serving cached metadata. Used when the object behind it turns out to have changed, for
+    /// example by being replaced with a directory of the same name.
+    pub fn expire(&self, inode: &Inode) {
+        if inode.kind() == InodeKind::File && self.inner.is_immutable(inode.full_key()) {
+            self.inner.revoke_immutable(inode.full_key());
+        }
+        let Ok(mut state) = inode.get_mut_inode_state() else {
+            return;
+        };
+        if state.write_status == WriteStatus::Remote {
+            state.stat.update_validity(Duration::ZERO, self.inner.now());
+        }
+        drop(state);
+        self.inner.key_failures.remove(inode.full_key());
+        self.inner.record_change();
+    }
+
+    /// Record that a read of the given file succeeded. The read was conditional on the object's
+    /// ETag, so it confirms the file's attributes just as a lookup would, and a `lookup` shortly
+    /// after can reuse them instead of asking S3 again. A stat already confirmed by an earlier read
+    /// is left alone until it expires, so most reads only need the inode's read lock.
+    pub fn confirm_read(&self, inode: &Inode, etag: &str) {
+        let needs_update = |state: &InodeState| {
+            // The stat may have been refreshed with a different object since this read started
+            state.write_status == WriteStatus::Remote
+                && state.stat.etag.as_deref() == Some(etag)
+                && !(state.stat.confirmed_by_read && state.stat.is_valid(self.inner.now()))
+        };
+        if !inode.get_inode_state().is_ok_and(|state| needs_update(&state)) {
+            return;
+        }
+        let Ok(mut state) = inode.get_mut_inode_state() else {
+            return;
+        };
+        if needs_update(&state) {
+            let validity = if self.inner.is_immutable(inode.full_key()) {
+                NEVER_EXPIRE_TTL
+            } else {
+                self.inner.ttl_for(inode.full_key(), InodeKind::File)
+            };
+            state.stat.update_validity(validity, self.inner.now());
+            state.stat.confirmed_by_read = true;
+        }
+    }
+
+    /// Record that the object behind the given file turned out to be `size` bytes when it was
+    /// read, as long as the file's stat is still for the same object.
+    pub fn update_size(&self, inode: &Inode, etag: &str, size: u64) {
+        let Ok(mut state) = inode.get_mut_inode_state() else {
+            return;
+        };
+        if state.write_status == WriteStatus::Remote && state.stat.etag.as_deref() == Some(etag) {
+            state.stat.size = size as usize;
+        }
+    }
+
     /// Retrieve the attributes for an inode
     pub async fn getattr<OC: ObjectClient>(
         &self,
         client: &OC,
         ino: InodeNo,
         force_revalidate: bool,
+    ) -> Result<LookedUp, InodeError> {
+        self.getattr_from(client, ino, force_revalidate, LookupSource::Remote)
+            .await
+    }
+
+    /// Retrieve the attributes for an inode from a new request to S3, rather than from the cache
+    /// or from a lookup that was already in flight or completed very recently. For callers that
+    /// need to see any change made to the object before the call.
+    pub async fn getattr_fresh<OC: ObjectClient>(&self, client: &OC, ino: InodeNo) -> Result<LookedUp, InodeError> {
+        self.getattr_from(client, ino, true, LookupSource::FreshRemote).await
+    }
+
+    async fn getattr_from<OC: ObjectClient>(
+        &self,
+        client: &OC,
+        ino: InodeNo,
+        force_revalidate: bool,
+        source: LookupSource,
     ) -> Result<LookedUp, InodeError> {
         let inode = self.inner.get(ino)?;
         logging::record_name(inode.name());
 
         if !force_revalidate {
             let sync = inode.get_inode_state()?;
-            if sync.stat.is_valid() {
+            // A file being written shadows any remote object, and its size so far is only known
+            // locally, so there's no point asking S3 about it. Its stat is only valid for
+            // [CacheConfig::recently_written_ttl], so the kernel comes back here to see it change.
+            if sync.stat.is_valid(self.inner.now()) || sync.write_status == WriteStatus::LocalOpen {
                 let stat = sync.stat.clone();
                 drop(sync);
                 return Ok(LookedUp { inode, stat });
             }
         }
 
-        let lookup = self
-            .inner
-            .lookup_by_name(client, inode.parent(), inode.name().as_ref(), false)
-            .await?;
+        // Remote directories only need to be found to still exist, which doesn't take a full lookup
+        let revalidated = if inode.kind() == InodeKind::Directory && ino != ROOT_INODE_NO && inode.is_remote()? {
+            self.inner.revalidate_directory(client, &inode, source).await?
+        } else {
+            None
+        };
+        let lookup = match revalidated {
+            Some(lookup) => lookup,
+            None => {
+                self.inner
+                    .lookup_by_name(client, inode.parent(), inode.name().as_ref(), source)
+                    .await?
+            }
+        };
         if lookup.inode.ino() != ino {
             Err(InodeError::StaleInode {
                 remote_key: lookup.inode.full_key().to_owned(),
@@ -271,13 +664,10 @@ impl Superblock {
             return Err(InodeError::SetAttrNotPermittedOnRemoteInode(inode.err()));
         }
 
-        let validity = match inode.kind() {
-            InodeKind::File => self.inner.config.cache_config.file_ttl,
-            InodeKind::Directory => self.inner.config.cache_config.dir_ttl,
-        };
+        let validity = self.inner.local_ttl_for(inode.full_key(), inode.kind());
 
         // Resetting the InodeStat expiry because the new InodeStat should have new validity
-        sync.stat.update_validity(validity);
+        sync.stat.update_validity(validity, self.inner.now());
 
         if let Some(t) = atime {
             sync.stat.atime = t;
@@ -310,13 +700,46 @@ impl Superblock {
     ///
     /// Doesn't currently do any IO, so doesn't need to be async, but reserving it for future use.
     pub async fn readdir<OC: ObjectClient>(
+        &self,
+        client: &OC,
+        dir_ino: InodeNo,
+        page_size: usize,
+    ) -> Result<ReaddirHandle, InodeError> {
+        self.readdir_with_options(client, dir_ino, page_size, false).await
+    }
+
+    /// Start a readdir stream for the given directory inode. If `dirs_only` is set, the stream
+    /// skips files and only returns subdirectories.
+    pub async fn readdir_with_options<OC: ObjectClient>(
         &self,
         _client: &OC,
         dir_ino: InodeNo,
         page_size: usize,
+        dirs_only: bool,
     ) -> Result<ReaddirHandle, InodeError> {
         trace!(dir=?dir_ino, "readdir");
+        self.start_readdir(dir_ino, page_size, dirs_only, None)
+    }
 
+    /// Start a readdir stream for the given directory inode that resumes after the entry `name`,
+    /// returning only entries with later names. The entry itself doesn't need to exist any more.
+    pub async fn readdir_after(
+        &self,
+        dir_ino: InodeNo,
+        page_size: usize,
+        name: &str,
+    ) -> Result<ReaddirHandle, InodeError> {
+        trace!(dir=?dir_ino, name, "readdir after");
+        self.start_readdir(dir_ino, page_size, false, Some(name.to_owned()))
+    }
+
+    fn start_readdir(
+        &self,
+        dir_ino: InodeNo,
+        page_size: usize,
+        dirs_only: bool,
+        start_after: Option<String>,
+    ) -> Result<ReaddirHandle, InodeError> {
         let dir = self.inner.get(dir_ino)?;
         logging::record_name(dir.name());
         if dir.kind() != InodeKind::Directory {
@@ -327,7 +750,15 @@ impl Superblock {
         let dir_key = dir.full_key();
         assert!(dir_key.is_empty() || dir_key.ends_with('/'));
 
-        ReaddirHandle::new(self.inner.clone(), dir_ino, parent_ino, dir_key.to_string(), page_size)
+        ReaddirHandle::new(
+            self.inner.clone(),
+            dir_ino,
+            parent_ino,
+            dir_key.to_string(),
+            page_size,
+            dirs_only,
+            start_after,
+        )
     }
 
     /// Create a new regular file or directory inode ready to be opened in write-only mode
@@ -340,15 +771,7 @@ impl Superblock {
     ) -> Result<LookedUp, InodeError> {
         trace!(parent=?dir, ?name, "create");
 
-        let existing = self
-            .inner
-            .lookup_by_name(
-                client,
-                dir,
-                name,
-                self.inner.config.cache_config.serve_lookup_from_cache,
-            )
-            .await;
+        let existing = self.inner.lookup_by_name(client, dir, name, LookupSource::Cache).await;
         match existing {
             Ok(lookup) => return Err(InodeError::FileAlreadyExists(lookup.inode.err())),
             Err(InodeError::FileDoesNotExist(_, _)) => (),
@@ -356,9 +779,7 @@ impl Superblock {
         }
 
         // Should be impossible to fail since [lookup] does this check, but let's be sure
-        let name = name
-            .to_str()
-            .ok_or_else(|| InodeError::InvalidFileName(name.to_owned()))?;
+        let name = validate_inode_name(name)?;
 
         // Put inode creation in a block so we don't hold the lock on the parent state longer than needed.
         let lookup = {
@@ -375,6 +796,9 @@ impl Superblock {
                 return Err(InodeError::FileAlreadyExists(inode.err()));
             }
 
+            let validity = self
+                .inner
+                .local_ttl_for(&format!("{}{}", parent_inode.full_key(), name), kind);
             let stat = match kind {
                 // Objects don't have an ETag until they are uploaded to S3
                 InodeKind::File => InodeStat::for_file(
@@ -383,11 +807,10 @@ impl Superblock {
                     None,
                     None,
                     None,
-                    self.inner.config.cache_config.file_ttl,
+                    validity,
+                    self.inner.now(),
                 ),
-                InodeKind::Directory => {
-                    InodeStat::for_directory(self.inner.mount_time, self.inner.config.cache_config.dir_ttl)
-                }
+                InodeKind::Directory => InodeStat::for_directory(self.inner.mount_time, validity, self.inner.now()),
             };
 
             let state = InodeState {
@@ -417,12 +840,7 @@ impl Superblock {
     ) -> Result<(), InodeError> {
         let LookedUp { inode, .. } = self
             .inner
-            .lookup_by_name(
-                client,
-                parent_ino,
-                name,
-                self.inner.config.cache_config.serve_lookup_from_cache,
-            )
+            .lookup_by_name(client, parent_ino, name, LookupSource::Cache)
             .await?;
 
         if inode.kind() == InodeKind::File {
@@ -488,15 +906,47 @@ impl Superblock {
         parent_ino: InodeNo,
         name: &OsStr,
     ) -> Result<(), InodeError> {
+        let (parent, inode) = self.unlinkable(client, parent_ino, name).await?;
+
+        let (bucket, s3_key) = (self.inner.bucket.as_str(), inode.full_key());
+        debug!(parent=?parent_ino, ?name, "unlink on remote file will delete key {}", s3_key);
+        if let Err(e) = client.delete_object(bucket, s3_key).await {
+            error!(
+                inode=%inode.err(),
+                error=?e,
+                "DeleteObject failed for unlink",
+            );
+            return Err(InodeError::ClientError(anyhow!(e).context("DeleteObject failed")));
+        }
+
+        self.remove_unlinked(&parent, &inode)
+    }
+
+    /// Unlink the entry described by `parent_ino` and `name` like [unlink](Self::unlink), but
+    /// without deleting its object from S3, which is left to the caller. Returns the object's key.
+    pub async fn unlink_deferred<OC: ObjectClient>(
+        &self,
+        client: &OC,
+        parent_ino: InodeNo,
+        name: &OsStr,
+    ) -> Result<String, InodeError> {
+        let (parent, inode) = self.unlinkable(client, parent_ino, name).await?;
+        debug!(parent=?parent_ino, ?name, "unlink on remote file will delete key {} later", inode.full_key());
+        self.remove_unlinked(&parent, &inode)?;
+        Ok(inode.full_key().to_owned())
+    }
+
+    /// Look up the entry to unlink, and check it's a remote file. Returns it with its parent.
+    async fn unlinkable<OC: ObjectClient>(
+        &self,
+        client: &OC,
+        parent_ino: InodeNo,
+        name: &OsStr,
+    ) -> Result<(Inode, Inode), InodeError> {
         let parent = self.inner.get(parent_ino)?;
         let LookedUp { inode, .. } = self
             .inner
-            .lookup_by_name(
-                client,
-                parent_ino,
-                name,
-                self.inner.config.cache_config.serve_lookup_from_cache,
-            )
+            .lookup_by_name(client, parent_ino, name, LookupSource::Cache)
             .await?;
 
         if inode.kind() == InodeKind::Directory {
@@ -516,26 +966,17 @@ impl Superblock {
                     ?name,
                     "unlink on local file not allowed until write is complete",
                 );
-                return Err(InodeError::UnlinkNotPermittedWhileWriting(inode.err()));
-            }
-            WriteStatus::Remote => {
-                let (bucket, s3_key) = (self.inner.bucket.as_str(), inode.full_key());
-                debug!(parent=?parent_ino, ?name, "unlink on remote file will delete key {}", s3_key);
-                let delete_obj_result = client.delete_object(bucket, s3_key).await;
-
-                match delete_obj_result {
-                    Ok(_res) => (),
-                    Err(e) => {
-                        error!(
-                            inode=%inode.err(),
-                            error=?e,
-                            "DeleteObject failed for unlink",
-                        );
-                        Err(InodeError::ClientError(anyhow!(e).context("DeleteObject failed")))?;
-                    }
-                };
+                Err(InodeError::UnlinkNotPermittedWhileWriting(inode.err()))
             }
+            WriteStatus::Remote => Ok((parent, inode)),
         }
+    }
+
+    /// Remove an unlinked file from its parent, once its object is deleted (or will be)
+    fn remove_unlinked(&self, parent: &Inode, inode: &Inode) -> Result<(), InodeError> {
+        self.inner.pinned_listings.remove(parent.full_key());
+        self.inner.bootstrap_listings.remove(parent.full_key());
+        self.inner.record_change();
 
         let mut parent_state = parent.get_mut_inode_state()?;
         match &mut parent_state.kind_data {
@@ -561,9 +1002,482 @@ impl Superblock {
 
         Ok(())
     }
+
+    /// Forget the cached contents of a directory whose objects were all deleted without going
+    /// through [unlink](Self::unlink), such as by a batched delete of everything under its prefix.
+    /// Remote entries are removed from it and every directory below it, so they are looked up
+    /// again, while local files and directories that weren't deleted stay in place.
+    pub fn remove_subtree(&self, ino: InodeNo) -> Result<(), InodeError> {
+        let dir = self.inner.get(ino)?;
+        if dir.kind() != InodeKind::Directory {
+            return Err(InodeError::NotADirectory(dir.err()));
+        }
+
+        // Find every directory below first, so each can be emptied before the directory holding it.
+        // A directory only goes once nothing local is left in it.
+        let mut dirs = vec![dir.clone()];
+        let mut next = 0;
+        while let Some(dir) = dirs.get(next) {
+            let subdirs: Vec<Inode> = match &dir.get_inode_state()?.kind_data {
+                InodeKindData::Directory { children, .. } => children
+                    .values()
+                    .filter(|child| child.kind() == InodeKind::Directory)
+                    .cloned()
+                    .collect(),
+                InodeKindData::File {} => Vec::new(),
+            };
+            dirs.extend(subdirs);
+            next += 1;
+        }
+
+        for dir in dirs.iter().rev() {
+            {
+                let mut dir_state = dir.get_mut_inode_state()?;
+                let InodeKindData::Directory { children, .. } = &mut dir_state.kind_data else {
+                    continue;
+                };
+                children.retain(|_, child| {
+                    let child_state = child.inner.sync.read().unwrap();
+                    match &child_state.kind_data {
+                        InodeKindData::Directory { children, .. } if !children.is_empty() => true,
+                        _ => child_state.write_status != WriteStatus::Remote,
+                    }
+                });
+            }
+            self.inner.record_directory_change(dir)?;
+        }
+
+        // The directory itself may be gone too, unless it was created locally
+        let parent = self.inner.get(dir.parent())?;
+        self.inner.record_directory_change(&parent)?;
+        Ok(())
+    }
+
+    /// Check that renaming `name` in `parent_ino` onto `newname` in `newparent_ino` would be
+    /// allowed by the types of the source and target, without changing anything. A file can't
+    /// replace a directory or vice versa, and a directory can only replace an empty directory.
+    /// There's nothing to check if the target doesn't exist.
+    pub async fn check_rename<OC: ObjectClient>(
+        &self,
+        client: &OC,
+        parent_ino: InodeNo,
+        name: &OsStr,
+        newparent_ino: InodeNo,
+        newname: &OsStr,
+    ) -> Result<(), InodeError> {
+        let LookedUp { inode: source, .. } = self
+            .inner
+            .lookup_by_name(client, parent_ino, name, LookupSource::Cache)
+            .await?;
+        let target = match self
+            .inner
+            .lookup_by_name(client, newparent_ino, newname, LookupSource::Cache)
+            .await
+        {
+            Ok(LookedUp { inode, .. }) => inode,
+            Err(InodeError::FileDoesNotExist(_, _)) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        match (source.kind(), target.kind()) {
+            (InodeKind::File, InodeKind::File) => Ok(()),
+            (InodeKind::File, InodeKind::Directory) => Err(InodeError::IsDirectory(target.err())),
+            (InodeKind::Directory, InodeKind::File) => Err(InodeError::NotADirectory(target.err())),
+            (InodeKind::Directory, InodeKind::Directory) => {
+                if source.ino() == target.ino() {
+                    return Ok(());
+                }
+                let entries = self.readdir(client, target.ino(), 1).await?;
+                if entries.next(client).await?.is_some() {
+                    return Err(InodeError::DirectoryNotEmpty(target.err()));
+                }
+                Ok(())
+            }
+        }
+    }
 }
 
 impl SuperblockInner {
+    /// Remove expired entries from the negative cache, and remote lookups that can no longer be
+    /// reused, rather than waiting for later inserts to push them out
+    fn sweep_caches(&self) {
+        let swept = self.negative_cache.sweep();
+        let changes = self.local_changes.load(Ordering::Acquire);
+        let now = self.now();
+        self.pending_lookups
+            .lock()
+            .unwrap()
+            .retain(|_, pending| pending.reusable(changes, now));
+        trace!(swept, "swept expired cache entries");
+        metrics::counter!("metadata_cache.negative_cache.entries_swept").increment(swept as u64);
+    }
+
+    /// List every watched directory once, and record a change for any whose contents differ from
+    /// its previous listing, reporting it to `on_change`.
+    async fn poll_watched_directories<OC: ObjectClient>(&self, client: &OC, on_change: &dyn Fn(InodeNo, &[String])) {
+        let watched = self
+            .watched_directories
+            .lock()
+            .unwrap()
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+        for dir_ino in watched {
+            match self.poll_directory(client, dir_ino).await {
+                Ok(Some(expired)) => on_change(dir_ino, &expired),
+                Ok(None) => {}
+                Err(InodeError::MetadataUnavailable(_)) => {
+                    debug!(dir=?dir_ino, "metadata requests are failing, not polling directory")
+                }
+                Err(e) => warn!(dir=?dir_ino, error=?e, "failed to poll directory for changes"),
+            }
+        }
+    }
+
+    /// Poll one watched directory, returning the names of the children expired by a change, if it
+    /// changed
+    async fn poll_directory<OC: ObjectClient>(
+        &self,
+        client: &OC,
+        dir_ino: InodeNo,
+    ) -> Result<Option<Vec<String>>, InodeError> {
+        let dir = self.get(dir_ino)?;
+        let listing = self.list_directory(client, dir.full_key()).await?;
+        let fingerprint = listing.fingerprint;
+        let changed = match self.watched_directories.lock().unwrap().get_mut(&dir_ino) {
+            // The directory was forgotten while we were listing it
+            None => return Ok(None),
+            // Otherwise this is the first listing, which is only the baseline
+            Some(previous) => previous
+                .replace(fingerprint)
+                .is_some_and(|previous| previous != fingerprint),
+        };
+        let expired = if changed {
+            debug!(dir=?dir_ino, "watched directory changed remotely");
+            metrics::counter!("metadata_cache.directory_poll.changes").increment(1);
+            Some(self.record_directory_change(&dir)?)
+        } else {
+            None
+        };
+        self.record_listing(&dir, listing);
+        Ok(expired)
+    }
+
+    /// List the remote contents of the directory with the given key
+    async fn list_directory<OC: ObjectClient>(
+        &self,
+        client: &OC,
+        dir_key: &str,
+    ) -> Result<ListingSnapshot, InodeError> {
+        let mut listing = ListingSnapshot::new(self.now());
+        let mut continuation_token = None;
+        loop {
+            let permit = self.admit_remote()?;
+            let result = client
+                .list_objects(&self.bucket, continuation_token.as_deref(), "/", 1000, dir_key)
+                .await
+                .map_err(|e| InodeError::ClientError(anyhow!(e).context("ListObjectsV2 failed")));
+            permit.complete(&result);
+            let result = result?;
+            listing.add_page(dir_key, &result);
+            continuation_token = result.next_continuation_token;
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+        Ok(listing)
+    }
+
+    /// Make a remote change to a directory visible: bump its modification time, and expire the
+    /// cached metadata of its children so that they are looked up again. Returns the names of the
+    /// children that were expired.
+    fn record_directory_change(&self, dir: &Inode) -> Result<Vec<String>, InodeError> {
+        let now = OffsetDateTime::now_utc();
+        let mut expired = Vec::new();
+        {
+            let mut dir_state = dir.get_mut_inode_state()?;
+            dir_state.stat.mtime = now;
+            dir_state.stat.ctime = now;
+            let InodeKindData::Directory { children, listing, .. } = &mut dir_state.kind_data else {
+                return Err(InodeError::NotADirectory(dir.err()));
+            };
+            *listing = None;
+            for child in children.values() {
+                // Objects that never change can't have been changed by whatever changed the listing
+                if child.kind() == InodeKind::File && self.is_immutable(child.full_key()) {
+                    continue;
+                }
+                let mut child_state = child.inner.sync.write().unwrap();
+                if child_state.write_status == WriteStatus::Remote {
+                    child_state.stat.update_validity(Duration::ZERO, self.now());
+                    expired.push(child.name().to_owned());
+                }
+            }
+        }
+        self.negative_cache.remove_parent(dir.ino());
+        self.key_failures.remove_prefix(dir.full_key());
+        self.pinned_listings.remove(dir.full_key());
+        self.bootstrap_listings.remove(dir.full_key());
+        self.record_change();
+        Ok(expired)
+    }
+
+    /// Keep the snapshot of a complete listing of a directory from S3, replacing any older one. If
+    /// the directory is watched but hasn't been listed yet, the listing is the baseline its polls
+    /// are compared with.
+    fn record_listing(&self, dir: &Inode, snapshot: ListingSnapshot) {
+        if let Some(previous @ None) = self.watched_directories.lock().unwrap().get_mut(&dir.ino()) {
+            *previous = Some(snapshot.fingerprint);
+        }
+        let Ok(mut dir_state) = dir.get_mut_inode_state() else {
+            return;
+        };
+        if let InodeKindData::Directory { listing, .. } = &mut dir_state.kind_data {
+            trace!(dir=?dir.ino(), subdirectories = snapshot.subdirectories.len(), "recording directory listing");
+            *listing = Some(snapshot);
+        }
+    }
+
+    /// Whether the directory with the given inode number is watched for remote changes
+    fn is_watched(&self, ino: InodeNo) -> bool {
+        self.watched_directories.lock().unwrap().contains_key(&ino)
+    }
+
+    /// Record a change that recent remote lookups might not reflect, so they aren't reused
+    fn record_change(&self) {
+        self.local_changes.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Whether the given key is under one of [CacheConfig::pinned_prefixes], and so its metadata
+    /// never expires. Prefixes match whole path components, so `data` pins `data/` but not
+    /// `database/`.
+    fn is_pinned(&self, key: &str) -> bool {
+        self.config.cache_config.pinned_prefixes.iter().any(|prefix| {
+            let prefix = prefix.trim_end_matches('/');
+            prefix.is_empty()
+                || key
+                    .strip_prefix(prefix)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+
+    /// Whether the given file key is under one of [SuperblockConfig::immutable_prefixes], and hasn't
+    /// been seen to change, so its metadata never expires
+    fn is_immutable(&self, key: &str) -> bool {
+        let matches =
+            self.config.immutable_prefixes.iter().any(|prefix| {
+                key.starts_with(prefix.as_str()) || prefix.strip_suffix('/').is_some_and(|file| key == file)
+            });
+        matches && !self.changed_immutable_keys.lock().unwrap().contains(key)
+    }
+
+    /// Stop treating the given key as immutable, because its object turned out to have changed
+    fn revoke_immutable(&self, key: &str) {
+        if self.changed_immutable_keys.lock().unwrap().insert(key.to_owned()) {
+            error!(
+                ?key,
+                "object matching an immutable key pattern has changed; its metadata will be revalidated from now on"
+            );
+            metrics::counter!("metadata_cache.immutable_changed").increment(1);
+        }
+    }
+
+    /// The cache settings for the given key: the global [CacheConfig], with any overrides from the
+    /// longest matching prefix in [SuperblockConfig::path_rules] applied
+    fn cache_settings(&self, key: &str) -> CacheSettings {
+        let cache_config = &self.config.cache_config;
+        let overrides = self.config.path_rules.resolve(key).copied().unwrap_or_default();
+        let file_ttl = overrides.file_ttl.unwrap_or(cache_config.file_ttl);
+        CacheSettings {
+            serve_lookup_from_cache: overrides
+                .serve_lookup_from_cache
+                .unwrap_or(cache_config.serve_lookup_from_cache),
+            file_ttl,
+            dir_ttl: overrides.dir_ttl.unwrap_or(cache_config.dir_ttl),
+            negative_cache_ttl: overrides.negative_cache_ttl.unwrap_or(file_ttl),
+        }
+    }
+
+    /// The current time, as cached metadata expires by
+    fn now(&self) -> Instant {
+        self.config.clock.now()
+    }
+
+    /// Ask the [metadata breaker](SuperblockConfig::metadata_breaker) to send a metadata request
+    /// to S3. The request's outcome must be reported to the returned permit.
+    fn admit_remote(&self) -> Result<Permit<'_>, InodeError> {
+        self.config
+            .metadata_breaker
+            .admit()
+            .map_err(|_| InodeError::MetadataUnavailable(CircuitBreakerOpen))
+    }
+
+    /// How long metadata of the given kind should be cached for the given key
+    fn ttl_for(&self, key: &str, kind: InodeKind) -> Duration {
+        let settings = self.cache_settings(key);
+        match kind {
+            InodeKind::File => settings.file_ttl,
+            InodeKind::Directory => settings.dir_ttl,
+        }
+    }
+
+    /// How long metadata should be cached for a local inode with the given key, which hasn't been
+    /// uploaded yet. Files are still being written, so get at most
+    /// [CacheConfig::recently_written_ttl].
+    fn local_ttl_for(&self, key: &str, kind: InodeKind) -> Duration {
+        match kind {
+            InodeKind::File => self.recently_written_ttl(key),
+            InodeKind::Directory => self.ttl_for(key, kind),
+        }
+    }
+
+    /// The TTL for a file with the given key that's open for writing or was recently modified:
+    /// [CacheConfig::recently_written_ttl], if that's shorter than its usual TTL
+    fn recently_written_ttl(&self, key: &str) -> Duration {
+        self.ttl_for(key, InodeKind::File)
+            .min(self.config.cache_config.recently_written_ttl)
+    }
+
+    /// Whether an object last modified at `mtime` was modified within
+    /// [CacheConfig::recently_modified_window]
+    fn recently_modified(&self, mtime: OffsetDateTime) -> bool {
+        let window = self.config.cache_config.recently_modified_window;
+        !window.is_zero() && OffsetDateTime::now_utc() - mtime < window
+    }
+
+    /// The TTL for metadata of the given kind with the given key, if a rule in
+    /// [SuperblockConfig::path_rules] overrides the global one
+    fn path_ttl(&self, key: &str, kind: InodeKind) -> Option<Duration> {
+        let overrides = self.config.path_rules.resolve(key)?;
+        match kind {
+            InodeKind::File => overrides.file_ttl,
+            InodeKind::Directory => overrides.dir_ttl,
+        }
+    }
+
+    /// Whether lookups of the entry `name` in `parent_ino` may be served from the cache
+    fn serve_lookup_from_cache(&self, parent_ino: InodeNo, name: &str) -> bool {
+        if self.config.path_rules.is_empty() {
+            return self.config.cache_config.serve_lookup_from_cache;
+        }
+        match self.get(parent_ino) {
+            Ok(parent) => {
+                self.cache_settings(&format!("{}{}", parent.full_key(), name))
+                    .serve_lookup_from_cache
+            }
+            Err(_) => self.config.cache_config.serve_lookup_from_cache,
+        }
+    }
+
+    /// Whether the entry `name` in `parent_ino` is the [SuperblockConfig::hidden_prefix] directory
+    fn is_hidden(&self, parent_ino: InodeNo, name: &str) -> bool {
+        let Some(hidden_prefix) = &self.config.hidden_prefix else {
+            return false;
+        };
+        let Ok(parent) = self.get(parent_ino) else {
+            return false;
+        };
+        hidden_prefix
+            .strip_prefix(parent.full_key())
+            .and_then(|suffix| suffix.strip_suffix('/'))
+            == Some(name)
+    }
+
+    /// Whether the inode is a new file that's hidden until its upload completes, see
+    /// [SuperblockConfig::hide_unpublished_files]
+    fn is_unpublished(&self, inode: &Inode) -> bool {
+        self.config.hide_unpublished_files && inode.kind() == InodeKind::File && !inode.is_remote().unwrap_or(true)
+    }
+
+    /// Stand in for a missing object with an empty file if its path matches
+    /// [SuperblockConfig::soft_missing_paths]. Reading the file never reaches S3, since it's empty.
+    fn soft_missing_lookup(&self, parent_ino: InodeNo, name: &str) -> Option<RemoteLookup> {
+        let soft_missing_paths = self.config.soft_missing_paths.as_ref()?;
+        let parent = self.get(parent_ino).ok()?;
+        let root = self.get(ROOT_INODE_NO).ok()?;
+        let dir = parent
+            .full_key()
+            .strip_prefix(root.full_key())
+            .unwrap_or(parent.full_key());
+        let path = format!("{dir}{name}");
+        if !soft_missing_paths.is_match(&path) {
+            return None;
+        }
+        trace!(path, "presenting missing object as an empty file");
+        let stat = InodeStat::for_file(
+            0,
+            self.mount_time,
+            Some(SOFT_MISSING_ETAG.to_owned()),
+            None,
+            None,
+            self.config.cache_config.file_ttl,
+            self.now(),
+        );
+        Some(RemoteLookup {
+            kind: InodeKind::File,
+            stat,
+        })
+    }
+
+    /// Whether a directory with the given key is nested further below the mount point than
+    /// [SuperblockConfig::max_listing_depth] allows us to list.
+    fn is_beyond_listing_depth(&self, dir_key: &str) -> bool {
+        let Some(max_depth) = self.config.max_listing_depth else {
+            return false;
+        };
+        let Ok(root) = self.get(ROOT_INODE_NO) else {
+            return false;
+        };
+        let depth = dir_key
+            .strip_prefix(root.full_key())
+            .unwrap_or(dir_key)
+            .matches('/')
+            .count();
+        depth > max_depth
+    }
+
+    /// Called when a complete listing of a directory found no keys under its prefix, not even a
+    /// marker object. If the directory only existed remotely, it has been removed from the bucket,
+    /// so its stat expires one directory TTL after the listing, like it would have if the listing
+    /// had refreshed it. After that, the next `getattr` or `lookup` asks S3 and finds it's gone,
+    /// rather than the parent's older listing vouching for it again.
+    fn expire_if_removed(&self, dir_ino: InodeNo) {
+        if dir_ino == ROOT_INODE_NO {
+            return;
+        }
+        let Ok(inode) = self.get(dir_ino) else {
+            return;
+        };
+        let Ok(mut state) = inode.get_mut_inode_state() else {
+            return;
+        };
+        let InodeKindData::Directory { writing_children, .. } = &state.kind_data else {
+            return;
+        };
+        // A local directory, or one with local children, still exists even with no remote keys
+        if state.write_status != WriteStatus::Remote || !writing_children.is_empty() {
+            return;
+        }
+        trace!(ino = dir_ino, "directory listed empty, expiring its stat after its TTL");
+        let ttl = self.ttl_for(inode.full_key(), InodeKind::Directory);
+        state.stat.update_validity(ttl, self.now());
+        drop(state);
+
+        // An older listing of the parent that found the directory no longer vouches for it
+        let Ok(parent) = self.get(inode.parent()) else {
+            return;
+        };
+        let Ok(mut parent_state) = parent.get_mut_inode_state() else {
+            return;
+        };
+        if let InodeKindData::Directory {
+            listing: Some(listing), ..
+        } = &mut parent_state.kind_data
+        {
+            listing.subdirectories.remove(inode.name());
+        }
+    }
+
     /// Retrieve the inode for the given number if it exists.
     ///
     /// The expiry of its stat field is not checked.
@@ -596,24 +1510,26 @@ impl SuperblockInner {
     /// Updates the parent inode to be in sync with the client, but does
     /// not add new inodes to the superblock. The caller is responsible
     /// for calling [`remember()`] if that is required.
+    ///
+    /// If `allow_cache` is set, the lookup may be served from cached entries when the cache
+    /// settings for the entry's key allow it.
     pub async fn lookup_by_name<OC: ObjectClient>(
         &self,
         client: &OC,
         parent_ino: InodeNo,
         name: &OsStr,
-        allow_cache: bool,
+        source: LookupSource,
     ) -> Result<LookedUp, InodeError> {
-        let name = name
-            .to_str()
-            .ok_or_else(|| InodeError::InvalidFileName(name.to_owned()))?;
+        // Reject invalid names before we build an S3 key containing them. In particular, names
+        // containing '/' could be shadowed by directories, and NUL is invalid in POSIX names.
+        let name = validate_inode_name(name)?;
 
-        // This should be impossible, but just to be safe, explicitly reject lookups to files that
-        // end with '/', since they could be shadowed by directories.
-        if name.ends_with('/') {
-            return Err(InodeError::InvalidFileName(name.into()));
+        if self.is_hidden(parent_ino, name) {
+            let parent = self.get(parent_ino)?;
+            return Err(InodeError::FileDoesNotExist(name.to_owned(), parent.err()));
         }
 
-        let lookup = if allow_cache {
+        let lookup = if source == LookupSource::Cache && self.serve_lookup_from_cache(parent_ino, name) {
             self.cache_lookup(parent_ino, name)
         } else {
             None
@@ -622,7 +1538,14 @@ impl SuperblockInner {
         let lookup = match lookup {
             Some(lookup) => lookup?,
             None => {
-                let remote = self.remote_lookup(client, parent_ino, name).await?;
+                let remote = match self.bootstrap_lookup(parent_ino, name) {
+                    Some(remote) => Some(remote),
+                    None => {
+                        self.remote_lookup(client, parent_ino, name, source == LookupSource::FreshRemote)
+                            .await?
+                    }
+                };
+                let remote = remote.or_else(|| self.soft_missing_lookup(parent_ino, name));
                 self.update_from_remote(parent_ino, name, remote)?
             }
         };
@@ -646,7 +1569,7 @@ impl SuperblockInner {
                 InodeKindData::Directory { children, .. } => {
                     if let Some(inode) = children.get(name) {
                         let inode_stat = &inode.get_inode_state().ok()?.stat;
-                        if inode_stat.is_valid() {
+                        if inode_stat.is_valid(superblock.now()) {
                             let lookup = LookedUp {
                                 inode: inode.clone(),
                                 stat: inode_stat.clone(),
@@ -678,13 +1601,162 @@ impl SuperblockInner {
         lookup
     }
 
+    /// Lookup an entry in the parent directory's listing from [Superblock::bootstrap_listings], if
+    /// it has one and the entry hasn't been looked up before. Names missing from the listing are
+    /// still looked up remotely, since they may have been created after the listing was exported.
+    fn bootstrap_lookup(&self, parent_ino: InodeNo, name: &str) -> Option<RemoteLookup> {
+        let parent = self.get(parent_ino).ok()?;
+        let remote = self.bootstrap_listings.lookup(self, parent.full_key(), name)?;
+        trace!(parent=?parent_ino, ?name, "lookup returned from bootstrap listing");
+        metrics::counter!("metadata_cache.bootstrap_lookup_hit").increment(1);
+        Some(remote)
+    }
+
+    /// Lookup a file in the parent directory whose attributes were confirmed by a recent read (see
+    /// [Superblock::confirm_read]). Only used where `serve_lookup_from_cache` allows cached lookups:
+    /// the read refreshed the stat's validity, so it can outlive the one an earlier lookup cached.
+    fn confirmed_by_read_lookup(&self, parent_ino: InodeNo, name: &OsStr) -> Option<LookedUp> {
+        let name = name.to_str()?;
+        let parent = self.get(parent_ino).ok()?;
+        let parent_state = parent.get_inode_state().ok()?;
+        let InodeKindData::Directory { children, .. } = &parent_state.kind_data else {
+            return None;
+        };
+        let inode = children.get(name)?;
+        let state = inode.get_inode_state().ok()?;
+        if state.write_status != WriteStatus::Remote
+            || !state.stat.confirmed_by_read
+            || !state.stat.is_valid(self.now())
+        {
+            return None;
+        }
+        let lookup = LookedUp {
+            inode: inode.clone(),
+            stat: state.stat.clone(),
+        };
+        trace!("lookup returned from stat confirmed by read: {:?}", lookup);
+        metrics::counter!("metadata_cache.confirmed_by_read_hit").increment(1);
+        Some(lookup)
+    }
+
+    /// Lookup a remote child in the parent directory whose key is under one of
+    /// [CacheConfig::pinned_prefixes], or is an immutable file. These are reused even when
+    /// `serve_lookup_from_cache` is disabled, since their metadata is only refreshed once it's
+    /// invalidated.
+    fn pinned_lookup(&self, parent_ino: InodeNo, name: &OsStr) -> Option<LookedUp> {
+        let name = name.to_str()?;
+        let parent = self.get(parent_ino).ok()?;
+        let parent_state = parent.get_inode_state().ok()?;
+        let InodeKindData::Directory { children, .. } = &parent_state.kind_data else {
+            return None;
+        };
+        let inode = children.get(name)?;
+        let counter = if self.is_pinned(inode.full_key()) {
+            "metadata_cache.pinned_hit"
+        } else if inode.kind() == InodeKind::File && self.is_immutable(inode.full_key()) {
+            "metadata_cache.immutable_hit"
+        } else {
+            return None;
+        };
+        let state = inode.get_inode_state().ok()?;
+        if state.write_status != WriteStatus::Remote || !state.stat.is_valid(self.now()) {
+            return None;
+        }
+        let lookup = LookedUp {
+            inode: inode.clone(),
+            stat: state.stat.clone(),
+        };
+        trace!("lookup returned from pinned stat: {:?}", lookup);
+        metrics::counter!(counter).increment(1);
+        Some(lookup)
+    }
+
+    /// Revalidate the expired stat of a remote directory without a full lookup of its name.
+    /// Directories shadow files and their attributes are synthetic, so all that matters is that
+    /// something still exists under the directory's prefix: a fresh listing of its parent that
+    /// found it is enough, and otherwise a ListObjectsV2 for a single key under the prefix. Unlike
+    /// a lookup, this never asks for an object with the directory's name, or for its marker.
+    ///
+    /// Returns `None` if the directory is gone, so a full lookup can find what replaced it, if
+    /// anything.
+    async fn revalidate_directory<OC: ObjectClient>(
+        &self,
+        client: &OC,
+        inode: &Inode,
+        source: LookupSource,
+    ) -> Result<Option<LookedUp>, InodeError> {
+        if source != LookupSource::FreshRemote {
+            if let Some(validity) = self.listed_validity(inode) {
+                let mut state = inode.get_mut_inode_state()?;
+                if state.write_status == WriteStatus::Remote {
+                    trace!(ino=?inode.ino(), "directory revalidated by its parent's listing");
+                    metrics::counter!("metadata_cache.directory_revalidations", "probe" => "parent_listing")
+                        .increment(1);
+                    state.stat.update_validity(validity, self.now());
+                    return Ok(Some(LookedUp {
+                        inode: inode.clone(),
+                        stat: state.stat.clone(),
+                    }));
+                }
+            }
+        }
+
+        metrics::counter!("metadata_cache.directory_revalidations", "probe" => "list").increment(1);
+        let permit = self.admit_remote()?;
+        let result = client
+            .list_objects(&self.bucket, None, "/", 1, inode.full_key())
+            .await
+            .map_err(|e| InodeError::from_client(client, e, "ListObjectsV2 failed"));
+        permit.complete(&result);
+        let result = result?;
+        if result.common_prefixes.is_empty() && result.objects.is_empty() {
+            trace!(ino=?inode.ino(), "directory not found by ListObjects, looking it up again");
+            return Ok(None);
+        }
+        let stat = InodeStat::for_directory(self.mount_time, self.config.cache_config.dir_ttl, self.now());
+        let remote = RemoteLookup {
+            kind: InodeKind::Directory,
+            stat,
+        };
+        self.update_from_remote(inode.parent(), inode.name(), Some(remote))
+            .map(Some)
+    }
+
+    /// How much longer the parent's listing vouches for the given directory, if the listing found
+    /// it and is still fresh. Listings are fresh for the parent's directory TTL from when they
+    /// started, and a directory is never vouched for longer than its own TTL.
+    fn listed_validity(&self, inode: &Inode) -> Option<Duration> {
+        let parent = self.get(inode.parent()).ok()?;
+        let parent_state = parent.get_inode_state().ok()?;
+        let InodeKindData::Directory {
+            children,
+            listing: Some(listing),
+            ..
+        } = &parent_state.kind_data
+        else {
+            return None;
+        };
+        // The name may have been taken by another inode since the listing
+        let current = children.get(inode.name())?;
+        if current.ino() != inode.ino() || !listing.subdirectories.contains(inode.name()) {
+            return None;
+        }
+        let remaining = self
+            .ttl_for(parent.full_key(), InodeKind::Directory)
+            .saturating_sub(self.now().saturating_duration_since(listing.listed_at));
+        let validity = remaining.min(self.ttl_for(inode.full_key(), InodeKind::Directory));
+        (!validity.is_zero()).then_some(validity)
+    }
+
     /// Lookup an inode in the parent directory with the given name
-    /// on the remote client.
+    /// on the remote client. Unless `fresh` is set, the result may be shared with other lookups of
+    /// the same name, see [CacheConfig::lookup_coalesce_window].
     async fn remote_lookup<OC: ObjectClient>(
         &self,
         client: &OC,
         parent_ino: InodeNo,
         name: &str,
+        fresh: bool,
     ) -> Result<Option<RemoteLookup>, InodeError> {
         let parent = self.get(parent_ino)?;
         if parent.kind() != InodeKind::Directory {
@@ -694,6 +1766,118 @@ impl SuperblockInner {
         assert!(full_path.is_empty() || full_path.ends_with('/'));
         full_path.push_str(name);
 
+        match self.key_failures.check(&full_path) {
+            Some(PermanentFailure::AccessDenied) => {
+                return Err(InodeError::AccessDenied(anyhow!(
+                    "lookups of {full_path:?} keep being denied, not asking S3 again yet"
+                )))
+            }
+            Some(PermanentFailure::NotFound) => return Ok(None),
+            None => {}
+        }
+
+        let result = self
+            .remote_lookup_shared(client, parent_ino, name, full_path.clone(), fresh)
+            .await;
+        match &result {
+            Ok(Some(_)) => self.key_failures.remove(&full_path),
+            Ok(None) => self.key_failures.fail(&full_path, PermanentFailure::NotFound),
+            Err(InodeError::AccessDenied(_)) => self.key_failures.fail(&full_path, PermanentFailure::AccessDenied),
+            // Other errors might not happen again, so they don't count against the key
+            Err(_) => {}
+        }
+        result
+    }
+
+    /// Make the requests for [remote_lookup](Self::remote_lookup) of `full_path`, sharing them
+    /// with other lookups of the name unless `fresh` is set.
+    async fn remote_lookup_shared<OC: ObjectClient>(
+        &self,
+        client: &OC,
+        parent_ino: InodeNo,
+        name: &str,
+        full_path: String,
+        fresh: bool,
+    ) -> Result<Option<RemoteLookup>, InodeError> {
+        if fresh {
+            metrics::counter!("metadata_cache.lookup_coalesce_bypass").increment(1);
+            return self
+                .remote_lookup_uncoalesced(client, parent_ino, name, full_path)
+                .await;
+        }
+
+        // Concurrent lookups of the same name, such as getattrs of a popular file whose stat has
+        // just expired, share a single set of requests and all observe its result. So do lookups
+        // shortly after it completes, if the coalesce window allows, as long as nothing has
+        // changed locally since the lookup started.
+        let window = self.config.cache_config.lookup_coalesce_window;
+        let changes = self.local_changes.load(Ordering::Acquire);
+        let key = (parent_ino, name.to_owned());
+        let pending = {
+            let now = self.now();
+            let mut pending_lookups = self.pending_lookups.lock().unwrap();
+            match pending_lookups.get(&key) {
+                Some(pending) if pending.reusable(changes, now) => {
+                    metrics::counter!("metadata_cache.lookup_coalesced").increment(1);
+                    pending.clone()
+                }
+                _ => {
+                    // Completed lookups are only retired when they're replaced, so clear out the
+                    // ones nobody has looked up again every so often
+                    if !window.is_zero() && pending_lookups.len() >= PENDING_LOOKUPS_PRUNE_SIZE {
+                        pending_lookups.retain(|_, pending| pending.reusable(changes, now));
+                    }
+                    let pending = Arc::new(PendingLookup::new(changes));
+                    pending_lookups.insert(key.clone(), pending.clone());
+                    pending
+                }
+            }
+        };
+        let result = pending
+            .result
+            .get_or_init(|| {
+                self.remote_lookup_uncoalesced(client, parent_ino, name, full_path)
+                    .map(|result| result.map_err(SharedLookupError::new))
+            })
+            .await
+            .clone();
+
+        if window.is_zero() {
+            // Whoever gets here first retires the entry, so lookups from now on make new requests
+            let mut pending_lookups = self.pending_lookups.lock().unwrap();
+            if matches!(pending_lookups.get(&key), Some(entry) if Arc::ptr_eq(entry, &pending)) {
+                pending_lookups.remove(&key);
+            }
+        } else {
+            pending.complete(window, self.now());
+        }
+
+        result.map_err(SharedLookupError::into_inode_error)
+    }
+
+    /// Make the requests for [remote_lookup](Self::remote_lookup) of `full_path`, if the
+    /// [metadata breaker](SuperblockConfig::metadata_breaker) allows.
+    async fn remote_lookup_uncoalesced<OC: ObjectClient>(
+        &self,
+        client: &OC,
+        parent_ino: InodeNo,
+        name: &str,
+        full_path: String,
+    ) -> Result<Option<RemoteLookup>, InodeError> {
+        let permit = self.admit_remote()?;
+        let result = self.remote_lookup_requests(client, parent_ino, name, full_path).await;
+        permit.complete(&result);
+        result
+    }
+
+    /// Send the HeadObject and ListObjects requests that look up `full_path`
+    async fn remote_lookup_requests<OC: ObjectClient>(
+        &self,
+        client: &OC,
+        parent_ino: InodeNo,
+        name: &str,
+        full_path: String,
+    ) -> Result<Option<RemoteLookup>, InodeError> {
         let mut full_path_suffixed = full_path.clone();
         full_path_suffixed.push('/');
 
@@ -721,29 +1905,39 @@ impl SuperblockInner {
         //       "/" to the prefix in the request, the first common prefix we'll get back will be
         //       "dir-1/", because that precedes "dir/" in lexicographic order. Doing the
         //       ListObjects with "/" appended makes sure we always observe the correct prefix.
+        //   (4) If the HeadObject fails for a reason other than the key not existing, we don't know
+        //       whether there's a file. But a directory found by the ListObjects shadows any file,
+        //       so we wait for the ListObjects before failing the lookup. If the ListObjects fails,
+        //       we fail the lookup straight away rather than report that the name doesn't exist.
+        // The two requests are made concurrently, so a lookup costs one round trip rather than two,
+        // and whichever is still in flight is cancelled when we return early.
         let mut file_lookup = client.head_object(&self.bucket, &full_path).fuse();
         let mut dir_lookup = client
             .list_objects(&self.bucket, None, "/", 1, &full_path_suffixed)
             .fuse();
 
         let mut file_state = None;
+        let mut file_error = None;
 
         for _ in 0..2 {
             select_biased! {
                 result = file_lookup => {
                     match result {
-                        Ok(HeadObjectResult { object, .. }) => {
-                            let stat = InodeStat::for_file(object.size as usize, object.last_modified, Some(object.etag.clone()), object.storage_class, object.restore_status, self.config.cache_config.file_ttl);
+                        Ok(HeadObjectResult { object, content_encoding, .. }) => {
+                            let mut stat = InodeStat::for_file(object.size as usize, object.last_modified, Some(object.etag.clone()), object.storage_class, object.restore_status, self.config.cache_config.file_ttl, self.now());
+                            stat.unknown_size = object.unknown_size;
+                            stat.content_encoding = content_encoding;
                             file_state = Some(stat);
                         }
                         // If the object is not found, might be a directory, so keep going
                         Err(ObjectClientError::ServiceError(HeadObjectError::NotFound)) => {},
-                        Err(e) => return Err(InodeError::ClientError(anyhow!(e).context("HeadObject failed"))),
+                        // Might still be shadowed by a directory, so keep going (case (4) above)
+                        Err(e) => file_error = Some(InodeError::from_client(client, e, "HeadObject failed")),
                     }
                 }
 
                 result = dir_lookup => {
-                    let result = result.map_err(|e| InodeError::ClientError(anyhow!(e).context("ListObjectsV2 failed")))?;
+                    let result = result.map_err(|e| InodeError::from_client(client, e, "ListObjectsV2 failed"))?;
 
                     let found_directory = if result
                         .common_prefixes
@@ -783,7 +1977,7 @@ impl SuperblockInner {
                     // semantics, directories always shadow files.
                     if found_directory {
                         trace!(parent = ?parent_ino, ?name, "lookup ListObjects found a directory");
-                        let stat = InodeStat::for_directory(self.mount_time, self.config.cache_config.dir_ttl);
+                        let stat = InodeStat::for_directory(self.mount_time, self.config.cache_config.dir_ttl, self.now());
                         return Ok(Some(RemoteLookup { kind: InodeKind::Directory, stat }));
                     }
                 }
@@ -792,10 +1986,13 @@ impl SuperblockInner {
 
         // If we reach here, the ListObjects didn't find a shadowing directory, so we know we either
         // have a valid file, or both requests failed to find the object so the file must not exist remotely
+        if let Some(e) = file_error {
+            return Err(e);
+        }
         if let Some(mut stat) = file_state {
             trace!(parent = ?parent_ino, ?name, etag =? stat.etag, "found a regular file in S3");
             // Update the validity of the stat in case the racing ListObjects took a long time
-            stat.update_validity(self.config.cache_config.file_ttl);
+            stat.update_validity(self.config.cache_config.file_ttl, self.now());
             Ok(Some(RemoteLookup {
                 kind: InodeKind::File,
                 stat,
@@ -821,26 +2018,77 @@ impl SuperblockInner {
             return Err(InodeError::NotADirectory(parent.err()));
         }
 
-        if self.config.cache_config.serve_lookup_from_cache {
+        // Metadata under a pinned prefix, or of an immutable file, doesn't expire until it's
+        // invalidated, recently modified files expire sooner, and other metadata expires after the
+        // TTL that applies to its key
+        let remote = remote.map(|mut remote| {
+            let key = match remote.kind {
+                InodeKind::File => format!("{}{}", parent.full_key(), name),
+                InodeKind::Directory => format!("{}{}/", parent.full_key(), name),
+            };
+            let immutable = remote.kind == InodeKind::File
+                && self.is_immutable(&key)
+                && self.check_immutable(&parent, name, &key, &remote);
+            if self.is_pinned(&key) || immutable {
+                remote.stat.update_validity(NEVER_EXPIRE_TTL, self.now());
+            } else if remote.kind == InodeKind::File && self.recently_modified(remote.stat.mtime) {
+                remote.stat.update_validity(self.recently_written_ttl(&key), self.now());
+            } else if let Some(ttl) = self.path_ttl(&key, remote.kind) {
+                remote.stat.update_validity(ttl, self.now());
+            }
+            remote
+        });
+
+        let settings = self.cache_settings(&format!("{}{}", parent.full_key(), name));
+        if settings.serve_lookup_from_cache {
             match &remote {
                 // Remove negative cache entry.
                 Some(_) => self.negative_cache.remove(parent_ino, name),
                 // Insert or update TTL of negative cache entry.
-                None => self.negative_cache.insert(parent_ino, name),
+                None => self
+                    .negative_cache
+                    .insert(parent_ino, name, settings.negative_cache_ttl),
             }
         }
 
         // Fast path: try with only a read lock on the directory first.
-        if let Some(looked_up) = Self::try_update_fast_path(&parent, name, &remote)? {
+        if let Some(looked_up) = self.try_update_fast_path(&parent, name, &remote)? {
             return Ok(looked_up);
         }
 
         self.update_slow_path(parent, name, remote)
     }
 
+    /// Check that a lookup of an immutable file found the same object as we already knew about, if
+    /// any. If it didn't, the object changed after all, so its key stops being treated as
+    /// immutable, and we return false.
+    fn check_immutable(&self, parent: &Inode, name: &str, key: &str, remote: &RemoteLookup) -> bool {
+        let existing = {
+            let Ok(parent_state) = parent.get_inode_state() else {
+                return true;
+            };
+            let InodeKindData::Directory { children, .. } = &parent_state.kind_data else {
+                return true;
+            };
+            children.get(name).cloned()
+        };
+        let Some(existing) = existing else {
+            return true;
+        };
+        let changed = existing.kind() == InodeKind::File
+            && existing
+                .get_inode_state()
+                .is_ok_and(|state| state.write_status == WriteStatus::Remote && state.stat.etag != remote.stat.etag);
+        if changed {
+            self.revoke_immutable(key);
+        }
+        !changed
+    }
+
     /// Try to update the inode for the given name in the parent directory with only a read lock on
     /// the parent.
     fn try_update_fast_path(
+        &self,
         parent: &Inode,
         name: &str,
         remote: &Option<RemoteLookup>,
@@ -860,10 +2108,15 @@ impl SuperblockInner {
                     && existing_state.stat.etag == remote.stat.etag
                 {
                     trace!(parent=?existing_inode.parent(), name=?existing_inode.name(), ino=?existing_inode.ino(), "updating inode in place");
-                    existing_state.stat = remote.stat.clone();
+                    let stat = remote.stat.clone().keep_directory_times(
+                        remote.kind,
+                        &existing_state.stat,
+                        self.is_watched(existing_inode.ino()),
+                    );
+                    existing_state.stat = stat.clone();
                     Ok(Some(LookedUp {
                         inode: existing_inode.clone(),
-                        stat: remote.stat.clone(),
+                        stat,
                     }))
                 } else {
                     Ok(None)
@@ -901,11 +2154,8 @@ impl SuperblockInner {
                 if writing_children.contains(&existing_inode.ino()) {
                     let mut sync = existing_inode.get_mut_inode_state()?;
 
-                    let validity = match existing_inode.kind() {
-                        InodeKind::File => self.config.cache_config.file_ttl,
-                        InodeKind::Directory => self.config.cache_config.dir_ttl,
-                    };
-                    sync.stat.update_validity(validity);
+                    let validity = self.local_ttl_for(existing_inode.full_key(), existing_inode.kind());
+                    sync.stat.update_validity(validity, self.now());
                     let stat = sync.stat.clone();
                     drop(sync);
 
@@ -959,7 +2209,12 @@ impl SuperblockInner {
                 let same_etag = existing_state.stat.etag == remote.stat.etag;
                 if same_kind && same_etag && (existing_is_remote || remote.kind == InodeKind::Directory) {
                     trace!(parent=?existing_inode.parent(), name=?existing_inode.name(), ino=?existing_inode.ino(), "updating inode in place (slow path)");
-                    existing_state.stat = remote.stat.clone();
+                    let stat = remote.stat.keep_directory_times(
+                        remote.kind,
+                        &existing_state.stat,
+                        self.is_watched(existing_inode.ino()),
+                    );
+                    existing_state.stat = stat.clone();
                     if remote.kind == InodeKind::Directory && !existing_is_remote {
                         trace!(parent=?existing_inode.parent(), name=?existing_inode.name(), ino=?existing_inode.ino(), "local directory has become remote");
                         existing_state.write_status = WriteStatus::Remote;
@@ -970,7 +2225,7 @@ impl SuperblockInner {
                     }
                     return Ok(LookedUp {
                         inode: existing_inode.clone(),
-                        stat: remote.stat,
+                        stat,
                     });
                 }
 
@@ -1059,15 +2314,113 @@ impl SuperblockInner {
             }
         }
 
-        Ok(inode)
+        Ok(inode)
+    }
+}
+
+/// Data from a remote object.
+#[derive(Debug, Clone)]
+pub struct RemoteLookup {
+    kind: InodeKind,
+    stat: InodeStat,
+}
+
+/// Once there are this many lookups in [SuperblockInner::pending_lookups], those that can't be
+/// reused any more are cleared out before another is added
+const PENDING_LOOKUPS_PRUNE_SIZE: usize = 1024;
+
+/// Where [SuperblockInner::lookup_by_name] may get its result from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LookupSource {
+    /// The metadata cache, if it has a valid entry and the cache settings allow it, or else S3
+    Cache,
+    /// S3, possibly sharing the requests of a concurrent or very recent lookup of the same name
+    Remote,
+    /// New requests to S3
+    FreshRemote,
+}
+
+/// A remote lookup, shared by every concurrent lookup of the name, and by later ones for
+/// [CacheConfig::lookup_coalesce_window] after it completes
+#[derive(Debug)]
+struct PendingLookup {
+    result: AsyncOnceCell<Result<Option<RemoteLookup>, SharedLookupError>>,
+    /// [SuperblockInner::local_changes] when the lookup started
+    changes: u64,
+    /// When the result stops being reused, once the lookup has completed
+    expiry: Mutex<Option<Expiry>>,
+}
+
+impl PendingLookup {
+    fn new(changes: u64) -> Self {
+        Self {
+            result: AsyncOnceCell::new(),
+            changes,
+            expiry: Mutex::new(None),
+        }
+    }
+
+    /// Whether a lookup starting at `now`, with `changes` local changes so far, can share this one
+    fn reusable(&self, changes: u64, now: Instant) -> bool {
+        self.changes == changes && !self.expiry.lock().unwrap().is_some_and(|expiry| expiry.is_expired(now))
+    }
+
+    /// Record that the lookup has completed at `now`, so its result is reused for `window` after
+    fn complete(&self, window: Duration, now: Instant) {
+        self.expiry
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| Expiry::new(now, window));
     }
 }
 
-/// Data from a remote object.
+/// An error from a remote lookup that may be reported to more than one caller
 #[derive(Debug, Clone)]
-pub struct RemoteLookup {
-    kind: InodeKind,
-    stat: InodeStat,
+struct SharedLookupError {
+    error: std::sync::Arc<anyhow::Error>,
+    /// Whether S3 denied access, so that every caller reports [InodeError::AccessDenied]
+    access_denied: bool,
+    /// Whether the lookup wasn't sent because metadata requests are failing, so that every caller
+    /// reports [InodeError::MetadataUnavailable]
+    unavailable: bool,
+}
+
+impl SharedLookupError {
+    fn new(error: InodeError) -> Self {
+        let access_denied = matches!(error, InodeError::AccessDenied(_));
+        let unavailable = matches!(error, InodeError::MetadataUnavailable(_));
+        let error = match error {
+            InodeError::ClientError(e) | InodeError::AccessDenied(e) => e,
+            e => anyhow!(e),
+        };
+        Self {
+            error: std::sync::Arc::new(error),
+            access_denied,
+            unavailable,
+        }
+    }
+
+    fn into_inode_error(self) -> InodeError {
+        if self.unavailable {
+            InodeError::MetadataUnavailable(CircuitBreakerOpen)
+        } else if self.access_denied {
+            InodeError::AccessDenied(anyhow::Error::new(self))
+        } else {
+            InodeError::ClientError(anyhow::Error::new(self))
+        }
+    }
+}
+
+impl Display for SharedLookupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&*self.error, f)
+    }
+}
+
+impl std::error::Error for SharedLookupError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        std::error::Error::source(&**self.error)
+    }
 }
 
 /// Result of a call to [Superblock::lookup] or [Superblock::getattr]. `stat` is a copy of the
@@ -1080,9 +2433,9 @@ pub struct LookedUp {
 }
 
 impl LookedUp {
-    /// How much longer this lookup will be valid for
-    pub fn validity(&self) -> Duration {
-        self.stat.expiry.remaining_ttl()
+    /// How much longer, from `now`, this lookup will be valid for
+    pub fn validity(&self, now: Instant) -> Duration {
+        self.stat.expiry.remaining_ttl(now)
     }
 }
 
@@ -1128,6 +2481,10 @@ impl WriteHandle {
             WriteStatus::LocalUnopened => {
                 state.write_status = WriteStatus::LocalOpen;
                 state.stat.size = 0;
+                state.stat.confirmed_by_read = false;
+                state
+                    .stat
+                    .update_validity(self.inner.recently_written_ttl(inode.full_key()), self.inner.now());
                 Ok(self)
             }
             WriteStatus::LocalOpen => Err(InodeError::InodeAlreadyWriting(inode.err())),
@@ -1148,8 +2505,34 @@ impl WriteHandle {
 
                 state.write_status = WriteStatus::LocalOpen;
                 state.stat.size = 0;
+                state.stat.confirmed_by_read = false;
+                state
+                    .stat
+                    .update_validity(self.inner.recently_written_ttl(inode.full_key()), self.inner.now());
+                Ok(self)
+            }
+        }
+    }
+
+    /// Set an existing file to writing state so part of its object can be overwritten in place.
+    /// Unlike [start_writing](Self::start_writing), the file keeps its contents and size, and
+    /// doesn't need to be opened in truncate mode.
+    pub fn start_partial_writing(self) -> Result<Self, InodeError> {
+        let inode = self.inner.get(self.ino)?;
+        let mut state = inode.get_mut_inode_state()?;
+        if state.reader_count > 0 {
+            return Err(InodeError::InodeNotWritableWhileReading(inode.err()));
+        }
+        match state.write_status {
+            WriteStatus::Remote if self.allow_overwrite => {
+                state.write_status = WriteStatus::LocalOpen;
+                state.stat.confirmed_by_read = false;
                 Ok(self)
             }
+            WriteStatus::Remote => Err(InodeError::InodeNotWritable(inode.err())),
+            WriteStatus::LocalUnopened | WriteStatus::LocalOpen => {
+                Err(InodeError::InodeInvalidWriteStatus(inode.err()))
+            }
         }
     }
 
@@ -1193,7 +2576,7 @@ impl WriteHandle {
                 state.write_status = WriteStatus::Remote;
 
                 // Invalidate the inode's stats so we refresh them from S3 when next queried
-                state.stat.update_validity(Duration::from_secs(0));
+                state.stat.update_validity(Duration::from_secs(0), self.inner.now());
 
                 // Walk up the ancestors from parent to first remote ancestor to transition
                 // the inode and all "local" containing directories to "remote".
@@ -1208,6 +2591,14 @@ impl WriteHandle {
                     ancestor_state.write_status = WriteStatus::Remote;
                 }
 
+                // The new object isn't in any listing of its ancestors we've kept
+                for ancestor in &ancestors {
+                    self.inner.pinned_listings.remove(ancestor.full_key());
+                    self.inner.bootstrap_listings.remove(ancestor.full_key());
+                }
+                self.inner.key_failures.remove(self.full_key());
+                self.inner.record_change();
+
                 Ok(())
             }
             _ => Err(InodeError::InodeInvalidWriteStatus(inode.err())),
@@ -1307,6 +2698,11 @@ impl Inode {
         state.stat.size += len;
     }
 
+    pub fn dec_file_size(&self, len: usize) {
+        let mut state = self.inner.sync.write().unwrap();
+        state.stat.size = state.stat.size.saturating_sub(len);
+    }
+
     pub fn start_reading(&self) -> Result<(), InodeError> {
         let mut state = self.get_mut_inode_state()?;
         match state.write_status {
@@ -1460,6 +2856,10 @@ enum InodeKindData {
 
         /// True if this directory has been deleted (`rmdir`) from its parent
         deleted: bool,
+
+        /// The subdirectories found by the last complete listing of this directory from S3, which
+        /// vouch for their existence while it's fresh. See [SuperblockInner::revalidate_directory].
+        listing: Option<ListingSnapshot>,
     },
 }
 
@@ -1471,11 +2871,55 @@ impl InodeKindData {
                 children: Default::default(),
                 writing_children: Default::default(),
                 deleted: false,
+                listing: None,
             },
         }
     }
 }
 
+/// The names of the subdirectories a complete listing of a directory found, as common prefixes
+/// (including those of directory markers), and a fingerprint of everything it found
+#[derive(Debug)]
+struct ListingSnapshot {
+    /// When the listing started, so anything it found existed at least until then
+    listed_at: Instant,
+    subdirectories: HashSet<String>,
+    /// Fingerprint of the listed prefixes and objects, to tell whether a directory changed between
+    /// listings. It doesn't depend on listing order or page size, as not every S3 implementation
+    /// lists in order.
+    fingerprint: u64,
+}
+
+impl ListingSnapshot {
+    /// Start a snapshot of a listing that's starting at `listed_at`
+    fn new(listed_at: Instant) -> Self {
+        Self {
+            listed_at,
+            subdirectories: HashSet::new(),
+            fingerprint: 0,
+        }
+    }
+
+    /// Record a page of the listing of the directory with the given key
+    fn add_page(&mut self, dir_key: &str, page: &ListObjectsResult) {
+        fn hash_entry(entry: impl Hash) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            entry.hash(&mut hasher);
+            hasher.finish()
+        }
+
+        for prefix in &page.common_prefixes {
+            self.fingerprint = self.fingerprint.wrapping_add(hash_entry(prefix));
+            if let Some(name) = prefix.strip_prefix(dir_key).and_then(|name| name.strip_suffix('/')) {
+                self.subdirectories.insert(name.to_owned());
+            }
+        }
+        for object in &page.objects {
+            self.fingerprint = self.fingerprint.wrapping_add(hash_entry((&object.key, &object.etag)));
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct InodeStat {
     /// Time this stat becomes invalid and needs to be refreshed
@@ -1483,6 +2927,12 @@ pub struct InodeStat {
 
     /// Size in bytes
     pub size: usize,
+    /// The object's size isn't known until it's read, so `size` is 0. See
+    /// [ObjectInfo::unknown_size](mountpoint_s3_client::types::ObjectInfo::unknown_size).
+    pub unknown_size: bool,
+    /// The object's `Content-Encoding`. Only known when the stat comes from HeadObject, so `None`
+    /// doesn't mean the object isn't encoded.
+    pub content_encoding: Option<String>,
 
     /// Time of last file content modification
     pub mtime: OffsetDateTime,
@@ -1496,6 +2946,10 @@ pub struct InodeStat {
     /// are only readable after restoration. For objects with other storage classes
     /// this field should be always `true`.
     pub is_readable: bool,
+
+    /// Whether the current validity comes from a successful read of the object, rather than a
+    /// lookup. See [Superblock::confirm_read].
+    confirmed_by_read: bool,
 }
 
 /// Inode write status (local vs remote)
@@ -1510,8 +2964,8 @@ pub enum WriteStatus {
 }
 
 impl InodeStat {
-    fn is_valid(&self) -> bool {
-        !self.expiry.is_expired()
+    fn is_valid(&self, now: Instant) -> bool {
+        !self.expiry.is_expired(now)
     }
 
     /// Objects in flexible retrieval storage classes can't be accessed via GetObject unless they are
@@ -1519,6 +2973,8 @@ impl InodeStat {
     /// the first time we see an object like this, because FUSE enforces the 000 permissions on our
     /// behalf so we might not see an attempted `open` call.
     fn is_readable(storage_class: Option<String>, restore_status: Option<RestoreStatus>) -> bool {
+        // Once per process rather than per file system, as it's about the storage classes, not any
+        // one bucket or prefix
         static HAS_SENT_WARNING: AtomicBool = AtomicBool::new(false);
         match storage_class.as_deref() {
             Some("GLACIER") | Some("DEEP_ARCHIVE") => {
@@ -1543,34 +2999,53 @@ impl InodeStat {
         storage_class: Option<String>,
         restore_status: Option<RestoreStatus>,
         validity: Duration,
+        now: Instant,
     ) -> InodeStat {
         let is_readable = Self::is_readable(storage_class, restore_status);
         InodeStat {
-            expiry: Expiry::from_now(validity),
+            expiry: Expiry::new(now, validity),
             size,
             atime: datetime,
             ctime: datetime,
             mtime: datetime,
             etag,
             is_readable,
+            confirmed_by_read: false,
+            unknown_size: false,
+            content_encoding: None,
         }
     }
 
     /// Initialize an [InodeStat] for a directory, given some metadata.
-    fn for_directory(datetime: OffsetDateTime, validity: Duration) -> InodeStat {
+    fn for_directory(datetime: OffsetDateTime, validity: Duration, now: Instant) -> InodeStat {
         InodeStat {
-            expiry: Expiry::from_now(validity),
+            expiry: Expiry::new(now, validity),
             size: 0,
             atime: datetime,
             ctime: datetime,
             mtime: datetime,
             etag: None,
             is_readable: true,
+            confirmed_by_read: false,
+            unknown_size: false,
+            content_encoding: None,
         }
     }
 
-    fn update_validity(&mut self, validity: Duration) {
-        self.expiry = Expiry::from_now(validity);
+    fn update_validity(&mut self, validity: Duration, now: Instant) {
+        self.expiry = Expiry::new(now, validity);
+    }
+
+    /// Directories don't have timestamps in S3, so when refreshing the stat of a watched directory
+    /// from the remote we keep its existing timestamps, which the poller bumps when the directory
+    /// changes. Other directories take the remote's timestamps as usual.
+    fn keep_directory_times(mut self, kind: InodeKind, existing: &InodeStat, watched: bool) -> InodeStat {
+        if kind == InodeKind::Directory && watched {
+            self.atime = existing.atime;
+            self.mtime = existing.mtime;
+            self.ctime = existing.ctime;
+        }
+        self
     }
 }
 
@@ -1606,12 +3081,18 @@ impl InodeMap {
 pub enum InodeError {
     #[error("error from ObjectClient")]
     ClientError(#[source] anyhow::Error),
+    #[error("access denied by S3")]
+    AccessDenied(#[source] anyhow::Error),
+    #[error("metadata requests to S3 are failing")]
+    MetadataUnavailable(#[source] CircuitBreakerOpen),
     #[error("file {0:?} does not exist in parent inode {1}")]
     FileDoesNotExist(String, InodeErrorInfo),
     #[error("inode {0} does not exist")]
     InodeDoesNotExist(InodeNo),
     #[error("invalid file name {0:?}")]
     InvalidFileName(OsString),
+    #[error("file name {0:?} is longer than {MAX_NAME_LEN} bytes")]
+    NameTooLong(OsString),
     #[error("inode {0} is not a directory")]
     NotADirectory(InodeErrorInfo),
     #[error("inode {0} is a directory")]
@@ -1646,17 +3127,34 @@ pub enum InodeError {
     },
 }
 
+impl InodeError {
+    /// Wrap a failed request to S3, as [InodeError::AccessDenied] if S3 refused to let us make it
+    fn from_client<OC, S>(client: &OC, err: ObjectClientError<S, OC::ClientError>, context: &'static str) -> Self
+    where
+        OC: ObjectClient,
+        S: std::error::Error + Send + Sync + 'static,
+    {
+        if matches!(&err, ObjectClientError::ClientError(e) if client.is_access_denied(e)) {
+            InodeError::AccessDenied(anyhow!(err).context(context))
+        } else {
+            InodeError::ClientError(anyhow!(err).context(context))
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
 
     use mountpoint_s3_client::{
-        mock_client::{MockClient, MockClientConfig, MockObject},
+        failure_client::countdown_failure_client,
+        mock_client::{MockClient, MockClientConfig, MockClientError, MockObject, Operation},
         types::ETag,
     };
     use test_case::test_case;
     use time::{Duration, OffsetDateTime};
 
+    use crate::clock::MockClock;
     use crate::fs::{ToErrno, FUSE_ROOT_INODE};
 
     use super::*;
@@ -1828,6 +3326,7 @@ mod tests {
                     ..Default::default()
                 },
                 s3_personality: S3Personality::Standard,
+                ..Default::default()
             },
         );
 
@@ -1853,6 +3352,214 @@ mod tests {
         }
     }
 
+    #[test_case(false; "getattr only")]
+    #[test_case(true; "getattr and lookup")]
+    #[tokio::test]
+    async fn test_concurrent_refresh_is_coalesced(with_lookups: bool) {
+        let bucket = "test_bucket";
+        let client_config = MockClientConfig {
+            bucket: bucket.to_string(),
+            part_size: 1024 * 1024,
+            ..Default::default()
+        };
+        let client = Arc::new(MockClient::new(client_config));
+        client.add_object("file.txt", MockObject::constant(0xaa, 30, ETag::for_tests()));
+
+        // With a zero TTL, every getattr finds the stat expired
+        let superblock = Superblock::new(
+            bucket,
+            &Default::default(),
+            SuperblockConfig {
+                cache_config: CacheConfig {
+                    serve_lookup_from_cache: false,
+                    dir_ttl: std::time::Duration::ZERO,
+                    file_ttl: std::time::Duration::ZERO,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        let ino = superblock
+            .lookup(&client, FUSE_ROOT_INODE, "file.txt".as_ref())
+            .await
+            .expect("should exist")
+            .inode
+            .ino();
+
+        // Same object, so the refresh updates the inode in place, but with a new modification time
+        let updated_mtime = OffsetDateTime::UNIX_EPOCH + Duration::days(1);
+        let mut updated = MockObject::constant(0xaa, 30, ETag::for_tests());
+        updated.set_last_modified(updated_mtime);
+        client.add_object("file.txt", updated);
+
+        let head_counter = client.new_counter(Operation::HeadObject);
+        let pause = client.pause_head_object();
+        let refreshes = (0..50).map(|i| {
+            let superblock = &superblock;
+            let client = &client;
+            async move {
+                if with_lookups && i % 2 == 1 {
+                    superblock.lookup(client, FUSE_ROOT_INODE, "file.txt".as_ref()).await
+                } else {
+                    superblock.getattr(client, ino, false).await
+                }
+            }
+        });
+        // All the refreshes start, and wait on the same HeadObject, before it's allowed to finish
+        let (results, ()) = futures::join!(futures::future::join_all(refreshes), async move { drop(pause) });
+
+        assert_eq!(head_counter.count(), 1);
+        for result in results {
+            let lookup = result.expect("refresh should succeed");
+            assert_eq!(lookup.inode.ino(), ino);
+            assert_inode_stat!(lookup, InodeKind::File, updated_mtime, 30);
+        }
+
+        // Once the refresh is done, the next getattr asks S3 again
+        superblock.getattr(&client, ino, false).await.expect("should exist");
+        assert_eq!(head_counter.count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_lookups_of_missing_name_are_coalesced() {
+        let bucket = "test_bucket";
+        let client_config = MockClientConfig {
+            bucket: bucket.to_string(),
+            part_size: 1024 * 1024,
+            ..Default::default()
+        };
+        let client = Arc::new(MockClient::new(client_config));
+        client.add_object("dir/file.txt", MockObject::constant(0xaa, 30, ETag::for_tests()));
+
+        // Without the lookup cache, nothing but coalescing stops every lookup going to S3
+        let superblock = Superblock::new(
+            bucket,
+            &Default::default(),
+            SuperblockConfig {
+                cache_config: CacheConfig {
+                    serve_lookup_from_cache: false,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+
+        let list_counter = client.new_counter(Operation::ListObjectsV2);
+        let head_counter = client.new_counter(Operation::HeadObject);
+        let pause = client.pause_head_object();
+        let lookups = (0..50).map(|_| superblock.lookup(&client, FUSE_ROOT_INODE, "missing".as_ref()));
+        // All the lookups start, and wait on the same requests, before they're allowed to finish
+        let (results, ()) = futures::join!(futures::future::join_all(lookups), async move { drop(pause) });
+
+        assert_eq!(list_counter.count(), 1);
+        assert_eq!(head_counter.count(), 1);
+        for result in results {
+            let err = result.expect_err("name should not exist");
+            assert!(
+                matches!(err, InodeError::FileDoesNotExist(_, _)),
+                "unexpected error {err:?}"
+            );
+        }
+
+        // Once those lookups are done, the next one asks S3 again
+        superblock
+            .lookup(&client, FUSE_ROOT_INODE, "missing".as_ref())
+            .await
+            .expect_err("name should not exist");
+        assert_eq!(list_counter.count(), 2);
+    }
+
+    #[test_case(true, false; "file")]
+    #[test_case(false, true; "directory")]
+    #[test_case(true, true; "directory shadowing file")]
+    #[test_case(false, false; "neither")]
+    #[tokio::test]
+    async fn test_remote_lookup_probes_concurrently(file: bool, dir: bool) {
+        let bucket = "test_bucket";
+        let client_config = MockClientConfig {
+            bucket: bucket.to_string(),
+            part_size: 1024 * 1024,
+            ..Default::default()
+        };
+        let client = Arc::new(MockClient::new(client_config));
+        if file {
+            client.add_object("name", MockObject::constant(0xaa, 30, ETag::for_tests()));
+        }
+        if dir {
+            client.add_object("name/child", MockObject::constant(0xaa, 30, ETag::for_tests()));
+        }
+        let latency = std::time::Duration::from_millis(200);
+        client.set_operation_latency(Operation::HeadObject, latency);
+        client.set_operation_latency(Operation::ListObjectsV2, latency);
+
+        let superblock = Superblock::new(bucket, &Default::default(), Default::default());
+        let start = std::time::Instant::now();
+        let result = superblock.lookup(&client, FUSE_ROOT_INODE, "name".as_ref()).await;
+        let elapsed = start.elapsed();
+
+        match (file, dir) {
+            (_, true) => assert_eq!(result.expect("should exist").inode.kind(), InodeKind::Directory),
+            (true, false) => assert_eq!(result.expect("should exist").inode.kind(), InodeKind::File),
+            (false, false) => assert!(matches!(result, Err(InodeError::FileDoesNotExist(_, _)))),
+        }
+        // The file and directory probes are in flight at the same time, so the lookup takes about
+        // one request's latency rather than two
+        assert!(elapsed >= latency, "lookup took {elapsed:?}");
+        assert!(elapsed < latency * 2, "lookup took {elapsed:?}");
+    }
+
+    #[test_case(true, false, false, true; "head fails, directory found")]
+    #[test_case(true, false, false, false; "head fails, nothing listed")]
+    #[test_case(false, true, false, false; "list fails, file not found")]
+    #[test_case(false, true, true, false; "list fails, file found")]
+    #[tokio::test]
+    async fn test_remote_lookup_probe_fails(head_fails: bool, list_fails: bool, file: bool, dir: bool) {
+        let bucket = "test_bucket";
+        let client_config = MockClientConfig {
+            bucket: bucket.to_string(),
+            part_size: 1024 * 1024,
+            ..Default::default()
+        };
+        let client = Arc::new(MockClient::new(client_config));
+        if file {
+            client.add_object("name", MockObject::constant(0xaa, 30, ETag::for_tests()));
+        }
+        if dir {
+            client.add_object("name/child", MockObject::constant(0xaa, 30, ETag::for_tests()));
+        }
+        // The failing probe fails straight away, while the other one is still in flight
+        client.set_operation_latency(Operation::HeadObject, std::time::Duration::from_millis(50));
+        client.set_operation_latency(Operation::ListObjectsV2, std::time::Duration::from_millis(50));
+
+        let mut head_failures = HashMap::new();
+        if head_fails {
+            head_failures.insert(1, ObjectClientError::ClientError(MockClientError("head failed".into())));
+        }
+        let mut list_failures = HashMap::new();
+        if list_fails {
+            list_failures.insert(1, ObjectClientError::ClientError(MockClientError("list failed".into())));
+        }
+        let client = countdown_failure_client(
+            client,
+            Default::default(),
+            head_failures,
+            list_failures,
+            Default::default(),
+        );
+
+        let superblock = Superblock::new(bucket, &Default::default(), Default::default());
+        let result = superblock.lookup(&client, FUSE_ROOT_INODE, "name".as_ref()).await;
+
+        if dir && !list_fails {
+            // A directory shadows any file, so it doesn't matter that we don't know about the file
+            assert_eq!(result.expect("should exist").inode.kind(), InodeKind::Directory);
+        } else {
+            // Without both probes, we can't know whether the name exists, so it's not ENOENT
+            let err = result.expect_err("lookup should fail");
+            assert!(matches!(err, InodeError::ClientError(_)), "unexpected error: {err:?}");
+        }
+    }
+
     #[test_case(true; "cached")]
     #[test_case(false; "not cached")]
     #[tokio::test]
@@ -1883,40 +3590,187 @@ mod tests {
                     ..Default::default()
                 },
                 s3_personality: S3Personality::Standard,
+                ..Default::default()
+            },
+        );
+
+        let entries = ["file0.txt", "sdir0"];
+        for entry in entries {
+            _ = superblock
+                .lookup(&client, FUSE_ROOT_INODE, entry.as_ref())
+                .await
+                .expect_err("should not exist");
+        }
+
+        let keys = &[
+            format!("{prefix}file0.txt"),
+            format!("{prefix}sdir0/file0.txt"),
+            format!("{prefix}sdir0/file1.txt"),
+        ];
+
+        let object_size = 30;
+        let mut last_modified = OffsetDateTime::UNIX_EPOCH;
+        for key in keys {
+            let mut obj = MockObject::constant(0xaa, object_size, ETag::for_tests());
+            last_modified += Duration::days(1);
+            obj.set_last_modified(last_modified);
+            client.add_object(key, obj);
+        }
+
+        for entry in entries {
+            let lookup = superblock.lookup(&client, FUSE_ROOT_INODE, entry.as_ref()).await;
+            if cached {
+                lookup.expect_err("negative entry should still be valid in the cache, so the new key should not have been looked up in S3");
+            } else {
+                lookup.expect("new object should have been looked up in S3");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_negative_cache_bounded_and_swept() {
+        let bucket = "test_bucket";
+        let client_config = MockClientConfig {
+            bucket: bucket.to_string(),
+            part_size: 1024 * 1024,
+            ..Default::default()
+        };
+        let client = Arc::new(MockClient::new(client_config));
+
+        let ttl = std::time::Duration::from_millis(50);
+        let superblock = Superblock::new(
+            bucket,
+            &Default::default(),
+            SuperblockConfig {
+                cache_config: CacheConfig {
+                    serve_lookup_from_cache: true,
+                    dir_ttl: ttl,
+                    file_ttl: ttl,
+                    negative_cache_size: 10,
+                    ..Default::default()
+                },
+                s3_personality: S3Personality::Standard,
+                ..Default::default()
+            },
+        );
+
+        for i in 0..100 {
+            let name = format!("missing{i}");
+            _ = superblock
+                .lookup(&client, FUSE_ROOT_INODE, name.as_ref())
+                .await
+                .expect_err("should not exist");
+            assert!(superblock.inner.negative_cache.len() <= 10);
+        }
+        assert_eq!(superblock.inner.negative_cache.len(), 10);
+
+        let head_counter = client.new_counter(Operation::HeadObject);
+        let list_counter = client.new_counter(Operation::ListObjectsV2);
+        _ = superblock
+            .lookup(&client, FUSE_ROOT_INODE, "missing99".as_ref())
+            .await
+            .expect_err("should not exist");
+        assert_eq!((head_counter.count(), list_counter.count()), (0, 0));
+
+        // Sweeping drops every expired entry, without waiting for new inserts to push them out
+        std::thread::sleep(ttl * 2);
+        superblock.inner.sweep_caches();
+        assert_eq!(superblock.inner.negative_cache.len(), 0);
+
+        // So the next lookup asks S3 exactly once, and is cached again
+        for _ in 0..3 {
+            _ = superblock
+                .lookup(&client, FUSE_ROOT_INODE, "missing99".as_ref())
+                .await
+                .expect_err("should not exist");
+        }
+        assert_eq!((head_counter.count(), list_counter.count()), (1, 1));
+        assert_eq!(superblock.inner.negative_cache.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_poll_watched_directory() {
+        let bucket = "test_bucket";
+        let client_config = MockClientConfig {
+            bucket: bucket.to_string(),
+            part_size: 1024 * 1024,
+            ..Default::default()
+        };
+        let client = Arc::new(MockClient::new(client_config));
+        client.add_object("dir/file0.txt", b"hello world".into());
+
+        let ttl = std::time::Duration::from_secs(60 * 60 * 24 * 7);
+        let superblock = Superblock::new(
+            bucket,
+            &Default::default(),
+            SuperblockConfig {
+                cache_config: CacheConfig {
+                    serve_lookup_from_cache: true,
+                    dir_ttl: ttl,
+                    file_ttl: ttl,
+                    ..Default::default()
+                },
+                s3_personality: S3Personality::Standard,
+                ..Default::default()
             },
         );
 
-        let entries = ["file0.txt", "sdir0"];
-        for entry in entries {
-            _ = superblock
-                .lookup(&client, FUSE_ROOT_INODE, entry.as_ref())
-                .await
-                .expect_err("should not exist");
-        }
+        let dir = superblock
+            .lookup(&client, FUSE_ROOT_INODE, "dir".as_ref())
+            .await
+            .expect("dir should exist");
+        let dir_ino = dir.inode.ino();
+        superblock
+            .lookup(&client, dir_ino, "file0.txt".as_ref())
+            .await
+            .expect("file should exist");
+        superblock
+            .lookup(&client, dir_ino, "file1.txt".as_ref())
+            .await
+            .expect_err("file should not exist yet");
+
+        superblock.watch_directory(dir_ino).unwrap();
+        let changes = std::sync::Mutex::new(Vec::new());
+        let on_change = |dir: InodeNo, expired: &[String]| changes.lock().unwrap().push((dir, expired.to_vec()));
 
-        let keys = &[
-            format!("{prefix}file0.txt"),
-            format!("{prefix}sdir0/file0.txt"),
-            format!("{prefix}sdir0/file1.txt"),
-        ];
+        // The first poll only takes the baseline, and then nothing changed, so the directory should
+        // be untouched
+        superblock
+            .inner
+            .poll_watched_directories(client.as_ref(), &on_change)
+            .await;
+        superblock
+            .inner
+            .poll_watched_directories(client.as_ref(), &on_change)
+            .await;
+        let attr = superblock.getattr(&client, dir_ino, false).await.unwrap();
+        assert_eq!(attr.stat.mtime, dir.stat.mtime);
 
-        let object_size = 30;
-        let mut last_modified = OffsetDateTime::UNIX_EPOCH;
-        for key in keys {
-            let mut obj = MockObject::constant(0xaa, object_size, ETag::for_tests());
-            last_modified += Duration::days(1);
-            obj.set_last_modified(last_modified);
-            client.add_object(key, obj);
-        }
+        client.add_object("dir/file1.txt", b"hello world".into());
+        client.remove_object("dir/file0.txt");
+        superblock
+            .inner
+            .poll_watched_directories(client.as_ref(), &on_change)
+            .await;
+        assert_eq!(*changes.lock().unwrap(), [(dir_ino, vec!["file0.txt".to_owned()])]);
 
-        for entry in entries {
-            let lookup = superblock.lookup(&client, FUSE_ROOT_INODE, entry.as_ref()).await;
-            if cached {
-                lookup.expect_err("negative entry should still be valid in the cache, so the new key should not have been looked up in S3");
-            } else {
-                lookup.expect("new object should have been looked up in S3");
-            }
-        }
+        let attr = superblock.getattr(&client, dir_ino, false).await.unwrap();
+        assert!(attr.stat.mtime > dir.stat.mtime, "mtime should have been bumped");
+        superblock
+            .lookup(&client, dir_ino, "file1.txt".as_ref())
+            .await
+            .expect("negative cache entry should have been removed");
+        superblock
+            .lookup(&client, dir_ino, "file0.txt".as_ref())
+            .await
+            .expect_err("cached stat should have been expired");
+
+        // Refreshing the directory from S3 shouldn't reset its mtime
+        let relookup = superblock
+            .lookup(&client, FUSE_ROOT_INODE, "dir".as_ref())
+            .await
+            .expect("dir should exist");
+        assert_eq!(relookup.stat.mtime, attr.stat.mtime);
     }
 
     #[tokio::test]
@@ -1932,7 +3786,15 @@ mod tests {
             InodeKind::File,
             InodeState {
                 write_status: WriteStatus::Remote,
-                stat: InodeStat::for_file(0, OffsetDateTime::now_utc(), None, None, None, Default::default()),
+                stat: InodeStat::for_file(
+                    0,
+                    OffsetDateTime::now_utc(),
+                    None,
+                    None,
+                    None,
+                    Default::default(),
+                    Instant::now(),
+                ),
                 kind_data: InodeKindData::File {},
                 lookup_count: 5,
                 reader_count: 0,
@@ -2005,6 +3867,54 @@ mod tests {
         assert_eq!(lookup_count, 1);
     }
 
+    #[tokio::test]
+    async fn test_forget_multi() {
+        let client_config = MockClientConfig {
+            bucket: "test_bucket".to_string(),
+            part_size: 1024 * 1024,
+            ..Default::default()
+        };
+        let client = Arc::new(MockClient::new(client_config));
+
+        const NUM_FILES: usize = 50;
+        for i in 0..NUM_FILES {
+            client.add_object(&format!("dir/file{i}"), b"foo".into());
+        }
+
+        let superblock = Superblock::new("test_bucket", &Default::default(), Default::default());
+        let inode_count = || superblock.inner.inodes.read().unwrap().map.len();
+
+        // Look up the directory twice, so that one forget doesn't remove it
+        let dir = superblock.lookup(&client, ROOT_INODE_NO, "dir".as_ref()).await.unwrap();
+        superblock.lookup(&client, ROOT_INODE_NO, "dir".as_ref()).await.unwrap();
+        let dir_ino = dir.inode.ino();
+        let mut batch = vec![(dir_ino, 1)];
+        for i in 0..NUM_FILES {
+            let name = format!("file{i}");
+            let lookup = superblock.lookup(&client, dir_ino, name.as_ref()).await.unwrap();
+            batch.push((lookup.inode.ino(), 1));
+        }
+        assert_eq!(inode_count(), NUM_FILES + 2);
+
+        superblock.forget_multi(&batch);
+        assert_eq!(inode_count(), 2, "only the root and the directory should be left");
+        assert_eq!(dir.inode.inner.sync.read().unwrap().lookup_count, 1);
+        let dir_state = dir.inode.get_inode_state().unwrap();
+        let InodeKindData::Directory { children, .. } = &dir_state.kind_data else {
+            panic!("should be a directory");
+        };
+        assert!(
+            children.is_empty(),
+            "forgotten files should be removed from their parent"
+        );
+        drop(dir_state);
+
+        // A parent can be forgotten in the same batch as its children, in any order
+        let lookup = superblock.lookup(&client, dir_ino, "file0".as_ref()).await.unwrap();
+        superblock.forget_multi(&[(dir_ino, 1), (lookup.inode.ino(), 1)]);
+        assert_eq!(inode_count(), 1, "only the root should be left");
+    }
+
     #[tokio::test]
     async fn test_forget_shadowed_inode() {
         let client_config = MockClientConfig {
@@ -2157,6 +4067,257 @@ mod tests {
         }
     }
 
+    #[test_case(true; "ordered")]
+    #[test_case(false; "unordered")]
+    #[tokio::test]
+    async fn test_readdir_object_at_prefix_is_root_marker(ordered: bool) {
+        let client_config = MockClientConfig {
+            bucket: "test_bucket".to_string(),
+            part_size: 1024 * 1024,
+            unordered_list_seed: (!ordered).then_some(123456),
+            ..Default::default()
+        };
+        let client = Arc::new(MockClient::new(client_config));
+
+        // An object with exactly the mount prefix as its key, and the same for a subdirectory
+        client.add_object("data/", MockObject::constant(0u8, 0, ETag::for_tests()));
+        client.add_object("data/file.txt", MockObject::constant(0u8, 10, ETag::for_tests()));
+        client.add_object("data/dir/", MockObject::constant(0u8, 0, ETag::for_tests()));
+        client.add_object("data/dir/nested.txt", MockObject::constant(0u8, 10, ETag::for_tests()));
+
+        let prefix = Prefix::new("data/").expect("valid prefix");
+        let s3_personality = if ordered {
+            S3Personality::Standard
+        } else {
+            S3Personality::ExpressOneZone
+        };
+        let superblock = Superblock::new(
+            "test_bucket",
+            &prefix,
+            SuperblockConfig {
+                s3_personality,
+                ..Default::default()
+            },
+        );
+
+        let dir_handle = superblock.readdir(&client, FUSE_ROOT_INODE, 2).await.unwrap();
+        let mut entries = dir_handle.collect(&client).await.unwrap();
+        entries.sort_by(|a, b| a.inode.name().cmp(b.inode.name()));
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| (entry.inode.name(), entry.inode.kind()))
+                .collect::<Vec<_>>(),
+            vec![("dir", InodeKind::Directory), ("file.txt", InodeKind::File)]
+        );
+
+        let dir_ino = entries[0].inode.ino();
+        let dir_handle = superblock.readdir(&client, dir_ino, 2).await.unwrap();
+        let entries = dir_handle.collect(&client).await.unwrap();
+        assert_eq!(
+            entries.iter().map(|entry| entry.inode.name()).collect::<Vec<_>>(),
+            vec!["nested.txt"]
+        );
+    }
+
+    #[test_case(false; "no marker")]
+    #[test_case(true; "marker")]
+    #[tokio::test]
+    async fn test_readdir_empty_remote_directory(marker: bool) {
+        let client_config = MockClientConfig {
+            bucket: "test_bucket".to_string(),
+            part_size: 1024 * 1024,
+            ..Default::default()
+        };
+        let client = Arc::new(MockClient::new(client_config));
+        if marker {
+            client.add_object("dir/", MockObject::constant(0u8, 0, ETag::for_tests()));
+        }
+        client.add_object("dir/file.txt", MockObject::constant(0u8, 10, ETag::for_tests()));
+
+        // Long TTLs, so only the listing can tell us the directory is gone
+        let ttl = std::time::Duration::from_secs(600);
+        let superblock = Superblock::new(
+            "test_bucket",
+            &Default::default(),
+            SuperblockConfig {
+                cache_config: CacheConfig {
+                    serve_lookup_from_cache: true,
+                    dir_ttl: ttl,
+                    file_ttl: ttl,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        let dir_ino = superblock
+            .lookup(&client, FUSE_ROOT_INODE, "dir".as_ref())
+            .await
+            .expect("should exist")
+            .inode
+            .ino();
+
+        client.remove_object("dir/file.txt");
+        superblock
+            .getattr(&client, dir_ino, false)
+            .await
+            .expect("stat is still cached");
+
+        let dir_handle = superblock.readdir(&client, dir_ino, 2).await.unwrap();
+        let entries = dir_handle.collect(&client).await.unwrap();
+        assert!(entries.is_empty());
+
+        let result = superblock.getattr(&client, dir_ino, false).await;
+        if marker {
+            // The marker keeps the directory in existence even with no children
+            let lookup = result.expect("directory with a marker should still exist");
+            assert_eq!(lookup.inode.kind(), InodeKind::Directory);
+        } else {
+            let err = result.expect_err("directory should be gone");
+            assert!(
+                matches!(err, InodeError::FileDoesNotExist(_, _)),
+                "unexpected error: {err:?}"
+            );
+            superblock
+                .lookup(&client, FUSE_ROOT_INODE, "dir".as_ref())
+                .await
+                .expect_err("directory should be gone");
+        }
+    }
+
+    #[test_case(true; "fresh parent listing")]
+    #[test_case(false; "stale parent listing")]
+    #[tokio::test]
+    async fn test_directory_revalidated_without_head(fresh: bool) {
+        let client_config = MockClientConfig {
+            bucket: "test_bucket".to_string(),
+            part_size: 1024 * 1024,
+            ..Default::default()
+        };
+        let client = Arc::new(MockClient::new(client_config));
+        client.add_object("dir/marker/", MockObject::constant(0u8, 0, ETag::for_tests()));
+        client.add_object("dir/implicit/a.txt", MockObject::constant(0u8, 10, ETag::for_tests()));
+
+        // A zero TTL means the listing is stale as soon as it's done
+        let ttl = if fresh {
+            std::time::Duration::from_secs(600)
+        } else {
+            std::time::Duration::ZERO
+        };
+        let superblock = Superblock::new(
+            "test_bucket",
+            &Default::default(),
+            SuperblockConfig {
+                cache_config: CacheConfig {
+                    serve_lookup_from_cache: true,
+                    dir_ttl: ttl,
+                    file_ttl: ttl,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        let dir_ino = superblock
+            .lookup(&client, FUSE_ROOT_INODE, "dir".as_ref())
+            .await
+            .expect("should exist")
+            .inode
+            .ino();
+        let dir_handle = superblock.readdir(&client, dir_ino, 2).await.unwrap();
+        let entries = dir_handle.collect(&client).await.unwrap();
+        assert_eq!(entries.len(), 2);
+        for entry in &entries {
+            dir_handle.remember(entry);
+        }
+        let implicit_ino = entries
+            .iter()
+            .find(|entry| entry.inode.name() == "implicit")
+            .expect("should list implicit directory")
+            .inode
+            .ino();
+
+        for entry in entries {
+            let head_counter = client.new_counter(Operation::HeadObject);
+            let list_counter = client.new_counter(Operation::ListObjectsV2);
+            // Force the revalidation, as it would be once the directory's own stat expired
+            for _ in 0..3 {
+                let lookup = superblock
+                    .getattr(&client, entry.inode.ino(), true)
+                    .await
+                    .expect("directory should exist");
+                assert_eq!(lookup.inode.ino(), entry.inode.ino());
+            }
+            let expected_lists = if fresh { 0 } else { 3 };
+            assert_eq!(
+                (head_counter.count(), list_counter.count()),
+                (0, expected_lists),
+                "requests for {:?}",
+                entry.inode.name()
+            );
+        }
+
+        // Once a directory is listed empty, the parent's listing no longer vouches for it, and it's
+        // looked up in full when the list probe doesn't find it either
+        client.remove_object("dir/implicit/a.txt");
+        let implicit_handle = superblock.readdir(&client, implicit_ino, 2).await.unwrap();
+        assert!(implicit_handle.collect(&client).await.unwrap().is_empty());
+        let head_counter = client.new_counter(Operation::HeadObject);
+        superblock
+            .getattr(&client, implicit_ino, true)
+            .await
+            .expect_err("directory should be gone");
+        assert_eq!(head_counter.count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_directory_revalidated_once_parent_listing_expires() {
+        let client_config = MockClientConfig {
+            bucket: "test_bucket".to_string(),
+            part_size: 1024 * 1024,
+            ..Default::default()
+        };
+        let client = Arc::new(MockClient::new(client_config));
+        client.add_object("dir/implicit/a.txt", MockObject::constant(0u8, 10, ETag::for_tests()));
+
+        let ttl = std::time::Duration::from_secs(10);
+        let clock = Arc::new(MockClock::new());
+        let superblock = Superblock::new(
+            "test_bucket",
+            &Default::default(),
+            SuperblockConfig {
+                cache_config: CacheConfig {
+                    serve_lookup_from_cache: true,
+                    dir_ttl: ttl,
+                    file_ttl: ttl,
+                    ..Default::default()
+                },
+                clock: clock.clone(),
+                ..Default::default()
+            },
+        );
+        let dir_ino = superblock
+            .lookup(&client, FUSE_ROOT_INODE, "dir".as_ref())
+            .await
+            .expect("should exist")
+            .inode
+            .ino();
+        let dir_handle = superblock.readdir(&client, dir_ino, 2).await.unwrap();
+        let entries = dir_handle.collect(&client).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        dir_handle.remember(&entries[0]);
+        let implicit_ino = entries[0].inode.ino();
+
+        // The listing vouches for the directory until the parent's TTL runs out
+        let list_counter = client.new_counter(Operation::ListObjectsV2);
+        clock.advance(ttl - std::time::Duration::from_secs(1));
+        superblock.getattr(&client, implicit_ino, true).await.unwrap();
+        assert_eq!(list_counter.count(), 0);
+
+        clock.advance(std::time::Duration::from_secs(1));
+        superblock.getattr(&client, implicit_ino, true).await.unwrap();
+        assert_eq!(list_counter.count(), 1);
+    }
+
     #[test_case(""; "unprefixed")]
     #[test_case("test_prefix/"; "prefixed")]
     #[tokio::test]
@@ -2471,6 +4632,64 @@ mod tests {
             .expect_err("Should not be able to get deleted Inode");
     }
 
+    #[test_case(true; "ordered")]
+    #[test_case(false; "unordered")]
+    #[tokio::test]
+    async fn test_readdir_max_buffered_entries(ordered: bool) {
+        const MAX_BUFFERED: usize = 100;
+        const NUM_FILES: usize = 10_000;
+
+        let client_config = MockClientConfig {
+            bucket: "test_bucket".to_string(),
+            part_size: 1024 * 1024,
+            unordered_list_seed: (!ordered).then_some(123456),
+        };
+        let client = Arc::new(MockClient::new(client_config));
+        for i in 0..NUM_FILES {
+            client.add_object(
+                &format!("huge/file{i:05}"),
+                MockObject::constant(0, 1, ETag::for_tests()),
+            );
+        }
+        for i in 0..10 {
+            client.add_object(
+                &format!("huge/dir{i}/file"),
+                MockObject::constant(0, 1, ETag::for_tests()),
+            );
+        }
+
+        let s3_personality = if ordered {
+            S3Personality::Standard
+        } else {
+            S3Personality::ExpressOneZone
+        };
+        let superblock = Superblock::new(
+            "test_bucket",
+            &Default::default(),
+            SuperblockConfig {
+                s3_personality,
+                max_buffered_dir_entries: Some(MAX_BUFFERED),
+                ..Default::default()
+            },
+        );
+        let dir = superblock
+            .lookup(&client, FUSE_ROOT_INODE, "huge".as_ref())
+            .await
+            .unwrap();
+
+        let list_counter = client.new_counter(Operation::ListObjectsV2);
+        let dir_handle = superblock.readdir(&client, dir.inode.ino(), 1000).await.unwrap();
+        let mut entries = 0;
+        while dir_handle.next(&client).await.unwrap().is_some() {
+            entries += 1;
+            let buffered = dir_handle.buffered_entries().await;
+            assert!(buffered < MAX_BUFFERED, "{buffered} entries buffered after {entries}");
+        }
+        assert_eq!(entries, NUM_FILES + 10);
+        // Pages are limited to the cap, rather than the requested page size
+        assert!(list_counter.count() >= (NUM_FILES + 10) / MAX_BUFFERED);
+    }
+
     #[test_case(""; "unprefixed")]
     #[test_case("test_prefix/"; "prefixed")]
     #[tokio::test]
@@ -2580,6 +4799,7 @@ mod tests {
                         None,
                         None,
                         NEVER_EXPIRE_TTL,
+                        superblock.now(),
                     ),
                     write_status: WriteStatus::Remote,
                     kind_data: InodeKindData::File {},
@@ -2774,6 +4994,18 @@ mod tests {
             "dir1/./a",
             MockObject::constant(0xaa, 30, ETag::from_str("test_etag_5").unwrap()),
         );
+        client.add_object(
+            "dir1/..",
+            MockObject::constant(0xaa, 30, ETag::from_str("test_etag_6").unwrap()),
+        );
+        client.add_object(
+            "dir1/../b",
+            MockObject::constant(0xaa, 30, ETag::from_str("test_etag_7").unwrap()),
+        );
+        // Names that only contain dots, or end in one, are fine
+        for key in ["dir1/...", "dir1/foo.", "dir1/..c"] {
+            client.add_object(key, MockObject::constant(0xaa, 30, ETag::for_tests()));
+        }
 
         let superblock = Superblock::new("test_bucket", &Default::default(), Default::default());
         let dir_handle = superblock.readdir(&client, FUSE_ROOT_INODE, 2).await.unwrap();
@@ -2789,16 +5021,23 @@ mod tests {
         let entries = dir_handle.collect(&client).await.unwrap();
         assert_eq!(
             entries.iter().map(|entry| entry.inode.name()).collect::<Vec<_>>(),
-            &["a"]
+            &["...", "..c", "a", "foo."]
         );
 
-        // Neither of these keys should exist in the directory
-        for key in ["/", "."] {
+        // None of these keys should exist in the directory
+        for key in ["/", ".", ".."] {
             let lookup = superblock
                 .lookup(&client, dir1_ino, OsStr::from_bytes(key.as_bytes()))
                 .await;
             assert!(matches!(lookup, Err(InodeError::InvalidFileName(_))));
         }
+        for key in ["...", "foo.", "..c"] {
+            let lookup = superblock
+                .lookup(&client, dir1_ino, OsStr::from_bytes(key.as_bytes()))
+                .await
+                .expect("names with dots should be looked up");
+            assert_eq!(lookup.inode.kind(), InodeKind::File);
+        }
     }
 
     #[test_case(""; "unprefixed")]
@@ -2887,7 +5126,15 @@ mod tests {
                 checksum,
                 sync: RwLock::new(InodeState {
                     write_status: WriteStatus::LocalOpen,
-                    stat: InodeStat::for_file(0, OffsetDateTime::UNIX_EPOCH, None, None, None, Default::default()),
+                    stat: InodeStat::for_file(
+                        0,
+                        OffsetDateTime::UNIX_EPOCH,
+                        None,
+                        None,
+                        None,
+                        Default::default(),
+                        superblock.now(),
+                    ),
                     kind_data: InodeKindData::File {},
                     lookup_count: 5,
                     reader_count: 0,
@@ -2899,7 +5146,7 @@ mod tests {
         // Verify that the stat is invalid
         let inode = superblock.inner.get(ino).unwrap();
         let stat = inode.get_inode_state().unwrap().stat.clone();
-        assert!(!stat.is_valid());
+        assert!(!stat.is_valid(superblock.now()));
 
         // Should be able to reset expiry back and make stat valid when calling setattr
         let atime = OffsetDateTime::UNIX_EPOCH + Duration::days(90);
@@ -2911,20 +5158,20 @@ mod tests {
         let stat = lookup.stat;
         assert_eq!(stat.atime, atime);
         assert_eq!(stat.mtime, mtime);
-        assert!(stat.is_valid());
+        assert!(stat.is_valid(superblock.now()));
     }
 
     #[test]
     fn test_inodestat_constructors() {
         let ts = OffsetDateTime::UNIX_EPOCH + Duration::days(90);
-        let file_inodestat = InodeStat::for_file(128, ts, None, None, None, Default::default());
+        let file_inodestat = InodeStat::for_file(128, ts, None, None, None, Default::default(), Instant::now());
         assert_eq!(file_inodestat.size, 128);
         assert_eq!(file_inodestat.atime, ts);
         assert_eq!(file_inodestat.ctime, ts);
         assert_eq!(file_inodestat.mtime, ts);
 
         let ts = OffsetDateTime::UNIX_EPOCH + Duration::days(180);
-        let file_inodestat = InodeStat::for_directory(ts, Default::default());
+        let file_inodestat = InodeStat::for_directory(ts, Default::default(), Instant::now());
         assert_eq!(file_inodestat.size, 0);
         assert_eq!(file_inodestat.atime, ts);
         assert_eq!(file_inodestat.ctime, ts);
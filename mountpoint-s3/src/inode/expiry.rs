@@ -1,24 +1,72 @@
 use std::time::{Duration, Instant};
 
+/// The longest TTL an [Expiry] tracks. Longer TTLs, up to [Duration::MAX], are clamped to this so
+/// they can't overflow an [Instant], while still never expiring in practice. The kernel is sent the
+/// remaining TTL, so it sees the clamped value too.
+pub const MAX_TTL: Duration = Duration::from_secs(200 * 365 * 24 * 60 * 60);
+
 #[derive(Debug, Clone, Copy)]
 pub struct Expiry(Instant);
 
 impl Expiry {
-    /// Create a new instance with the given TTL starting from now.
-    pub fn from_now(ttl: Duration) -> Self {
-        let expiry = Instant::now()
-            .checked_add(ttl)
+    /// Create a new instance with the given TTL starting from `now`. The TTL keeps its sub-second
+    /// precision, and is clamped to [MAX_TTL].
+    pub fn new(now: Instant, ttl: Duration) -> Self {
+        let expiry = now
+            .checked_add(ttl.min(MAX_TTL))
             .expect("TTL value should not overflow 64-bit time");
         Self(expiry)
     }
 
-    /// The remaining TTL for this instance.
-    pub fn remaining_ttl(&self) -> Duration {
-        self.0.saturating_duration_since(Instant::now())
+    /// The TTL remaining at `now` for this instance, which is zero once it has expired.
+    pub fn remaining_ttl(&self, now: Instant) -> Duration {
+        self.0.saturating_duration_since(now)
+    }
+
+    /// Check whether this instance is expired at `now`. An instance with a zero TTL is expired
+    /// straight away.
+    pub fn is_expired(&self, now: Instant) -> bool {
+        self.0 <= now
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(Duration::ZERO; "zero")]
+    #[test_case(Duration::from_millis(100); "100ms")]
+    #[test_case(Duration::from_secs(1); "1s")]
+    #[test_case(Duration::from_secs(86400); "1 day")]
+    #[test_case(Duration::MAX; "max")]
+    fn test_remaining_ttl(ttl: Duration) {
+        let now = Instant::now();
+        let expected = ttl.min(MAX_TTL);
+        // The TTL keeps any sub-second part
+        assert_eq!(Expiry::new(now, ttl).remaining_ttl(now), expected);
+    }
+
+    #[test]
+    fn test_zero_ttl_is_expired() {
+        let now = Instant::now();
+        let expiry = Expiry::new(now, Duration::ZERO);
+        assert_eq!(expiry.remaining_ttl(now), Duration::ZERO);
+        assert!(expiry.is_expired(now));
     }
 
-    /// Check whether this instance is expired.
-    pub fn is_expired(&self) -> bool {
-        self.0 < Instant::now()
+    #[test]
+    fn test_sub_second_ttl_expires() {
+        let now = Instant::now();
+        let ttl = Duration::from_millis(10);
+        let expiry = Expiry::new(now, ttl);
+        assert!(!expiry.is_expired(now + ttl - Duration::from_nanos(1)));
+        assert_eq!(
+            expiry.remaining_ttl(now + Duration::from_millis(4)),
+            Duration::from_millis(6)
+        );
+        assert!(expiry.is_expired(now + ttl));
+        assert_eq!(expiry.remaining_ttl(now + Duration::from_millis(20)), Duration::ZERO);
     }
 }
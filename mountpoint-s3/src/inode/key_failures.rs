@@ -0,0 +1,236 @@
+//! Memory of keys whose lookups keep failing, so that they aren't looked up in S3 over and over.
+//!
+//! Some lookups fail in a way that asking again won't fix: S3 denies access to the key, or there's
+//! nothing there. Applications that keep trying such a key, like a job polling for a file it can't
+//! read, would otherwise send requests to S3 every time. [KeyFailures] counts each key's failures
+//! in a row, and once enough of them have happened close together, lookups of the key fail the same
+//! way for a cooldown without asking S3. Other failures, like timeouts, throttling, or S3 being
+//! unavailable, might not happen again, and are never remembered.
+
+use std::time::Instant;
+
+use linked_hash_map::LinkedHashMap;
+use tracing::debug;
+
+use crate::clock::Clock;
+use crate::fs::KeyFailureConfig;
+use crate::sync::{Arc, Mutex};
+
+/// A lookup failure that won't go away by asking S3 again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PermanentFailure {
+    /// S3 denied access to the key
+    AccessDenied,
+    /// There's no object or directory at the key
+    NotFound,
+}
+
+impl PermanentFailure {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PermanentFailure::AccessDenied => "access_denied",
+            PermanentFailure::NotFound => "not_found",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    failure: PermanentFailure,
+    /// Failures in a row, all with [Entry::failure]
+    failures: u32,
+    /// When the first of those failures happened
+    first_failure: Instant,
+    /// When lookups of the key go to S3 again, once it has failed enough times
+    cooldown_until: Option<Instant>,
+}
+
+/// Tracks the keys whose lookups keep failing permanently, and fails lookups of them without
+/// asking S3 while they cool down. See the [module docs](self).
+#[derive(Debug)]
+pub struct KeyFailures {
+    config: Option<KeyFailureConfig>,
+    /// Holds keys in order from least to most recently failed
+    entries: Mutex<LinkedHashMap<String, Entry>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl KeyFailures {
+    /// Create a new memory with the given config, or one that never remembers anything if `None`.
+    /// Windows and cooldowns are timed by `clock`.
+    pub fn new(config: Option<KeyFailureConfig>, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            config,
+            entries: Mutex::new(LinkedHashMap::new()),
+            clock,
+        }
+    }
+
+    /// The failure a lookup of `key` should report without asking S3, if the key is cooling down
+    pub fn check(&self, key: &str) -> Option<PermanentFailure> {
+        self.config.as_ref()?;
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.get(key)?;
+        if entry.cooldown_until? <= self.clock.now() {
+            return None;
+        }
+        metrics::counter!("metadata_cache.key_failures.short_circuited", "failure" => entry.failure.as_str())
+            .increment(1);
+        Some(entry.failure)
+    }
+
+    /// Record that a lookup of `key` failed permanently. Once enough lookups in a row have failed
+    /// within the window, the key cools down. A failure of the first lookup after a cooldown
+    /// starts another one straight away.
+    pub fn fail(&self, key: &str, failure: PermanentFailure) {
+        let Some(config) = &self.config else {
+            return;
+        };
+        let now = self.clock.now();
+        let mut entries = self.entries.lock().unwrap();
+        let continues_streak = entries.get(key).is_some_and(|entry| {
+            entry.failure == failure
+                && (entry.cooldown_until.is_some() || now.duration_since(entry.first_failure) <= config.window)
+        });
+        if !continues_streak {
+            let entry = Entry {
+                failure,
+                failures: 0,
+                first_failure: now,
+                cooldown_until: None,
+            };
+            entries.insert(key.to_owned(), entry);
+        }
+        let entry = entries.get_refresh(key).expect("entry was just found or inserted");
+        entry.failures = entry.failures.saturating_add(1);
+        if entry.failures >= config.failure_threshold {
+            debug!(
+                key,
+                ?failure,
+                failures = entry.failures,
+                "lookups keep failing, not asking S3 again for {:?}",
+                config.cooldown
+            );
+            entry.cooldown_until = Some(now + config.cooldown);
+            metrics::counter!("metadata_cache.key_failures.cooldowns", "failure" => failure.as_str()).increment(1);
+        }
+        while entries.len() > config.max_keys {
+            entries.pop_front();
+        }
+    }
+
+    /// Forget the failures of `key`, because a lookup of it succeeded or it has changed
+    pub fn remove(&self, key: &str) {
+        if self.config.is_none() {
+            return;
+        }
+        self.entries.lock().unwrap().remove(key);
+    }
+
+    /// Forget the failures of every key starting with `prefix`
+    pub fn remove_prefix(&self, prefix: &str) {
+        if self.config.is_none() {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        let keys = entries
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in keys {
+            entries.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::clock::{MockClock, SystemClock};
+
+    use super::*;
+
+    fn key_failures(failure_threshold: u32, window: Duration, cooldown: Duration) -> (KeyFailures, Arc<MockClock>) {
+        let clock = Arc::new(MockClock::new());
+        let config = KeyFailureConfig {
+            failure_threshold,
+            window,
+            cooldown,
+            max_keys: 2,
+        };
+        (KeyFailures::new(Some(config), clock.clone()), clock)
+    }
+
+    #[test]
+    fn test_cools_down_after_threshold() {
+        let (failures, _) = key_failures(3, Duration::from_secs(60), Duration::from_secs(60));
+        for _ in 0..2 {
+            failures.fail("a", PermanentFailure::AccessDenied);
+            assert_eq!(failures.check("a"), None);
+        }
+        failures.fail("a", PermanentFailure::AccessDenied);
+        assert_eq!(failures.check("a"), Some(PermanentFailure::AccessDenied));
+        assert_eq!(failures.check("b"), None);
+
+        failures.remove("a");
+        assert_eq!(failures.check("a"), None);
+    }
+
+    #[test]
+    fn test_different_failures_restart_count() {
+        let (failures, _) = key_failures(2, Duration::from_secs(60), Duration::from_secs(60));
+        failures.fail("a", PermanentFailure::AccessDenied);
+        failures.fail("a", PermanentFailure::NotFound);
+        assert_eq!(failures.check("a"), None);
+        failures.fail("a", PermanentFailure::NotFound);
+        assert_eq!(failures.check("a"), Some(PermanentFailure::NotFound));
+    }
+
+    #[test]
+    fn test_failures_outside_window_restart_count() {
+        let (failures, clock) = key_failures(2, Duration::from_secs(1), Duration::from_secs(60));
+        failures.fail("a", PermanentFailure::NotFound);
+        clock.advance(Duration::from_secs(2));
+        failures.fail("a", PermanentFailure::NotFound);
+        assert_eq!(failures.check("a"), None);
+    }
+
+    #[test]
+    fn test_failure_after_cooldown_cools_down_again() {
+        let cooldown = Duration::from_secs(60);
+        let (failures, clock) = key_failures(2, Duration::from_secs(60), cooldown);
+        failures.fail("a", PermanentFailure::AccessDenied);
+        failures.fail("a", PermanentFailure::AccessDenied);
+        assert_eq!(failures.check("a"), Some(PermanentFailure::AccessDenied));
+
+        clock.advance(cooldown);
+        assert_eq!(failures.check("a"), None);
+        failures.fail("a", PermanentFailure::AccessDenied);
+        assert_eq!(failures.check("a"), Some(PermanentFailure::AccessDenied));
+    }
+
+    #[test]
+    fn test_bounded() {
+        let (failures, _) = key_failures(1, Duration::from_secs(60), Duration::from_secs(60));
+        for key in ["a", "b", "c"] {
+            failures.fail(key, PermanentFailure::NotFound);
+        }
+        assert_eq!(failures.check("a"), None, "least recently failed key is forgotten");
+        assert_eq!(failures.check("b"), Some(PermanentFailure::NotFound));
+        assert_eq!(failures.check("c"), Some(PermanentFailure::NotFound));
+
+        failures.remove_prefix("");
+        assert_eq!(failures.check("c"), None);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let failures = KeyFailures::new(None, Arc::new(SystemClock));
+        for _ in 0..10 {
+            failures.fail("a", PermanentFailure::AccessDenied);
+        }
+        assert_eq!(failures.check("a"), None);
+    }
+}
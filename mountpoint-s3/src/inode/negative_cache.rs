@@ -1,21 +1,45 @@
+use std::mem::size_of;
 use std::time::{Duration, Instant};
 
 use linked_hash_map::LinkedHashMap;
 
 use super::{expiry::Expiry, InodeNo};
 
-use crate::sync::RwLock;
+use crate::clock::Clock;
+use crate::metrics::GaugeShare;
+use crate::sync::{Arc, Mutex, RwLock};
+
+/// Most hits [NegativeCache::contains] keeps track of before it takes the write lock to apply them
+const MAX_PENDING_HITS: usize = 1024;
 
 /// A caches for negative lookups.
-/// Maintains a bounded set of (parent_ino, child_name) entries that expire after a fixed time.
+/// Maintains a bounded set of (parent_ino, child_name) entries that expire after the TTL they were
+/// inserted with. When the cache is full, the least recently used entries are evicted first.
 #[derive(Debug)]
 pub struct NegativeCache {
-    /// Holds keys in expiration order from oldest to newest.
-    map: RwLock<LinkedHashMap<Key, Expiry>>,
-    /// Upper bound for the cache.
+    entries: RwLock<Entries>,
+    /// Keys found by [contains](Self::contains) that haven't been made the most recently used yet,
+    /// oldest first. Lookups only take the read lock on `entries`, and these are applied the next
+    /// time something takes the write lock to insert.
+    pending_hits: Mutex<Vec<Key>>,
+    /// Upper bound for the number of entries in the cache.
     max_size: usize,
-    /// TTL of a key at insertion.
-    ttl: Duration,
+    /// Upper bound for the approximate memory used by the cache's entries, if any.
+    max_bytes: Option<usize>,
+    /// Source of the current time that entries expire by
+    clock: Arc<dyn Clock>,
+}
+
+#[derive(Debug)]
+struct Entries {
+    /// Holds keys in order from least to most recently used.
+    map: LinkedHashMap<Key, Expiry>,
+    /// Approximate memory used by the entries in `map`, see [Key::size].
+    bytes: usize,
+    /// This cache's part of the entry and memory gauges, which other file systems in the same
+    /// process add to too
+    entries_gauge: GaugeShare,
+    bytes_gauge: GaugeShare,
 }
 
 #[derive(Debug, Hash, PartialEq, Eq)]
@@ -24,17 +48,62 @@ struct Key {
     child_name: String,
 }
 
+impl Key {
+    /// Approximate memory used by an entry with this key, including the name and expiry
+    fn size(&self) -> usize {
+        size_of::<Self>() + size_of::<Expiry>() + self.child_name.len()
+    }
+}
+
+impl Entries {
+    fn remove(&mut self, key: &Key) -> bool {
+        if self.map.remove(key).is_some() {
+            self.bytes -= key.size();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn pop_front(&mut self) -> Option<Expiry> {
+        let (key, expiry) = self.map.pop_front()?;
+        self.bytes -= key.size();
+        Some(expiry)
+    }
+
+    fn update_gauges(&mut self) {
+        self.entries_gauge.set(self.map.len() as f64);
+        self.bytes_gauge.set(self.bytes as f64);
+    }
+
+    /// Make the entries for the given keys the most recently used, in order. Keys that have been
+    /// removed since are ignored.
+    fn apply_hits(&mut self, hits: Vec<Key>) {
+        for key in hits {
+            _ = self.map.get_refresh(&key);
+        }
+    }
+}
+
 impl NegativeCache {
-    pub fn new(max_size: usize, ttl: Duration) -> Self {
+    pub fn new(max_size: usize, max_bytes: Option<usize>, clock: Arc<dyn Clock>) -> Self {
         Self {
-            map: RwLock::new(Default::default()),
+            entries: RwLock::new(Entries {
+                map: Default::default(),
+                bytes: 0,
+                entries_gauge: GaugeShare::new("metadata_cache.negative_cache.entries"),
+                bytes_gauge: GaugeShare::new("metadata_cache.negative_cache.bytes"),
+            }),
+            pending_hits: Mutex::new(Vec::new()),
             max_size,
-            ttl,
+            max_bytes,
+            clock,
         }
     }
 
     /// Check whether the cache contains a **current** entry for the given
-    /// (`parent_ino`, `child_name`) pair.
+    /// (`parent_ino`, `child_name`) pair. A hit makes the entry the most recently used, though only
+    /// once it's applied before the next insert.
     pub fn contains(&self, parent_ino: InodeNo, child_name: &str) -> bool {
         let key = Key {
             parent_ino,
@@ -42,11 +111,22 @@ impl NegativeCache {
         };
         let start = Instant::now();
         let contains_current = self
-            .map
+            .entries
             .read()
             .unwrap()
+            .map
             .get(&key)
-            .is_some_and(|expiry| !expiry.is_expired());
+            .is_some_and(|expiry| !expiry.is_expired(self.clock.now()));
+        if contains_current {
+            let mut pending_hits = self.pending_hits.lock().unwrap();
+            pending_hits.push(key);
+            // Don't let the hits pile up if nothing is inserted for a while
+            if pending_hits.len() >= MAX_PENDING_HITS {
+                let hits = std::mem::take(&mut *pending_hits);
+                drop(pending_hits);
+                self.entries.write().unwrap().apply_hits(hits);
+            }
+        }
         metrics::histogram!(
             "metadata_cache.negative_cache.operation_duration_us",
             "op" => "contains",
@@ -56,6 +136,16 @@ impl NegativeCache {
         contains_current
     }
 
+    /// Check whether the cache contains an entry for the given (`parent_ino`, `child_name`) pair,
+    /// even one that has expired but hasn't been removed yet. Doesn't count as a hit.
+    pub fn remembers(&self, parent_ino: InodeNo, child_name: &str) -> bool {
+        let key = Key {
+            parent_ino,
+            child_name: child_name.to_owned(),
+        };
+        self.entries.read().unwrap().map.contains_key(&key)
+    }
+
     /// Remove an entry from the cache. If the entry was not present, this is a no-op.
     pub fn remove(&self, parent_ino: InodeNo, child_name: &str) {
         let key = Key {
@@ -63,9 +153,9 @@ impl NegativeCache {
             child_name: child_name.to_owned(),
         };
         let start = Instant::now();
-        let mut map = self.map.write().unwrap();
-        if map.remove(&key).is_some() {
-            metrics::gauge!("metadata_cache.negative_cache.entries").set(map.len() as f64);
+        let mut entries = self.entries.write().unwrap();
+        if entries.remove(&key) {
+            entries.update_gauges();
         }
         metrics::histogram!(
             "metadata_cache.negative_cache.operation_duration_us",
@@ -74,34 +164,68 @@ impl NegativeCache {
         .record(start.elapsed().as_micros() as f64);
     }
 
-    /// Insert an entry into the cache. If the entry already existed,
-    /// update its TTL.
-    /// Upon insertion, remove entries that exceed the cache limit or
+    /// Remove all the entries for children of the given parent.
+    pub fn remove_parent(&self, parent_ino: InodeNo) {
+        let start = Instant::now();
+        let mut entries = self.entries.write().unwrap();
+        let keys = entries
+            .map
+            .keys()
+            .filter(|key| key.parent_ino == parent_ino)
+            .map(|key| Key {
+                parent_ino,
+                child_name: key.child_name.clone(),
+            })
+            .collect::<Vec<_>>();
+        if !keys.is_empty() {
+            for key in keys {
+                entries.remove(&key);
+            }
+            entries.update_gauges();
+        }
+        metrics::histogram!(
+            "metadata_cache.negative_cache.operation_duration_us",
+            "op" => "remove_parent",
+        )
+        .record(start.elapsed().as_micros() as f64);
+    }
+
+    /// Insert an entry into the cache that expires after `ttl`. If the entry already existed,
+    /// update its TTL and make it the most recently used.
+    /// Upon insertion, remove entries that exceed the cache limits or
     /// that have already expired.
-    pub fn insert(&self, parent_ino: InodeNo, child_name: &str) {
-        let expiry = Expiry::from_now(self.ttl);
+    pub fn insert(&self, parent_ino: InodeNo, child_name: &str, ttl: Duration) {
+        let now = self.clock.now();
+        let expiry = Expiry::new(now, ttl);
         let key = Key {
             parent_ino,
             child_name: child_name.to_owned(),
         };
+        let size = key.size();
         let start = Instant::now();
-        let mut map = self.map.write().unwrap();
-        if map.insert(key, expiry).is_none() {
-            // Remove entries that have expired.
-            while map.front().is_some_and(|(_, e)| e.is_expired()) {
-                _ = map.pop_front();
+        let mut entries = self.entries.write().unwrap();
+        let hits = std::mem::take(&mut *self.pending_hits.lock().unwrap());
+        entries.apply_hits(hits);
+        if entries.map.insert(key, expiry).is_none() {
+            entries.bytes += size;
+
+            // Remove entries that have expired. Entries inserted with different TTLs don't expire in
+            // insertion order, so this may leave some expired entries behind until they're evicted
+            // or swept.
+            while entries.map.front().is_some_and(|(_, e)| e.is_expired(now)) {
+                _ = entries.pop_front();
             }
 
-            // Remove entries that exceed the limit.
-            while map.len() > self.max_size {
-                let Some((_, e)) = map.pop_front() else {
+            // Remove entries that exceed the limits.
+            while entries.map.len() > self.max_size || self.max_bytes.is_some_and(|max| entries.bytes > max) {
+                let Some(e) = entries.pop_front() else {
                     break;
                 };
                 // Report how many entries are evicted while still current.
                 metrics::counter!("metadata_cache.negative_cache.entries_evicted_before_expiry")
-                    .increment((!e.is_expired()).into());
+                    .increment((!e.is_expired(now)).into());
             }
-            metrics::gauge!("metadata_cache.negative_cache.entries").set(map.len() as f64);
+            entries.update_gauges();
         }
 
         metrics::histogram!(
@@ -110,20 +234,65 @@ impl NegativeCache {
         )
         .record(start.elapsed().as_micros() as f64);
     }
+
+    /// Remove every expired entry, rather than waiting for them to be evicted by later inserts.
+    /// Returns the number of entries removed.
+    pub fn sweep(&self) -> usize {
+        let start = Instant::now();
+        let now = self.clock.now();
+        let mut entries = self.entries.write().unwrap();
+        let expired = entries
+            .map
+            .iter()
+            .filter(|(_, expiry)| expiry.is_expired(now))
+            .map(|(key, _)| Key {
+                parent_ino: key.parent_ino,
+                child_name: key.child_name.clone(),
+            })
+            .collect::<Vec<_>>();
+        for key in &expired {
+            entries.remove(key);
+        }
+        if !expired.is_empty() {
+            entries.update_gauges();
+        }
+        metrics::histogram!(
+            "metadata_cache.negative_cache.operation_duration_us",
+            "op" => "sweep",
+        )
+        .record(start.elapsed().as_micros() as f64);
+        expired.len()
+    }
+
+    /// The number of entries in the cache, including expired ones that haven't been removed yet
+    pub fn len(&self) -> usize {
+        self.entries.read().unwrap().map.len()
+    }
+
+    /// The approximate memory used by the cache's entries
+    pub fn bytes(&self) -> usize {
+        self.entries.read().unwrap().bytes
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::thread::sleep;
-    use std::time::{Duration, Instant};
+    use std::time::Duration;
 
-    use super::NegativeCache;
+    use super::{Key, NegativeCache, MAX_PENDING_HITS};
+    use crate::clock::{MockClock, SystemClock};
+    use crate::sync::Arc;
+
+    fn new_cache(max_size: usize, max_bytes: Option<usize>) -> NegativeCache {
+        NegativeCache::new(max_size, max_bytes, Arc::new(SystemClock))
+    }
 
     #[test]
     fn test_contains() {
-        let cache = NegativeCache::new(100, Duration::from_secs(60));
+        let ttl = Duration::from_secs(60);
+        let cache = new_cache(100, None);
 
-        cache.insert(1, "child1");
+        cache.insert(1, "child1", ttl);
         assert!(cache.contains(1, "child1"));
         assert!(!cache.contains(1, "child2"));
         assert!(!cache.contains(2, "child1"));
@@ -131,16 +300,17 @@ mod tests {
 
     #[test]
     fn test_insert() {
-        let cache = NegativeCache::new(100, Duration::from_secs(60));
+        let ttl = Duration::from_secs(60);
+        let cache = new_cache(100, None);
 
-        cache.insert(1, "child1");
+        cache.insert(1, "child1", ttl);
         assert!(cache.contains(1, "child1"));
 
-        cache.insert(1, "child2");
+        cache.insert(1, "child2", ttl);
         assert!(cache.contains(1, "child2"));
         assert!(cache.contains(1, "child1"));
 
-        cache.insert(2, "child1");
+        cache.insert(2, "child1", ttl);
         assert!(cache.contains(2, "child1"));
         assert!(cache.contains(1, "child2"));
         assert!(cache.contains(1, "child1"));
@@ -148,11 +318,12 @@ mod tests {
 
     #[test]
     fn test_remove() {
-        let cache = NegativeCache::new(100, Duration::from_secs(60));
+        let ttl = Duration::from_secs(60);
+        let cache = new_cache(100, None);
 
-        cache.insert(1, "child1");
-        cache.insert(1, "child2");
-        cache.insert(2, "child1");
+        cache.insert(1, "child1", ttl);
+        cache.insert(1, "child2", ttl);
+        cache.insert(2, "child1", ttl);
         assert!(cache.contains(1, "child1"));
         assert!(cache.contains(1, "child2"));
         assert!(cache.contains(2, "child1"));
@@ -163,66 +334,178 @@ mod tests {
         assert!(cache.contains(2, "child1"));
     }
 
+    #[test]
+    fn test_remove_parent() {
+        let ttl = Duration::from_secs(60);
+        let cache = new_cache(100, None);
+
+        cache.insert(1, "child1", ttl);
+        cache.insert(1, "child2", ttl);
+        cache.insert(2, "child1", ttl);
+
+        cache.remove_parent(1);
+        assert!(!cache.contains(1, "child1"));
+        assert!(!cache.contains(1, "child2"));
+        assert!(cache.contains(2, "child1"));
+    }
+
     #[test]
     fn test_max_size() {
-        let cache = NegativeCache::new(2, Duration::from_secs(60));
+        let ttl = Duration::from_secs(60);
+        let cache = new_cache(2, None);
 
-        cache.insert(1, "child1");
+        cache.insert(1, "child1", ttl);
         assert!(cache.contains(1, "child1"));
 
-        cache.insert(1, "child2");
+        cache.insert(1, "child2", ttl);
         assert!(cache.contains(1, "child2"));
         assert!(cache.contains(1, "child1"));
 
-        cache.insert(1, "child3");
+        // child1 was used more recently than child2
+        cache.insert(1, "child3", ttl);
         assert!(cache.contains(1, "child3"));
-        assert!(cache.contains(1, "child2"));
-        assert!(!cache.contains(1, "child1"));
+        assert!(!cache.contains(1, "child2"));
+        assert!(cache.contains(1, "child1"));
     }
 
     #[test]
     fn test_expiration() {
-        let cache = NegativeCache::new(100, Duration::from_millis(1));
+        let ttl = Duration::from_secs(1);
+        let clock = Arc::new(MockClock::new());
+        let cache = NegativeCache::new(100, None, clock.clone());
 
-        cache.insert(1, "child1");
-        sleep(Duration::from_millis(2));
+        cache.insert(1, "child1", ttl);
+        clock.advance(ttl - Duration::from_millis(1));
+        assert!(cache.contains(1, "child1"));
+        clock.advance(Duration::from_millis(1));
         assert!(!cache.contains(1, "child1"));
     }
 
+    #[test]
+    fn test_expiration_with_different_ttls() {
+        let clock = Arc::new(MockClock::new());
+        let cache = NegativeCache::new(100, None, clock.clone());
+
+        cache.insert(1, "long", Duration::from_secs(60));
+        cache.insert(1, "short", Duration::from_millis(1));
+        clock.advance(Duration::from_millis(2));
+        assert!(cache.contains(1, "long"));
+        assert!(!cache.contains(1, "short"));
+    }
+
     #[test]
     fn test_insert_after_expiry() {
-        let cache = NegativeCache::new(100, Duration::from_millis(50));
+        let ttl = Duration::from_millis(50);
+        let clock = Arc::new(MockClock::new());
+        let cache = NegativeCache::new(100, None, clock.clone());
 
-        cache.insert(1, "child1");
-        sleep(Duration::from_millis(100));
+        cache.insert(1, "child1", ttl);
+        clock.advance(Duration::from_millis(100));
         assert!(!cache.contains(1, "child1"));
 
-        cache.insert(1, "child1");
+        cache.insert(1, "child1", ttl);
         assert!(cache.contains(1, "child1"));
     }
 
     #[test]
     fn test_insert_resets_ttl() {
         let ttl = Duration::from_millis(100);
-        let cache = NegativeCache::new(100, ttl);
-
-        cache.insert(1, "child1");
-        let inserted_time = Instant::now();
-        // Wait for about half ttl, verify the entry has not expirted yet.
-        let half_ttl = ttl / 2;
-        while Instant::now().saturating_duration_since(inserted_time) <= half_ttl {
-            sleep(Duration::from_millis(1));
-        }
-        assert!(Instant::now().saturating_duration_since(inserted_time) < ttl);
+        let clock = Arc::new(MockClock::new());
+        let cache = NegativeCache::new(100, None, clock.clone());
+
+        cache.insert(1, "child1", ttl);
+        clock.advance(ttl / 2);
+        assert!(cache.contains(1, "child1"));
+
+        // Past the initial insert's expiry, but not the reset one's
+        cache.insert(1, "child1", ttl);
+        clock.advance(ttl - Duration::from_millis(1));
+        assert!(cache.contains(1, "child1"));
+        clock.advance(Duration::from_millis(1));
+        assert!(!cache.contains(1, "child1"));
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let ttl = Duration::from_secs(60);
+        let cache = new_cache(2, None);
+
+        cache.insert(1, "child1", ttl);
+        cache.insert(1, "child2", ttl);
+        // Using child1 makes child2 the least recently used
         assert!(cache.contains(1, "child1"));
 
-        cache.insert(1, "child1");
-        let reset_time = Instant::now();
-        // Wait until the initial insert has expired, but the reset has not.
-        while Instant::now().saturating_duration_since(inserted_time) <= ttl {
-            sleep(Duration::from_millis(1));
+        cache.insert(1, "child3", ttl);
+        assert!(cache.contains(1, "child1"));
+        assert!(!cache.contains(1, "child2"));
+        assert!(cache.contains(1, "child3"));
+    }
+
+    #[test]
+    fn test_lru_eviction_many_hits() {
+        let ttl = Duration::from_secs(60);
+        let cache = new_cache(3, None);
+
+        cache.insert(1, "child1", ttl);
+        cache.insert(1, "child2", ttl);
+        cache.insert(1, "child3", ttl);
+        // More hits than are kept pending, so some are applied before the next insert
+        for _ in 0..MAX_PENDING_HITS {
+            assert!(cache.contains(1, "child2"));
         }
-        assert!(Instant::now().saturating_duration_since(reset_time) < ttl);
         assert!(cache.contains(1, "child1"));
+
+        // child3 is now the least recently used
+        cache.insert(1, "child4", ttl);
+        assert!(cache.contains(1, "child1"));
+        assert!(cache.contains(1, "child2"));
+        assert!(!cache.contains(1, "child3"));
+        assert!(cache.contains(1, "child4"));
+    }
+
+    #[test]
+    fn test_max_bytes() {
+        let ttl = Duration::from_secs(60);
+        let entry_size = Key {
+            parent_ino: 1,
+            child_name: "child0".to_owned(),
+        }
+        .size();
+        let cache = new_cache(1000, Some(10 * entry_size));
+
+        for i in 0..100 {
+            cache.insert(1, &format!("child{i}"), ttl);
+            assert!(cache.bytes() <= 10 * entry_size);
+        }
+        assert_eq!(cache.len(), 10);
+        assert!(cache.contains(1, "child99"));
+        assert!(!cache.contains(1, "child89"));
+
+        // Removing entries frees their bytes
+        for i in 90..100 {
+            cache.remove(1, &format!("child{i}"));
+        }
+        assert_eq!(cache.len(), 0);
+        assert_eq!(cache.bytes(), 0);
+    }
+
+    #[test]
+    fn test_sweep() {
+        let clock = Arc::new(MockClock::new());
+        let cache = NegativeCache::new(100, None, clock.clone());
+
+        // Expired entries behind a current one aren't removed by inserts
+        cache.insert(1, "long", Duration::from_secs(60));
+        cache.insert(1, "short1", Duration::from_millis(1));
+        cache.insert(1, "short2", Duration::from_millis(1));
+        clock.advance(Duration::from_millis(2));
+        cache.insert(1, "other", Duration::from_secs(60));
+        assert_eq!(cache.len(), 4);
+
+        assert_eq!(cache.sweep(), 2);
+        assert_eq!(cache.len(), 2);
+        assert!(cache.contains(1, "long"));
+        assert!(cache.contains(1, "other"));
+        assert_eq!(cache.sweep(), 0);
     }
 }
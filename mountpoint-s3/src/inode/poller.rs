@@ -0,0 +1,75 @@
+//! Background polling of watched directories for remote changes.
+//!
+//! S3 has no way to notify us when objects are added or removed, so applications that wait for
+//! a directory to change would otherwise never see a change until its cached metadata expires and
+//! they happen to list it again. When enabled, the [DirectoryPoller] periodically lists every
+//! watched directory (see [Superblock::watch_directory](super::Superblock::watch_directory)) and,
+//! when the listing changes, bumps the directory's mtime and invalidates the cached metadata of
+//! its children. The change is then reported to a callback, which the file system uses to tell
+//! the kernel to drop what it cached about the directory.
+//!
+//! Another thread periodically sweeps expired entries out of the metadata caches, which otherwise
+//! only drop them when they're looked up or pushed out by new entries.
+
+use std::ops::ControlFlow;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::time::Duration;
+
+use futures::executor::block_on;
+use mountpoint_s3_client::ObjectClient;
+use tracing::{error, trace};
+
+use crate::logging::panic_message;
+use crate::periodic::PeriodicTask;
+use crate::sync::Arc;
+
+use super::{InodeNo, SuperblockInner};
+
+/// Handle to the background threads polling watched directories and sweeping the metadata caches.
+/// The threads are shut down when the handle is dropped.
+#[derive(Debug)]
+pub struct DirectoryPoller {
+    _poll: Option<PeriodicTask>,
+    _sweep: Option<PeriodicTask>,
+}
+
+impl DirectoryPoller {
+    pub(super) fn new<OC>(
+        inner: Arc<SuperblockInner>,
+        client: Arc<OC>,
+        poll_interval: Option<Duration>,
+        sweep_interval: Option<Duration>,
+        on_change: impl Fn(InodeNo, &[String]) + Send + 'static,
+    ) -> Self
+    where
+        OC: ObjectClient + Send + Sync + 'static,
+    {
+        let poll = poll_interval.map(|interval| {
+            let inner = inner.clone();
+            PeriodicTask::start("directory-poller", interval, move || {
+                trace!("polling watched directories");
+                // Keep polling after a panic, rather than silently losing change notifications
+                let result = catch_unwind(AssertUnwindSafe(|| {
+                    block_on(inner.poll_watched_directories(client.as_ref(), &on_change))
+                }));
+                if let Err(payload) = result {
+                    error!(message = panic_message(payload.as_ref()), "directory poller panicked");
+                    metrics::counter!("metadata_cache.directory_poll.panics").increment(1);
+                }
+                ControlFlow::Continue(())
+            })
+            .expect("failed to spawn directory poller thread")
+        });
+        let sweep = sweep_interval.map(|interval| {
+            PeriodicTask::start("metadata-cache-sweeper", interval, move || {
+                inner.sweep_caches();
+                ControlFlow::Continue(())
+            })
+            .expect("failed to spawn metadata cache sweeper thread")
+        });
+        Self {
+            _poll: poll,
+            _sweep: sweep,
+        }
+    }
+}
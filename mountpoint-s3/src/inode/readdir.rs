@@ -34,18 +34,34 @@
 //! * [LocalIter] is an iterator over [ReaddirEntry]s that are local children of the directory.
 //!   These children are listed only once, at the start of the readdir operation, and so are a
 //!   snapshot in time of the directory.
+//!
+//! Directories under one of the configured pinned prefixes are only listed once: the complete
+//! remote listing is kept in [PinnedListings], and later [RemoteIter]s replay it instead of calling
+//! ListObjectsV2 again. Similarly, [BootstrapListings] loaded from a listing manifest are replayed
+//! by the first [RemoteIter] of each directory they cover.
+//!
+//! A stream can also resume after a given entry name, for example one checkpointed by an earlier
+//! process. Its [RemoteIter] then starts listing just after that name with ListObjectsV2's
+//! `start-after`, and every entry up to and including the name is skipped. Resumed streams always
+//! list from S3, since the listings kept for pinned directories and loaded from a manifest cover
+//! the whole directory.
 
 use std::cmp::Ordering;
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::mem::size_of;
 
+use linked_hash_map::LinkedHashMap;
 use mountpoint_s3_client::types::ObjectInfo;
 use mountpoint_s3_client::ObjectClient;
 use tracing::{error, trace, warn};
 
+use crate::fs::{CircuitBreaker, CircuitBreakerOpen, RewindMode};
+use crate::metrics::GaugeShare;
 use crate::sync::{Arc, AsyncMutex, Mutex};
 
 use super::{
-    valid_inode_name, InodeError, InodeKind, InodeKindData, InodeNo, InodeStat, LookedUp, RemoteLookup, SuperblockInner,
+    valid_inode_name, InodeError, InodeKind, InodeKindData, InodeNo, InodeStat, ListingSnapshot, LookedUp,
+    RemoteLookup, SuperblockInner,
 };
 
 /// Handle for an inflight directory listing
@@ -56,6 +72,10 @@ pub struct ReaddirHandle {
     parent_ino: InodeNo,
     iter: AsyncMutex<ReaddirIter>,
     readded: Mutex<Option<LookedUp>>,
+    /// Skip files, and only return subdirectories
+    dirs_only: bool,
+    /// The directory's key, if it's pinned and so its complete listing should be kept
+    pinned_key: Option<String>,
 }
 
 impl ReaddirHandle {
@@ -65,37 +85,54 @@ impl ReaddirHandle {
         parent_ino: InodeNo,
         full_path: String,
         page_size: usize,
+        dirs_only: bool,
+        start_after: Option<String>,
     ) -> Result<Self, InodeError> {
-        let local_entries = {
-            let inode = inner.get(dir_ino)?;
-            let kind_data = &inode.get_inode_state()?.kind_data;
-            let local_files = match kind_data {
-                InodeKindData::File { .. } => return Err(InodeError::NotADirectory(inode.err())),
-                InodeKindData::Directory { writing_children, .. } => writing_children.iter().map(|ino| {
-                    let inode = inner.get(*ino)?;
-                    let stat = inode.get_inode_state()?.stat.clone();
-                    Ok(ReaddirEntry::LocalInode {
-                        lookup: LookedUp { inode, stat },
-                    })
-                }),
-            };
+        let mut local_entries = Self::local_entries(&inner, dir_ino)?;
+        if let Some(start_after) = &start_after {
+            local_entries.retain(|entry| entry.name() > start_after.as_str());
+        }
 
-            match local_files.collect::<Result<Vec<_>, _>>() {
-                Ok(mut new_results) => {
-                    new_results.sort();
-                    new_results
-                }
-                Err(e) => {
-                    error!(error=?e, "readdir failed listing local files");
-                    return Err(e);
-                }
-            }
+        // Only keep the entries we've already returned around if we might need to replay them
+        let retain_snapshot = inner.config.readdir_rewind_mode == RewindMode::Snapshot;
+        let pinned = start_after.is_none() && inner.is_pinned(&full_path);
+        // Never list more entries at once than we're willing to hold on to. We only ask for the
+        // next page once every entry of the last one has been returned, so this bounds how many
+        // remote entries the handle holds at a time. Dirs-only handles drop the files of each page
+        // as it's listed, so they can list full pages.
+        let page_size = match inner.config.max_buffered_dir_entries {
+            Some(max_buffered) if !dirs_only => page_size.min(max_buffered.max(1)),
+            _ => page_size,
         };
-
-        let iter = if inner.config.s3_personality.is_list_ordered() {
-            ReaddirIter::ordered(&inner.bucket, &full_path, page_size, local_entries.into())
+        let iter = if inner.is_beyond_listing_depth(&full_path) {
+            trace!(dir=?dir_ino, "directory is beyond the maximum listing depth, listing it as empty");
+            ReaddirIter::Empty
         } else {
-            ReaddirIter::unordered(&inner.bucket, &full_path, page_size, local_entries.into())
+            let ordered = inner.config.s3_personality.is_list_ordered();
+            let resuming = start_after.is_some();
+            let mut remote = RemoteIter::new(&inner, &full_path, page_size, ordered, retain_snapshot, pinned);
+            if let Some(start_after) = start_after {
+                remote = remote.starting_after(start_after);
+            }
+            if dirs_only {
+                remote = remote.dirs_only();
+            }
+            if resuming {
+                trace!(dir=?dir_ino, "resuming the listing of a directory from S3");
+            } else if let Some(listing) = pinned.then(|| inner.pinned_listings.get(&full_path)).flatten() {
+                trace!(dir=?dir_ino, "replaying the cached listing of a pinned directory");
+                metrics::counter!("metadata_cache.pinned_listing_hit").increment(1);
+                remote = remote.replaying(&listing);
+            } else if let Some(listing) = inner.bootstrap_listings.take(&full_path) {
+                trace!(dir=?dir_ino, "replaying the bootstrap listing of a directory");
+                metrics::counter!("metadata_cache.bootstrap_listing_hit").increment(1);
+                remote = remote.replaying(&listing);
+            }
+            if ordered {
+                ReaddirIter::ordered(remote, local_entries.into())
+            } else {
+                ReaddirIter::unordered(remote, local_entries.into())
+            }
         };
 
         Ok(Self {
@@ -104,9 +141,94 @@ impl ReaddirHandle {
             parent_ino,
             iter: AsyncMutex::new(iter),
             readded: Default::default(),
+            dirs_only,
+            pinned_key: pinned.then_some(full_path),
         })
     }
 
+    /// List the local children of the directory, sorted by name. This is a snapshot in time of the
+    /// local state of the directory.
+    fn local_entries(inner: &SuperblockInner, dir_ino: InodeNo) -> Result<Vec<ReaddirEntry>, InodeError> {
+        let inode = inner.get(dir_ino)?;
+        let kind_data = &inode.get_inode_state()?.kind_data;
+        let local_files = match kind_data {
+            InodeKindData::File { .. } => return Err(InodeError::NotADirectory(inode.err())),
+            InodeKindData::Directory { writing_children, .. } => writing_children.iter().map(|ino| {
+                let inode = inner.get(*ino)?;
+                let stat = inode.get_inode_state()?.stat.clone();
+                Ok(ReaddirEntry::LocalInode {
+                    lookup: LookedUp { inode, stat },
+                })
+            }),
+        };
+
+        match local_files.collect::<Result<Vec<_>, _>>() {
+            Ok(mut new_results) => {
+                new_results.retain(|entry| match entry {
+                    ReaddirEntry::LocalInode { lookup } => !inner.is_unpublished(&lookup.inode),
+                    _ => true,
+                });
+                new_results.sort();
+                Ok(new_results)
+            }
+            Err(e) => {
+                error!(error=?e, "readdir failed listing local files");
+                Err(e)
+            }
+        }
+    }
+
+    /// The children of the directory it already knows about from earlier listings and lookups,
+    /// other than local ones, sorted by name. Their metadata may have expired.
+    fn cached_entries(&self) -> Vec<ReaddirEntry> {
+        let Ok(dir) = self.inner.get(self.dir_ino) else {
+            return Vec::new();
+        };
+        let children = {
+            let Ok(state) = dir.get_inode_state() else {
+                return Vec::new();
+            };
+            let InodeKindData::Directory {
+                children,
+                writing_children,
+                ..
+            } = &state.kind_data
+            else {
+                return Vec::new();
+            };
+            children
+                .values()
+                .filter(|child| !writing_children.contains(&child.ino()))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        let mut entries = children
+            .into_iter()
+            .filter_map(|inode| {
+                let stat = inode.get_inode_state().ok()?.stat.clone();
+                Some(ReaddirEntry::Cached {
+                    lookup: LookedUp { inode, stat },
+                })
+            })
+            .collect::<Vec<_>>();
+        entries.sort();
+        entries
+    }
+
+    /// Rewind the stream to the start of the directory, replaying the remote entries seen so far
+    /// rather than listing them again. Local entries are listed again, so local changes made since
+    /// the handle was created are visible after the rewind.
+    ///
+    /// Only supported when the superblock is configured with [RewindMode::Snapshot].
+    pub async fn rewind(&self) -> Result<(), InodeError> {
+        let local_entries = Self::local_entries(&self.inner, self.dir_ino)?;
+        // Any readded entry is either remote (and so will be replayed) or local (and so has just
+        // been listed again).
+        self.readded.lock().unwrap().take();
+        self.iter.lock().await.rewind(local_entries.into());
+        Ok(())
+    }
+
     /// Return the next inode for the directory stream. If the stream is finished, returns
     /// `Ok(None)`. Does not increment the lookup count of the returned inodes: the caller
     /// is responsible for calling [`remember()`] if required.
@@ -116,14 +238,38 @@ impl ReaddirHandle {
         }
 
         // Loop because the next entry from the [ReaddirIter] may be hidden from the file system,
-        // if it has an invalid name.
+        // if it has an invalid name, is a file on a dirs-only handle, or is the hidden directory.
         loop {
-            let next = {
+            let (next, listed_empty, listing, snapshot) = {
                 let mut iter = self.iter.lock().await;
-                iter.next(client).await?
+                let next = match iter.next(client).await {
+                    // Rather than failing the listing, list what we already know about the directory
+                    Err(err @ InodeError::MetadataUnavailable(_)) if self.inner.config.serve_stale_metadata => {
+                        if !iter.serve_cached(self.cached_entries()) {
+                            return Err(err);
+                        }
+                        trace!(dir=?self.dir_ino, "listing cached children while S3 metadata requests are failing");
+                        metrics::counter!("fs.metadata_circuit_breaker.stale_listings").increment(1);
+                        iter.next(client).await?
+                    }
+                    result => result?,
+                };
+                let (listing, snapshot) = if next.is_none() {
+                    (iter.take_listing(), iter.take_snapshot())
+                } else {
+                    (None, None)
+                };
+                (next, iter.listed_empty(), listing, snapshot)
             };
 
             if let Some(next) = next {
+                // Skip files before instantiating them, so we don't create inodes we'll never use
+                if self.dirs_only && !next.is_directory() {
+                    continue;
+                }
+                if next.is_directory() && self.inner.is_hidden(self.dir_ino, next.name()) {
+                    continue;
+                }
                 // Short-circuit the update if we know it'll fail because the name is invalid
                 if !valid_inode_name(next.name()) {
                     warn!("{} has an invalid name and will be unavailable", next.description());
@@ -132,6 +278,18 @@ impl ReaddirHandle {
                     return Ok(Some(lookup));
                 }
             } else {
+                if listed_empty {
+                    self.inner.expire_if_removed(self.dir_ino);
+                }
+                if let Some(snapshot) = snapshot {
+                    if let Ok(dir) = self.inner.get(self.dir_ino) {
+                        self.inner.record_listing(&dir, snapshot);
+                    }
+                }
+                if let (Some(key), Some(listing)) = (&self.pinned_key, listing) {
+                    trace!(dir=?self.dir_ino, entries = listing.len(), "caching the listing of a pinned directory");
+                    self.inner.pinned_listings.insert(key, listing);
+                }
                 return Ok(None);
             }
         }
@@ -156,36 +314,23 @@ impl ReaddirHandle {
 
     /// Create or update an inode for the given ReaddirEntry.
     fn instantiate_remote_inode(&self, entry: ReaddirEntry) -> Result<LookedUp, InodeError> {
-        let remote_lookup = match &entry {
-            // If we made it this far with a local inode, we know there's nothing on the remote with
-            // the same name, because [LocalInode] is last in the ordering and so otherwise would
-            // have been deduplicated by now.
-            ReaddirEntry::LocalInode { .. } => None,
-            ReaddirEntry::RemotePrefix { .. } => {
-                let stat = InodeStat::for_directory(self.inner.mount_time, self.inner.config.cache_config.dir_ttl);
-                Some(RemoteLookup {
-                    stat,
-                    kind: InodeKind::Directory,
-                })
-            }
-            ReaddirEntry::RemoteObject { object_info, .. } => {
-                let stat = InodeStat::for_file(
-                    object_info.size as usize,
-                    object_info.last_modified,
-                    Some(object_info.etag.clone()),
-                    object_info.storage_class.clone(),
-                    object_info.restore_status,
-                    self.inner.config.cache_config.file_ttl,
-                );
-                Some(RemoteLookup {
-                    stat,
-                    kind: InodeKind::File,
-                })
-            }
-        };
+        // Cached entries already have inodes, and there's nothing new to update them with
+        if let ReaddirEntry::Cached { lookup } = entry {
+            return Ok(lookup);
+        }
+        // If we made it this far with a local inode, we know there's nothing on the remote with
+        // the same name, because [LocalInode] is last in the ordering and so otherwise would
+        // have been deduplicated by now.
+        let remote_lookup = entry.remote_lookup(&self.inner);
         self.inner.update_from_remote(self.dir_ino, entry.name(), remote_lookup)
     }
 
+    /// How many remote entries the handle is holding on to, waiting to be returned
+    #[cfg(test)]
+    pub(super) async fn buffered_entries(&self) -> usize {
+        self.iter.lock().await.buffered_entries()
+    }
+
     #[cfg(test)]
     pub(super) async fn collect<OC: ObjectClient>(&self, client: &OC) -> Result<Vec<LookedUp>, InodeError> {
         let mut result = vec![];
@@ -200,9 +345,20 @@ impl ReaddirHandle {
 /// should be done lazily by the consumer of the entry.
 #[derive(Debug, Clone)]
 enum ReaddirEntry {
-    RemotePrefix { name: String },
-    RemoteObject { name: String, object_info: ObjectInfo },
-    LocalInode { lookup: LookedUp },
+    RemotePrefix {
+        name: String,
+    },
+    RemoteObject {
+        name: String,
+        object_info: ObjectInfo,
+    },
+    LocalInode {
+        lookup: LookedUp,
+    },
+    /// A child the directory already knows about, listed from the cache while S3 can't be asked
+    Cached {
+        lookup: LookedUp,
+    },
 }
 
 // This looks a little silly but makes the [Ord] implementation for [ReaddirEntry] a bunch clearer
@@ -210,6 +366,7 @@ enum ReaddirEntry {
 enum ReaddirEntryKind {
     RemotePrefix,
     RemoteObject,
+    Cached,
     LocalInode,
 }
 
@@ -218,7 +375,60 @@ impl ReaddirEntry {
         match self {
             Self::RemotePrefix { name } => name,
             Self::RemoteObject { name, .. } => name,
-            Self::LocalInode { lookup } => lookup.inode.name(),
+            Self::LocalInode { lookup } | Self::Cached { lookup } => lookup.inode.name(),
+        }
+    }
+
+    /// Approximate memory used by this entry
+    fn size(&self) -> usize {
+        let owned = match self {
+            Self::RemotePrefix { name } => name.len(),
+            Self::RemoteObject { name, object_info } => {
+                name.len()
+                    + object_info.key.len()
+                    + object_info.etag.len()
+                    + object_info.storage_class.as_ref().map_or(0, String::len)
+            }
+            Self::LocalInode { .. } | Self::Cached { .. } => 0,
+        };
+        size_of::<Self>() + owned
+    }
+
+    fn is_directory(&self) -> bool {
+        match self {
+            Self::RemotePrefix { .. } => true,
+            Self::RemoteObject { .. } => false,
+            Self::LocalInode { lookup } | Self::Cached { lookup } => lookup.inode.kind() == InodeKind::Directory,
+        }
+    }
+
+    /// The result of looking up this entry remotely, or `None` for local and cached entries
+    fn remote_lookup(&self, inner: &SuperblockInner) -> Option<RemoteLookup> {
+        match self {
+            Self::LocalInode { .. } | Self::Cached { .. } => None,
+            Self::RemotePrefix { .. } => {
+                let stat = InodeStat::for_directory(inner.mount_time, inner.config.cache_config.dir_ttl, inner.now());
+                Some(RemoteLookup {
+                    stat,
+                    kind: InodeKind::Directory,
+                })
+            }
+            Self::RemoteObject { object_info, .. } => {
+                let mut stat = InodeStat::for_file(
+                    object_info.size as usize,
+                    object_info.last_modified,
+                    Some(object_info.etag.clone()),
+                    object_info.storage_class.clone(),
+                    object_info.restore_status,
+                    inner.config.cache_config.file_ttl,
+                    inner.now(),
+                );
+                stat.unknown_size = object_info.unknown_size;
+                Some(RemoteLookup {
+                    stat,
+                    kind: InodeKind::File,
+                })
+            }
         }
     }
 
@@ -226,6 +436,7 @@ impl ReaddirEntry {
         match self {
             Self::RemotePrefix { .. } => ReaddirEntryKind::RemotePrefix,
             Self::RemoteObject { .. } => ReaddirEntryKind::RemoteObject,
+            Self::Cached { .. } => ReaddirEntryKind::Cached,
             Self::LocalInode { .. } => ReaddirEntryKind::LocalInode,
         }
     }
@@ -246,6 +457,13 @@ impl ReaddirEntry {
                 };
                 format!("local {} '{}'", kind, lookup.inode.name())
             }
+            Self::Cached { lookup } => {
+                let kind = match lookup.inode.kind() {
+                    InodeKind::Directory => "directory",
+                    InodeKind::File => "file",
+                };
+                format!("cached {} '{}'", kind, lookup.inode.name())
+            }
         }
     }
 }
@@ -279,23 +497,301 @@ impl Ord for ReaddirEntry {
 enum ReaddirIter {
     Ordered(ordered::ReaddirIter),
     Unordered(unordered::ReaddirIter),
+    /// Lists nothing, for directories we refuse to list
+    Empty,
 }
 
 impl ReaddirIter {
-    fn ordered(bucket: &str, full_path: &str, page_size: usize, local_entries: VecDeque<ReaddirEntry>) -> Self {
-        Self::Ordered(ordered::ReaddirIter::new(bucket, full_path, page_size, local_entries))
+    fn ordered(remote: RemoteIter, local_entries: VecDeque<ReaddirEntry>) -> Self {
+        Self::Ordered(ordered::ReaddirIter::new(remote, local_entries))
     }
 
-    fn unordered(bucket: &str, full_path: &str, page_size: usize, local_entries: VecDeque<ReaddirEntry>) -> Self {
-        Self::Unordered(unordered::ReaddirIter::new(bucket, full_path, page_size, local_entries))
+    fn unordered(remote: RemoteIter, local_entries: VecDeque<ReaddirEntry>) -> Self {
+        Self::Unordered(unordered::ReaddirIter::new(remote, local_entries))
     }
 
     async fn next(&mut self, client: &impl ObjectClient) -> Result<Option<ReaddirEntry>, InodeError> {
         match self {
             Self::Ordered(iter) => iter.next(client).await,
             Self::Unordered(iter) => iter.next(client).await,
+            Self::Empty => Ok(None),
+        }
+    }
+
+    fn rewind(&mut self, local_entries: VecDeque<ReaddirEntry>) {
+        match self {
+            Self::Ordered(iter) => iter.rewind(local_entries),
+            Self::Unordered(iter) => iter.rewind(local_entries),
+            Self::Empty => {}
+        }
+    }
+
+    /// List the given cached entries instead of the directory's remote entries, if none have been
+    /// listed from S3 yet. Returns whether it did.
+    fn serve_cached(&mut self, entries: Vec<ReaddirEntry>) -> bool {
+        match self {
+            Self::Ordered(iter) => iter.serve_cached(entries),
+            Self::Unordered(iter) => iter.serve_cached(entries),
+            Self::Empty => false,
+        }
+    }
+
+    /// Whether the remote listing is complete and found nothing under the directory's prefix.
+    /// Directories we refuse to list are never known to be empty.
+    fn listed_empty(&self) -> bool {
+        match self {
+            Self::Ordered(iter) => iter.listed_empty(),
+            Self::Unordered(iter) => iter.listed_empty(),
+            Self::Empty => false,
+        }
+    }
+
+    /// Take the complete remote listing, if it's finished and the iterator was keeping it
+    fn take_listing(&mut self) -> Option<Vec<ReaddirEntry>> {
+        match self {
+            Self::Ordered(iter) => iter.take_listing(),
+            Self::Unordered(iter) => iter.take_listing(),
+            Self::Empty => None,
+        }
+    }
+
+    /// Take the snapshot of the subdirectories the remote listing found, if it's finished
+    fn take_snapshot(&mut self) -> Option<ListingSnapshot> {
+        match self {
+            Self::Ordered(iter) => iter.take_snapshot(),
+            Self::Unordered(iter) => iter.take_snapshot(),
+            Self::Empty => None,
+        }
+    }
+
+    #[cfg(test)]
+    fn buffered_entries(&self) -> usize {
+        match self {
+            Self::Ordered(iter) => iter.buffered_entries(),
+            Self::Unordered(iter) => iter.buffered_entries(),
+            Self::Empty => 0,
+        }
+    }
+}
+
+/// Complete listings of pinned directories, by directory key. Pinned directories are only listed
+/// again once their listing is removed from here, either explicitly or because the cache is over
+/// its limits, in which case the least recently used listings are evicted first. Evicting a
+/// listing doesn't affect `readdir`s already replaying it, as each [RemoteIter] has its own copy.
+#[derive(Debug)]
+pub(super) struct PinnedListings {
+    listings: Mutex<PinnedListingsState>,
+    /// Maximum total number of entries across all listings, if any
+    max_entries: Option<usize>,
+    /// Maximum approximate memory used by all listings, if any
+    max_bytes: Option<usize>,
+}
+
+#[derive(Debug)]
+struct PinnedListingsState {
+    /// Listings in order from least to most recently used
+    map: LinkedHashMap<String, PinnedListing>,
+    entries: usize,
+    bytes: usize,
+    /// This file system's part of the entry and memory gauges
+    entries_gauge: GaugeShare,
+    bytes_gauge: GaugeShare,
+}
+
+#[derive(Debug)]
+struct PinnedListing {
+    entries: Arc<Vec<ReaddirEntry>>,
+    bytes: usize,
+}
+
+impl PinnedListingsState {
+    fn remove(&mut self, dir_key: &str) -> Option<PinnedListing> {
+        let listing = self.map.remove(dir_key)?;
+        self.entries -= listing.entries.len();
+        self.bytes -= listing.bytes;
+        Some(listing)
+    }
+
+    fn update_gauges(&mut self) {
+        self.entries_gauge.set(self.entries as f64);
+        self.bytes_gauge.set(self.bytes as f64);
+    }
+}
+
+impl PinnedListings {
+    pub(super) fn new(max_entries: Option<usize>, max_bytes: Option<usize>) -> Self {
+        Self {
+            listings: Mutex::new(PinnedListingsState {
+                map: Default::default(),
+                entries: 0,
+                bytes: 0,
+                entries_gauge: GaugeShare::new("metadata_cache.pinned_listings.entries"),
+                bytes_gauge: GaugeShare::new("metadata_cache.pinned_listings.bytes"),
+            }),
+            max_entries,
+            max_bytes,
+        }
+    }
+
+    fn over_limits(&self, entries: usize, bytes: usize) -> bool {
+        self.max_entries.is_some_and(|max| entries > max) || self.max_bytes.is_some_and(|max| bytes > max)
+    }
+
+    fn get(&self, dir_key: &str) -> Option<Arc<Vec<ReaddirEntry>>> {
+        self.listings
+            .lock()
+            .unwrap()
+            .map
+            .get_refresh(dir_key)
+            .map(|listing| listing.entries.clone())
+    }
+
+    fn insert(&self, dir_key: &str, listing: Vec<ReaddirEntry>) {
+        let bytes = dir_key.len() + listing.iter().map(ReaddirEntry::size).sum::<usize>();
+        let mut state = self.listings.lock().unwrap();
+        state.remove(dir_key);
+        if self.over_limits(listing.len(), bytes) {
+            trace!(dir_key, entries = listing.len(), bytes, "listing is too big to cache");
+            state.update_gauges();
+            return;
+        }
+
+        state.entries += listing.len();
+        state.bytes += bytes;
+        let listing = PinnedListing {
+            entries: Arc::new(listing),
+            bytes,
+        };
+        state.map.insert(dir_key.to_owned(), listing);
+        while self.over_limits(state.entries, state.bytes) {
+            let Some(evicted) = state.map.front().map(|(key, _)| key.clone()) else {
+                break;
+            };
+            trace!(dir_key = evicted, "evicting pinned listing");
+            state.remove(&evicted);
+            metrics::counter!("metadata_cache.pinned_listings.evicted").increment(1);
+        }
+        state.update_gauges();
+    }
+
+    /// Forget the listing of the directory with the given key, so that it's listed again
+    pub(super) fn remove(&self, dir_key: &str) {
+        let mut state = self.listings.lock().unwrap();
+        if state.remove(dir_key).is_some() {
+            state.update_gauges();
         }
     }
+
+    /// The total number of entries and approximate memory used by all listings
+    pub(super) fn usage(&self) -> (usize, usize) {
+        let state = self.listings.lock().unwrap();
+        (state.entries, state.bytes)
+    }
+}
+
+/// Listings of directories loaded from a listing manifest, by directory key. Each listing is only
+/// used for the first `readdir` of its directory and the first lookup of each of its entries; after
+/// that, the directory and its entries are listed and looked up in S3 as usual.
+#[derive(Debug, Default)]
+pub(super) struct BootstrapListings {
+    listings: Mutex<HashMap<String, BootstrapListing>>,
+}
+
+#[derive(Debug, Default)]
+struct BootstrapListing {
+    /// Entries in the order ListObjectsV2 would return them
+    entries: Vec<ReaddirEntry>,
+    /// Names of entries that have already been looked up
+    looked_up: HashSet<String>,
+}
+
+impl BootstrapListings {
+    /// Replace the listings with those of the directories under `root_key` that contain the given
+    /// objects. Returns the number of directories listed. Like a listing from S3, objects below a
+    /// path component that isn't a valid name (e.g. `..` in `dir/../file`) are left out, though
+    /// the directories above it are still listed.
+    pub(super) fn load(&self, root_key: &str, objects: impl IntoIterator<Item = ObjectInfo>) -> usize {
+        let mut listings: HashMap<String, BootstrapListing> = HashMap::new();
+        listings.insert(root_key.to_owned(), Default::default());
+        'objects: for object_info in objects {
+            let Some(relative) = object_info.key.strip_prefix(root_key) else {
+                continue;
+            };
+            let (dirs, name) = match relative.rsplit_once('/') {
+                Some((dirs, name)) => (Some(dirs.to_owned()), name.to_owned()),
+                None => (None, relative.to_owned()),
+            };
+
+            let mut dir_key = root_key.to_owned();
+            for dir in dirs.iter().flat_map(|dirs| dirs.split('/')) {
+                if !valid_inode_name(dir) {
+                    warn!(
+                        key = object_info.key,
+                        "key has an invalid directory name and will be unavailable"
+                    );
+                    continue 'objects;
+                }
+                let child_key = format!("{dir_key}{dir}/");
+                if !listings.contains_key(&child_key) {
+                    let parent = listings
+                        .get_mut(&dir_key)
+                        .expect("parent is listed before its children");
+                    parent.entries.push(ReaddirEntry::RemotePrefix { name: dir.to_owned() });
+                    listings.insert(child_key.clone(), Default::default());
+                }
+                dir_key = child_key;
+            }
+
+            // An object whose key ends in `/` is a directory marker, not a child with an empty name
+            if !name.is_empty() {
+                if !valid_inode_name(&name) {
+                    warn!(
+                        key = object_info.key,
+                        "key has an invalid file name and will be unavailable"
+                    );
+                    continue;
+                }
+                let listing = listings.get_mut(&dir_key).expect("directory was listed above");
+                listing.entries.push(ReaddirEntry::RemoteObject { name, object_info });
+            }
+        }
+
+        for listing in listings.values_mut() {
+            listing.entries.sort();
+            listing.entries.dedup();
+        }
+        let len = listings.len();
+        *self.listings.lock().unwrap() = listings;
+        len
+    }
+
+    /// Take the listing of the directory with the given key, for its first `readdir`
+    fn take(&self, dir_key: &str) -> Option<Vec<ReaddirEntry>> {
+        self.listings
+            .lock()
+            .unwrap()
+            .remove(dir_key)
+            .map(|listing| listing.entries)
+    }
+
+    /// Look up `name` in the listing of the directory with the given key. Returns `None` if there's
+    /// no listing for the directory, the name isn't in it, or it has already been looked up.
+    pub(super) fn lookup(&self, inner: &SuperblockInner, dir_key: &str, name: &str) -> Option<RemoteLookup> {
+        let mut listings = self.listings.lock().unwrap();
+        let listing = listings.get_mut(dir_key)?;
+        // A directory shadows a file with the same name, and sorts before it
+        let index = listing.entries.partition_point(|entry| entry.name() < name);
+        let entry = listing.entries.get(index).filter(|entry| entry.name() == name)?;
+        if !listing.looked_up.insert(name.to_owned()) {
+            return None;
+        }
+        entry.remote_lookup(inner)
+    }
+
+    /// Forget the listing of the directory with the given key, so that it's listed from S3
+    pub(super) fn remove(&self, dir_key: &str) {
+        self.listings.lock().unwrap().remove(dir_key);
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -318,47 +814,186 @@ struct RemoteIter {
     page_size: usize,
     state: RemoteIterState,
     ordered: bool,
+    /// Entries already returned by this iterator, in order, if we need to be able to replay them
+    snapshot: Option<Vec<ReaddirEntry>>,
+    /// Whether any ListObjects call so far returned a key under the prefix, including the
+    /// directory's own marker
+    found_keys: bool,
+    /// Every entry listed so far, in order, if we need to keep the complete listing
+    listing: Option<Vec<ReaddirEntry>>,
+    /// Name of the entry to resume the listing after. Only entries with later names are returned.
+    start_after: Option<String>,
+    /// The subdirectories listed so far, and the fingerprint of the listing, if this is a complete
+    /// listing from S3 rather than one that's resumed or replayed
+    subdirectories: Option<ListingSnapshot>,
+    /// Drop the objects of each page as soon as it's listed, keeping only the common prefixes
+    dirs_only: bool,
+    /// Circuit breaker each ListObjects request goes through
+    breaker: Arc<CircuitBreaker>,
 }
 
 impl RemoteIter {
-    fn new(bucket: &str, full_path: &str, page_size: usize, ordered: bool) -> Self {
+    fn new(
+        inner: &SuperblockInner,
+        full_path: &str,
+        page_size: usize,
+        ordered: bool,
+        retain_snapshot: bool,
+        keep_listing: bool,
+    ) -> Self {
         Self {
             entries: VecDeque::new(),
-            bucket: bucket.to_owned(),
+            bucket: inner.bucket.clone(),
             full_path: full_path.to_owned(),
             page_size,
             state: RemoteIterState::InProgress(None),
             ordered,
+            snapshot: retain_snapshot.then(Vec::new),
+            found_keys: false,
+            listing: keep_listing.then(Vec::new),
+            start_after: None,
+            subdirectories: Some(ListingSnapshot::new(inner.now())),
+            dirs_only: false,
+            breaker: inner.config.metadata_breaker.clone(),
         }
     }
 
+    /// Only return the subdirectories, dropping the objects of each page of the listing without
+    /// buffering them. The listing isn't complete, so it isn't kept for a pinned directory.
+    fn dirs_only(mut self) -> Self {
+        self.dirs_only = true;
+        self.listing = None;
+        self
+    }
+
+    /// Resume the listing after the entry `name`, rather than starting at the beginning
+    fn starting_after(mut self, name: String) -> Self {
+        self.start_after = Some(name);
+        self.subdirectories = None;
+        self
+    }
+
+    /// Replay a complete listing kept by an earlier iterator, rather than listing the directory
+    fn replaying(mut self, listing: &[ReaddirEntry]) -> Self {
+        self.entries = listing.iter().cloned().collect();
+        self.state = RemoteIterState::Finished;
+        // The directory existed when it was listed, and pinned directories aren't listed again
+        self.found_keys = true;
+        self.listing = None;
+        self.subdirectories = None;
+        self
+    }
+
+    /// List the given cached entries rather than the directory, if its first page hasn't been
+    /// listed yet. Returns whether it did.
+    fn serve_cached(&mut self, entries: Vec<ReaddirEntry>) -> bool {
+        if self.state != RemoteIterState::InProgress(None) {
+            return false;
+        }
+        self.entries = entries
+            .into_iter()
+            .filter(|entry| {
+                self.start_after
+                    .as_deref()
+                    .map_or(true, |start_after| entry.name() > start_after)
+            })
+            .collect();
+        self.state = RemoteIterState::Finished;
+        // We don't know whether the directory still exists, so don't forget it, and the cached
+        // entries are neither a complete listing nor a snapshot of the directory in S3
+        self.found_keys = true;
+        self.listing = None;
+        self.subdirectories = None;
+        true
+    }
+
+    /// Take the complete listing, if it's finished and we were keeping it
+    fn take_listing(&mut self) -> Option<Vec<ReaddirEntry>> {
+        if self.state != RemoteIterState::Finished {
+            return None;
+        }
+        self.listing.take()
+    }
+
+    /// Take the snapshot of the subdirectories listed, if the listing is finished
+    fn take_snapshot(&mut self) -> Option<ListingSnapshot> {
+        if self.state != RemoteIterState::Finished {
+            return None;
+        }
+        self.subdirectories.take()
+    }
+
+    /// Whether the listing is complete and found no keys at all under the directory's prefix, not
+    /// even a marker object, so the directory no longer exists in the bucket.
+    fn listed_empty(&self) -> bool {
+        matches!(self.state, RemoteIterState::Finished) && !self.found_keys
+    }
+
+    /// Rewind the iterator so that it replays every entry it has already returned before continuing
+    /// with the remaining entries.
+    fn rewind(&mut self) {
+        let Some(snapshot) = self.snapshot.as_mut() else {
+            debug_assert!(false, "can only rewind an iterator that retains a snapshot");
+            return;
+        };
+        let mut entries: VecDeque<_> = std::mem::take(snapshot).into();
+        entries.append(&mut self.entries);
+        self.entries = entries;
+    }
+
     async fn next(&mut self, client: &impl ObjectClient) -> Result<Option<ReaddirEntry>, InodeError> {
         if self.entries.is_empty() {
-            let continuation_token = match &mut self.state {
-                RemoteIterState::Finished => {
-                    trace!(self=?self as *const _, prefix=?self.full_path, "remote iter finished");
-                    return Ok(None);
-                }
-                RemoteIterState::InProgress(token) => token.take(),
+            let RemoteIterState::InProgress(token) = &mut self.state else {
+                trace!(self=?self as *const _, prefix=?self.full_path, "remote iter finished");
+                return Ok(None);
             };
+            // Admit the request before taking the continuation token, so the listing can carry on
+            // once the breaker closes
+            let breaker = self.breaker.clone();
+            let permit = breaker
+                .admit()
+                .map_err(|_| InodeError::MetadataUnavailable(CircuitBreakerOpen))?;
+            let continuation_token = token.take();
 
             trace!(self=?self as *const _, prefix=?self.full_path, ?continuation_token, "continuing remote iter");
 
-            let result = client
-                .list_objects(
-                    &self.bucket,
-                    continuation_token.as_deref(),
-                    "/",
-                    self.page_size,
-                    self.full_path.as_str(),
-                )
-                .await
-                .map_err(|e| InodeError::ClientError(anyhow::Error::new(e)))?;
+            let result = match (&continuation_token, &self.start_after) {
+                // The first page of a resumed listing
+                (None, Some(start_after)) => {
+                    client
+                        .list_objects_start_after(
+                            &self.bucket,
+                            &format!("{}{start_after}", self.full_path),
+                            "/",
+                            self.page_size,
+                            self.full_path.as_str(),
+                        )
+                        .await
+                }
+                _ => {
+                    client
+                        .list_objects(
+                            &self.bucket,
+                            continuation_token.as_deref(),
+                            "/",
+                            self.page_size,
+                            self.full_path.as_str(),
+                        )
+                        .await
+                }
+            }
+            .map_err(|e| InodeError::ClientError(anyhow::Error::new(e)));
+            permit.complete(&result);
+            let result = result?;
 
             self.state = match result.next_continuation_token {
                 Some(token) => RemoteIterState::InProgress(Some(token)),
                 None => RemoteIterState::Finished,
             };
+            self.found_keys |= !result.common_prefixes.is_empty() || !result.objects.is_empty();
+            if let Some(subdirectories) = self.subdirectories.as_mut() {
+                subdirectories.add_page(&self.full_path, &result);
+            }
 
             let prefixes = result
                 .common_prefixes
@@ -367,29 +1002,45 @@ impl RemoteIter {
                     name: prefix[self.full_path.len()..prefix.len() - 1].to_owned(),
                 });
 
+            // An object whose key is exactly the directory's prefix is the marker for the directory
+            // itself (including the root of a prefixed mount), not a child with an empty name.
             let objects = result
                 .objects
                 .into_iter()
+                .filter(|object_info| !self.dirs_only && object_info.key != self.full_path)
                 .map(|object_info| ReaddirEntry::RemoteObject {
                     name: object_info.key[self.full_path.len()..].to_owned(),
                     object_info,
                 });
 
+            // Keys after the name we resume from can still belong to entries that sort before it or
+            // are the entry itself: `a/` comes after `a` and `a-b`, but names `a` and `a-b` don't.
+            let mut new_entries = prefixes
+                .chain(objects)
+                .filter(|entry| {
+                    self.start_after
+                        .as_deref()
+                        .map_or(true, |start_after| entry.name() > start_after)
+                })
+                .collect::<Vec<_>>();
             if self.ordered {
                 // ListObjectsV2 results are sorted, so ideally we'd just merge-sort the two streams.
                 // But `prefixes` isn't quite in sorted order any more because we trimmed off the
                 // trailing `/` from the names. There's still probably a less naive way to do this sort,
                 // but this should be good enough.
-                let mut new_entries = prefixes.chain(objects).collect::<Vec<_>>();
                 new_entries.sort();
-
-                self.entries.extend(new_entries);
-            } else {
-                self.entries.extend(prefixes.chain(objects));
             }
+            if let Some(listing) = self.listing.as_mut() {
+                listing.extend(new_entries.iter().cloned());
+            }
+            self.entries.extend(new_entries);
         }
 
-        Ok(self.entries.pop_front())
+        let next = self.entries.pop_front();
+        if let (Some(snapshot), Some(entry)) = (self.snapshot.as_mut(), next.as_ref()) {
+            snapshot.push(entry.clone());
+        }
+        Ok(next)
     }
 }
 
@@ -410,14 +1061,9 @@ mod ordered {
     }
 
     impl ReaddirIter {
-        pub(super) fn new(
-            bucket: &str,
-            full_path: &str,
-            page_size: usize,
-            local_entries: VecDeque<ReaddirEntry>,
-        ) -> Self {
+        pub(super) fn new(remote: RemoteIter, local_entries: VecDeque<ReaddirEntry>) -> Self {
             Self {
-                remote: RemoteIter::new(bucket, full_path, page_size, true),
+                remote,
                 local: LocalIter::new(local_entries),
                 next_remote: None,
                 next_local: None,
@@ -425,6 +1071,42 @@ mod ordered {
             }
         }
 
+        /// Rewind to the start of the directory, replaying remote entries and starting again with
+        /// the given local entries.
+        pub(super) fn rewind(&mut self, local_entries: VecDeque<ReaddirEntry>) {
+            // The peeked remote entry is already part of the remote snapshot, so will be replayed.
+            self.next_remote = None;
+            self.remote.rewind();
+            self.local = LocalIter::new(local_entries);
+            self.next_local = None;
+            self.last_entry = None;
+        }
+
+        /// List the given cached entries instead of the remote ones, if none have been listed yet
+        pub(super) fn serve_cached(&mut self, entries: Vec<ReaddirEntry>) -> bool {
+            self.remote.serve_cached(entries)
+        }
+
+        /// Whether the remote listing is complete and found nothing under the directory's prefix
+        pub(super) fn listed_empty(&self) -> bool {
+            self.remote.listed_empty()
+        }
+
+        /// Take the complete remote listing, if it's finished and we were keeping it
+        pub(super) fn take_listing(&mut self) -> Option<Vec<ReaddirEntry>> {
+            self.remote.take_listing()
+        }
+
+        /// Take the snapshot of the subdirectories the remote listing found, if it's finished
+        pub(super) fn take_snapshot(&mut self) -> Option<ListingSnapshot> {
+            self.remote.take_snapshot()
+        }
+
+        #[cfg(test)]
+        pub(super) fn buffered_entries(&self) -> usize {
+            self.remote.entries.len() + usize::from(self.next_remote.is_some())
+        }
+
         /// Return the next [ReaddirEntry] for the directory stream. If the stream is finished, returns
         /// `Ok(None)`.
         pub(super) async fn next(&mut self, client: &impl ObjectClient) -> Result<Option<ReaddirEntry>, InodeError> {
@@ -512,13 +1194,16 @@ mod unordered {
     }
 
     impl ReaddirIter {
-        pub(super) fn new(
-            bucket: &str,
-            full_path: &str,
-            page_size: usize,
-            local_entries: VecDeque<ReaddirEntry>,
-        ) -> Self {
-            let local_map = local_entries
+        pub(super) fn new(remote: RemoteIter, local_entries: VecDeque<ReaddirEntry>) -> Self {
+            Self {
+                remote,
+                local: Self::local_map(local_entries),
+                local_iter: VecDeque::new(),
+            }
+        }
+
+        fn local_map(local_entries: VecDeque<ReaddirEntry>) -> HashMap<String, ReaddirEntry> {
+            local_entries
                 .into_iter()
                 .map(|entry| {
                     let ReaddirEntry::LocalInode { lookup } = &entry else {
@@ -526,13 +1211,40 @@ mod unordered {
                     };
                     (lookup.inode.name().to_owned(), entry)
                 })
-                .collect::<HashMap<_, _>>();
+                .collect()
+        }
 
-            Self {
-                remote: RemoteIter::new(bucket, full_path, page_size, false),
-                local: local_map,
-                local_iter: VecDeque::new(),
-            }
+        /// Rewind to the start of the directory, replaying remote entries and starting again with
+        /// the given local entries.
+        pub(super) fn rewind(&mut self, local_entries: VecDeque<ReaddirEntry>) {
+            self.remote.rewind();
+            self.local = Self::local_map(local_entries);
+            self.local_iter.clear();
+        }
+
+        /// List the given cached entries instead of the remote ones, if none have been listed yet
+        pub(super) fn serve_cached(&mut self, entries: Vec<ReaddirEntry>) -> bool {
+            self.remote.serve_cached(entries)
+        }
+
+        /// Whether the remote listing is complete and found nothing under the directory's prefix
+        pub(super) fn listed_empty(&self) -> bool {
+            self.remote.listed_empty()
+        }
+
+        /// Take the complete remote listing, if it's finished and we were keeping it
+        pub(super) fn take_listing(&mut self) -> Option<Vec<ReaddirEntry>> {
+            self.remote.take_listing()
+        }
+
+        /// Take the snapshot of the subdirectories the remote listing found, if it's finished
+        pub(super) fn take_snapshot(&mut self) -> Option<ListingSnapshot> {
+            self.remote.take_snapshot()
+        }
+
+        #[cfg(test)]
+        pub(super) fn buffered_entries(&self) -> usize {
+            self.remote.entries.len()
         }
 
         /// Return the next [ReaddirEntry] for the directory stream. If the stream is finished, returns
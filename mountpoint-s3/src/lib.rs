@@ -1,18 +1,26 @@
 pub mod autoconfigure;
+pub mod blocking;
 mod build_info;
 mod checksums;
+#[cfg(feature = "fuse")]
 pub mod cli;
+pub mod clock;
 pub mod data_cache;
 pub mod fs;
+#[cfg(feature = "fuse")]
 pub mod fuse;
 mod inode;
 pub mod logging;
+pub mod mem_limiter;
 pub mod metrics;
 mod object;
+mod periodic;
 pub mod prefetch;
 pub mod prefix;
 pub mod s3;
 mod sync;
+#[cfg(feature = "test-utils")]
+pub mod test_utils;
 mod upload;
 
 pub use fs::{S3Filesystem, S3FilesystemConfig, ServerSideEncryption};
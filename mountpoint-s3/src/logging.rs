@@ -1,3 +1,4 @@
+use std::any::Any;
 use std::backtrace::Backtrace;
 use std::fs::{DirBuilder, OpenOptions};
 use std::os::unix::fs::DirBuilderExt;
@@ -18,9 +19,25 @@ use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::{Layer, Registry};
 
+#[cfg(feature = "otel")]
+mod otel;
+mod slow_ops;
 mod syslog;
+#[cfg(feature = "otel")]
+pub use self::otel::{otel_layer, otel_layer_with_exporter, OtelConfig, OtelConfigError};
+pub use self::slow_ops::{slow_op_layer, SlowOpConfig, SLOW_OP_TARGET};
 use self::syslog::SyslogLayer;
 
+/// The name of the module containing the FUSE operations, whose spans are the root of each
+/// operation's tree of spans
+const FUSE_MODULE_NAME: &str = "mountpoint_s3::fuse";
+
+/// Spans of S3 requests are in this crate, with a target ending in `::request`
+const CLIENT_CRATE_NAME: &str = "mountpoint_s3_client";
+
+/// Requests started by the prefetcher are linked to the read that started them through its spans
+const PREFETCH_MODULE_NAME: &str = "mountpoint_s3::prefetch";
+
 /// Configuration for Mountpoint logging
 #[derive(Debug)]
 pub struct LoggingConfig {
@@ -31,6 +48,11 @@ pub struct LoggingConfig {
     /// The default filter directive (in the sense of [tracing_subscriber::filter::EnvFilter]) to
     /// use for logs. Will be overridden by the `MOUNTPOINT_LOG` environment variable if set.
     pub default_filter: String,
+    /// Log a warning for each FUSE operation slower than these thresholds, or `None` not to
+    pub slow_ops: Option<SlowOpConfig>,
+    /// Export traces of FUSE operations to this OpenTelemetry collector, or `None` not to
+    #[cfg(feature = "otel")]
+    pub otel: Option<OtelConfig>,
 }
 
 /// Set up all our logging infrastructure.
@@ -45,20 +67,20 @@ pub fn init_logging(config: LoggingConfig) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Flush the logging infrastructure that buffers its output, before the process exits
+pub fn shutdown_logging() {
+    // Export the traces that are still waiting to be batched
+    #[cfg(feature = "otel")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
 fn tracing_panic_hook(panic_info: &PanicInfo) {
     let location = panic_info
         .location()
         .map(|l| format!("{}", l))
         .unwrap_or_else(|| String::from("<unknown>"));
 
-    let payload = panic_info.payload();
-    let payload = if let Some(s) = payload.downcast_ref::<&'static str>() {
-        *s
-    } else if let Some(s) = payload.downcast_ref::<String>() {
-        s.as_str()
-    } else {
-        "<unknown payload>"
-    };
+    let payload = panic_message(panic_info.payload());
 
     let thd = thread::current();
 
@@ -68,6 +90,17 @@ fn tracing_panic_hook(panic_info: &PanicInfo) {
     tracing::error!("backtrace:\n{backtrace}");
 }
 
+/// The message a panic was raised with, if its payload is a string as it is for `panic!`
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&'static str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.as_str()
+    } else {
+        "<unknown payload>"
+    }
+}
+
 fn install_panic_hook() {
     let old_hook = panic::take_hook();
     panic::set_hook(Box::new(move |panic_info| {
@@ -142,7 +175,19 @@ fn init_tracing_subscriber(config: LoggingConfig) -> anyhow::Result<()> {
         .with(syslog_layer)
         .with(console_layer)
         .with(file_layer)
-        .with(metrics_tracing_span_layer());
+        .with(metrics_tracing_span_layer())
+        .with(config.slow_ops.map(slow_op_layer));
+
+    #[cfg(feature = "otel")]
+    let registry = {
+        let otel_layer = config
+            .otel
+            .as_ref()
+            .map(otel_layer)
+            .transpose()
+            .context("couldn't set up OpenTelemetry export")?;
+        registry.with(otel_layer)
+    };
 
     registry.init();
 
@@ -0,0 +1,168 @@
+//! Export of file system operation traces to an OpenTelemetry collector.
+//!
+//! Each FUSE operation's span becomes the root of an OpenTelemetry trace, with the spans started
+//! beneath it (like prefetches and the S3 requests they make) as its descendants. The fields of
+//! each span are exported as its attributes. The spans are converted by [tracing_opentelemetry],
+//! batched by the OpenTelemetry SDK on a background thread, and sent to the collector (like
+//! Jaeger, or the OpenTelemetry Collector) with OTLP over HTTP.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use opentelemetry::trace::{TraceError, TracerProvider as _};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::export::trace::SpanExporter;
+use opentelemetry_sdk::trace::{BatchConfigBuilder, BatchSpanProcessor, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use serde::Deserialize;
+use thiserror::Error;
+use tracing::{Level, Subscriber};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use crate::build_info;
+
+use super::{CLIENT_CRATE_NAME, FUSE_MODULE_NAME, PREFETCH_MODULE_NAME};
+
+/// Configuration for exporting traces to an OpenTelemetry collector.
+///
+/// It can be loaded from a TOML file with [OtelConfig::from_toml_str]. Every field of the file is
+/// optional, omitted fields take their default value, and unknown fields are rejected.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OtelConfig {
+    /// Base URL of the collector's OTLP/HTTP receiver, like `http://localhost:4318`. Traces are
+    /// sent to its `/v1/traces` path.
+    pub endpoint: String,
+    /// Extra HTTP headers to send with each export, like the credentials the collector expects
+    pub headers: HashMap<String, String>,
+    /// The `service.name` the traces are reported under
+    pub service_name: String,
+    /// Most spans to send in one request
+    pub max_batch_size: usize,
+    /// Longest a finished span waits before it's sent, written like `"5s"` in a file
+    #[serde(deserialize_with = "crate::fs::deserialize_duration")]
+    pub export_interval: Duration,
+}
+
+impl Default for OtelConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: String::from("http://localhost:4318"),
+            headers: HashMap::new(),
+            service_name: String::from("mountpoint-s3"),
+            max_batch_size: 512,
+            export_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+impl OtelConfig {
+    /// Load a configuration from the contents of a TOML file
+    pub fn from_toml_str(s: &str) -> Result<Self, OtelConfigError> {
+        Ok(toml::from_str(s)?)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum OtelConfigError {
+    #[error("invalid OpenTelemetry config: {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("max_batch_size must be greater than zero")]
+    InvalidBatchSize,
+    #[error("couldn't create the OTLP exporter: {0}")]
+    Exporter(#[from] TraceError),
+}
+
+/// Create a [Layer] that exports FUSE operation traces to the collector in `config`.
+///
+/// The layer's tracer provider is installed as the global one, which keeps it for the life of the
+/// process.
+pub fn otel_layer<S>(config: &OtelConfig) -> Result<impl Layer<S>, OtelConfigError>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let exporter = opentelemetry_otlp::new_exporter()
+        .http()
+        .with_endpoint(&config.endpoint)
+        .with_headers(config.headers.clone())
+        .build_span_exporter()?;
+    let (layer, provider) = otel_layer_with_exporter(config, exporter)?;
+    opentelemetry::global::set_tracer_provider(provider);
+    Ok(layer)
+}
+
+/// Create a [Layer] that exports FUSE operation traces with the given exporter, and the provider
+/// that batches spans for it. The layer only holds a weak reference to the provider, so it stops
+/// exporting once the provider is dropped; [TracerProvider::force_flush] exports the spans that
+/// are still waiting.
+pub fn otel_layer_with_exporter<S>(
+    config: &OtelConfig,
+    exporter: impl SpanExporter + 'static,
+) -> Result<(impl Layer<S>, TracerProvider), OtelConfigError>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    if config.max_batch_size == 0 {
+        return Err(OtelConfigError::InvalidBatchSize);
+    }
+    let batch_config = BatchConfigBuilder::default()
+        .with_max_export_batch_size(config.max_batch_size)
+        .with_scheduled_delay(config.export_interval)
+        .build();
+    // Mountpoint doesn't run in a Tokio runtime, so the batches are exported from a thread of
+    // their own, with a runtime just for that thread
+    let processor = BatchSpanProcessor::builder(exporter, runtime::TokioCurrentThread)
+        .with_batch_config(batch_config)
+        .build();
+    let resource = Resource::new([
+        KeyValue::new("service.name", config.service_name.clone()),
+        KeyValue::new("service.version", build_info::FULL_VERSION),
+    ]);
+    let provider = TracerProvider::builder()
+        .with_span_processor(processor)
+        .with_config(opentelemetry_sdk::trace::config().with_resource(resource))
+        .build();
+
+    let filter = Targets::new()
+        .with_target(FUSE_MODULE_NAME, Level::WARN)
+        .with_target(PREFETCH_MODULE_NAME, Level::DEBUG)
+        .with_target(CLIENT_CRATE_NAME, Level::DEBUG);
+    let layer = tracing_opentelemetry::layer()
+        .with_tracer(provider.tracer("mountpoint-s3"))
+        .with_filter(filter);
+    Ok((layer, provider))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_toml_config() {
+        let config = OtelConfig::from_toml_str(
+            r#"
+            endpoint = "http://collector:4318"
+            service_name = "my-mount"
+            export_interval = "500ms"
+
+            [headers]
+            authorization = "Bearer token"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.endpoint, "http://collector:4318");
+        assert_eq!(config.service_name, "my-mount");
+        assert_eq!(config.export_interval, Duration::from_millis(500));
+        assert_eq!(config.headers.get("authorization").unwrap(), "Bearer token");
+        assert_eq!(config.max_batch_size, OtelConfig::default().max_batch_size);
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        let err = OtelConfig::from_toml_str("endpont = \"http://collector:4318\"").unwrap_err();
+        assert!(matches!(err, OtelConfigError::Toml(_)));
+    }
+}
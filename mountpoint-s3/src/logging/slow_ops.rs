@@ -0,0 +1,471 @@
+//! Logging of slow file system operations.
+//!
+//! Full tracing is too verbose to leave on, but when an application sees a slow operation it's
+//! useful to know which S3 requests it was waiting for. This layer times each FUSE operation's
+//! span, counts the S3 request spans started beneath it, and logs a single warning for each
+//! operation that takes longer than its threshold.
+
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Record};
+use tracing::{Event, Id, Level, Subscriber};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use super::{CLIENT_CRATE_NAME, FUSE_MODULE_NAME, PREFETCH_MODULE_NAME};
+
+/// Target of the records this layer logs
+pub const SLOW_OP_TARGET: &str = "mountpoint_s3::slow_op";
+
+/// FUSE operations that move file data, rather than just metadata
+const DATA_OPS: &[&str] = &["read", "write", "flush", "fsync", "fallocate", "release"];
+
+/// Configuration for logging slow FUSE operations
+#[derive(Debug, Clone)]
+pub struct SlowOpConfig {
+    /// Operations on metadata (lookups, attributes, directory listings, ...) that take longer
+    /// than this are logged
+    pub metadata_threshold: Duration,
+    /// Operations on file data (reads, writes, and the flushes and releases that complete
+    /// uploads) that take longer than this are logged
+    pub data_threshold: Duration,
+    /// Most records to log each minute. Slow operations beyond that are counted, and the count
+    /// is logged with the next record.
+    pub max_records_per_minute: u32,
+}
+
+impl Default for SlowOpConfig {
+    fn default() -> Self {
+        Self {
+            metadata_threshold: Duration::from_secs(1),
+            data_threshold: Duration::from_secs(5),
+            max_records_per_minute: 10,
+        }
+    }
+}
+
+/// Create a [Layer] that logs FUSE operations slower than the thresholds in `config`
+pub fn slow_op_layer<S>(config: SlowOpConfig) -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let filter = Targets::new()
+        .with_target(FUSE_MODULE_NAME, Level::WARN)
+        .with_target(PREFETCH_MODULE_NAME, Level::DEBUG)
+        .with_target(CLIENT_CRATE_NAME, Level::DEBUG);
+    SlowOpLayer::new(config).with_filter(filter)
+}
+
+#[derive(Debug)]
+struct SlowOpLayer {
+    config: SlowOpConfig,
+    rate_limit: Mutex<RateLimit>,
+}
+
+/// How many records were logged in the current minute, and how many were dropped since the last
+/// one was logged
+#[derive(Debug)]
+struct RateLimit {
+    window_start: Instant,
+    logged: u32,
+    suppressed: u64,
+}
+
+/// The progress of a FUSE operation, attached to its span
+#[derive(Debug)]
+struct OpTiming {
+    start: Instant,
+    /// How many times the span is entered. The operation is done when it's exited for the last time.
+    depth: usize,
+    done: bool,
+    ino: Option<u64>,
+    name: Option<String>,
+    requests: Arc<Mutex<Vec<RequestTiming>>>,
+}
+
+/// An S3 request made by a FUSE operation
+#[derive(Debug)]
+struct RequestTiming {
+    start: Instant,
+    /// How long the request took, or `None` if it's still in flight
+    duration: Option<Duration>,
+    request_id: Option<String>,
+}
+
+impl RequestTiming {
+    fn duration(&self) -> Duration {
+        self.duration.unwrap_or_else(|| self.start.elapsed())
+    }
+}
+
+/// Links an S3 request's span to the [RequestTiming] of the operation that made it
+#[derive(Debug)]
+struct RequestLink {
+    requests: Arc<Mutex<Vec<RequestTiming>>>,
+    index: usize,
+}
+
+impl SlowOpLayer {
+    fn new(config: SlowOpConfig) -> Self {
+        Self {
+            config,
+            rate_limit: Mutex::new(RateLimit {
+                window_start: Instant::now(),
+                logged: 0,
+                suppressed: 0,
+            }),
+        }
+    }
+
+    fn threshold(&self, op: &str) -> Duration {
+        if DATA_OPS.contains(&op) {
+            self.config.data_threshold
+        } else {
+            self.config.metadata_threshold
+        }
+    }
+
+    /// Count a slow operation against the rate limit. Returns how many records were suppressed
+    /// since the last one, or `None` if this one should be suppressed too.
+    fn admit(&self) -> Option<u64> {
+        let mut rate_limit = self.rate_limit.lock().unwrap();
+        if rate_limit.window_start.elapsed() >= Duration::from_secs(60) {
+            rate_limit.window_start = Instant::now();
+            rate_limit.logged = 0;
+        }
+        if rate_limit.logged >= self.config.max_records_per_minute {
+            rate_limit.suppressed += 1;
+            return None;
+        }
+        rate_limit.logged += 1;
+        Some(std::mem::take(&mut rate_limit.suppressed))
+    }
+
+    fn finish_op(&self, op: &'static str, timing: &OpTiming) {
+        let duration = timing.start.elapsed();
+        if duration <= self.threshold(op) {
+            return;
+        }
+        let Some(suppressed) = self.admit() else {
+            return;
+        };
+
+        let requests = timing.requests.lock().unwrap();
+        let slowest = requests.iter().max_by_key(|request| request.duration());
+        let slowest_request_duration = slowest.map(|request| request.duration());
+        let slowest_request_id = slowest.and_then(|request| request.request_id.as_deref());
+        tracing::warn!(
+            target: SLOW_OP_TARGET,
+            parent: None,
+            op,
+            ino = timing.ino,
+            name = timing.name.as_deref(),
+            ?duration,
+            s3_requests = requests.len(),
+            ?slowest_request_duration,
+            slowest_request_id,
+            suppressed,
+            "slow {op} operation",
+        );
+    }
+}
+
+impl<S> Layer<S> for SlowOpLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let metadata = span.metadata();
+        if metadata.target() == FUSE_MODULE_NAME && span.parent().is_none() {
+            let mut timing = OpTiming {
+                start: Instant::now(),
+                depth: 0,
+                done: false,
+                ino: None,
+                name: None,
+                requests: Default::default(),
+            };
+            attrs.record(&mut OpFieldVisitor(&mut timing));
+            span.extensions_mut().insert(timing);
+        } else if metadata.target().starts_with(CLIENT_CRATE_NAME) && metadata.target().ends_with("::request") {
+            let Some(root) = span.scope().from_root().next() else {
+                return;
+            };
+            let Some(timing) = root
+                .extensions()
+                .get::<OpTiming>()
+                .map(|timing| timing.requests.clone())
+            else {
+                return;
+            };
+            let index = {
+                let mut requests = timing.lock().unwrap();
+                requests.push(RequestTiming {
+                    start: Instant::now(),
+                    duration: None,
+                    request_id: None,
+                });
+                requests.len() - 1
+            };
+            span.extensions_mut().insert(RequestLink {
+                requests: timing,
+                index,
+            });
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        if let Some(timing) = span.extensions_mut().get_mut::<OpTiming>() {
+            values.record(&mut OpFieldVisitor(timing));
+        }
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        if let Some(timing) = span.extensions_mut().get_mut::<OpTiming>() {
+            timing.depth += 1;
+        }
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        let Some(timing) = extensions.get_mut::<OpTiming>() else {
+            return;
+        };
+        timing.depth = timing.depth.saturating_sub(1);
+        // Requests the operation started, like prefetches, can keep its span open after it
+        // returns, so it's done when it's last exited rather than when it's closed
+        if timing.depth == 0 && !timing.done {
+            timing.done = true;
+            self.finish_op(span.name(), timing);
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.event_span(event) else {
+            return;
+        };
+        if let Some(link) = span.extensions().get::<RequestLink>() {
+            let mut visitor = RequestIdVisitor(None);
+            event.record(&mut visitor);
+            if let Some(request_id) = visitor.0 {
+                link.requests.lock().unwrap()[link.index].request_id = Some(request_id);
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        if let Some(link) = span.extensions().get::<RequestLink>() {
+            let mut requests = link.requests.lock().unwrap();
+            let request = &mut requests[link.index];
+            request.duration = Some(request.start.elapsed());
+        }
+    }
+}
+
+/// Picks the inode and file name out of a FUSE operation's span fields
+struct OpFieldVisitor<'a>(&'a mut OpTiming);
+
+impl Visit for OpFieldVisitor<'_> {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        if field.name() == "ino" {
+            self.0.ino = Some(value);
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "name" {
+            self.0.name = Some(value.to_owned());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "name" {
+            self.0.name = Some(format!("{value:?}"));
+        }
+    }
+}
+
+/// Picks the request ID out of an S3 request's events
+struct RequestIdVisitor(Option<String>);
+
+impl Visit for RequestIdVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "request_id" {
+            self.0 = Some(value.to_owned());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "request_id" {
+            self.0 = Some(format!("{value:?}"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+
+    use futures::executor::{block_on, ThreadPool};
+    use mountpoint_s3_client::mock_client::{MockClient, MockClientConfig, MockObject, Operation};
+    use mountpoint_s3_client::types::ETag;
+    use tracing::{field, Dispatch, Instrument};
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::Registry;
+
+    use super::*;
+    use crate::fs::FUSE_ROOT_INODE;
+    use crate::prefetch::default_prefetch;
+    use crate::S3Filesystem;
+
+    /// A [Layer] that keeps the fields of every slow operation record
+    #[derive(Clone, Default)]
+    struct CaptureLayer(Arc<Mutex<Vec<Vec<(String, String)>>>>);
+
+    struct FieldCollector(Vec<(String, String)>);
+
+    impl Visit for FieldCollector {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.0.push((field.name().to_owned(), format!("{value:?}")));
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for CaptureLayer {
+        fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+            if event.metadata().target() == SLOW_OP_TARGET {
+                let mut collector = FieldCollector(Vec::new());
+                event.record(&mut collector);
+                self.0.lock().unwrap().push(collector.0);
+            }
+        }
+    }
+
+    impl CaptureLayer {
+        fn take(&self) -> Vec<Vec<(String, String)>> {
+            std::mem::take(&mut *self.0.lock().unwrap())
+        }
+    }
+
+    fn field<'a>(record: &'a [(String, String)], name: &str) -> &'a str {
+        &record
+            .iter()
+            .find(|(field, _)| field == name)
+            .expect("record has field")
+            .1
+    }
+
+    /// Run a file system operation the way the FUSE layer does: on this thread, in a span named
+    /// after it that's entered for the whole operation
+    fn run_op<T>(op: &'static str, ino: u64, future: impl Future<Output = T>) -> T {
+        let span = match op {
+            "lookup" => tracing::warn_span!(target: "mountpoint_s3::fuse", "lookup", ino, name = field::Empty),
+            "read" => tracing::warn_span!(target: "mountpoint_s3::fuse", "read", ino, name = field::Empty),
+            _ => unreachable!("unexpected op {op}"),
+        };
+        span.in_scope(|| block_on(future.in_current_span()))
+    }
+
+    #[test]
+    fn logs_slow_read() {
+        const GET_LATENCY: Duration = Duration::from_millis(300);
+
+        let capture = CaptureLayer::default();
+        let config = SlowOpConfig {
+            metadata_threshold: Duration::from_secs(60),
+            data_threshold: GET_LATENCY / 2,
+            max_records_per_minute: 10,
+        };
+        let subscriber = Registry::default().with(slow_op_layer(config)).with(capture.clone());
+        let dispatch = Dispatch::new(subscriber);
+
+        let client = Arc::new(MockClient::new(MockClientConfig {
+            bucket: "bucket".to_owned(),
+            part_size: 1024 * 1024,
+            ..Default::default()
+        }));
+        client.add_object("slow.bin", MockObject::constant(0xaa, 4096, ETag::for_tests()));
+        client.add_object("fast.bin", MockObject::constant(0xbb, 4096, ETag::for_tests()));
+
+        // Prefetch requests run on the pool's thread, which has to log to the same subscriber
+        let pool_dispatch = dispatch.clone();
+        let runtime = ThreadPool::builder()
+            .pool_size(1)
+            .after_start(move |_| std::mem::forget(tracing::dispatcher::set_default(&pool_dispatch)))
+            .create()
+            .unwrap();
+        let prefetcher = default_prefetch(runtime, Default::default());
+        let fs = S3Filesystem::new(
+            client.clone(),
+            prefetcher,
+            "bucket",
+            &Default::default(),
+            Default::default(),
+        );
+
+        tracing::dispatcher::with_default(&dispatch, || {
+            for (name, latency) in [("fast.bin", Duration::ZERO), ("slow.bin", GET_LATENCY)] {
+                let ino = run_op("lookup", FUSE_ROOT_INODE, fs.lookup(FUSE_ROOT_INODE, name.as_ref()))
+                    .unwrap()
+                    .attr
+                    .ino;
+                let fh = block_on(fs.open(ino, libc::O_RDONLY, 0)).unwrap().fh;
+                client.set_operation_latency(Operation::GetObject, latency);
+                let data = run_op("read", ino, fs.read(ino, fh, 0, 4096, 0, None)).unwrap();
+                assert_eq!(data.len(), 4096);
+                block_on(fs.release(ino, fh, 0, None, false)).unwrap();
+
+                let records = capture.take();
+                if latency.is_zero() {
+                    assert!(records.is_empty(), "fast operations shouldn't be logged: {records:?}");
+                    continue;
+                }
+                assert_eq!(records.len(), 1, "slow read should be logged once: {records:?}");
+                let record = &records[0];
+                assert_eq!(field(record, "op"), "\"read\"");
+                assert_eq!(field(record, "ino"), ino.to_string());
+                assert_eq!(field(record, "name"), "\"slow.bin\"");
+                assert_eq!(field(record, "s3_requests"), "1");
+                assert!(field(record, "slowest_request_id").starts_with("\"mock-"));
+                assert!(field(record, "slowest_request_duration").starts_with("Some("));
+            }
+        });
+    }
+
+    #[test]
+    fn rate_limits_records() {
+        let layer = SlowOpLayer::new(SlowOpConfig {
+            max_records_per_minute: 2,
+            ..Default::default()
+        });
+        assert_eq!(layer.admit(), Some(0));
+        assert_eq!(layer.admit(), Some(0));
+        assert_eq!(layer.admit(), None);
+        assert_eq!(layer.admit(), None);
+
+        // The next record after the minute is up says how many were dropped
+        layer.rate_limit.lock().unwrap().window_start -= Duration::from_secs(60);
+        assert_eq!(layer.admit(), Some(2));
+        assert_eq!(layer.admit(), Some(0));
+        assert_eq!(layer.admit(), None);
+    }
+}
@@ -0,0 +1,129 @@
+//! A budget for the memory Mountpoint holds on to for file data.
+//!
+//! Several parts of Mountpoint keep object data in memory for a while: the buffers that hold
+//! prefetched parts until they're read, and the bytes of writes held back until they can be
+//! uploaded. A [MemoryLimiter] keeps a running total of that memory, so they can all be bounded by
+//! one budget. It's shared explicitly, through [S3FilesystemConfig](crate::fs::S3FilesystemConfig)
+//! and the [BufferPool](crate::prefetch::BufferPool), rather than through any global state, so
+//! filesystems that should share a budget can do so and others stay independent.
+
+use std::fmt::Debug;
+
+use metrics::gauge;
+
+use crate::sync::atomic::{AtomicU64, Ordering};
+
+/// A budget for memory used by file data. Reservations fail rather than exceed the limit.
+pub struct MemoryLimiter {
+    mem_limit: u64,
+    mem_reserved: AtomicU64,
+}
+
+impl MemoryLimiter {
+    /// Create a budget of `mem_limit` bytes
+    pub fn new(mem_limit: u64) -> Self {
+        Self {
+            mem_limit,
+            mem_reserved: AtomicU64::new(0),
+        }
+    }
+
+    /// Reserve `size` bytes if that wouldn't exceed the limit. Returns whether it was reserved.
+    pub fn try_reserve(&self, size: u64) -> bool {
+        let mut reserved = self.mem_reserved.load(Ordering::SeqCst);
+        loop {
+            let Some(new_reserved) = reserved.checked_add(size).filter(|&new| new <= self.mem_limit) else {
+                return false;
+            };
+            match self
+                .mem_reserved
+                .compare_exchange_weak(reserved, new_reserved, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => {
+                    gauge!("mem.bytes_reserved").increment(size as f64);
+                    return true;
+                }
+                Err(current) => reserved = current,
+            }
+        }
+    }
+
+    /// Reserve `size` bytes even if that exceeds the limit, for small amounts of memory that are
+    /// needed to make progress. Other reservations fail until enough is released again.
+    pub fn reserve(&self, size: u64) {
+        self.mem_reserved.fetch_add(size, Ordering::SeqCst);
+        gauge!("mem.bytes_reserved").increment(size as f64);
+    }
+
+    /// Give back `size` bytes reserved earlier
+    pub fn release(&self, size: u64) {
+        let previous = self.mem_reserved.fetch_sub(size, Ordering::SeqCst);
+        debug_assert!(previous >= size, "released more memory than was reserved");
+        gauge!("mem.bytes_reserved").decrement(size as f64);
+    }
+
+    /// Bytes currently reserved
+    pub fn mem_reserved(&self) -> u64 {
+        self.mem_reserved.load(Ordering::SeqCst)
+    }
+
+    /// Bytes that can be reserved in total
+    pub fn mem_limit(&self) -> u64 {
+        self.mem_limit
+    }
+}
+
+impl Default for MemoryLimiter {
+    /// A budget with no limit, which only keeps count
+    fn default() -> Self {
+        Self::new(u64::MAX)
+    }
+}
+
+impl Debug for MemoryLimiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MemoryLimiter")
+            .field("mem_limit", &self.mem_limit)
+            .field("mem_reserved", &self.mem_reserved())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserve_up_to_limit() {
+        let limiter = MemoryLimiter::new(100);
+        assert!(limiter.try_reserve(60));
+        assert!(!limiter.try_reserve(41));
+        assert!(limiter.try_reserve(40));
+        assert_eq!(limiter.mem_reserved(), 100);
+        assert!(!limiter.try_reserve(1));
+
+        limiter.release(60);
+        assert_eq!(limiter.mem_reserved(), 40);
+        assert!(limiter.try_reserve(1));
+    }
+
+    #[test]
+    fn test_reserve_past_limit() {
+        let limiter = MemoryLimiter::new(100);
+        assert!(limiter.try_reserve(90));
+        limiter.reserve(20);
+        assert_eq!(limiter.mem_reserved(), 110);
+        assert!(!limiter.try_reserve(1));
+
+        limiter.release(20);
+        assert!(limiter.try_reserve(10));
+    }
+
+    #[test]
+    fn test_default_is_unlimited() {
+        let limiter = MemoryLimiter::default();
+        assert!(limiter.try_reserve(u64::MAX / 2));
+        assert!(limiter.try_reserve(u64::MAX / 2));
+        assert!(!limiter.try_reserve(u64::MAX));
+    }
+}
@@ -83,6 +83,36 @@ fn poll_process_metrics(sys: &mut System) {
     }
 }
 
+/// One owner's part of a process-wide gauge, like the size of one file system's cache when
+/// several file systems run in the same process. Each part is added to the gauge rather than
+/// setting it, so the gauge reports the total across owners. Dropping the part removes it from the
+/// total again.
+#[derive(Debug)]
+pub(crate) struct GaugeShare {
+    name: &'static str,
+    value: f64,
+}
+
+impl GaugeShare {
+    pub(crate) fn new(name: &'static str) -> Self {
+        Self { name, value: 0.0 }
+    }
+
+    /// Set this owner's part of the gauge
+    pub(crate) fn set(&mut self, value: f64) {
+        if value != self.value {
+            metrics::gauge!(self.name).increment(value - self.value);
+            self.value = value;
+        }
+    }
+}
+
+impl Drop for GaugeShare {
+    fn drop(&mut self) {
+        self.set(0.0);
+    }
+}
+
 #[derive(Debug)]
 struct MetricsSink {
     metrics: DashMap<Key, Metric>,
@@ -144,10 +174,38 @@ impl MetricsSink {
 
 /// The actual recorder that will be installed for the metrics facade. Just a wrapper around a
 /// [MetricsSinkInner] that does all the real work.
-struct MetricsRecorder {
+pub(crate) struct MetricsRecorder {
     sink: Arc<MetricsSink>,
 }
 
+#[cfg(test)]
+impl MetricsRecorder {
+    /// Create a recorder that isn't installed globally, for tests to record into with
+    /// [metrics::with_local_recorder] and then inspect
+    pub(crate) fn new_for_test() -> Self {
+        Self {
+            sink: Arc::new(MetricsSink::new()),
+        }
+    }
+
+    /// Number of values recorded into the histogram `name` with the label `label`, which resets
+    /// the histogram
+    pub(crate) fn histogram_count(&self, name: &str, label: (&str, &str)) -> usize {
+        self.sink
+            .metrics
+            .iter()
+            .filter(|entry| {
+                let key = entry.key();
+                key.name() == name && key.labels().any(|l| l.key() == label.0 && l.value() == label.1)
+            })
+            .filter_map(|entry| match entry.value() {
+                Metric::Histogram(histogram) => histogram.run_and_reset(|histogram| histogram.len() as usize),
+                _ => None,
+            })
+            .sum()
+    }
+}
+
 impl Recorder for MetricsRecorder {
     fn describe_counter(
         &self,
@@ -306,4 +364,33 @@ mod tests {
             }
         });
     }
+
+    #[test]
+    fn gauge_shares_add_up() {
+        let sink = Arc::new(MetricsSink::new());
+        let recorder = MetricsRecorder { sink: sink.clone() };
+        let load_gauge = || {
+            let key = Key::from_name(TEST_GAUGE);
+            let entry = sink.metrics.get(&key).expect("gauge should be registered");
+            let Metric::Gauge(inner) = entry.value() else {
+                panic!("wrong metric type");
+            };
+            inner.load_if_changed()
+        };
+        with_local_recorder(&recorder, || {
+            let mut first = GaugeShare::new(TEST_GAUGE);
+            let mut second = GaugeShare::new(TEST_GAUGE);
+            first.set(5.0);
+            second.set(3.0);
+            assert_eq!(load_gauge(), Some(8.0));
+
+            first.set(2.0);
+            assert_eq!(load_gauge(), Some(5.0));
+
+            drop(second);
+            assert_eq!(load_gauge(), Some(2.0));
+            drop(first);
+            assert_eq!(load_gauge(), Some(0.0));
+        });
+    }
 }
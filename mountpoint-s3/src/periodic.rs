@@ -0,0 +1,128 @@
+//! Background threads that run a task periodically until they're stopped.
+
+use std::io;
+use std::ops::ControlFlow;
+use std::time::Duration;
+
+// The thread waits on real time, which Shuttle can't model, so this uses std's primitives rather
+// than [crate::sync]'s.
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+
+/// Handle to a background thread that calls a task every interval. The thread is stopped when the
+/// handle is dropped, and the drop waits for the task to finish if it's running.
+#[derive(Debug)]
+pub struct PeriodicTask {
+    stop: Sender<()>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl PeriodicTask {
+    /// Start a thread named `name` that calls `task` every `interval`, until the task returns
+    /// [ControlFlow::Break] or the handle is dropped
+    pub fn start<F>(name: &str, interval: Duration, task: F) -> io::Result<Self>
+    where
+        F: FnMut() -> ControlFlow<()> + Send + 'static,
+    {
+        Self::start_with_stop(name, interval, task, || {})
+    }
+
+    /// Like [Self::start], but also call `on_stop` on the thread once it stops, however it stops
+    pub fn start_with_stop<F, G>(name: &str, interval: Duration, mut task: F, on_stop: G) -> io::Result<Self>
+    where
+        F: FnMut() -> ControlFlow<()> + Send + 'static,
+        G: FnOnce() + Send + 'static,
+    {
+        let (stop, stopped) = mpsc::channel();
+        let handle = thread::Builder::new().name(name.to_owned()).spawn(move || {
+            loop {
+                match stopped.recv_timeout(interval) {
+                    Ok(()) | Err(RecvTimeoutError::Disconnected) => break,
+                    Err(RecvTimeoutError::Timeout) => {}
+                }
+                if task().is_break() {
+                    break;
+                }
+            }
+            on_stop();
+        })?;
+        Ok(Self {
+            stop,
+            handle: Some(handle),
+        })
+    }
+}
+
+impl Drop for PeriodicTask {
+    fn drop(&mut self) {
+        let _ = self.stop.send(());
+        if let Some(handle) = self.handle.take() {
+            // The task may drop its own handle, like when it unmounts the file system it belongs
+            // to, and a thread can't wait for itself
+            if handle.thread().id() != thread::current().id() {
+                let _ = handle.join();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn runs_until_break() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let (stopped_tx, stopped_rx) = mpsc::channel();
+        let _task = PeriodicTask::start_with_stop(
+            "test-periodic",
+            Duration::from_millis(1),
+            {
+                let runs = runs.clone();
+                move || {
+                    if runs.fetch_add(1, Ordering::SeqCst) + 1 == 3 {
+                        ControlFlow::Break(())
+                    } else {
+                        ControlFlow::Continue(())
+                    }
+                }
+            },
+            move || stopped_tx.send(()).unwrap(),
+        )
+        .unwrap();
+        stopped_rx
+            .recv_timeout(Duration::from_secs(10))
+            .expect("task should stop itself");
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn drop_stops_task() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let stops = Arc::new(AtomicUsize::new(0));
+        let task = PeriodicTask::start_with_stop(
+            "test-periodic",
+            Duration::from_secs(3600),
+            {
+                let runs = runs.clone();
+                move || {
+                    runs.fetch_add(1, Ordering::SeqCst);
+                    ControlFlow::Continue(())
+                }
+            },
+            {
+                let stops = stops.clone();
+                move || {
+                    stops.fetch_add(1, Ordering::SeqCst);
+                }
+            },
+        )
+        .unwrap();
+        drop(task);
+        // The drop waits for the thread, so it's stopped by now, without waiting for the interval
+        assert_eq!(runs.load(Ordering::SeqCst), 0);
+        assert_eq!(stops.load(Ordering::SeqCst), 1);
+    }
+}
@@ -6,7 +6,12 @@
 //! wastefully download data we'll never read. As the reader continues to make sequential reads,
 //! we increase the size of the GetObject requests up to some maximum. If the reader ever makes a
 //! non-sequential read, we abandon the prefetching and start again with the minimum request size.
+//!
+//! Readers that pause between bursts of reads would otherwise keep everything their grown window
+//! prefetched in memory for the whole pause. [IdleBufferPolicy] controls what happens to that data
+//! once the reader has paused, see [PrefetchBuffers::release_idle_buffers].
 
+mod buffer_pool;
 mod caching_stream;
 mod part;
 mod part_queue;
@@ -14,6 +19,8 @@ mod part_stream;
 mod seek_window;
 mod task;
 
+pub use buffer_pool::BufferPool;
+
 use std::collections::VecDeque;
 use std::fmt::Debug;
 use std::time::Duration;
@@ -24,13 +31,15 @@ use metrics::{counter, histogram};
 use mountpoint_s3_client::error::{GetObjectError, ObjectClientError};
 use mountpoint_s3_client::types::ETag;
 use mountpoint_s3_client::ObjectClient;
+use serde::Deserialize;
 use thiserror::Error;
-use tracing::trace;
+use tracing::{debug, error, trace};
 
 use crate::checksums::{ChecksummedBytes, IntegrityError};
 use crate::data_cache::DataCache;
 use crate::object::ObjectId;
 use crate::prefetch::caching_stream::CachingPartStream;
+use crate::prefetch::part::{Part, PartMismatchError};
 use crate::prefetch::part_stream::{ClientPartStream, ObjectPartStream, RequestRange};
 use crate::prefetch::seek_window::SeekWindow;
 use crate::prefetch::task::RequestTask;
@@ -38,7 +47,7 @@ use crate::sync::Arc;
 
 /// Generic interface to handle reading data from an object.
 pub trait Prefetch {
-    type PrefetchResult<Client: ObjectClient + Send + Sync + 'static>: PrefetchResult<Client>;
+    type PrefetchResult<Client: ObjectClient + Send + Sync + 'static>: PrefetchResult<Client> + 'static;
 
     /// Start a new prefetch request to the specified object.
     fn prefetch<Client>(
@@ -53,17 +62,156 @@ pub trait Prefetch {
         Client: ObjectClient + Send + Sync + 'static;
 }
 
+/// The data a prefetch request holds, which can be released while its reader is paused
+pub trait PrefetchBuffers: Send + Sync {
+    /// Apply `policy` to the data this request has prefetched, because its reader has paused.
+    /// Returns whether any data was released, which it never is with [IdleBufferPolicy::Retain].
+    fn release_idle_buffers(&mut self, _policy: IdleBufferPolicy) -> bool {
+        false
+    }
+
+    /// How much data this request holds, and how much it has fetched from S3
+    fn stats(&self) -> PrefetchStats {
+        PrefetchStats::default()
+    }
+}
+
 /// Result of a prefetch request. Allows callers to read object data.
 #[async_trait]
-pub trait PrefetchResult<Client: ObjectClient>: Send + Sync {
-    /// Read some bytes from the object. This function will always return exactly `size` bytes,
-    /// except at the end of the object where it will return however many bytes are left (including
-    /// possibly 0 bytes).
+pub trait PrefetchResult<Client: ObjectClient>: PrefetchBuffers {
+    /// Read some bytes from the object as a sequence of buffers. Together, the buffers will always
+    /// hold exactly `size` bytes, except at the end of the object where they will hold however many
+    /// bytes are left (including possibly 0 bytes). The buffers share memory with the prefetched
+    /// parts, so no data is copied.
+    async fn read_vectored(
+        &mut self,
+        offset: u64,
+        length: usize,
+    ) -> Result<Vec<ChecksummedBytes>, PrefetchReadError<Client::ClientError>> {
+        let (parts, _source) = self.read_vectored_with_source(offset, length).await?;
+        Ok(parts)
+    }
+
+    /// Like [read_vectored](Self::read_vectored), but also reports where the data came from.
+    async fn read_vectored_with_source(
+        &mut self,
+        offset: u64,
+        length: usize,
+    ) -> Result<(Vec<ChecksummedBytes>, ReadSource), PrefetchReadError<Client::ClientError>>;
+
+    /// Read some bytes from the object into a single buffer. This function will always return
+    /// exactly `size` bytes, except at the end of the object where it will return however many
+    /// bytes are left (including possibly 0 bytes). Unlike [read_vectored](Self::read_vectored),
+    /// this copies the data if the read spans more than one prefetched part.
     async fn read(
         &mut self,
         offset: u64,
         length: usize,
-    ) -> Result<ChecksummedBytes, PrefetchReadError<Client::ClientError>>;
+    ) -> Result<ChecksummedBytes, PrefetchReadError<Client::ClientError>> {
+        let mut parts = self.read_vectored(offset, length).await?.into_iter();
+        let mut response = parts.next().unwrap_or_default();
+        for part in parts {
+            response.extend(part)?;
+        }
+        Ok(response)
+    }
+}
+
+/// What a prefetch request does with the data it has prefetched once its reader has paused, see
+/// [PrefetchBuffers::release_idle_buffers]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum IdleBufferPolicy {
+    /// Keep everything, so reads after the pause continue from the prefetched data at full speed
+    #[default]
+    Retain,
+    /// Keep up to `floor` bytes of the data already downloaded ahead of the reader, cancel the
+    /// rest, and shrink the next request to at most `floor` bytes (but no less than the first
+    /// request size)
+    Shrink { floor: usize },
+    /// Cancel everything, and start again from the first request size when reads resume
+    Reset,
+}
+
+impl IdleBufferPolicy {
+    fn as_str(&self) -> &'static str {
+        match self {
+            IdleBufferPolicy::Retain => "retain",
+            IdleBufferPolicy::Shrink { .. } => "shrink",
+            IdleBufferPolicy::Reset => "reset",
+        }
+    }
+}
+
+/// How much data a prefetch request holds, and how much it has fetched from S3, see
+/// [PrefetchBuffers::stats]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PrefetchStats {
+    /// Bytes downloaded ahead of the reader that it hasn't read yet
+    pub buffered_bytes: u64,
+    /// Bytes requested ahead of the reader that haven't been downloaded yet
+    pub inflight_bytes: u64,
+    /// Bytes already read that are kept for backwards seeks
+    pub seek_window_bytes: u64,
+    /// Size of the next request, which grows as the reader keeps reading sequentially
+    pub next_request_size: u64,
+    /// Number of GetObject requests made
+    pub requests: u64,
+    /// Total bytes requested from S3
+    pub requested_bytes: u64,
+    /// Bytes requested again, after an earlier request had already asked for them
+    pub refetched_bytes: u64,
+    /// Number of times the [IdleBufferPolicy] released data after the reader paused
+    pub idle_releases: u64,
+}
+
+impl std::ops::Add for PrefetchStats {
+    type Output = Self;
+
+    /// The combined stats of two requests
+    fn add(self, other: Self) -> Self {
+        Self {
+            buffered_bytes: self.buffered_bytes + other.buffered_bytes,
+            inflight_bytes: self.inflight_bytes + other.inflight_bytes,
+            seek_window_bytes: self.seek_window_bytes + other.seek_window_bytes,
+            next_request_size: self.next_request_size + other.next_request_size,
+            requests: self.requests + other.requests,
+            requested_bytes: self.requested_bytes + other.requested_bytes,
+            refetched_bytes: self.refetched_bytes + other.refetched_bytes,
+            idle_releases: self.idle_releases + other.idle_releases,
+        }
+    }
+}
+
+/// Where the data returned by a read came from, in bytes
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReadSource {
+    /// Served from the data cache
+    pub cache_bytes: usize,
+    /// Already downloaded by the prefetcher when the read asked for it
+    pub buffered_bytes: usize,
+    /// Downloaded while the read waited for it
+    pub fetched_bytes: usize,
+}
+
+impl ReadSource {
+    /// Total number of bytes read
+    pub fn total_bytes(&self) -> usize {
+        self.cache_bytes + self.buffered_bytes + self.fetched_bytes
+    }
+
+    /// Record a part read at `offset` from a request that had received data up to `available_offset`
+    /// before the read
+    fn record(&mut self, part: &Part, offset: u64, available_offset: u64) {
+        let len = part.len();
+        if part.is_from_cache() {
+            self.cache_bytes += len;
+        } else if offset + len as u64 <= available_offset {
+            self.buffered_bytes += len;
+        } else {
+            self.fetched_bytes += len;
+        }
+    }
 }
 
 #[derive(Debug, Error)]
@@ -77,10 +225,26 @@ pub enum PrefetchReadError<E> {
     #[error("get request terminated unexpectedly")]
     GetRequestTerminatedUnexpectedly,
 
+    /// The response ended, without error, before the end of the requested range. S3 does this
+    /// when a range reaches past the end of the object, so the object has shrunk since we learned
+    /// its size.
+    #[error("get object request ended at offset {offset}, before the end of the requested range")]
+    GetRequestEndedEarly { offset: u64 },
+
+    #[error("get request task panicked: {0}")]
+    GetRequestPanicked(String),
+
     #[error("integrity check failed")]
     Integrity(#[from] IntegrityError),
+
+    #[error("prefetched part doesn't continue from the previous one")]
+    PartMismatch(#[from] PartMismatchError),
 }
 
+/// Largest size of the parts we split downloaded data into, and so also a good size for the buffers
+/// of a [BufferPool] the client copies downloaded data into
+pub const MAX_PREFERRED_PART_SIZE: usize = 1024 * 1024;
+
 pub type DefaultPrefetcher<Runtime> = Prefetcher<ClientPartStream<Runtime>>;
 
 /// Creates an instance of the default [Prefetch].
@@ -124,6 +288,18 @@ pub struct PrefetcherConfig {
     /// The maximum distance the prefetcher will seek backwards before resetting and starting a new
     /// S3 request. We keep this much data in memory in addition to any inflight requests.
     pub max_backward_seek_distance: u64,
+    /// Minimum size of any request, however small the read that triggers it. Data fetched beyond
+    /// the read is kept for nearby subsequent reads.
+    pub min_read_request_size: usize,
+    /// Largest gap, in bytes, between the end of the inflight requests and a forward read that we'll
+    /// fetch rather than skip. Reads closer than this continue the current run of requests, so the
+    /// gap is downloaded and kept for backwards seeks instead of starting over with a new small
+    /// request. 0 disables coalescing.
+    pub read_coalesce_gap: u64,
+    /// Objects smaller than this many bytes aren't prefetched. Instead, the first read fetches the
+    /// whole object in a single request and keeps it in memory, so later reads of any part of it
+    /// don't make new requests. 0 disables this.
+    pub prefetch_min_file_size: u64,
 }
 
 impl Default for PrefetcherConfig {
@@ -148,6 +324,9 @@ impl Default for PrefetcherConfig {
             // just start a new request instead.
             max_forward_seek_wait_distance: 16 * 1024 * 1024,
             max_backward_seek_distance: 1 * 1024 * 1024,
+            min_read_request_size: 0,
+            read_coalesce_gap: 0,
+            prefetch_min_file_size: 0,
         }
     }
 }
@@ -224,6 +403,12 @@ pub struct PrefetchGetObject<Stream: ObjectPartStream, Client: ObjectClient> {
     next_request_size: usize,
     next_request_offset: u64,
     size: u64,
+    /// Whether the object is below [PrefetcherConfig::prefetch_min_file_size], so is fetched whole
+    /// rather than prefetched
+    whole_object: bool,
+    /// End of the furthest range requested so far, to count data that's requested again
+    requested_end_offset: u64,
+    stats: PrefetchStats,
 }
 
 #[async_trait]
@@ -232,14 +417,11 @@ where
     Stream: ObjectPartStream + Send + Sync + 'static,
     Client: ObjectClient + Send + Sync + 'static,
 {
-    /// Read some bytes from the object. This function will always return exactly `size` bytes,
-    /// except at the end of the object where it will return however many bytes are left (including
-    /// possibly 0 bytes).
-    async fn read(
+    async fn read_vectored_with_source(
         &mut self,
         offset: u64,
         length: usize,
-    ) -> Result<ChecksummedBytes, PrefetchReadError<Client::ClientError>> {
+    ) -> Result<(Vec<ChecksummedBytes>, ReadSource), PrefetchReadError<Client::ClientError>> {
         trace!(
             offset,
             length,
@@ -254,15 +436,20 @@ where
         // We initialize this value to 128k as it is the Linux's readahead size
         // and it can also be used as a lower bound in case the read size is too small.
         // The upper bound is 1MiB since it should be a common IO size.
-        let max_preferred_part_size = 1024 * 1024;
-        self.preferred_part_size = self.preferred_part_size.max(length).min(max_preferred_part_size);
+        self.preferred_part_size = self.preferred_part_size.max(length).min(MAX_PREFERRED_PART_SIZE);
 
         let remaining = self.size.saturating_sub(offset);
         if remaining == 0 {
-            return Ok(ChecksummedBytes::default());
+            return Ok((Vec::new(), ReadSource::default()));
         }
         let mut to_read = (length as u64).min(remaining);
 
+        // The first read of a small object fetches the whole object, wherever the read starts, and
+        // seeks forward within it if it needs to
+        if self.whole_object && self.current_task.is_none() && self.next_request_offset == 0 {
+            self.current_task = self.spawn_next_request();
+        }
+
         // Try to seek if this read is not sequential, and if seeking fails, cancel and reset the
         // prefetcher.
         if self.next_sequential_read_offset != offset {
@@ -286,7 +473,8 @@ where
 
         self.prepare_requests();
 
-        let mut response = ChecksummedBytes::default();
+        let mut response = Vec::new();
+        let mut source = ReadSource::default();
         while to_read > 0 {
             let Some(current_task) = self.current_task.as_mut() else {
                 // If [prepare_requests] didn't spawn a request, we've reached the end of the object.
@@ -295,6 +483,7 @@ where
             };
             debug_assert!(current_task.remaining() > 0);
 
+            let available_offset = current_task.available_offset();
             let part = match current_task.read(to_read as usize).await {
                 Err(e) => {
                     self.reset_prefetch_to_offset(offset);
@@ -302,36 +491,85 @@ where
                 }
                 Ok(part) => part,
             };
-            self.backward_seek_window.push(part.clone());
-            let part_bytes = part
-                .into_bytes(&self.object_id, self.next_sequential_read_offset)
-                .unwrap();
+            // A read can span parts from more than one request. Each part must start exactly where
+            // the last one ended, or we'd return a gap or duplicated bytes at the seam.
+            let checked = part
+                .clone()
+                .into_bytes(&self.object_id, self.next_sequential_read_offset);
+            let part_bytes = match checked {
+                Ok(part_bytes) => part_bytes,
+                Err(e) => {
+                    error!(error = ?e, "prefetched parts don't line up");
+                    self.reset_prefetch_to_offset(offset);
+                    return Err(e.into());
+                }
+            };
+            // Parts of a read that spans several of them are validated here, so that corrupt data
+            // cancels the requests in flight rather than prefetching more. A read of a single part
+            // is validated when its caller takes the bytes.
+            if !response.is_empty() || (part_bytes.len() as u64) < to_read {
+                if let Err(e) = part_bytes.validate() {
+                    error!(error = ?e, "prefetched data is corrupt");
+                    // cancel inflight tasks
+                    self.reset_prefetch_to_offset(offset);
+                    return Err(e.into());
+                }
+            }
+            source.record(&part, self.next_sequential_read_offset, available_offset);
+            self.backward_seek_window.push(part);
 
             self.next_sequential_read_offset += part_bytes.len() as u64;
             self.prepare_requests();
 
-            // If we can complete the read with just a single buffer, early return to avoid copying
-            // into a new buffer. This should be the common case as long as part size is larger than
-            // read size, which it almost always is for real S3 clients and FUSE.
-            if response.is_empty() && part_bytes.len() == to_read as usize {
-                return Ok(part_bytes);
-            }
+            to_read -= part_bytes.len() as u64;
+            response.push(part_bytes);
+        }
 
-            let part_len = part_bytes.len() as u64;
-            let result = response.extend(part_bytes);
-            match result {
-                Ok(()) => {}
-                Err(e @ IntegrityError::ChecksumMismatch(_, _)) => {
-                    // cancel inflight tasks
-                    self.current_task = None;
-                    self.future_tasks.drain(..);
-                    return Err(e.into());
-                }
-            }
-            to_read -= part_len;
+        Ok((response, source))
+    }
+}
+
+impl<Stream, Client> PrefetchBuffers for PrefetchGetObject<Stream, Client>
+where
+    Stream: ObjectPartStream + Send + Sync + 'static,
+    Client: ObjectClient + Send + Sync + 'static,
+{
+    fn release_idle_buffers(&mut self, policy: IdleBufferPolicy) -> bool {
+        let before = self.stats();
+        match policy {
+            IdleBufferPolicy::Retain => return false,
+            IdleBufferPolicy::Shrink { floor } => self.shrink_to(floor),
+            IdleBufferPolicy::Reset => self.reset_prefetch_to_offset(self.next_sequential_read_offset),
         }
+        self.stats.idle_releases += 1;
+        let after = self.stats();
+        // Data can arrive while we shrink, so what's kept can exceed what was buffered before
+        let released_bytes =
+            (before.buffered_bytes + before.inflight_bytes).saturating_sub(after.buffered_bytes + after.inflight_bytes);
+        debug!(
+            key = self.object_id.key(),
+            policy = policy.as_str(),
+            released_bytes,
+            "released prefetched data of idle request"
+        );
+        counter!("prefetch.idle_releases", "policy" => policy.as_str()).increment(1);
+        counter!("prefetch.idle_released_bytes", "policy" => policy.as_str()).increment(released_bytes);
+        true
+    }
 
-        Ok(response)
+    fn stats(&self) -> PrefetchStats {
+        let tasks = self.current_task.iter().chain(self.future_tasks.iter());
+        let (buffered_bytes, inflight_bytes) = tasks.fold((0, 0), |(buffered, inflight), task| {
+            let unavailable = task.end_offset().saturating_sub(task.available_offset());
+            (buffered + task.buffered() as u64, inflight + unavailable)
+        });
+        PrefetchStats {
+            buffered_bytes,
+            inflight_bytes,
+            seek_window_bytes: self.backward_seek_window.size() as u64,
+            next_request_size: self.next_request_size as u64,
+            ..self.stats
+        }
     }
 }
 
@@ -350,13 +588,21 @@ where
         size: u64,
         etag: ETag,
     ) -> Self {
+        // Small objects are kept in memory once fetched, so reads of any part of them can be served
+        // by seeking backwards
+        let whole_object = size < config.prefetch_min_file_size;
+        let backward_seek_distance = if whole_object {
+            config.max_backward_seek_distance.max(size)
+        } else {
+            config.max_backward_seek_distance
+        };
         PrefetchGetObject {
             client,
             part_stream,
             config,
             current_task: None,
             future_tasks: Default::default(),
-            backward_seek_window: SeekWindow::new(config.max_backward_seek_distance as usize),
+            backward_seek_window: SeekWindow::new(backward_seek_distance as usize),
             preferred_part_size: 128 * 1024,
             sequential_read_start_offset: 0,
             next_sequential_read_offset: 0,
@@ -365,6 +611,9 @@ where
             bucket: bucket.to_owned(),
             object_id: ObjectId::new(key.to_owned(), etag),
             size,
+            whole_object,
+            requested_end_offset: 0,
+            stats: Default::default(),
         }
     }
 
@@ -396,12 +645,28 @@ where
 
     /// Spawn the next required request
     fn spawn_next_request(&mut self) -> Option<RequestTask<Client::ClientError>> {
+        self.spawn_next_request_after_gap(0)
+    }
+
+    /// Spawn the next required request, extended by `gap` bytes so that it still covers as much
+    /// data past the gap as a request would normally fetch
+    fn spawn_next_request_after_gap(&mut self, gap: u64) -> Option<RequestTask<Client::ClientError>> {
         let start = self.next_request_offset;
         if start >= self.size {
             return None;
         }
 
-        let range = RequestRange::new(self.size as usize, start, self.next_request_size);
+        // Never plan past the end of the object, however large the window has grown
+        let remaining = (self.size - start) as usize;
+        let request_size = if self.whole_object {
+            remaining
+        } else {
+            self.next_request_size
+                .max(self.config.min_read_request_size)
+                .saturating_add(gap as usize)
+                .min(remaining)
+        };
+        let range = RequestRange::new(self.size as usize, start, request_size);
         let task = self.part_stream.spawn_get_object_request(
             &self.client,
             &self.bucket,
@@ -411,6 +676,19 @@ where
             self.preferred_part_size,
         );
 
+        counter!("prefetch.bytes_requested").increment(task.total_size() as u64);
+        histogram!("prefetch.request_size").record(task.total_size() as f64);
+
+        let end = task.end_offset();
+        self.stats.requests += 1;
+        self.stats.requested_bytes += task.total_size() as u64;
+        if start < self.requested_end_offset {
+            let refetched = end.min(self.requested_end_offset) - start;
+            self.stats.refetched_bytes += refetched;
+            counter!("prefetch.bytes_refetched").increment(refetched);
+        }
+        self.requested_end_offset = self.requested_end_offset.max(end);
+
         // [read] will reset these if the reader stops making sequential requests
         self.next_request_offset += task.total_size() as u64;
         self.next_request_size = self.get_next_request_size(task.total_size());
@@ -440,6 +718,33 @@ where
         self.next_request_offset = offset;
     }
 
+    /// Keep up to `floor` bytes of the data already downloaded ahead of the reader, and cancel
+    /// everything else ahead of it. The next request continues from the kept data, with its size
+    /// cut to `floor` (but no less than the first request size).
+    fn shrink_to(&mut self, floor: usize) {
+        let mut kept = Vec::new();
+        let mut kept_size = 0;
+        let tasks = self.current_task.take().into_iter().chain(self.future_tasks.drain(..));
+        'tasks: for mut task in tasks {
+            while kept_size < floor && task.remaining() > 0 {
+                match task.try_read(floor - kept_size) {
+                    Some(Ok(part)) => {
+                        kept_size += part.len();
+                        kept.push(part);
+                    }
+                    // The rest of the data will be fetched again when it's read
+                    Some(Err(_)) | None => break 'tasks,
+                }
+            }
+        }
+        let offset = self.next_sequential_read_offset;
+        if kept_size > 0 {
+            self.current_task = Some(RequestTask::from_parts(kept, offset));
+        }
+        self.next_request_offset = offset + kept_size as u64;
+        self.next_request_size = self.next_request_size.min(floor.max(self.config.first_request_size));
+    }
+
     /// Try to seek within the current inflight requests without restarting them. Returns true if
     /// the seek succeeded, in which case self.next_sequential_read_offset will be updated to the
     /// new offset. If this returns false, the prefetcher is in an unknown state and must be reset.
@@ -475,6 +780,19 @@ where
                     self.next_sequential_read_offset = next_request.end_offset();
                 }
             }
+            if self.current_task.is_none()
+                && self.next_sequential_read_offset == self.next_request_offset
+                && offset - self.next_request_offset < self.config.read_coalesce_gap
+                && offset < self.size
+            {
+                // The target is only a small gap past the inflight data, so fetch the gap as part of
+                // the next request in this run rather than starting a new run at the target. The
+                // gap can be larger than the request would otherwise be, so extend it to reach past
+                // the target.
+                let gap = offset - self.next_request_offset;
+                trace!(gap, "coalescing read across gap");
+                self.current_task = self.spawn_next_request_after_gap(gap);
+            }
             if self.current_task.is_none() {
                 // No inflight task containing the target offset.
                 trace!(current_offset=?self.next_sequential_read_offset, requested_offset=?offset, "seek failed: not enough inflight data");
@@ -555,19 +873,27 @@ mod tests {
     // It's convenient to write test constants like "1 * 1024 * 1024" for symmetry
     #![allow(clippy::identity_op)]
 
-    use crate::data_cache::InMemoryDataCache;
+    use crate::data_cache::{BlockIndex, DataCacheResult, InMemoryDataCache};
     use crate::prefetch::part_stream::ClientPartStream;
 
     use super::caching_stream::CachingPartStream;
     use super::*;
+    use bytes::Bytes;
     use futures::executor::{block_on, ThreadPool};
     use mountpoint_s3_client::error::{GetObjectError, ObjectClientError};
-    use mountpoint_s3_client::failure_client::{countdown_failure_client, RequestFailureMap};
-    use mountpoint_s3_client::mock_client::{ramp_bytes, MockClient, MockClientConfig, MockClientError, MockObject};
+    use mountpoint_s3_client::failure_client::{
+        countdown_failure_client, FailureClient, FailureRequestWrapper, RequestFailureMap,
+    };
+    use mountpoint_s3_client::mock_client::{
+        ramp_bytes, MockClient, MockClientConfig, MockClientError, MockObject, Operation,
+    };
+    use mountpoint_s3_crt::checksums::crc32c::Crc32c;
     use proptest::proptest;
     use proptest::strategy::{Just, Strategy};
     use proptest_derive::Arbitrary;
     use std::collections::HashMap;
+    use std::ops::Range;
+    use std::sync::Mutex;
     use test_case::test_case;
 
     const MB: usize = 1024 * 1024;
@@ -593,13 +919,63 @@ mod tests {
         ClientPartStream::new(runtime)
     }
 
+    fn pooled_stream(max_buffers: usize) -> PooledStream {
+        PooledStream {
+            stream: default_stream(),
+            buffer_pool: BufferPool::new(MAX_PREFERRED_PART_SIZE, max_buffers, Default::default()),
+        }
+    }
+
     fn caching_stream(block_size: usize) -> CachingPartStream<InMemoryDataCache, ThreadPool> {
         let runtime = ThreadPool::builder().pool_size(1).create().unwrap();
         let cache = InMemoryDataCache::new(block_size as u64);
         CachingPartStream::new(runtime, cache)
     }
 
-    fn run_sequential_read_test<Stream: ObjectPartStream + Send + Sync + 'static>(
+    /// A part stream to run tests with, and how to set up the client it reads from
+    trait TestStream: ObjectPartStream + Send + Sync + 'static {
+        fn client(&self, config: MockClientConfig) -> MockClient {
+            MockClient::new(config)
+        }
+    }
+
+    impl TestStream for ClientPartStream<ThreadPool> {}
+
+    impl TestStream for CachingPartStream<InMemoryDataCache, ThreadPool> {}
+
+    /// A [ClientPartStream] whose client copies response bodies into pooled buffers
+    struct PooledStream {
+        stream: ClientPartStream<ThreadPool>,
+        buffer_pool: BufferPool,
+    }
+
+    impl ObjectPartStream for PooledStream {
+        fn spawn_get_object_request<Client>(
+            &self,
+            client: &Client,
+            bucket: &str,
+            key: &str,
+            if_match: ETag,
+            range: RequestRange,
+            preferred_part_size: usize,
+        ) -> RequestTask<Client::ClientError>
+        where
+            Client: ObjectClient + Clone + Send + Sync + 'static,
+        {
+            self.stream
+                .spawn_get_object_request(client, bucket, key, if_match, range, preferred_part_size)
+        }
+    }
+
+    impl TestStream for PooledStream {
+        fn client(&self, config: MockClientConfig) -> MockClient {
+            let client = MockClient::new(config);
+            client.set_body_buffer_pool(Arc::new(self.buffer_pool.clone()));
+            client
+        }
+    }
+
+    fn run_sequential_read_test<Stream: TestStream>(
         part_stream: Stream,
         size: u64,
         read_size: usize,
@@ -610,7 +986,7 @@ mod tests {
             part_size: test_config.client_part_size,
             ..Default::default()
         };
-        let client = Arc::new(MockClient::new(config));
+        let client = Arc::new(part_stream.client(config));
         let object = MockObject::ramp(0xaa, size as usize, ETag::for_tests());
         let etag = object.etag();
 
@@ -623,6 +999,9 @@ mod tests {
             read_timeout: Duration::from_secs(5),
             max_forward_seek_wait_distance: test_config.max_forward_seek_wait_distance,
             max_backward_seek_distance: test_config.max_backward_seek_distance,
+            min_read_request_size: 0,
+            read_coalesce_gap: 0,
+            prefetch_min_file_size: 0,
         };
 
         let prefetcher = Prefetcher::new(part_stream, prefetcher_config);
@@ -643,10 +1022,11 @@ mod tests {
     }
 
     #[test_case(default_stream())]
+    #[test_case(pooled_stream(1))]
     #[test_case(caching_stream(1 * MB))]
     fn sequential_read_small<Stream>(part_stream: Stream)
     where
-        Stream: ObjectPartStream + Send + Sync + 'static,
+        Stream: TestStream,
     {
         let config = TestConfig {
             first_request_size: 256 * 1024,
@@ -660,10 +1040,11 @@ mod tests {
     }
 
     #[test_case(default_stream())]
+    #[test_case(pooled_stream(1))]
     #[test_case(caching_stream(1 * MB))]
     fn sequential_read_medium<Stream>(part_stream: Stream)
     where
-        Stream: ObjectPartStream + Send + Sync + 'static,
+        Stream: TestStream,
     {
         let config = TestConfig {
             first_request_size: 256 * 1024,
@@ -677,10 +1058,11 @@ mod tests {
     }
 
     #[test_case(default_stream())]
+    #[test_case(pooled_stream(1))]
     #[test_case(caching_stream(1 * MB))]
     fn sequential_read_large<Stream>(part_stream: Stream)
     where
-        Stream: ObjectPartStream + Send + Sync + 'static,
+        Stream: TestStream,
     {
         let config = TestConfig {
             first_request_size: 256 * 1024,
@@ -694,7 +1076,7 @@ mod tests {
         run_sequential_read_test(part_stream, 256 * 1024 * 1024 + 111, 1024 * 1024, config);
     }
 
-    fn fail_sequential_read_test<Stream: ObjectPartStream + Send + Sync + 'static>(
+    fn fail_sequential_read_test<Stream: TestStream>(
         part_stream: Stream,
         size: u64,
         read_size: usize,
@@ -706,7 +1088,7 @@ mod tests {
             part_size: test_config.client_part_size,
             ..Default::default()
         };
-        let client = MockClient::new(config);
+        let client = part_stream.client(config);
         let object = MockObject::ramp(0xaa, size as usize, ETag::for_tests());
         let etag = object.etag();
 
@@ -743,13 +1125,15 @@ mod tests {
     }
 
     #[test_case("invalid range; length=42", default_stream())]
+    #[test_case("invalid range; length=42", pooled_stream(1))]
     #[test_case("invalid range; length=42", caching_stream(1 * MB))]
     // test case for the request failure due to etag not matching
     #[test_case("At least one of the pre-conditions you specified did not hold", default_stream())]
+    #[test_case("At least one of the pre-conditions you specified did not hold", pooled_stream(1))]
     #[test_case("At least one of the pre-conditions you specified did not hold", caching_stream(1 * MB))]
     fn fail_request_sequential_small<Stream>(err_value: &str, part_stream: Stream)
     where
-        Stream: ObjectPartStream + Send + Sync + 'static,
+        Stream: TestStream,
     {
         let config = TestConfig {
             first_request_size: 256 * 1024,
@@ -771,6 +1155,196 @@ mod tests {
         fail_sequential_read_test(part_stream, 1024 * 1024 + 111, 1024 * 1024, config, get_failures);
     }
 
+    #[test_case(default_stream())]
+    #[test_case(pooled_stream(1))]
+    #[test_case(caching_stream(1 * MB))]
+    fn test_incomplete_response_bodies<Stream>(part_stream: Stream)
+    where
+        Stream: TestStream,
+    {
+        let size = 4 * 1024 * 1024 + 111;
+        let read_size = 128 * 1024;
+        let config = MockClientConfig {
+            bucket: "test-bucket".to_string(),
+            part_size: 256 * 1024,
+            ..Default::default()
+        };
+        let client = part_stream.client(config);
+        let object = MockObject::ramp(0xaa, size, ETag::for_tests());
+        let etag = object.etag();
+        client.add_object("hello", object);
+        client.fail_next_get_object_bodies(3);
+
+        let prefetcher = Prefetcher::new(part_stream, Default::default());
+        let mut request = prefetcher.prefetch(Arc::new(client), "test-bucket", "hello", size as u64, etag);
+
+        // Reads can fail, but must never return short or wrong data. Retrying a failed read starts
+        // a new request, so we should eventually read the whole object.
+        let mut next_offset = 0;
+        let mut failures = 0;
+        while next_offset < size as u64 {
+            let expected_len = read_size.min(size - next_offset as usize);
+            match block_on(request.read(next_offset, read_size)) {
+                Ok(buf) => {
+                    let buf = buf.into_bytes().unwrap();
+                    assert_eq!(buf.len(), expected_len, "read at {next_offset} returned short data");
+                    assert_eq!(&buf[..], &ramp_bytes((0xaa + next_offset) as usize, expected_len)[..]);
+                    next_offset += buf.len() as u64;
+                }
+                Err(e) => {
+                    assert!(
+                        matches!(e, PrefetchReadError::GetRequestFailed(_)),
+                        "unexpected error: {e:?}"
+                    );
+                    failures += 1;
+                    assert!(failures <= 3, "too many failed reads");
+                }
+            }
+        }
+        assert!(failures > 0, "incomplete response bodies should fail reads");
+    }
+
+    #[test_case(default_stream())]
+    #[test_case(pooled_stream(1))]
+    #[test_case(caching_stream(1 * MB))]
+    fn test_read_after_request_task_panic<Stream>(part_stream: Stream)
+    where
+        Stream: TestStream,
+    {
+        let size = 1024 * 1024;
+        let config = MockClientConfig {
+            bucket: "test-bucket".to_string(),
+            part_size: 256 * 1024,
+            ..Default::default()
+        };
+        let client = part_stream.client(config);
+        let object = MockObject::ramp(0xaa, size, ETag::for_tests());
+        let etag = object.etag();
+        client.add_object("hello", object);
+
+        // Panic while downloading the first part of the first GetObject request only
+        let client = FailureClient {
+            client,
+            state: Mutex::new(0usize),
+            get_object_cb: |get_count, _bucket, _key, _range, _if_match| {
+                *get_count += 1;
+                Ok(FailureRequestWrapper::new(*get_count == 1, |should_panic| {
+                    if *should_panic {
+                        panic!("injected panic in part download");
+                    }
+                    Ok(())
+                }))
+            },
+            head_object_cb: |_, _, _| Ok(()),
+            list_objects_cb: |_, _, _, _, _, _| Ok(()),
+            put_object_cb: |_, _, _, _| Ok(FailureRequestWrapper::new(false, |_| Ok(()))),
+        };
+
+        let prefetcher = Prefetcher::new(part_stream, Default::default());
+        let mut request = prefetcher.prefetch(Arc::new(client), "test-bucket", "hello", size as u64, etag);
+
+        let err = block_on(request.read(0, 1024)).expect_err("read should fail when the request task panics");
+        assert!(
+            matches!(err, PrefetchReadError::GetRequestPanicked(ref message) if message.contains("injected panic")),
+            "unexpected error: {err:?}"
+        );
+
+        // The next read should start a new request and succeed
+        let buf = block_on(request.read(0, 1024))
+            .expect("retry should succeed")
+            .into_bytes()
+            .unwrap();
+        assert_eq!(&buf[..], &ramp_bytes(0xaa, 1024)[..]);
+    }
+
+    /// A cache that has every block, but whose blocks are all corrupt
+    struct CorruptCache {
+        block_size: u64,
+    }
+
+    impl DataCache for CorruptCache {
+        fn get_block(
+            &self,
+            _cache_key: &ObjectId,
+            _block_idx: BlockIndex,
+            _block_offset: u64,
+        ) -> DataCacheResult<Option<ChecksummedBytes>> {
+            let block = Bytes::from(vec![0u8; self.block_size as usize]);
+            Ok(Some(ChecksummedBytes::new_from_inner_data(block, Crc32c::new(1))))
+        }
+
+        fn put_block(
+            &self,
+            _cache_key: ObjectId,
+            _block_idx: BlockIndex,
+            _block_offset: u64,
+            _bytes: ChecksummedBytes,
+        ) -> DataCacheResult<()> {
+            Ok(())
+        }
+
+        fn block_size(&self) -> u64 {
+            self.block_size
+        }
+    }
+
+    #[test]
+    fn corrupt_read_cancels_requests() {
+        let block_size = 64 * 1024;
+        let size = 1024 * 1024;
+        let config = MockClientConfig {
+            bucket: "test-bucket".to_string(),
+            part_size: block_size,
+            ..Default::default()
+        };
+        let client = MockClient::new(config);
+        let object = MockObject::ramp(0xaa, size, ETag::for_tests());
+        let etag = object.etag();
+        client.add_object("hello", object);
+
+        let runtime = ThreadPool::builder().pool_size(1).create().unwrap();
+        let cache = CorruptCache {
+            block_size: block_size as u64,
+        };
+        let prefetcher = caching_prefetch(cache, runtime, Default::default());
+        let mut request = prefetcher.prefetch(Arc::new(client), "test-bucket", "hello", size as u64, etag);
+
+        // The read spans two blocks, so the prefetcher validates them itself
+        let result = block_on(request.read_vectored(0, 2 * block_size));
+        assert!(matches!(result, Err(PrefetchReadError::Integrity(_))), "{result:?}");
+        let stats = request.stats();
+        assert_eq!(stats.buffered_bytes + stats.inflight_bytes, 0);
+    }
+
+    #[test_case(0, 64 * 1024; "aligned")]
+    #[test_case(100, 200 * 1024; "spanning parts")]
+    fn read_vectored_does_not_copy(offset: u64, length: usize) {
+        let size = 1024 * 1024;
+        let config = MockClientConfig {
+            bucket: "test-bucket".to_string(),
+            part_size: 64 * 1024,
+            ..Default::default()
+        };
+        let client = MockClient::new(config);
+        let body = Bytes::from(ramp_bytes(0xaa, size));
+        let object = MockObject::from_shared_bytes(body.clone(), ETag::for_tests());
+        let etag = object.etag();
+        client.add_object("hello", object);
+
+        let prefetcher = Prefetcher::new(default_stream(), Default::default());
+        let mut request = prefetcher.prefetch(Arc::new(client), "test-bucket", "hello", size as u64, etag);
+
+        let parts = block_on(request.read_vectored(offset, length)).unwrap();
+        let mut next_offset = offset as usize;
+        for part in parts {
+            let part = part.into_bytes().unwrap();
+            // Each part should point directly into the object's buffer
+            assert_eq!(part.as_ptr(), body[next_offset..].as_ptr());
+            next_offset += part.len();
+        }
+        assert_eq!(next_offset, offset as usize + length);
+    }
+
     proptest! {
         #[test]
         fn proptest_sequential_read(
@@ -781,6 +1355,16 @@ mod tests {
             run_sequential_read_test(default_stream(), size, read_size, config);
         }
 
+        #[test]
+        fn proptest_sequential_read_with_buffer_pool(
+            size in 1u64..1 * 1024 * 1024,
+            read_size in 1usize..1 * 1024 * 1024,
+            max_buffers in 0usize..4,
+            config: TestConfig,
+        ) {
+            run_sequential_read_test(pooled_stream(max_buffers), size, read_size, config);
+        }
+
         #[test]
         fn proptest_sequential_read_small_read_size(size in 1u64..1 * 1024 * 1024, read_factor in 1usize..10, config: TestConfig) {
             // Pick read size smaller than the object size
@@ -822,7 +1406,7 @@ mod tests {
         run_sequential_read_test(default_stream(), object_size, read_size, config);
     }
 
-    fn run_random_read_test<Stream: ObjectPartStream + Send + Sync + 'static>(
+    fn run_random_read_test<Stream: TestStream>(
         part_stream: Stream,
         object_size: u64,
         reads: Vec<(u64, usize)>,
@@ -833,7 +1417,7 @@ mod tests {
             part_size: test_config.client_part_size,
             ..Default::default()
         };
-        let client = Arc::new(MockClient::new(config));
+        let client = Arc::new(part_stream.client(config));
         let object = MockObject::ramp(0xaa, object_size as usize, ETag::for_tests());
         let etag = object.etag();
 
@@ -896,6 +1480,16 @@ mod tests {
             run_random_read_test(default_stream(), object_size, reads, config);
         }
 
+        #[test]
+        fn proptest_random_read_with_buffer_pool(
+            reads in random_read_strategy(1 * 1024 * 1024),
+            max_buffers in 0usize..4,
+            config: TestConfig,
+        ) {
+            let (object_size, reads) = reads;
+            run_random_read_test(pooled_stream(max_buffers), object_size, reads, config);
+        }
+
         #[test]
         fn proptest_random_read_with_cache(
             reads in random_read_strategy(1 * 1024 * 1024),
@@ -1045,6 +1639,459 @@ mod tests {
         }
     }
 
+    #[test_case(default_stream())]
+    #[test_case(pooled_stream(1))]
+    #[test_case(caching_stream(1 * MB))]
+    fn test_read_across_part_boundaries<Stream>(part_stream: Stream)
+    where
+        Stream: TestStream,
+    {
+        const OBJECT_SIZE: usize = 16 * 1024;
+        const PART_SIZE: usize = 1000;
+        const FIRST_REQUEST_SIZE: usize = 2500;
+        const SEAM_READ_SIZE: usize = 20;
+
+        let config = MockClientConfig {
+            bucket: "test-bucket".to_string(),
+            part_size: PART_SIZE,
+            ..Default::default()
+        };
+        let client = Arc::new(part_stream.client(config));
+        let object = MockObject::ramp(0xaa, OBJECT_SIZE, ETag::for_tests());
+        let etag = object.etag();
+        client.add_object("hello", object);
+
+        let prefetcher_config = PrefetcherConfig {
+            first_request_size: FIRST_REQUEST_SIZE,
+            ..Default::default()
+        };
+        let prefetcher = Prefetcher::new(part_stream, prefetcher_config);
+
+        // Seams between the client's parts within a request, and between the first request and the
+        // second one
+        for boundary in [PART_SIZE, 2 * PART_SIZE, FIRST_REQUEST_SIZE, 3 * PART_SIZE] {
+            let mut request =
+                prefetcher.prefetch(client.clone(), "test-bucket", "hello", OBJECT_SIZE as u64, etag.clone());
+
+            // Read sequentially up to just before the boundary, then across it
+            let start = boundary - SEAM_READ_SIZE / 2;
+            let before = block_on(request.read(0, start)).unwrap();
+            assert_eq!(before.into_bytes().unwrap()[..], ramp_bytes(0xaa, start)[..]);
+
+            let parts = block_on(request.read_vectored(start as u64, SEAM_READ_SIZE)).unwrap();
+            let mut seam = Vec::new();
+            for part in parts {
+                seam.extend_from_slice(&part.into_bytes().unwrap());
+            }
+            assert_eq!(
+                seam[..],
+                ramp_bytes(0xaa + start, SEAM_READ_SIZE)[..],
+                "bytes around boundary {boundary} should be exactly the object's"
+            );
+
+            // And reading on continues from the right place
+            let after = block_on(request.read((start + SEAM_READ_SIZE) as u64, PART_SIZE)).unwrap();
+            assert_eq!(
+                after.into_bytes().unwrap()[..],
+                ramp_bytes(0xaa + start + SEAM_READ_SIZE, PART_SIZE)[..]
+            );
+        }
+    }
+
+    #[test]
+    fn test_min_read_request_size() {
+        const OBJECT_SIZE: usize = 1024 * 1024;
+        const MIN_READ_REQUEST_SIZE: usize = 64 * 1024;
+
+        let config = MockClientConfig {
+            bucket: "test-bucket".to_string(),
+            part_size: 8 * 1024 * 1024,
+            ..Default::default()
+        };
+        let client = Arc::new(MockClient::new(config));
+        let object = MockObject::ramp(0xaa, OBJECT_SIZE, ETag::for_tests());
+        let etag = object.etag();
+
+        client.add_object("hello", object);
+
+        let prefetcher_config = PrefetcherConfig {
+            first_request_size: 1,
+            min_read_request_size: MIN_READ_REQUEST_SIZE,
+            ..Default::default()
+        };
+
+        let prefetcher = Prefetcher::new(default_stream(), prefetcher_config);
+        let get_counter = client.new_counter(Operation::GetObject);
+        let mut request = prefetcher.prefetch(client.clone(), "test-bucket", "hello", OBJECT_SIZE as u64, etag);
+
+        let byte = block_on(request.read(0, 1)).unwrap();
+        assert_eq!(byte.into_bytes().unwrap()[..], ramp_bytes(0xaa, 1)[..]);
+        assert_eq!(get_counter.count(), 1);
+        let current_task = request.current_task.as_ref().expect("request should be in flight");
+        assert_eq!(current_task.total_size(), MIN_READ_REQUEST_SIZE);
+
+        // A nearby read is served from the data already fetched
+        let byte = block_on(request.read(1000, 1)).unwrap();
+        assert_eq!(byte.into_bytes().unwrap()[..], ramp_bytes(0xaa + 1000, 1)[..]);
+        assert_eq!(get_counter.count(), 1);
+    }
+
+    #[test_case(0; "first read at start")]
+    #[test_case(300 * 1024; "first read in middle")]
+    fn test_prefetch_min_file_size(first_read_offset: u64) {
+        const OBJECT_SIZE: usize = 512 * 1024;
+        const READ_SIZE: usize = 64 * 1024;
+
+        let config = MockClientConfig {
+            bucket: "test-bucket".to_string(),
+            part_size: 8 * 1024 * 1024,
+            ..Default::default()
+        };
+        let client = Arc::new(MockClient::new(config));
+        let object = MockObject::ramp(0xaa, OBJECT_SIZE, ETag::for_tests());
+        let etag = object.etag();
+
+        client.add_object("hello", object);
+
+        // Without the threshold, the first request would only fetch one read's worth of data, and
+        // later requests would prefetch more
+        let prefetcher_config = PrefetcherConfig {
+            first_request_size: READ_SIZE,
+            max_backward_seek_distance: 0,
+            prefetch_min_file_size: 1024 * 1024,
+            ..Default::default()
+        };
+
+        let prefetcher = Prefetcher::new(default_stream(), prefetcher_config);
+        let get_counter = client.new_counter(Operation::GetObject);
+        let mut request = prefetcher.prefetch(client.clone(), "test-bucket", "hello", OBJECT_SIZE as u64, etag);
+        let expected = MockObject::ramp(0xaa, OBJECT_SIZE, ETag::for_tests());
+
+        let bytes = block_on(request.read(first_read_offset, READ_SIZE)).unwrap();
+        assert_eq!(
+            bytes.into_bytes().unwrap()[..],
+            expected.read(first_read_offset, READ_SIZE)[..]
+        );
+        assert_eq!(get_counter.count(), 1);
+        let current_task = request.current_task.as_ref().expect("request should be in flight");
+        assert_eq!(current_task.total_size(), OBJECT_SIZE);
+        assert!(request.future_tasks.is_empty(), "nothing should be prefetched");
+
+        // Every other read, in any order, is served from the object already fetched
+        for offset in (0..OBJECT_SIZE as u64).step_by(READ_SIZE).rev() {
+            let bytes = block_on(request.read(offset, READ_SIZE)).unwrap();
+            assert_eq!(bytes.into_bytes().unwrap()[..], expected.read(offset, READ_SIZE)[..]);
+        }
+        assert_eq!(get_counter.count(), 1);
+    }
+
+    #[test_case(0, 5; "no coalescing")]
+    #[test_case(16 * 1024, 2; "coalescing")]
+    fn test_read_coalesce_gap(read_coalesce_gap: u64, expected_requests: u64) {
+        const OBJECT_SIZE: usize = 1024 * 1024;
+        const FIRST_REQUEST_SIZE: usize = 64 * 1024;
+        const READ_SIZE: usize = 4 * 1024;
+        const GAP: u64 = 8 * 1024;
+
+        let config = MockClientConfig {
+            bucket: "test-bucket".to_string(),
+            part_size: 8 * 1024 * 1024,
+            ..Default::default()
+        };
+        let client = Arc::new(MockClient::new(config));
+        let object = MockObject::ramp(0xaa, OBJECT_SIZE, ETag::for_tests());
+        let etag = object.etag();
+
+        client.add_object("hello", object);
+
+        let prefetcher_config = PrefetcherConfig {
+            first_request_size: FIRST_REQUEST_SIZE,
+            read_coalesce_gap,
+            ..Default::default()
+        };
+
+        let prefetcher = Prefetcher::new(default_stream(), prefetcher_config);
+        let get_counter = client.new_counter(Operation::GetObject);
+        let mut request = prefetcher.prefetch(client.clone(), "test-bucket", "hello", OBJECT_SIZE as u64, etag);
+        let expected = MockObject::ramp(0xaa, OBJECT_SIZE, ETag::for_tests());
+
+        // Each read starts a small gap past the end of the data fetched so far, so without
+        // coalescing every one of them starts a new request
+        let mut offset = 0;
+        for _ in 0..5 {
+            let bytes = block_on(request.read(offset, READ_SIZE)).unwrap();
+            assert_eq!(bytes.into_bytes().unwrap()[..], expected.read(offset, READ_SIZE)[..]);
+            offset += FIRST_REQUEST_SIZE as u64 + GAP;
+        }
+        assert_eq!(get_counter.count(), expected_requests);
+    }
+
+    #[test]
+    fn test_read_coalesce_gap_larger_than_request() {
+        const OBJECT_SIZE: usize = 1024 * 1024;
+        const REQUEST_SIZE: usize = 64 * 1024;
+        const READ_SIZE: usize = 4 * 1024;
+        const GAP: u64 = 2 * REQUEST_SIZE as u64;
+
+        let config = MockClientConfig {
+            bucket: "test-bucket".to_string(),
+            part_size: 8 * 1024 * 1024,
+            ..Default::default()
+        };
+        let client = Arc::new(MockClient::new(config));
+        let object = MockObject::ramp(0xaa, OBJECT_SIZE, ETag::for_tests());
+        let etag = object.etag();
+
+        client.add_object("hello", object);
+
+        // Requests never grow past the first request size, so every gap is larger than the request
+        // that would have been made to cover it
+        let prefetcher_config = PrefetcherConfig {
+            first_request_size: REQUEST_SIZE,
+            max_request_size: REQUEST_SIZE,
+            read_coalesce_gap: 2 * GAP,
+            ..Default::default()
+        };
+
+        let prefetcher = Prefetcher::new(default_stream(), prefetcher_config);
+        let mut request = prefetcher.prefetch(client.clone(), "test-bucket", "hello", OBJECT_SIZE as u64, etag);
+        let expected = MockObject::ramp(0xaa, OBJECT_SIZE, ETag::for_tests());
+
+        let mut offset = 0;
+        for _ in 0..4 {
+            let bytes = block_on(request.read(offset, READ_SIZE)).unwrap();
+            assert_eq!(bytes.into_bytes().unwrap()[..], expected.read(offset, READ_SIZE)[..]);
+            offset += REQUEST_SIZE as u64 + GAP;
+        }
+
+        // Every read continued the same run of requests, so nothing was requested twice
+        let stats = request.stats();
+        assert_eq!(stats.refetched_bytes, 0);
+        assert!(stats.requested_bytes >= offset - GAP - REQUEST_SIZE as u64 + READ_SIZE as u64);
+    }
+
+    #[test_case(default_stream(); "default")]
+    #[test_case(pooled_stream(4); "buffer pool")]
+    #[test_case(caching_stream(1 * MB); "caching")]
+    fn test_requests_stop_at_end_of_object<Stream>(part_stream: Stream)
+    where
+        Stream: TestStream,
+    {
+        const OBJECT_SIZE: usize = 98 * MB;
+        const TAIL_SIZE: usize = 10 * MB;
+        const READ_SIZE: usize = 128 * 1024;
+
+        let config = MockClientConfig {
+            bucket: "test-bucket".to_string(),
+            part_size: 8 * MB,
+            ..Default::default()
+        };
+        let client = part_stream.client(config);
+        let object = MockObject::ramp(0xaa, OBJECT_SIZE, ETag::for_tests());
+        let etag = object.etag();
+        client.add_object("hello", object);
+
+        // Record the range of every GetObject request
+        let client = Arc::new(FailureClient {
+            client,
+            state: Mutex::new(Vec::<Range<u64>>::new()),
+            get_object_cb: |ranges, _bucket, _key, range, _if_match| {
+                ranges.push(range.expect("prefetcher requests should be ranged"));
+                Ok(FailureRequestWrapper::new((), |_| Ok(())))
+            },
+            head_object_cb: |_, _, _| Ok(()),
+            list_objects_cb: |_, _, _, _, _, _| Ok(()),
+            put_object_cb: |_, _, _, _| Ok(FailureRequestWrapper::new((), |_| Ok(()))),
+        });
+
+        // The window is far larger than what's left of the object
+        let prefetcher_config = PrefetcherConfig {
+            first_request_size: 64 * MB,
+            max_request_size: 64 * MB,
+            ..Default::default()
+        };
+        let prefetcher = Prefetcher::new(part_stream, prefetcher_config);
+        let mut request = prefetcher.prefetch(client.clone(), "test-bucket", "hello", OBJECT_SIZE as u64, etag);
+        let expected = MockObject::ramp(0xaa, OBJECT_SIZE, ETag::for_tests());
+
+        let mut offset = (OBJECT_SIZE - TAIL_SIZE) as u64;
+        loop {
+            let bytes = block_on(request.read(offset, READ_SIZE)).unwrap().into_bytes().unwrap();
+            if bytes.is_empty() {
+                break;
+            }
+            assert_eq!(bytes[..], expected.read(offset, bytes.len())[..]);
+            offset += bytes.len() as u64;
+        }
+        assert_eq!(offset, OBJECT_SIZE as u64);
+
+        // A single request covers exactly the remaining bytes, and nothing is planned past the end
+        let ranges = client.state.lock().unwrap().clone();
+        assert!(ranges.iter().all(|range| range.end <= OBJECT_SIZE as u64));
+        assert_eq!(ranges, vec![(OBJECT_SIZE - TAIL_SIZE) as u64..OBJECT_SIZE as u64]);
+        assert_eq!(request.next_request_offset, OBJECT_SIZE as u64);
+    }
+
+    /// Wait for every request in flight to finish downloading, so how much is buffered is settled
+    fn wait_for_downloads<Stream, Client>(request: &PrefetchGetObject<Stream, Client>)
+    where
+        Stream: ObjectPartStream + Send + Sync + 'static,
+        Client: ObjectClient + Send + Sync + 'static,
+    {
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        while request.stats().inflight_bytes > 0 {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "requests should finish downloading"
+            );
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test_case(IdleBufferPolicy::Retain; "retain")]
+    #[test_case(IdleBufferPolicy::Shrink { floor: 2 * MB }; "shrink")]
+    #[test_case(IdleBufferPolicy::Reset; "reset")]
+    fn test_idle_buffer_policy(policy: IdleBufferPolicy) {
+        const OBJECT_SIZE: usize = 32 * MB;
+        const READ_SIZE: usize = 1 * MB;
+        const BURST_SIZE: usize = 8 * MB;
+        const FIRST_REQUEST_SIZE: usize = 1 * MB;
+
+        let config = MockClientConfig {
+            bucket: "test-bucket".to_string(),
+            part_size: 8 * MB,
+            ..Default::default()
+        };
+        let client = Arc::new(MockClient::new(config));
+        let object = MockObject::ramp(0xaa, OBJECT_SIZE, ETag::for_tests());
+        let etag = object.etag();
+        client.add_object("hello", object);
+
+        let prefetcher_config = PrefetcherConfig {
+            first_request_size: FIRST_REQUEST_SIZE,
+            max_request_size: 8 * MB,
+            sequential_prefetch_multiplier: 2,
+            ..Default::default()
+        };
+        let prefetcher = Prefetcher::new(default_stream(), prefetcher_config);
+        let mut request = prefetcher.prefetch(client.clone(), "test-bucket", "hello", OBJECT_SIZE as u64, etag);
+        let expected = MockObject::ramp(0xaa, OBJECT_SIZE, ETag::for_tests());
+
+        // Read in bursts, applying the policy during the pause after each
+        let mut expected_refetched_bytes = 0;
+        let mut offset = 0;
+        for burst in 0..OBJECT_SIZE / BURST_SIZE {
+            if burst > 0 {
+                wait_for_downloads(&request);
+                let before = request.stats();
+                assert!(
+                    before.buffered_bytes >= 2 * MB as u64,
+                    "window should run ahead of the reader"
+                );
+
+                let kept_bytes = match policy {
+                    IdleBufferPolicy::Retain => before.buffered_bytes,
+                    IdleBufferPolicy::Shrink { floor } => floor as u64,
+                    IdleBufferPolicy::Reset => 0,
+                };
+                expected_refetched_bytes += before.buffered_bytes - kept_bytes;
+                let released = request.release_idle_buffers(policy);
+                assert_eq!(released, policy != IdleBufferPolicy::Retain);
+
+                let after = request.stats();
+                assert_eq!(after.buffered_bytes, kept_bytes);
+                assert_eq!(after.inflight_bytes, 0);
+                match policy {
+                    IdleBufferPolicy::Retain => assert_eq!(after, before),
+                    IdleBufferPolicy::Shrink { floor } => {
+                        assert!(after.next_request_size <= floor as u64);
+                        assert_eq!(after.seek_window_bytes, before.seek_window_bytes);
+                    }
+                    IdleBufferPolicy::Reset => {
+                        assert_eq!(after.next_request_size, FIRST_REQUEST_SIZE as u64);
+                        assert_eq!(after.seek_window_bytes, 0);
+                    }
+                }
+            }
+
+            for _ in 0..BURST_SIZE / READ_SIZE {
+                let bytes = block_on(request.read(offset, READ_SIZE)).unwrap();
+                assert_eq!(bytes.into_bytes().unwrap()[..], expected.read(offset, READ_SIZE)[..]);
+                offset += READ_SIZE as u64;
+            }
+        }
+
+        // Everything released ahead of the reader had to be fetched again
+        let stats = request.stats();
+        let expected_releases = if policy == IdleBufferPolicy::Retain { 0 } else { 3 };
+        assert_eq!(stats.idle_releases, expected_releases);
+        assert_eq!(stats.refetched_bytes, expected_refetched_bytes);
+        assert_eq!(stats.requested_bytes, OBJECT_SIZE as u64 + expected_refetched_bytes);
+        if policy == IdleBufferPolicy::Retain {
+            assert_eq!(stats.refetched_bytes, 0);
+        } else {
+            assert!(stats.refetched_bytes > 0);
+        }
+    }
+
+    #[test]
+    fn test_read_source() {
+        const OBJECT_SIZE: usize = 1024 * 1024;
+        const READ_SIZE: usize = 64 * 1024;
+
+        let config = MockClientConfig {
+            bucket: "test-bucket".to_string(),
+            part_size: 8 * 1024 * 1024,
+            ..Default::default()
+        };
+        let client = Arc::new(MockClient::new(config));
+        let object = MockObject::ramp(0xaa, OBJECT_SIZE, ETag::for_tests());
+        let etag = object.etag();
+        client.add_object("hello", object);
+        // Make sure the first read has to wait for its data
+        client.set_operation_latency(Operation::GetObject, Duration::from_millis(100));
+
+        let prefetcher_config = PrefetcherConfig {
+            first_request_size: 4 * READ_SIZE,
+            ..Default::default()
+        };
+        let prefetcher = Prefetcher::new(caching_stream(READ_SIZE), prefetcher_config);
+
+        // Cold read
+        let mut request = prefetcher.prefetch(client.clone(), "test-bucket", "hello", OBJECT_SIZE as u64, etag.clone());
+        let (_, source) = block_on(request.read_vectored_with_source(0, READ_SIZE)).unwrap();
+        let expected = ReadSource {
+            fetched_bytes: READ_SIZE,
+            ..Default::default()
+        };
+        assert_eq!(source, expected);
+
+        // Buffered read, once the rest of the first request has arrived
+        let current_task = request.current_task.as_ref().expect("request should be in flight");
+        while current_task.available_offset() < 2 * READ_SIZE as u64 {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let (_, source) = block_on(request.read_vectored_with_source(READ_SIZE as u64, READ_SIZE)).unwrap();
+        let expected = ReadSource {
+            buffered_bytes: READ_SIZE,
+            ..Default::default()
+        };
+        assert_eq!(source, expected);
+
+        // Cached read, of the blocks the first request put in the cache
+        let mut request = prefetcher.prefetch(client.clone(), "test-bucket", "hello", OBJECT_SIZE as u64, etag);
+        let (parts, source) = block_on(request.read_vectored_with_source(0, 2 * READ_SIZE)).unwrap();
+        let expected = ReadSource {
+            cache_bytes: 2 * READ_SIZE,
+            ..Default::default()
+        };
+        assert_eq!(source, expected);
+        assert_eq!(
+            source.total_bytes(),
+            parts.iter().map(ChecksummedBytes::len).sum::<usize>()
+        );
+    }
+
     #[cfg(feature = "shuttle")]
     mod shuttle_tests {
         use super::*;
@@ -0,0 +1,274 @@
+//! A pool of fixed-size buffers to hold downloaded object data.
+//!
+//! Prefetched parts can sit in memory for a while before they're read. Allocating a new buffer for
+//! every part and freeing it once the part is read churns through large allocations, which
+//! fragments the heap and makes memory usage spiky. Instead, a [BufferPool] can be given to the
+//! client as its [BodyBufferPool], so the client copies response bodies straight into buffers
+//! rented from the pool. The pool takes a buffer back once every reference to its data has been
+//! dropped, whether because the parts it holds were read or because their request was cancelled.
+//! Buffers the pool allocates are charged to a [MemoryLimiter], and when the pool has no free
+//! buffers, or the limiter no room for another one, the client falls back to allocating its own.
+
+use std::fmt::Debug;
+
+use bytes::Bytes;
+use metrics::counter;
+use mountpoint_s3_client::types::BodyBufferPool;
+
+use crate::mem_limiter::MemoryLimiter;
+use crate::metrics::GaugeShare;
+use crate::sync::{Arc, Mutex};
+
+/// A bounded pool of buffers of a fixed size. Cloning a pool shares its buffers.
+#[derive(Clone)]
+pub struct BufferPool {
+    inner: Arc<BufferPoolInner>,
+}
+
+struct BufferPoolInner {
+    buffer_size: usize,
+    max_buffers: usize,
+    memory_limiter: Arc<MemoryLimiter>,
+    buffers: Mutex<Buffers>,
+}
+
+struct Buffers {
+    free: Vec<Vec<u8>>,
+    /// Number of buffers the pool has allocated, whether free or rented out
+    allocated: usize,
+    /// This pool's part of the buffer gauges, which other pools in the same process add to too
+    free_gauge: GaugeShare,
+    rented_gauge: GaugeShare,
+}
+
+impl BufferPool {
+    /// Create a pool of at most `max_buffers` buffers of `buffer_size` bytes each. Buffers are only
+    /// allocated once they're first needed, and only if `memory_limiter` has room for them. They
+    /// stay charged to it until the pool is dropped.
+    pub fn new(buffer_size: usize, max_buffers: usize, memory_limiter: Arc<MemoryLimiter>) -> Self {
+        assert!(buffer_size > 0);
+        let inner = BufferPoolInner {
+            buffer_size,
+            max_buffers,
+            memory_limiter,
+            buffers: Mutex::new(Buffers {
+                free: Vec::new(),
+                allocated: 0,
+                free_gauge: GaugeShare::new("prefetch.buffer_pool.free"),
+                rented_gauge: GaugeShare::new("prefetch.buffer_pool.rented"),
+            }),
+        };
+        Self { inner: Arc::new(inner) }
+    }
+
+    /// Create a pool of buffers of `buffer_size` bytes each that holds at most `memory_limit` bytes
+    pub fn with_memory_limit(buffer_size: usize, memory_limit: usize, memory_limiter: Arc<MemoryLimiter>) -> Self {
+        Self::new(buffer_size, memory_limit / buffer_size, memory_limiter)
+    }
+
+    /// Number of buffers that are free to be rented without allocating
+    #[cfg(test)]
+    fn free_buffers(&self) -> usize {
+        self.inner.buffers.lock().unwrap().free.len()
+    }
+
+    /// Number of buffers currently rented out
+    #[cfg(test)]
+    fn rented_buffers(&self) -> usize {
+        let buffers = self.inner.buffers.lock().unwrap();
+        buffers.allocated - buffers.free.len()
+    }
+}
+
+impl BodyBufferPool for BufferPool {
+    fn buffer_size(&self) -> usize {
+        self.inner.buffer_size
+    }
+
+    fn rent(&self) -> Option<Vec<u8>> {
+        let mut buffers = self.inner.buffers.lock().unwrap();
+        let buffer = match buffers.free.pop() {
+            Some(buffer) => buffer,
+            None if buffers.allocated < self.inner.max_buffers
+                && self.inner.memory_limiter.try_reserve(self.inner.buffer_size as u64) =>
+            {
+                buffers.allocated += 1;
+                Vec::with_capacity(self.inner.buffer_size)
+            }
+            None => {
+                counter!("prefetch.buffer_pool.fallback").increment(1);
+                return None;
+            }
+        };
+        buffers.record_metrics();
+        Some(buffer)
+    }
+
+    fn freeze(&self, buffer: Vec<u8>) -> Bytes {
+        Bytes::from_owner(PooledBuffer {
+            buffer,
+            pool: self.inner.clone(),
+        })
+    }
+}
+
+impl Debug for BufferPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BufferPool")
+            .field("buffer_size", &self.inner.buffer_size)
+            .field("max_buffers", &self.inner.max_buffers)
+            .finish()
+    }
+}
+
+impl BufferPoolInner {
+    fn give_back(&self, mut buffer: Vec<u8>) {
+        buffer.clear();
+        let mut buffers = self.buffers.lock().unwrap();
+        buffers.free.push(buffer);
+        buffers.record_metrics();
+    }
+}
+
+impl Drop for BufferPoolInner {
+    fn drop(&mut self) {
+        // Every rented buffer holds a reference to the pool, so they've all been given back by now
+        let allocated = self.buffers.lock().unwrap().allocated;
+        self.memory_limiter.release((allocated * self.buffer_size) as u64);
+    }
+}
+
+impl Buffers {
+    fn record_metrics(&mut self) {
+        let free = self.free.len();
+        self.free_gauge.set(free as f64);
+        self.rented_gauge.set((self.allocated - free) as f64);
+    }
+}
+
+/// A buffer rented from a [BufferPool], which it returns to the pool when dropped
+struct PooledBuffer {
+    buffer: Vec<u8>,
+    pool: Arc<BufferPoolInner>,
+}
+
+impl AsRef<[u8]> for PooledBuffer {
+    fn as_ref(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        self.pool.give_back(std::mem::take(&mut self.buffer));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Copy `data` into a buffer rented from `pool` as a client would, or into a new allocation if
+    /// the pool has none to spare
+    fn fill(pool: &BufferPool, data: &[u8]) -> Bytes {
+        assert!(data.len() <= pool.buffer_size());
+        match pool.rent() {
+            Some(mut buffer) => {
+                buffer.extend_from_slice(data);
+                pool.freeze(buffer)
+            }
+            None => Bytes::copy_from_slice(data),
+        }
+    }
+
+    #[test]
+    fn test_buffers_return_to_pool() {
+        let pool = BufferPool::new(16, 2, Default::default());
+        let first = fill(&pool, b"hello");
+        let mut second = fill(&pool, b"hello world");
+        assert_eq!(&first[..], b"hello");
+        assert_eq!(&second[..], b"hello world");
+        assert_eq!(pool.rented_buffers(), 2);
+        assert_eq!(pool.free_buffers(), 0);
+
+        // A buffer only goes back once everything referencing it is dropped
+        let split = second.split_to(5);
+        drop(second);
+        assert_eq!(pool.rented_buffers(), 2);
+        drop(split);
+        assert_eq!(pool.rented_buffers(), 1);
+        assert_eq!(pool.free_buffers(), 1);
+
+        // Buffers are reused rather than allocated again
+        let third = fill(&pool, b"again");
+        assert_eq!(&third[..], b"again");
+        assert_eq!(pool.rented_buffers(), 2);
+        assert_eq!(pool.free_buffers(), 0);
+
+        drop(first);
+        drop(third);
+        assert_eq!(pool.rented_buffers(), 0);
+        assert_eq!(pool.free_buffers(), 2);
+    }
+
+    #[test]
+    fn test_fallback_when_exhausted() {
+        let pool = BufferPool::new(16, 1, Default::default());
+        let pooled = fill(&pool, b"pooled");
+        let unpooled = fill(&pool, b"unpooled");
+        assert_eq!(&unpooled[..], b"unpooled");
+        assert_eq!(pool.rented_buffers(), 1);
+
+        drop(unpooled);
+        assert_eq!(pool.free_buffers(), 0);
+        drop(pooled);
+        assert_eq!(pool.free_buffers(), 1);
+    }
+
+    #[test]
+    fn test_short_part_keeps_full_buffer() {
+        // The last part of an object is usually shorter than the buffers, but still rents a whole
+        // buffer, which goes back to the pool able to hold a full-size part
+        let pool = BufferPool::new(16, 1, Default::default());
+        let short = fill(&pool, b"end");
+        assert_eq!(&short[..], b"end");
+        assert_eq!(pool.rented_buffers(), 1);
+        drop(short);
+        assert_eq!(pool.free_buffers(), 1);
+        assert!(pool.inner.buffers.lock().unwrap().free[0].capacity() >= 16);
+
+        let full = fill(&pool, &[0xaa; 16]);
+        assert_eq!(&full[..], &[0xaa; 16]);
+        assert_eq!(pool.rented_buffers(), 1);
+        assert_eq!(pool.free_buffers(), 0);
+    }
+
+    #[test]
+    fn test_empty_pool() {
+        let pool = BufferPool::with_memory_limit(16, 8, Default::default());
+        let data = fill(&pool, b"data");
+        assert_eq!(&data[..], b"data");
+        assert_eq!(pool.rented_buffers(), 0);
+    }
+
+    #[test]
+    fn test_buffers_charged_to_limiter() {
+        let limiter = Arc::new(MemoryLimiter::new(40));
+        let pool = BufferPool::new(16, 4, limiter.clone());
+        let first = fill(&pool, b"first");
+        let second = fill(&pool, b"second");
+        assert_eq!(limiter.mem_reserved(), 32);
+
+        // The limiter has no room for a third buffer, even though the pool does
+        let third = fill(&pool, b"third");
+        assert_eq!(&third[..], b"third");
+        assert_eq!(pool.rented_buffers(), 2);
+
+        // Free buffers stay charged until the pool goes away
+        drop(first);
+        drop(second);
+        assert_eq!(limiter.mem_reserved(), 32);
+        drop(pool);
+        assert_eq!(limiter.mem_reserved(), 0);
+    }
+}
@@ -1,8 +1,7 @@
 use std::time::Instant;
 use std::{ops::Range, sync::Arc};
 
-use bytes::Bytes;
-use futures::task::{Spawn, SpawnExt};
+use futures::task::Spawn;
 use futures::{pin_mut, StreamExt};
 use mountpoint_s3_client::{types::ETag, ObjectClient};
 use tracing::{debug_span, trace, warn, Instrument};
@@ -13,7 +12,7 @@ use crate::object::ObjectId;
 use crate::prefetch::part::Part;
 use crate::prefetch::part_queue::{unbounded_part_queue, PartQueueProducer};
 use crate::prefetch::part_stream::{ObjectPartStream, RequestRange};
-use crate::prefetch::task::RequestTask;
+use crate::prefetch::task::{spawn_request_task, RequestTask};
 use crate::prefetch::PrefetchReadError;
 
 /// [ObjectPartStream] implementation which maintains a [DataCache] for the object data
@@ -65,13 +64,13 @@ where
                 bucket.to_owned(),
                 key.to_owned(),
                 if_match,
-                part_queue_producer,
+                part_queue_producer.clone(),
             );
             let span = debug_span!("prefetch", ?range);
             request.get_from_cache(range).instrument(span)
         };
 
-        let task_handle = self.runtime.spawn_with_handle(request_task).unwrap();
+        let task_handle = spawn_request_task(&self.runtime, key, part_queue_producer, request_task);
 
         RequestTask::from_handle(task_handle, size, start, part_queue)
     }
@@ -124,7 +123,7 @@ where
             match self.cache.get_block(cache_key, block_index, block_offset) {
                 Ok(Some(block)) => {
                     trace!(?cache_key, ?range, block_index, "cache hit");
-                    let part = self.make_part(block, block_index, block_offset, &range);
+                    let part = self.make_part(block, block_index, block_offset, &range, true);
                     self.part_queue_producer.push(Ok(part));
                     block_offset += block_size;
                     continue;
@@ -157,6 +156,7 @@ where
         // Always request a range aligned with block boundaries (or to the end of the object).
         let block_aligned_byte_range =
             (block_range.start * block_size)..(block_range.end * block_size).min(range.object_size() as u64);
+        let requested_end = block_aligned_byte_range.end;
 
         trace!(
             ?key,
@@ -193,7 +193,7 @@ where
                 "buffer should be flushed when we get a full block"
             );
             match get_object_result.next().await {
-                Some(Ok((offset, body))) => {
+                Some(Ok((offset, mut body))) => {
                     trace!(offset, length = body.len(), "received GetObject part");
                     metrics::counter!("s3.client.total_bytes", "type" => "read").increment(body.len() as u64);
 
@@ -209,7 +209,6 @@ where
                     }
 
                     // Split the body into blocks.
-                    let mut body: Bytes = body.into();
                     while !body.is_empty() {
                         let remaining = (block_size as usize).saturating_sub(buffer.len()).min(body.len());
                         let chunk = body.split_to(remaining);
@@ -224,8 +223,13 @@ where
 
                         // We have a full block: write it to the cache, send it to the queue, and flush the buffer.
                         self.update_cache(block_index, block_offset, &buffer);
-                        self.part_queue_producer
-                            .push(Ok(self.make_part(buffer, block_index, block_offset, &range)));
+                        self.part_queue_producer.push(Ok(self.make_part(
+                            buffer,
+                            block_index,
+                            block_offset,
+                            &range,
+                            false,
+                        )));
                         block_index += 1;
                         block_offset += block_size;
                         buffer = ChecksummedBytes::default();
@@ -238,6 +242,14 @@ where
                     break;
                 }
                 None => {
+                    let received_end = block_offset + buffer.len() as u64;
+                    if received_end < requested_end {
+                        // The object shrank, so don't cache a block that isn't really its last
+                        warn!(key, received_end, requested_end, "GetObject response ended early");
+                        self.part_queue_producer
+                            .push(Err(PrefetchReadError::GetRequestEndedEarly { offset: received_end }));
+                        break;
+                    }
                     if !buffer.is_empty() {
                         // If we still have data in the buffer, this must be the last block for this object,
                         // which can be smaller than block_size (and ends at the end of the object).
@@ -248,8 +260,13 @@ where
                         );
                         // Write the last block to the cache.
                         self.update_cache(block_index, block_offset, &buffer);
-                        self.part_queue_producer
-                            .push(Ok(self.make_part(buffer, block_index, block_offset, &range)));
+                        self.part_queue_producer.push(Ok(self.make_part(
+                            buffer,
+                            block_index,
+                            block_offset,
+                            &range,
+                            false,
+                        )));
                     }
                     break;
                 }
@@ -274,8 +291,16 @@ where
     }
 
     /// Creates a Part that can be streamed to the prefetcher from the given cache block.
-    /// If required, trims the block bytes to the request range.
-    fn make_part(&self, block: ChecksummedBytes, block_index: u64, block_offset: u64, range: &RequestRange) -> Part {
+    /// If required, trims the block bytes to the request range. `from_cache` says whether the block
+    /// was read from the cache, rather than just downloaded.
+    fn make_part(
+        &self,
+        block: ChecksummedBytes,
+        block_index: u64,
+        block_offset: u64,
+        range: &RequestRange,
+        from_cache: bool,
+    ) -> Part {
         assert_eq!(
             block_offset,
             block_index * self.cache.block_size(),
@@ -299,7 +324,11 @@ where
         let trim_start = (part_range.start().saturating_sub(block_offset)) as usize;
         let trim_end = (part_range.end().saturating_sub(block_offset)) as usize;
         let bytes = block.slice(trim_start..trim_end);
-        Part::new(cache_key.clone(), part_range.start(), bytes)
+        if from_cache {
+            Part::new_from_cache(cache_key.clone(), part_range.start(), bytes)
+        } else {
+            Part::new(cache_key.clone(), part_range.start(), bytes)
+        }
     }
 
     fn block_indices_for_byte_range(&self, range: &RequestRange) -> Range<BlockIndex> {
@@ -10,6 +10,7 @@ pub struct Part {
     id: ObjectId,
     offset: u64,
     checksummed_bytes: ChecksummedBytes,
+    from_cache: bool,
 }
 
 impl Part {
@@ -18,6 +19,15 @@ impl Part {
             id,
             offset,
             checksummed_bytes,
+            from_cache: false,
+        }
+    }
+
+    /// Create a part whose data was served from a [DataCache](crate::data_cache::DataCache)
+    pub fn new_from_cache(id: ObjectId, offset: u64, checksummed_bytes: ChecksummedBytes) -> Self {
+        Self {
+            from_cache: true,
+            ..Self::new(id, offset, checksummed_bytes)
         }
     }
 
@@ -35,6 +45,7 @@ impl Part {
             id: self.id.clone(),
             offset: self.offset + at as u64,
             checksummed_bytes: new_bytes,
+            from_cache: self.from_cache,
         }
     }
 
@@ -46,6 +57,10 @@ impl Part {
         self.checksummed_bytes.is_empty()
     }
 
+    pub(super) fn is_from_cache(&self) -> bool {
+        self.from_cache
+    }
+
     fn check(&self, id: &ObjectId, offset: u64) -> Result<(), PartMismatchError> {
         if self.id != *id {
             return Err(PartMismatchError::Id {
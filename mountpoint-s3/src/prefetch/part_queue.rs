@@ -27,6 +27,16 @@ pub struct PartQueueProducer<E: std::error::Error> {
     bytes_sent: Arc<AtomicUsize>,
 }
 
+// Not derived, since that would require `E: Clone`
+impl<E: std::error::Error> Clone for PartQueueProducer<E> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            bytes_sent: self.bytes_sent.clone(),
+        }
+    }
+}
+
 /// Creates an unbounded [PartQueue] and its related [PartQueueProducer].
 pub fn unbounded_part_queue<E: std::error::Error>() -> (PartQueue<E>, PartQueueProducer<E>) {
     let (sender, receiver) = unbounded();
@@ -93,6 +103,38 @@ impl<E: std::error::Error + Send + Sync> PartQueue<E> {
         Ok(part)
     }
 
+    /// Like [read](Self::read), but returns `None` rather than waiting if the queue is empty (or
+    /// another read is in progress).
+    ///
+    /// If this method returns an Err, the PartQueue must never be accessed again.
+    pub fn try_read(&self, length: usize) -> Option<Result<Part, PrefetchReadError<E>>> {
+        let mut current_part = self.current_part.try_lock()?;
+
+        assert!(
+            !self.failed.load(Ordering::SeqCst),
+            "cannot use a PartQueue after failure"
+        );
+
+        let mut part = match current_part.take() {
+            Some(current_part) => current_part,
+            None => match self.receiver.try_recv().ok()? {
+                Err(e) => {
+                    self.failed.store(true, Ordering::SeqCst);
+                    return Some(Err(e));
+                }
+                Ok(part) => part,
+            },
+        };
+        debug_assert!(!part.is_empty(), "parts must not be empty");
+
+        if length < part.len() {
+            let tail = part.split_off(length);
+            *current_part = Some(tail);
+        }
+        metrics::gauge!("prefetch.bytes_in_queue").decrement(part.len() as f64);
+        Some(Ok(part))
+    }
+
     pub fn bytes_received(&self) -> usize {
         self.bytes_received.load(Ordering::SeqCst)
     }
@@ -211,6 +253,24 @@ mod tests {
         block_on(run_test(vec![Op::Push(1), Op::Push(1), Op::Read(1), Op::Read(1)]));
     }
 
+    #[test]
+    fn part_queue_try_read() {
+        let part_id = ObjectId::new("key".to_owned(), ETag::for_tests());
+        let (part_queue, part_queue_producer) = unbounded_part_queue::<DummyError>();
+        assert!(part_queue.try_read(1).is_none(), "nothing to read yet");
+
+        let bytes = ChecksummedBytes::new(Bytes::from_static(&[0, 1, 2, 3]));
+        part_queue_producer.push(Ok(Part::new(part_id.clone(), 0, bytes)));
+        let part = part_queue.try_read(3).unwrap().unwrap();
+        assert_eq!(
+            part.into_bytes(&part_id, 0).unwrap().into_bytes().unwrap(),
+            &[0, 1, 2][..]
+        );
+        let part = part_queue.try_read(3).unwrap().unwrap();
+        assert_eq!(part.into_bytes(&part_id, 3).unwrap().into_bytes().unwrap(), &[3][..]);
+        assert!(part_queue.try_read(1).is_none());
+    }
+
     proptest! {
         #[test]
         fn proptest_part_queue(ops: Vec<Op>) {
@@ -1,16 +1,14 @@
 use std::{fmt::Debug, ops::Range};
 
-use bytes::Bytes;
-use futures::task::SpawnExt;
 use futures::{pin_mut, task::Spawn, StreamExt};
 use mountpoint_s3_client::{types::ETag, ObjectClient};
-use tracing::{debug_span, error, trace, Instrument};
+use tracing::{debug_span, error, trace, warn, Instrument};
 
 use crate::checksums::ChecksummedBytes;
 use crate::object::ObjectId;
 use crate::prefetch::part::Part;
 use crate::prefetch::part_queue::unbounded_part_queue;
-use crate::prefetch::task::RequestTask;
+use crate::prefetch::task::{spawn_request_task, RequestTask};
 use crate::prefetch::PrefetchReadError;
 
 /// A generic interface to retrieve data from objects in a S3-like store.
@@ -97,7 +95,8 @@ impl RequestRange {
 
     /// Try to align the end of this range to the given part boundaries.
     /// The `trim_only` flags controls whether the range is only trimmed down to
-    /// part boundaries or is allowed to grow wider.
+    /// part boundaries or is allowed to grow wider. The end of the object counts as a part
+    /// boundary, so a range that reaches it is never trimmed back.
     pub fn align(&self, part_alignment: u64, trim_only: bool) -> RequestRange {
         let offset_in_part = self.offset % part_alignment;
         let size = if offset_in_part != 0 {
@@ -117,6 +116,9 @@ impl RequestRange {
                 // return the whole part
                 part_alignment as usize
             }
+        } else if self.end() == self.object_size as u64 {
+            // if it ends at the end of the object, the last part ends there too
+            self.size
         } else {
             // if it exceeds part boundaries,
             let remainder = self.end() % part_alignment;
@@ -187,6 +189,7 @@ where
             let client = client.clone();
             let bucket = bucket.to_owned();
             let id = ObjectId::new(key.to_owned(), if_match);
+            let part_queue_producer = part_queue_producer.clone();
             let span = debug_span!("prefetch", range=?request_range);
 
             async move {
@@ -203,14 +206,16 @@ where
                 };
 
                 pin_mut!(get_object_result);
+                let mut received_end = request_range.start();
                 loop {
                     match get_object_result.next().await {
-                        Some(Ok((offset, body))) => {
+                        Some(Ok((offset, mut body))) => {
                             trace!(offset, length = body.len(), "received GetObject part");
                             metrics::counter!("s3.client.total_bytes", "type" => "read").increment(body.len() as u64);
                             // pre-split the body into multiple parts as suggested by preferred part size
-                            // in order to avoid validating checksum on large parts at read.
-                            let mut body: Bytes = body.into();
+                            // in order to avoid validating checksum on large parts at read. Splitting
+                            // doesn't copy: each part references the body's buffer, which is pooled
+                            // if the client has a buffer pool.
                             let mut curr_offset = offset;
                             loop {
                                 let chunk_size = preferred_part_size.min(body.len());
@@ -225,13 +230,26 @@ where
                                 curr_offset += part.len() as u64;
                                 part_queue_producer.push(Ok(part));
                             }
+                            received_end = curr_offset;
                         }
                         Some(Err(e)) => {
                             error!(key=id.key(), error=?e, "GetObject body part failed");
                             part_queue_producer.push(Err(PrefetchReadError::GetRequestFailed(e)));
                             break;
                         }
-                        None => break,
+                        None => {
+                            if received_end < request_range.end() {
+                                warn!(
+                                    key = id.key(),
+                                    received_end,
+                                    requested_end = request_range.end(),
+                                    "GetObject response ended early"
+                                );
+                                part_queue_producer
+                                    .push(Err(PrefetchReadError::GetRequestEndedEarly { offset: received_end }));
+                            }
+                            break;
+                        }
                     }
                 }
                 trace!("request finished");
@@ -239,7 +257,7 @@ where
             .instrument(span)
         };
 
-        let task_handle = self.runtime.spawn_with_handle(request_task).unwrap();
+        let task_handle = spawn_request_task(&self.runtime, key, part_queue_producer, request_task);
 
         RequestTask::from_handle(task_handle, size, start, part_queue)
     }
@@ -265,6 +283,8 @@ mod tests {
     #[test_case(9 * MB, (22 * MB) + 11, 100 * MB, 9 * MB, false, 27 * MB; "grow to part boundaries")]
     #[test_case(8 * MB, 16 * MB, 100 * MB, 8 * MB, true, 16 * MB; "already aligned (trim_only)")]
     #[test_case(8 * MB, 16 * MB, 100 * MB, 8 * MB, false, 16 * MB; "already aligned")]
+    #[test_case(88 * MB, 64 * MB, 98 * MB, 8 * MB, true, 10 * MB; "reaches end of object (trim_only)")]
+    #[test_case(88 * MB, 64 * MB, 98 * MB, 8 * MB, false, 10 * MB; "reaches end of object")]
     fn test_request_range_align(
         offset: usize,
         request_size: usize,
@@ -70,6 +70,11 @@ impl SeekWindow {
         Some(result.into())
     }
 
+    /// Number of bytes in the window
+    pub fn size(&self) -> usize {
+        self.current_size
+    }
+
     /// Reset the seek window to an empty state
     pub fn clear(&mut self) {
         self.parts.drain(..);
@@ -1,9 +1,42 @@
-use futures::future::RemoteHandle;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
 
+use futures::future::{FutureExt, RemoteHandle};
+use futures::task::{Spawn, SpawnExt};
+use tracing::error;
+
+use crate::logging::panic_message;
 use crate::prefetch::part::Part;
-use crate::prefetch::part_queue::{unbounded_part_queue, PartQueue};
+use crate::prefetch::part_queue::{unbounded_part_queue, PartQueue, PartQueueProducer};
 use crate::prefetch::PrefetchReadError;
 
+/// Spawn the future that feeds a request's part queue onto the runtime.
+///
+/// If the future panics, the panic is caught, logged and counted, and delivered to the reader as a
+/// [PrefetchReadError::GetRequestPanicked] error. Without this, the panic would be silently held
+/// in the [RemoteHandle] (which we never poll) and the reader would only see the queue close.
+pub fn spawn_request_task<Runtime, E>(
+    runtime: &Runtime,
+    key: &str,
+    part_queue_producer: PartQueueProducer<E>,
+    request: impl Future<Output = ()> + Send + 'static,
+) -> RemoteHandle<()>
+where
+    Runtime: Spawn,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let key = key.to_owned();
+    let task = async move {
+        if let Err(payload) = AssertUnwindSafe(request).catch_unwind().await {
+            let message = panic_message(payload.as_ref()).to_owned();
+            error!(key, message, "prefetch request task panicked");
+            metrics::counter!("prefetch.task_panics").increment(1);
+            part_queue_producer.push(Err(PrefetchReadError::GetRequestPanicked(message)));
+        }
+    };
+    runtime.spawn_with_handle(task).unwrap()
+}
+
 /// A single GetObject request submitted to the S3 client
 #[derive(Debug)]
 pub struct RequestTask<E: std::error::Error> {
@@ -50,6 +83,16 @@ impl<E: std::error::Error + Send + Sync> RequestTask<E> {
         Ok(part)
     }
 
+    /// Like [read](Self::read), but returns `None` rather than waiting if no data is available
+    pub fn try_read(&mut self, length: usize) -> Option<Result<Part, PrefetchReadError<E>>> {
+        let part = self.part_queue.try_read(length)?;
+        if let Ok(part) = &part {
+            debug_assert!(part.len() <= self.remaining);
+            self.remaining -= part.len();
+        }
+        Some(part)
+    }
+
     pub fn start_offset(&self) -> u64 {
         self.start_offset
     }
@@ -71,6 +114,12 @@ impl<E: std::error::Error + Send + Sync> RequestTask<E> {
         self.start_offset + self.part_queue.bytes_received() as u64
     }
 
+    /// Bytes of this request that have been downloaded but not read yet
+    pub fn buffered(&self) -> usize {
+        let read_offset = self.end_offset() - self.remaining as u64;
+        self.available_offset().saturating_sub(read_offset) as usize
+    }
+
     /// Some requests aren't actually streaming data (they're fake, created by backwards seeks), and
     /// shouldn't be counted for prefetcher progress.
     pub fn is_streaming(&self) -> bool {
@@ -7,9 +7,12 @@ use thiserror::Error;
 pub enum PrefixError {
     #[error("prefix must end in '/'")]
     MissingFinalDelimiter,
+    #[error("prefix must not contain '.' or '..' components")]
+    DotComponent,
 }
 
-/// A prefix string ending in `/`, or the empty string
+/// A prefix string ending in `/`, or the empty string. Keys with `.` or `..` components aren't
+/// accessible through the file system, so prefixes can't contain them either.
 #[derive(Debug, Clone, Default)]
 pub struct Prefix {
     path: String,
@@ -19,6 +22,8 @@ impl Prefix {
     pub fn new(prefix: &str) -> Result<Self, PrefixError> {
         if !prefix.is_empty() && !prefix.ends_with('/') {
             Err(PrefixError::MissingFinalDelimiter)
+        } else if prefix.split('/').any(|component| component == "." || component == "..") {
+            Err(PrefixError::DotComponent)
         } else {
             Ok(Self {
                 path: prefix.to_owned(),
@@ -54,6 +59,9 @@ mod tests {
     #[test_case(" "; "whitespace")]
     #[test_case("hello"; "not ending in slash")]
     #[test_case("hello/world"; "nested folder not ending in slash")]
+    #[test_case("./"; "current directory")]
+    #[test_case("hello/../"; "parent directory")]
+    #[test_case("hello/./world/"; "inner current directory")]
     fn test_invalid_prefix(prefix: &str) {
         assert!(Prefix::new(prefix).is_err(), "Prefix should be invalid: '{}'", prefix);
     }
@@ -65,6 +73,7 @@ mod tests {
     #[test_case("/"; "single slash")]
     #[test_case("//"; "double slash")]
     #[test_case("/hello/"; "starting with slash")]
+    #[test_case("hello./...world/"; "names with dots")]
     fn test_valid_prefix(prefix: &str) {
         assert!(Prefix::new(prefix).is_ok(), "Prefix should be valid: '{}'", prefix);
     }
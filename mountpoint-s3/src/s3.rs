@@ -1,11 +1,14 @@
 //! Personalities of different S3 implementations. We use this to auto-configure some sensible
 //! defaults that differ between implementations.
 
+use serde::Deserialize;
+
 /// The type of S3 we're talking to.
 ///
 /// This enum intentionally doesn't implement PartialEq/Eq. You shouldn't test it directly. Instead,
 /// use its methods like `is_list_ordered` to check the actual behavior you're looking for.
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum S3Personality {
     #[default]
     Standard,
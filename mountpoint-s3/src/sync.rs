@@ -11,6 +11,7 @@ mod std {
     pub use std::thread;
 
     pub use async_lock::Mutex as AsyncMutex;
+    pub use async_lock::OnceCell as AsyncOnceCell;
     pub use async_lock::RwLock as AsyncRwLock;
 
     pub use async_channel;
@@ -27,8 +28,14 @@ mod shuttle {
     // TODO these might need a richer Shuttle mock
     pub use async_channel;
     pub use async_lock::Mutex as AsyncMutex;
+    pub use async_lock::OnceCell as AsyncOnceCell;
     pub use async_lock::RwLock as AsyncRwLock;
 }
 
 #[cfg(all(feature = "shuttle", test))]
 pub use self::shuttle::*;
+
+mod instrumented;
+#[cfg_attr(not(feature = "fuse"), allow(unused_imports))]
+pub use instrumented::check_locks_not_held_across_await;
+pub use instrumented::{InstrumentedAsyncRwLock, InstrumentedRwLock};
@@ -0,0 +1,358 @@
+//! Locks that report how long callers wait for them and how long they're held.
+//!
+//! Contention on the big shared tables, like the superblock's inode table and the file system's
+//! handle tables, shows up as latency in every operation. Wrapping those locks in
+//! [InstrumentedRwLock] or [InstrumentedAsyncRwLock] records `lock.wait_us` and `lock.hold_us`
+//! histograms, labelled with the lock's name, and logs a warning whenever a lock is held for longer
+//! than [SLOW_HOLD_THRESHOLD].
+//!
+//! A guard of a blocking lock held across an `.await` blocks every other thread that needs the lock
+//! until the future is polled again. In debug builds, these guards are counted per thread, and
+//! [check_locks_not_held_across_await] wraps a future to panic if it returns `Pending` while
+//! holding one.
+
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::task::Poll;
+use std::time::{Duration, Instant};
+
+use async_lock::{RwLockReadGuard as AsyncRwLockReadGuard, RwLockWriteGuard as AsyncRwLockWriteGuard};
+use futures::future::poll_fn;
+use futures::pin_mut;
+use metrics::histogram;
+use tracing::warn;
+
+use super::{AsyncRwLock, LockResult, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Holding an instrumented lock for longer than this logs a warning
+pub const SLOW_HOLD_THRESHOLD: Duration = Duration::from_millis(100);
+
+/// A [RwLock] that records how long it's waited for and held
+#[derive(Debug)]
+pub struct InstrumentedRwLock<T> {
+    name: &'static str,
+    inner: RwLock<T>,
+}
+
+impl<T> InstrumentedRwLock<T> {
+    /// Create a lock whose metrics are labelled with `name`
+    pub fn new(name: &'static str, value: T) -> Self {
+        Self {
+            name,
+            inner: RwLock::new(value),
+        }
+    }
+
+    pub fn read(&self) -> LockResult<InstrumentedReadGuard<'_, T>> {
+        let start = Instant::now();
+        map_lock_result(self.inner.read(), |guard| InstrumentedReadGuard {
+            guard,
+            _held: HeldLock::new(),
+            _timer: HoldTimer::start(self.name, start),
+        })
+    }
+
+    pub fn write(&self) -> LockResult<InstrumentedWriteGuard<'_, T>> {
+        let start = Instant::now();
+        map_lock_result(self.inner.write(), |guard| InstrumentedWriteGuard {
+            guard,
+            _held: HeldLock::new(),
+            _timer: HoldTimer::start(self.name, start),
+        })
+    }
+}
+
+fn map_lock_result<G, H>(result: LockResult<G>, f: impl FnOnce(G) -> H) -> LockResult<H> {
+    match result {
+        Ok(guard) => Ok(f(guard)),
+        Err(e) => Err(PoisonError::new(f(e.into_inner()))),
+    }
+}
+
+/// Shared access to the contents of an [InstrumentedRwLock]
+pub struct InstrumentedReadGuard<'a, T> {
+    guard: RwLockReadGuard<'a, T>,
+    _held: HeldLock,
+    _timer: HoldTimer,
+}
+
+impl<T> Deref for InstrumentedReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+/// Exclusive access to the contents of an [InstrumentedRwLock]
+pub struct InstrumentedWriteGuard<'a, T> {
+    guard: RwLockWriteGuard<'a, T>,
+    _held: HeldLock,
+    _timer: HoldTimer,
+}
+
+impl<T> Deref for InstrumentedWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for InstrumentedWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+/// An [AsyncRwLock] that records how long it's waited for and held. Its guards may be held across
+/// an `.await`, so they aren't counted by [check_locks_not_held_across_await].
+#[derive(Debug)]
+pub struct InstrumentedAsyncRwLock<T> {
+    name: &'static str,
+    inner: AsyncRwLock<T>,
+}
+
+impl<T> InstrumentedAsyncRwLock<T> {
+    /// Create a lock whose metrics are labelled with `name`
+    pub fn new(name: &'static str, value: T) -> Self {
+        Self {
+            name,
+            inner: AsyncRwLock::new(value),
+        }
+    }
+
+    pub async fn read(&self) -> InstrumentedAsyncReadGuard<'_, T> {
+        let start = Instant::now();
+        let guard = self.inner.read().await;
+        InstrumentedAsyncReadGuard {
+            guard,
+            _timer: HoldTimer::start(self.name, start),
+        }
+    }
+
+    pub async fn write(&self) -> InstrumentedAsyncWriteGuard<'_, T> {
+        let start = Instant::now();
+        let guard = self.inner.write().await;
+        InstrumentedAsyncWriteGuard {
+            guard,
+            _timer: HoldTimer::start(self.name, start),
+        }
+    }
+}
+
+/// Shared access to the contents of an [InstrumentedAsyncRwLock]
+pub struct InstrumentedAsyncReadGuard<'a, T> {
+    guard: AsyncRwLockReadGuard<'a, T>,
+    _timer: HoldTimer,
+}
+
+impl<T> Deref for InstrumentedAsyncReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+/// Exclusive access to the contents of an [InstrumentedAsyncRwLock]
+pub struct InstrumentedAsyncWriteGuard<'a, T> {
+    guard: AsyncRwLockWriteGuard<'a, T>,
+    _timer: HoldTimer,
+}
+
+impl<T> Deref for InstrumentedAsyncWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for InstrumentedAsyncWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+/// Records how long a lock was waited for when created, and how long it was held when dropped
+struct HoldTimer {
+    name: &'static str,
+    acquired: Instant,
+}
+
+impl HoldTimer {
+    fn start(name: &'static str, wait_start: Instant) -> Self {
+        let acquired = Instant::now();
+        histogram!("lock.wait_us", "lock" => name).record(acquired.duration_since(wait_start).as_micros() as f64);
+        Self { name, acquired }
+    }
+}
+
+impl Drop for HoldTimer {
+    fn drop(&mut self) {
+        let held = self.acquired.elapsed();
+        histogram!("lock.hold_us", "lock" => self.name).record(held.as_micros() as f64);
+        if held > SLOW_HOLD_THRESHOLD {
+            warn!(lock = self.name, ?held, "lock was held for a long time");
+        }
+    }
+}
+
+#[cfg(debug_assertions)]
+thread_local! {
+    /// Number of guards of blocking instrumented locks held by this thread
+    static HELD_LOCKS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// Counts a guard of a blocking lock as held by the current thread, in debug builds. The guards
+/// of blocking locks can't be sent to other threads, so they're released on the same thread.
+struct HeldLock;
+
+impl HeldLock {
+    fn new() -> Self {
+        #[cfg(debug_assertions)]
+        HELD_LOCKS.with(|held| held.set(held.get() + 1));
+        Self
+    }
+}
+
+impl Drop for HeldLock {
+    fn drop(&mut self) {
+        #[cfg(debug_assertions)]
+        HELD_LOCKS.with(|held| held.set(held.get() - 1));
+    }
+}
+
+/// Number of guards of blocking instrumented locks held by the current thread. Always zero in
+/// release builds.
+fn held_locks() -> usize {
+    #[cfg(debug_assertions)]
+    return HELD_LOCKS.with(|held| held.get());
+    #[cfg(not(debug_assertions))]
+    0
+}
+
+/// Run `future`, checking in debug builds that it never returns `Pending` while holding a guard of
+/// an [InstrumentedRwLock] that it acquired
+#[cfg_attr(not(feature = "fuse"), allow(dead_code))] // Only the FUSE bindings run file system futures
+pub async fn check_locks_not_held_across_await<F: Future>(future: F) -> F::Output {
+    pin_mut!(future);
+    poll_fn(|cx| {
+        let held_before = held_locks();
+        let result = future.as_mut().poll(cx);
+        if result.is_pending() {
+            debug_assert!(
+                held_locks() <= held_before,
+                "a blocking lock guard was held across an .await"
+            );
+        }
+        result
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures::executor::block_on;
+    use metrics::with_local_recorder;
+
+    use crate::metrics::MetricsRecorder;
+
+    use super::*;
+
+    #[test]
+    fn test_histograms_populate_under_contention() {
+        const THREADS: usize = 4;
+        const ITERATIONS: usize = 100;
+
+        let recorder = Arc::new(MetricsRecorder::new_for_test());
+        let lock = Arc::new(InstrumentedRwLock::new("test", 0u64));
+        let threads: Vec<_> = (0..THREADS)
+            .map(|i| {
+                let recorder = recorder.clone();
+                let lock = lock.clone();
+                std::thread::spawn(move || {
+                    with_local_recorder(&*recorder, || {
+                        for _ in 0..ITERATIONS {
+                            if i % 2 == 0 {
+                                *lock.write().unwrap() += 1;
+                            } else {
+                                let _ = *lock.read().unwrap();
+                            }
+                        }
+                    })
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(*lock.read().unwrap(), (THREADS / 2 * ITERATIONS) as u64);
+        assert_eq!(
+            recorder.histogram_count("lock.wait_us", ("lock", "test")),
+            THREADS * ITERATIONS
+        );
+        assert_eq!(
+            recorder.histogram_count("lock.hold_us", ("lock", "test")),
+            THREADS * ITERATIONS
+        );
+    }
+
+    #[test]
+    fn test_async_histograms_populate() {
+        let recorder = MetricsRecorder::new_for_test();
+        let lock = InstrumentedAsyncRwLock::new("test_async", Vec::new());
+        with_local_recorder(&recorder, || {
+            block_on(async {
+                lock.write().await.push(1);
+                assert_eq!(*lock.read().await, [1]);
+            })
+        });
+        assert_eq!(recorder.histogram_count("lock.wait_us", ("lock", "test_async")), 2);
+        assert_eq!(recorder.histogram_count("lock.hold_us", ("lock", "test_async")), 2);
+    }
+
+    #[test]
+    fn test_lock_released_before_await() {
+        let lock = InstrumentedRwLock::new("test", 0);
+        block_on(check_locks_not_held_across_await(async {
+            *lock.write().unwrap() += 1;
+            yield_now().await;
+            *lock.write().unwrap() += 1;
+        }));
+        assert_eq!(*lock.read().unwrap(), 2);
+        assert_eq!(held_locks(), 0);
+    }
+
+    // This is a deliberately bad code path, only here to check the assertion fires
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "a blocking lock guard was held across an .await")]
+    fn test_lock_held_across_await() {
+        let lock = InstrumentedRwLock::new("test", 0);
+        block_on(check_locks_not_held_across_await(async {
+            let mut guard = lock.write().unwrap();
+            yield_now().await;
+            *guard += 1;
+        }));
+    }
+
+    /// A future that returns `Pending` once before completing
+    async fn yield_now() {
+        let mut yielded = false;
+        poll_fn(|cx| {
+            if yielded {
+                Poll::Ready(())
+            } else {
+                yielded = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
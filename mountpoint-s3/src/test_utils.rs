@@ -0,0 +1,250 @@
+//! Helpers for testing applications built on [S3Filesystem] without a real bucket or FUSE mount.
+//!
+//! Only available with the `test-utils` feature. The file system runs against a [MockClient],
+//! which keeps objects in memory and can inject failures and latency. Mountpoint's own integration
+//! tests are written with these same helpers.
+//!
+//! ```
+//! use mountpoint_s3::fs::FUSE_ROOT_INODE;
+//! use mountpoint_s3::test_utils::{make_test_filesystem, DirectoryReply, ETag, MockObject, ReadReply};
+//!
+//! # futures::executor::block_on(async {
+//! let (client, fs) = make_test_filesystem("test_bucket", &Default::default(), Default::default());
+//! client.add_object("dir/hello.txt", MockObject::from_bytes(b"hello world", ETag::for_tests()));
+//! client.add_object("dir/ramp.bin", MockObject::ramp(0x11, 4096, ETag::for_tests()));
+//!
+//! // List the directory
+//! let dir = fs.lookup(FUSE_ROOT_INODE, "dir".as_ref()).await.unwrap();
+//! let dir_handle = fs.opendir(dir.attr.ino, 0).await.unwrap().fh;
+//! let mut reply = DirectoryReply::default();
+//! fs.readdirplus(dir.attr.ino, dir_handle, 0, &mut reply).await.unwrap();
+//! let names: Vec<_> = reply.entries.iter().map(|entry| entry.name.clone()).collect();
+//! assert_eq!(names, [".", "..", "hello.txt", "ramp.bin"]);
+//!
+//! // Read one of the files, in small chunks
+//! let file = fs.lookup(dir.attr.ino, "hello.txt".as_ref()).await.unwrap();
+//! let fh = fs.open(file.attr.ino, libc::O_RDONLY, 0).await.unwrap().fh;
+//! let mut reply = ReadReply::default();
+//! while reply.read(&fs, file.attr.ino, fh, 4).await.unwrap() > 0 {}
+//! assert_eq!(reply.data, b"hello world");
+//! assert_eq!(reply.reads, 4);
+//! fs.release(file.attr.ino, fh, 0, None, true).await.unwrap();
+//! # });
+//! ```
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures::executor::ThreadPool;
+use mountpoint_s3_client::ObjectClient;
+
+use crate::fs::{DirectoryEntry, DirectoryReplier, Error, FileAttr, FileType, InodeNo};
+use crate::prefetch::{default_prefetch, DefaultPrefetcher};
+use crate::prefix::Prefix;
+use crate::{S3Filesystem, S3FilesystemConfig};
+
+pub use mountpoint_s3_client::failure_client::{countdown_failure_client, CountdownFailureClient, RequestFailureMap};
+pub use mountpoint_s3_client::mock_client::{
+    MockClient, MockClientConfig, MockClientError, MockObject, Operation, OperationCounter,
+};
+pub use mountpoint_s3_client::types::ETag;
+
+pub use crate::clock::MockClock;
+
+/// An [S3Filesystem] with the default prefetcher, as created by [make_test_filesystem]
+pub type TestS3Filesystem<Client> = S3Filesystem<Client, DefaultPrefetcher<ThreadPool>>;
+
+/// Create a file system for the given bucket and prefix, backed by a new, empty [MockClient].
+/// Returns the client too, so the test can add objects to it.
+pub fn make_test_filesystem(
+    bucket: &str,
+    prefix: &Prefix,
+    config: S3FilesystemConfig,
+) -> (Arc<MockClient>, TestS3Filesystem<Arc<MockClient>>) {
+    let client_config = MockClientConfig {
+        bucket: bucket.to_string(),
+        part_size: 1024 * 1024,
+        ..Default::default()
+    };
+
+    let client = Arc::new(MockClient::new(client_config));
+    let fs = make_test_filesystem_with_client(client.clone(), bucket, prefix, config);
+    (client, fs)
+}
+
+/// Create a file system for the given bucket and prefix, backed by the given client. Useful with
+/// [countdown_failure_client] to inject failures.
+pub fn make_test_filesystem_with_client<Client>(
+    client: Client,
+    bucket: &str,
+    prefix: &Prefix,
+    config: S3FilesystemConfig,
+) -> TestS3Filesystem<Client>
+where
+    Client: ObjectClient + Send + Sync + 'static,
+{
+    let runtime = ThreadPool::builder().pool_size(1).create().unwrap();
+    let prefetcher = default_prefetch(runtime, Default::default());
+    S3Filesystem::new(client, prefetcher, bucket, prefix, config)
+}
+
+/// Check the type, size, owner, and permissions of a file's attributes
+#[track_caller]
+pub fn assert_attr(attr: FileAttr, ftype: FileType, size: u64, uid: u32, gid: u32, perm: u16) {
+    assert_eq!(attr.kind, ftype);
+    assert_eq!(attr.size, size);
+    assert_eq!(attr.uid, uid);
+    assert_eq!(attr.gid, gid);
+    assert_eq!(attr.perm, perm);
+}
+
+/// A [DirectoryReplier] that records the entries added to it, like the kernel's `readdir` buffer
+#[derive(Debug, Default)]
+pub struct DirectoryReply {
+    readdir_limit: usize,
+    pub entries: VecDeque<DirectoryEntry>,
+}
+
+impl DirectoryReplier for &mut DirectoryReply {
+    fn add(&mut self, entry: DirectoryEntry) -> bool {
+        if self.readdir_limit > 0 && !self.entries.is_empty() && self.entries.len() % self.readdir_limit == 0 {
+            true
+        } else {
+            self.entries.push_back(entry);
+            false
+        }
+    }
+}
+
+impl DirectoryReply {
+    /// Create a reply that fills up after every `max_entries` entries, or never if 0
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            readdir_limit: max_entries,
+            ..Default::default()
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// Records the data returned by a sequence of reads through one file handle, like an application
+/// reading a file in fixed-size chunks
+#[derive(Debug, Default)]
+pub struct ReadReply {
+    /// Everything read so far, in order
+    pub data: Vec<u8>,
+    /// How many reads were made, including the last one if it reached the end of the file
+    pub reads: usize,
+}
+
+impl ReadReply {
+    /// Read up to `size` bytes from the end of what's been read so far, and append them to
+    /// [data](Self::data). Returns how many bytes were read, which is 0 at the end of the file.
+    pub async fn read<Client>(
+        &mut self,
+        fs: &TestS3Filesystem<Client>,
+        ino: InodeNo,
+        fh: u64,
+        size: u32,
+    ) -> Result<usize, Error>
+    where
+        Client: ObjectClient + Send + Sync + 'static,
+    {
+        let parts = fs.read_vectored(ino, fh, self.data.len() as i64, size, 0, None).await?;
+        self.reads += 1;
+        let len = parts.iter().map(Bytes::len).sum();
+        for part in parts {
+            self.data.extend_from_slice(&part);
+        }
+        Ok(len)
+    }
+
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.reads = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fs::FUSE_ROOT_INODE;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_directory_reply_limit() {
+        let (client, fs) = make_test_filesystem("test_bucket", &Default::default(), Default::default());
+        for i in 0..5 {
+            client.add_object(&format!("file{i}"), MockObject::constant(0, 1, ETag::for_tests()));
+        }
+        let fh = fs.opendir(FUSE_ROOT_INODE, 0).await.unwrap().fh;
+
+        // The reply fills up every 3 entries, starting with `.` and `..`
+        let mut reply = DirectoryReply::new(3);
+        fs.readdirplus(FUSE_ROOT_INODE, fh, 0, &mut reply).await.unwrap();
+        assert_eq!(reply.entries.len(), 3);
+        reply.clear();
+        fs.readdirplus(FUSE_ROOT_INODE, fh, 3, &mut reply).await.unwrap();
+        assert_eq!(reply.entries.len(), 3);
+        let mut unlimited = DirectoryReply::new(0);
+        fs.readdirplus(FUSE_ROOT_INODE, fh, 6, &mut unlimited).await.unwrap();
+        assert_eq!(unlimited.entries.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_make_test_filesystem_with_prefix() {
+        let prefix = Prefix::new("some/prefix/").unwrap();
+        let (client, fs) = make_test_filesystem("test_bucket", &prefix, Default::default());
+        client.add_object(
+            "some/prefix/file.txt",
+            MockObject::from_bytes(b"hello", ETag::for_tests()),
+        );
+        client.add_object("other/file.txt", MockObject::from_bytes(b"world", ETag::for_tests()));
+
+        let entry = fs.lookup(FUSE_ROOT_INODE, "file.txt".as_ref()).await.unwrap();
+        assert_attr(
+            entry.attr,
+            FileType::RegularFile,
+            5,
+            entry.attr.uid,
+            entry.attr.gid,
+            0o644,
+        );
+        fs.lookup(FUSE_ROOT_INODE, "other".as_ref())
+            .await
+            .expect_err("keys outside the prefix should be hidden");
+    }
+
+    #[tokio::test]
+    async fn test_read_reply() {
+        let (client, fs) = make_test_filesystem("test_bucket", &Default::default(), Default::default());
+        let object = MockObject::ramp(0x22, 10_000, ETag::for_tests());
+        let expected = object.read(0, object.len());
+        client.add_object("ramp.bin", object);
+
+        let entry = fs.lookup(FUSE_ROOT_INODE, "ramp.bin".as_ref()).await.unwrap();
+        let fh = fs.open(entry.attr.ino, libc::O_RDONLY, 0).await.unwrap().fh;
+        let mut reply = ReadReply::default();
+        assert_eq!(reply.read(&fs, entry.attr.ino, fh, 4096).await.unwrap(), 4096);
+        while reply.read(&fs, entry.attr.ino, fh, 4096).await.unwrap() > 0 {}
+        assert_eq!(reply.data, expected);
+        // Two full reads, one partial, and one at the end of the file
+        assert_eq!(reply.reads, 4);
+        fs.release(entry.attr.ino, fh, 0, None, true).await.unwrap();
+    }
+
+    #[test]
+    fn test_mock_clock() {
+        use crate::clock::Clock;
+
+        let clock = MockClock::new();
+        let start = clock.now();
+        assert_eq!(clock.now(), start);
+        clock.advance(std::time::Duration::from_secs(5));
+        assert_eq!(clock.now() - start, std::time::Duration::from_secs(5));
+    }
+}
@@ -1,25 +1,47 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::{fmt::Debug, sync::Arc};
 
+use bytes::Bytes;
+use futures::{pin_mut, TryStreamExt};
 use mountpoint_s3_client::checksums::crc32c_from_base64;
-use mountpoint_s3_client::error::{ObjectClientError, PutObjectError};
-use mountpoint_s3_client::types::{PutObjectParams, PutObjectResult, PutObjectTrailingChecksums, UploadReview};
+use mountpoint_s3_client::error::{CopyObjectError, GetObjectError, ObjectClientError, PutObjectError};
+use mountpoint_s3_client::types::{
+    ETag, PutObjectParams, PutObjectResult, PutObjectTrailingChecksums, UploadPartSource, UploadReview,
+};
 use mountpoint_s3_client::{ObjectClient, PutObjectRequest};
 
 use mountpoint_s3_crt::checksums::crc32c::{Crc32c, Hasher};
 use thiserror::Error;
-use tracing::error;
+use tracing::{debug, error, warn};
 
 use crate::checksums::combine_checksums;
 use crate::fs::{ServerSideEncryption, SseCorruptedError};
+use crate::mem_limiter::MemoryLimiter;
 
 type PutRequestError<Client> = ObjectClientError<PutObjectError, <Client as ObjectClient>::ClientError>;
 
 const MAX_S3_MULTIPART_UPLOAD_PARTS: usize = 10000;
 
+/// Zeros to pad uploads with, a chunk at a time, when files are extended with `truncate`
+static ZEROS: [u8; 64 * 1024] = [0; 64 * 1024];
+
+/// The kernel's writeback cache writes whole pages, so writes it sends out of order start on a
+/// page boundary, and a page that was only partly written can be sent again once it's extended
+const WRITEBACK_PAGE_SIZE: u64 = 4096;
+
 /// An [Uploader] creates and manages streaming PutObject requests.
 #[derive(Debug)]
 pub struct Uploader<Client> {
     inner: Arc<UploaderInner<Client>>,
+    /// Key prefix to upload objects under before publishing them to their final key, if any
+    staging_prefix: Option<String>,
+    /// How far ahead of the upload writes can arrive and be held back until it catches up
+    reorder_window: u64,
+    /// Budget the memory of held back writes is charged to
+    memory_limiter: Arc<MemoryLimiter>,
 }
 
 #[derive(Debug)]
@@ -28,6 +50,29 @@ struct UploaderInner<Client> {
     storage_class: Option<String>,
     server_side_encryption: ServerSideEncryption,
     use_additional_checksums: bool,
+    require_content_md5: bool,
+}
+
+impl<Client> UploaderInner<Client> {
+    /// Parameters for new uploads. Fails if the SSE settings were corrupted.
+    fn put_params(&self) -> Result<PutObjectParams, SseCorruptedError> {
+        let mut params = PutObjectParams::new();
+
+        if self.use_additional_checksums {
+            params = params.trailing_checksums(PutObjectTrailingChecksums::Enabled);
+        } else {
+            params = params.trailing_checksums(PutObjectTrailingChecksums::ReviewOnly);
+        }
+
+        if let Some(storage_class) = &self.storage_class {
+            params = params.storage_class(storage_class.clone());
+        }
+        params = params.content_md5(self.require_content_md5);
+        let (sse_type, key_id) = self.server_side_encryption.clone().into_inner()?;
+        params = params.server_side_encryption(sse_type);
+        params = params.ssekms_key_id(key_id);
+        Ok(params)
+    }
 }
 
 #[derive(Debug, Error)]
@@ -45,14 +90,46 @@ impl<Client: ObjectClient> Uploader<Client> {
         storage_class: Option<String>,
         server_side_encryption: ServerSideEncryption,
         use_additional_checksums: bool,
+        require_content_md5: bool,
     ) -> Self {
         let inner = UploaderInner {
             client,
             storage_class,
             server_side_encryption,
             use_additional_checksums,
+            require_content_md5,
         };
-        Self { inner: Arc::new(inner) }
+        Self {
+            inner: Arc::new(inner),
+            staging_prefix: None,
+            reorder_window: 0,
+            memory_limiter: Default::default(),
+        }
+    }
+
+    /// Upload new objects under the key prefix `staging_prefix` (which should end in `/`), and
+    /// only copy them to their final key once the upload has succeeded. Readers of the bucket then
+    /// never see a partially written or failed object under its final key.
+    pub fn with_staging_prefix(mut self, staging_prefix: Option<String>) -> Self {
+        self.staging_prefix = staging_prefix;
+        self
+    }
+
+    /// Accept page-aligned writes that arrive up to `reorder_window` bytes ahead of the end of the
+    /// data uploaded so far, as the kernel's writeback cache sends them, rather than failing them
+    /// as out of order. They're held in memory until the writes before them arrive. The last page
+    /// written is also held back until it's complete, since the cache sends a partly written page
+    /// again each time it's extended. Zero (the default) accepts only sequential writes.
+    pub fn with_reorder_window(mut self, reorder_window: u64) -> Self {
+        self.reorder_window = reorder_window;
+        self
+    }
+
+    /// Charge the memory of held back writes to `memory_limiter`. Writes that arrive out of order
+    /// fail if it has no room left for them.
+    pub fn with_memory_limiter(mut self, memory_limiter: Arc<MemoryLimiter>) -> Self {
+        self.memory_limiter = memory_limiter;
+        self
     }
 
     /// Start a new put request to the specified object.
@@ -61,7 +138,149 @@ impl<Client: ObjectClient> Uploader<Client> {
         bucket: &str,
         key: &str,
     ) -> Result<UploadRequest<Client>, UploadPutError<PutObjectError, Client::ClientError>> {
-        UploadRequest::new(Arc::clone(&self.inner), bucket, key).await
+        let staging_key = self.staging_prefix.as_ref().map(|prefix| {
+            // Keep the object's name, so a leftover staged object can be traced back to its file
+            let name = key.rsplit('/').next().unwrap_or(key);
+            format!("{prefix}{}/{name}", unique_staging_id())
+        });
+        let held_back_memory = HeldBackMemory::new(self.memory_limiter.clone());
+        UploadRequest::new(
+            Arc::clone(&self.inner),
+            bucket,
+            key,
+            staging_key,
+            self.reorder_window,
+            held_back_memory,
+        )
+        .await
+    }
+
+    /// Overwrite part of an existing object with `data`, starting at `offset`. Only the parts
+    /// the write touches are uploaded again, after reading the rest of the last one from the
+    /// object. All other parts are copied server-side.
+    ///
+    /// For now, the write must start on a part boundary and must not extend the object.
+    pub async fn write_parts(
+        &self,
+        bucket: &str,
+        key: &str,
+        etag: &ETag,
+        object_size: u64,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(), PartialWriteError<Client::ClientError>> {
+        let part_size = self.inner.client.part_size().ok_or(PartialWriteError::Unsupported)? as u64;
+        if offset % part_size != 0 {
+            return Err(PartialWriteError::UnalignedWrite { offset, part_size });
+        }
+        let end = offset + data.len() as u64;
+        if end > object_size {
+            return Err(PartialWriteError::ExtendsObject {
+                offset,
+                length: data.len(),
+                object_size,
+            });
+        }
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        // Complete the last part the write touches with the existing bytes that follow the write
+        let last_part_start = (end - 1) / part_size * part_size;
+        let rewrite_end = (last_part_start + part_size).min(object_size);
+        let mut buffer = Vec::with_capacity((rewrite_end - offset) as usize);
+        buffer.extend_from_slice(data);
+        if end < rewrite_end {
+            let request = self
+                .inner
+                .client
+                .get_object(bucket, key, Some(end..rewrite_end), Some(etag.clone()))
+                .await
+                .map_err(PartialWriteError::GetFailed)?;
+            pin_mut!(request);
+            while let Some((_offset, body)) = request.try_next().await.map_err(PartialWriteError::GetFailed)? {
+                buffer.extend_from_slice(&body);
+            }
+        }
+        let buffer = Bytes::from(buffer);
+
+        let copy_part = |start: u64| UploadPartSource::Copy(start..(start + part_size).min(object_size));
+        let parts: Vec<_> = (0..offset)
+            .step_by(part_size as usize)
+            .map(copy_part)
+            .chain((0..buffer.len()).step_by(part_size as usize).map(|start| {
+                let part_end = (start + part_size as usize).min(buffer.len());
+                UploadPartSource::Data(buffer.slice(start..part_end))
+            }))
+            .chain((rewrite_end..object_size).step_by(part_size as usize).map(copy_part))
+            .collect();
+
+        let params = self.inner.put_params()?;
+        let result = self
+            .inner
+            .client
+            .put_object_from_parts(bucket, key, etag, &parts, &params)
+            .await
+            .map_err(PartialWriteError::PutFailed)?;
+        verify_sse_response(&self.inner.server_side_encryption, key, &result);
+        Ok(())
+    }
+
+    /// Extend an existing object to `size` bytes with zeros, as `truncate` does. Its complete
+    /// parts are copied server-side, and the rest of its last part is read from the object and
+    /// uploaded again followed by the zeros. Every part of zeros is uploaded from the same buffer,
+    /// so extending an object by a lot doesn't need all the zeros in memory.
+    pub async fn extend_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        etag: &ETag,
+        object_size: u64,
+        size: u64,
+    ) -> Result<(), PartialWriteError<Client::ClientError>> {
+        let part_size = self.inner.client.part_size().ok_or(PartialWriteError::Unsupported)? as u64;
+        if size <= object_size {
+            return Ok(());
+        }
+
+        // The first part to upload starts with the existing bytes of the last, incomplete part
+        let tail_start = object_size / part_size * part_size;
+        let first_end = (tail_start + part_size).min(size);
+        let mut first = Vec::with_capacity((first_end - tail_start) as usize);
+        if tail_start < object_size {
+            let request = self
+                .inner
+                .client
+                .get_object(bucket, key, Some(tail_start..object_size), Some(etag.clone()))
+                .await
+                .map_err(PartialWriteError::GetFailed)?;
+            pin_mut!(request);
+            while let Some((_offset, body)) = request.try_next().await.map_err(PartialWriteError::GetFailed)? {
+                first.extend_from_slice(&body);
+            }
+        }
+        first.resize((first_end - tail_start) as usize, 0);
+
+        let zeros = Bytes::from(vec![0; part_size.min(size - first_end) as usize]);
+        let parts: Vec<_> = (0..tail_start)
+            .step_by(part_size as usize)
+            .map(|start| UploadPartSource::Copy(start..start + part_size))
+            .chain(std::iter::once(UploadPartSource::Data(Bytes::from(first))))
+            .chain((first_end..size).step_by(part_size as usize).map(|start| {
+                let len = (size - start).min(part_size) as usize;
+                UploadPartSource::Data(zeros.slice(..len))
+            }))
+            .collect();
+
+        let params = self.inner.put_params()?;
+        let result = self
+            .inner
+            .client
+            .put_object_from_parts(bucket, key, etag, &parts, &params)
+            .await
+            .map_err(PartialWriteError::PutFailed)?;
+        verify_sse_response(&self.inner.server_side_encryption, key, &result);
+        Ok(())
     }
 
     #[cfg(test)]
@@ -73,6 +292,52 @@ impl<Client: ObjectClient> Uploader<Client> {
     }
 }
 
+#[derive(Debug, Error)]
+pub enum PartialWriteError<C: std::error::Error> {
+    #[error("partial writes need a client that uses multi-part uploads")]
+    Unsupported,
+
+    #[error("partial write at offset {offset} is not aligned to the part size {part_size}")]
+    UnalignedWrite { offset: u64, part_size: u64 },
+
+    #[error(
+        "partial write of {length} bytes at offset {offset} would extend the object beyond its size {object_size}"
+    )]
+    ExtendsObject {
+        offset: u64,
+        length: usize,
+        object_size: u64,
+    },
+
+    #[error("failed to read the existing part")]
+    GetFailed(#[source] ObjectClientError<GetObjectError, C>),
+
+    #[error("put request failed")]
+    PutFailed(#[source] ObjectClientError<PutObjectError, C>),
+
+    #[error("SSE settings corrupted")]
+    SseCorruptedError(#[from] SseCorruptedError),
+}
+
+#[derive(Debug, Error)]
+pub enum UploadCompleteError<C: std::error::Error> {
+    #[error("put request failed")]
+    PutFailed(#[from] ObjectClientError<PutObjectError, C>),
+
+    #[error("failed to publish staged object {staging_key:?}")]
+    PublishFailed {
+        staging_key: String,
+        #[source]
+        source: ObjectClientError<CopyObjectError, C>,
+    },
+
+    #[error("server-side copy failed")]
+    CopyFailed(#[source] ObjectClientError<CopyObjectError, C>),
+
+    #[error("data was written after offset {offset}, but never at it")]
+    MissingData { offset: u64 },
+}
+
 #[derive(Debug, Error, Clone)]
 pub enum UploadWriteError<E: std::error::Error> {
     #[error("put request failed")]
@@ -83,19 +348,36 @@ pub enum UploadWriteError<E: std::error::Error> {
 
     #[error("object exceeded maximum upload size of {maximum_size} bytes")]
     ObjectTooBig { maximum_size: usize },
+
+    #[error("no memory left to hold back out of order write at offset {write_offset}")]
+    OutOfMemory { write_offset: u64 },
 }
 
 /// Manages the upload of an object to S3.
 ///
-/// Wraps a PutObject request and enforces sequential writes.
+/// Wraps a PutObject request and enforces sequential writes, apart from those within its reorder
+/// window (see [Uploader::with_reorder_window]).
 pub struct UploadRequest<Client: ObjectClient> {
+    client: Arc<Client>,
     bucket: String,
     key: String,
+    /// The key the object is actually uploaded to, if it's staged before being published to `key`
+    staging_key: Option<String>,
+    params: PutObjectParams,
     next_request_offset: u64,
     hasher: Hasher,
     request: Client::PutObjectRequest,
     maximum_upload_size: Option<usize>,
     sse: ServerSideEncryption,
+    /// The size the object was extended to, which the written data is padded to with zeros
+    extended_size: u64,
+    /// How far past `next_request_offset` writes can be held back, or zero if they can't
+    reorder_window: u64,
+    /// Writes that arrived ahead of `next_request_offset`, or the partial page at its end, by
+    /// offset. They don't overlap.
+    held_back: BTreeMap<u64, Bytes>,
+    /// The memory reserved for `held_back`
+    held_back_memory: HeldBackMemory,
 }
 
 impl<Client: ObjectClient> UploadRequest<Client> {
@@ -103,92 +385,392 @@ impl<Client: ObjectClient> UploadRequest<Client> {
         inner: Arc<UploaderInner<Client>>,
         bucket: &str,
         key: &str,
+        staging_key: Option<String>,
+        reorder_window: u64,
+        held_back_memory: HeldBackMemory,
     ) -> Result<UploadRequest<Client>, UploadPutError<PutObjectError, Client::ClientError>> {
-        let mut params = PutObjectParams::new();
-
-        if inner.use_additional_checksums {
-            params = params.trailing_checksums(PutObjectTrailingChecksums::Enabled);
-        } else {
-            params = params.trailing_checksums(PutObjectTrailingChecksums::ReviewOnly);
-        }
-
-        if let Some(storage_class) = &inner.storage_class {
-            params = params.storage_class(storage_class.clone());
-        }
         // If we have detected corruption of SSE settings, we return an error, which will currently be reported as
         // `libc::EIO` on `open()`. MP won't be able to open files for write from this point, but this is a relatively
         // low-risk error as data can not be uploaded with wrong SSE settings yet. Thus there is no strong reason for
         // MP to crash and it may continue serving read's.
-        let (sse_type, key_id) = inner.server_side_encryption.clone().into_inner()?;
-        params = params.server_side_encryption(sse_type);
-        params = params.ssekms_key_id(key_id);
+        let params = inner.put_params()?;
 
-        let request = inner.client.put_object(bucket, key, &params).await?;
+        let upload_key = staging_key.as_deref().unwrap_or(key);
+        let request = inner.client.put_object(bucket, upload_key, &params).await?;
         let maximum_upload_size = inner
             .client
             .part_size()
             .map(|ps| ps.saturating_mul(MAX_S3_MULTIPART_UPLOAD_PARTS));
 
         Ok(Self {
+            client: Arc::clone(&inner.client),
             bucket: bucket.to_owned(),
             key: key.to_owned(),
+            staging_key,
+            params,
             next_request_offset: 0,
             hasher: Hasher::new(),
             request,
             maximum_upload_size,
             sse: inner.server_side_encryption.clone(),
+            extended_size: 0,
+            reorder_window,
+            held_back: BTreeMap::new(),
+            held_back_memory,
         })
     }
 
+    /// The size of the object, including any zeros it will be padded with and any writes held back
     pub fn size(&self) -> u64 {
-        self.next_request_offset
+        let held_back_end = self
+            .held_back
+            .last_key_value()
+            .map(|(offset, data)| offset + data.len() as u64)
+            .unwrap_or(0);
+        self.next_request_offset.max(self.extended_size).max(held_back_end)
     }
 
     pub async fn write(
         &mut self,
         offset: i64,
         data: &[u8],
+    ) -> Result<usize, UploadWriteError<PutRequestError<Client>>> {
+        self.write_with(offset, data, |_, _| {}).await
+    }
+
+    /// Like [write](Self::write), but calls `committed` with each piece of data as it's added to the
+    /// upload, in order. That can include writes that were held back earlier, but never the
+    /// zeros the object is padded with.
+    pub async fn write_with(
+        &mut self,
+        offset: i64,
+        data: &[u8],
+        mut committed: impl FnMut(u64, &[u8]) + Send,
     ) -> Result<usize, UploadWriteError<PutRequestError<Client>>> {
         let next_offset = self.next_request_offset;
-        if offset != next_offset as i64 {
+        if offset < next_offset as i64 {
             return Err(UploadWriteError::OutOfOrderWrite {
                 write_offset: offset as u64,
                 expected_offset: next_offset,
             });
         }
+        let offset = offset as u64;
+        let end = offset + data.len() as u64;
+        if let Some(maximum_size) = self.maximum_upload_size {
+            if end > maximum_size as u64 {
+                return Err(UploadWriteError::ObjectTooBig { maximum_size });
+            }
+        }
+        if self.reorder_window > 0 {
+            return self.write_reordered(offset, data, &mut committed).await;
+        }
+
+        // A write into the zeros the object was extended with must start where they do
+        if offset > next_offset && offset <= self.extended_size {
+            self.fill_to(offset, &mut committed).await?;
+        }
+        if offset != self.next_request_offset {
+            return Err(UploadWriteError::OutOfOrderWrite {
+                write_offset: offset,
+                expected_offset: self.next_request_offset,
+            });
+        }
+
+        self.write_data(data).await?;
+        committed(offset, data);
+        Ok(data.len())
+    }
+
+    /// Write `data` at `offset`, which isn't behind the data uploaded so far, when there's a
+    /// reorder window. Writes that don't follow on from the data uploaded so far are held back
+    /// until they do, and so is the partial page at the end, which may yet be written again.
+    async fn write_reordered(
+        &mut self,
+        offset: u64,
+        data: &[u8],
+        committed: &mut impl FnMut(u64, &[u8]),
+    ) -> Result<usize, UploadWriteError<PutRequestError<Client>>> {
+        let len = data.len();
+        let end = offset + len as u64;
+        let reserved = if offset <= self.unsent_end() {
+            false
+        } else if self.can_hold_back(offset, end) {
+            if !self.held_back_memory.try_grow(len as u64) {
+                return Err(UploadWriteError::OutOfMemory { write_offset: offset });
+            }
+            true
+        } else if offset <= self.extended_size {
+            // A write into the zeros the object was extended with must start where they do, and
+            // can only come after them (and any writes held back before it)
+            self.discard_held_back(offset..end);
+            self.fill_to(offset, committed).await?;
+            false
+        } else {
+            return Err(UploadWriteError::OutOfOrderWrite {
+                write_offset: offset,
+                expected_offset: self.unsent_end(),
+            });
+        };
+
+        // The newest write of each byte wins over any held back before it
+        self.discard_held_back(offset..end);
+        let (mut offset, mut data) = (offset, data);
+        if offset == self.next_request_offset {
+            // Upload the whole pages of a write that follows on from the upload straight away
+            let whole_pages = (end / WRITEBACK_PAGE_SIZE * WRITEBACK_PAGE_SIZE).saturating_sub(offset) as usize;
+            if whole_pages > 0 {
+                let (whole_pages, rest) = data.split_at(whole_pages);
+                self.write_data(whole_pages).await?;
+                committed(offset, whole_pages);
+                (offset, data) = (offset + whole_pages.len() as u64, rest);
+            }
+        }
+        if !data.is_empty() {
+            // Rewrites of data that isn't uploaded yet are expected, so always make room for them
+            if !reserved {
+                self.held_back_memory.grow(data.len() as u64);
+            }
+            self.held_back.insert(offset, Bytes::copy_from_slice(data));
+        }
+        self.write_held_back(self.upload_limit(), committed).await?;
+        Ok(len)
+    }
+
+    /// Extend the object to `size` bytes, as if with `truncate`. The zeros it's extended with
+    /// are only uploaded once a write after them or the completion of the upload needs them.
+    pub fn extend_to(&mut self, size: u64) -> Result<(), UploadWriteError<PutRequestError<Client>>> {
         if let Some(maximum_size) = self.maximum_upload_size {
-            if next_offset + data.len() as u64 > maximum_size as u64 {
+            if size > maximum_size as u64 {
                 return Err(UploadWriteError::ObjectTooBig { maximum_size });
             }
         }
+        self.extended_size = self.extended_size.max(size);
+        Ok(())
+    }
+
+    /// Shrink the object to `size` bytes, as if with `truncate`, dropping any writes held back and
+    /// zeros beyond it. Data that was already uploaded can't be truncated, so returns `false`,
+    /// leaving the object unchanged, if `size` is smaller than that.
+    pub fn truncate_to(&mut self, size: u64) -> bool {
+        if size < self.next_request_offset {
+            return false;
+        }
+        self.discard_held_back(size..u64::MAX);
+        self.extended_size = self.extended_size.min(size);
+        true
+    }
 
+    /// Whether a write of `offset..end`, which is ahead of the upload, is in the reorder window
+    fn can_hold_back(&self, offset: u64, end: u64) -> bool {
+        self.reorder_window > 0
+            && offset % WRITEBACK_PAGE_SIZE == 0
+            && end <= self.next_request_offset + self.reorder_window
+    }
+
+    /// The end of the writes held back that follow on from the data uploaded so far without a gap
+    fn unsent_end(&self) -> u64 {
+        let mut unsent_end = self.next_request_offset;
+        for (offset, data) in self.held_back.range(self.next_request_offset..) {
+            if *offset != unsent_end {
+                break;
+            }
+            unsent_end += data.len() as u64;
+        }
+        unsent_end
+    }
+
+    /// How far held back writes can be uploaded before the upload completes. With a reorder window,
+    /// that's up to the start of the partial page at the end, which may yet be written again.
+    fn upload_limit(&self) -> u64 {
+        if self.reorder_window > 0 {
+            self.unsent_end() / WRITEBACK_PAGE_SIZE * WRITEBACK_PAGE_SIZE
+        } else {
+            u64::MAX
+        }
+    }
+
+    /// Drop the parts of held back writes that fall within `range`
+    fn discard_held_back(&mut self, range: Range<u64>) {
+        let overlapping: Vec<u64> = self
+            .held_back
+            .range(..range.end)
+            .filter(|(offset, data)| *offset + data.len() as u64 > range.start)
+            .map(|(offset, _)| *offset)
+            .collect();
+        for offset in overlapping {
+            let data = self.held_back.remove(&offset).expect("offset was just found");
+            let end = offset + data.len() as u64;
+            let mut discarded = data.len() as u64;
+            if offset < range.start {
+                let kept = data.slice(..(range.start - offset) as usize);
+                discarded -= kept.len() as u64;
+                self.held_back.insert(offset, kept);
+            }
+            if end > range.end {
+                let kept = data.slice((range.end - offset) as usize..);
+                discarded -= kept.len() as u64;
+                self.held_back.insert(range.end, kept);
+            }
+            self.held_back_memory.shrink(discarded);
+        }
+    }
+
+    /// Upload the held back writes that now follow on from the data uploaded so far, up to `limit`
+    async fn write_held_back(
+        &mut self,
+        limit: u64,
+        committed: &mut impl FnMut(u64, &[u8]),
+    ) -> Result<(), PutRequestError<Client>> {
+        while let Some(entry) = self.held_back.first_entry() {
+            let offset = *entry.key();
+            if offset != self.next_request_offset || offset >= limit {
+                break;
+            }
+            let mut data = entry.remove();
+            if offset + data.len() as u64 > limit {
+                let rest = data.split_off((limit - offset) as usize);
+                self.held_back.insert(limit, rest);
+            }
+            self.held_back_memory.shrink(data.len() as u64);
+            self.write_data(&data).await?;
+            committed(offset, &data);
+        }
+        Ok(())
+    }
+
+    /// Pad the object with zeros up to `size`, a chunk at a time so that extending a file by a lot
+    /// doesn't need the zeros all in memory. Writes held back within the padding are uploaded in
+    /// place of the zeros.
+    async fn fill_to(
+        &mut self,
+        size: u64,
+        committed: &mut impl FnMut(u64, &[u8]),
+    ) -> Result<(), PutRequestError<Client>> {
+        while self.next_request_offset < size {
+            self.write_held_back(size, committed).await?;
+            let zeros_end = match self.held_back.first_key_value() {
+                Some((offset, _)) => (*offset).min(size),
+                None => size,
+            };
+            while self.next_request_offset < zeros_end {
+                let len = (zeros_end - self.next_request_offset).min(ZEROS.len() as u64) as usize;
+                self.write_data(&ZEROS[..len]).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn write_data(&mut self, data: &[u8]) -> Result<(), PutRequestError<Client>> {
         self.hasher.update(data);
         self.request.write(data).await?;
         self.next_request_offset += data.len() as u64;
-        Ok(data.len())
+        Ok(())
     }
 
-    pub async fn complete(self) -> Result<PutObjectResult, PutRequestError<Client>> {
+    pub async fn complete(mut self) -> Result<PutObjectResult, UploadCompleteError<Client::ClientError>> {
+        let mut ignore = |_: u64, _: &[u8]| {};
+        self.fill_to(self.extended_size, &mut ignore).await?;
+        self.write_held_back(u64::MAX, &mut ignore).await?;
+        // Writes held back past a gap that was never written can't be uploaded, so fail rather than
+        // make up the missing data
+        if !self.held_back.is_empty() {
+            return Err(UploadCompleteError::MissingData {
+                offset: self.next_request_offset,
+            });
+        }
         let size = self.size();
         let checksum = self.hasher.finalize();
         let result = self
             .request
             .review_and_complete(move |review| verify_checksums(review, size, checksum))
             .await?;
-        if let Err(err) = self
-            .sse
-            .verify_response(result.sse_type.as_deref(), result.sse_kms_key_id.as_deref())
-        {
-            error!(key=?self.key, error=?err, "SSE settings were corrupted after the upload completion");
-            // Reaching this point is very unlikely and means that SSE settings were corrupted in transit or on S3 side, this may be a sign of a bug
-            // in CRT code or S3. Thus, we terminate Mountpoint to send the most noticeable signal to customer about the issue. We prefer exiting
-            // instead of returning an error because:
-            // 1. this error would only be reported on `flush` which many applications ignore and
-            // 2. the reported error is severe as the object was already uploaded to S3.
-            std::process::exit(1);
+        verify_sse_response(&self.sse, &self.key, &result);
+
+        if let Some(staging_key) = self.staging_key {
+            // S3 has no rename, so publish the staged object with a server-side copy. If this
+            // fails, the staged object is left behind for `cleanup_staging` to remove.
+            if let Err(source) = self
+                .client
+                .copy_object(&self.bucket, &staging_key, &self.key, None, &self.params)
+                .await
+            {
+                return Err(UploadCompleteError::PublishFailed { staging_key, source });
+            }
+            debug!(key = ?self.key, ?staging_key, "published staged object");
+            if let Err(error) = self.client.delete_object(&self.bucket, &staging_key).await {
+                warn!(
+                    ?error,
+                    ?staging_key,
+                    "failed to delete staged object after publishing it"
+                );
+            }
         }
         Ok(result)
     }
+
+    /// Instead of uploading the data written to this request, make the object a server-side copy
+    /// of the existing object `source_key` in the same bucket, as long as it still has the ETag
+    /// `source_etag`. Nothing must have been written yet.
+    pub async fn complete_with_copy(
+        self,
+        source_key: &str,
+        source_etag: &ETag,
+    ) -> Result<(), UploadCompleteError<Client::ClientError>> {
+        debug_assert_eq!(self.size(), 0, "data was already written to the upload");
+        // Dropping the request aborts the upload. The copy is atomic, so it can go straight to
+        // the final key even if uploads are usually staged.
+        drop(self.request);
+        self.client
+            .copy_object(&self.bucket, source_key, &self.key, Some(source_etag), &self.params)
+            .await
+            .map_err(UploadCompleteError::CopyFailed)?;
+        Ok(())
+    }
+}
+
+/// Memory reserved on a [MemoryLimiter] for the writes an upload holds back. It's given back when
+/// the upload is dropped, whether it completed or not.
+#[derive(Debug)]
+struct HeldBackMemory {
+    memory_limiter: Arc<MemoryLimiter>,
+    reserved: u64,
+}
+
+impl HeldBackMemory {
+    fn new(memory_limiter: Arc<MemoryLimiter>) -> Self {
+        Self {
+            memory_limiter,
+            reserved: 0,
+        }
+    }
+
+    /// Reserve another `size` bytes if the limiter has room for them
+    fn try_grow(&mut self, size: u64) -> bool {
+        let reserved = self.memory_limiter.try_reserve(size);
+        if reserved {
+            self.reserved += size;
+        }
+        reserved
+    }
+
+    /// Reserve another `size` bytes even if the limiter has no room for them
+    fn grow(&mut self, size: u64) {
+        self.memory_limiter.reserve(size);
+        self.reserved += size;
+    }
+
+    fn shrink(&mut self, size: u64) {
+        debug_assert!(size <= self.reserved, "released more memory than was held back");
+        let size = size.min(self.reserved);
+        self.memory_limiter.release(size);
+        self.reserved -= size;
+    }
+}
+
+impl Drop for HeldBackMemory {
+    fn drop(&mut self) {
+        self.memory_limiter.release(self.reserved);
+    }
 }
 
 impl<Client: ObjectClient> Debug for UploadRequest<Client> {
@@ -196,12 +778,38 @@ impl<Client: ObjectClient> Debug for UploadRequest<Client> {
         f.debug_struct("UploadRequest")
             .field("bucket", &self.bucket)
             .field("key", &self.key)
+            .field("staging_key", &self.staging_key)
             .field("next_request_offset", &self.next_request_offset)
+            .field("extended_size", &self.extended_size)
+            .field("held_back", &self.held_back.len())
             .field("hasher", &self.hasher)
             .finish()
     }
 }
 
+/// A name for a staging directory that's unique across uploads from this and other processes
+fn unique_staging_id() -> String {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    format!("{nanos:x}-{:x}-{id:x}", std::process::id())
+}
+
+fn verify_sse_response(sse: &ServerSideEncryption, key: &str, result: &PutObjectResult) {
+    if let Err(err) = sse.verify_response(result.sse_type.as_deref(), result.sse_kms_key_id.as_deref()) {
+        error!(?key, error=?err, "SSE settings were corrupted after the upload completion");
+        // Reaching this point is very unlikely and means that SSE settings were corrupted in transit or on S3 side, this may be a sign of a bug
+        // in CRT code or S3. Thus, we terminate Mountpoint to send the most noticeable signal to customer about the issue. We prefer exiting
+        // instead of returning an error because:
+        // 1. this error would only be reported on `flush` which many applications ignore and
+        // 2. the reported error is severe as the object was already uploaded to S3.
+        std::process::exit(1);
+    }
+}
+
 fn verify_checksums(review: UploadReview, expected_size: u64, expected_checksum: Crc32c) -> bool {
     let mut uploaded_size = 0u64;
     let mut uploaded_checksum = Crc32c::new(0);
@@ -250,7 +858,7 @@ mod tests {
     use super::*;
     use mountpoint_s3_client::{
         failure_client::countdown_failure_client,
-        mock_client::{MockClient, MockClientConfig, MockClientError},
+        mock_client::{MockClient, MockClientConfig, MockClientError, MockObject, Operation},
     };
     use test_case::test_case;
 
@@ -265,7 +873,7 @@ mod tests {
             part_size: 32,
             ..Default::default()
         }));
-        let uploader = Uploader::new(client.clone(), None, ServerSideEncryption::default(), true);
+        let uploader = Uploader::new(client.clone(), None, ServerSideEncryption::default(), true, false);
         let request = uploader.put(bucket, key).await.unwrap();
 
         assert!(!client.contains_key(key));
@@ -277,6 +885,35 @@ mod tests {
         assert!(!client.is_upload_in_progress(key));
     }
 
+    #[tokio::test]
+    async fn complete_staged_test() {
+        let bucket = "bucket";
+        let key = "dir/hello";
+
+        let client = Arc::new(MockClient::new(MockClientConfig {
+            bucket: bucket.to_owned(),
+            part_size: 32,
+            ..Default::default()
+        }));
+        let uploader = Uploader::new(client.clone(), None, ServerSideEncryption::default(), true, false)
+            .with_staging_prefix(Some(".inprogress/".to_owned()));
+        let mut request = uploader.put(bucket, key).await.unwrap();
+        let staging_key = request.staging_key.clone().expect("upload should be staged");
+        assert!(staging_key.starts_with(".inprogress/"));
+        assert!(staging_key.ends_with("/hello"));
+
+        request.write(0, b"hello world").await.unwrap();
+        assert!(client.is_upload_in_progress(&staging_key));
+        assert!(!client.is_upload_in_progress(key));
+
+        let copy_counter = client.new_counter(Operation::CopyObject);
+        request.complete().await.unwrap();
+
+        assert!(client.contains_key(key));
+        assert!(!client.contains_key(&staging_key));
+        assert_eq!(copy_counter.count(), 1);
+    }
+
     #[tokio::test]
     async fn write_order_test() {
         let bucket = "bucket";
@@ -294,6 +931,7 @@ mod tests {
             Some(storage_class.to_owned()),
             ServerSideEncryption::default(),
             true,
+            false,
         );
 
         let mut request = uploader.put(bucket, key).await.unwrap();
@@ -343,7 +981,13 @@ mod tests {
             put_failures,
         ));
 
-        let uploader = Uploader::new(failure_client.clone(), None, ServerSideEncryption::default(), true);
+        let uploader = Uploader::new(
+            failure_client.clone(),
+            None,
+            ServerSideEncryption::default(),
+            true,
+            false,
+        );
 
         // First request fails on first write.
         {
@@ -384,7 +1028,7 @@ mod tests {
             part_size: PART_SIZE,
             ..Default::default()
         }));
-        let uploader = Uploader::new(client.clone(), None, ServerSideEncryption::default(), true);
+        let uploader = Uploader::new(client.clone(), None, ServerSideEncryption::default(), true, false);
         let mut request = uploader.put(bucket, key).await.unwrap();
 
         let successful_writes = PART_SIZE * MAX_S3_MULTIPART_UPLOAD_PARTS / write_size;
@@ -406,6 +1050,45 @@ mod tests {
         assert!(!client.is_upload_in_progress(key));
     }
 
+    #[test_case(true; "with content md5")]
+    #[test_case(false; "without content md5")]
+    #[tokio::test]
+    async fn put_with_content_md5_test(require_content_md5: bool) {
+        let bucket = "bucket";
+        let key = "hello";
+
+        let client = Arc::new(MockClient::new(MockClientConfig {
+            bucket: bucket.to_owned(),
+            part_size: 32,
+            ..Default::default()
+        }));
+        let uploader = Uploader::new(
+            client.clone(),
+            None,
+            ServerSideEncryption::default(),
+            true,
+            require_content_md5,
+        );
+
+        let mut request = uploader.put(bucket, key).await.unwrap();
+        let data: Vec<u8> = (0..80).collect();
+        request.write(0, &data).await.unwrap();
+        request.complete().await.unwrap();
+
+        let content_md5 = client.get_object_content_md5(key).expect("object should exist");
+        if require_content_md5 {
+            // Base64-encoded MD5 of each 32 byte part
+            let expected = [
+                "tP/LI3N87DFaSk0aoqYgzg==",
+                "v2HomVYPq94vbXb0BabrcA==",
+                "DXEt6ka2NYpjQ2JzP1VFhw==",
+            ];
+            assert_eq!(content_md5.expect("Content-MD5 should be sent"), expected);
+        } else {
+            assert_eq!(content_md5, None);
+        }
+    }
+
     #[test_case(Some("aws:kmr"), Some("some_key_alias"))]
     #[test_case(Some("aws:kms"), Some("some_key_ali`s"))]
     #[test_case(None, Some("some_key_alias"))]
@@ -418,6 +1101,7 @@ mod tests {
             None,
             ServerSideEncryption::new(Some("aws:kms".to_string()), Some("some_key_alias".to_string())),
             true,
+            false,
         );
         std::sync::Arc::<UploaderInner<MockClient>>::get_mut(&mut uploader.inner)
             .unwrap()
@@ -449,7 +1133,297 @@ mod tests {
             None,
             ServerSideEncryption::new(Some("aws:kms".to_string()), Some("some_key".to_string())),
             true,
+            false,
         );
         uploader.put(bucket, key).await.expect("put with sse should succeed");
     }
+
+    async fn read_object(client: &MockClient, bucket: &str, key: &str) -> Vec<u8> {
+        let mut request = client.get_object(bucket, key, None, None).await.unwrap();
+        let mut contents = Vec::new();
+        while let Some((_offset, body)) = request.try_next().await.unwrap() {
+            contents.extend_from_slice(&body);
+        }
+        contents
+    }
+
+    #[test_case(32, 32; "whole middle part")]
+    #[test_case(32, 10; "start of middle part")]
+    #[test_case(64, 26; "whole last part")]
+    #[test_case(0, 40; "spanning two parts")]
+    #[tokio::test]
+    async fn write_parts_test(offset: u64, length: usize) {
+        let bucket = "bucket";
+        let key = "hello";
+
+        let client = Arc::new(MockClient::new(MockClientConfig {
+            bucket: bucket.to_owned(),
+            part_size: 32,
+            ..Default::default()
+        }));
+        let mut contents: Vec<u8> = (0..90).collect();
+        let object = MockObject::from_bytes(&contents, ETag::for_tests());
+        let etag = object.etag();
+        client.add_object(key, object);
+
+        let get_counter = client.new_counter(Operation::GetObject);
+        let copy_counter = client.new_counter(Operation::UploadPartCopy);
+        let upload_counter = client.new_counter(Operation::UploadPart);
+
+        let uploader = Uploader::new(client.clone(), None, ServerSideEncryption::default(), true, false);
+        let data = vec![0xffu8; length];
+        uploader
+            .write_parts(bucket, key, &etag, contents.len() as u64, offset, &data)
+            .await
+            .expect("write_parts should succeed");
+
+        // Only the parts the write touches are uploaded, the rest is copied server-side
+        let first_part = offset / 32;
+        let last_part = (offset + length as u64 - 1) / 32;
+        assert_eq!(upload_counter.count(), (last_part - first_part + 1) as usize);
+        assert_eq!(copy_counter.count(), 3 - (last_part - first_part + 1) as usize);
+        let end = offset as usize + length;
+        let ends_on_boundary = end % 32 == 0 || end == contents.len();
+        assert_eq!(get_counter.count(), if ends_on_boundary { 0 } else { 1 });
+
+        contents[offset as usize..end].copy_from_slice(&data);
+        assert_eq!(read_object(&client, bucket, key).await, contents);
+    }
+
+    #[tokio::test]
+    async fn write_parts_unaligned_test() {
+        let bucket = "bucket";
+        let key = "hello";
+
+        let client = Arc::new(MockClient::new(MockClientConfig {
+            bucket: bucket.to_owned(),
+            part_size: 32,
+            ..Default::default()
+        }));
+        let contents = vec![0u8; 90];
+        let object = MockObject::from_bytes(&contents, ETag::for_tests());
+        let etag = object.etag();
+        client.add_object(key, object);
+
+        let uploader = Uploader::new(client.clone(), None, ServerSideEncryption::default(), true, false);
+        let err = uploader
+            .write_parts(bucket, key, &etag, 90, 16, &[1; 16])
+            .await
+            .expect_err("unaligned write should fail");
+        assert!(matches!(
+            err,
+            PartialWriteError::UnalignedWrite {
+                offset: 16,
+                part_size: 32
+            }
+        ));
+        let err = uploader
+            .write_parts(bucket, key, &etag, 90, 64, &[1; 32])
+            .await
+            .expect_err("extending write should fail");
+        assert!(matches!(err, PartialWriteError::ExtendsObject { .. }));
+        assert_eq!(read_object(&client, bucket, key).await, contents);
+    }
+
+    /// Write `data` in page-sized chunks, in the order given by `pages`
+    async fn write_pages(request: &mut UploadRequest<MockClient>, data: &[u8], pages: &[usize]) -> Vec<u64> {
+        let page_size = WRITEBACK_PAGE_SIZE as usize;
+        let mut committed = Vec::new();
+        for &page in pages {
+            let offset = page * page_size;
+            let chunk = &data[offset..(offset + page_size).min(data.len())];
+            request
+                .write_with(offset as i64, chunk, |offset, _| committed.push(offset))
+                .await
+                .unwrap();
+        }
+        committed
+    }
+
+    #[test_case(&[0, 1, 2, 3, 4, 5, 6, 7]; "in order")]
+    #[test_case(&[0, 2, 1, 3, 5, 4, 7, 6]; "swapped pairs")]
+    #[test_case(&[1, 2, 3, 0, 4, 5, 6, 7]; "first page last")]
+    #[test_case(&[0, 1, 4, 5, 6, 7, 2, 3]; "gap filled later")]
+    #[test_case(&[7, 6, 5, 4, 3, 2, 1, 0]; "reversed")]
+    #[tokio::test]
+    async fn reorder_window_test(pages: &[usize]) {
+        let bucket = "bucket";
+        let key = "hello";
+        let size = 8 * WRITEBACK_PAGE_SIZE as usize - 100;
+
+        let client = Arc::new(MockClient::new(MockClientConfig {
+            bucket: bucket.to_owned(),
+            part_size: 32 * 1024,
+            ..Default::default()
+        }));
+        let uploader = Uploader::new(client.clone(), None, ServerSideEncryption::default(), true, false)
+            .with_reorder_window(32 * 1024);
+        let mut request = uploader.put(bucket, key).await.unwrap();
+
+        let data: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+        let committed = write_pages(&mut request, &data, pages).await;
+        assert_eq!(request.size(), size as u64);
+
+        // Data reaches the upload in order, whatever order it was written in. The partial page at the
+        // end is only uploaded once the upload completes.
+        let expected: Vec<u64> = (0..7).map(|page| page * WRITEBACK_PAGE_SIZE).collect();
+        assert_eq!(committed, expected);
+
+        request.complete().await.unwrap();
+        assert_eq!(read_object(&client, bucket, key).await, data);
+    }
+
+    #[tokio::test]
+    async fn reorder_window_limits_test() {
+        let bucket = "bucket";
+        let key = "hello";
+        let page = WRITEBACK_PAGE_SIZE as i64;
+
+        let client = Arc::new(MockClient::new(MockClientConfig {
+            bucket: bucket.to_owned(),
+            part_size: 32 * 1024,
+            ..Default::default()
+        }));
+        let uploader = Uploader::new(client.clone(), None, ServerSideEncryption::default(), true, false)
+            .with_reorder_window(4 * WRITEBACK_PAGE_SIZE);
+        let mut request = uploader.put(bucket, key).await.unwrap();
+
+        // Writes past the window, or that don't start on a page boundary, are still out of order
+        let err = request
+            .write(4 * page, &[1; 4096])
+            .await
+            .expect_err("write past the window should fail");
+        assert!(matches!(err, UploadWriteError::OutOfOrderWrite { .. }));
+        let err = request
+            .write(page + 1, &[1; 10])
+            .await
+            .expect_err("unaligned write should fail");
+        assert!(matches!(err, UploadWriteError::OutOfOrderWrite { .. }));
+
+        // A write held back over a gap that's never filled fails the upload
+        request.write(2 * page, &[2; 4096]).await.unwrap();
+        assert_eq!(request.size(), 3 * WRITEBACK_PAGE_SIZE);
+        let err = request.complete().await.expect_err("upload with a gap should fail");
+        assert!(matches!(err, UploadCompleteError::MissingData { offset: 0 }));
+        assert!(!client.contains_key(key));
+    }
+
+    #[tokio::test]
+    async fn reorder_window_truncate_test() {
+        let bucket = "bucket";
+        let key = "hello";
+        let page = WRITEBACK_PAGE_SIZE as i64;
+
+        let client = Arc::new(MockClient::new(MockClientConfig {
+            bucket: bucket.to_owned(),
+            part_size: 32 * 1024,
+            ..Default::default()
+        }));
+        let uploader = Uploader::new(client.clone(), None, ServerSideEncryption::default(), true, false)
+            .with_reorder_window(32 * 1024);
+        let mut request = uploader.put(bucket, key).await.unwrap();
+
+        // The kernel extends the file, then flushes its pages out of order
+        request.extend_to(4 * WRITEBACK_PAGE_SIZE).unwrap();
+        request.write(2 * page, &[3; 4096]).await.unwrap();
+        request.write(0, &[1; 4096]).await.unwrap();
+        request.write(3 * page, &[4; 4096]).await.unwrap();
+
+        // Truncating drops the held back data past the new size, but not data already uploaded
+        assert!(!request.truncate_to(100));
+        assert!(request.truncate_to(3 * WRITEBACK_PAGE_SIZE + 10));
+        assert_eq!(request.size(), 3 * WRITEBACK_PAGE_SIZE + 10);
+
+        let mut expected = vec![1; 4096];
+        expected.extend_from_slice(&[0; 4096]);
+        expected.extend_from_slice(&[3; 4096]);
+        expected.extend_from_slice(&[4; 10]);
+        request.complete().await.unwrap();
+        assert_eq!(read_object(&client, bucket, key).await, expected);
+    }
+
+    #[tokio::test]
+    async fn reorder_window_small_appends_test() {
+        let bucket = "bucket";
+        let key = "hello";
+        let page_size = WRITEBACK_PAGE_SIZE as usize;
+
+        let client = Arc::new(MockClient::new(MockClientConfig {
+            bucket: bucket.to_owned(),
+            part_size: 32 * 1024,
+            ..Default::default()
+        }));
+        let memory_limiter = Arc::new(MemoryLimiter::default());
+        let uploader = Uploader::new(client.clone(), None, ServerSideEncryption::default(), true, false)
+            .with_reorder_window(32 * 1024)
+            .with_memory_limiter(memory_limiter.clone());
+        let mut request = uploader.put(bucket, key).await.unwrap();
+
+        // Append 100 bytes at a time. Like the writeback cache, send whole pages from the start of
+        // the page each append begins in, so a partly written page is sent again with every append.
+        let data: Vec<u8> = (0..3 * page_size + 500).map(|i| (i % 251) as u8).collect();
+        let mut committed = Vec::new();
+        let mut written = 0;
+        for end in (100..data.len()).step_by(100).chain([data.len()]) {
+            let start = written / page_size * page_size;
+            written = end;
+            request
+                .write_with(start as i64, &data[start..end], |offset, data| {
+                    committed.push((offset, data.len()))
+                })
+                .await
+                .unwrap();
+            assert_eq!(request.size(), end as u64);
+            assert_eq!(memory_limiter.mem_reserved(), end as u64 % WRITEBACK_PAGE_SIZE);
+        }
+
+        // Only whole pages were uploaded before completion
+        let expected: Vec<(u64, usize)> = (0..3).map(|page| (page * WRITEBACK_PAGE_SIZE, page_size)).collect();
+        assert_eq!(committed, expected);
+
+        request.complete().await.unwrap();
+        assert_eq!(read_object(&client, bucket, key).await, data);
+        assert_eq!(memory_limiter.mem_reserved(), 0);
+    }
+
+    #[tokio::test]
+    async fn reorder_window_memory_limit_test() {
+        let bucket = "bucket";
+        let key = "hello";
+        let page = WRITEBACK_PAGE_SIZE as i64;
+
+        let client = Arc::new(MockClient::new(MockClientConfig {
+            bucket: bucket.to_owned(),
+            part_size: 32 * 1024,
+            ..Default::default()
+        }));
+        let memory_limiter = Arc::new(MemoryLimiter::new(2 * WRITEBACK_PAGE_SIZE));
+        let uploader = Uploader::new(client.clone(), None, ServerSideEncryption::default(), true, false)
+            .with_reorder_window(32 * 1024)
+            .with_memory_limiter(memory_limiter.clone());
+        let mut request = uploader.put(bucket, key).await.unwrap();
+
+        // Writes held back out of order are charged to the limiter, and fail once it's full
+        request.write(page, &[1; 4096]).await.unwrap();
+        request.write(2 * page, &[2; 4096]).await.unwrap();
+        assert_eq!(memory_limiter.mem_reserved(), 2 * WRITEBACK_PAGE_SIZE);
+        let err = request
+            .write(3 * page, &[3; 4096])
+            .await
+            .expect_err("write past the memory limit should fail");
+        assert!(matches!(
+            err,
+            UploadWriteError::OutOfMemory { write_offset } if write_offset == 3 * WRITEBACK_PAGE_SIZE
+        ));
+
+        // Filling the gap uploads the held back writes and gives their memory back
+        request.write(0, &[0; 4096]).await.unwrap();
+        assert_eq!(memory_limiter.mem_reserved(), 0);
+
+        // Memory held back when an upload is dropped is given back too
+        request.write(4 * page, &[4; 4096]).await.unwrap();
+        assert_eq!(memory_limiter.mem_reserved(), WRITEBACK_PAGE_SIZE);
+        drop(request);
+        assert_eq!(memory_limiter.mem_reserved(), 0);
+    }
 }
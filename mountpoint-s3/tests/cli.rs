@@ -1,6 +1,11 @@
+#![cfg(feature = "fuse")]
+
 use assert_cmd::prelude::*; // Add methods on commands
 use predicates::prelude::*; // Used for writing assertions
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
 use std::{fs, os::unix::prelude::PermissionsExt, process::Command}; // Run programs
+use test_case::test_case;
 
 /// Regular expression for something that looks mostly like a SemVer version.
 /// See https://semver.org/#is-there-a-suggested-regular-expression-regex-to-check-a-semver-string.
@@ -281,3 +286,49 @@ fn sse_key_not_allowed_with_aes256() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+/// Start a server that answers every request with the given status and body, and return the
+/// endpoint URL to reach it at
+fn start_s3_stub(status: u16, body: &'static str) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else {
+                continue;
+            };
+            // Read the request line and headers, the probe has no body
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap_or(0) > 0 && line.trim_end() != "" {
+                line.clear();
+            }
+            let response = format!(
+                "HTTP/1.1 {status} Status\r\nContent-Type: application/xml\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            let _ = stream.write_all(response.as_bytes());
+        }
+    });
+    format!("http://127.0.0.1:{port}")
+}
+
+#[test_case(404, "<Error><Code>NoSuchBucket</Code><Message>The specified bucket does not exist</Message></Error>", "bucket \"test-bucket\" does not exist in region us-east-1"; "no such bucket")]
+#[test_case(403, "<Error><Code>AccessDenied</Code><Message>Access Denied</Message></Error>", "access denied to bucket \"test-bucket\" in region us-east-1"; "access denied")]
+#[test_case(400, "<Error><Code>InvalidRequest</Code><Message>Bad request</Message></Error>", "initial ListObjectsV2 failed for bucket test-bucket in region us-east-1"; "other error")]
+fn bucket_probe_error(status: u16, body: &'static str, error_message: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let endpoint_url = start_s3_stub(status, body);
+    let dir = assert_fs::TempDir::new()?;
+    let mut cmd = Command::cargo_bin("mount-s3")?;
+
+    cmd.arg("test-bucket")
+        .arg(dir.path())
+        .arg("--foreground")
+        .arg("--no-sign-request")
+        .arg("--force-path-style")
+        .arg("--region=us-east-1")
+        .arg(format!("--endpoint-url={endpoint_url}"));
+    cmd.assert().failure().stderr(predicate::str::contains(error_message));
+
+    Ok(())
+}
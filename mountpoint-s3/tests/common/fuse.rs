@@ -1,12 +1,24 @@
 use std::ffi::OsStr;
 use std::fs::ReadDir;
 use std::path::Path;
+#[cfg(feature = "fuse-integration-tests")]
+use std::sync::mpsc;
 use std::sync::Arc;
-
+#[cfg(feature = "fuse-integration-tests")]
+use std::thread::{self, JoinHandle};
+#[cfg(feature = "fuse-integration-tests")]
+use std::time::Duration;
+
+#[cfg(feature = "fuse-integration-tests")]
+use anyhow::Context;
+#[cfg(feature = "fuse-integration-tests")]
+use fuser::Filesystem;
 use fuser::{BackgroundSession, MountOption, Session};
 use mountpoint_s3::data_cache::DataCache;
+#[cfg(feature = "fuse-integration-tests")]
+use mountpoint_s3::fuse::session::{FuseSession, ShutdownHandle};
 use mountpoint_s3::fuse::S3FuseFilesystem;
-use mountpoint_s3::prefetch::{Prefetch, PrefetcherConfig};
+use mountpoint_s3::prefetch::{BufferPool, Prefetch, PrefetcherConfig};
 use mountpoint_s3::prefix::Prefix;
 use mountpoint_s3::S3FilesystemConfig;
 use mountpoint_s3_client::config::S3ClientAuthConfig;
@@ -54,6 +66,8 @@ pub struct TestSessionConfig {
     pub filesystem_config: S3FilesystemConfig,
     pub prefetcher_config: PrefetcherConfig,
     pub auth_config: S3ClientAuthConfig,
+    /// Pool for the client to copy downloaded data into
+    pub read_buffer_pool: Option<BufferPool>,
 }
 
 impl Default for TestSessionConfig {
@@ -63,6 +77,7 @@ impl Default for TestSessionConfig {
             filesystem_config: Default::default(),
             prefetcher_config: Default::default(),
             auth_config: Default::default(),
+            read_buffer_pool: None,
         }
     }
 }
@@ -110,10 +125,113 @@ where
     BackgroundSession::new(session).unwrap()
 }
 
+/// A FUSE mount run by [FuseSession], the session runner `mount-s3` itself uses, instead of by
+/// [BackgroundSession]. The file system is unmounted when this is dropped.
+#[cfg(feature = "fuse-integration-tests")]
+pub struct RunnerSession {
+    shutdown: ShutdownHandle,
+    session: Option<JoinHandle<anyhow::Result<()>>>,
+}
+
+#[cfg(feature = "fuse-integration-tests")]
+impl RunnerSession {
+    /// How long mounting or unmounting may take before we give up on it
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// Mount `fs` at `mount_dir`, or return `None` if this host can't mount FUSE file systems
+    fn mount<FS>(fs: FS, mount_dir: &Path) -> Option<Self>
+    where
+        FS: Filesystem + Send + Sync + 'static,
+    {
+        if !fuse_available() {
+            eprintln!("skipping test: FUSE is not available");
+            return None;
+        }
+
+        // Unmount even if the test process dies without dropping us
+        let options = vec![
+            MountOption::DefaultPermissions,
+            MountOption::FSName("mountpoint-s3".to_string()),
+            MountOption::NoAtime,
+            MountOption::AllowOther,
+            MountOption::AutoUnmount,
+        ];
+        let mount_dir = mount_dir.to_owned();
+        let (tx, rx) = mpsc::channel();
+        let session = thread::spawn(move || {
+            let session = Session::new(fs, &mount_dir, &options)
+                .context("mount failed")
+                .and_then(|session| FuseSession::new_without_signal_handler(session, 16));
+            match session {
+                Ok(session) => {
+                    let _ = tx.send(Ok(session.shutdown_handle()));
+                    session.join()
+                }
+                Err(e) => {
+                    let _ = tx.send(Err(e));
+                    Ok(())
+                }
+            }
+        });
+
+        match rx.recv_timeout(Self::TIMEOUT) {
+            Ok(Ok(shutdown)) => Some(Self {
+                shutdown,
+                session: Some(session),
+            }),
+            // For example, when /etc/fuse.conf doesn't have `user_allow_other`
+            Ok(Err(e)) => {
+                eprintln!("skipping test: {e:?}");
+                None
+            }
+            Err(_) => panic!("mount did not finish within {:?}", Self::TIMEOUT),
+        }
+    }
+
+    /// Unmount the file system and wait for the session to finish
+    pub fn unmount(&mut self) -> anyhow::Result<()> {
+        let Some(session) = self.session.take() else {
+            return Ok(());
+        };
+        self.shutdown.shutdown();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = tx.send(session.join());
+        });
+        match rx.recv_timeout(Self::TIMEOUT) {
+            Ok(result) => result.map_err(|_| anyhow::anyhow!("session thread panicked"))?,
+            Err(_) => Err(anyhow::anyhow!("unmount did not finish within {:?}", Self::TIMEOUT)),
+        }
+    }
+}
+
+#[cfg(feature = "fuse-integration-tests")]
+impl Drop for RunnerSession {
+    fn drop(&mut self) {
+        if let Err(e) = self.unmount() {
+            // Don't panic while panicking. `AutoUnmount` cleans up when we exit anyway.
+            eprintln!("failed to unmount: {e:?}");
+        }
+    }
+}
+
+/// Whether this host can mount FUSE file systems as this user
+#[cfg(feature = "fuse-integration-tests")]
+fn fuse_available() -> bool {
+    if std::fs::File::open("/dev/fuse").is_err() {
+        return false;
+    }
+    ["fusermount3", "fusermount"]
+        .iter()
+        .any(|bin| std::process::Command::new(bin).arg("--version").output().is_ok())
+}
+
 pub mod mock_session {
     use super::*;
 
     use futures::executor::ThreadPool;
+    #[cfg(feature = "fuse-integration-tests")]
+    use mountpoint_s3::prefetch::DefaultPrefetcher;
     use mountpoint_s3::prefetch::{caching_prefetch, default_prefetch};
     use mountpoint_s3_client::mock_client::{MockClient, MockClientConfig, MockObject};
     use mountpoint_s3_client::types::ObjectAttribute;
@@ -136,6 +254,9 @@ pub mod mock_session {
             ..Default::default()
         };
         let client = Arc::new(MockClient::new(client_config));
+        if let Some(buffer_pool) = test_config.read_buffer_pool {
+            client.set_body_buffer_pool(Arc::new(buffer_pool));
+        }
         let runtime = ThreadPool::builder().pool_size(1).create().unwrap();
         let prefetcher = default_prefetch(runtime, test_config.prefetcher_config);
         let session = create_fuse_session(
@@ -151,6 +272,59 @@ pub mod mock_session {
         (mount_dir, session, test_client)
     }
 
+    /// The file system [new_with_runner_and_adapter] mounts
+    #[cfg(feature = "fuse-integration-tests")]
+    pub type MockFilesystem = S3FuseFilesystem<Arc<MockClient>, DefaultPrefetcher<ThreadPool>>;
+
+    /// Create a FUSE mount backed by a mock object client that does not talk to S3, and run it with
+    /// [FuseSession] like `mount-s3` does. Returns `None` if this host can't mount it.
+    #[cfg(feature = "fuse-integration-tests")]
+    pub fn new_with_runner(
+        test_name: &str,
+        test_config: TestSessionConfig,
+    ) -> Option<(TempDir, RunnerSession, TestClientBox)> {
+        new_with_runner_and_adapter(test_name, test_config, |fs| fs)
+    }
+
+    /// Like [new_with_runner], but mount whatever `adapter` wraps the file system in
+    #[cfg(feature = "fuse-integration-tests")]
+    pub fn new_with_runner_and_adapter<FS>(
+        test_name: &str,
+        test_config: TestSessionConfig,
+        adapter: impl FnOnce(MockFilesystem) -> FS,
+    ) -> Option<(TempDir, RunnerSession, TestClientBox)>
+    where
+        FS: Filesystem + Send + Sync + 'static,
+    {
+        let mount_dir = tempfile::tempdir().unwrap();
+
+        let prefix = if test_name.is_empty() {
+            test_name.to_string()
+        } else {
+            format!("{test_name}/")
+        };
+
+        let client_config = MockClientConfig {
+            bucket: BUCKET_NAME.to_string(),
+            part_size: test_config.part_size,
+            ..Default::default()
+        };
+        let client = Arc::new(MockClient::new(client_config));
+        let runtime = ThreadPool::builder().pool_size(1).create().unwrap();
+        let prefetcher = default_prefetch(runtime, test_config.prefetcher_config);
+        let fs = S3FuseFilesystem::new(
+            client.clone(),
+            prefetcher,
+            BUCKET_NAME,
+            &Prefix::new(&prefix).expect("valid prefix"),
+            test_config.filesystem_config,
+        );
+        let session = RunnerSession::mount(adapter(fs), mount_dir.path())?;
+        let test_client = create_test_client(client, &prefix);
+
+        Some((mount_dir, session, test_client))
+    }
+
     /// Create a FUSE mount backed by a mock object client, with caching, that does not talk to S3
     pub fn new_with_cache<Cache>(
         cache: Cache,
@@ -289,10 +463,13 @@ pub mod s3_session {
         let (bucket, prefix) = get_test_bucket_and_prefix(test_name);
         let region = get_test_region();
 
-        let client_config = S3ClientConfig::default()
+        let mut client_config = S3ClientConfig::default()
             .part_size(test_config.part_size)
             .endpoint_config(EndpointConfig::new(&region))
             .auth_config(test_config.auth_config);
+        if let Some(buffer_pool) = test_config.read_buffer_pool {
+            client_config = client_config.body_buffer_pool(Arc::new(buffer_pool));
+        }
         let client = S3CrtClient::new(client_config).unwrap();
         let runtime = client.event_loop_group();
         let prefetcher = default_prefetch(runtime, test_config.prefetcher_config);
@@ -0,0 +1,55 @@
+//! Capture of the traces exported by Mountpoint's OpenTelemetry layer, for tests to check.
+
+use std::sync::{Mutex, OnceLock};
+
+use futures::future::BoxFuture;
+use mountpoint_s3::logging::{otel_layer_with_exporter, OtelConfig};
+use opentelemetry::Value;
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry_sdk::trace::TracerProvider;
+use tracing::Subscriber;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+static EXPORTED_SPANS: Mutex<Vec<SpanData>> = Mutex::new(Vec::new());
+static PROVIDER: OnceLock<TracerProvider> = OnceLock::new();
+
+/// An exporter that keeps every span it's asked to export, rather than sending it to a collector
+#[derive(Debug)]
+struct InMemoryExporter;
+
+impl SpanExporter for InMemoryExporter {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        EXPORTED_SPANS.lock().unwrap().extend(batch);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// The OpenTelemetry layer of the global subscriber, which exports to memory
+pub fn layer<S>() -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let (layer, provider) =
+        otel_layer_with_exporter(&OtelConfig::default(), InMemoryExporter).expect("default config is valid");
+    PROVIDER.set(provider).expect("layer is only created once");
+    layer
+}
+
+/// Every span the layer exported so far, from all the tests running in this process, after
+/// flushing the spans still waiting to be batched
+pub fn exported_spans() -> Vec<SpanData> {
+    let provider = PROVIDER.get().expect("global subscriber has the OpenTelemetry layer");
+    for result in provider.force_flush() {
+        result.expect("flush should succeed");
+    }
+    EXPORTED_SPANS.lock().unwrap().clone()
+}
+
+/// The value of the attribute with the given key, if the span has it
+pub fn attribute(span: &SpanData, key: &str) -> Option<Value> {
+    span.attributes
+        .iter()
+        .find(|kv| kv.key.as_str() == key)
+        .map(|kv| kv.value.clone())
+}
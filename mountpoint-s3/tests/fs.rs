@@ -1,25 +1,41 @@
 //! Manually implemented tests executing the FUSE protocol against [S3Filesystem]
 
-use fuser::FileType;
+use bytes::Bytes;
+use futures::executor::ThreadPool;
+use globset::Glob;
 use libc::S_IFREG;
-use mountpoint_s3::fs::{CacheConfig, ToErrno, FUSE_ROOT_INODE};
+use mountpoint_s3::clock::MockClock;
+use mountpoint_s3::data_cache::{DiskDataCache, SharedCacheDir};
+use mountpoint_s3::fs::{
+    CacheConfig, CircuitBreakerConfig, DirOptions, FileType, InodeNo, KernelNotifier, KeyFailureConfig,
+    ListingBootstrap, LookupResult, PathOverrides, PermissionChangeMode, PrefixPattern, ReadCounters, RewindMode,
+    S3FilesystemView, ToErrno, ETAG_XATTR, FUSE_ROOT_INODE, MAX_READDIR_SKIP,
+};
+use mountpoint_s3::mem_limiter::MemoryLimiter;
+use mountpoint_s3::prefetch::{caching_prefetch, BufferPool, IdleBufferPolicy, Prefetch};
 use mountpoint_s3::prefix::Prefix;
 use mountpoint_s3::s3::S3Personality;
-use mountpoint_s3::S3FilesystemConfig;
+use mountpoint_s3::{S3Filesystem, S3FilesystemConfig};
 use mountpoint_s3_client::failure_client::countdown_failure_client;
 use mountpoint_s3_client::mock_client::{MockClient, MockClientConfig, MockClientError, MockObject, Operation};
-use mountpoint_s3_client::types::{ETag, RestoreStatus};
+use mountpoint_s3_client::types::{ETag, ObjectAttribute, RestoreStatus};
 use mountpoint_s3_client::ObjectClient;
 use nix::unistd::{getgid, getuid};
+use proptest::proptest;
+use proptest::sample::Index;
+use proptest_derive::Arbitrary;
 use rand::{Rng, SeedableRng};
 use rand_chacha::ChaCha20Rng;
-use std::collections::HashMap;
-use std::ffi::OsString;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
 use std::ops::Add;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
 use std::str::FromStr;
-use std::sync::Arc;
-use std::time::{Duration, SystemTime};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 use test_case::test_case;
+use time::OffsetDateTime;
 
 mod common;
 use common::{assert_attr, make_test_filesystem, make_test_filesystem_with_client, DirectoryReply, TestS3Filesystem};
@@ -171,6 +187,222 @@ async fn test_read_dir_nested(prefix: &str) {
     fs.releasedir(dir_ino, dir_handle, 0).await.unwrap();
 }
 
+#[test_case(""; "unprefixed")]
+#[test_case("test_prefix/"; "prefixed")]
+#[tokio::test]
+async fn test_max_listing_depth(prefix: &str) {
+    let prefix = Prefix::new(prefix).expect("valid prefix");
+    let config = S3FilesystemConfig {
+        max_listing_depth: Some(2),
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_max_listing_depth", &prefix, config);
+
+    client.add_object(&format!("{prefix}a/b/file.txt"), b"hello".into());
+    client.add_object(&format!("{prefix}a/b/c/d/file.txt"), b"hello".into());
+
+    // Directories up to the maximum depth are listed as normal, and deeper ones are empty
+    let expected_entries: [&[&str]; 4] = [&["a"], &["b"], &["c", "file.txt"], &[]];
+    let mut dir_ino = FUSE_ROOT_INODE;
+    for (depth, expected) in expected_entries.iter().enumerate() {
+        let dir_handle = fs.opendir(dir_ino, 0).await.unwrap().fh;
+        let mut reply = DirectoryReply::default();
+        let _reply = fs.readdirplus(dir_ino, dir_handle, 0, &mut reply).await.unwrap();
+        fs.releasedir(dir_ino, dir_handle, 0).await.unwrap();
+
+        let names = reply.entries.iter().skip(2).map(|e| e.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names, *expected, "unexpected entries at depth {depth}");
+
+        if let Some(&subdir) = expected.first() {
+            dir_ino = fs.lookup(dir_ino, subdir.as_ref()).await.unwrap().attr.ino;
+        }
+    }
+
+    // Lookups beyond the maximum depth still work
+    let entry = fs.lookup(dir_ino, "d".as_ref()).await.unwrap();
+    assert_eq!(entry.attr.kind, FileType::Directory);
+}
+
+#[tokio::test]
+async fn test_opendir_dirs_only() {
+    // Limit normal listings to small pages, which dirs-only listings aren't held to
+    let config = S3FilesystemConfig {
+        max_buffered_dir_entries: Some(10),
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_opendir_dirs_only", &Default::default(), config);
+
+    for i in 0..5 {
+        for j in 0..20 {
+            client.add_object(&format!("dir{i}/file{j}.txt"), b"hello".into());
+        }
+    }
+    for j in 0..50 {
+        client.add_object(&format!("file{j}.txt"), b"hello".into());
+    }
+    // A local directory should still be listed, and a local file skipped
+    fs.mkdir(FUSE_ROOT_INODE, "localdir".as_ref(), libc::S_IFDIR, 0)
+        .await
+        .unwrap();
+    fs.mknod(
+        FUSE_ROOT_INODE,
+        "localfile".as_ref(),
+        libc::S_IFREG | libc::S_IRWXU,
+        0,
+        0,
+    )
+    .await
+    .unwrap();
+
+    let list_dir = |options: DirOptions| {
+        let fs = &fs;
+        async move {
+            let dir_handle = fs.opendir_with_options(FUSE_ROOT_INODE, options).await.unwrap().fh;
+            let mut reply = DirectoryReply::default();
+            let _reply = fs
+                .readdirplus(FUSE_ROOT_INODE, dir_handle, 0, &mut reply)
+                .await
+                .unwrap();
+            fs.releasedir(FUSE_ROOT_INODE, dir_handle, 0).await.unwrap();
+            reply
+                .entries
+                .iter()
+                .skip(2)
+                .map(|e| (e.name.clone(), e.attr.kind))
+                .collect::<Vec<_>>()
+        }
+    };
+
+    // The 55 remote entries of the root fit in one full page, and only the directories get inodes
+    let inodes = fs.metadata_cache_stats().inodes;
+    let list_counter = client.new_counter(Operation::ListObjectsV2);
+    let dir_entries = list_dir(DirOptions { dirs_only: true }).await;
+    assert_eq!(list_counter.count(), 1);
+    assert_eq!(fs.metadata_cache_stats().inodes, inodes + 5);
+
+    // A normal listing of the same directory takes a page per 10 entries
+    let list_counter = client.new_counter(Operation::ListObjectsV2);
+    let all_entries = list_dir(DirOptions::default()).await;
+    assert_eq!(list_counter.count(), 6);
+    assert_eq!(fs.metadata_cache_stats().inodes, inodes + 5 + 50);
+
+    assert_eq!(all_entries.len(), 5 + 50 + 2);
+    let expected_dirs = all_entries
+        .iter()
+        .filter(|(_, kind)| *kind == FileType::Directory)
+        .cloned()
+        .collect::<Vec<_>>();
+    assert_eq!(expected_dirs.len(), 6);
+    assert_eq!(
+        dir_entries, expected_dirs,
+        "dirs_only should emit exactly the directories"
+    );
+}
+
+#[tokio::test]
+async fn test_opendir_on_file() {
+    let (client, fs) = make_test_filesystem("test_opendir_on_file", &Default::default(), Default::default());
+
+    client.add_object("data.bin", b"hello".into());
+    fs.mknod(
+        FUSE_ROOT_INODE,
+        "localfile".as_ref(),
+        libc::S_IFREG | libc::S_IRWXU,
+        0,
+        0,
+    )
+    .await
+    .unwrap();
+
+    for name in ["data.bin", "localfile"] {
+        let entry = fs.lookup(FUSE_ROOT_INODE, name.as_ref()).await.unwrap();
+        assert_eq!(entry.attr.kind, FileType::RegularFile);
+
+        // Files aren't listed as if they were a prefix
+        let list_counter = client.new_counter(Operation::ListObjectsV2);
+        let err = fs
+            .opendir(entry.attr.ino, 0)
+            .await
+            .expect_err("opendir on a file should fail");
+        assert_eq!(err.to_errno(), libc::ENOTDIR, "opendir on {name}");
+        let err = fs
+            .opendir_with_options(entry.attr.ino, DirOptions { dirs_only: true })
+            .await
+            .expect_err("opendir on a file should fail");
+        assert_eq!(err.to_errno(), libc::ENOTDIR, "opendir on {name}");
+        assert_eq!(list_counter.count(), 0);
+    }
+}
+
+#[tokio::test]
+async fn test_open_handles_count() {
+    let (client, fs) = make_test_filesystem("test_open_handles_count", &Default::default(), Default::default());
+    client.add_object("data.bin", b"hello".into());
+    let open_handles = fs.open_handles();
+
+    let ino = fs.lookup(FUSE_ROOT_INODE, "data.bin".as_ref()).await.unwrap().attr.ino;
+    let file_handle = fs.open(ino, libc::O_RDONLY, 0).await.unwrap().fh;
+    let dir_handle = fs.opendir(FUSE_ROOT_INODE, 0).await.unwrap().fh;
+    assert_eq!(open_handles.count(), 2);
+
+    // Failed opens and releases of unknown handles don't change the count
+    fs.opendir(ino, 0).await.expect_err("opendir on a file should fail");
+    fs.release(ino, dir_handle + 1, 0, None, false)
+        .await
+        .expect_err("unknown handle can't be released");
+    assert_eq!(open_handles.count(), 2);
+
+    fs.release(ino, file_handle, 0, None, false).await.unwrap();
+    assert_eq!(open_handles.count(), 1);
+    fs.releasedir(FUSE_ROOT_INODE, dir_handle, 0).await.unwrap();
+    assert_eq!(open_handles.count(), 0);
+}
+
+#[test_case("data.bin", FileType::RegularFile; "file")]
+#[test_case("dir", FileType::Directory; "directory")]
+#[tokio::test]
+async fn test_getattr_after_direct_lookup(name: &str, kind: FileType) {
+    // Without `serve_lookup_from_cache`, lookups always go to S3, but the attributes they find are
+    // still good for `getattr` until the TTL expires
+    let fs_config = S3FilesystemConfig {
+        cache_config: CacheConfig {
+            serve_lookup_from_cache: false,
+            dir_ttl: Duration::from_secs(600),
+            file_ttl: Duration::from_secs(600),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_getattr_after_direct_lookup", &Default::default(), fs_config);
+
+    let last_modified = OffsetDateTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let mut object = MockObject::constant(0xaa, 12345, ETag::for_tests());
+    object.set_last_modified(last_modified);
+    client.add_object("data.bin", object);
+    client.add_object("dir/a.txt", MockObject::constant(0xbb, 10, ETag::for_tests()));
+
+    // Look the name up directly, without listing its parent first
+    let head_counter = client.new_counter(Operation::HeadObject);
+    let list_counter = client.new_counter(Operation::ListObjectsV2);
+    let entry = fs.lookup(FUSE_ROOT_INODE, name.as_ref()).await.unwrap();
+    assert_eq!(entry.attr.kind, kind);
+    let (heads, lists) = (head_counter.count(), list_counter.count());
+    assert!(heads + lists > 0, "lookup should have asked S3");
+
+    // The lookup's attributes are complete, so getattr doesn't need to ask again
+    let attr = fs.getattr(entry.attr.ino).await.unwrap();
+    assert_eq!(head_counter.count(), heads);
+    assert_eq!(list_counter.count(), lists);
+    assert_eq!(attr.attr.ino, entry.attr.ino);
+    assert_eq!(attr.attr.kind, kind);
+    assert_eq!(attr.attr.size, entry.attr.size);
+    assert_eq!(attr.attr.mtime, entry.attr.mtime);
+    if kind == FileType::RegularFile {
+        assert_eq!(attr.attr.size, 12345);
+        assert_eq!(attr.attr.mtime, SystemTime::from(last_modified));
+    }
+}
+
 #[tokio::test]
 async fn test_lookup_negative_cached() {
     let fs_config = S3FilesystemConfig {
@@ -242,6 +474,463 @@ async fn test_lookup_negative_cached() {
     assert_eq!(list_counter.count(), 2);
 }
 
+#[tokio::test]
+async fn test_filesystems_sharing_client() {
+    let bucket = "test_filesystems_sharing_client";
+    let client = Arc::new(MockClient::new(MockClientConfig {
+        bucket: bucket.to_string(),
+        part_size: 1024 * 1024,
+        ..Default::default()
+    }));
+    for prefix in ["a/", "b/"] {
+        for i in 0..5 {
+            let key = format!("{prefix}file{i}");
+            client.add_object(&key, MockObject::ramp(0xaa, 1024, ETag::for_tests()));
+        }
+    }
+
+    // The file systems share a clock, so their TTLs can be compared, and a memory budget, which
+    // the buffers the client downloads into are charged to
+    let clock = Arc::new(MockClock::new());
+    let buffer_size = 1024 * 1024;
+    let memory_limiter = Arc::new(MemoryLimiter::new(2 * buffer_size as u64));
+    client.set_body_buffer_pool(Arc::new(BufferPool::new(buffer_size, 8, memory_limiter.clone())));
+
+    // Both file systems use the negative cache, but with different limits and TTLs
+    let fs_a = make_test_filesystem_with_client(
+        client.clone(),
+        bucket,
+        &Prefix::new("a/").unwrap(),
+        S3FilesystemConfig {
+            cache_config: CacheConfig {
+                serve_lookup_from_cache: true,
+                file_ttl: Duration::from_secs(600),
+                negative_cache_size: 3,
+                ..Default::default()
+            },
+            memory_limiter: memory_limiter.clone(),
+            clock: clock.clone(),
+            ..Default::default()
+        },
+    );
+    let fs_b = make_test_filesystem_with_client(
+        client.clone(),
+        bucket,
+        &Prefix::new("b/").unwrap(),
+        S3FilesystemConfig {
+            cache_config: CacheConfig {
+                serve_lookup_from_cache: true,
+                file_ttl: Duration::from_secs(1),
+                negative_cache_size: 100,
+                ..Default::default()
+            },
+            memory_limiter: memory_limiter.clone(),
+            clock: clock.clone(),
+            ..Default::default()
+        },
+    );
+
+    async fn workload(fs: &TestS3Filesystem<Arc<MockClient>>) {
+        for i in 0..10 {
+            let name = format!("missing{i}");
+            let err = fs
+                .lookup(FUSE_ROOT_INODE, name.as_ref())
+                .await
+                .expect_err("should not exist");
+            assert_eq!(err.to_errno(), libc::ENOENT);
+        }
+        for i in 0..5 {
+            let name = format!("file{i}");
+            let entry = fs.lookup(FUSE_ROOT_INODE, name.as_ref()).await.unwrap();
+            let fh = fs.open(entry.attr.ino, libc::O_RDONLY, 0).await.unwrap().fh;
+            let data = fs.read(entry.attr.ino, fh, 0, 1024, 0, None).await.unwrap();
+            assert_eq!(data.len(), 1024);
+            fs.release(entry.attr.ino, fh, 0, None, true).await.unwrap();
+        }
+    }
+    tokio::join!(workload(&fs_a), workload(&fs_b));
+
+    // Each file system's caches only hold its own entries, within its own limits
+    let stats_a = fs_a.metadata_cache_stats();
+    let stats_b = fs_b.metadata_cache_stats();
+    assert_eq!(stats_a.inodes, 6);
+    assert_eq!(stats_b.inodes, 6);
+    assert_eq!(stats_a.negative_cache_entries, 3);
+    assert_eq!(stats_b.negative_cache_entries, 10);
+
+    // Each negative cache expires entries with its own TTL
+    client.add_object("a/missing9", MockObject::ramp(0xaa, 1024, ETag::for_tests()));
+    client.add_object("b/missing9", MockObject::ramp(0xaa, 1024, ETag::for_tests()));
+    clock.advance(Duration::from_secs(1));
+    let err = fs_a
+        .lookup(FUSE_ROOT_INODE, "missing9".as_ref())
+        .await
+        .expect_err("negative cache entry should still be current");
+    assert_eq!(err.to_errno(), libc::ENOENT);
+    fs_b.lookup(FUSE_ROOT_INODE, "missing9".as_ref())
+        .await
+        .expect("negative cache entry should have expired");
+
+    // A partly read file keeps the rest of its data in a pooled buffer. With one in each file
+    // system, the shared budget is full, so further reads can't take more buffers, but still work.
+    let mut handles = Vec::new();
+    for fs in [&fs_a, &fs_b] {
+        let entry = fs.lookup(FUSE_ROOT_INODE, "file0".as_ref()).await.unwrap();
+        let fh = fs.open(entry.attr.ino, libc::O_RDONLY, 0).await.unwrap().fh;
+        let data = fs.read(entry.attr.ino, fh, 0, 10, 0, None).await.unwrap();
+        assert_eq!(data.len(), 10);
+        handles.push((entry.attr.ino, fh));
+    }
+    assert_eq!(memory_limiter.mem_reserved(), memory_limiter.mem_limit());
+    let entry = fs_a.lookup(FUSE_ROOT_INODE, "file1".as_ref()).await.unwrap();
+    let fh = fs_a.open(entry.attr.ino, libc::O_RDONLY, 0).await.unwrap().fh;
+    let data = fs_a.read(entry.attr.ino, fh, 0, 1024, 0, None).await.unwrap();
+    assert_eq!(data.len(), 1024);
+    fs_a.release(entry.attr.ino, fh, 0, None, true).await.unwrap();
+    assert_eq!(memory_limiter.mem_reserved(), memory_limiter.mem_limit());
+    for (fs, (ino, fh)) in [&fs_a, &fs_b].into_iter().zip(handles) {
+        fs.release(ino, fh, 0, None, true).await.unwrap();
+    }
+
+    // Dropping one file system leaves the other's caches alone
+    drop(fs_b);
+    assert_eq!(fs_a.metadata_cache_stats(), stats_a);
+}
+
+#[tokio::test]
+async fn test_pinned_prefix_never_expires() {
+    let clock = Arc::new(MockClock::new());
+    let ttl = Duration::from_millis(10);
+    let fs_config = S3FilesystemConfig {
+        cache_config: CacheConfig {
+            dir_ttl: ttl,
+            file_ttl: ttl,
+            pinned_prefixes: vec!["reference".to_owned()],
+            ..Default::default()
+        },
+        clock: clock.clone(),
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_pinned_prefix_never_expires", &Default::default(), fs_config);
+    client.add_object("reference/file1.txt", b"hello".into());
+    client.add_object("reference/sub/file2.txt", b"world".into());
+    client.add_object("other/file3.txt", b"other".into());
+    client.add_object("reference-old/file4.txt", b"old".into());
+
+    let head_counter = client.new_counter(Operation::HeadObject);
+    let list_counter = client.new_counter(Operation::ListObjectsV2);
+
+    async fn access(fs: &TestS3Filesystem<Arc<MockClient>>, dir_name: &str, file_name: &str) {
+        let dir = fs.lookup(FUSE_ROOT_INODE, dir_name.as_ref()).await.unwrap();
+        let dir_handle = fs.opendir(dir.attr.ino, 0).await.unwrap().fh;
+        let mut reply = Default::default();
+        let _reply = fs.readdirplus(dir.attr.ino, dir_handle, 0, &mut reply).await.unwrap();
+        fs.releasedir(dir.attr.ino, dir_handle, 0).await.unwrap();
+        let file = fs.lookup(dir.attr.ino, file_name.as_ref()).await.unwrap();
+        let _ = fs.getattr(file.attr.ino).await.unwrap();
+        let _ = fs.getattr(dir.attr.ino).await.unwrap();
+    }
+
+    access(&fs, "reference", "file1.txt").await;
+    let (heads, lists) = (head_counter.count(), list_counter.count());
+    assert!(lists > 0);
+
+    // Long after the TTL would have expired, the pinned directory and its entries are served from
+    // cache, and the directory isn't listed again
+    for _ in 0..3 {
+        clock.advance(ttl * 5);
+        access(&fs, "reference", "file1.txt").await;
+        assert_eq!(head_counter.count(), heads);
+        assert_eq!(list_counter.count(), lists);
+    }
+
+    // Directories outside the pinned prefix still expire, even if their names start with it
+    for (dir_name, file_name) in [("other", "file3.txt"), ("reference-old", "file4.txt")] {
+        access(&fs, dir_name, file_name).await;
+        let lists = list_counter.count();
+        clock.advance(ttl * 5);
+        access(&fs, dir_name, file_name).await;
+        assert!(list_counter.count() > lists, "{dir_name} should expire");
+    }
+}
+
+#[tokio::test]
+async fn test_path_rules_cache_overrides() {
+    let clock = Arc::new(MockClock::new());
+    let long_ttl = Duration::from_secs(600);
+    let fs_config = S3FilesystemConfig {
+        cache_config: CacheConfig {
+            dir_ttl: Duration::from_millis(10),
+            file_ttl: Duration::from_millis(10),
+            ..Default::default()
+        },
+        path_rules: vec![
+            (
+                PrefixPattern::new("archive").unwrap(),
+                PathOverrides {
+                    serve_lookup_from_cache: Some(true),
+                    file_ttl: Some(long_ttl),
+                    dir_ttl: Some(long_ttl),
+                    negative_cache_ttl: Some(long_ttl),
+                },
+            ),
+            (
+                PrefixPattern::new("incoming").unwrap(),
+                PathOverrides {
+                    serve_lookup_from_cache: Some(false),
+                    file_ttl: Some(Duration::ZERO),
+                    dir_ttl: Some(Duration::ZERO),
+                    ..Default::default()
+                },
+            ),
+        ],
+        clock: clock.clone(),
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_path_rules_cache_overrides", &Default::default(), fs_config);
+    client.add_object("archive/file1.txt", b"hello".into());
+    client.add_object("incoming/file2.txt", b"world".into());
+
+    let head_counter = client.new_counter(Operation::HeadObject);
+
+    async fn access(fs: &TestS3Filesystem<Arc<MockClient>>, dir_name: &str, file_name: &str) {
+        let dir = fs.lookup(FUSE_ROOT_INODE, dir_name.as_ref()).await.unwrap();
+        let file = fs.lookup(dir.attr.ino, file_name.as_ref()).await.unwrap();
+        let _ = fs.getattr(file.attr.ino).await.unwrap();
+        let _ = fs
+            .lookup(dir.attr.ino, "missing.txt".as_ref())
+            .await
+            .expect_err("should fail as no object exists");
+    }
+
+    access(&fs, "archive", "file1.txt").await;
+    access(&fs, "incoming", "file2.txt").await;
+
+    let mut archive_heads = 0;
+    let mut incoming_heads = 0;
+    for _ in 0..3 {
+        clock.advance(Duration::from_millis(50));
+
+        let heads = head_counter.count();
+        access(&fs, "archive", "file1.txt").await;
+        archive_heads += head_counter.count() - heads;
+
+        let heads = head_counter.count();
+        access(&fs, "incoming", "file2.txt").await;
+        incoming_heads += head_counter.count() - heads;
+    }
+
+    // Entries under `archive/` are served from the cache, including the missing name, while every
+    // access under `incoming/` goes back to S3
+    assert_eq!(archive_heads, 0);
+    assert!(
+        incoming_heads >= 3 * 3,
+        "expected a HeadObject per lookup, got {incoming_heads}"
+    );
+}
+
+#[tokio::test]
+async fn test_immutable_key_patterns() {
+    let clock = Arc::new(MockClock::new());
+    let ttl = Duration::from_millis(10);
+    let fs_config = S3FilesystemConfig {
+        cache_config: CacheConfig {
+            dir_ttl: ttl,
+            file_ttl: ttl,
+            ..Default::default()
+        },
+        immutable_key_patterns: vec![PrefixPattern::new("objects/sha256").unwrap()],
+        clock: clock.clone(),
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_immutable_key_patterns", &Default::default(), fs_config);
+    client.add_object("objects/sha256/abc", MockObject::from(b"immutable"));
+    client.add_object("objects/mutable", MockObject::from(b"mutable"));
+
+    let head_counter = client.new_counter(Operation::HeadObject);
+    let list_counter = client.new_counter(Operation::ListObjectsV2);
+
+    let objects = fs.lookup(FUSE_ROOT_INODE, "objects".as_ref()).await.unwrap().attr.ino;
+    let sha256 = fs.lookup(objects, "sha256".as_ref()).await.unwrap().attr.ino;
+
+    async fn access(fs: &TestS3Filesystem<Arc<MockClient>>, parent: InodeNo, name: &str) -> (InodeNo, Duration) {
+        let entry = fs.lookup(parent, name.as_ref()).await.unwrap();
+        let fh = fs.open(entry.attr.ino, libc::O_RDONLY, 0).await.unwrap().fh;
+        fs.release(entry.attr.ino, fh, 0, None, true).await.unwrap();
+        let attr = fs.getattr(entry.attr.ino).await.unwrap();
+        (entry.attr.ino, entry.ttl.min(attr.ttl))
+    }
+
+    let (ino, ttl_reported) = access(&fs, sha256, "abc").await;
+    assert!(ttl_reported > Duration::from_secs(365 * 24 * 60 * 60));
+    let requests = head_counter.count() + list_counter.count();
+
+    // Long after the TTL would have expired, lookups, opens, and getattrs of the immutable file are
+    // all served from cache
+    for _ in 0..3 {
+        clock.advance(ttl * 5);
+        assert_eq!(access(&fs, sha256, "abc").await.0, ino);
+        assert_eq!(head_counter.count() + list_counter.count(), requests);
+    }
+
+    // Files that don't match are revalidated once their TTL expires
+    access(&fs, objects, "mutable").await;
+    let requests = head_counter.count() + list_counter.count();
+    clock.advance(ttl * 5);
+    access(&fs, objects, "mutable").await;
+    assert!(head_counter.count() + list_counter.count() > requests);
+
+    // If the immutable object changes anyway, reading it fails as for any other file, and from then
+    // on it's revalidated like any other file
+    client.add_object("objects/sha256/abc", MockObject::from(b"changed!!!"));
+    let fh = fs.open(ino, libc::O_RDONLY, 0).await.unwrap().fh;
+    let err = fs
+        .read(ino, fh, 0, 9, 0, None)
+        .await
+        .expect_err("object changed since it was looked up");
+    assert_eq!(err.to_errno(), libc::ESTALE);
+    fs.release(ino, fh, 0, None, true).await.unwrap();
+
+    let heads = head_counter.count();
+    let entry = fs.lookup(sha256, "abc".as_ref()).await.unwrap();
+    assert!(head_counter.count() > heads);
+    assert_eq!(entry.attr.size, 10);
+    assert!(entry.ttl <= ttl);
+}
+
+#[tokio::test]
+async fn test_recently_written_ttl() {
+    let ttl = Duration::from_secs(600);
+    let fs_config = S3FilesystemConfig {
+        cache_config: CacheConfig {
+            serve_lookup_from_cache: true,
+            dir_ttl: ttl,
+            file_ttl: ttl,
+            recently_written_ttl: Duration::ZERO,
+            recently_modified_window: Duration::from_secs(60),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_recently_written_ttl", &Default::default(), fs_config);
+    let mut stable = MockObject::from_bytes(b"stable", ETag::for_tests());
+    stable.set_last_modified(OffsetDateTime::now_utc() - time::Duration::hours(1));
+    client.add_object("stable.txt", stable);
+    let mut fresh = MockObject::from_bytes(b"fresh", ETag::for_tests());
+    fresh.set_last_modified(OffsetDateTime::now_utc());
+    client.add_object("fresh.txt", fresh);
+
+    // A file that hasn't changed in a while gets the full TTL
+    let entry = fs.lookup(FUSE_ROOT_INODE, "stable.txt".as_ref()).await.unwrap();
+    assert!(entry.ttl > ttl / 2, "unexpected TTL {:?}", entry.ttl);
+    let attr = fs.getattr(entry.attr.ino).await.unwrap();
+    assert!(attr.ttl > ttl / 2, "unexpected TTL {:?}", attr.ttl);
+
+    // A recently modified one may still be changing
+    let entry = fs.lookup(FUSE_ROOT_INODE, "fresh.txt".as_ref()).await.unwrap();
+    assert_eq!(entry.ttl, Duration::ZERO);
+
+    // While a file is being written, every getattr shows its current size
+    let mode = libc::S_IFREG | libc::S_IRWXU; // regular file + 0700 permissions
+    let dentry = fs.mknod(FUSE_ROOT_INODE, "new.txt".as_ref(), mode, 0, 0).await.unwrap();
+    assert_eq!(dentry.ttl, Duration::ZERO);
+    let file_ino = dentry.attr.ino;
+    let fh = fs
+        .open(file_ino, libc::S_IFREG as i32 | libc::O_WRONLY, 0)
+        .await
+        .unwrap()
+        .fh;
+    let mut offset = 0;
+    for chunk in [&b"hello"[..], &b" world"[..]] {
+        let written = fs.write(file_ino, fh, offset, chunk, 0, 0, None).await.unwrap();
+        offset += written as i64;
+        let attr = fs.getattr(file_ino).await.unwrap();
+        assert_eq!(attr.attr.size, offset as u64);
+        assert_eq!(attr.ttl, Duration::ZERO);
+    }
+    fs.release(file_ino, fh, 0, None, false).await.unwrap();
+
+    // Once uploaded, the new object is looked up in S3 again, and is still recently modified
+    let head_counter = client.new_counter(Operation::HeadObject);
+    let entry = fs.lookup(FUSE_ROOT_INODE, "new.txt".as_ref()).await.unwrap();
+    assert_eq!(entry.attr.size, 11);
+    assert_eq!(entry.ttl, Duration::ZERO);
+    assert_eq!(head_counter.count(), 1);
+}
+
+#[test_case(Duration::ZERO; "zero")]
+#[test_case(Duration::from_millis(100); "100ms")]
+#[test_case(Duration::from_secs(1); "1s")]
+#[test_case(Duration::from_secs(86400); "1 day")]
+#[test_case(Duration::MAX; "max")]
+#[tokio::test]
+async fn test_reply_ttls(ttl: Duration) {
+    // TTLs longer than this are clamped rather than overflowing
+    const MAX_TTL: Duration = Duration::from_secs(200 * 365 * 24 * 60 * 60);
+
+    let fs_config = S3FilesystemConfig {
+        cache_config: CacheConfig {
+            serve_lookup_from_cache: true,
+            dir_ttl: ttl,
+            file_ttl: ttl,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_reply_ttls", &Default::default(), fs_config);
+    client.add_object("dir/file.txt", b"hello".into());
+
+    // The TTLs in replies are whatever is left of the configured TTL, to the nanosecond
+    let expected = ttl.min(MAX_TTL);
+    let assert_ttl = |actual: Duration| {
+        assert!(actual <= expected, "{actual:?} should be at most {expected:?}");
+        assert!(
+            expected - actual < Duration::from_millis(50),
+            "{actual:?} should be close to {expected:?}"
+        );
+    };
+
+    let dir = fs.lookup(FUSE_ROOT_INODE, "dir".as_ref()).await.unwrap();
+    assert_ttl(dir.ttl);
+    let file = fs.lookup(dir.attr.ino, "file.txt".as_ref()).await.unwrap();
+    assert_ttl(file.ttl);
+    let attr = fs.getattr(file.attr.ino).await.unwrap();
+    assert_ttl(attr.ttl);
+}
+
+#[tokio::test]
+async fn test_names_with_nul_rejected() {
+    let (client, fs) = make_test_filesystem("test_names_with_nul_rejected", &Default::default(), Default::default());
+    client.add_object("foo", b"foo".into());
+
+    let head_counter = client.new_counter(Operation::HeadObject);
+    let list_counter = client.new_counter(Operation::ListObjectsV2);
+
+    let name = OsStr::from_bytes(b"foo\0bar");
+
+    let err = fs
+        .lookup(FUSE_ROOT_INODE, name)
+        .await
+        .expect_err("lookup of name with NUL should fail");
+    assert_eq!(err.to_errno(), libc::EINVAL);
+
+    let err = fs
+        .mknod(FUSE_ROOT_INODE, name, libc::S_IFREG | libc::S_IRWXU, 0, 0)
+        .await
+        .expect_err("mknod of name with NUL should fail");
+    assert_eq!(err.to_errno(), libc::EINVAL);
+
+    let err = fs
+        .mkdir(FUSE_ROOT_INODE, name, libc::S_IFDIR, 0)
+        .await
+        .expect_err("mkdir of name with NUL should fail");
+    assert_eq!(err.to_errno(), libc::EINVAL);
+
+    // Names should be rejected before sending any requests to S3
+    assert_eq!(head_counter.count(), 0);
+    assert_eq!(list_counter.count(), 0);
+}
+
 #[tokio::test]
 async fn test_lookup_then_open_cached() {
     let fs_config = S3FilesystemConfig {
@@ -308,30 +997,72 @@ async fn test_lookup_then_open_no_cache() {
     assert_eq!(list_counter.count(), 3);
 }
 
+#[test_case(true; "cached lookups")]
+#[test_case(false; "no cached lookups")]
 #[tokio::test]
-async fn test_readdir_then_open_cached() {
+async fn test_read_then_lookup(serve_lookup_from_cache: bool) {
+    let clock = Arc::new(MockClock::new());
+    let file_ttl = Duration::from_secs(600);
     let fs_config = S3FilesystemConfig {
         cache_config: CacheConfig {
-            serve_lookup_from_cache: true,
-            dir_ttl: Duration::from_secs(600),
-            file_ttl: Duration::from_secs(600),
+            serve_lookup_from_cache,
+            file_ttl,
             ..Default::default()
         },
+        clock: clock.clone(),
         ..Default::default()
     };
-    let (client, fs) = make_test_filesystem("test_readdir_then_open_cached", &Default::default(), fs_config);
+    let (client, fs) = make_test_filesystem("test_read_then_lookup", &Default::default(), fs_config);
 
     client.add_object("file1.txt", MockObject::constant(0xa1, 15, ETag::for_tests()));
 
-    // Repeat to check readdir is not currently served from cache
-    for _ in 0..2 {
-        let head_counter = client.new_counter(Operation::HeadObject);
-        let list_counter = client.new_counter(Operation::ListObjectsV2);
-
-        let dir_ino = FUSE_ROOT_INODE;
-        let dir_handle = fs.opendir(dir_ino, 0).await.unwrap().fh;
-        let mut reply = Default::default();
-        let _reply = fs.readdirplus(dir_ino, dir_handle, 0, &mut reply).await.unwrap();
+    let entry = fs.lookup(FUSE_ROOT_INODE, "file1.txt".as_ref()).await.unwrap();
+    let ino = entry.attr.ino;
+
+    // Read the file most of a TTL after the lookup
+    clock.advance(file_ttl - Duration::from_secs(100));
+    let fh = fs.open(ino, S_IFREG as i32, 0).await.unwrap().fh;
+    let data = fs.read(ino, fh, 0, 15, 0, None).await.unwrap();
+    assert_eq!(&data[..], &[0xa1; 15]);
+    fs.release(ino, fh, 0, None, true).await.unwrap();
+
+    // After the lookup's stat would have expired, the attributes the read confirmed are reused,
+    // but only if lookups may be served from the cache at all
+    clock.advance(Duration::from_secs(200));
+    let head_counter = client.new_counter(Operation::HeadObject);
+    let list_counter = client.new_counter(Operation::ListObjectsV2);
+    let entry = fs.lookup(FUSE_ROOT_INODE, "file1.txt".as_ref()).await.unwrap();
+    assert_eq!(entry.attr.ino, ino);
+    assert_eq!(entry.attr.size, 15);
+    let expected_requests = if serve_lookup_from_cache { 0 } else { 1 };
+    assert_eq!(head_counter.count(), expected_requests);
+    assert_eq!(list_counter.count(), expected_requests);
+}
+
+#[tokio::test]
+async fn test_readdir_then_open_cached() {
+    let fs_config = S3FilesystemConfig {
+        cache_config: CacheConfig {
+            serve_lookup_from_cache: true,
+            dir_ttl: Duration::from_secs(600),
+            file_ttl: Duration::from_secs(600),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_readdir_then_open_cached", &Default::default(), fs_config);
+
+    client.add_object("file1.txt", MockObject::constant(0xa1, 15, ETag::for_tests()));
+
+    // Repeat to check readdir is not currently served from cache
+    for _ in 0..2 {
+        let head_counter = client.new_counter(Operation::HeadObject);
+        let list_counter = client.new_counter(Operation::ListObjectsV2);
+
+        let dir_ino = FUSE_ROOT_INODE;
+        let dir_handle = fs.opendir(dir_ino, 0).await.unwrap().fh;
+        let mut reply = Default::default();
+        let _reply = fs.readdirplus(dir_ino, dir_handle, 0, &mut reply).await.unwrap();
 
         assert_eq!(reply.entries.len(), 2 + 1);
 
@@ -408,6 +1139,284 @@ async fn test_unlink_cached() {
     assert_eq!(list_counter.count(), 2);
 }
 
+/// A [KernelNotifier] that records the entries and inodes it was asked to invalidate
+#[derive(Debug, Clone, Default)]
+struct RecordingNotifier {
+    invalidated: Arc<Mutex<Vec<(InodeNo, OsString)>>>,
+    invalidated_inodes: Arc<Mutex<Vec<InodeNo>>>,
+}
+
+impl RecordingNotifier {
+    fn take(&self) -> Vec<(InodeNo, OsString)> {
+        std::mem::take(&mut *self.invalidated.lock().unwrap())
+    }
+
+    fn take_inodes(&self) -> Vec<InodeNo> {
+        std::mem::take(&mut *self.invalidated_inodes.lock().unwrap())
+    }
+}
+
+impl KernelNotifier for RecordingNotifier {
+    fn invalidate_entry(&self, parent: InodeNo, name: &OsStr) {
+        self.invalidated.lock().unwrap().push((parent, name.to_owned()));
+    }
+
+    fn invalidate_inode(&self, ino: InodeNo) {
+        self.invalidated_inodes.lock().unwrap().push(ino);
+    }
+}
+
+#[test_case(true; "enabled")]
+#[test_case(false; "disabled")]
+#[tokio::test]
+async fn test_invalidate_kernel_entries(invalidate_kernel_entries: bool) {
+    let fs_config = S3FilesystemConfig {
+        allow_delete: true,
+        invalidate_kernel_entries,
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_invalidate_kernel_entries", &Default::default(), fs_config);
+    let notifier = RecordingNotifier::default();
+    fs.notifier_slot().set(notifier.clone());
+
+    client.add_object("dir/file1.txt", b"hello".into());
+    let dir_ino = fs.lookup(FUSE_ROOT_INODE, "dir".as_ref()).await.unwrap().attr.ino;
+    fs.lookup(dir_ino, "file1.txt".as_ref()).await.unwrap();
+
+    // Failed mutations shouldn't invalidate anything
+    fs.unlink(dir_ino, "missing.txt".as_ref())
+        .await
+        .expect_err("file doesn't exist");
+    assert_eq!(notifier.take(), vec![]);
+
+    fs.unlink(dir_ino, "file1.txt".as_ref()).await.unwrap();
+    let expected = if invalidate_kernel_entries {
+        vec![(dir_ino, OsString::from("file1.txt"))]
+    } else {
+        vec![]
+    };
+    assert_eq!(notifier.take(), expected);
+    assert!(!client.contains_key("dir/file1.txt"));
+
+    fs.mkdir(FUSE_ROOT_INODE, "localdir".as_ref(), libc::S_IFDIR, 0)
+        .await
+        .unwrap();
+    fs.rmdir(FUSE_ROOT_INODE, "localdir".as_ref()).await.unwrap();
+    let expected = if invalidate_kernel_entries {
+        vec![(FUSE_ROOT_INODE, OsString::from("localdir"))]
+    } else {
+        vec![]
+    };
+    assert_eq!(notifier.take(), expected);
+}
+
+#[test_case(true; "enabled")]
+#[test_case(false; "disabled")]
+#[tokio::test]
+async fn test_negative_entry_replies(negative_entry_replies: bool) {
+    let ttl = Duration::from_secs(60);
+    let fs_config = S3FilesystemConfig {
+        cache_config: CacheConfig {
+            file_ttl: ttl,
+            ..Default::default()
+        },
+        negative_entry_replies,
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_negative_entry_replies", &Default::default(), fs_config);
+    let notifier = RecordingNotifier::default();
+    fs.notifier_slot().set(notifier.clone());
+
+    client.add_object("existing.txt", b"hello".into());
+    let result = fs.lookup_entry(FUSE_ROOT_INODE, "existing.txt".as_ref()).await.unwrap();
+    assert!(matches!(result, LookupResult::Found(entry) if entry.attr.ino != 0));
+
+    let result = fs.lookup_entry(FUSE_ROOT_INODE, "new.txt".as_ref()).await;
+    if negative_entry_replies {
+        assert!(matches!(result, Ok(LookupResult::NegativeCached(t)) if t == ttl));
+    } else {
+        assert_eq!(result.expect_err("file doesn't exist").to_errno(), libc::ENOENT);
+    }
+    // Plain lookups still report missing names as errors
+    let err = fs
+        .lookup(FUSE_ROOT_INODE, "new.txt".as_ref())
+        .await
+        .expect_err("file doesn't exist");
+    assert_eq!(err.to_errno(), libc::ENOENT);
+
+    // Creating the name must invalidate the negative entry the kernel may be caching
+    fs.mknod(FUSE_ROOT_INODE, "new.txt".as_ref(), libc::S_IFREG | libc::S_IRWXU, 0, 0)
+        .await
+        .unwrap();
+    let expected = if negative_entry_replies {
+        vec![(FUSE_ROOT_INODE, OsString::from("new.txt"))]
+    } else {
+        vec![]
+    };
+    assert_eq!(notifier.take(), expected);
+
+    // Names that were never reported as missing don't need invalidating
+    fs.mkdir(FUSE_ROOT_INODE, "newdir".as_ref(), libc::S_IFDIR, 0)
+        .await
+        .unwrap();
+    assert_eq!(notifier.take(), vec![]);
+}
+
+#[test_case(Duration::ZERO; "zero ttl")]
+#[test_case(Duration::from_millis(50); "expired")]
+#[tokio::test]
+async fn test_negative_entry_replies_ttl(ttl: Duration) {
+    let fs_config = S3FilesystemConfig {
+        cache_config: CacheConfig {
+            file_ttl: ttl,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (_client, fs) = make_test_filesystem("test_negative_entry_replies_ttl", &Default::default(), fs_config);
+    let notifier = RecordingNotifier::default();
+    fs.notifier_slot().set(notifier.clone());
+
+    let result = fs.lookup_entry(FUSE_ROOT_INODE, "new.txt".as_ref()).await;
+    if ttl.is_zero() {
+        // There's nothing for the kernel to cache
+        assert_eq!(result.expect_err("file doesn't exist").to_errno(), libc::ENOENT);
+    } else {
+        assert!(matches!(result, Ok(LookupResult::NegativeCached(t)) if t == ttl));
+        std::thread::sleep(ttl);
+    }
+
+    // The kernel no longer has a negative entry to invalidate
+    fs.mknod(FUSE_ROOT_INODE, "new.txt".as_ref(), libc::S_IFREG | libc::S_IRWXU, 0, 0)
+        .await
+        .unwrap();
+    assert_eq!(notifier.take(), vec![]);
+}
+
+#[tokio::test]
+async fn test_directory_poll_invalidates_kernel() {
+    let fs_config = S3FilesystemConfig {
+        cache_config: CacheConfig {
+            file_ttl: Duration::from_secs(60),
+            dir_ttl: Duration::from_secs(60),
+            ..Default::default()
+        },
+        negative_entry_replies: true,
+        directory_poll_interval: Some(Duration::from_millis(10)),
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_directory_poll_invalidates_kernel", &Default::default(), fs_config);
+    let notifier = RecordingNotifier::default();
+    fs.notifier_slot().set(notifier.clone());
+    let list_counter = client.new_counter(Operation::ListObjectsV2);
+
+    client.add_object("dir/file1.txt", b"hello".into());
+    let dir_ino = fs.lookup(FUSE_ROOT_INODE, "dir".as_ref()).await.unwrap().attr.ino;
+    fs.lookup(dir_ino, "file1.txt".as_ref()).await.unwrap();
+    let result = fs.lookup_entry(dir_ino, "file2.txt".as_ref()).await;
+    assert!(matches!(result, Ok(LookupResult::NegativeCached(_))));
+
+    // The first listing, by readdir or the poller, is the baseline
+    let lists = list_counter.count();
+    let dir_handle = fs.opendir(dir_ino, 0).await.unwrap().fh;
+    let mut reply = DirectoryReply::default();
+    let _reply = fs.readdir(dir_ino, dir_handle, 0, &mut reply).await.unwrap();
+
+    // Let the poller list the directory a few times, without finding a change
+    while list_counter.count() < lists + 4 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(notifier.take_inodes(), vec![]);
+    assert_eq!(notifier.take(), vec![]);
+
+    client.add_object("dir/file2.txt", b"world".into());
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while notifier.take_inodes().is_empty() {
+        assert!(Instant::now() < deadline, "poller should notice the change");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    // The file the kernel may have cached, and the name it was told doesn't exist
+    let mut invalidated = notifier.take();
+    invalidated.sort();
+    assert_eq!(
+        invalidated,
+        vec![
+            (dir_ino, OsString::from("file1.txt")),
+            (dir_ino, OsString::from("file2.txt"))
+        ]
+    );
+    fs.lookup(dir_ino, "file2.txt".as_ref())
+        .await
+        .expect("new file should be visible");
+    fs.releasedir(dir_ino, dir_handle, 0).await.unwrap();
+}
+
+#[test_case(false; "no marker")]
+#[test_case(true; "marker")]
+#[tokio::test]
+async fn test_getattr_directory_removed_remotely(marker: bool) {
+    let clock = Arc::new(MockClock::new());
+    let dir_ttl = Duration::from_secs(600);
+    let fs_config = S3FilesystemConfig {
+        cache_config: CacheConfig {
+            serve_lookup_from_cache: true,
+            dir_ttl,
+            file_ttl: Duration::from_secs(600),
+            ..Default::default()
+        },
+        clock: clock.clone(),
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem(
+        "test_getattr_directory_removed_remotely",
+        &Default::default(),
+        fs_config,
+    );
+    let notifier = RecordingNotifier::default();
+    fs.notifier_slot().set(notifier.clone());
+
+    if marker {
+        client.add_object("dir/", MockObject::constant(0, 0, ETag::for_tests()));
+    }
+    client.add_object("dir/file1.txt", b"hello".into());
+    client.add_object("dir/file2.txt", b"world".into());
+    let dir_ino = fs.lookup(FUSE_ROOT_INODE, "dir".as_ref()).await.unwrap().attr.ino;
+
+    // Remove every child behind our back, then list the directory a while after the lookup
+    client.remove_object("dir/file1.txt");
+    client.remove_object("dir/file2.txt");
+    clock.advance(Duration::from_secs(100));
+    let dir_handle = fs.opendir(dir_ino, 0).await.unwrap().fh;
+    let mut reply = DirectoryReply::default();
+    let _reply = fs.readdir(dir_ino, dir_handle, 0, &mut reply).await.unwrap();
+    let names: Vec<_> = reply.entries.iter().map(|entry| entry.name.clone()).collect();
+    assert_eq!(names, vec![OsString::from("."), OsString::from("..")]);
+    fs.releasedir(dir_ino, dir_handle, 0).await.unwrap();
+
+    // Until a directory TTL has passed since the listing, which is after the lookup's stat would
+    // have expired, the directory is still served from the cache
+    clock.advance(dir_ttl - Duration::from_secs(1));
+    let attr = fs.getattr(dir_ino).await.expect("directory should be cached").attr;
+    assert_eq!(attr.kind, FileType::Directory);
+    assert_eq!(notifier.take(), vec![]);
+
+    clock.advance(Duration::from_secs(2));
+    if marker {
+        // Only the marker is left, but that's enough for the directory to exist
+        let attr = fs.getattr(dir_ino).await.expect("directory should still exist").attr;
+        assert_eq!(attr.kind, FileType::Directory);
+        assert_eq!(notifier.take(), vec![]);
+    } else {
+        let err = fs.getattr(dir_ino).await.expect_err("directory should be gone");
+        assert_eq!(err.to_errno(), libc::ENOENT);
+        assert_eq!(notifier.take(), vec![(FUSE_ROOT_INODE, OsString::from("dir"))]);
+        // The kernel drops its last reference, and the inode is evicted
+        fs.forget(dir_ino, 1).await;
+        let err = fs.getattr(dir_ino).await.expect_err("inode should be evicted");
+        assert_eq!(err.to_errno(), libc::ENOENT);
+    }
+}
+
 #[tokio::test]
 async fn test_mknod_cached() {
     const BUCKET_NAME: &str = "test_mknod_cached";
@@ -708,147 +1717,665 @@ async fn test_duplicate_write_fails() {
     assert_eq!(err, libc::EPERM);
 }
 
+#[test_case(false; "new file")]
+#[test_case(true; "overwritten file")]
 #[tokio::test]
-async fn test_upload_aborted_on_write_failure() {
-    const BUCKET_NAME: &str = "test_upload_aborted_on_write_failure";
-    const FILE_NAME: &str = "foo.bin";
+async fn test_getattr_during_write(overwrite: bool) {
+    const BUCKET_NAME: &str = "test_getattr_during_write";
 
-    let client_config = MockClientConfig {
-        bucket: BUCKET_NAME.to_string(),
-        part_size: 1024 * 1024,
+    // Expire cached metadata immediately, so getattr can't just be served from the cache
+    let config = S3FilesystemConfig {
+        cache_config: CacheConfig {
+            file_ttl: Duration::ZERO,
+            ..Default::default()
+        },
+        allow_overwrite: true,
         ..Default::default()
     };
+    let (client, fs) = make_test_filesystem(BUCKET_NAME, &Default::default(), config);
+
+    let (file_ino, flags) = if overwrite {
+        client.add_object("file.bin", MockObject::constant(0xa1, 1024, ETag::for_tests()));
+        let entry = fs.lookup(FUSE_ROOT_INODE, "file.bin".as_ref()).await.unwrap();
+        assert_eq!(entry.attr.size, 1024);
+        (entry.attr.ino, libc::O_WRONLY | libc::O_TRUNC)
+    } else {
+        let mode = libc::S_IFREG | libc::S_IRWXU; // regular file + 0700 permissions
+        let dentry = fs
+            .mknod(FUSE_ROOT_INODE, "file.bin".as_ref(), mode, 0, 0)
+            .await
+            .unwrap();
+        (dentry.attr.ino, libc::O_WRONLY)
+    };
 
-    let client = Arc::new(MockClient::new(client_config));
-    let mut put_failures = HashMap::new();
-    put_failures.insert(1, Ok((2, MockClientError("error".to_owned().into()))));
+    let fh = fs.open(file_ino, libc::S_IFREG as i32 | flags, 0).await.unwrap().fh;
+    let head_counter = client.new_counter(Operation::HeadObject);
 
-    let failure_client = countdown_failure_client(
-        client.clone(),
-        Default::default(),
-        Default::default(),
-        Default::default(),
-        put_failures,
-    );
-    let fs = make_test_filesystem_with_client(
-        Arc::new(failure_client),
-        BUCKET_NAME,
-        &Default::default(),
-        Default::default(),
+    let mut offset = 0;
+    for _ in 0..3 {
+        let data = [0xaa; 100];
+        let written = fs.write(file_ino, fh, offset, &data, 0, 0, None).await.unwrap();
+        offset += written as i64;
+
+        let attr = fs.getattr(file_ino).await.unwrap();
+        assert_eq!(
+            attr.attr.size, offset as u64,
+            "getattr should report the size written so far"
+        );
+    }
+    assert_eq!(
+        head_counter.count(),
+        0,
+        "getattr shouldn't go to S3 for a file being written"
     );
 
+    fs.release(file_ino, fh, 0, None, false).await.unwrap();
+}
+
+#[test_case(false; "extend only")]
+#[test_case(true; "write after extending")]
+#[tokio::test]
+async fn test_setattr_extends_file(write_after: bool) {
+    const BUCKET_NAME: &str = "test_setattr_extends_file";
+    // Bigger than the chunks of zeros the upload is padded with
+    const EXTENDED_SIZE: u64 = 200 * 1024;
+
+    let (client, fs) = make_test_filesystem(BUCKET_NAME, &Default::default(), Default::default());
+
     let mode = libc::S_IFREG | libc::S_IRWXU; // regular file + 0700 permissions
-    let dentry = fs.mknod(FUSE_ROOT_INODE, FILE_NAME.as_ref(), mode, 0, 0).await.unwrap();
-    assert_eq!(dentry.attr.size, 0);
+    let dentry = fs
+        .mknod(FUSE_ROOT_INODE, "file.bin".as_ref(), mode, 0, 0)
+        .await
+        .unwrap();
     let file_ino = dentry.attr.ino;
-
     let fh = fs
         .open(file_ino, libc::S_IFREG as i32 | libc::O_WRONLY, 0)
         .await
         .unwrap()
         .fh;
 
-    let written = fs
-        .write(file_ino, fh, 0, &[0xaa; 27], 0, 0, None)
-        .await
-        .expect("first write should succeed");
-
-    assert!(client.is_upload_in_progress(FILE_NAME));
+    let head = [0xaa; 100];
+    fs.write(file_ino, fh, 0, &head, 0, 0, None).await.unwrap();
 
-    let write_error = fs
-        .write(file_ino, fh, written as i64, &[0xaa; 27], 0, 0, None)
+    let attr = fs
+        .setattr(file_ino, None, None, None, None, None, Some(EXTENDED_SIZE), None)
         .await
-        .expect_err("second write should fail")
-        .to_errno();
-    assert_eq!(write_error, libc::EIO);
+        .unwrap();
+    assert_eq!(attr.attr.size, EXTENDED_SIZE);
+    assert_eq!(fs.getattr(file_ino).await.unwrap().attr.size, EXTENDED_SIZE);
 
-    let err = fs
-        .write(file_ino, fh, 0, &[0xaa; 27], 0, 0, None)
+    // Truncating down isn't supported, and leaves the size alone
+    let attr = fs
+        .setattr(file_ino, None, None, None, None, None, Some(10), None)
         .await
-        .expect_err("subsequent writes should fail")
-        .to_errno();
-
-    assert_eq!(err, libc::EIO);
+        .unwrap();
+    assert_eq!(attr.attr.size, EXTENDED_SIZE);
 
-    assert!(!client.is_upload_in_progress(FILE_NAME));
-    assert!(!client.contains_key(FILE_NAME));
+    let mut expected = head.to_vec();
+    expected.resize(EXTENDED_SIZE as usize, 0);
+    if write_after {
+        let tail = [0xbb; 100];
+        fs.write(file_ino, fh, EXTENDED_SIZE as i64, &tail, 0, 0, None)
+            .await
+            .unwrap();
+        expected.extend_from_slice(&tail);
+        assert_eq!(fs.getattr(file_ino).await.unwrap().attr.size, expected.len() as u64);
+    }
 
-    let err = fs
-        .fsync(file_ino, fh, true)
-        .await
-        .expect_err("subsequent fsync should fail")
-        .to_errno();
-    assert_eq!(err, libc::EIO);
+    fs.release(file_ino, fh, 0, None, false).await.unwrap();
 
-    fs.release(file_ino, fh, 0, None, true)
-        .await
-        .expect("release succeeds (no op)");
+    let get = client.get_object(BUCKET_NAME, "file.bin", None, None).await.unwrap();
+    let actual = get.collect().await.unwrap();
+    assert_eq!(actual.len(), expected.len());
+    assert_eq!(&actual[..], &expected[..]);
 }
 
 #[tokio::test]
-async fn test_upload_aborted_on_fsync_failure() {
-    const BUCKET_NAME: &str = "test_upload_aborted_on_fsync_failure";
-    const FILE_NAME: &str = "foo.bin";
+async fn test_partial_write_copies_unchanged_parts() {
+    const BUCKET_NAME: &str = "test_partial_write_copies_unchanged_parts";
+    // The test file system's client uses 1MiB parts
+    const PART_SIZE: usize = 1024 * 1024;
+    const OBJECT_SIZE: usize = 3 * PART_SIZE + 100;
 
-    let client_config = MockClientConfig {
-        bucket: BUCKET_NAME.to_string(),
-        part_size: 1024 * 1024,
+    let config = S3FilesystemConfig {
+        allow_overwrite: true,
+        allow_partial_writes: true,
         ..Default::default()
     };
+    let (client, fs) = make_test_filesystem(BUCKET_NAME, &Default::default(), config);
+    let object = MockObject::ramp(0x11, OBJECT_SIZE, ETag::for_tests());
+    let mut expected = object.read(0, OBJECT_SIZE).to_vec();
+    client.add_object("file.bin", object);
 
-    let client = Arc::new(MockClient::new(client_config));
-    let mut put_failures = HashMap::new();
-    put_failures.insert(1, Ok((2, MockClientError("error".to_owned().into()))));
+    let entry = fs.lookup(FUSE_ROOT_INODE, "file.bin".as_ref()).await.unwrap();
+    let ino = entry.attr.ino;
+    let fh = fs.open(ino, libc::O_WRONLY, 0).await.unwrap().fh;
 
-    let failure_client = countdown_failure_client(
-        client.clone(),
-        Default::default(),
-        Default::default(),
-        Default::default(),
-        put_failures,
+    // Writes must start on a part boundary and stay within the file
+    let err = fs.write(ino, fh, 100, &[0xff; 100], 0, 0, None).await.unwrap_err();
+    assert_eq!(err.to_errno(), libc::EINVAL);
+    let err = fs
+        .write(ino, fh, (3 * PART_SIZE) as i64, &[0xff; 200], 0, 0, None)
+        .await
+        .unwrap_err();
+    assert_eq!(err.to_errno(), libc::EINVAL);
+
+    // Overwrite the second part
+    for offset in (PART_SIZE..2 * PART_SIZE).step_by(128 * 1024) {
+        let written = fs
+            .write(ino, fh, offset as i64, &[0xff; 128 * 1024], 0, 0, None)
+            .await
+            .unwrap();
+        assert_eq!(written, 128 * 1024);
+    }
+    expected[PART_SIZE..2 * PART_SIZE].fill(0xff);
+    assert_eq!(fs.getattr(ino).await.unwrap().attr.size, OBJECT_SIZE as u64);
+
+    let get_counter = client.new_counter(Operation::GetObject);
+    let copy_counter = client.new_counter(Operation::UploadPartCopy);
+    let upload_counter = client.new_counter(Operation::UploadPart);
+    fs.flush(ino, fh, 0, 0).await.unwrap();
+    fs.release(ino, fh, 0, None, false).await.unwrap();
+
+    // Only the modified part was uploaded, and the rest copied server-side
+    assert_eq!(upload_counter.count(), 1);
+    assert_eq!(copy_counter.count(), 3);
+    assert_eq!(get_counter.count(), 0);
+    let get = client.get_object(BUCKET_NAME, "file.bin", None, None).await.unwrap();
+    let actual = get.collect().await.unwrap();
+    assert_eq!(actual.len(), expected.len());
+    assert!(
+        actual[..] == expected[..],
+        "object should have the new part and keep the others"
     );
-    let fs = make_test_filesystem_with_client(
-        Arc::new(failure_client),
-        BUCKET_NAME,
-        &Default::default(),
-        Default::default(),
+
+    // A write fails if the object changed since the file was opened
+    let fh = fs.open(ino, libc::O_WRONLY, 0).await.unwrap().fh;
+    fs.write(ino, fh, 0, &[0xee; 100], 0, 0, None).await.unwrap();
+    client.add_object(
+        "file.bin",
+        MockObject::constant(0x22, OBJECT_SIZE, ETag::from_str("changed").unwrap()),
     );
+    let err = fs.flush(ino, fh, 0, 0).await.unwrap_err();
+    assert_eq!(err.to_errno(), libc::ESTALE);
+    fs.release(ino, fh, 0, None, false).await.unwrap();
+}
 
-    let mode = libc::S_IFREG | libc::S_IRWXU; // regular file + 0700 permissions
-    let dentry = fs.mknod(FUSE_ROOT_INODE, FILE_NAME.as_ref(), mode, 0, 0).await.unwrap();
-    assert_eq!(dentry.attr.size, 0);
-    let file_ino = dentry.attr.ino;
+#[test_case(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]; "sequential")]
+#[test_case(&[0, 2, 1, 3, 4, 6, 5, 7, 9, 8]; "swapped pages")]
+#[test_case(&[1, 2, 3, 0, 4, 5, 6, 7, 8, 9]; "first page last")]
+#[test_case(&[9, 8, 7, 6, 5, 4, 3, 2, 1, 0]; "reversed")]
+#[tokio::test]
+async fn test_writeback_cache_out_of_order_writes(pages: &[usize]) {
+    const BUCKET_NAME: &str = "test_writeback_cache_out_of_order_writes";
+    const PAGE_SIZE: usize = 4096;
+    // The kernel extends the file past the last page it writes, then truncates it back
+    const EXTENDED_SIZE: u64 = 12 * PAGE_SIZE as u64;
+    const FILE_SIZE: u64 = 10 * PAGE_SIZE as u64 - 100;
 
-    let fh = fs
-        .open(file_ino, libc::S_IFREG as i32 | libc::O_WRONLY, 0)
-        .await
-        .unwrap()
+    let config = S3FilesystemConfig {
+        writeback_cache: true,
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem(BUCKET_NAME, &Default::default(), config);
+
+    let mode = libc::S_IFREG | libc::S_IRWXU; // regular file + 0700 permissions
+    let dentry = fs
+        .mknod(FUSE_ROOT_INODE, "file.bin".as_ref(), mode, 0, 0)
+        .await
+        .unwrap();
+    let file_ino = dentry.attr.ino;
+    // With the writeback cache, the kernel opens files for reading and writing
+    let fh = fs
+        .open(file_ino, libc::S_IFREG as i32 | libc::O_RDWR, 0)
+        .await
+        .unwrap()
         .fh;
 
-    _ = fs
+    fs.setattr(file_ino, None, None, None, None, None, Some(EXTENDED_SIZE), None)
+        .await
+        .unwrap();
+
+    // Dirty pages are flushed roughly, but not exactly, in order
+    let data: Vec<u8> = (0..FILE_SIZE as usize).map(|i| (i % 251) as u8).collect();
+    for &page in pages {
+        let offset = page * PAGE_SIZE;
+        let chunk = &data[offset..(offset + PAGE_SIZE).min(data.len())];
+        fs.write(file_ino, fh, offset as i64, chunk, 0, 0, None).await.unwrap();
+    }
+    assert_eq!(fs.getattr(file_ino).await.unwrap().attr.size, EXTENDED_SIZE);
+
+    let attr = fs
+        .setattr(file_ino, None, None, None, None, None, Some(FILE_SIZE), None)
+        .await
+        .unwrap();
+    assert_eq!(attr.attr.size, FILE_SIZE);
+
+    fs.flush(file_ino, fh, 0, 0).await.unwrap();
+    fs.release(file_ino, fh, 0, None, false).await.unwrap();
+
+    let get = client.get_object(BUCKET_NAME, "file.bin", None, None).await.unwrap();
+    let actual = get.collect().await.unwrap();
+    assert_eq!(actual.len(), data.len());
+    assert_eq!(&actual[..], &data[..]);
+}
+
+#[tokio::test]
+async fn test_writeback_cache_random_writes() {
+    const BUCKET_NAME: &str = "test_writeback_cache_random_writes";
+    const PAGE_SIZE: i64 = 4096;
+
+    let config = S3FilesystemConfig {
+        writeback_cache: true,
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem(BUCKET_NAME, &Default::default(), config);
+
+    let mode = libc::S_IFREG | libc::S_IRWXU; // regular file + 0700 permissions
+    let dentry = fs
+        .mknod(FUSE_ROOT_INODE, "file.bin".as_ref(), mode, 0, 0)
+        .await
+        .unwrap();
+    let file_ino = dentry.attr.ino;
+    let fh = fs
+        .open(file_ino, libc::S_IFREG as i32 | libc::O_RDWR, 0)
+        .await
+        .unwrap()
+        .fh;
+
+    fs.write(file_ino, fh, 0, &[0xaa; PAGE_SIZE as usize], 0, 0, None)
+        .await
+        .unwrap();
+
+    // Writes more than a part beyond the end of the upload aren't ones the writeback cache would
+    // send, so they still fail and abort the upload
+    let offset = 64 * 1024 * 1024;
+    let err = fs
+        .write(file_ino, fh, offset, &[0xbb; PAGE_SIZE as usize], 0, 0, None)
+        .await
+        .expect_err("random write should fail");
+    assert_eq!(err.to_errno(), libc::EINVAL);
+    let err = fs
+        .write(file_ino, fh, PAGE_SIZE, &[0xbb; PAGE_SIZE as usize], 0, 0, None)
+        .await
+        .expect_err("upload should be aborted");
+    assert_eq!(err.to_errno(), libc::EINVAL);
+
+    fs.release(file_ino, fh, 0, None, false).await.unwrap();
+    assert!(!client.contains_key("file.bin"));
+}
+
+#[test_case(300 * 1024; "write fills preallocation")]
+#[test_case(200 * 1024; "write stops short of preallocation")]
+#[test_case(400 * 1024; "write goes past preallocation")]
+#[tokio::test]
+async fn test_fallocate_then_write(written: usize) {
+    const BUCKET_NAME: &str = "test_fallocate_then_write";
+    const PREALLOCATED: u64 = 300 * 1024;
+
+    let (client, fs) = make_test_filesystem(BUCKET_NAME, &Default::default(), Default::default());
+
+    let mode = libc::S_IFREG | libc::S_IRWXU; // regular file + 0700 permissions
+    let dentry = fs
+        .mknod(FUSE_ROOT_INODE, "file.bin".as_ref(), mode, 0, 0)
+        .await
+        .unwrap();
+    let file_ino = dentry.attr.ino;
+    let fh = fs
+        .open(file_ino, libc::S_IFREG as i32 | libc::O_WRONLY, 0)
+        .await
+        .unwrap()
+        .fh;
+
+    fs.fallocate(file_ino, fh, 0, PREALLOCATED as i64, 0).await.unwrap();
+    assert_eq!(fs.getattr(file_ino).await.unwrap().attr.size, PREALLOCATED);
+
+    // Other modes, like punching holes, aren't supported
+    let err = fs
+        .fallocate(
+            file_ino,
+            fh,
+            0,
+            10,
+            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+        )
+        .await
+        .expect_err("punching holes should fail");
+    assert_eq!(err.to_errno(), libc::EOPNOTSUPP);
+
+    // Sequential writes replace the zeros the file was preallocated with
+    let data = vec![0xaa; written];
+    for (i, chunk) in data.chunks(64 * 1024).enumerate() {
+        fs.write(file_ino, fh, (i * 64 * 1024) as i64, chunk, 0, 0, None)
+            .await
+            .unwrap();
+    }
+    let expected_size = (written as u64).max(PREALLOCATED);
+    assert_eq!(fs.getattr(file_ino).await.unwrap().attr.size, expected_size);
+
+    fs.release(file_ino, fh, 0, None, false).await.unwrap();
+
+    let mut expected = data;
+    expected.resize(expected_size as usize, 0);
+    let get = client.get_object(BUCKET_NAME, "file.bin", None, None).await.unwrap();
+    let actual = get.collect().await.unwrap();
+    assert_eq!(actual.len(), expected.len());
+    assert_eq!(&actual[..], &expected[..]);
+}
+
+#[tokio::test]
+async fn test_read_while_writing() {
+    const BUCKET_NAME: &str = "test_read_while_writing";
+    const WRITE_SIZE: usize = 64 * 1024;
+    const WINDOW: usize = 4 * WRITE_SIZE;
+
+    let config = S3FilesystemConfig {
+        local_read_window: WINDOW,
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem(BUCKET_NAME, &Default::default(), config);
+
+    let mode = libc::S_IFREG | libc::S_IRWXU; // regular file + 0700 permissions
+    let dentry = fs
+        .mknod(FUSE_ROOT_INODE, "output.bin".as_ref(), mode, 0, 0)
+        .await
+        .unwrap();
+    let file_ino = dentry.attr.ino;
+    let write_fh = fs
+        .open(file_ino, libc::S_IFREG as i32 | libc::O_WRONLY, 0)
+        .await
+        .unwrap()
+        .fh;
+
+    // A reader can open the file as soon as it's opened for writing, and sees it empty
+    let opened = fs.open(file_ino, libc::O_RDONLY, 0).await.unwrap();
+    assert_ne!(opened.flags, 0, "reads of a file being written should use direct I/O");
+    let read_fh = opened.fh;
+    let read = fs.read(file_ino, read_fh, 0, 1024, 0, None).await.unwrap();
+    assert!(read.is_empty());
+
+    // Tail the file as it's written. Each read sees everything written so far, and nothing more.
+    let mut rng = ChaCha20Rng::seed_from_u64(0x12345678);
+    let mut body = vec![0u8; 8 * WRITE_SIZE];
+    rng.fill(&mut body[..]);
+    let mut read_offset = 0;
+    for (i, chunk) in body.chunks(WRITE_SIZE).enumerate() {
+        let written = fs
+            .write(file_ino, write_fh, (i * WRITE_SIZE) as i64, chunk, 0, 0, None)
+            .await
+            .unwrap();
+        assert_eq!(written as usize, chunk.len());
+        let written_size = (i + 1) * WRITE_SIZE;
+        assert_eq!(fs.getattr(file_ino).await.unwrap().attr.size, written_size as u64);
+
+        let read = fs
+            .read(file_ino, read_fh, read_offset as i64, 2 * WRITE_SIZE as u32, 0, None)
+            .await
+            .unwrap();
+        assert_eq!(read_offset + read.len(), written_size);
+        assert_eq!(&read[..], &body[read_offset..written_size]);
+        read_offset = written_size;
+    }
+
+    let read = fs
+        .read(file_ino, read_fh, (body.len() - WINDOW) as i64, WINDOW as u32, 0, None)
+        .await
+        .unwrap();
+    assert_eq!(&read[..], &body[body.len() - WINDOW..]);
+
+    // Nothing is visible in the bucket until the upload completes
+    assert!(!client.contains_key("output.bin"));
+    let get_counter = client.new_counter(Operation::GetObject);
+
+    // Data that's dropped out of the window is read from S3 once the upload completes. The read
+    // behind the window waits for the writer to close the file.
+    let behind = (body.len() - WINDOW - WRITE_SIZE) as i64;
+    let (read, released) = futures::join!(
+        fs.read(file_ino, read_fh, behind, 2 * WRITE_SIZE as u32, 0, None),
+        async {
+            assert_eq!(
+                get_counter.count(),
+                0,
+                "reads behind the window should wait for the upload"
+            );
+            fs.release(file_ino, write_fh, 0, None, false).await
+        },
+    );
+    released.unwrap();
+    let read = read.unwrap();
+    assert_eq!(&read[..], &body[behind as usize..behind as usize + 2 * WRITE_SIZE]);
+    assert_eq!(get_counter.count(), 1);
+
+    // Later reads of old data don't have to wait, and recent data is still served locally
+    let read = fs.read(file_ino, read_fh, 0, 1024, 0, None).await.unwrap();
+    assert_eq!(&read[..], &body[..1024]);
+    let read = fs
+        .read(file_ino, read_fh, (body.len() - 1024) as i64, 1024, 0, None)
+        .await
+        .unwrap();
+    assert_eq!(&read[..], &body[body.len() - 1024..]);
+    assert_eq!(get_counter.count(), 2);
+    fs.release(file_ino, read_fh, 0, None, false).await.unwrap();
+
+    // Once it has, new readers read the object from S3 as usual
+    let read_fh = fs.open(file_ino, libc::O_RDONLY, 0).await.unwrap().fh;
+    let read = fs.read(file_ino, read_fh, 0, body.len() as u32, 0, None).await.unwrap();
+    assert_eq!(&read[..], &body[..]);
+    fs.release(file_ino, read_fh, 0, None, false).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_read_while_writing_disabled() {
+    let config = S3FilesystemConfig {
+        local_read_window: 0,
+        ..Default::default()
+    };
+    let (_client, fs) = make_test_filesystem("test_read_while_writing_disabled", &Default::default(), config);
+
+    let mode = libc::S_IFREG | libc::S_IRWXU; // regular file + 0700 permissions
+    let dentry = fs
+        .mknod(FUSE_ROOT_INODE, "output.bin".as_ref(), mode, 0, 0)
+        .await
+        .unwrap();
+    let file_ino = dentry.attr.ino;
+    let write_fh = fs
+        .open(file_ino, libc::S_IFREG as i32 | libc::O_WRONLY, 0)
+        .await
+        .unwrap()
+        .fh;
+    fs.write(file_ino, write_fh, 0, b"hello", 0, 0, None).await.unwrap();
+
+    let err = fs
+        .open(file_ino, libc::O_RDONLY, 0)
+        .await
+        .expect_err("files being written shouldn't be readable");
+    assert_eq!(err.to_errno(), libc::EPERM);
+    fs.release(file_ino, write_fh, 0, None, false).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_idle_read_buffers_released() {
+    const OBJECT_SIZE: usize = 8 * 1024 * 1024;
+    const READ_SIZE: usize = 128 * 1024;
+
+    let clock = Arc::new(MockClock::new());
+    let config = S3FilesystemConfig {
+        idle_read_buffer_policy: IdleBufferPolicy::Reset,
+        idle_read_buffer_timeout: Duration::from_secs(30),
+        clock: clock.clone(),
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_idle_read_buffers_released", &Default::default(), config);
+    let object = MockObject::ramp(0xaa, OBJECT_SIZE, ETag::for_tests());
+    let body = object.read(0, OBJECT_SIZE);
+    client.add_object("file.bin", object);
+
+    let entry = fs.lookup(FUSE_ROOT_INODE, "file.bin".as_ref()).await.unwrap();
+    let ino = entry.attr.ino;
+    let fh = fs.open(ino, libc::O_RDONLY, 0).await.unwrap().fh;
+    let read = fs.read(ino, fh, 0, READ_SIZE as u32, 0, None).await.unwrap();
+    assert_eq!(&read[..], &body[..READ_SIZE]);
+    let stats = fs.prefetch_stats(fh).await.unwrap();
+    assert!(stats.buffered_bytes + stats.inflight_bytes > 0);
+    assert_eq!(stats.idle_releases, 0);
+
+    // The policy applies once the read has paused for the whole timeout, and only once per pause
+    clock.advance(Duration::from_secs(29));
+    assert_eq!(fs.release_idle_reads(), 0);
+    assert_eq!(fs.prefetch_stats(fh).await.unwrap().idle_releases, 0);
+    clock.advance(Duration::from_secs(1));
+    // The background sweeper shares the clock, so it may get to the stream first
+    fs.release_idle_reads();
+    assert_eq!(fs.release_idle_reads(), 0);
+    let stats = fs.prefetch_stats(fh).await.unwrap();
+    assert_eq!(stats.buffered_bytes + stats.inflight_bytes, 0);
+    assert_eq!(stats.idle_releases, 1);
+
+    // Reads carry on where they left off, fetching the data again
+    let read = fs
+        .read(ino, fh, READ_SIZE as i64, READ_SIZE as u32, 0, None)
+        .await
+        .unwrap();
+    assert_eq!(&read[..], &body[READ_SIZE..2 * READ_SIZE]);
+    assert!(fs.prefetch_stats(fh).await.unwrap().requests > stats.requests);
+    fs.release(ino, fh, 0, None, true).await.unwrap();
+}
+
+#[test_case(false; "without overwrite")]
+#[test_case(true; "with overwrite")]
+#[tokio::test]
+async fn test_setattr_extends_remote_file(allow_overwrite: bool) {
+    const BUCKET_NAME: &str = "test_setattr_extends_remote_file";
+    // The test file system's client uses 1MiB parts
+    const PART_SIZE: usize = 1024 * 1024;
+    const OBJECT_SIZE: usize = PART_SIZE + 100;
+    const EXTENDED_SIZE: usize = 3 * PART_SIZE + 50;
+
+    let config = S3FilesystemConfig {
+        allow_overwrite,
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem(BUCKET_NAME, &Default::default(), config);
+    let object = MockObject::ramp(0x11, OBJECT_SIZE, ETag::for_tests());
+    let mut expected = object.read(0, OBJECT_SIZE).to_vec();
+    client.add_object("file.bin", object);
+
+    // A handle that's open for reading while the file is extended reads the extended object
+    let entry = fs.lookup(FUSE_ROOT_INODE, "file.bin".as_ref()).await.unwrap();
+    let ino = entry.attr.ino;
+    let fh = fs.open(ino, libc::O_RDONLY, 0).await.unwrap().fh;
+    let data = fs.read(ino, fh, 0, 100, 0, None).await.unwrap();
+    assert_eq!(&data[..], &expected[..100]);
+
+    let copy_counter = client.new_counter(Operation::UploadPartCopy);
+    let upload_counter = client.new_counter(Operation::UploadPart);
+    let result = fs
+        .setattr(ino, None, None, None, None, None, Some(EXTENDED_SIZE as u64), None)
+        .await;
+    if !allow_overwrite {
+        assert_eq!(result.unwrap_err().to_errno(), libc::EPERM);
+        assert_eq!(fs.getattr(ino).await.unwrap().attr.size, OBJECT_SIZE as u64);
+        return;
+    }
+    assert_eq!(result.unwrap().attr.size, EXTENDED_SIZE as u64);
+    assert_eq!(fs.getattr(ino).await.unwrap().attr.size, EXTENDED_SIZE as u64);
+
+    // The complete first part was copied, and the rest uploaded with the zeros a part at a time
+    assert_eq!(copy_counter.count(), 1);
+    assert_eq!(upload_counter.count(), 3);
+
+    expected.resize(EXTENDED_SIZE, 0);
+    for offset in [OBJECT_SIZE - 100, EXTENDED_SIZE - 50] {
+        let data = fs.read(ino, fh, offset as i64, 1000, 0, None).await.unwrap();
+        assert_eq!(&data[..], &expected[offset..(offset + 1000).min(EXTENDED_SIZE)]);
+    }
+    fs.release(ino, fh, 0, None, true).await.unwrap();
+
+    let get = client.get_object(BUCKET_NAME, "file.bin", None, None).await.unwrap();
+    let actual = get.collect().await.unwrap();
+    assert_eq!(actual.len(), expected.len());
+    assert_eq!(&actual[..], &expected[..]);
+}
+
+#[tokio::test]
+async fn test_upload_aborted_on_write_failure() {
+    const BUCKET_NAME: &str = "test_upload_aborted_on_write_failure";
+    const FILE_NAME: &str = "foo.bin";
+
+    let client_config = MockClientConfig {
+        bucket: BUCKET_NAME.to_string(),
+        part_size: 1024 * 1024,
+        ..Default::default()
+    };
+
+    let client = Arc::new(MockClient::new(client_config));
+    let mut put_failures = HashMap::new();
+    put_failures.insert(1, Ok((2, MockClientError("error".to_owned().into()))));
+
+    let failure_client = countdown_failure_client(
+        client.clone(),
+        Default::default(),
+        Default::default(),
+        Default::default(),
+        put_failures,
+    );
+    let fs = make_test_filesystem_with_client(
+        Arc::new(failure_client),
+        BUCKET_NAME,
+        &Default::default(),
+        Default::default(),
+    );
+
+    let mode = libc::S_IFREG | libc::S_IRWXU; // regular file + 0700 permissions
+    let dentry = fs.mknod(FUSE_ROOT_INODE, FILE_NAME.as_ref(), mode, 0, 0).await.unwrap();
+    assert_eq!(dentry.attr.size, 0);
+    let file_ino = dentry.attr.ino;
+
+    let fh = fs
+        .open(file_ino, libc::S_IFREG as i32 | libc::O_WRONLY, 0)
+        .await
+        .unwrap()
+        .fh;
+
+    let written = fs
         .write(file_ino, fh, 0, &[0xaa; 27], 0, 0, None)
         .await
         .expect("first write should succeed");
 
     assert!(client.is_upload_in_progress(FILE_NAME));
 
+    let write_error = fs
+        .write(file_ino, fh, written as i64, &[0xaa; 27], 0, 0, None)
+        .await
+        .expect_err("second write should fail")
+        .to_errno();
+    assert_eq!(write_error, libc::EIO);
+
     let err = fs
-        .fsync(file_ino, fh, true)
+        .write(file_ino, fh, 0, &[0xaa; 27], 0, 0, None)
         .await
-        .expect_err("subsequent fsync should fail")
+        .expect_err("subsequent writes should fail")
         .to_errno();
+
     assert_eq!(err, libc::EIO);
 
     assert!(!client.is_upload_in_progress(FILE_NAME));
     assert!(!client.contains_key(FILE_NAME));
 
+    let err = fs
+        .fsync(file_ino, fh, true)
+        .await
+        .expect_err("subsequent fsync should fail")
+        .to_errno();
+    assert_eq!(err, libc::EIO);
+
     fs.release(file_ino, fh, 0, None, true)
         .await
         .expect("release succeeds (no op)");
 }
 
 #[tokio::test]
-async fn test_upload_aborted_on_release_failure() {
+async fn test_upload_aborted_on_fsync_failure() {
     const BUCKET_NAME: &str = "test_upload_aborted_on_fsync_failure";
     const FILE_NAME: &str = "foo.bin";
 
@@ -895,52 +2422,143 @@ async fn test_upload_aborted_on_release_failure() {
     assert!(client.is_upload_in_progress(FILE_NAME));
 
     let err = fs
-        .release(file_ino, fh, 0, None, true)
+        .fsync(file_ino, fh, true)
         .await
-        .expect_err("subsequent release should fail")
+        .expect_err("subsequent fsync should fail")
         .to_errno();
     assert_eq!(err, libc::EIO);
 
     assert!(!client.is_upload_in_progress(FILE_NAME));
     assert!(!client.contains_key(FILE_NAME));
+
+    fs.release(file_ino, fh, 0, None, true)
+        .await
+        .expect("release succeeds (no op)");
 }
 
 #[tokio::test]
-async fn test_stat_block_size() {
-    let (client, fs) = make_test_filesystem("test_stat_block_size", &Default::default(), Default::default());
+async fn test_upload_aborted_on_release_failure() {
+    const BUCKET_NAME: &str = "test_upload_aborted_on_fsync_failure";
+    const FILE_NAME: &str = "foo.bin";
 
-    client.add_object(
-        "file0.txt",
-        MockObject::constant(0xa1, 0, ETag::from_str("test_etag_1").unwrap()),
-    );
-    client.add_object(
-        "file1.txt",
-        MockObject::constant(0xa2, 1, ETag::from_str("test_etag_2").unwrap()),
-    );
-    client.add_object(
-        "file4096.txt",
-        MockObject::constant(0xa3, 4096, ETag::from_str("test_etag_3").unwrap()),
-    );
-    client.add_object(
-        "file4097.txt",
-        MockObject::constant(0xa3, 4097, ETag::from_str("test_etag_4").unwrap()),
-    );
+    let client_config = MockClientConfig {
+        bucket: BUCKET_NAME.to_string(),
+        part_size: 1024 * 1024,
+        ..Default::default()
+    };
+
+    let client = Arc::new(MockClient::new(client_config));
+    let mut put_failures = HashMap::new();
+    put_failures.insert(1, Ok((2, MockClientError("error".to_owned().into()))));
+
+    let failure_client = countdown_failure_client(
+        client.clone(),
+        Default::default(),
+        Default::default(),
+        Default::default(),
+        put_failures,
+    );
+    let fs = make_test_filesystem_with_client(
+        Arc::new(failure_client),
+        BUCKET_NAME,
+        &Default::default(),
+        Default::default(),
+    );
+
+    let mode = libc::S_IFREG | libc::S_IRWXU; // regular file + 0700 permissions
+    let dentry = fs.mknod(FUSE_ROOT_INODE, FILE_NAME.as_ref(), mode, 0, 0).await.unwrap();
+    assert_eq!(dentry.attr.size, 0);
+    let file_ino = dentry.attr.ino;
+
+    let fh = fs
+        .open(file_ino, libc::S_IFREG as i32 | libc::O_WRONLY, 0)
+        .await
+        .unwrap()
+        .fh;
+
+    _ = fs
+        .write(file_ino, fh, 0, &[0xaa; 27], 0, 0, None)
+        .await
+        .expect("first write should succeed");
+
+    assert!(client.is_upload_in_progress(FILE_NAME));
+
+    let err = fs
+        .release(file_ino, fh, 0, None, true)
+        .await
+        .expect_err("subsequent release should fail")
+        .to_errno();
+    assert_eq!(err, libc::EIO);
+
+    assert!(!client.is_upload_in_progress(FILE_NAME));
+    assert!(!client.contains_key(FILE_NAME));
+}
+
+#[tokio::test]
+async fn test_stat_block_size() {
+    let (client, fs) = make_test_filesystem("test_stat_block_size", &Default::default(), Default::default());
+
+    client.add_object(
+        "file0.txt",
+        MockObject::constant(0xa1, 0, ETag::from_str("test_etag_1").unwrap()),
+    );
+    client.add_object(
+        "file1.txt",
+        MockObject::constant(0xa2, 1, ETag::from_str("test_etag_2").unwrap()),
+    );
+    client.add_object(
+        "file4096.txt",
+        MockObject::constant(0xa3, 4096, ETag::from_str("test_etag_3").unwrap()),
+    );
+    client.add_object(
+        "file4097.txt",
+        MockObject::constant(0xa3, 4097, ETag::from_str("test_etag_4").unwrap()),
+    );
 
     let lookup = fs.lookup(FUSE_ROOT_INODE, "file0.txt".as_ref()).await.unwrap();
     assert_eq!(lookup.attr.blocks, 0);
-    assert_eq!(lookup.attr.blksize, 4096);
+    assert_eq!(lookup.attr.blksize, 128 * 1024);
 
     let lookup = fs.lookup(FUSE_ROOT_INODE, "file1.txt".as_ref()).await.unwrap();
     assert_eq!(lookup.attr.blocks, 1);
-    assert_eq!(lookup.attr.blksize, 4096);
+    assert_eq!(lookup.attr.blksize, 128 * 1024);
 
     let lookup = fs.lookup(FUSE_ROOT_INODE, "file4096.txt".as_ref()).await.unwrap();
     assert_eq!(lookup.attr.blocks, 8);
-    assert_eq!(lookup.attr.blksize, 4096);
+    assert_eq!(lookup.attr.blksize, 128 * 1024);
 
     let lookup = fs.lookup(FUSE_ROOT_INODE, "file4097.txt".as_ref()).await.unwrap();
     assert_eq!(lookup.attr.blocks, 9);
-    assert_eq!(lookup.attr.blksize, 4096);
+    assert_eq!(lookup.attr.blksize, 128 * 1024);
+}
+
+#[test_case(None; "default")]
+#[test_case(Some(1024 * 1024); "custom")]
+#[tokio::test]
+async fn test_stat_block_size_consistent(block_size: Option<u32>) {
+    let mut config = S3FilesystemConfig::default();
+    if let Some(block_size) = block_size {
+        config.block_size = block_size;
+    }
+    let expected = block_size.unwrap_or(128 * 1024);
+    let (client, fs) = make_test_filesystem("test_stat_block_size_consistent", &Default::default(), config);
+
+    client.add_object("dir/file.txt", MockObject::constant(0xa1, 10, ETag::for_tests()));
+
+    let dir = fs.lookup(FUSE_ROOT_INODE, "dir".as_ref()).await.unwrap();
+    assert_eq!(dir.attr.blksize, expected);
+    let file = fs.lookup(dir.attr.ino, "file.txt".as_ref()).await.unwrap();
+    assert_eq!(file.attr.blksize, expected);
+    let attr = fs.getattr(file.attr.ino).await.unwrap().attr;
+    assert_eq!(attr.blksize, expected);
+
+    let dir_handle = fs.opendir(dir.attr.ino, 0).await.unwrap().fh;
+    let mut reply = Default::default();
+    let _reply = fs.readdirplus(dir.attr.ino, dir_handle, 0, &mut reply).await.unwrap();
+    assert_eq!(reply.entries.len(), 3);
+    for entry in reply.entries {
+        assert_eq!(entry.attr.blksize, expected, "wrong block size for {:?}", entry.name);
+    }
 }
 
 #[test_case("foo"; "remove file")]
@@ -1276,6 +2894,180 @@ async fn test_flexible_retrieval_objects() {
     }
 }
 
+#[tokio::test]
+async fn test_readdir_offset_past_end() {
+    let (client, fs) = make_test_filesystem("test_readdir_offset_past_end", &Default::default(), Default::default());
+
+    for i in 0..5 {
+        client.add_object(&format!("foo{i}"), b"foo".into());
+    }
+
+    // An offset far past the end of the directory returns nothing, and isn't an error
+    let dir_handle = fs.opendir(FUSE_ROOT_INODE, 0).await.unwrap().fh;
+    let entries = ls(&fs, dir_handle, 1000, 20).await;
+    assert_eq!(entries, vec![]);
+    let entries = ls(&fs, dir_handle, i64::MAX, 20).await;
+    assert_eq!(entries, vec![]);
+    fs.releasedir(FUSE_ROOT_INODE, dir_handle, 0).await.unwrap();
+
+    // A later offset within the directory skips the entries before it
+    let dir_handle = fs.opendir(FUSE_ROOT_INODE, 0).await.unwrap().fh;
+    let entries = ls(&fs, dir_handle, 4, 20).await;
+    let names = entries.into_iter().map(|(_, name)| name).collect::<Vec<_>>();
+    assert_eq!(names, vec!["foo2", "foo3", "foo4"]);
+    fs.releasedir(FUSE_ROOT_INODE, dir_handle, 0).await.unwrap();
+
+    // Offsets from before the directory shrank may now be past its end
+    let dir_handle = fs.opendir(FUSE_ROOT_INODE, 0).await.unwrap().fh;
+    let entries = ls(&fs, dir_handle, 0, 20).await;
+    assert_eq!(entries.len(), 7); // 5 files + 2 dirs (. and ..) = 7 entries
+    for i in 1..5 {
+        client.remove_object(&format!("foo{i}"));
+    }
+    let entries = ls(&fs, dir_handle, 0, 3).await;
+    assert_eq!(entries.len(), 3);
+    let entries = ls(&fs, dir_handle, 6, 20).await;
+    assert_eq!(entries, vec![]);
+    fs.releasedir(FUSE_ROOT_INODE, dir_handle, 0).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_readdir_skip_limit() {
+    let (client, fs) = make_test_filesystem("test_readdir_skip_limit", &Default::default(), Default::default());
+
+    let count = MAX_READDIR_SKIP + 10;
+    for i in 0..count {
+        client.add_object(&format!("foo{i:05}"), b"foo".into());
+    }
+
+    // Skipping more entries than the limit fails, rather than listing as far as the offset
+    let dir_handle = fs.opendir(FUSE_ROOT_INODE, 0).await.unwrap().fh;
+    let mut reply = DirectoryReply::new(20);
+    let err = fs
+        .readdirplus(FUSE_ROOT_INODE, dir_handle, MAX_READDIR_SKIP + 5, &mut reply)
+        .await
+        .expect_err("offset is too far ahead");
+    assert_eq!(err.to_errno(), libc::EINVAL);
+    fs.releasedir(FUSE_ROOT_INODE, dir_handle, 0).await.unwrap();
+
+    // Up to the limit is fine
+    let dir_handle = fs.opendir(FUSE_ROOT_INODE, 0).await.unwrap().fh;
+    let entries = ls(&fs, dir_handle, MAX_READDIR_SKIP, 20).await;
+    let names = entries.into_iter().map(|(_, name)| name).collect::<Vec<_>>();
+    let expected = (MAX_READDIR_SKIP - 2..count)
+        .map(|i| OsString::from(format!("foo{i:05}")))
+        .collect::<Vec<_>>();
+    assert_eq!(names, expected);
+    fs.releasedir(FUSE_ROOT_INODE, dir_handle, 0).await.unwrap();
+}
+
+#[test_case(20; "listed completely before shrinking")]
+#[test_case(5; "shrunk mid-iteration")]
+#[tokio::test]
+async fn test_readdir_offset_past_shrunken_end(first_page_size: usize) {
+    let (client, fs) = make_test_filesystem(
+        "test_readdir_offset_past_shrunken_end",
+        &Default::default(),
+        Default::default(),
+    );
+
+    for i in 0..10 {
+        client.add_object(&format!("foo{i}"), b"foo".into());
+    }
+
+    let dir_handle = fs.opendir(FUSE_ROOT_INODE, 0).await.unwrap().fh;
+    let entries = ls(&fs, dir_handle, 0, first_page_size).await;
+    assert_eq!(entries.len(), first_page_size.min(12)); // 10 files + 2 dirs (. and ..)
+    let old_offset = entries.len() as i64;
+
+    // Shrink the directory, and rewind the handle so that it's listed again
+    for i in 2..10 {
+        client.remove_object(&format!("foo{i}"));
+    }
+    let entries = ls(&fs, dir_handle, 0, 20).await;
+    let names = entries.into_iter().map(|(_, name)| name).collect::<Vec<_>>();
+    assert_eq!(names, vec![".", "..", "foo0", "foo1"]);
+
+    // Offsets handed out for the old listing are now past the end, so are a clean EOF every time
+    for offset in [old_offset, old_offset, 11, i64::MAX] {
+        let entries = ls(&fs, dir_handle, offset, 20).await;
+        assert_eq!(entries, vec![], "offset {offset} should be past the end");
+    }
+
+    // The handle doesn't wrap around to list the directory again
+    let entries = ls(&fs, dir_handle, 4, 20).await;
+    assert_eq!(entries, vec![]);
+    fs.releasedir(FUSE_ROOT_INODE, dir_handle, 0).await.unwrap();
+}
+
+#[test_case(""; "unprefixed")]
+#[test_case("test_prefix/"; "prefixed")]
+#[tokio::test]
+async fn test_readdir_resume_from_token(prefix: &str) {
+    const BUCKET_NAME: &str = "test_readdir_resume_from_token";
+    let prefix = Prefix::new(prefix).expect("valid prefix");
+    let (client, fs) = make_test_filesystem(BUCKET_NAME, &prefix, Default::default());
+
+    // Enough entries to span several ListObjects pages, plus names that sort differently as keys
+    // (`a-b` < `a/`) and as names (`a` < `a-b`)
+    let mut expected = Vec::new();
+    for i in 0..2500 {
+        let name = format!("file{i:05}");
+        client.add_object(&format!("{prefix}dir/{name}"), b"foo".into());
+        expected.push(name);
+    }
+    for key in ["a-b", "a/nested", "b/nested", "b0"] {
+        client.add_object(&format!("{prefix}dir/{key}"), b"foo".into());
+    }
+    expected.extend(["a", "a-b", "b", "b0"].map(String::from));
+    expected.sort();
+    client.add_object(&format!("{prefix}other/file"), b"foo".into());
+
+    // Read the first two pages and checkpoint the last name
+    let dir_ino = fs.lookup(FUSE_ROOT_INODE, "dir".as_ref()).await.unwrap().attr.ino;
+    let token = fs.encode_dir_position(dir_ino, "".as_ref()).unwrap();
+    let mut names = ls_from_token(&fs, dir_ino, &token, 700).await;
+    assert_eq!(names.len(), 700);
+    let token = fs.encode_dir_position(dir_ino, names.last().unwrap().as_ref()).unwrap();
+    names.extend(ls_from_token(&fs, dir_ino, &token, 700).await);
+    let checkpoint = fs.encode_dir_position(dir_ino, names.last().unwrap().as_ref()).unwrap();
+
+    // Resuming after a name that sorts before a directory's key doesn't skip or repeat entries
+    let token = fs.encode_dir_position(dir_ino, "a".as_ref()).unwrap();
+    assert_eq!(&ls_from_token(&fs, dir_ino, &token, 3).await, &expected[1..4]);
+    let token = fs.encode_dir_position(dir_ino, "a-b".as_ref()).unwrap();
+    assert_eq!(&ls_from_token(&fs, dir_ino, &token, 3).await, &expected[2..5]);
+
+    // A token only works for the directory it came from
+    let other_ino = fs.lookup(FUSE_ROOT_INODE, "other".as_ref()).await.unwrap().attr.ino;
+    let err = fs
+        .readdir_from_token(other_ino, &checkpoint, &mut DirectoryReply::new(0))
+        .await
+        .expect_err("token is for a different directory");
+    assert_eq!(err.to_errno(), libc::EINVAL);
+    let err = fs
+        .readdir_from_token(dir_ino, "not a token", &mut DirectoryReply::new(0))
+        .await
+        .expect_err("invalid token");
+    assert_eq!(err.to_errno(), libc::EINVAL);
+    drop(fs);
+
+    // Resume in a new filesystem, as if after a restart
+    let fs = make_test_filesystem_with_client(client.clone(), BUCKET_NAME, &prefix, Default::default());
+    let dir_ino = fs.lookup(FUSE_ROOT_INODE, "dir".as_ref()).await.unwrap().attr.ino;
+    let mut token = checkpoint;
+    loop {
+        let page = ls_from_token(&fs, dir_ino, &token, 700).await;
+        let Some(last) = page.last() else {
+            break;
+        };
+        token = fs.encode_dir_position(dir_ino, last.as_ref()).unwrap();
+        names.extend(page);
+    }
+
+    assert_eq!(names, expected, "resumed listing should have no gaps or duplicates");
+}
+
 #[tokio::test]
 async fn test_readdir_rewind_ordered() {
     let (client, fs) = make_test_filesystem("test_readdir_rewind", &Default::default(), Default::default());
@@ -1465,38 +3257,2741 @@ async fn test_readdir_rewind_with_local_files_only() {
     assert_eq!(new_entries.len(), 3); // 1 new local file + 2 dirs (. and ..) = 3 entries
 }
 
-async fn new_local_file(fs: &TestS3Filesystem<Arc<MockClient>>, filename: &str) {
-    let mode = libc::S_IFREG | libc::S_IRWXU; // regular file + 0700 permissions
-    let dentry = fs.mknod(FUSE_ROOT_INODE, filename.as_ref(), mode, 0, 0).await.unwrap();
-    assert_eq!(dentry.attr.size, 0);
-    let file_ino = dentry.attr.ino;
+#[test_case(RewindMode::Refresh; "refresh")]
+#[test_case(RewindMode::Snapshot; "snapshot")]
+#[tokio::test]
+async fn test_readdir_rewind_mode(rewind_mode: RewindMode) {
+    let config = S3FilesystemConfig {
+        readdir_rewind_mode: rewind_mode,
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_readdir_rewind_mode", &Default::default(), config);
 
-    let fh = fs
-        .open(file_ino, libc::S_IFREG as i32 | libc::O_WRONLY, 0)
+    for i in 0..10 {
+        client.add_object(&format!("foo{i}"), b"foo".into());
+    }
+
+    let dir_handle = fs.opendir(FUSE_ROOT_INODE, 0).await.unwrap().fh;
+
+    let entries = ls(&fs, dir_handle, 0, 20).await;
+    assert_eq!(entries.len(), 12); // 10 files + 2 dirs (. and ..) = 12 entries
+
+    // Add a new remote file and a new local directory
+    client.add_object("bar", b"bar".into());
+    fs.mkdir(FUSE_ROOT_INODE, "newdir".as_ref(), libc::S_IFDIR, 0)
         .await
-        .unwrap()
-        .fh;
+        .unwrap();
 
-    let slice = &[0xaa; 27];
-    let written = fs.write(file_ino, fh, 0, slice, 0, 0, None).await.unwrap();
-    assert_eq!(written as usize, slice.len());
-    fs.fsync(file_ino, fh, true).await.unwrap();
+    // Rewind and iterate again
+    let entries = ls(&fs, dir_handle, 0, 20).await;
+
+    // The local directory is visible in both modes
+    assert!(entries.iter().any(|(_, name)| name == "newdir"));
+
+    // The new remote file is only visible if the listing was refreshed
+    let has_new_remote = entries.iter().any(|(_, name)| name == "bar");
+    match rewind_mode {
+        RewindMode::Refresh => {
+            assert!(has_new_remote);
+            assert_eq!(entries.len(), 14);
+        }
+        RewindMode::Snapshot => {
+            assert!(!has_new_remote);
+            assert_eq!(entries.len(), 13);
+        }
+    }
+
+    // Partial iteration followed by a rewind should return the same entries again
+    let first_page = ls(&fs, dir_handle, 0, 5).await;
+    let rewound_page = ls(&fs, dir_handle, 0, 20).await;
+    assert_eq!(&first_page[..], &rewound_page[..5]);
+    assert_eq!(rewound_page, entries);
 }
 
-async fn ls(
-    fs: &TestS3Filesystem<Arc<MockClient>>,
-    dir_handle: u64,
-    offset: i64,
-    max_entries: usize,
-) -> Vec<(u64, OsString)> {
-    let mut reply = DirectoryReply::new(max_entries);
-    let _ = fs
-        .readdirplus(FUSE_ROOT_INODE, dir_handle, offset, &mut reply)
+/// A handle to pass to a [HandleOp], either one previously returned by the file system (which
+/// may since have been released) or an arbitrary one.
+#[derive(Debug, Clone, Arbitrary)]
+enum HandleChoice {
+    Known(Index),
+    Any(u64),
+}
+
+#[derive(Debug, Clone, Arbitrary)]
+enum HandleOp {
+    Open,
+    Read(HandleChoice, #[proptest(strategy = "0i64..2048")] i64),
+    Release(HandleChoice),
+    Opendir,
+    Readdir(HandleChoice),
+    Releasedir(HandleChoice),
+}
+
+/// Apply a sequence of handle operations, checking that operations on open handles succeed, and
+/// that operations on any other handle (including released ones) fail with `EBADF` without panicking.
+async fn run_handle_ops(ops: Vec<HandleOp>) {
+    let (client, fs) = make_test_filesystem("test_handle_ops", &Default::default(), Default::default());
+    client.add_object("file.txt", MockObject::ramp(0xaa, 1024, ETag::for_tests()));
+    let file_ino = fs.lookup(FUSE_ROOT_INODE, "file.txt".as_ref()).await.unwrap().attr.ino;
+
+    let mut known = Vec::new();
+    let mut open_files = HashSet::new();
+    let mut open_dirs = HashSet::new();
+    let choose = |known: &Vec<u64>, choice: &HandleChoice| match choice {
+        HandleChoice::Known(index) if !known.is_empty() => *index.get(known),
+        HandleChoice::Known(_) => 0,
+        HandleChoice::Any(fh) => *fh,
+    };
+
+    for op in ops {
+        match op {
+            HandleOp::Open => {
+                let fh = fs.open(file_ino, libc::O_RDONLY, 0).await.unwrap().fh;
+                known.push(fh);
+                open_files.insert(fh);
+            }
+            HandleOp::Read(choice, offset) => {
+                let fh = choose(&known, &choice);
+                let result = fs.read(file_ino, fh, offset, 128, 0, None).await;
+                if open_files.contains(&fh) {
+                    let expected_len = 1024usize.saturating_sub(offset as usize).min(128);
+                    assert_eq!(result.unwrap().len(), expected_len);
+                } else {
+                    assert_eq!(result.unwrap_err().to_errno(), libc::EBADF);
+                }
+            }
+            HandleOp::Release(choice) => {
+                let fh = choose(&known, &choice);
+                let result = fs.release(file_ino, fh, 0, None, false).await;
+                if open_files.remove(&fh) {
+                    result.unwrap();
+                } else {
+                    assert_eq!(result.unwrap_err().to_errno(), libc::EBADF);
+                }
+            }
+            HandleOp::Opendir => {
+                let fh = fs.opendir(FUSE_ROOT_INODE, 0).await.unwrap().fh;
+                known.push(fh);
+                open_dirs.insert(fh);
+            }
+            HandleOp::Readdir(choice) => {
+                let fh = choose(&known, &choice);
+                let mut reply = DirectoryReply::new(0);
+                let result = fs.readdir(FUSE_ROOT_INODE, fh, 0, &mut reply).await;
+                if open_dirs.contains(&fh) {
+                    result.unwrap();
+                } else {
+                    assert_eq!(result.unwrap_err().to_errno(), libc::EBADF);
+                }
+            }
+            HandleOp::Releasedir(choice) => {
+                let fh = choose(&known, &choice);
+                let result = fs.releasedir(FUSE_ROOT_INODE, fh, 0).await;
+                if open_dirs.remove(&fh) {
+                    result.unwrap();
+                } else {
+                    assert_eq!(result.unwrap_err().to_errno(), libc::EBADF);
+                }
+            }
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    fn handle_ops_fail_with_errno_after_release(ops: Vec<HandleOp>) {
+        futures::executor::block_on(run_handle_ops(ops));
+    }
+}
+
+#[test_case(PermissionChangeMode::Ignore; "ignore")]
+#[test_case(PermissionChangeMode::Reject; "reject")]
+#[tokio::test]
+async fn test_chmod_chown(permission_change_mode: PermissionChangeMode) {
+    let fs_config = S3FilesystemConfig {
+        permission_change_mode,
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_chmod_chown", &Default::default(), fs_config);
+    client.add_object("dir/file.txt", b"hello".into());
+
+    let dir = fs.lookup(FUSE_ROOT_INODE, "dir".as_ref()).await.unwrap();
+    let file = fs.lookup(dir.attr.ino, "file.txt".as_ref()).await.unwrap();
+    let local_file = fs
+        .mknod(dir.attr.ino, "local.txt".as_ref(), libc::S_IFREG | libc::S_IRWXU, 0, 0)
         .await
         .unwrap();
-    reply
-        .entries
-        .iter()
-        .map(|e| (e.ino, e.name.clone()))
+
+    for ino in [dir.attr.ino, file.attr.ino, local_file.attr.ino] {
+        let original = fs.getattr(ino).await.unwrap().attr;
+
+        let chmod = fs.setattr(ino, Some(0o600), None, None, None, None, None, None).await;
+        let chown = fs
+            .setattr(ino, None, Some(1234), Some(5678), None, None, None, None)
+            .await;
+        match permission_change_mode {
+            PermissionChangeMode::Ignore => {
+                let attr = chmod.expect("chmod should succeed").attr;
+                assert_eq!(attr.perm, 0o600);
+                assert_eq!(attr.uid, original.uid);
+                let attr = chown.expect("chown should succeed").attr;
+                assert_eq!(attr.perm, original.perm);
+                assert_eq!((attr.uid, attr.gid), (1234, 5678));
+            }
+            PermissionChangeMode::Reject => {
+                assert_eq!(chmod.expect_err("chmod should fail").to_errno(), libc::EPERM);
+                assert_eq!(chown.expect_err("chown should fail").to_errno(), libc::EPERM);
+            }
+        }
+
+        // Nothing actually changed
+        let attr = fs.getattr(ino).await.unwrap().attr;
+        assert_eq!(
+            (attr.perm, attr.uid, attr.gid),
+            (original.perm, original.uid, original.gid)
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_copy_file_range_server_side() {
+    let (client, fs) = make_test_filesystem(
+        "test_copy_file_range_server_side",
+        &Default::default(),
+        Default::default(),
+    );
+    let object = MockObject::ramp(0xaa, 3000, ETag::for_tests());
+    client.add_object("src/file.bin", object.clone());
+
+    let src_dir = fs.lookup(FUSE_ROOT_INODE, "src".as_ref()).await.unwrap();
+    let src = fs.lookup(src_dir.attr.ino, "file.bin".as_ref()).await.unwrap().attr.ino;
+    let dst = fs
+        .mknod(
+            FUSE_ROOT_INODE,
+            "copy.bin".as_ref(),
+            libc::S_IFREG | libc::S_IRWXU,
+            0,
+            0,
+        )
+        .await
+        .unwrap()
+        .attr
+        .ino;
+    let fh_in = fs.open(src, libc::O_RDONLY, 0).await.unwrap().fh;
+    let fh_out = fs.open(dst, libc::S_IFREG as i32 | libc::O_WRONLY, 0).await.unwrap().fh;
+
+    let copy_counter = client.new_counter(Operation::CopyObject);
+    let get_counter = client.new_counter(Operation::GetObject);
+    let upload_counter = client.new_counter(Operation::UploadPart);
+
+    // Like `cp`, keep copying until nothing is left
+    let len = 1 << 30;
+    let copied = fs.copy_file_range(src, fh_in, 0, dst, fh_out, 0, len, 0).await.unwrap();
+    assert_eq!(copied, 3000);
+    let copied = fs
+        .copy_file_range(src, fh_in, 3000, dst, fh_out, 3000, len, 0)
+        .await
+        .unwrap();
+    assert_eq!(copied, 0);
+    fs.release(dst, fh_out, 0, None, true).await.unwrap();
+    fs.release(src, fh_in, 0, None, false).await.unwrap();
+
+    // The object was copied server-side, rather than read and written back
+    assert_eq!(copy_counter.count(), 1);
+    assert_eq!(get_counter.count(), 0);
+    assert_eq!(upload_counter.count(), 0);
+    assert!(!client.is_upload_in_progress("copy.bin"));
+
+    let attr = fs.getattr(dst).await.unwrap().attr;
+    assert_eq!(attr.size, 3000);
+    let body = client
+        .get_object("test_copy_file_range_server_side", "copy.bin", None, None)
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+    assert_eq!(&body[..], &object.read(0, 3000)[..]);
+}
+
+#[tokio::test]
+async fn test_copy_file_range_source_changed() {
+    let (client, fs) = make_test_filesystem(
+        "test_copy_file_range_source_changed",
+        &Default::default(),
+        Default::default(),
+    );
+    client.add_object("file.bin", MockObject::ramp(0xaa, 3000, ETag::for_tests()));
+
+    let src = fs.lookup(FUSE_ROOT_INODE, "file.bin".as_ref()).await.unwrap().attr.ino;
+    let dst = fs
+        .mknod(
+            FUSE_ROOT_INODE,
+            "copy.bin".as_ref(),
+            libc::S_IFREG | libc::S_IRWXU,
+            0,
+            0,
+        )
+        .await
+        .unwrap()
+        .attr
+        .ino;
+    let fh_in = fs.open(src, libc::O_RDONLY, 0).await.unwrap().fh;
+    let fh_out = fs.open(dst, libc::S_IFREG as i32 | libc::O_WRONLY, 0).await.unwrap().fh;
+
+    // The source is overwritten after it was opened, so the copy mustn't pick up the new object
+    client.add_object(
+        "file.bin",
+        MockObject::ramp(0xbb, 3000, ETag::from_str("changed").unwrap()),
+    );
+    let err = fs
+        .copy_file_range(src, fh_in, 0, dst, fh_out, 0, 3000, 0)
+        .await
+        .expect_err("copy of a changed source should fail");
+    assert_eq!(err.to_errno(), libc::ESTALE);
+    assert!(!client.contains_key("copy.bin"));
+}
+
+#[tokio::test]
+async fn test_copy_file_range_partial() {
+    let (client, fs) = make_test_filesystem("test_copy_file_range_partial", &Default::default(), Default::default());
+    client.add_object("file.bin", MockObject::ramp(0xaa, 3000, ETag::for_tests()));
+
+    let src = fs.lookup(FUSE_ROOT_INODE, "file.bin".as_ref()).await.unwrap().attr.ino;
+    let dst = fs
+        .mknod(
+            FUSE_ROOT_INODE,
+            "copy.bin".as_ref(),
+            libc::S_IFREG | libc::S_IRWXU,
+            0,
+            0,
+        )
+        .await
+        .unwrap()
+        .attr
+        .ino;
+    let fh_in = fs.open(src, libc::O_RDONLY, 0).await.unwrap().fh;
+    let fh_out = fs.open(dst, libc::S_IFREG as i32 | libc::O_WRONLY, 0).await.unwrap().fh;
+    let copy_counter = client.new_counter(Operation::CopyObject);
+
+    // Copies of part of the object, or into a file already written to, fall back to the caller
+    for (offset_in, offset_out, len) in [(0, 0, 1000), (1000, 0, 2000), (0, 1000, 3000)] {
+        let err = fs
+            .copy_file_range(src, fh_in, offset_in, dst, fh_out, offset_out, len, 0)
+            .await
+            .expect_err("partial copy should not be supported");
+        assert_eq!(err.to_errno(), libc::EOPNOTSUPP);
+    }
+    fs.write(dst, fh_out, 0, &[0xaa; 27], 0, 0, None).await.unwrap();
+    let err = fs
+        .copy_file_range(src, fh_in, 0, dst, fh_out, 0, 3000, 0)
+        .await
+        .expect_err("copy into a written file should not be supported");
+    assert_eq!(err.to_errno(), libc::EOPNOTSUPP);
+    assert_eq!(copy_counter.count(), 0);
+
+    // The upload is unaffected
+    fs.release(dst, fh_out, 0, None, true).await.unwrap();
+    assert!(client.contains_key("copy.bin"));
+}
+
+#[derive(Debug, Clone, Copy)]
+enum NameOp {
+    Lookup,
+    Mknod,
+    Mkdir,
+    RenameSource,
+    RenameTarget,
+    Symlink,
+}
+
+async fn run_name_op(fs: &TestS3Filesystem<Arc<MockClient>>, op: NameOp, name: &OsStr) -> Result<(), libc::c_int> {
+    let result = match op {
+        NameOp::Lookup => fs.lookup(FUSE_ROOT_INODE, name).await.map(|_| ()),
+        NameOp::Mknod => fs
+            .mknod(FUSE_ROOT_INODE, name, libc::S_IFREG | libc::S_IRWXU, 0, 0)
+            .await
+            .map(|_| ()),
+        NameOp::Mkdir => fs.mkdir(FUSE_ROOT_INODE, name, libc::S_IFDIR, 0).await.map(|_| ()),
+        NameOp::RenameSource => {
+            fs.rename(FUSE_ROOT_INODE, name, FUSE_ROOT_INODE, "target".as_ref(), 0)
+                .await
+        }
+        NameOp::RenameTarget => {
+            fs.rename(FUSE_ROOT_INODE, "source".as_ref(), FUSE_ROOT_INODE, name, 0)
+                .await
+        }
+        NameOp::Symlink => fs.symlink(FUSE_ROOT_INODE, name, Path::new("target")).await.map(|_| ()),
+    };
+    result.map_err(|e| e.to_errno())
+}
+
+#[test_case(NameOp::Lookup; "lookup")]
+#[test_case(NameOp::Mknod; "mknod")]
+#[test_case(NameOp::Mkdir; "mkdir")]
+#[test_case(NameOp::RenameSource; "rename source")]
+#[test_case(NameOp::RenameTarget; "rename target")]
+#[test_case(NameOp::Symlink; "symlink")]
+#[tokio::test]
+async fn test_invalid_names(op: NameOp) {
+    let (client, fs) = make_test_filesystem("test_invalid_names", &Default::default(), Default::default());
+    let head_counter = client.new_counter(Operation::HeadObject);
+    let list_counter = client.new_counter(Operation::ListObjectsV2);
+
+    let too_long = "a".repeat(256);
+    let too_long_multibyte = "é".repeat(128);
+    let cases: [(&[u8], libc::c_int); 8] = [
+        (too_long.as_bytes(), libc::ENAMETOOLONG),
+        (too_long_multibyte.as_bytes(), libc::ENAMETOOLONG),
+        (b"", libc::EINVAL),
+        (b".", libc::EINVAL),
+        (b"..", libc::EINVAL),
+        (b"a/b", libc::EINVAL),
+        (b"a\0b", libc::EINVAL),
+        (b"\xff", libc::EINVAL),
+    ];
+    for (name, expected_errno) in cases {
+        let result = run_name_op(&fs, op, OsStr::from_bytes(name)).await;
+        assert_eq!(result, Err(expected_errno), "{op:?} with name {name:?}");
+    }
+
+    // Invalid names are rejected before making any requests
+    assert_eq!(head_counter.count(), 0);
+    assert_eq!(list_counter.count(), 0);
+}
+
+#[tokio::test]
+async fn test_statfs_name_max() {
+    let (_client, fs) = make_test_filesystem("test_statfs_name_max", &Default::default(), Default::default());
+
+    let statfs = fs.statfs(FUSE_ROOT_INODE).await.unwrap();
+    assert_eq!(statfs.maximum_name_length, 255);
+
+    // The reported maximum is the longest name operations accept
+    let longest = "a".repeat(statfs.maximum_name_length as usize);
+    let mode = libc::S_IFREG | libc::S_IRWXU;
+    fs.mknod(FUSE_ROOT_INODE, longest.as_ref(), mode, 0, 0)
+        .await
+        .expect("longest name should be accepted");
+    let too_long = "a".repeat(statfs.maximum_name_length as usize + 1);
+    let err = fs
+        .mknod(FUSE_ROOT_INODE, too_long.as_ref(), mode, 0, 0)
+        .await
+        .expect_err("name should be too long");
+    assert_eq!(err.to_errno(), libc::ENAMETOOLONG);
+}
+
+#[tokio::test]
+async fn test_max_length_name() {
+    let (_client, fs) = make_test_filesystem("test_max_length_name", &Default::default(), Default::default());
+
+    // Exactly 255 bytes, made mostly of two-byte characters
+    let file_name = format!("a{}", "é".repeat(127));
+    let dir_name = format!("b{}", "é".repeat(127));
+    assert_eq!(file_name.len(), 255);
+
+    assert_eq!(
+        run_name_op(&fs, NameOp::Lookup, file_name.as_ref()).await,
+        Err(libc::ENOENT)
+    );
+    assert_eq!(run_name_op(&fs, NameOp::Mknod, file_name.as_ref()).await, Ok(()));
+    assert_eq!(run_name_op(&fs, NameOp::Lookup, file_name.as_ref()).await, Ok(()));
+    assert_eq!(run_name_op(&fs, NameOp::Mkdir, dir_name.as_ref()).await, Ok(()));
+    assert_eq!(run_name_op(&fs, NameOp::Lookup, dir_name.as_ref()).await, Ok(()));
+    // Valid names get as far as the operations themselves, which aren't supported
+    assert_eq!(
+        run_name_op(&fs, NameOp::RenameSource, file_name.as_ref()).await,
+        Err(libc::ENOSYS)
+    );
+    assert_eq!(
+        run_name_op(&fs, NameOp::RenameTarget, file_name.as_ref()).await,
+        Err(libc::ENOSYS)
+    );
+    assert_eq!(
+        run_name_op(&fs, NameOp::Symlink, file_name.as_ref()).await,
+        Err(libc::EPERM)
+    );
+}
+
+fn staging_config() -> S3FilesystemConfig {
+    S3FilesystemConfig {
+        upload_staging_directory: Some(".inprogress".to_owned()),
+        ..Default::default()
+    }
+}
+
+#[test_case(""; "unprefixed")]
+#[test_case("test_prefix/"; "prefixed")]
+#[tokio::test]
+async fn test_staged_upload_published(prefix: &str) {
+    let prefix = Prefix::new(prefix).expect("valid prefix");
+    let (client, fs) = make_test_filesystem("test_staged_upload_published", &prefix, staging_config());
+    let key = format!("{prefix}hello.txt");
+
+    let dentry = fs
+        .mknod(
+            FUSE_ROOT_INODE,
+            "hello.txt".as_ref(),
+            libc::S_IFREG | libc::S_IRWXU,
+            0,
+            0,
+        )
+        .await
+        .unwrap();
+    let file_ino = dentry.attr.ino;
+    let fh = fs
+        .open(file_ino, libc::S_IFREG as i32 | libc::O_WRONLY, 0)
+        .await
+        .unwrap()
+        .fh;
+    fs.write(file_ino, fh, 0, &[0xaa; 27], 0, 0, None).await.unwrap();
+
+    // The upload goes to the staging directory, not the final key
+    assert!(!client.is_upload_in_progress(&key));
+    assert!(!client.contains_key(&key));
+
+    // Nor does the file appear in the mount under its final name until it's published
+    let err = fs
+        .lookup(FUSE_ROOT_INODE, "hello.txt".as_ref())
+        .await
+        .expect_err("file should be hidden until published");
+    assert_eq!(err.to_errno(), libc::ENOENT);
+    let dir_handle = fs.opendir(FUSE_ROOT_INODE, 0).await.unwrap().fh;
+    let mut reply = DirectoryReply::new(10);
+    fs.readdirplus(FUSE_ROOT_INODE, dir_handle, 0, &mut reply)
+        .await
+        .unwrap();
+    let names: Vec<_> = reply.entries.iter().map(|entry| entry.name.clone()).collect();
+    assert_eq!(names, [".", ".."]);
+    fs.releasedir(FUSE_ROOT_INODE, dir_handle, 0).await.unwrap();
+
+    let copy_counter = client.new_counter(Operation::CopyObject);
+    fs.release(file_ino, fh, 0, None, true).await.unwrap();
+    assert_eq!(copy_counter.count(), 1);
+    assert!(client.contains_key(&key));
+    let entry = fs.lookup(FUSE_ROOT_INODE, "hello.txt".as_ref()).await.unwrap();
+    assert_eq!(entry.attr.ino, file_ino);
+
+    // The published object keeps the upload's additional checksum
+    let attributes = client
+        .get_object_attributes(
+            "test_staged_upload_published",
+            &key,
+            None,
+            None,
+            &[ObjectAttribute::ObjectParts],
+        )
+        .await
+        .unwrap();
+    let parts = attributes.object_parts.and_then(|parts| parts.parts).unwrap();
+    assert!(parts
+        .iter()
+        .all(|part| part.checksum.as_ref().is_some_and(|c| c.checksum_crc32c.is_some())));
+
+    // The staged object was removed after publishing
+    let staged = client
+        .list_objects(
+            "test_staged_upload_published",
+            None,
+            "",
+            1000,
+            &format!("{prefix}.inprogress/"),
+        )
+        .await
+        .unwrap();
+    assert!(
+        staged.objects.is_empty(),
+        "unexpected staged objects {:?}",
+        staged.objects
+    );
+
+    let attr = fs.getattr(file_ino).await.unwrap().attr;
+    assert_eq!(attr.size, 27);
+}
+
+#[tokio::test]
+async fn test_staged_upload_failed() {
+    const BUCKET_NAME: &str = "test_staged_upload_failed";
+    const FILE_NAME: &str = "foo.bin";
+
+    let client = Arc::new(MockClient::new(MockClientConfig {
+        bucket: BUCKET_NAME.to_string(),
+        part_size: 1024 * 1024,
+        ..Default::default()
+    }));
+    let mut put_failures = HashMap::new();
+    put_failures.insert(1, Ok((2, MockClientError("error".to_owned().into()))));
+    let failure_client = countdown_failure_client(
+        client.clone(),
+        Default::default(),
+        Default::default(),
+        Default::default(),
+        put_failures,
+    );
+    let fs = make_test_filesystem_with_client(
+        Arc::new(failure_client),
+        BUCKET_NAME,
+        &Default::default(),
+        staging_config(),
+    );
+
+    let dentry = fs
+        .mknod(FUSE_ROOT_INODE, FILE_NAME.as_ref(), libc::S_IFREG | libc::S_IRWXU, 0, 0)
+        .await
+        .unwrap();
+    let file_ino = dentry.attr.ino;
+    let fh = fs
+        .open(file_ino, libc::S_IFREG as i32 | libc::O_WRONLY, 0)
+        .await
+        .unwrap()
+        .fh;
+
+    let copy_counter = client.new_counter(Operation::CopyObject);
+    let written = fs.write(file_ino, fh, 0, &[0xaa; 27], 0, 0, None).await.unwrap();
+    let err = fs
+        .write(file_ino, fh, written as i64, &[0xaa; 27], 0, 0, None)
+        .await
+        .expect_err("second write should fail");
+    assert_eq!(err.to_errno(), libc::EIO);
+    fs.release(file_ino, fh, 0, None, true)
+        .await
+        .expect("release succeeds (no op)");
+
+    // Nothing was ever published under the final name
+    assert_eq!(copy_counter.count(), 0);
+    assert!(!client.contains_key(FILE_NAME));
+}
+
+#[tokio::test]
+async fn test_cleanup_staging() {
+    let (client, fs) = make_test_filesystem("test_cleanup_staging", &Default::default(), staging_config());
+
+    let two_hours_ago = OffsetDateTime::now_utc() - Duration::from_secs(2 * 60 * 60);
+    let mut old_object = MockObject::constant(0xaa, 27, ETag::for_tests());
+    old_object.set_last_modified(two_hours_ago);
+    client.add_object(".inprogress/1/old.txt", old_object.clone());
+    client.add_object("other.txt", old_object);
+    client.add_object(
+        ".inprogress/2/new.txt",
+        MockObject::constant(0xaa, 27, ETag::for_tests()),
+    );
+
+    // The staging directory is hidden from the file system
+    let err = fs
+        .lookup(FUSE_ROOT_INODE, ".inprogress".as_ref())
+        .await
+        .expect_err("staging directory should be hidden");
+    assert_eq!(err.to_errno(), libc::ENOENT);
+    let dir_handle = fs.opendir(FUSE_ROOT_INODE, 0).await.unwrap().fh;
+    let mut reply = DirectoryReply::new(10);
+    fs.readdirplus(FUSE_ROOT_INODE, dir_handle, 0, &mut reply)
+        .await
+        .unwrap();
+    let names: Vec<_> = reply.entries.iter().map(|entry| entry.name.clone()).collect();
+    assert_eq!(names, [".", "..", "other.txt"]);
+
+    let delete_objects = client.new_counter(Operation::DeleteObjects);
+    let deleted = fs.cleanup_staging(Duration::from_secs(60 * 60)).await.unwrap();
+    assert_eq!(deleted, 1);
+    assert_eq!(delete_objects.count(), 1);
+    assert!(!client.contains_key(".inprogress/1/old.txt"));
+    assert!(client.contains_key(".inprogress/2/new.txt"));
+    assert!(client.contains_key("other.txt"));
+}
+
+#[tokio::test]
+async fn test_remove_dir_all() {
+    let config = S3FilesystemConfig {
+        allow_delete: true,
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_remove_dir_all", &Default::default(), config);
+
+    for i in 0..1500 {
+        let key = format!("dir/sub{}/file{i}.txt", i % 10);
+        client.add_object(&key, MockObject::constant(0xaa, 27, ETag::for_tests()));
+    }
+    client.add_object("dir2/keep.txt", MockObject::constant(0xaa, 27, ETag::for_tests()));
+    client.add_object("dir.txt", MockObject::constant(0xaa, 27, ETag::for_tests()));
+
+    // Cache some of the directory, which should be forgotten once it's removed
+    let dir = fs.lookup(FUSE_ROOT_INODE, "dir".as_ref()).await.unwrap();
+    let sub = fs.lookup(dir.attr.ino, "sub0".as_ref()).await.unwrap();
+    fs.lookup(sub.attr.ino, "file0.txt".as_ref()).await.unwrap();
+
+    let delete_object = client.new_counter(Operation::DeleteObject);
+    let delete_objects = client.new_counter(Operation::DeleteObjects);
+    let deleted = fs.remove_dir_all("dir").await.unwrap();
+    assert_eq!(deleted, 1500);
+    assert_eq!(delete_object.count(), 0);
+    assert_eq!(delete_objects.count(), 2);
+    assert!(!client.contains_prefix("dir"));
+    assert!(client.contains_key("dir2/keep.txt"));
+    assert!(client.contains_key("dir.txt"));
+
+    let err = fs
+        .lookup(sub.attr.ino, "file0.txt".as_ref())
+        .await
+        .expect_err("file should be gone");
+    assert_eq!(err.to_errno(), libc::ENOENT);
+    let err = fs
+        .lookup(FUSE_ROOT_INODE, "dir".as_ref())
+        .await
+        .expect_err("directory should be gone");
+    assert_eq!(err.to_errno(), libc::ENOENT);
+
+    // Keys that can't be deleted fail the removal, but don't stop the others being deleted
+    for i in 0..300 {
+        client.add_object(
+            &format!("other/file{i}.txt"),
+            MockObject::constant(0xaa, 27, ETag::for_tests()),
+        );
+    }
+    client.set_key_undeletable("other/file42.txt", true);
+    let err = fs.remove_dir_all("other").await.expect_err("one key can't be deleted");
+    assert_eq!(err.to_errno(), libc::EIO);
+    assert_eq!(delete_object.count(), 0);
+    assert_eq!(delete_objects.count(), 3);
+    let dir = fs.lookup(FUSE_ROOT_INODE, "other".as_ref()).await.unwrap();
+    let dir_handle = fs.opendir(dir.attr.ino, 0).await.unwrap().fh;
+    let mut reply = DirectoryReply::new(10);
+    fs.readdirplus(dir.attr.ino, dir_handle, 0, &mut reply).await.unwrap();
+    let names: Vec<_> = reply.entries.iter().map(|entry| entry.name.clone()).collect();
+    assert_eq!(names, [".", "..", "file42.txt"]);
+
+    let err = fs.remove_dir_all("dir.txt").await.expect_err("files can't be removed");
+    assert_eq!(err.to_errno(), libc::ENOTDIR);
+    let err = fs
+        .remove_dir_all("")
+        .await
+        .expect_err("the mount point can't be removed");
+    assert_eq!(err.to_errno(), libc::EBUSY);
+
+    let (client, fs) = make_test_filesystem("test_remove_dir_all", &Default::default(), Default::default());
+    client.add_object("dir/file.txt", MockObject::constant(0xaa, 27, ETag::for_tests()));
+    let err = fs.remove_dir_all("dir").await.expect_err("deletes are disabled");
+    assert_eq!(err.to_errno(), libc::EPERM);
+    assert!(client.contains_key("dir/file.txt"));
+}
+
+#[tokio::test]
+async fn test_unlink_batch() {
+    let config = S3FilesystemConfig {
+        allow_delete: true,
+        unlink_batch_delay: Some(Duration::from_secs(60)),
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_unlink_batch", &Default::default(), config);
+
+    for i in 0..1200 {
+        client.add_object(
+            &format!("dir/file{i}.txt"),
+            MockObject::constant(0xaa, 27, ETag::for_tests()),
+        );
+    }
+
+    // Unlink every file in the directory one at a time, like `rm -r` does
+    let dir = fs.lookup(FUSE_ROOT_INODE, "dir".as_ref()).await.unwrap();
+    let delete_object = client.new_counter(Operation::DeleteObject);
+    let delete_objects = client.new_counter(Operation::DeleteObjects);
+    for i in 0..1200 {
+        let name = format!("file{i}.txt");
+        fs.lookup(dir.attr.ino, name.as_ref()).await.unwrap();
+        fs.unlink(dir.attr.ino, name.as_ref()).await.unwrap();
+    }
+
+    // A full batch is deleted straight away, and the rest wait
+    assert_eq!(delete_object.count(), 0);
+    assert_eq!(delete_objects.count(), 1);
+    assert!(!client.contains_key("dir/file999.txt"));
+    assert!(client.contains_key("dir/file1000.txt"));
+    let err = fs
+        .lookup(dir.attr.ino, "file1000.txt".as_ref())
+        .await
+        .expect_err("unlinked file should be gone");
+    assert_eq!(err.to_errno(), libc::ENOENT);
+    assert_eq!(delete_objects.count(), 1);
+
+    // Listing the directory deletes the rest first
+    let dir_handle = fs.opendir(dir.attr.ino, 0).await.unwrap().fh;
+    let mut reply = DirectoryReply::new(10);
+    fs.readdirplus(dir.attr.ino, dir_handle, 0, &mut reply).await.unwrap();
+    let names: Vec<_> = reply.entries.iter().map(|entry| entry.name.clone()).collect();
+    assert_eq!(names, [".", ".."]);
+    assert_eq!(delete_object.count(), 0);
+    assert_eq!(delete_objects.count(), 2);
+    assert!(!client.contains_prefix("dir/"));
+
+    // Keys that can't be deleted are reported by rmdir, and the files come back
+    client.add_object("other/a.txt", MockObject::constant(0xaa, 27, ETag::for_tests()));
+    client.add_object("other/b.txt", MockObject::constant(0xaa, 27, ETag::for_tests()));
+    client.set_key_undeletable("other/b.txt", true);
+    let other = fs.lookup(FUSE_ROOT_INODE, "other".as_ref()).await.unwrap();
+    for name in ["a.txt", "b.txt"] {
+        fs.lookup(other.attr.ino, name.as_ref()).await.unwrap();
+        fs.unlink(other.attr.ino, name.as_ref()).await.unwrap();
+    }
+    let err = fs
+        .rmdir(FUSE_ROOT_INODE, "other".as_ref())
+        .await
+        .expect_err("one key can't be deleted");
+    assert_eq!(err.to_errno(), libc::EIO);
+    assert_eq!(delete_object.count(), 0);
+    assert_eq!(delete_objects.count(), 3);
+    assert!(!client.contains_key("other/a.txt"));
+    fs.lookup(other.attr.ino, "b.txt".as_ref()).await.unwrap();
+}
+
+/// List every file and directory below the root of the file system, returning their paths (with a
+/// trailing `/` for directories) in sorted order
+async fn list_tree(fs: &TestS3Filesystem<Arc<MockClient>>) -> Vec<String> {
+    let mut paths = Vec::new();
+    let mut dirs = vec![(FUSE_ROOT_INODE, String::new())];
+    while let Some((dir_ino, dir_path)) = dirs.pop() {
+        let dir_handle = fs.opendir(dir_ino, 0).await.unwrap().fh;
+        let mut reply = DirectoryReply::default();
+        fs.readdirplus(dir_ino, dir_handle, 0, &mut reply).await.unwrap();
+        fs.releasedir(dir_ino, dir_handle, 0).await.unwrap();
+        for entry in reply
+            .entries
+            .iter()
+            .filter(|entry| entry.name != "." && entry.name != "..")
+        {
+            let path = format!("{dir_path}{}", entry.name.to_str().unwrap());
+            if entry.attr.kind == FileType::Directory {
+                dirs.push((entry.ino, format!("{path}/")));
+                paths.push(format!("{path}/"));
+            } else {
+                paths.push(path);
+            }
+        }
+    }
+    paths.sort();
+    paths
+}
+
+#[test_case("", "listing.jsonl"; "unprefixed")]
+#[test_case("prefix/", "listing.jsonl"; "prefixed")]
+#[test_case("", "listing.jsonl.gz"; "gzip")]
+#[tokio::test]
+async fn test_listing_bootstrap(prefix: &str, manifest_name: &str) {
+    const BUCKET: &str = "test_listing_bootstrap";
+    let prefix = Prefix::new(prefix).expect("valid prefix");
+    let (client, fs) = make_test_filesystem(BUCKET, &prefix, Default::default());
+    for key in ["a.txt", "dir/b.txt", "dir/sub/c.txt", "empty/"] {
+        client.add_object(
+            &format!("{prefix}{key}"),
+            MockObject::constant(0xaa, 27, ETag::for_tests()),
+        );
+    }
+    let expected_tree = ["a.txt", "dir/", "dir/b.txt", "dir/sub/", "dir/sub/c.txt", "empty/"];
+    assert_eq!(list_tree(&fs).await, expected_tree);
+
+    let manifest_dir = tempfile::tempdir().unwrap();
+    let manifest_path = manifest_dir.path().join(manifest_name);
+    let exported = fs.export_listing(&manifest_path).await.unwrap();
+    assert_eq!(exported, 4);
+
+    let config = S3FilesystemConfig {
+        listing_bootstrap: Some(ListingBootstrap::File(manifest_path)),
+        ..Default::default()
+    };
+    let fs = make_test_filesystem_with_client(client.clone(), BUCKET, &prefix, config);
+
+    // The initial traversal is served entirely from the manifest
+    let list_counter = client.new_counter(Operation::ListObjectsV2);
+    let head_counter = client.new_counter(Operation::HeadObject);
+    assert_eq!(list_tree(&fs).await, expected_tree);
+    assert_eq!(list_counter.count(), 0);
+    assert_eq!(head_counter.count(), 0);
+
+    // After that, directories are listed from S3 as usual
+    assert_eq!(list_tree(&fs).await, expected_tree);
+    assert!(list_counter.count() > 0);
+}
+
+#[tokio::test]
+async fn test_listing_bootstrap_lookup() {
+    const BUCKET: &str = "test_listing_bootstrap_lookup";
+    let (client, fs) = make_test_filesystem(BUCKET, &Default::default(), Default::default());
+    client.add_object("dir/a.txt", MockObject::constant(0xaa, 27, ETag::for_tests()));
+
+    let manifest_dir = tempfile::tempdir().unwrap();
+    let manifest_path = manifest_dir.path().join("listing.jsonl");
+    fs.export_listing(&manifest_path).await.unwrap();
+
+    // Manifests can also be loaded from the bucket
+    let manifest = std::fs::read(&manifest_path).unwrap();
+    client.add_object(".listing.jsonl", MockObject::from_bytes(&manifest, ETag::for_tests()));
+    let config = S3FilesystemConfig {
+        listing_bootstrap: Some(ListingBootstrap::Object(".listing.jsonl".to_owned())),
+        ..Default::default()
+    };
+    let fs = make_test_filesystem_with_client(client.clone(), BUCKET, &Default::default(), config);
+
+    let list_counter = client.new_counter(Operation::ListObjectsV2);
+    let head_counter = client.new_counter(Operation::HeadObject);
+    let dir = fs.lookup(FUSE_ROOT_INODE, "dir".as_ref()).await.unwrap();
+    assert_eq!(dir.attr.kind, FileType::Directory);
+    let file = fs.lookup(dir.attr.ino, "a.txt".as_ref()).await.unwrap();
+    assert_attr(
+        file.attr,
+        FileType::RegularFile,
+        27,
+        getuid().into(),
+        getgid().into(),
+        0o644,
+    );
+    assert_eq!(list_counter.count(), 0);
+    assert_eq!(head_counter.count(), 0);
+
+    // Names missing from the manifest, and later lookups, are looked up in S3
+    let err = fs
+        .lookup(dir.attr.ino, "b.txt".as_ref())
+        .await
+        .expect_err("file should not exist");
+    assert_eq!(err.to_errno(), libc::ENOENT);
+    fs.lookup(dir.attr.ino, "a.txt".as_ref()).await.unwrap();
+    assert!(head_counter.count() > 0);
+}
+
+#[test_case(false; "listed from S3")]
+#[test_case(true; "listed from bootstrap manifest")]
+#[tokio::test]
+async fn test_dot_names(bootstrap: bool) {
+    const BUCKET: &str = "test_dot_names";
+    let (client, fs) = make_test_filesystem(BUCKET, &Default::default(), Default::default());
+    // Keys with a component that's exactly `.` or `..` can't be presented, but other names with
+    // dots in them are fine
+    let keys = [
+        "foo.",
+        "...",
+        "..a",
+        ".hidden",
+        "a..b",
+        ".",
+        "..",
+        "./x",
+        "dir/../file",
+        "dir/./file",
+        "dir/ok.",
+        "dir2/..",
+    ];
+    for key in keys {
+        client.add_object(key, MockObject::constant(0xaa, 5, ETag::for_tests()));
+    }
+
+    let manifest_dir = tempfile::tempdir().unwrap();
+    let fs = if bootstrap {
+        let manifest_path = manifest_dir.path().join("listing.jsonl");
+        let exported = fs.export_listing(&manifest_path).await.unwrap();
+        assert_eq!(exported, keys.len());
+        let config = S3FilesystemConfig {
+            listing_bootstrap: Some(ListingBootstrap::File(manifest_path)),
+            ..Default::default()
+        };
+        make_test_filesystem_with_client(client.clone(), BUCKET, &Default::default(), config)
+    } else {
+        fs
+    };
+
+    assert_eq!(
+        list_tree(&fs).await,
+        ["...", "..a", ".hidden", "a..b", "dir/", "dir/ok.", "dir2/", "foo."]
+    );
+
+    // The only `.` and `..` entries are the ones the file system adds itself
+    for name in ["", "dir", "dir2"] {
+        let dir_ino = if name.is_empty() {
+            FUSE_ROOT_INODE
+        } else {
+            fs.lookup(FUSE_ROOT_INODE, name.as_ref()).await.unwrap().attr.ino
+        };
+        let dir_handle = fs.opendir(dir_ino, 0).await.unwrap().fh;
+        let mut reply = DirectoryReply::default();
+        fs.readdirplus(dir_ino, dir_handle, 0, &mut reply).await.unwrap();
+        fs.releasedir(dir_ino, dir_handle, 0).await.unwrap();
+        let dots = reply
+            .entries
+            .iter()
+            .filter(|entry| entry.name == "." || entry.name == "..")
+            .collect::<Vec<_>>();
+        assert_eq!(dots.len(), 2, "directory {name:?} should have one each of . and ..");
+        assert_eq!(dots[0].name, ".");
+        assert_eq!(dots[0].ino, dir_ino);
+        assert_eq!(dots[1].name, "..");
+        assert_eq!(dots[0].attr.kind, FileType::Directory);
+        assert_eq!(dots[1].attr.kind, FileType::Directory);
+    }
+
+    for name in ["foo.", "...", "..a", ".hidden", "a..b"] {
+        let lookup = fs.lookup(FUSE_ROOT_INODE, name.as_ref()).await.unwrap();
+        assert_eq!(lookup.attr.kind, FileType::RegularFile, "{name:?} should be a file");
+        assert_eq!(lookup.attr.size, 5);
+    }
+    let dir = fs.lookup(FUSE_ROOT_INODE, "dir".as_ref()).await.unwrap();
+    let err = fs
+        .lookup(dir.attr.ino, "file".as_ref())
+        .await
+        .expect_err("file under an invalid directory name should not exist");
+    assert_eq!(err.to_errno(), libc::ENOENT);
+    for name in [".", ".."] {
+        let err = fs
+            .lookup(dir.attr.ino, name.as_ref())
+            .await
+            .expect_err("dot names can't be looked up");
+        assert_eq!(err.to_errno(), libc::EINVAL);
+    }
+}
+
+#[test_case(""; "unprefixed")]
+#[test_case("prefix/"; "prefixed")]
+#[tokio::test]
+async fn test_executable_paths(prefix: &str) {
+    let config = S3FilesystemConfig {
+        executable_paths: vec![Glob::new("*.sh").unwrap()],
+        ..Default::default()
+    };
+    let prefix = Prefix::new(prefix).expect("valid prefix");
+    let (client, fs) = make_test_filesystem("test_executable_paths", &prefix, config);
+    for key in ["run.sh", "data.txt", "dir/build.sh", "run.sh.bak"] {
+        client.add_object(
+            &format!("{prefix}{key}"),
+            MockObject::constant(0xaa, 27, ETag::for_tests()),
+        );
+    }
+
+    let dir = fs.lookup(FUSE_ROOT_INODE, "dir".as_ref()).await.unwrap();
+    for (parent, name, mode) in [
+        (FUSE_ROOT_INODE, "run.sh", 0o755),
+        (FUSE_ROOT_INODE, "data.txt", 0o644),
+        (dir.attr.ino, "build.sh", 0o755),
+        (FUSE_ROOT_INODE, "run.sh.bak", 0o644),
+    ] {
+        let entry = fs.lookup(parent, name.as_ref()).await.unwrap();
+        assert_attr(
+            entry.attr,
+            FileType::RegularFile,
+            27,
+            getuid().into(),
+            getgid().into(),
+            mode,
+        );
+        let attr = fs.getattr(entry.attr.ino).await.unwrap();
+        assert_eq!(attr.attr.perm, mode, "getattr of {name} should report the same mode");
+    }
+
+    // Directories aren't affected
+    assert_eq!(dir.attr.perm, 0o755);
+}
+
+#[test_case(""; "unprefixed")]
+#[test_case("prefix/"; "prefixed")]
+#[tokio::test]
+async fn test_soft_missing_paths(prefix: &str) {
+    let config = S3FilesystemConfig {
+        soft_missing_paths: vec![Glob::new("**/_SUCCESS").unwrap()],
+        ..Default::default()
+    };
+    let prefix = Prefix::new(prefix).expect("valid prefix");
+    let (client, fs) = make_test_filesystem("test_soft_missing_paths", &prefix, config);
+    client.add_object(
+        &format!("{prefix}dir/a.txt"),
+        MockObject::constant(0xaa, 27, ETag::for_tests()),
+    );
+
+    let dir = fs.lookup(FUSE_ROOT_INODE, "dir".as_ref()).await.unwrap();
+    let get_counter = client.new_counter(Operation::GetObject);
+    let file = fs.lookup(dir.attr.ino, "_SUCCESS".as_ref()).await.unwrap();
+    assert_attr(
+        file.attr,
+        FileType::RegularFile,
+        0,
+        getuid().into(),
+        getgid().into(),
+        0o644,
+    );
+    let attr = fs.getattr(file.attr.ino).await.unwrap();
+    assert_attr(
+        attr.attr,
+        FileType::RegularFile,
+        0,
+        getuid().into(),
+        getgid().into(),
+        0o644,
+    );
+
+    let fh = fs.open(file.attr.ino, S_IFREG as i32, 0).await.unwrap().fh;
+    let bytes_read = fs
+        .read(file.attr.ino, fh, 0, 4096, 0, None)
+        .await
+        .expect("fs read should succeed");
+    assert!(bytes_read.is_empty());
+    fs.release(file.attr.ino, fh, 0, None, true).await.unwrap();
+    assert_eq!(get_counter.count(), 0);
+
+    // Later lookups find the same empty file
+    let again = fs.lookup(dir.attr.ino, "_SUCCESS".as_ref()).await.unwrap();
+    assert_eq!(again.attr.ino, file.attr.ino);
+
+    // Missing paths that don't match are still not found
+    let err = fs
+        .lookup(dir.attr.ino, "_FAILURE".as_ref())
+        .await
+        .expect_err("file should not exist");
+    assert_eq!(err.to_errno(), libc::ENOENT);
+}
+
+#[test_case(""; "unprefixed")]
+#[test_case("prefix/"; "prefixed")]
+#[tokio::test]
+async fn test_download_to(prefix: &str) {
+    let prefix = Prefix::new(prefix).expect("valid prefix");
+    let (client, fs) = make_test_filesystem("test_download_to", &prefix, Default::default());
+    // Large enough to take several requests and reads from the prefetcher
+    let object = MockObject::ramp(0xaa, 5 * 1024 * 1024 + 111, ETag::for_tests());
+    let expected = object.read(0, object.len());
+    client.add_object(&format!("{prefix}dir/file.bin"), object);
+
+    let mut data = Vec::new();
+    let written = fs.download_to("dir/file.bin", &mut data).await.unwrap();
+    assert_eq!(written, expected.len() as u64);
+    assert_eq!(&data[..], &expected[..]);
+
+    let err = fs
+        .download_to("dir/missing.bin", &mut Vec::new())
+        .await
+        .expect_err("file should not exist");
+    assert_eq!(err.to_errno(), libc::ENOENT);
+    let err = fs
+        .download_to("dir", &mut Vec::new())
+        .await
+        .expect_err("directories can't be downloaded");
+    assert_eq!(err.to_errno(), libc::EISDIR);
+}
+
+#[tokio::test]
+async fn test_dot_components() {
+    let (client, fs) = make_test_filesystem("test_dot_components", &Default::default(), Default::default());
+    for key in [
+        "dir/a",
+        "dir/./a",
+        "dir/./b",
+        "dir/../c",
+        "dir/.",
+        "dir/..",
+        "dir/trailing.",
+        "dir/...",
+        "./d",
+        "../e",
+        "dotonly/./x",
+    ] {
+        client.add_object(key, b"hello".into());
+    }
+
+    async fn list(fs: &TestS3Filesystem<Arc<MockClient>>, ino: InodeNo) -> Vec<OsString> {
+        let dir_handle = fs.opendir(ino, 0).await.unwrap().fh;
+        let mut reply = DirectoryReply::default();
+        let _reply = fs.readdir(ino, dir_handle, 0, &mut reply).await.unwrap();
+        fs.releasedir(ino, dir_handle, 0).await.unwrap();
+        reply.entries.iter().skip(2).map(|entry| entry.name.clone()).collect()
+    }
+
+    // Keys with `.` or `..` components are never listed, and the only `.` and `..` entries are the
+    // directories themselves
+    assert_eq!(list(&fs, FUSE_ROOT_INODE).await, ["dir", "dotonly"]);
+    let dir_ino = fs.lookup(FUSE_ROOT_INODE, "dir".as_ref()).await.unwrap().attr.ino;
+    assert_eq!(list(&fs, dir_ino).await, ["...", "a", "trailing."]);
+    let dotonly_ino = fs.lookup(FUSE_ROOT_INODE, "dotonly".as_ref()).await.unwrap().attr.ino;
+    assert_eq!(list(&fs, dotonly_ino).await, Vec::<OsString>::new());
+
+    // Nor can they be looked up, by their own names or by the names they'd navigate to
+    for name in [".", ".."] {
+        let err = fs.lookup(dir_ino, name.as_ref()).await.expect_err("reserved name");
+        assert_eq!(err.to_errno(), libc::EINVAL, "lookup of {name:?}");
+    }
+    let err = fs
+        .lookup(dir_ino, "b".as_ref())
+        .await
+        .expect_err("dir/./b is not dir/b");
+    assert_eq!(err.to_errno(), libc::ENOENT);
+    let err = fs
+        .lookup(FUSE_ROOT_INODE, "c".as_ref())
+        .await
+        .expect_err("dir/../c is not c");
+    assert_eq!(err.to_errno(), libc::ENOENT);
+
+    // Names that only contain dots, or end in one, are ordinary names
+    for name in ["...", "trailing."] {
+        let entry = fs.lookup(dir_ino, name.as_ref()).await.unwrap();
+        assert_eq!(entry.attr.kind, FileType::RegularFile, "lookup of {name:?}");
+    }
+
+    // Paths aren't normalized either, so they can't reach a different key than they spell out
+    let mut data = Vec::new();
+    fs.download_to("dir/trailing.", &mut data).await.unwrap();
+    assert_eq!(data, b"hello");
+    for path in ["dir/./a", "dir/.", "dir/../dir/a", "./dir/a", "/dir/a"] {
+        let err = fs
+            .download_to(path, &mut Vec::new())
+            .await
+            .expect_err("path with dot components");
+        assert_eq!(err.to_errno(), libc::EINVAL, "download of {path:?}");
+    }
+}
+
+#[tokio::test]
+async fn test_read_incomplete_response_body() {
+    let (client, fs) = make_test_filesystem(
+        "test_read_incomplete_response_body",
+        &Default::default(),
+        Default::default(),
+    );
+    let object = MockObject::ramp(0xaa, 1024 * 1024, ETag::for_tests());
+    let expected = object.read(0, object.len());
+    client.add_object("file.bin", object);
+
+    let entry = fs.lookup(FUSE_ROOT_INODE, "file.bin".as_ref()).await.unwrap();
+    let fh = fs.open(entry.attr.ino, libc::O_RDONLY, 0).await.unwrap().fh;
+
+    // A response that fails partway through its body fails the read rather than returning short data
+    client.fail_next_get_object_bodies(1);
+    let err = fs
+        .read(entry.attr.ino, fh, 0, expected.len() as u32, 0, None)
+        .await
+        .expect_err("read should fail");
+    assert_eq!(err.to_errno(), libc::EIO);
+
+    // Retrying the read starts a new request
+    let data = fs
+        .read(entry.attr.ino, fh, 0, expected.len() as u32, 0, None)
+        .await
+        .unwrap();
+    assert_eq!(&data[..], &expected[..]);
+}
+
+#[tokio::test]
+async fn test_read_mismatched_content_range() {
+    let (client, fs) = make_test_filesystem(
+        "test_read_mismatched_content_range",
+        &Default::default(),
+        Default::default(),
+    );
+    let object = MockObject::ramp(0xaa, 1024 * 1024, ETag::for_tests());
+    let expected = object.read(0, object.len());
+    client.add_object("file.bin", object);
+
+    let entry = fs.lookup(FUSE_ROOT_INODE, "file.bin".as_ref()).await.unwrap();
+    let fh = fs.open(entry.attr.ino, libc::O_RDONLY, 0).await.unwrap().fh;
+
+    // A response holding a different range than requested fails the read rather than returning
+    // data from the wrong offset
+    client.mismatch_next_get_object_ranges(1);
+    let err = fs
+        .read(entry.attr.ino, fh, 0, expected.len() as u32, 0, None)
+        .await
+        .expect_err("read should fail");
+    assert_eq!(err.to_errno(), libc::EIO);
+
+    // Retrying the read starts a new request
+    let data = fs
+        .read(entry.attr.ino, fh, 0, expected.len() as u32, 0, None)
+        .await
+        .unwrap();
+    assert_eq!(&data[..], &expected[..]);
+}
+
+#[test_case(0, 20 * 1024 * 1024 + 111, 3, true; "zero placeholder size")]
+#[test_case(4096, 20 * 1024 * 1024 + 111, 3, true; "non-zero placeholder size")]
+#[test_case(0, 16 * 1024 * 1024, 3, true; "ends with a full request")]
+#[test_case(0, 20 * 1024 * 1024 + 111, 4, false; "ranges past the end refused")]
+#[tokio::test]
+async fn test_read_unknown_length_object(placeholder_size: u64, size: usize, requests: u64, short_responses: bool) {
+    let fs_config = S3FilesystemConfig {
+        unknown_object_size: placeholder_size,
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_read_unknown_length_object", &Default::default(), fs_config);
+    client.truncate_get_object_ranges(short_responses);
+    let mut object = MockObject::ramp(0xaa, size, ETag::for_tests());
+    object.set_unknown_size(true);
+    let expected = object.read(0, object.len());
+    client.add_object("lambda.bin", object);
+
+    let entry = fs.lookup(FUSE_ROOT_INODE, "lambda.bin".as_ref()).await.unwrap();
+    assert_eq!(entry.attr.size, placeholder_size);
+    let ino = entry.attr.ino;
+    let fh = fs.open(ino, libc::O_RDONLY, 0).await.unwrap().fh;
+
+    // Reads stream the whole object, however big its attributes say it is
+    let get_counter = client.new_counter(Operation::GetObject);
+    let mut data = Vec::new();
+    loop {
+        let bytes = fs.read(ino, fh, data.len() as i64, 100_000, 0, None).await.unwrap();
+        if bytes.is_empty() {
+            break;
+        }
+        data.extend_from_slice(&bytes);
+    }
+    assert_eq!(data.len(), expected.len());
+    assert_eq!(&data[..], &expected[..]);
+    // The object is read with bounded ranged requests, one after the other, until one comes back
+    // short or finds nothing left
+    assert_eq!(get_counter.count(), requests);
+
+    // Once the end has been found, reads past it are empty, wherever they are
+    let bytes = fs
+        .read(ino, fh, expected.len() as i64 + 12345, 1024, 0, None)
+        .await
+        .unwrap();
+    assert!(bytes.is_empty());
+
+    fs.release(ino, fh, 0, None, true).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_read_unknown_length_object_not_sequential() {
+    let (client, fs) = make_test_filesystem(
+        "test_read_unknown_length_object_not_sequential",
+        &Default::default(),
+        Default::default(),
+    );
+    let mut object = MockObject::ramp(0xaa, 1024 * 1024, ETag::for_tests());
+    object.set_unknown_size(true);
+    let expected = object.read(0, 1024);
+    client.add_object("lambda.bin", object);
+
+    let entry = fs.lookup(FUSE_ROOT_INODE, "lambda.bin".as_ref()).await.unwrap();
+    let ino = entry.attr.ino;
+    let fh = fs.open(ino, libc::O_RDONLY, 0).await.unwrap().fh;
+
+    let bytes = fs.read(ino, fh, 0, 1024, 0, None).await.unwrap();
+    assert_eq!(&bytes[..], &expected[..]);
+
+    // Seeking past the data read so far, or back into it, isn't supported
+    for offset in [4096, 0] {
+        let err = fs
+            .read(ino, fh, offset, 1024, 0, None)
+            .await
+            .expect_err("non-sequential read should fail");
+        assert_eq!(err.to_errno(), libc::EINVAL);
+    }
+
+    // But reading on from where the last read ended still works
+    let bytes = fs.read(ino, fh, 1024, 1024, 0, None).await.unwrap();
+    assert_eq!(bytes.len(), 1024);
+
+    fs.release(ino, fh, 0, None, true).await.unwrap();
+}
+
+#[test_case(true; "decompress")]
+#[test_case(false; "no decompress")]
+#[tokio::test]
+async fn test_read_zstd_object(transparent_decompress: bool) {
+    let fs_config = S3FilesystemConfig {
+        transparent_decompress,
+        unknown_object_size: 4096,
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_read_zstd_object", &Default::default(), fs_config);
+    let decompressed = MockObject::ramp(0xaa, 2 * 1024 * 1024 + 111, ETag::for_tests()).read(0, 2 * 1024 * 1024 + 111);
+    let compressed = zstd::encode_all(&decompressed[..], 3).unwrap();
+    let mut object = MockObject::from_bytes(&compressed, ETag::for_tests());
+    object.set_content_encoding(Some("zstd".to_owned()));
+    client.add_object("data.zst", object);
+
+    let entry = fs.lookup(FUSE_ROOT_INODE, "data.zst".as_ref()).await.unwrap();
+    let expected: &[u8] = if transparent_decompress {
+        assert_eq!(entry.attr.size, 4096);
+        &decompressed
+    } else {
+        assert_eq!(entry.attr.size, compressed.len() as u64);
+        &compressed
+    };
+    let ino = entry.attr.ino;
+    let fh = fs.open(ino, libc::O_RDONLY, 0).await.unwrap().fh;
+
+    let mut data = Vec::new();
+    loop {
+        let bytes = fs.read(ino, fh, data.len() as i64, 100_000, 0, None).await.unwrap();
+        if bytes.is_empty() {
+            break;
+        }
+        data.extend_from_slice(&bytes);
+    }
+    assert_eq!(data.len(), expected.len());
+    assert_eq!(&data[..], expected);
+
+    fs.release(ino, fh, 0, None, true).await.unwrap();
+}
+
+#[test_case(2 * 1024 * 1024, 0, false; "past new end")]
+#[test_case(1536 * 1024 - 100, 100, false; "overlapping new end")]
+#[test_case(2 * 1024 * 1024, 0, true; "past new end with short responses")]
+#[test_case(1536 * 1024 - 100, 100, true; "overlapping new end with short responses")]
+#[tokio::test]
+async fn test_read_after_object_shrinks(read_offset: u64, expected_len: usize, short_responses: bool) {
+    let fs_config = S3FilesystemConfig {
+        cache_config: CacheConfig {
+            serve_lookup_from_cache: true,
+            file_ttl: Duration::from_secs(600),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_read_after_object_shrinks", &Default::default(), fs_config);
+    // Like S3, answer ranges that overlap the end of the object with the bytes up to its end,
+    // rather than refusing them
+    client.truncate_get_object_ranges(short_responses);
+    let object = MockObject::ramp(0xaa, 3 * 1024 * 1024, ETag::for_tests());
+    client.add_object("file.bin", object);
+
+    let entry = fs.lookup(FUSE_ROOT_INODE, "file.bin".as_ref()).await.unwrap();
+    let ino = entry.attr.ino;
+    let fh = fs.open(ino, libc::O_RDONLY, 0).await.unwrap().fh;
+    let _ = fs.read(ino, fh, 0, 4096, 0, None).await.unwrap();
+
+    // Shrink the object without changing its ETag, so reads still match it
+    let shrunk = MockObject::ramp(0xaa, 1536 * 1024, ETag::for_tests());
+    let expected = shrunk.read(read_offset, expected_len);
+    client.add_object("file.bin", shrunk);
+
+    let bytes = fs.read(ino, fh, read_offset as i64, 4096, 0, None).await.unwrap();
+    assert_eq!(bytes.len(), expected_len);
+    assert_eq!(&bytes[..], &expected[..]);
+
+    // Reads past the new end are at EOF from now on
+    let bytes = fs.read(ino, fh, 2 * 1024 * 1024, 4096, 0, None).await.unwrap();
+    assert!(bytes.is_empty());
+
+    // The file's cached size was corrected too
+    let attr = fs.getattr(ino).await.unwrap();
+    assert_eq!(attr.attr.size, 1536 * 1024);
+
+    fs.release(ino, fh, 0, None, true).await.unwrap();
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ReadHandleState {
+    /// Nothing has been read through the handle yet
+    Cold,
+    /// The whole object has been read through the handle
+    Warm,
+    /// The object shrank after the handle was opened, and a read found its new size
+    SizeRefreshed,
+}
+
+#[test_case(ReadHandleState::Cold; "cold handle")]
+#[test_case(ReadHandleState::Warm; "warm handle")]
+#[test_case(ReadHandleState::SizeRefreshed; "size-refreshed handle")]
+#[tokio::test]
+async fn test_read_at_or_past_eof(handle_state: ReadHandleState) {
+    const SIZE: usize = 1536 * 1024;
+
+    let fs_config = S3FilesystemConfig {
+        cache_config: CacheConfig {
+            serve_lookup_from_cache: true,
+            file_ttl: Duration::from_secs(600),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_read_at_or_past_eof", &Default::default(), fs_config);
+    let initial_size = match handle_state {
+        ReadHandleState::SizeRefreshed => 2 * SIZE,
+        _ => SIZE,
+    };
+    client.add_object("file.bin", MockObject::ramp(0xaa, initial_size, ETag::for_tests()));
+
+    let entry = fs.lookup(FUSE_ROOT_INODE, "file.bin".as_ref()).await.unwrap();
+    let ino = entry.attr.ino;
+    let fh = fs.open(ino, libc::O_RDONLY, 0).await.unwrap().fh;
+    match handle_state {
+        ReadHandleState::Cold => {}
+        ReadHandleState::Warm => {
+            let bytes = fs.read(ino, fh, 0, SIZE as u32, 0, None).await.unwrap();
+            assert_eq!(bytes.len(), SIZE);
+        }
+        ReadHandleState::SizeRefreshed => {
+            // Shrink the object without changing its ETag, so reads still match it
+            client.add_object("file.bin", MockObject::ramp(0xaa, SIZE, ETag::for_tests()));
+            let bytes = fs.read(ino, fh, SIZE as i64 + 4096, 4096, 0, None).await.unwrap();
+            assert!(bytes.is_empty());
+        }
+    }
+
+    // Reads at or past the end return no data, and negative offsets are invalid, without asking S3
+    let get_counter = client.new_counter(Operation::GetObject);
+    for offset in [SIZE as i64, SIZE as i64 + 1, 2 * SIZE as i64, i64::MAX] {
+        let bytes = fs.read(ino, fh, offset, 4096, 0, None).await.unwrap();
+        assert!(bytes.is_empty(), "read at {offset} should be at EOF");
+    }
+    let err = fs
+        .read(ino, fh, -1, 4096, 0, None)
+        .await
+        .expect_err("negative offset should fail");
+    assert_eq!(err.to_errno(), libc::EINVAL);
+    assert_eq!(get_counter.count(), 0);
+
+    // Reads before the end still return the object's data
+    let expected = MockObject::ramp(0xaa, SIZE, ETag::for_tests()).read(SIZE as u64 - 100, 100);
+    let bytes = fs.read(ino, fh, SIZE as i64 - 100, 4096, 0, None).await.unwrap();
+    assert_eq!(&bytes[..], &expected[..]);
+
+    fs.release(ino, fh, 0, None, true).await.unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_read_handle_stats() {
+    const SIZE: usize = 64 * 1024;
+    const READ_SIZE: usize = 16 * 1024;
+
+    let (client, fs) = make_test_filesystem("test_read_handle_stats", &Default::default(), Default::default());
+    client.add_object("file.bin", MockObject::ramp(0xaa, SIZE, ETag::for_tests()));
+    let ino = fs.lookup(FUSE_ROOT_INODE, "file.bin".as_ref()).await.unwrap().attr.ino;
+
+    let read_range = |fh: u64, range: std::ops::Range<usize>| {
+        let fs = &fs;
+        async move {
+            for offset in range.step_by(READ_SIZE) {
+                let bytes = fs
+                    .read(ino, fh, offset as i64, READ_SIZE as u32, 0, None)
+                    .await
+                    .unwrap();
+                assert_eq!(bytes.len(), READ_SIZE);
+            }
+        }
+    };
+
+    // The application reads the file twice through a direct I/O handle, and every read reaches the
+    // file system
+    let direct = fs.open(ino, libc::O_RDONLY | libc::O_DIRECT, 0).await.unwrap();
+    assert_ne!(direct.flags, 0, "O_DIRECT opens should use direct I/O");
+    read_range(direct.fh, 0..SIZE).await;
+    read_range(direct.fh, 0..SIZE).await;
+    // Reads at EOF count as reads, but return nothing
+    fs.read(ino, direct.fh, i64::MAX, 4096, 0, None).await.unwrap();
+    fs.report_application_reads(direct.fh, 2 * SIZE as u64).await.unwrap();
+    fs.release(ino, direct.fh, 0, None, true).await.unwrap();
+
+    // It reads the file twice through a page-cache handle too. The kernel serves the second pass
+    // from its cache, so only the first reaches the file system.
+    let cached = fs.open(ino, libc::O_RDONLY, 0).await.unwrap();
+    assert_eq!(cached.flags, 0, "other opens should use the page cache");
+    read_range(cached.fh, 0..SIZE).await;
+    fs.report_application_reads(cached.fh, 2 * SIZE as u64).await.unwrap();
+    fs.release(ino, cached.fh, 0, None, true).await.unwrap();
+
+    // A page-cache handle opened while the kernel had the first half of the file cached only sees
+    // reads of the second half. Without a report, the application is assumed to have read
+    // everything up to the furthest offset.
+    let partial = fs.open(ino, libc::O_RDONLY, 0).await.unwrap();
+    read_range(partial.fh, SIZE / 2..SIZE).await;
+
+    // Handles only count once they're released
+    let stats = fs.read_handle_stats();
+    assert_eq!(stats.page_cache.handles, 1);
+    fs.release(ino, partial.fh, 0, None, true).await.unwrap();
+
+    let err = fs
+        .report_application_reads(partial.fh, SIZE as u64)
+        .await
+        .expect_err("released handles can't be reported on");
+    assert_eq!(err.to_errno(), libc::EBADF);
+
+    let stats = fs.read_handle_stats();
+    let reads_per_pass = (SIZE / READ_SIZE) as u64;
+    assert_eq!(
+        stats.direct_io,
+        ReadCounters {
+            handles: 1,
+            reads: 2 * reads_per_pass + 1,
+            bytes: 2 * SIZE as u64,
+            spanned_bytes: SIZE as u64,
+            application_bytes: 2 * SIZE as u64,
+            page_cache_bytes: 0,
+        }
+    );
+    // The page cache served the whole second pass of the second handle, and the first half of the
+    // file for the third
+    assert_eq!(
+        stats.page_cache,
+        ReadCounters {
+            handles: 2,
+            reads: reads_per_pass + reads_per_pass / 2,
+            bytes: SIZE as u64 + SIZE as u64 / 2,
+            spanned_bytes: 2 * SIZE as u64,
+            application_bytes: 3 * SIZE as u64,
+            page_cache_bytes: SIZE as u64 + SIZE as u64 / 2,
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_etag_xattr() {
+    let fs_config = S3FilesystemConfig {
+        etag_xattr: true,
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_etag_xattr", &Default::default(), fs_config);
+    client.add_object("dir/file.txt", MockObject::from(b"hello world"));
+
+    let dir = fs.lookup(FUSE_ROOT_INODE, "dir".as_ref()).await.unwrap();
+    let file = fs.lookup(dir.attr.ino, "file.txt".as_ref()).await.unwrap();
+    let etag = fs.getxattr(file.attr.ino, ETAG_XATTR.as_ref()).await.unwrap();
+    assert_eq!(etag, ETag::from_object_bytes(b"hello world").as_str().as_bytes());
+    let names = fs.listxattr(file.attr.ino).await.unwrap();
+    assert_eq!(names, b"user.s3.etag\0");
+
+    // Same size, different content
+    client.add_object("dir/file.txt", MockObject::from(b"HELLO WORLD"));
+    let new_etag = fs.getxattr(file.attr.ino, ETAG_XATTR.as_ref()).await.unwrap();
+    assert_ne!(new_etag, etag);
+    assert_eq!(new_etag, ETag::from_object_bytes(b"HELLO WORLD").as_str().as_bytes());
+
+    // Directories and other attribute names have no value
+    let err = fs.getxattr(file.attr.ino, "user.other".as_ref()).await.unwrap_err();
+    assert_ne!(err.to_errno(), libc::ENOSYS);
+    fs.getxattr(dir.attr.ino, ETAG_XATTR.as_ref()).await.unwrap_err();
+    assert!(fs.listxattr(dir.attr.ino).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_etag_xattr_disabled() {
+    let (client, fs) = make_test_filesystem("test_etag_xattr_disabled", &Default::default(), Default::default());
+    client.add_object("file.txt", MockObject::from(b"hello world"));
+
+    let file = fs.lookup(FUSE_ROOT_INODE, "file.txt".as_ref()).await.unwrap();
+    let err = fs.getxattr(file.attr.ino, ETAG_XATTR.as_ref()).await.unwrap_err();
+    assert_eq!(err.to_errno(), libc::ENOSYS);
+}
+
+#[test_case(None; "no manifest")]
+#[test_case(Some(ListingBootstrap::Object(".listing.jsonl".to_owned())); "manifest in bucket")]
+#[tokio::test]
+async fn test_new_makes_no_requests(listing_bootstrap: Option<ListingBootstrap>) {
+    const BUCKET: &str = "test_new_makes_no_requests";
+    let (client, seed_fs) = make_test_filesystem(BUCKET, &Default::default(), Default::default());
+    client.add_object("dir/a.txt", MockObject::constant(0xaa, 27, ETag::for_tests()));
+
+    let manifest_dir = tempfile::tempdir().unwrap();
+    let manifest_path = manifest_dir.path().join("listing.jsonl");
+    seed_fs.export_listing(&manifest_path).await.unwrap();
+    let manifest = std::fs::read(&manifest_path).unwrap();
+    client.add_object(".listing.jsonl", MockObject::from_bytes(&manifest, ETag::for_tests()));
+
+    let counters =
+        [Operation::GetObject, Operation::HeadObject, Operation::ListObjectsV2].map(|op| client.new_counter(op));
+    let config = S3FilesystemConfig {
+        listing_bootstrap,
+        directory_poll_interval: Some(Duration::from_secs(3600)),
+        ..Default::default()
+    };
+    let fs = make_test_filesystem_with_client(client.clone(), BUCKET, &Default::default(), config);
+    let _ = fs.getattr(FUSE_ROOT_INODE).await.unwrap();
+    for counter in &counters {
+        assert_eq!(counter.count(), 0, "no requests until the first lookup");
+    }
+
+    let dir_ino = fs.lookup(FUSE_ROOT_INODE, "dir".as_ref()).await.unwrap().attr.ino;
+    assert!(counters.iter().any(|counter| counter.count() > 0));
+
+    // Watching a directory for changes doesn't list it either
+    let counts = counters.iter().map(|counter| counter.count()).collect::<Vec<_>>();
+    let dir_handle = fs.opendir(dir_ino, 0).await.unwrap().fh;
+    assert_eq!(
+        counters.iter().map(|counter| counter.count()).collect::<Vec<_>>(),
+        counts
+    );
+    fs.releasedir(dir_ino, dir_handle, 0).await.unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_open_path_only() {
+    let (client, fs) = make_test_filesystem("test_open_path_only", &Default::default(), Default::default());
+    client.add_object("dir/file.txt", MockObject::constant(0xaa, 10, ETag::for_tests()));
+    let get_counter = client.new_counter(Operation::GetObject);
+
+    let dir = fs.lookup(FUSE_ROOT_INODE, "dir".as_ref()).await.unwrap();
+    let file = fs.lookup(dir.attr.ino, "file.txt".as_ref()).await.unwrap();
+
+    // Other flags are ignored, so this doesn't start an upload
+    let flags = libc::O_PATH | libc::O_WRONLY | libc::O_TRUNC;
+    for (ino, kind) in [
+        (file.attr.ino, FileType::RegularFile),
+        (dir.attr.ino, FileType::Directory),
+    ] {
+        let fh = fs.open(ino, flags, 0).await.expect("O_PATH open should succeed").fh;
+
+        let attr = fs.getattr(ino).await.expect("fstat should succeed").attr;
+        assert_eq!(attr.kind, kind);
+
+        let err = fs
+            .read(ino, fh, 0, 4096, 0, None)
+            .await
+            .expect_err("O_PATH handle can't be read");
+        assert_eq!(err.to_errno(), libc::EBADF);
+        let err = fs
+            .write(ino, fh, 0, b"hello", 0, 0, None)
+            .await
+            .expect_err("O_PATH handle can't be written");
+        assert_eq!(err.to_errno(), libc::EBADF);
+
+        fs.flush(ino, fh, 0, 0).await.unwrap();
+        fs.release(ino, fh, 0, None, true).await.unwrap();
+    }
+
+    assert_eq!(get_counter.count(), 0);
+    assert_eq!(
+        fs.getattr(file.attr.ino).await.unwrap().attr.size,
+        10,
+        "file should not have been truncated"
+    );
+}
+
+#[tokio::test]
+async fn test_open_twice_shares_reads() {
+    const BUCKET: &str = "test_open_twice_shares_reads";
+    let (client, fs) = make_test_filesystem(BUCKET, &Default::default(), Default::default());
+    let object = MockObject::ramp(0xaa, 1024 * 1024, ETag::for_tests());
+    let expected = object.read(0, 1024 * 1024);
+    client.add_object("file.bin", object);
+
+    let entry = fs.lookup(FUSE_ROOT_INODE, "file.bin".as_ref()).await.unwrap();
+    let ino = entry.attr.ino;
+    let fh1 = fs.open(ino, libc::O_RDONLY, 0).await.unwrap().fh;
+    let fh2 = fs.open(ino, libc::O_RDONLY, 0).await.unwrap().fh;
+    assert_ne!(fh1, fh2);
+
+    let get_counter = client.new_counter(Operation::GetObject);
+    let data = fs.read(ino, fh1, 0, 64 * 1024, 0, None).await.unwrap();
+    assert_eq!(&data[..], &expected[..64 * 1024]);
+    // The object fits in the prefetcher's first request
+    assert_eq!(get_counter.count(), 1);
+
+    // The second handle's overlapping read is served from the first handle's prefetched data
+    let data = fs.read(ino, fh2, 32 * 1024, 32 * 1024, 0, None).await.unwrap();
+    assert_eq!(&data[..], &expected[32 * 1024..64 * 1024]);
+    assert_eq!(get_counter.count(), 1);
+
+    // The state survives until the last handle is released
+    fs.release(ino, fh1, 0, None, true).await.unwrap();
+    let data = fs.read(ino, fh2, 0, 64 * 1024, 0, None).await.unwrap();
+    assert_eq!(&data[..], &expected[..64 * 1024]);
+    assert_eq!(get_counter.count(), 1);
+    fs.release(ino, fh2, 0, None, true).await.unwrap();
+
+    // A new handle after that starts over
+    let fh3 = fs.open(ino, libc::O_RDONLY, 0).await.unwrap().fh;
+    let data = fs.read(ino, fh3, 0, 64 * 1024, 0, None).await.unwrap();
+    assert_eq!(&data[..], &expected[..64 * 1024]);
+    assert_eq!(get_counter.count(), 2);
+    fs.release(ino, fh3, 0, None, true).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_concurrent_readdir_handles() {
+    const BUCKET: &str = "test_concurrent_readdir_handles";
+    let config = S3FilesystemConfig {
+        // List in small pages, so the streams take turns listing
+        max_buffered_dir_entries: Some(10),
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem(BUCKET, &Default::default(), config);
+    let mut expected = vec![".".to_owned(), "..".to_owned()];
+    for i in 0..55 {
+        let name = format!("file{i:03}");
+        client.add_object(&name, MockObject::constant(0, 1, ETag::for_tests()));
+        expected.push(name);
+    }
+    client.set_operation_latency(Operation::ListObjectsV2, Duration::from_millis(20));
+    let list_counter = client.new_counter(Operation::ListObjectsV2);
+
+    let fh1 = fs.opendir(FUSE_ROOT_INODE, 0).await.unwrap().fh;
+    let fh2 = fs.opendir(FUSE_ROOT_INODE, 0).await.unwrap().fh;
+
+    let list_all = |fh: u64, max_entries: usize| {
+        let fs = &fs;
+        async move {
+            let mut names = Vec::new();
+            loop {
+                let entries = ls(fs, fh, names.len() as i64, max_entries).await;
+                if entries.is_empty() {
+                    break;
+                }
+                names.extend(entries.into_iter().map(|(_, name)| name.into_string().unwrap()));
+            }
+            names
+        }
+    };
+    let listings = futures::future::join(list_all(fh1, 7), list_all(fh2, 4)).await;
+    assert_eq!(listings.0, expected);
+    assert_eq!(listings.1, expected);
+    // Each stream listed the directory itself, in pages of 10
+    assert_eq!(list_counter.count(), 2 * 6);
+
+    fs.releasedir(FUSE_ROOT_INODE, fh1, 0).await.unwrap();
+    fs.releasedir(FUSE_ROOT_INODE, fh2, 0).await.unwrap();
+}
+
+#[test_case(true; "report types")]
+#[test_case(false; "unknown types")]
+#[tokio::test]
+async fn test_readdir_report_types(readdir_report_types: bool) {
+    let config = S3FilesystemConfig {
+        readdir_report_types,
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_readdir_report_types", &Default::default(), config);
+    client.add_object("dir/file.txt", MockObject::constant(0, 1, ETag::for_tests()));
+    client.add_object("file.txt", MockObject::constant(0, 1, ETag::for_tests()));
+
+    let fh = fs.opendir(FUSE_ROOT_INODE, 0).await.unwrap().fh;
+    let mut reply = DirectoryReply::default();
+    fs.readdir(FUSE_ROOT_INODE, fh, 0, &mut reply).await.unwrap();
+    let entries: Vec<_> = reply
+        .entries
+        .iter()
+        .map(|entry| (entry.name.to_str().unwrap(), entry.attr.kind, entry.dtype))
+        .collect();
+    let expected_type = |kind| readdir_report_types.then_some(kind);
+    assert_eq!(
+        entries,
+        [
+            (".", FileType::Directory, expected_type(FileType::Directory)),
+            ("..", FileType::Directory, expected_type(FileType::Directory)),
+            ("dir", FileType::Directory, expected_type(FileType::Directory)),
+            ("file.txt", FileType::RegularFile, expected_type(FileType::RegularFile)),
+        ]
+    );
+    fs.releasedir(FUSE_ROOT_INODE, fh, 0).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_concurrent_lookups_share_head_object() {
+    let config = S3FilesystemConfig {
+        cache_config: CacheConfig {
+            file_ttl: Duration::ZERO,
+            ..Default::default()
+        },
+        etag_xattr: true,
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_concurrent_lookups_share_head_object", &Default::default(), config);
+    client.add_object("file.txt", MockObject::from(b"hello world"));
+    let ino = fs.lookup(FUSE_ROOT_INODE, "file.txt".as_ref()).await.unwrap().attr.ino;
+
+    // Keep the HeadObject in flight long enough for all three to join it
+    client.set_operation_latency(Operation::HeadObject, Duration::from_millis(50));
+    let head_counter = client.new_counter(Operation::HeadObject);
+    let (lookup, getattr, xattr) = futures::join!(
+        fs.lookup(FUSE_ROOT_INODE, "file.txt".as_ref()),
+        fs.getattr(ino),
+        fs.getxattr(ino, ETAG_XATTR.as_ref()),
+    );
+    assert_eq!(lookup.unwrap().attr.ino, ino);
+    assert_eq!(getattr.unwrap().attr.size, 11);
+    assert_eq!(
+        xattr.unwrap(),
+        ETag::from_object_bytes(b"hello world").as_str().as_bytes()
+    );
+    assert_eq!(head_counter.count(), 1);
+}
+
+#[test_case(Duration::ZERO; "no window")]
+#[test_case(Duration::from_secs(60); "long window")]
+#[tokio::test]
+async fn test_lookup_coalesce_window(window: Duration) {
+    let config = S3FilesystemConfig {
+        cache_config: CacheConfig {
+            file_ttl: Duration::ZERO,
+            lookup_coalesce_window: window,
+            ..Default::default()
+        },
+        etag_xattr: true,
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_lookup_coalesce_window", &Default::default(), config);
+    client.add_object("file.txt", MockObject::from(b"hello world"));
+
+    let head_counter = client.new_counter(Operation::HeadObject);
+    let ino = fs.lookup(FUSE_ROOT_INODE, "file.txt".as_ref()).await.unwrap().attr.ino;
+    fs.getattr(ino).await.unwrap();
+    fs.getxattr(ino, ETAG_XATTR.as_ref()).await.unwrap();
+    let expected_heads = if window.is_zero() { 3 } else { 1 };
+    assert_eq!(head_counter.count(), expected_heads);
+
+    // Opens with O_DIRECT always ask S3
+    let fh = fs.open(ino, libc::O_RDONLY | libc::O_DIRECT, 0).await.unwrap().fh;
+    assert_eq!(head_counter.count(), expected_heads + 1);
+    fs.release(ino, fh, 0, None, true).await.unwrap();
+
+    // So do lookups after a local change
+    let fh = fs.open(ino, libc::O_RDONLY, 0).await.unwrap().fh;
+    client.add_object("file.txt", MockObject::from(b"HELLO WORLD"));
+    let err = fs
+        .read(ino, fh, 0, 11, 0, None)
+        .await
+        .expect_err("object changed since it was opened");
+    assert_eq!(err.to_errno(), libc::ESTALE);
+    fs.release(ino, fh, 0, None, true).await.unwrap();
+    let etag = fs.getxattr(ino, ETAG_XATTR.as_ref()).await.unwrap();
+    assert_eq!(etag, ETag::from_object_bytes(b"HELLO WORLD").as_str().as_bytes());
+}
+
+#[test_case("file", "dir", libc::EISDIR; "file onto directory")]
+#[test_case("dir", "file", libc::ENOTDIR; "directory onto file")]
+#[test_case("dir", "dir2", libc::ENOTEMPTY; "directory onto non-empty directory")]
+#[test_case("dir", "emptydir", libc::ENOSYS; "directory onto empty directory")]
+#[test_case("file", "file2", libc::ENOSYS; "file onto file")]
+#[test_case("file", "missing", libc::ENOSYS; "file onto nothing")]
+#[test_case("missing", "file", libc::ENOENT; "missing source")]
+#[tokio::test]
+async fn test_rename_target_types(source: &str, target: &str, expected_errno: libc::c_int) {
+    let (client, fs) = make_test_filesystem("test_rename_target_types", &Default::default(), Default::default());
+    let keys = ["file", "file2", "dir/a", "dir2/b"];
+    for key in keys {
+        client.add_object(key, MockObject::constant(0xaa, 16, ETag::for_tests()));
+    }
+    fs.mkdir(FUSE_ROOT_INODE, "emptydir".as_ref(), libc::S_IFDIR, 0)
+        .await
+        .unwrap();
+
+    let err = fs
+        .rename(FUSE_ROOT_INODE, source.as_ref(), FUSE_ROOT_INODE, target.as_ref(), 0)
+        .await
+        .expect_err("rename should fail");
+    assert_eq!(err.to_errno(), expected_errno);
+
+    // Nothing was copied or deleted
+    for key in keys {
+        assert!(client.contains_key(key));
+    }
+}
+
+#[tokio::test]
+async fn test_readdirplus_lookup_counts() {
+    let (client, fs) = make_test_filesystem(
+        "test_readdirplus_lookup_counts",
+        &Default::default(),
+        Default::default(),
+    );
+    for i in 0..6 {
+        client.add_object(&format!("foo{i}"), b"foo".into());
+    }
+
+    let dir_handle = fs.opendir(FUSE_ROOT_INODE, 0).await.unwrap().fh;
+    let mut delivered = Vec::new();
+    // The reply fills up after . and .. and two files, so foo2 is dropped rather than delivered
+    delivered.extend(ls(&fs, dir_handle, 0, 4).await);
+    delivered.extend(ls(&fs, dir_handle, 4, 3).await);
+    // Repeating the same offset replays the last response, into a smaller buffer this time
+    delivered.extend(ls(&fs, dir_handle, 4, 2).await);
+    delivered.extend(ls(&fs, dir_handle, 7, 0).await);
+    fs.releasedir(FUSE_ROOT_INODE, dir_handle, 0).await.unwrap();
+
+    // Plain readdir and getattr don't count as lookups
+    let dir_handle = fs.opendir(FUSE_ROOT_INODE, 0).await.unwrap().fh;
+    let mut reply = DirectoryReply::new(0);
+    let _ = fs.readdir(FUSE_ROOT_INODE, dir_handle, 0, &mut reply).await.unwrap();
+    assert_eq!(reply.entries.len(), 8);
+    fs.releasedir(FUSE_ROOT_INODE, dir_handle, 0).await.unwrap();
+    for (ino, _) in &delivered {
+        fs.getattr(*ino).await.unwrap();
+    }
+
+    let mut counts: HashMap<OsString, (u64, u64)> = HashMap::new();
+    for (ino, name) in delivered {
+        if name != "." && name != ".." {
+            counts.entry(name).or_insert((ino, 0)).1 += 1;
+        }
+    }
+    let expected: HashMap<OsString, u64> = [
+        ("foo0", 1),
+        ("foo1", 1),
+        ("foo2", 2),
+        ("foo3", 2),
+        ("foo4", 1),
+        ("foo5", 1),
+    ]
+    .into_iter()
+    .map(|(name, count)| (name.into(), count))
+    .collect();
+    assert_eq!(
+        counts
+            .iter()
+            .map(|(name, (_, count))| (name.clone(), *count))
+            .collect::<HashMap<_, _>>(),
+        expected
+    );
+
+    // Forgetting each inode as many times as it was delivered drops the last reference to it.
+    // Forget panics if this makes the lookup count underflow.
+    for (name, (ino, count)) in counts {
+        fs.forget(ino, count).await;
+        let err = fs.getattr(ino).await.expect_err("inode should be forgotten");
+        assert_eq!(err.to_errno(), libc::ENOENT, "{name:?} should have been forgotten");
+    }
+}
+
+#[tokio::test]
+async fn test_pinned_listings_evicted_over_capacity() {
+    let fs_config = S3FilesystemConfig {
+        cache_config: CacheConfig {
+            pinned_prefixes: vec!["reference/".to_owned()],
+            pinned_listings_max_entries: Some(3),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_pinned_listings_evicted", &Default::default(), fs_config);
+    for key in ["reference/a/1", "reference/a/2", "reference/b/1", "reference/b/2"] {
+        client.add_object(key, b"hello".into());
+    }
+    let list_counter = client.new_counter(Operation::ListObjectsV2);
+
+    let reference = fs.lookup(FUSE_ROOT_INODE, "reference".as_ref()).await.unwrap().attr.ino;
+    let a = fs.lookup(reference, "a".as_ref()).await.unwrap().attr.ino;
+    let b = fs.lookup(reference, "b".as_ref()).await.unwrap().attr.ino;
+
+    async fn read_dir(fs: &TestS3Filesystem<Arc<MockClient>>, ino: InodeNo) -> usize {
+        let dir_handle = fs.opendir(ino, 0).await.unwrap().fh;
+        let mut reply = DirectoryReply::new(0);
+        let _ = fs.readdir(ino, dir_handle, 0, &mut reply).await.unwrap();
+        fs.releasedir(ino, dir_handle, 0).await.unwrap();
+        reply.entries.len()
+    }
+
+    // Both listings don't fit, so listing b evicts a's
+    assert_eq!(read_dir(&fs, a).await, 4);
+    assert_eq!(read_dir(&fs, b).await, 4);
+    let lists = list_counter.count();
+    assert_eq!(read_dir(&fs, b).await, 4);
+    assert_eq!(list_counter.count(), lists, "b should still be cached");
+    assert_eq!(read_dir(&fs, a).await, 4);
+    assert_eq!(list_counter.count(), lists + 1, "a should have been listed again");
+
+    // Evicting a listing doesn't affect a readdir that's already replaying it
+    let dir_handle = fs.opendir(a, 0).await.unwrap().fh;
+    let mut reply = DirectoryReply::new(3);
+    let _ = fs.readdir(a, dir_handle, 0, &mut reply).await.unwrap();
+    assert_eq!(read_dir(&fs, b).await, 4);
+    let lists = list_counter.count();
+    let mut rest = DirectoryReply::new(0);
+    let _ = fs.readdir(a, dir_handle, 3, &mut rest).await.unwrap();
+    fs.releasedir(a, dir_handle, 0).await.unwrap();
+    assert_eq!(reply.entries.len() + rest.entries.len(), 4);
+    assert_eq!(list_counter.count(), lists);
+}
+
+/// Read the whole of the file `name` in the directory `parent` of a view
+async fn read_view_file<Client, Prefetcher>(
+    view: &S3FilesystemView<'_, Client, Prefetcher>,
+    parent: InodeNo,
+    name: &str,
+) -> Result<Bytes, i32>
+where
+    Client: ObjectClient + Send + Sync + 'static,
+    Prefetcher: Prefetch,
+{
+    let entry = view.lookup(parent, name.as_ref()).await.map_err(|e| e.to_errno())?;
+    let fh = view
+        .open(entry.attr.ino, libc::O_RDONLY, 0)
+        .await
+        .map_err(|e| e.to_errno())?
+        .fh;
+    let data = view
+        .read(entry.attr.ino, fh, 0, entry.attr.size as u32, 0, None)
+        .await
+        .map_err(|e| e.to_errno())?;
+    view.release(entry.attr.ino, fh, 0, None, false).await.unwrap();
+    Ok(data)
+}
+
+#[tokio::test]
+async fn test_subviews() {
+    let config = S3FilesystemConfig {
+        cache_config: CacheConfig {
+            serve_lookup_from_cache: true,
+            dir_ttl: Duration::from_secs(600),
+            file_ttl: Duration::from_secs(600),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_subviews", &Default::default(), config);
+    client.add_object(
+        "tenants/a/data.txt",
+        MockObject::from_bytes(b"tenant a", ETag::for_tests()),
+    );
+    client.add_object(
+        "tenants/a/sub/nested.txt",
+        MockObject::from_bytes(b"nested a", ETag::for_tests()),
+    );
+    client.add_object(
+        "tenants/b/data.txt",
+        MockObject::from_bytes(b"tenant b", ETag::for_tests()),
+    );
+    client.add_object("shared.txt", MockObject::from_bytes(b"shared", ETag::for_tests()));
+
+    let view_a = fs.subview("tenants/a/").await.unwrap();
+    let view_b = fs.subview("tenants/b").await.unwrap();
+
+    // Each view is rooted at its own directory
+    let root = view_a.getattr(FUSE_ROOT_INODE).await.unwrap();
+    assert_eq!(root.attr.ino, FUSE_ROOT_INODE);
+    assert_eq!(root.attr.kind, FileType::Directory);
+    assert_eq!(
+        read_view_file(&view_a, FUSE_ROOT_INODE, "data.txt").await.unwrap(),
+        &b"tenant a"[..]
+    );
+    assert_eq!(
+        read_view_file(&view_b, FUSE_ROOT_INODE, "data.txt").await.unwrap(),
+        &b"tenant b"[..]
+    );
+    let sub = view_a.lookup(FUSE_ROOT_INODE, "sub".as_ref()).await.unwrap();
+    assert_eq!(
+        read_view_file(&view_a, sub.attr.ino, "nested.txt").await.unwrap(),
+        &b"nested a"[..]
+    );
+
+    // Listing the root shows only the view's own directory, and its `..` is itself
+    let dir_fh = view_a.opendir(FUSE_ROOT_INODE, 0).await.unwrap().fh;
+    let mut reply = DirectoryReply::new(0);
+    let _ = view_a.readdir(FUSE_ROOT_INODE, dir_fh, 0, &mut reply).await.unwrap();
+    let entries: Vec<_> = reply.entries.iter().map(|e| (e.name.clone(), e.ino)).collect();
+    assert_eq!(
+        entries,
+        [
+            (OsString::from("."), FUSE_ROOT_INODE),
+            (OsString::from(".."), FUSE_ROOT_INODE),
+            (OsString::from("data.txt"), entries[2].1),
+            (OsString::from("sub"), sub.attr.ino),
+        ]
+    );
+    view_a.releasedir(FUSE_ROOT_INODE, dir_fh, 0).await.unwrap();
+
+    // Nothing above or beside the view's root can be reached through it
+    let err = view_a
+        .lookup(FUSE_ROOT_INODE, "..".as_ref())
+        .await
+        .expect_err("lookup of .. should fail");
+    assert_eq!(err.to_errno(), libc::EINVAL);
+    assert_eq!(
+        read_view_file(&view_a, FUSE_ROOT_INODE, "shared.txt").await,
+        Err(libc::ENOENT)
+    );
+    let tenants = fs.lookup(FUSE_ROOT_INODE, "tenants".as_ref()).await.unwrap();
+    let b_data = view_b.lookup(FUSE_ROOT_INODE, "data.txt".as_ref()).await.unwrap();
+    for ino in [tenants.attr.ino, b_data.attr.ino] {
+        let err = view_a.getattr(ino).await.expect_err("inode should be outside the view");
+        assert_eq!(err.to_errno(), libc::ENOENT);
+        let err = view_a
+            .open(ino, libc::O_RDONLY, 0)
+            .await
+            .expect_err("inode should be outside the view");
+        assert_eq!(err.to_errno(), libc::ENOENT);
+    }
+    // Nor can another view's handles be used
+    let b_fh = view_b.open(b_data.attr.ino, libc::O_RDONLY, 0).await.unwrap().fh;
+    let err = view_a
+        .read(b_data.attr.ino, b_fh, 0, 8, 0, None)
+        .await
+        .expect_err("handle should belong to another view");
+    assert_eq!(err.to_errno(), libc::EBADF);
+    view_b.release(b_data.attr.ino, b_fh, 0, None, false).await.unwrap();
+
+    // Views are read-only
+    let a_data = view_a.lookup(FUSE_ROOT_INODE, "data.txt".as_ref()).await.unwrap();
+    let err = view_a
+        .open(a_data.attr.ino, libc::O_WRONLY | libc::O_TRUNC, 0)
+        .await
+        .expect_err("views should be read-only");
+    assert_eq!(err.to_errno(), libc::EROFS);
+
+    // Views share the file system's caches, so an overlapping view finds everything already
+    // looked up through the first
+    let head_counter = client.new_counter(Operation::HeadObject);
+    let list_counter = client.new_counter(Operation::ListObjectsV2);
+    let view_c = fs.subview("tenants/a").await.unwrap();
+    let entry = view_c.lookup(FUSE_ROOT_INODE, "data.txt".as_ref()).await.unwrap();
+    assert_eq!(entry.attr.ino, a_data.attr.ino);
+    assert_eq!(head_counter.count(), 0);
+    assert_eq!(list_counter.count(), 0);
+
+    // A missing prefix, or one that names a file, can't be viewed
+    let err = fs.subview("tenants/c").await.err().expect("missing prefix should fail");
+    assert_eq!(err.to_errno(), libc::ENOENT);
+    let err = fs.subview("shared.txt").await.err().expect("file prefix should fail");
+    assert_eq!(err.to_errno(), libc::ENOTDIR);
+}
+
+#[tokio::test]
+async fn test_shared_disk_cache() {
+    const BUCKET: &str = "test_shared_disk_cache";
+    const OBJECT_SIZE: usize = 3 * 1024 * 1024 + 17;
+    let client = Arc::new(MockClient::new(MockClientConfig {
+        bucket: BUCKET.to_string(),
+        part_size: 1024 * 1024,
+        ..Default::default()
+    }));
+    client.add_object("dir/file.bin", MockObject::ramp(0xaa, OBJECT_SIZE, ETag::for_tests()));
+
+    let cache_dir = tempfile::tempdir().unwrap();
+    let make_fs = || {
+        let shared_cache_dir = SharedCacheDir::new_from_parent(cache_dir.path()).unwrap();
+        let cache = DiskDataCache::new_shared(&shared_cache_dir, BUCKET, Default::default());
+        let runtime = ThreadPool::builder().pool_size(1).create().unwrap();
+        let prefetcher = caching_prefetch(cache, runtime, Default::default());
+        let fs = S3Filesystem::new(
+            client.clone(),
+            prefetcher,
+            BUCKET,
+            &Default::default(),
+            Default::default(),
+        );
+        (shared_cache_dir, fs)
+    };
+    let (first_cache_dir, first_fs) = make_fs();
+    let (second_cache_dir, second_fs) = make_fs();
+
+    let expected = MockObject::ramp(0xaa, OBJECT_SIZE, ETag::for_tests()).read(0, OBJECT_SIZE);
+    let mut get_counts = Vec::new();
+    for fs in [&first_fs, &second_fs] {
+        let get_counter = client.new_counter(Operation::GetObject);
+        let dir = fs.lookup(FUSE_ROOT_INODE, "dir".as_ref()).await.unwrap();
+        let file = fs.lookup(dir.attr.ino, "file.bin".as_ref()).await.unwrap();
+        let fh = fs.open(file.attr.ino, libc::O_RDONLY, 0).await.unwrap().fh;
+        let mut data = Vec::new();
+        while data.len() < OBJECT_SIZE {
+            let bytes = fs
+                .read(file.attr.ino, fh, data.len() as i64, 256 * 1024, 0, None)
+                .await
+                .unwrap();
+            assert!(!bytes.is_empty(), "read should not end early");
+            data.extend_from_slice(&bytes);
+        }
+        fs.release(file.attr.ino, fh, 0, None, true).await.unwrap();
+        assert_eq!(data[..], expected[..]);
+        get_counts.push(get_counter.count());
+    }
+
+    // The first file system downloaded the object, and the second read it from the shared cache
+    assert!(get_counts[0] > 0);
+    assert_eq!(get_counts[1], 0);
+
+    drop(first_fs);
+    drop(first_cache_dir);
+    drop(second_fs);
+    drop(second_cache_dir);
+    assert!(
+        !cache_dir.path().join("mountpoint-cache-shared").try_exists().unwrap(),
+        "last user should clean up the shared cache"
+    );
+}
+
+#[tokio::test]
+async fn test_open_twice_interleaved_streams() {
+    const BUCKET: &str = "test_open_twice_interleaved_streams";
+    const OBJECT_SIZE: usize = 64 * 1024 * 1024;
+    const READ_SIZE: usize = 128 * 1024;
+    const READS: usize = 32;
+    let (client, fs) = make_test_filesystem(BUCKET, &Default::default(), Default::default());
+    let object = MockObject::ramp(0xaa, OBJECT_SIZE, ETag::for_tests());
+    let expected = object.read(0, OBJECT_SIZE);
+    client.add_object("file.bin", object);
+
+    let entry = fs.lookup(FUSE_ROOT_INODE, "file.bin".as_ref()).await.unwrap();
+    let ino = entry.attr.ino;
+
+    // How many requests one handle reading sequentially makes
+    let get_counter = client.new_counter(Operation::GetObject);
+    let fh = fs.open(ino, libc::O_RDONLY, 0).await.unwrap().fh;
+    for i in 0..READS {
+        let offset = i * READ_SIZE;
+        let data = fs
+            .read(ino, fh, offset as i64, READ_SIZE as u32, 0, None)
+            .await
+            .unwrap();
+        assert_eq!(&data[..], &expected[offset..offset + READ_SIZE]);
+    }
+    fs.release(ino, fh, 0, None, true).await.unwrap();
+    let sequential_gets = get_counter.count();
+
+    // Two handles taking turns reading sequentially from distant offsets each keep their own
+    // prefetch window, rather than resetting a shared one on every read
+    let get_counter = client.new_counter(Operation::GetObject);
+    let fh1 = fs.open(ino, libc::O_RDONLY, 0).await.unwrap().fh;
+    let fh2 = fs.open(ino, libc::O_RDONLY, 0).await.unwrap().fh;
+    for i in 0..READS {
+        for (fh, start) in [(fh1, 0), (fh2, OBJECT_SIZE / 2)] {
+            let offset = start + i * READ_SIZE;
+            let data = fs
+                .read(ino, fh, offset as i64, READ_SIZE as u32, 0, None)
+                .await
+                .unwrap();
+            assert_eq!(&data[..], &expected[offset..offset + READ_SIZE]);
+        }
+    }
+    assert_eq!(get_counter.count(), 2 * sequential_gets);
+    fs.release(ino, fh1, 0, None, true).await.unwrap();
+    fs.release(ino, fh2, 0, None, true).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_circuit_breaker() {
+    let clock = Arc::new(MockClock::new());
+    let cooldown = Duration::from_secs(5);
+    let fs_config = S3FilesystemConfig {
+        circuit_breaker: Some(CircuitBreakerConfig {
+            window: Duration::from_secs(600),
+            failure_threshold: 0.5,
+            min_requests: 4,
+            cooldown,
+        }),
+        clock: clock.clone(),
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_circuit_breaker", &Default::default(), fs_config);
+    let object = MockObject::ramp(0xaa, 1024 * 1024, ETag::for_tests());
+    let expected = object.read(0, object.len());
+    client.add_object("file.bin", object);
+
+    let entry = fs.lookup(FUSE_ROOT_INODE, "file.bin".as_ref()).await.unwrap();
+    let ino = entry.attr.ino;
+    let fh = fs.open(ino, libc::O_RDONLY, 0).await.unwrap().fh;
+
+    // Two successes and then two failures reach the threshold, and the breaker opens
+    client.fail_next_get_object_bodies(2);
+    for _ in 0..2 {
+        let err = fs
+            .read(ino, fh, 0, expected.len() as u32, 0, None)
+            .await
+            .expect_err("read should fail");
+        assert_eq!(err.to_errno(), libc::EIO);
+    }
+
+    // While open, every operation that would go to S3 fails fast without sending a request
+    let get_counter = client.new_counter(Operation::GetObject);
+    let head_counter = client.new_counter(Operation::HeadObject);
+    let err = fs
+        .read(ino, fh, 0, expected.len() as u32, 0, None)
+        .await
+        .expect_err("breaker should be open");
+    assert_eq!(err.to_errno(), libc::EAGAIN);
+    let err = fs
+        .lookup(FUSE_ROOT_INODE, "other.bin".as_ref())
+        .await
+        .expect_err("breaker should be open");
+    assert_eq!(err.to_errno(), libc::EAGAIN);
+    assert_eq!(get_counter.count(), 0);
+    assert_eq!(head_counter.count(), 0);
+
+    // After the cooldown, a successful probe closes the breaker again
+    clock.advance(cooldown);
+    let data = fs.read(ino, fh, 0, expected.len() as u32, 0, None).await.unwrap();
+    assert_eq!(&data[..], &expected[..]);
+    let err = fs
+        .lookup(FUSE_ROOT_INODE, "other.bin".as_ref())
+        .await
+        .expect_err("file doesn't exist");
+    assert_eq!(err.to_errno(), libc::ENOENT);
+}
+
+#[tokio::test]
+async fn test_circuit_breaker_serves_cache() {
+    let fs_config = S3FilesystemConfig {
+        cache_config: CacheConfig {
+            serve_lookup_from_cache: true,
+            dir_ttl: Duration::from_secs(600),
+            file_ttl: Duration::from_secs(600),
+            ..Default::default()
+        },
+        circuit_breaker: Some(CircuitBreakerConfig {
+            window: Duration::from_secs(600),
+            failure_threshold: 0.5,
+            min_requests: 2,
+            cooldown: Duration::from_secs(600),
+        }),
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_circuit_breaker_serves_cache", &Default::default(), fs_config);
+    client.add_object("file.bin", MockObject::constant(0xaa, 1024, ETag::for_tests()));
+    let file = fs.lookup(FUSE_ROOT_INODE, "file.bin".as_ref()).await.unwrap();
+
+    // One success and one failure reach the threshold, and the breaker opens
+    client.set_operation_failing(Operation::HeadObject, true);
+    client.set_operation_failing(Operation::ListObjectsV2, true);
+    let err = fs
+        .lookup(FUSE_ROOT_INODE, "missing".as_ref())
+        .await
+        .expect_err("metadata requests should fail");
+    assert_eq!(err.to_errno(), libc::EIO);
+    let err = fs
+        .lookup(FUSE_ROOT_INODE, "missing".as_ref())
+        .await
+        .expect_err("breaker should be open");
+    assert_eq!(err.to_errno(), libc::EAGAIN);
+
+    // Metadata that's cached and still valid doesn't need S3, so the open breaker doesn't refuse it
+    let entry = fs.lookup(FUSE_ROOT_INODE, "file.bin".as_ref()).await.unwrap();
+    assert_eq!(entry.attr.ino, file.attr.ino);
+    let attr = fs.getattr(file.attr.ino).await.unwrap();
+    assert_eq!(attr.attr.size, 1024);
+    fs.open(file.attr.ino, libc::O_RDONLY, 0).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_metadata_circuit_breaker() {
+    let clock = Arc::new(MockClock::new());
+    let cooldown = Duration::from_secs(5);
+    let fs_config = S3FilesystemConfig {
+        metadata_circuit_breaker: Some(CircuitBreakerConfig {
+            window: Duration::from_secs(600),
+            failure_threshold: 0.5,
+            min_requests: 4,
+            cooldown,
+        }),
+        clock: clock.clone(),
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_metadata_circuit_breaker", &Default::default(), fs_config);
+    let object = MockObject::ramp(0xaa, 64 * 1024, ETag::for_tests());
+    let expected = object.read(0, object.len());
+    client.add_object("dir/file.bin", object);
+    client.add_object("uncached.bin", MockObject::constant(0xbb, 1024, ETag::for_tests()));
+
+    // Three successful metadata requests resolve and open the file
+    let dir = fs.lookup(FUSE_ROOT_INODE, "dir".as_ref()).await.unwrap();
+    let file = fs.lookup(dir.attr.ino, "file.bin".as_ref()).await.unwrap();
+    let fh = fs.open(file.attr.ino, libc::O_RDONLY, 0).await.unwrap().fh;
+
+    // S3's metadata APIs go down, and three failures reach the threshold
+    client.set_operation_failing(Operation::HeadObject, true);
+    client.set_operation_failing(Operation::ListObjectsV2, true);
+    for name in ["missing1", "missing2", "missing3"] {
+        let err = fs
+            .lookup(FUSE_ROOT_INODE, name.as_ref())
+            .await
+            .expect_err("metadata requests should fail");
+        assert_eq!(err.to_errno(), libc::EIO);
+    }
+
+    // While the breaker is open, cached metadata is served without asking S3, and reads still work
+    let head_counter = client.new_counter(Operation::HeadObject);
+    let list_counter = client.new_counter(Operation::ListObjectsV2);
+    let entry = fs.lookup(dir.attr.ino, "file.bin".as_ref()).await.unwrap();
+    assert_eq!(entry.attr.ino, file.attr.ino);
+    assert_eq!(entry.attr.size, expected.len() as u64);
+    let attr = fs.getattr(file.attr.ino).await.unwrap();
+    assert_eq!(attr.attr.size, expected.len() as u64);
+    let data = fs
+        .read(file.attr.ino, fh, 0, expected.len() as u32, 0, None)
+        .await
+        .unwrap();
+    assert_eq!(&data[..], &expected[..]);
+    let fh2 = fs.open(file.attr.ino, libc::O_RDONLY, 0).await.unwrap().fh;
+    let data = fs
+        .read(file.attr.ino, fh2, 0, expected.len() as u32, 0, None)
+        .await
+        .unwrap();
+    assert_eq!(&data[..], &expected[..]);
+
+    // Only metadata that was never cached is unavailable
+    let err = fs
+        .lookup(FUSE_ROOT_INODE, "uncached.bin".as_ref())
+        .await
+        .expect_err("uncached metadata should be unavailable");
+    assert_eq!(err.to_errno(), libc::EAGAIN);
+    assert_eq!(head_counter.count(), 0);
+    assert_eq!(list_counter.count(), 0);
+
+    // Once S3 recovers, a successful probe after the cooldown closes the breaker again
+    client.set_operation_failing(Operation::HeadObject, false);
+    client.set_operation_failing(Operation::ListObjectsV2, false);
+    clock.advance(cooldown);
+    let entry = fs.lookup(FUSE_ROOT_INODE, "uncached.bin".as_ref()).await.unwrap();
+    assert_eq!(entry.attr.size, 1024);
+    let err = fs
+        .lookup(FUSE_ROOT_INODE, "missing1".as_ref())
+        .await
+        .expect_err("file doesn't exist");
+    assert_eq!(err.to_errno(), libc::ENOENT);
+    assert!(head_counter.count() > 0);
+}
+
+#[tokio::test]
+async fn test_metadata_circuit_breaker_serves_stale_listings() {
+    let clock = Arc::new(MockClock::new());
+    let fs_config = S3FilesystemConfig {
+        cache_config: CacheConfig {
+            serve_lookup_from_cache: true,
+            dir_ttl: Duration::from_secs(1),
+            file_ttl: Duration::from_secs(1),
+            ..Default::default()
+        },
+        metadata_circuit_breaker: Some(CircuitBreakerConfig {
+            window: Duration::from_secs(600),
+            failure_threshold: 0.5,
+            min_requests: 4,
+            cooldown: Duration::from_secs(600),
+        }),
+        clock: clock.clone(),
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem(
+        "test_metadata_circuit_breaker_serves_stale_listings",
+        &Default::default(),
+        fs_config,
+    );
+    client.add_object("dir/file.bin", MockObject::constant(0xaa, 1024, ETag::for_tests()));
+    client.add_object("file.bin", MockObject::constant(0xbb, 1024, ETag::for_tests()));
+
+    // List the root and learn that a name doesn't exist, then let all of that expire
+    let dir_handle = fs.opendir(FUSE_ROOT_INODE, 0).await.unwrap().fh;
+    let listed = ls(&fs, dir_handle, 0, 10).await;
+    assert_eq!(listed.len(), 2 + 2);
+    let err = fs
+        .lookup(FUSE_ROOT_INODE, "missing".as_ref())
+        .await
+        .expect_err("file doesn't exist");
+    assert_eq!(err.to_errno(), libc::ENOENT);
+    clock.advance(Duration::from_secs(2));
+
+    // S3's metadata APIs go down, and failures open the breaker
+    client.set_operation_failing(Operation::HeadObject, true);
+    client.set_operation_failing(Operation::ListObjectsV2, true);
+    let mut failures = 0;
+    let err = loop {
+        let err = fs
+            .lookup(FUSE_ROOT_INODE, format!("unknown{failures}").as_ref())
+            .await
+            .expect_err("metadata requests should fail");
+        if err.to_errno() != libc::EIO || failures == 10 {
+            break err;
+        }
+        failures += 1;
+    };
+    assert_eq!(err.to_errno(), libc::EAGAIN);
+
+    // The expired listing is served as it was, and the name that didn't exist still doesn't
+    let head_counter = client.new_counter(Operation::HeadObject);
+    let list_counter = client.new_counter(Operation::ListObjectsV2);
+    let dir_handle = fs.opendir(FUSE_ROOT_INODE, 0).await.unwrap().fh;
+    assert_eq!(ls(&fs, dir_handle, 0, 10).await, listed);
+    let err = fs
+        .lookup(FUSE_ROOT_INODE, "missing".as_ref())
+        .await
+        .expect_err("file still doesn't exist");
+    assert_eq!(err.to_errno(), libc::ENOENT);
+    let entry = fs.lookup(FUSE_ROOT_INODE, "file.bin".as_ref()).await.unwrap();
+    assert_eq!(entry.attr.size, 1024);
+    assert_eq!(head_counter.count(), 0);
+    assert_eq!(list_counter.count(), 0);
+}
+
+#[tokio::test]
+async fn test_read_denied() {
+    let (client, fs) = make_test_filesystem("test_read_denied", &Default::default(), Default::default());
+    client.add_object("secret.bin", MockObject::constant(0xaa, 1024, ETag::for_tests()));
+
+    let entry = fs.lookup(FUSE_ROOT_INODE, "secret.bin".as_ref()).await.unwrap();
+    let fh = fs.open(entry.attr.ino, libc::O_RDONLY, 0).await.unwrap().fh;
+
+    // Access is revoked after the file was opened, so only the read is denied
+    client.set_key_forbidden("secret.bin", true);
+    let err = fs
+        .read(entry.attr.ino, fh, 0, 1024, 0, None)
+        .await
+        .expect_err("access is denied");
+    assert_eq!(err.to_errno(), libc::EACCES);
+}
+
+#[tokio::test]
+async fn test_key_failures() {
+    let cooldown = Duration::from_secs(30);
+    let clock = Arc::new(MockClock::new());
+    let fs_config = S3FilesystemConfig {
+        key_failures: Some(KeyFailureConfig {
+            failure_threshold: 3,
+            window: Duration::from_secs(600),
+            cooldown,
+            max_keys: 100,
+        }),
+        clock: clock.clone(),
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_key_failures", &Default::default(), fs_config);
+    for key in ["secret.bin", "public.bin"] {
+        client.add_object(key, MockObject::constant(0xaa, 1024, ETag::for_tests()));
+    }
+    let secret_ino = fs
+        .lookup(FUSE_ROOT_INODE, "secret.bin".as_ref())
+        .await
+        .unwrap()
+        .attr
+        .ino;
+    let public_ino = fs
+        .lookup(FUSE_ROOT_INODE, "public.bin".as_ref())
+        .await
+        .unwrap()
+        .attr
+        .ino;
+
+    // Opens look up the file again, and only the first few of them ask S3 once access is denied
+    client.set_key_forbidden("secret.bin", true);
+    let head_counter = client.new_counter(Operation::HeadObject);
+    for _ in 0..10 {
+        let err = fs
+            .open(secret_ino, libc::O_RDONLY, 0)
+            .await
+            .expect_err("access is denied");
+        assert_eq!(err.to_errno(), libc::EACCES);
+    }
+    assert_eq!(head_counter.count(), 3);
+
+    // Other keys are unaffected
+    let head_counter = client.new_counter(Operation::HeadObject);
+    for _ in 0..10 {
+        let opened = fs.open(public_ino, libc::O_RDONLY, 0).await.unwrap();
+        fs.release(public_ino, opened.fh, 0, None, true).await.unwrap();
+    }
+    assert_eq!(head_counter.count(), 10);
+
+    // Names that don't exist are remembered too
+    let head_counter = client.new_counter(Operation::HeadObject);
+    for _ in 0..5 {
+        let err = fs
+            .lookup(FUSE_ROOT_INODE, "missing.bin".as_ref())
+            .await
+            .expect_err("file doesn't exist");
+        assert_eq!(err.to_errno(), libc::ENOENT);
+    }
+    assert_eq!(head_counter.count(), 3);
+
+    // After the cooldown, S3 is asked again
+    clock.advance(cooldown);
+    let head_counter = client.new_counter(Operation::HeadObject);
+    let err = fs
+        .open(secret_ino, libc::O_RDONLY, 0)
+        .await
+        .expect_err("access is still denied");
+    assert_eq!(err.to_errno(), libc::EACCES);
+    assert_eq!(head_counter.count(), 1);
+
+    clock.advance(cooldown);
+    client.set_key_forbidden("secret.bin", false);
+    let opened = fs.open(secret_ino, libc::O_RDONLY, 0).await.unwrap();
+    fs.release(secret_ino, opened.fh, 0, None, true).await.unwrap();
+}
+
+#[test_case(true; "replaced by directory")]
+#[test_case(false; "deleted")]
+#[tokio::test]
+async fn test_read_object_replaced_by_directory(replaced: bool) {
+    let fs_config = S3FilesystemConfig {
+        cache_config: CacheConfig {
+            serve_lookup_from_cache: true,
+            dir_ttl: Duration::from_secs(600),
+            file_ttl: Duration::from_secs(600),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (client, fs) = make_test_filesystem("test_read_object_replaced_by_directory", &Default::default(), fs_config);
+    let object = MockObject::ramp(0xaa, 16 * 1024 * 1024, ETag::for_tests());
+    let expected = object.read(0, 1024);
+    client.add_object("data.bin", object);
+
+    let entry = fs.lookup(FUSE_ROOT_INODE, "data.bin".as_ref()).await.unwrap();
+    let fh = fs.open(entry.attr.ino, libc::O_RDONLY, 0).await.unwrap().fh;
+    let data = fs.read(entry.attr.ino, fh, 0, 1024, 0, None).await.unwrap();
+    assert_eq!(&data[..], &expected[..]);
+
+    // Another writer deletes the object, and maybe creates a directory of the same name
+    client.remove_object("data.bin");
+    if replaced {
+        client.add_object("data.bin/part-0001", b"part".into());
+    }
+
+    // The next read past what was prefetched needs a new request, which finds the object gone
+    let err = fs
+        .read(entry.attr.ino, fh, 15 * 1024 * 1024, 1024, 0, None)
+        .await
+        .expect_err("read should fail");
+    if !replaced {
+        assert_eq!(err.to_errno(), libc::EIO);
+        return;
+    }
+    assert_eq!(err.to_errno(), libc::ESTALE);
+    fs.release(entry.attr.ino, fh, 0, None, true).await.unwrap();
+
+    // Even though lookups are served from the cache, the next one finds the directory
+    let entry = fs.lookup(FUSE_ROOT_INODE, "data.bin".as_ref()).await.unwrap();
+    assert_eq!(entry.attr.kind, FileType::Directory);
+    let part = fs.lookup(entry.attr.ino, "part-0001".as_ref()).await.unwrap();
+    assert_eq!(part.attr.kind, FileType::RegularFile);
+}
+
+#[tokio::test]
+async fn test_read_and_release_after_release() {
+    run_handle_ops(vec![
+        HandleOp::Open,
+        HandleOp::Release(HandleChoice::Any(1)),
+        HandleOp::Read(HandleChoice::Any(1), 0),
+        HandleOp::Release(HandleChoice::Any(1)),
+    ])
+    .await;
+}
+
+async fn new_local_file(fs: &TestS3Filesystem<Arc<MockClient>>, filename: &str) {
+    let mode = libc::S_IFREG | libc::S_IRWXU; // regular file + 0700 permissions
+    let dentry = fs.mknod(FUSE_ROOT_INODE, filename.as_ref(), mode, 0, 0).await.unwrap();
+    assert_eq!(dentry.attr.size, 0);
+    let file_ino = dentry.attr.ino;
+
+    let fh = fs
+        .open(file_ino, libc::S_IFREG as i32 | libc::O_WRONLY, 0)
+        .await
+        .unwrap()
+        .fh;
+
+    let slice = &[0xaa; 27];
+    let written = fs.write(file_ino, fh, 0, slice, 0, 0, None).await.unwrap();
+    assert_eq!(written as usize, slice.len());
+    fs.fsync(file_ino, fh, true).await.unwrap();
+}
+
+async fn ls(
+    fs: &TestS3Filesystem<Arc<MockClient>>,
+    dir_handle: u64,
+    offset: i64,
+    max_entries: usize,
+) -> Vec<(u64, OsString)> {
+    let mut reply = DirectoryReply::new(max_entries);
+    let _ = fs
+        .readdirplus(FUSE_ROOT_INODE, dir_handle, offset, &mut reply)
+        .await
+        .unwrap();
+    reply
+        .entries
+        .iter()
+        .map(|e| (e.ino, e.name.clone()))
+        .collect::<Vec<_>>()
+}
+
+async fn ls_from_token(
+    fs: &TestS3Filesystem<Arc<MockClient>>,
+    dir_ino: InodeNo,
+    token: &str,
+    max_entries: usize,
+) -> Vec<String> {
+    let mut reply = DirectoryReply::new(max_entries);
+    let _ = fs.readdir_from_token(dir_ino, token, &mut reply).await.unwrap();
+    reply
+        .entries
+        .iter()
+        .map(|e| e.name.to_str().unwrap().to_owned())
         .collect::<Vec<_>>()
 }
@@ -2,12 +2,17 @@ mod consistency_test;
 mod fork_test;
 mod lookup_test;
 mod mkdir_test;
+#[cfg(feature = "otel")]
+mod otel_test;
 mod perm_test;
 mod prefetch_test;
 mod read_test;
 mod readdir_test;
 mod rmdir_test;
 mod semantics_doc_test;
+#[cfg(all(feature = "fuse-integration-tests", target_os = "linux"))]
+mod session_test;
 mod setattr_test;
+mod statfs_test;
 mod unlink_test;
 mod write_test;
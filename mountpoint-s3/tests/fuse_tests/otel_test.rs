@@ -0,0 +1,41 @@
+use std::fs;
+
+use opentelemetry::trace::SpanId;
+use opentelemetry::Value;
+
+use crate::common::fuse;
+use crate::common::otel::{attribute, exported_spans};
+
+#[test]
+fn read_exports_trace_mock() {
+    let (mount_point, session, mut test_client) = fuse::mock_session::new("read_exports_trace", Default::default());
+
+    // A name no other test reads, to find this test's spans among everything exported
+    let file_name = "otel_trace.bin";
+    test_client.put_object(file_name, &[0xaa; 4096]).unwrap();
+
+    let data = fs::read(mount_point.path().join(file_name)).unwrap();
+    assert_eq!(data.len(), 4096);
+    // Unmounting releases the file handle, which stops the prefetches that keep the read's span open
+    drop(session);
+
+    let spans = exported_spans();
+    let read = spans
+        .iter()
+        .find(|span| span.name == "read" && attribute(span, "name") == Some(Value::from(file_name)))
+        .expect("read span should be exported");
+    assert_eq!(read.parent_span_id, SpanId::INVALID);
+    assert_eq!(attribute(read, "offset"), Some(Value::I64(0)));
+    assert!(read.end_time >= read.start_time);
+
+    // The GetObject the read waited for is part of the read's trace
+    let get = spans
+        .iter()
+        .find(|span| {
+            span.name == "request"
+                && span.span_context.trace_id() == read.span_context.trace_id()
+                && attribute(span, "operation").map(|op| op.as_str().into_owned()) == Some("GetObject".to_owned())
+        })
+        .expect("GetObject request should be exported in the read's trace");
+    assert_ne!(get.parent_span_id, SpanId::INVALID);
+}
@@ -8,6 +8,7 @@ use std::time::{Duration, Instant};
 
 use fuser::BackgroundSession;
 use mountpoint_s3::data_cache::InMemoryDataCache;
+use mountpoint_s3::prefetch::BufferPool;
 use mountpoint_s3::S3FilesystemConfig;
 #[cfg(not(feature = "s3express_tests"))]
 use mountpoint_s3_client::types::PutObjectParams;
@@ -28,12 +29,19 @@ fn open_for_read(path: impl AsRef<Path>, read_only: bool) -> std::io::Result<Fil
 }
 
 fn basic_read_test<F>(creator_fn: F, prefix: &str, read_only: bool)
+where
+    F: FnOnce(&str, TestSessionConfig) -> (TempDir, BackgroundSession, TestClientBox),
+{
+    basic_read_test_with_config(creator_fn, prefix, read_only, Default::default());
+}
+
+fn basic_read_test_with_config<F>(creator_fn: F, prefix: &str, read_only: bool, test_config: TestSessionConfig)
 where
     F: FnOnce(&str, TestSessionConfig) -> (TempDir, BackgroundSession, TestClientBox),
 {
     let mut rng = ChaChaRng::seed_from_u64(0x87654321);
 
-    let (mount_point, _session, mut test_client) = creator_fn(prefix, Default::default());
+    let (mount_point, _session, mut test_client) = creator_fn(prefix, test_config);
 
     test_client.put_object("hello.txt", b"hello world").unwrap();
     let mut two_mib_body = vec![0; 2 * 1024 * 1024];
@@ -108,6 +116,19 @@ fn basic_read_test_mock(prefix: &str, read_only: bool) {
     basic_read_test(fuse::mock_session::new, prefix, read_only);
 }
 
+// A pool with room for a single buffer, so that most parts fall back to allocating their own
+#[test_case("", true; "no prefix read only")]
+#[test_case("", false; "no prefix readwrite")]
+#[test_case("basic_read_test_with_buffer_pool", true; "prefix read only")]
+#[test_case("basic_read_test_with_buffer_pool", false; "prefix readwrite")]
+fn basic_read_test_mock_with_buffer_pool(prefix: &str, read_only: bool) {
+    let test_config = TestSessionConfig {
+        read_buffer_pool: Some(BufferPool::new(1024 * 1024, 1, Default::default())),
+        ..Default::default()
+    };
+    basic_read_test_with_config(fuse::mock_session::new, prefix, read_only, test_config);
+}
+
 #[test_case("", true; "no prefix read only")]
 #[test_case("", false; "no prefix readwrite")]
 #[test_case("basic_read_test_with_cache", true; "prefix read only")]
@@ -0,0 +1,313 @@
+//! End-to-end tests of the whole FUSE stack. These mount a mock-backed file system with
+//! [FuseSession](mountpoint_s3::fuse::session::FuseSession), the session runner `mount-s3` uses,
+//! and drive it with real system calls and tools, so they also cover the `fuser` adapter: reply
+//! conversions, flag handling, and session setup and teardown. Tests are skipped (and pass) on
+//! hosts that can't mount FUSE file systems.
+
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::Read;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+use fuser::{
+    Filesystem, KernelConfig, ReplyAttr, ReplyData, ReplyDirectory, ReplyDirectoryPlus, ReplyEmpty, ReplyEntry,
+    ReplyOpen, Request,
+};
+use test_case::test_case;
+
+use crate::common::fuse::{mock_session, TestSessionConfig};
+
+/// How long any one file system operation in these tests may take before we give up on it. A
+/// broken reply can leave the kernel waiting forever, and we'd rather fail than hang.
+const OPERATION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Run `f` on another thread, and panic if it doesn't finish within [OPERATION_TIMEOUT]. The
+/// thread is leaked if it's stuck in a system call.
+#[track_caller]
+fn with_timeout<T: Send + 'static>(description: &str, f: impl FnOnce() -> T + Send + 'static) -> T {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = tx.send(f());
+    });
+    match rx.recv_timeout(OPERATION_TIMEOUT) {
+        Ok(result) => result,
+        Err(_) => panic!("{description} did not finish within {OPERATION_TIMEOUT:?}"),
+    }
+}
+
+fn ramp_bytes(seed: u8, size: usize) -> Vec<u8> {
+    (0..size).map(|i| seed.wrapping_add(i as u8)).collect()
+}
+
+/// Whether anything is mounted at the given path, according to the kernel
+fn is_mounted(path: &Path) -> bool {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo").unwrap();
+    mountinfo
+        .lines()
+        .filter_map(|line| line.split(' ').nth(4))
+        .any(|mount_point| Path::new(mount_point) == path)
+}
+
+/// Read the file at `path` with `cat`, and check it has the `expected` contents
+fn check_cat(path: PathBuf, expected: &[u8]) -> Result<(), String> {
+    let output = with_timeout("cat", move || Command::new("cat").arg(path).output().unwrap());
+    if !output.status.success() {
+        return Err(format!("cat failed: {output:?}"));
+    }
+    if output.stdout != expected {
+        return Err(format!(
+            "cat read {} bytes that don't match the {} expected",
+            output.stdout.len(),
+            expected.len()
+        ));
+    }
+    Ok(())
+}
+
+fn test_config() -> TestSessionConfig {
+    TestSessionConfig {
+        part_size: 1024 * 1024,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn ls_and_stat() {
+    let Some((mount_point, _session, mut test_client)) = mock_session::new_with_runner("ls_and_stat", test_config())
+    else {
+        return;
+    };
+    test_client.put_object("dir/a.txt", b"hello").unwrap();
+    test_client.put_object("dir/b.bin", &ramp_bytes(0x11, 12345)).unwrap();
+    test_client.put_object("dir/sub/c.txt", b"world").unwrap();
+
+    let dir = mount_point.path().join("dir");
+    let mut names = with_timeout("readdir", {
+        let dir = dir.clone();
+        move || {
+            fs::read_dir(dir)
+                .unwrap()
+                .map(|entry| {
+                    let entry = entry.unwrap();
+                    (
+                        entry.file_name().into_string().unwrap(),
+                        entry.file_type().unwrap().is_dir(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        }
+    });
+    names.sort();
+    assert_eq!(
+        names,
+        [
+            ("a.txt".to_owned(), false),
+            ("b.bin".to_owned(), false),
+            ("sub".to_owned(), true)
+        ]
+    );
+
+    let (file, sub) = with_timeout("stat", move || {
+        (
+            fs::metadata(dir.join("b.bin")).unwrap(),
+            fs::metadata(dir.join("sub")).unwrap(),
+        )
+    });
+    assert!(file.is_file());
+    assert_eq!(file.len(), 12345);
+    assert_eq!(file.mode() & 0o777, 0o644);
+    assert!(sub.is_dir());
+    assert_eq!(sub.mode() & 0o777, 0o755);
+
+    let missing = mount_point.path().join("dir/missing");
+    let err = with_timeout("stat", move || fs::metadata(missing).unwrap_err());
+    assert_eq!(err.raw_os_error(), Some(libc::ENOENT));
+}
+
+#[test]
+fn cat() {
+    let Some((mount_point, _session, mut test_client)) = mock_session::new_with_runner("cat", test_config()) else {
+        return;
+    };
+    let data = ramp_bytes(0x42, 3 * 1024 * 1024 + 17);
+    test_client.put_object("file.bin", &data).unwrap();
+
+    check_cat(mount_point.path().join("file.bin"), &data).unwrap();
+}
+
+#[test_case(1; "one byte")]
+#[test_case(4097; "just over a page")]
+#[test_case(65521; "prime")]
+#[test_case(1024 * 1024 + 1; "just over a part")]
+fn dd_odd_block_sizes(block_size: usize) {
+    let Some((mount_point, _session, mut test_client)) =
+        mock_session::new_with_runner("dd_odd_block_sizes", test_config())
+    else {
+        return;
+    };
+    // Keep the one-byte reads to a reasonable number
+    let size = if block_size == 1 { 10_000 } else { 2 * 1024 * 1024 + 333 };
+    let data = ramp_bytes(0x07, size);
+    test_client.put_object("file.bin", &data).unwrap();
+
+    let path = mount_point.path().join("file.bin");
+    let output = with_timeout("dd", move || {
+        Command::new("dd")
+            .arg(format!("if={}", path.display()))
+            .arg(format!("bs={block_size}"))
+            .arg("status=none")
+            .output()
+            .unwrap()
+    });
+    assert!(output.status.success(), "dd failed: {output:?}");
+    assert_eq!(output.stdout, data);
+}
+
+#[test]
+fn concurrent_readers() {
+    let Some((mount_point, _session, mut test_client)) =
+        mock_session::new_with_runner("concurrent_readers", test_config())
+    else {
+        return;
+    };
+    let size = 5 * 1024 * 1024 + 1;
+    let expected = Arc::new(ramp_bytes(0x99, size));
+    test_client.put_object("file.bin", &expected).unwrap();
+
+    let path = mount_point.path().join("file.bin");
+    with_timeout("concurrent reads", move || {
+        let readers: Vec<_> = (0..8)
+            .map(|i| {
+                let path = path.clone();
+                let expected = expected.clone();
+                thread::spawn(move || {
+                    // Each reader uses a different read size, so they're at different offsets
+                    let mut file = File::open(path).unwrap();
+                    let mut buf = vec![0u8; 4096 * (i + 1) + i];
+                    let mut data = Vec::with_capacity(size);
+                    loop {
+                        let n = file.read(&mut buf).unwrap();
+                        if n == 0 {
+                            break;
+                        }
+                        data.extend_from_slice(&buf[..n]);
+                    }
+                    assert!(data == *expected, "reader {i} read the wrong data");
+                })
+            })
+            .collect();
+        for reader in readers {
+            reader.join().expect("reader should succeed");
+        }
+    });
+}
+
+#[test]
+fn unmount() {
+    let Some((mount_point, mut session, mut test_client)) = mock_session::new_with_runner("unmount", test_config())
+    else {
+        return;
+    };
+    test_client.put_object("file.txt", b"hello").unwrap();
+    let mount_path = mount_point.path().to_owned();
+    assert!(is_mounted(&mount_path));
+    let path = mount_path.join("file.txt");
+    assert_eq!(with_timeout("read", move || fs::read(path).unwrap()), b"hello");
+
+    session.unmount().expect("unmount should succeed");
+    assert!(!is_mounted(&mount_path));
+    // The mount directory is now just an empty local directory
+    assert_eq!(fs::read_dir(&mount_path).unwrap().count(), 0);
+}
+
+/// An adapter with a deliberate bug: it reads one byte past the offset the kernel asked for
+struct OffByOneReads<FS>(FS);
+
+impl<FS: Filesystem> Filesystem for OffByOneReads<FS> {
+    fn init(&self, req: &Request<'_>, config: &mut KernelConfig) -> Result<(), libc::c_int> {
+        self.0.init(req, config)
+    }
+
+    fn lookup(&self, req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        self.0.lookup(req, parent, name, reply)
+    }
+
+    fn forget(&self, req: &Request<'_>, ino: u64, nlookup: u64) {
+        self.0.forget(req, ino, nlookup)
+    }
+
+    fn getattr(&self, req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        self.0.getattr(req, ino, reply)
+    }
+
+    fn open(&self, req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        self.0.open(req, ino, flags, reply)
+    }
+
+    fn read(
+        &self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        flags: i32,
+        lock: Option<u64>,
+        reply: ReplyData,
+    ) {
+        self.0.read(req, ino, fh, offset + 1, size, flags, lock, reply)
+    }
+
+    fn flush(&self, req: &Request<'_>, ino: u64, fh: u64, lock_owner: u64, reply: ReplyEmpty) {
+        self.0.flush(req, ino, fh, lock_owner, reply)
+    }
+
+    fn release(
+        &self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        flags: i32,
+        lock_owner: Option<u64>,
+        flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        self.0.release(req, ino, fh, flags, lock_owner, flush, reply)
+    }
+
+    fn opendir(&self, req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        self.0.opendir(req, ino, flags, reply)
+    }
+
+    fn readdir(&self, req: &Request<'_>, ino: u64, fh: u64, offset: i64, reply: ReplyDirectory) {
+        self.0.readdir(req, ino, fh, offset, reply)
+    }
+
+    fn readdirplus(&self, req: &Request<'_>, ino: u64, fh: u64, offset: i64, reply: ReplyDirectoryPlus) {
+        self.0.readdirplus(req, ino, fh, offset, reply)
+    }
+
+    fn releasedir(&self, req: &Request<'_>, ino: u64, fh: u64, flags: i32, reply: ReplyEmpty) {
+        self.0.releasedir(req, ino, fh, flags, reply)
+    }
+}
+
+/// Check that these tests can catch bugs in the adapter, by running one against a broken one
+#[test]
+fn broken_adapter_is_caught() {
+    let Some((mount_point, _session, mut test_client)) =
+        mock_session::new_with_runner_and_adapter("broken_adapter_is_caught", test_config(), OffByOneReads)
+    else {
+        return;
+    };
+    let data = ramp_bytes(0x42, 3 * 1024 * 1024 + 17);
+    test_client.put_object("file.bin", &data).unwrap();
+
+    check_cat(mount_point.path().join("file.bin"), &data).expect_err("the off-by-one reads should be caught");
+}
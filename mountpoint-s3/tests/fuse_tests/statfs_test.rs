@@ -0,0 +1,33 @@
+use crate::common::fuse::{self, TestClientBox, TestSessionConfig};
+use fuser::BackgroundSession;
+use nix::sys::statvfs::statvfs;
+use std::fs::File;
+use tempfile::TempDir;
+
+fn statfs_test<F>(creator_fn: F, prefix: &str)
+where
+    F: FnOnce(&str, TestSessionConfig) -> (TempDir, BackgroundSession, TestClientBox),
+{
+    let (mount_point, _session, _test_client) = creator_fn(prefix, Default::default());
+
+    let stat = statvfs(mount_point.path()).expect("statfs should succeed");
+    assert_eq!(stat.name_max(), 255);
+
+    // Names as long as the reported maximum are accepted, and longer ones are not
+    let longest = "a".repeat(stat.name_max() as usize);
+    File::create(mount_point.path().join(&longest)).expect("longest name should be accepted");
+    let too_long = "a".repeat(stat.name_max() as usize + 1);
+    let err = File::create(mount_point.path().join(too_long)).expect_err("name should be too long");
+    assert_eq!(err.raw_os_error(), Some(libc::ENAMETOOLONG));
+}
+
+#[cfg(feature = "s3_tests")]
+#[test]
+fn statfs_test_s3() {
+    statfs_test(fuse::s3_session::new, "statfs_test");
+}
+
+#[test]
+fn statfs_test_mock() {
+    statfs_test(fuse::mock_session::new, "statfs_test");
+}
@@ -4,9 +4,8 @@ use std::path::{Component, Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 
-use fuser::FileType;
 use futures::future::{BoxFuture, FutureExt};
-use mountpoint_s3::fs::{CacheConfig, InodeNo, ToErrno, FUSE_ROOT_INODE};
+use mountpoint_s3::fs::{CacheConfig, FileType, InodeNo, ToErrno, FUSE_ROOT_INODE};
 use mountpoint_s3::prefix::Prefix;
 use mountpoint_s3::S3FilesystemConfig;
 use mountpoint_s3_client::mock_client::{MockClient, MockObject};
@@ -45,6 +44,18 @@ pub enum Op {
     /// this as an explicit operation tests a different code path (doing recursive path resolution
     /// rather than walking the directory hierarchy with `readdir`).
     Read(DirectoryIndex, ChildIndex),
+    /// Read a range of a file, which may reach past its end
+    ReadRange(
+        DirectoryIndex,
+        ChildIndex,
+        #[proptest(strategy = "0..3*1024*1024u64")] u64,
+        #[proptest(strategy = "0..256*1024u32")] u32,
+    ),
+    /// Look up a name in a directory, which may or may not exist
+    Lookup(DirectoryIndex, ValidName),
+    /// List a directory, then list it again on a new handle starting from the offset of one of the
+    /// entries (like `seekdir`) and check the rest of the listing is the same
+    ReaddirFrom(DirectoryIndex, usize),
 
     /// Put a new object into the bucket (to simulate concurrent access by a non-Mountpoint client).
     /// This includes generating keys that would be invalid filenames by using [Name] instead of
@@ -226,6 +237,16 @@ impl Harness {
                 Op::Read(directory_index, file_index) => {
                     self.perform_read(*directory_index, *file_index).await;
                 }
+                Op::ReadRange(directory_index, file_index, offset, size) => {
+                    self.perform_read_range(*directory_index, *file_index, *offset, *size)
+                        .await;
+                }
+                Op::Lookup(directory_index, name) => {
+                    self.perform_lookup(*directory_index, name).await;
+                }
+                Op::ReaddirFrom(directory_index, entry_index) => {
+                    self.perform_readdir_from(*directory_index, *entry_index).await;
+                }
                 Op::PutObject(directory_index, name, contents) => {
                     self.perform_put_object(*directory_index, name, contents).await;
                 }
@@ -552,6 +573,109 @@ impl Harness {
         }
     }
 
+    /// Read a range of a file from a directory. Reads past the end of the file are clamped to it.
+    async fn perform_read_range(
+        &self,
+        directory_index: DirectoryIndex,
+        file_index: ChildIndex,
+        offset: u64,
+        size: u32,
+    ) {
+        let dir_path = directory_index.get(&self.reference);
+        let Some(Node::Directory { children, .. }) = self.reference.lookup(dir_path.as_ref()) else {
+            panic!("directory must already exist");
+        };
+        let Some((name, Node::File(File::Remote(object)))) = file_index.get(children) else {
+            // Local files are checked by `compare_contents`, and directories can't be read
+            return;
+        };
+
+        let full_path = dir_path.as_ref().join(name);
+        trace!(path=?full_path, offset, size, "read range");
+        let inode = self.lookup(&full_path).await.expect("file should exist");
+        let fh = self
+            .fs
+            .open(inode, libc::O_RDONLY, 0)
+            .await
+            .expect("open should succeed")
+            .fh;
+        let expected_len = (size as u64).min((object.len() as u64).saturating_sub(offset));
+        let expected = object.read(offset, expected_len as usize);
+        let bytes = self
+            .fs
+            .read(inode, fh, offset as i64, size, 0, None)
+            .await
+            .expect("read should succeed");
+        assert_eq!(
+            bytes.len(),
+            expected.len(),
+            "read at {offset} of {size} bytes returned wrong length"
+        );
+        assert_eq!(&bytes[..], &expected[..], "read bytes did not match");
+        self.fs.release(inode, fh, 0, None, true).await.unwrap();
+    }
+
+    /// Look up a name in a directory, and check it exists with the right type if and only if it
+    /// exists in the reference
+    async fn perform_lookup(&self, directory_index: DirectoryIndex, name: &str) {
+        let dir_path = directory_index.get(&self.reference);
+        let Some(Node::Directory { children, .. }) = self.reference.lookup(dir_path.as_ref()) else {
+            panic!("directory must already exist");
+        };
+        let full_path = dir_path.as_ref().join(name);
+        trace!(path=?full_path, "lookup");
+        let dir = self.lookup(dir_path.as_ref()).await.expect("directory should exist");
+        let lookup = self.fs.lookup(dir, name.as_ref()).await;
+        match children.get(name) {
+            Some(node) => {
+                let lookup = lookup.unwrap_or_else(|e| panic!("lookup of {full_path:?} failed: {e:?}"));
+                let ref_kind: FileType = node.node_type().into();
+                assert_eq!(lookup.attr.kind, ref_kind, "wrong type for {full_path:?}");
+                if let Node::File(File::Remote(object)) = node {
+                    assert_eq!(lookup.attr.size, object.len() as u64, "wrong size for {full_path:?}");
+                }
+            }
+            None => {
+                let err = lookup.expect_err("lookup of a name not in the reference should fail");
+                assert_eq!(err.to_errno(), libc::ENOENT, "wrong error for {full_path:?}");
+            }
+        }
+    }
+
+    /// List a directory, then list it again on a new handle from the offset of one of its entries
+    async fn perform_readdir_from(&self, directory_index: DirectoryIndex, entry_index: usize) {
+        let dir_path = directory_index.get(&self.reference);
+        trace!(path=?dir_path.as_ref(), entry_index, "readdir from offset");
+        let dir = self.lookup(dir_path.as_ref()).await.expect("directory should exist");
+
+        let entries = self.readdir_all(dir, 0).await;
+        let start = entry_index % entries.len();
+        let (offset, _) = entries[start];
+        let rest = self.readdir_all(dir, offset).await;
+        assert_eq!(rest, entries[start + 1..], "listing from offset {offset} did not match");
+    }
+
+    /// List a directory on a new handle from the given offset, returning each entry's offset and
+    /// name
+    async fn readdir_all(&self, dir: InodeNo, mut offset: i64) -> Vec<(i64, String)> {
+        let fh = self.fs.opendir(dir, 0).await.unwrap().fh;
+        let mut entries = Vec::new();
+        let mut reply = DirectoryReply::new(self.readdir_limit);
+        loop {
+            reply.clear();
+            self.fs.readdir(dir, fh, offset, &mut reply).await.unwrap();
+            if reply.entries.is_empty() {
+                break;
+            }
+            while let Some(entry) = reply.entries.pop_front() {
+                offset = entry.offset;
+                entries.push((entry.offset, entry.name.to_str().unwrap().to_owned()));
+            }
+        }
+        self.fs.releasedir(dir, fh, 0).await.unwrap();
+        entries
+    }
+
     /// Perform a PutObject on the bucket, to simulate concurrent access to the bucket by a client
     /// other than this filesystem. We use a [DirectoryIndex] to generate an interesting key to
     /// put to, one that is likely to overlap existing directories.
@@ -698,12 +822,33 @@ impl Harness {
         }
     }
 
-    /// Local files are in the process of being written, and so should be stat-able, but not
-    /// readable (open should fail).
+    /// Local files are in the process of being written, and so should be stat-able. Once they've
+    /// been opened for writing, reads see the data written so far; before then, open fails.
     async fn check_local_file(&self, inode: InodeNo) {
         let _stat = self.fs.getattr(inode).await.expect("stat should succeed");
         let open = self.fs.open(inode, libc::O_RDONLY, 0).await;
-        assert!(matches!(open, Err(e) if e.to_errno() == libc::EPERM));
+        let writing = self
+            .inflight_writes
+            .writes
+            .iter()
+            .find(|write| write.inode == inode && write.file_handle.is_some());
+        let Some(write) = writing else {
+            assert!(matches!(open, Err(e) if e.to_errno() == libc::EPERM));
+            return;
+        };
+        let fh = open.expect("open of a file being written should succeed").fh;
+        let expected = write.object.read(0, write.written);
+        let bytes = self
+            .fs
+            .read(inode, fh, 0, write.written as u32 + 1, 0, None)
+            .await
+            .expect("read of a file being written should succeed");
+        assert_eq!(
+            &bytes[..],
+            &expected[..],
+            "read bytes did not match the data written so far"
+        );
+        self.fs.release(inode, fh, 0, None, true).await.unwrap();
     }
 }
 
@@ -862,7 +1007,8 @@ mod read_only {
 }
 
 /// Mutation tests that run a sequence of mutations against a file system and check equivalence to
-/// the reference model.
+/// the reference model. Set `PROPTEST_CASES` to run more or fewer random sequences than proptest's
+/// default of 256.
 mod mutations {
     use super::*;
     use proptest::collection::vec;
@@ -1278,6 +1424,66 @@ mod mutations {
         )
     }
 
+    #[test]
+    fn regression_put_object_shadowed_by_directory() {
+        run_test(
+            TreeNode::Directory(BTreeMap::from([(
+                "a".into(),
+                TreeNode::Directory(BTreeMap::from([(
+                    "b".into(),
+                    TreeNode::File(FileContent(0, FileSize::Small(10))),
+                )])),
+            )])),
+            vec![
+                // A file at the directory's key is shadowed by it
+                Op::PutObject(DirectoryIndex(0), "a".into(), FileContent(1, FileSize::Small(20))),
+                Op::Lookup(DirectoryIndex(0), "a".into()),
+                Op::ReadRange(DirectoryIndex(0), ChildIndex(0), 0, 20),
+                // Until the directory goes away, and the file is visible
+                Op::DeleteObject(KeyIndex(1)),
+                Op::Lookup(DirectoryIndex(0), "a".into()),
+                Op::ReadRange(DirectoryIndex(0), ChildIndex(0), 0, 20),
+            ],
+            0,
+        )
+    }
+
+    #[test]
+    fn regression_read_range_past_eof() {
+        run_test(
+            TreeNode::Directory(BTreeMap::from([
+                ("a".into(), TreeNode::File(FileContent(0, FileSize::Small(10)))),
+                ("b".into(), TreeNode::File(FileContent(0, FileSize::Large(300 * 1024)))),
+            ])),
+            vec![
+                Op::ReadRange(DirectoryIndex(0), ChildIndex(0), 5, 100),
+                Op::ReadRange(DirectoryIndex(0), ChildIndex(0), 10, 100),
+                Op::ReadRange(DirectoryIndex(0), ChildIndex(0), 20, 100),
+                Op::ReadRange(DirectoryIndex(0), ChildIndex(1), 300 * 1024 - 1, 4096),
+                Op::ReadRange(DirectoryIndex(0), ChildIndex(1), 1024 * 1024, 4096),
+            ],
+            0,
+        )
+    }
+
+    #[test]
+    fn regression_readdir_from_offset() {
+        run_test(
+            TreeNode::Directory(BTreeMap::from([
+                ("a".into(), TreeNode::File(FileContent(0, FileSize::Small(0)))),
+                ("aa".into(), TreeNode::File(FileContent(0, FileSize::Small(0)))),
+                ("-".into(), TreeNode::Directory(BTreeMap::new())),
+            ])),
+            vec![
+                Op::CreateFile("a-".into(), DirectoryIndex(0), FileContent(0, FileSize::Small(1))),
+                Op::ReaddirFrom(DirectoryIndex(0), 0),
+                Op::ReaddirFrom(DirectoryIndex(0), 3),
+                Op::ReaddirFrom(DirectoryIndex(0), 100),
+            ],
+            2,
+        )
+    }
+
     #[test]
     fn regression_unlink_newly_put_object() {
         run_test(
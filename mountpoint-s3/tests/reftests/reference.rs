@@ -1,3 +1,4 @@
+use mountpoint_s3::fs::FileType;
 use mountpoint_s3_client::mock_client::MockObject;
 use std::cell::RefCell;
 use std::collections::BTreeMap;
@@ -26,11 +27,11 @@ pub enum NodeType {
     File,
 }
 
-impl From<NodeType> for fuser::FileType {
+impl From<NodeType> for FileType {
     fn from(value: NodeType) -> Self {
         match value {
-            NodeType::Directory => fuser::FileType::Directory,
-            NodeType::File => fuser::FileType::RegularFile,
+            NodeType::Directory => FileType::Directory,
+            NodeType::File => FileType::RegularFile,
         }
     }
 }
@@ -4,6 +4,7 @@ set -euo pipefail
 FUSER_VENDOR_PATH="vendor/fuser"
 BASE_PATH=$( cd -- "$( dirname -- "${BASH_SOURCE[0]}" )" &> /dev/null && pwd )
 FUSER_FULL_PATH="$BASE_PATH/$FUSER_VENDOR_PATH"
+FUSER_PATCHES_PATH="$BASE_PATH/vendor/fuser-patches"
 
 STATUS=$(git status --porcelain $FUSER_FULL_PATH)
 if [ -n "$STATUS"  ]; then
@@ -18,6 +19,12 @@ COMMIT=$(git -C $FUSER_FULL_PATH rev-parse --short HEAD)
 
 rm -rf $FUSER_FULL_PATH/.git
 
+# Re-apply our changes to fuser that aren't in the fork yet, in order
+shopt -s nullglob
+for PATCH in "$FUSER_PATCHES_PATH"/*.patch; do
+    git -C "$BASE_PATH" apply --directory="$FUSER_VENDOR_PATH" "$PATCH"
+done
+
 git add $FUSER_FULL_PATH
 
 git commit -m "Update vendored fuser to $COMMIT" -s